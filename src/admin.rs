@@ -0,0 +1,200 @@
+use crate::{
+    config::Config, error, features, logging::LogHandle, registry, snapshot::SNAPSHOT,
+    watch::PAUSES,
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use axum_prometheus::metrics_exporter_prometheus::PrometheusHandle;
+use serde_json::{json, Map, Value};
+use std::{
+    collections::BTreeMap,
+    sync::{LazyLock, Mutex},
+};
+use tracing::info;
+
+// the filters each pipeline runs with, by the name of its watcher, for /admin/state.
+// They change with reloads and EventMonitors, so like the snapshot it's a global.
+pub static FILTERS: LazyLock<Mutex<BTreeMap<String, Value>>> = LazyLock::new(Default::default);
+
+pub fn set_filters(watcher: &str, filters: Value) {
+    FILTERS
+        .lock()
+        .expect("filters lock poisoned")
+        .insert(watcher.to_string(), filters);
+}
+
+pub fn remove_filters(watcher: &str) {
+    FILTERS
+        .lock()
+        .expect("filters lock poisoned")
+        .remove(watcher);
+}
+
+// what the pod pipeline keeps and how it enriches it
+pub fn pipeline_filters(config: &Config) -> Value {
+    json!({
+        "kinds": config.event_kinds(),
+        "sinks": config.pipeline.sinks,
+        "pod_labels": config.enrichment.pod_labels,
+        "pod_annotations": config.enrichment.pod_annotations,
+    })
+}
+
+#[derive(Clone)]
+struct AdminState {
+    log: LogHandle,
+    metrics: PrometheusHandle,
+}
+
+// endpoints to poke at the running operator, only served when `admin.enabled` is set
+pub fn router(log: LogHandle, metrics: PrometheusHandle) -> Router {
+    Router::new()
+        .route("/admin/loglevel", get(get_log_level).put(put_log_level))
+        .route("/admin/features", get(feature_flags))
+        .route("/admin/runtime", get(runtime_metrics))
+        .route("/admin/state", get(state))
+        .route("/admin/watchers", get(watchers))
+        .route("/admin/watchers/:name/pause", post(pause_watcher))
+        .route("/admin/watchers/:name/resume", post(resume_watcher))
+        .with_state(AdminState { log, metrics })
+}
+
+async fn feature_flags() -> Json<Value> {
+    Json(features::status())
+}
+
+// every watcher and whether it's paused
+async fn watchers() -> Json<Value> {
+    Json(json!(PAUSES.list()))
+}
+
+// what's going on inside, like the debug endpoints of kube-controller-manager:
+// the watchers and where their streams are at, the filters in effect, how the sinks
+// are doing, the last error of each component and how many series each metric has
+async fn state(State(state): State<AdminState>) -> Json<Value> {
+    let mut watchers = Map::new();
+    for (name, paused) in PAUSES.list() {
+        watchers.insert(name, json!({ "paused": paused }));
+    }
+    if let Value::Object(health) = SNAPSHOT.watchers() {
+        for (name, health) in health {
+            let watcher = watchers.entry(name).or_insert_with(|| json!({}));
+            if let (Value::Object(watcher), Value::Object(health)) = (watcher, health) {
+                watcher.extend(health);
+            }
+        }
+    }
+    let filters = FILTERS.lock().expect("filters lock poisoned").clone();
+    let metrics = state.metrics.render() + registry::render().as_str();
+    Json(json!({
+        "watchers": watchers,
+        "filters": filters,
+        "sinks": SNAPSHOT.sinks(),
+        "errors": error::reported(),
+        "cardinality": cardinality(&metrics),
+    }))
+}
+
+// the series of each metric in a /metrics page. A histogram's series are counted
+// once, not once per bucket.
+fn cardinality(metrics: &str) -> Value {
+    let mut series = BTreeMap::<&str, u64>::new();
+    let mut family = "";
+    let mut histogram = false;
+    for line in metrics.lines() {
+        if let Some(declaration) = line.strip_prefix("# TYPE ") {
+            let mut parts = declaration.split_whitespace();
+            family = parts.next().unwrap_or_default();
+            histogram = matches!(parts.next(), Some("histogram" | "summary"));
+            series.entry(family).or_default();
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let name = line.split(['{', ' ']).next().unwrap_or_default();
+        if histogram && name != format!("{}_count", family) {
+            continue;
+        }
+        *series.entry(family).or_default() += 1;
+    }
+    json!({
+        "series": series.values().sum::<u64>(),
+        "by_metric": series,
+    })
+}
+
+// names with a slash (like eventmonitor/default/oom) have it url encoded, as %2F
+async fn pause_watcher(Path(name): Path<String>) -> (StatusCode, String) {
+    set_paused(&name, true)
+}
+
+async fn resume_watcher(Path(name): Path<String>) -> (StatusCode, String) {
+    set_paused(&name, false)
+}
+
+fn set_paused(name: &str, paused: bool) -> (StatusCode, String) {
+    match PAUSES.set(name, paused) {
+        true => (StatusCode::OK, "ok\n".to_string()),
+        false => (
+            StatusCode::NOT_FOUND,
+            format!("no watcher named {}\n", name),
+        ),
+    }
+}
+
+async fn get_log_level(State(state): State<AdminState>) -> (StatusCode, String) {
+    match state.log.current() {
+        Ok(filter) => (StatusCode::OK, filter + "\n"),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err + "\n"),
+    }
+}
+
+// the body is the new filter, like `info,k8rs::watch=debug`
+async fn put_log_level(State(state): State<AdminState>, body: String) -> (StatusCode, String) {
+    let directives = body.trim();
+    match state.log.set(directives) {
+        Ok(()) => {
+            info!("Log filter changed to {}", directives);
+            (StatusCode::OK, directives.to_string() + "\n")
+        }
+        Err(err) => (StatusCode::BAD_REQUEST, err + "\n"),
+    }
+}
+
+// what the tokio runtime is up to. The per worker numbers (polls, busy time, queues)
+// are only there in builds with `--cfg tokio_unstable`, like the `console` ones.
+async fn runtime_metrics() -> Json<Value> {
+    let metrics = tokio::runtime::Handle::current().metrics();
+    #[allow(unused_mut)]
+    let mut report = json!({
+        "workers": metrics.num_workers(),
+        "alive_tasks": metrics.num_alive_tasks(),
+        "global_queue_depth": metrics.global_queue_depth(),
+    });
+
+    #[cfg(tokio_unstable)]
+    {
+        let workers = (0..metrics.num_workers())
+            .map(|worker| {
+                json!({
+                    "polls": metrics.worker_poll_count(worker),
+                    "mean_poll_time_us": metrics.worker_mean_poll_time(worker).as_micros() as u64,
+                    "busy_seconds": metrics.worker_total_busy_duration(worker).as_secs_f64(),
+                    "local_queue_depth": metrics.worker_local_queue_depth(worker),
+                    "steals": metrics.worker_steal_count(worker),
+                    "parks": metrics.worker_park_count(worker),
+                })
+            })
+            .collect::<Vec<_>>();
+        report["spawned_tasks"] = json!(metrics.spawned_tasks_count());
+        report["blocking_threads"] = json!(metrics.num_blocking_threads());
+        report["blocking_queue_depth"] = json!(metrics.blocking_queue_depth());
+        report["per_worker"] = json!(workers);
+    }
+    Json(report)
+}
@@ -0,0 +1,310 @@
+use crate::{
+    config::{AdmissionSettings, PodMutationSettings},
+    error::{Context, ErrorKind},
+    handover,
+    monitor::{EventMonitor, EventMonitorSpec},
+    sinks::SinkRegistry,
+    tls,
+};
+use axum::{extract::State, routing::post, Json, Router};
+use k8s_openapi::api::core::v1::Pod;
+use kube::core::{
+    admission::{AdmissionRequest, AdmissionResponse, AdmissionReview},
+    DynamicObject,
+};
+use serde_json::json;
+use std::{collections::BTreeMap, future::Future, path::PathBuf, sync::Arc, time::Duration};
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+// the fields the api server can select events on, anything else makes the watch fail
+const EVENT_SELECTOR_FIELDS: &[&str] = &[
+    "metadata.name",
+    "metadata.namespace",
+    "involvedObject.kind",
+    "involvedObject.namespace",
+    "involvedObject.name",
+    "involvedObject.uid",
+    "involvedObject.apiVersion",
+    "involvedObject.resourceVersion",
+    "involvedObject.fieldPath",
+    "reason",
+    "reportingComponent",
+    "source",
+    "type",
+];
+
+const EVENT_TYPES: &[&str] = &["Normal", "Warning"];
+
+// checks EventMonitors before they're stored, so a broken one is rejected by
+// kubectl right away instead of showing up later as an inactive monitor
+pub struct Validator {
+    sinks: SinkRegistry,
+    max_kinds: usize,
+    max_reasons: usize,
+}
+
+impl Validator {
+    pub fn new(sinks: SinkRegistry, settings: &AdmissionSettings) -> Self {
+        Validator {
+            sinks,
+            max_kinds: settings.max_kinds,
+            max_reasons: settings.max_reasons,
+        }
+    }
+
+    // everything wrong with the spec, nothing if it's fine
+    pub fn check(&self, spec: &EventMonitorSpec) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if let Some(ref selector) = spec.field_selector {
+            if let Err(err) = check_field_selector(selector) {
+                problems.push(format!("fieldSelector: {}", err));
+            }
+        }
+        if let Err(err) = self.sinks.dispatcher(&spec.sinks) {
+            problems.push(format!("sinks: {}", err));
+        }
+        for type_ in spec.types.iter() {
+            if !EVENT_TYPES.contains(&type_.as_str()) {
+                problems.push(format!("types: {} is not Normal or Warning", type_));
+            }
+        }
+
+        // every kind and reason is a new series on monitor_events_total (per namespace)
+        if spec.kinds.len() > self.max_kinds {
+            problems.push(format!(
+                "kinds: at most {} are allowed, got {}",
+                self.max_kinds,
+                spec.kinds.len()
+            ));
+        }
+        if spec.reasons.len() > self.max_reasons {
+            problems.push(format!(
+                "reasons: at most {} are allowed, got {}",
+                self.max_reasons,
+                spec.reasons.len()
+            ));
+        }
+        for (field, values) in [("kinds", &spec.kinds), ("reasons", &spec.reasons)] {
+            if values.iter().any(|value| value.trim().is_empty()) {
+                problems.push(format!("{}: values can't be empty", field));
+            }
+        }
+        problems
+    }
+}
+
+// a field selector is a comma separated list of `field=value`, `field==value` or `field!=value`
+fn check_field_selector(selector: &str) -> Result<(), String> {
+    for term in selector.split(',') {
+        let term = term.trim();
+        let field = match term.split_once("!=").or_else(|| term.split_once('=')) {
+            Some((field, _)) => field.trim(),
+            None => return Err(format!("{:?} must look like field=value", term)),
+        };
+        if field.is_empty() {
+            return Err(format!("{:?} has no field", term));
+        }
+        if !EVENT_SELECTOR_FIELDS.contains(&field) {
+            return Err(format!("events can't be selected by {}", field));
+        }
+    }
+    Ok(())
+}
+
+// stamps new pods with annotations the pipeline can later copy onto the pod counters
+pub struct PodMutator {
+    source_annotation: Option<String>,
+    annotations: BTreeMap<String, String>,
+}
+
+impl PodMutator {
+    pub fn new(settings: &PodMutationSettings) -> Self {
+        PodMutator {
+            source_annotation: settings.source_annotation.clone(),
+            annotations: settings.annotations.clone(),
+        }
+    }
+
+    // the annotations to add to the pod, the ones it already has are left alone
+    fn annotations_for(
+        &self,
+        request: &AdmissionRequest<Pod>,
+        pod: &Pod,
+    ) -> BTreeMap<String, String> {
+        let mut annotations = self.annotations.clone();
+        if let Some(ref key) = self.source_annotation {
+            // a controller's service account for pods created by a controller,
+            // like system:serviceaccount:kube-system:replicaset-controller
+            if let Some(ref username) = request.user_info.username {
+                annotations.insert(key.clone(), username.clone());
+            }
+        }
+        if let Some(ref existing) = pod.metadata.annotations {
+            annotations.retain(|key, _| !existing.contains_key(key));
+        }
+        annotations
+    }
+}
+
+// the webhook server, serving https with the certificate of the mounted tls Secret.
+// The certificate is read once here, so a missing one stops the operator from starting,
+// and then reloaded periodically so rotated certificates get picked up.
+pub async fn server(
+    settings: &AdmissionSettings,
+    listener: TcpListener,
+    sinks: SinkRegistry,
+) -> Result<impl Future<Output = ()> + Send + 'static, ErrorKind> {
+    let (cert, key) = (
+        settings.cert_dir.join("tls.crt"),
+        settings.cert_dir.join("tls.key"),
+    );
+    let tls = tls::Acceptor::from_pem_file(&cert, &key)
+        .await
+        .with_context(|| {
+            format!(
+                "could not load the webhook certificate from {:?}",
+                settings.cert_dir
+            )
+        })?;
+
+    let mut app = Router::new()
+        .route("/validate-eventmonitor", post(validate_monitor))
+        .with_state(Arc::new(Validator::new(sinks, settings)));
+    if settings.pod_mutation.enabled {
+        app = app.merge(
+            Router::new()
+                .route("/mutate-pod", post(mutate_pod))
+                .with_state(Arc::new(PodMutator::new(&settings.pod_mutation))),
+        );
+    }
+
+    let listen = settings.listen;
+    let cert_reload = settings.cert_reload;
+    let serving = handover::serving();
+    Ok(async move {
+        let _serving = serving;
+        tokio::spawn(reload_certificate(tls.clone(), cert, key, cert_reload));
+
+        let handle = axum_server::Handle::new();
+        tokio::spawn({
+            let handle = handle.clone();
+            async move {
+                crate::shutdown_signal().await;
+                handle.graceful_shutdown(None);
+            }
+        });
+
+        info!("Admission webhook listening on {}", listen);
+        if let Err(err) = tls.serve(listener, handle, app).await {
+            warn!("Admission webhook server stopped: {}", err);
+        }
+    })
+}
+
+// the kubelet updates mounted Secrets in place, so rereading the files is enough
+async fn reload_certificate(tls: tls::Acceptor, cert: PathBuf, key: PathBuf, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+    // the first tick is right away, and we just loaded them
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        if let Err(err) = tls.reload_from_pem_file(&cert, &key).await {
+            warn!("Could not reload the webhook certificate: {}", err);
+        }
+    }
+}
+
+async fn validate_monitor(
+    State(validator): State<Arc<Validator>>,
+    Json(review): Json<AdmissionReview<EventMonitor>>,
+) -> Json<AdmissionReview<DynamicObject>> {
+    let request: AdmissionRequest<EventMonitor> = match review.try_into() {
+        Ok(request) => request,
+        Err(err) => {
+            warn!("Invalid AdmissionReview: {}", err);
+            return Json(AdmissionResponse::invalid(err.to_string()).into_review());
+        }
+    };
+
+    let mut response = AdmissionResponse::from(&request);
+    // deletes come without an object, and there's nothing to check on those
+    if let Some(ref monitor) = request.object {
+        let problems = validator.check(&monitor.spec);
+        if !problems.is_empty() {
+            info!(
+                "Rejected EventMonitor {}/{}: {}",
+                request.namespace.clone().unwrap_or_default(),
+                request.name,
+                problems.join("; ")
+            );
+            response = response.deny(problems.join("; "));
+        }
+    }
+    Json(response.into_review())
+}
+
+async fn mutate_pod(
+    State(mutator): State<Arc<PodMutator>>,
+    Json(review): Json<AdmissionReview<Pod>>,
+) -> Json<AdmissionReview<DynamicObject>> {
+    let request: AdmissionRequest<Pod> = match review.try_into() {
+        Ok(request) => request,
+        Err(err) => {
+            warn!("Invalid AdmissionReview: {}", err);
+            return Json(AdmissionResponse::invalid(err.to_string()).into_review());
+        }
+    };
+
+    let response = AdmissionResponse::from(&request);
+    let annotations = match request.object {
+        Some(ref pod) => mutator.annotations_for(&request, pod),
+        None => BTreeMap::new(),
+    };
+    if annotations.is_empty() {
+        return Json(response.into_review());
+    }
+
+    let has_annotations = request
+        .object
+        .as_ref()
+        .is_some_and(|pod| pod.metadata.annotations.is_some());
+    // a json patch can't add a key to a map that isn't there, so we add the whole map then
+    let operations = if has_annotations {
+        annotations
+            .iter()
+            .map(|(key, value)| {
+                json!({
+                    "op": "add",
+                    "path": format!("/metadata/annotations/{}", escape_pointer(key)),
+                    "value": value,
+                })
+            })
+            .collect()
+    } else {
+        vec![json!({ "op": "add", "path": "/metadata/annotations", "value": annotations })]
+    };
+
+    let patched = serde_json::from_value::<json_patch::Patch>(json!(operations))
+        .map_err(|err| err.to_string())
+        .and_then(|patch| {
+            response
+                .clone()
+                .with_patch(patch)
+                .map_err(|err| err.to_string())
+        });
+    match patched {
+        Ok(response) => Json(response.into_review()),
+        Err(err) => {
+            // never keep a pod from being created because of us
+            warn!("Could not annotate pod: {}", err);
+            Json(response.into_review())
+        }
+    }
+}
+
+// annotation keys have slashes, which mean something else in a json pointer
+fn escape_pointer(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
@@ -0,0 +1,189 @@
+use crate::{config::AggregationSettings, record::EventRecord, sinks::Dispatcher};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::watch;
+use tracing::debug;
+
+// the events rolled up together share these: namespace, reason and workload
+type GroupKey = (String, String, String);
+
+struct Group {
+    // the latest record of the group, what gets delivered
+    record: EventRecord,
+    first_timestamp: Option<String>,
+    count: u64,
+}
+
+// sits between the workers and the pipeline's sinks. With a window, the identical
+// events (same reason and workload) that come within it are delivered as a single
+// record, which says how many there were. Without one, records go straight through.
+pub struct Aggregator {
+    settings: AggregationSettings,
+    dispatcher: watch::Receiver<Dispatcher>,
+    groups: Mutex<HashMap<GroupKey, Group>>,
+}
+
+impl Aggregator {
+    pub fn new(settings: &AggregationSettings, dispatcher: watch::Receiver<Dispatcher>) -> Self {
+        Aggregator {
+            settings: settings.clone(),
+            dispatcher,
+            groups: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // the sinks the records end up in, which can change on a reload
+    pub fn dispatcher(&self) -> Dispatcher {
+        self.dispatcher.borrow().clone()
+    }
+
+    pub async fn deliver(&self, record: EventRecord) {
+        if self.settings.window.is_some() {
+            let key = (
+                record.namespace.clone(),
+                record.reason.clone(),
+                record
+                    .owner
+                    .clone()
+                    .unwrap_or_else(|| format!("{}/{}", record.kind, record.object_name)),
+            );
+            let mut groups = self.groups.lock().expect("aggregation lock poisoned");
+            if let Some(group) = groups.get_mut(&key) {
+                group.count += 1;
+                group.record = record;
+                return;
+            }
+            // past the limit the new groups aren't held back, so memory stays bounded
+            if groups.len() < self.settings.max_groups {
+                groups.insert(
+                    key,
+                    Group {
+                        first_timestamp: record.first_timestamp.clone(),
+                        record,
+                        count: 1,
+                    },
+                );
+                return;
+            }
+        }
+        self.dispatcher().dispatch(&record).await;
+    }
+
+    // delivers what was rolled up at the end of every window.
+    // Does nothing without a window.
+    pub async fn run(self: Arc<Self>) {
+        let Some(window) = self.settings.window else {
+            return;
+        };
+        let mut interval = tokio::time::interval(window);
+        // the first tick is right away
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            self.drain().await;
+        }
+    }
+
+    // delivers every group, right now
+    pub async fn drain(&self) {
+        let groups = std::mem::take(&mut *self.groups.lock().expect("aggregation lock poisoned"));
+        if groups.is_empty() {
+            return;
+        }
+        debug!("Delivering {} rolled up record(s)", groups.len());
+        let dispatcher = self.dispatcher();
+        for (_, group) in groups {
+            let mut record = group.record;
+            // a lone event is delivered as it came
+            if group.count > 1 {
+                record.first_timestamp = group.first_timestamp;
+                record.aggregated = Some(group.count);
+            }
+            dispatcher.dispatch(&record).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sinks::{EventSink, TestSink};
+    use std::time::Duration;
+
+    fn record(object: &str, timestamp: &str) -> EventRecord {
+        EventRecord {
+            namespace: "shop".to_string(),
+            reason: "BackOff".to_string(),
+            kind: "Pod".to_string(),
+            object_name: object.to_string(),
+            first_timestamp: Some(timestamp.to_string()),
+            last_timestamp: Some(timestamp.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn aggregator(settings: &AggregationSettings, sink: Arc<dyn EventSink>) -> Aggregator {
+        let (_, dispatcher) = watch::channel(Dispatcher::to(vec![("log".to_string(), sink)]));
+        Aggregator::new(settings, dispatcher)
+    }
+
+    // the object, when its group started and ended, and how many it had
+    type Delivered = (String, Option<String>, Option<String>, Option<u64>);
+
+    fn delivered(sink: &TestSink) -> Vec<Delivered> {
+        let mut delivered = sink
+            .records()
+            .into_iter()
+            .map(|record| {
+                (
+                    record.object_name,
+                    record.first_timestamp,
+                    record.last_timestamp,
+                    record.aggregated,
+                )
+            })
+            .collect::<Vec<_>>();
+        delivered.sort();
+        delivered
+    }
+
+    #[tokio::test]
+    async fn rolls_up_the_events_of_a_window() {
+        let sink = TestSink::failing(0);
+        let settings = AggregationSettings {
+            window: Some(Duration::from_secs(60)),
+            max_groups: 2,
+        };
+        let aggregator = aggregator(&settings, sink.clone());
+        for timestamp in ["10:00", "10:01", "10:02"] {
+            aggregator.deliver(record("web-1", timestamp)).await;
+        }
+        aggregator.deliver(record("web-2", "10:03")).await;
+        // past max_groups, delivered right away
+        aggregator.deliver(record("web-3", "10:04")).await;
+        let at = |timestamp: &str| Some(timestamp.to_string());
+        let web_3 = ("web-3".to_string(), at("10:04"), at("10:04"), None);
+        assert_eq!(delivered(&sink), [web_3.clone()]);
+
+        aggregator.drain().await;
+        assert_eq!(
+            delivered(&sink),
+            [
+                ("web-1".to_string(), at("10:00"), at("10:02"), Some(3)),
+                ("web-2".to_string(), at("10:03"), at("10:03"), None),
+                web_3,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn goes_straight_through_without_a_window() {
+        let sink = TestSink::failing(0);
+        let aggregator = aggregator(&AggregationSettings::default(), sink.clone());
+        aggregator.deliver(record("web-1", "10:00")).await;
+        aggregator.deliver(record("web-1", "10:01")).await;
+        assert_eq!(sink.batches(), [1, 1]);
+    }
+}
@@ -0,0 +1,605 @@
+mod expr;
+
+pub use expr::Expression;
+
+use crate::{
+    clock,
+    config::{AlertGrouping, AlertRule, AlertSettings, PagerDutySettings, PAGERDUTY_SEVERITIES},
+    error::ErrorKind,
+    metrics::{self, ALERTS_FIRED_COUNTER, ALERT_LABEL},
+    outbound,
+    record::EventRecord,
+    severity,
+    sinks::{not_sent, SinkRegistry},
+};
+use axum_prometheus::metrics::counter;
+use k8s_openapi::chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+type GroupLabels = BTreeMap<String, String>;
+
+// the alerts that fired and are waiting to be delivered, more are dropped so a slow
+// Alertmanager doesn't hold the pipeline up
+const FIRED_QUEUE: usize = 256;
+
+// the alert rules of the config, fed with every event of the pod pipeline.
+// An alert fires for a group of events (a workload, a namespace...) as soon as the
+// rule's threshold is crossed, and resolves once the window has moved past it. The
+// rules with an expr are evaluated on our own series every interval instead, each
+// series selected firing while it holds.
+pub struct Alerts {
+    rules: Vec<Rule>,
+    sinks: SinkRegistry,
+    alertmanager: Option<Alertmanager>,
+    pagerduty: Option<PagerDuty>,
+    interval: Duration,
+    dry_run: bool,
+    fired: mpsc::Sender<Fired>,
+    // taken by run, which delivers what fired apart from the events
+    delivery: Mutex<Option<mpsc::Receiver<Fired>>>,
+}
+
+// an alert that just fired, with the record its rule's sinks get
+struct Fired {
+    rule: usize,
+    record: EventRecord,
+    alert: Alert,
+}
+
+struct Rule {
+    settings: AlertRule,
+    expression: Option<Expression>,
+    groups: Mutex<HashMap<GroupLabels, Group>>,
+}
+
+#[derive(Default)]
+struct Group {
+    // when the latest events were seen, the threshold is all we need to keep
+    seen: VecDeque<Instant>,
+    // the samples of the series of an expr
+    samples: expr::Samples,
+    // when it started firing, if it is
+    firing: Option<DateTime<Utc>>,
+}
+
+// an alert as Alertmanager's api wants it
+struct Alert {
+    labels: GroupLabels,
+    annotations: BTreeMap<String, String>,
+    starts_at: DateTime<Utc>,
+    ends_at: DateTime<Utc>,
+}
+
+impl Alert {
+    fn to_json(&self) -> Value {
+        json!({
+            "labels": self.labels,
+            "annotations": self.annotations,
+            "startsAt": self.starts_at.to_rfc3339(),
+            "endsAt": self.ends_at.to_rfc3339(),
+        })
+    }
+}
+
+struct Alertmanager {
+    client: reqwest::Client,
+    url: String,
+    dry_run: bool,
+}
+
+impl Alertmanager {
+    async fn post(&self, alerts: &[Alert]) {
+        if alerts.is_empty() {
+            return;
+        }
+        if self.dry_run {
+            for alert in alerts {
+                not_sent("alertmanager", alert.to_json());
+            }
+            return;
+        }
+        let body = alerts.iter().map(Alert::to_json).collect::<Vec<_>>();
+        let result = self
+            .client
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(err) = result {
+            warn!(
+                "Could not send {} alert(s) to Alertmanager: {}",
+                alerts.len(),
+                err
+            );
+        }
+    }
+}
+
+// triggers an incident when an alert fires and resolves it with it, the dedup key
+// being the alert's labels: its name and group, like the workload
+struct PagerDuty {
+    client: reqwest::Client,
+    settings: PagerDutySettings,
+    dry_run: bool,
+}
+
+impl PagerDuty {
+    // with the rule's severity label if PagerDuty knows it, or the severity of the event
+    // that fired the alert, the configured one for the rules with an expr
+    async fn trigger(&self, alert: &Alert, record: &EventRecord) {
+        let labels = &alert.labels;
+        let severity = labels
+            .get("severity")
+            .map(String::as_str)
+            .filter(|severity| PAGERDUTY_SEVERITIES.contains(severity))
+            .or_else(|| {
+                record
+                    .severity
+                    .as_ref()
+                    .map(|_| severity::of_record(record).as_str())
+            })
+            .unwrap_or(&self.settings.severity);
+        // like "KillingLoop default/web: pods keep getting killed"
+        let mut summary = format!(
+            "{} {}",
+            labels.get("alertname").map_or("", String::as_str),
+            self.group(labels).join("/")
+        );
+        if let Some(annotation) = alert.annotations.get("summary") {
+            summary.push_str(": ");
+            summary.push_str(annotation);
+        }
+        let mut details = labels.clone();
+        details.extend(alert.annotations.clone());
+        self.send(json!({
+            "routing_key": self.settings.routing_key,
+            "event_action": "trigger",
+            "dedup_key": dedup_key(labels),
+            "payload": {
+                "summary": summary,
+                "source": "k8rs",
+                "severity": severity,
+                "timestamp": alert.starts_at.to_rfc3339(),
+                "custom_details": details,
+            },
+        }))
+        .await;
+    }
+
+    async fn resolve(&self, labels: &GroupLabels) {
+        self.send(json!({
+            "routing_key": self.settings.routing_key,
+            "event_action": "resolve",
+            "dedup_key": dedup_key(labels),
+        }))
+        .await;
+    }
+
+    // the labels of the group, without the rule's own
+    fn group<'a>(&self, labels: &'a GroupLabels) -> Vec<&'a str> {
+        ["namespace", "workload", "object"]
+            .iter()
+            .filter_map(|label| labels.get(*label).map(String::as_str))
+            .collect()
+    }
+
+    async fn send(&self, mut event: Value) {
+        if self.dry_run {
+            // not a secret to log
+            event["routing_key"] = json!("...");
+            not_sent("pagerduty", event);
+            return;
+        }
+        let result = self
+            .client
+            .post(&self.settings.url)
+            .json(&event)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(err) = result {
+            warn!(
+                "Could not {} the PagerDuty incident {}: {}",
+                event["event_action"].as_str().unwrap_or_default(),
+                event["dedup_key"].as_str().unwrap_or_default(),
+                err
+            );
+        }
+    }
+}
+
+// PagerDuty takes up to 255 characters
+fn dedup_key(labels: &GroupLabels) -> String {
+    let key = labels
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join(",");
+    key.chars().take(255).collect()
+}
+
+// the names the rules' exprs select, for their series to be kept as they're recorded.
+// Before the counters are restored, for the restored ones to be too.
+pub fn watch(settings: &AlertSettings) {
+    metrics::watch(
+        settings
+            .rules
+            .iter()
+            .filter_map(|rule| Expression::parse(rule.expr.as_ref()?).ok())
+            .map(|expression| expression.name().to_string()),
+    );
+}
+
+impl Alerts {
+    pub fn new(settings: &AlertSettings, sinks: SinkRegistry) -> Result<Self, ErrorKind> {
+        let alertmanager = match settings.alertmanager {
+            Some(ref url) => Some(Alertmanager {
+                client: outbound::client().timeout(settings.timeout).build()?,
+                url: format!("{}/api/v2/alerts", url.trim_end_matches('/')),
+                dry_run: settings.dry_run,
+            }),
+            None => None,
+        };
+        let pagerduty = match settings.pagerduty {
+            Some(ref pagerduty) => Some(PagerDuty {
+                client: outbound::client().timeout(settings.timeout).build()?,
+                settings: pagerduty.clone(),
+                dry_run: settings.dry_run,
+            }),
+            None => None,
+        };
+        let rules = settings
+            .rules
+            .iter()
+            .map(|rule| {
+                Ok(Rule {
+                    settings: rule.clone(),
+                    expression: match rule.expr {
+                        Some(ref expr) => Some(Expression::parse(expr)?),
+                        None => None,
+                    },
+                    groups: Mutex::new(HashMap::new()),
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        let (fired, delivery) = mpsc::channel(FIRED_QUEUE);
+        Ok(Alerts {
+            rules,
+            sinks,
+            alertmanager,
+            pagerduty,
+            interval: settings.interval,
+            dry_run: settings.dry_run,
+            fired,
+            delivery: Mutex::new(Some(delivery)),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // counts an event for every rule it matches, firing what crossed its threshold.
+    // What fired is delivered by run's task, the pipeline doesn't wait for it.
+    pub fn observe(&self, record: &EventRecord) {
+        let now = clock::instant();
+        for (index, rule) in self.rules.iter().enumerate() {
+            let settings = &rule.settings;
+            if rule.expression.is_some() || !settings.matches(record) {
+                continue;
+            }
+            let labels = settings.group_labels(record);
+            let alert = {
+                let mut groups = rule.groups.lock().expect("alert rule lock poisoned");
+                let group = groups.entry(labels.clone()).or_default();
+                group.seen.push_back(now);
+                group.prune(now, settings);
+                if group.firing.is_some() || group.seen.len() <= settings.threshold {
+                    continue;
+                }
+                let starts_at = clock::now();
+                group.firing = Some(starts_at);
+                settings.alert(&labels, starts_at, self.ends_at())
+            };
+            let fired = Fired {
+                rule: index,
+                record: settings.record(record),
+                alert,
+            };
+            if let Err(mpsc::error::TrySendError::Full(fired)) = self.fired.try_send(fired) {
+                warn!(
+                    "Dropped alert {} for {:?}, too many are waiting to be delivered",
+                    settings.name, fired.alert.labels
+                );
+            }
+        }
+    }
+
+    async fn fire(&self, fired: Fired) {
+        let Fired {
+            rule,
+            record,
+            alert,
+        } = fired;
+        let rule = &self.rules[rule].settings;
+        info!("Alert {} firing for {:?}", rule.name, alert.labels);
+        counter!(ALERTS_FIRED_COUNTER, &[(ALERT_LABEL, rule.name.clone())]).increment(1);
+        if let Some(ref pagerduty) = self.pagerduty {
+            pagerduty.trigger(&alert, &record).await;
+        }
+        if let Some(ref alertmanager) = self.alertmanager {
+            alertmanager.post(&[alert]).await;
+        }
+        if rule.sinks.is_empty() {
+            return;
+        }
+        // taken from the registry every time, so reloaded sinks are picked up
+        match self.sinks.dispatcher(&rule.sinks) {
+            Ok(dispatcher) if self.dry_run => dispatcher.dry_run().dispatch(&record).await,
+            Ok(dispatcher) => dispatcher.dispatch(&record).await,
+            Err(err) => warn!("Could not deliver alert {}: {}", rule.name, err),
+        }
+    }
+
+    // firing alerts are resent before they'd expire on Alertmanager's side
+    fn ends_at(&self) -> DateTime<Utc> {
+        clock::now() + self.interval * 3
+    }
+
+    // evaluates the rules every interval, resending what's firing and resolving
+    // what isn't anymore
+    pub async fn run(self: Arc<Self>) {
+        let delivery = self.delivery.lock().expect("alerts lock poisoned").take();
+        if let Some(mut delivery) = delivery {
+            let alerts = self.clone();
+            tokio::spawn(async move {
+                while let Some(fired) = delivery.recv().await {
+                    alerts.fire(fired).await;
+                }
+            });
+        }
+        let mut interval = tokio::time::interval(self.interval);
+        loop {
+            interval.tick().await;
+            let now = clock::instant();
+            let mut alerts = Vec::new();
+            let mut resolved = Vec::new();
+            let mut fired = Vec::new();
+            for (index, rule) in self.rules.iter().enumerate() {
+                let settings = &rule.settings;
+                if let Some(ref expression) = rule.expression {
+                    let mut groups = rule.groups.lock().expect("alert rule lock poisoned");
+                    let selected = expression.select();
+                    // the series gone are forgotten, resolving them if they were firing
+                    groups.retain(|labels, group| {
+                        if selected.contains_key(labels) {
+                            return true;
+                        }
+                        if let Some(starts_at) = group.firing {
+                            info!("Alert {} resolved for {:?}", settings.name, labels);
+                            let alert = settings.alert(labels, starts_at, clock::now());
+                            if self.pagerduty.is_some() {
+                                resolved.push(alert.labels.clone());
+                            }
+                            alerts.push(alert);
+                        }
+                        false
+                    });
+                    for (labels, sample) in selected {
+                        let group = groups.entry(labels.clone()).or_default();
+                        expression.record(&mut group.samples, now, sample);
+                        let value = expression.value(&group.samples);
+                        let holds = value.is_some_and(|value| expression.holds(value));
+                        match (group.firing, value) {
+                            (None, Some(value)) if holds => {
+                                let starts_at = clock::now();
+                                group.firing = Some(starts_at);
+                                let mut alert = settings.alert(&labels, starts_at, self.ends_at());
+                                alert
+                                    .annotations
+                                    .insert("value".to_string(), value.to_string());
+                                fired.push(Fired {
+                                    rule: index,
+                                    record: settings.expression_record(&labels, value),
+                                    alert,
+                                });
+                            }
+                            (Some(starts_at), _) if holds => {
+                                alerts.push(settings.alert(&labels, starts_at, self.ends_at()));
+                            }
+                            (Some(starts_at), _) => {
+                                info!("Alert {} resolved for {:?}", settings.name, labels);
+                                let alert = settings.alert(&labels, starts_at, clock::now());
+                                if self.pagerduty.is_some() {
+                                    resolved.push(alert.labels.clone());
+                                }
+                                alerts.push(alert);
+                                group.firing = None;
+                            }
+                            (None, _) => {}
+                        }
+                    }
+                    continue;
+                }
+                let mut groups = rule.groups.lock().expect("alert rule lock poisoned");
+                groups.retain(|labels, group| {
+                    group.prune(now, settings);
+                    let Some(starts_at) = group.firing else {
+                        return !group.seen.is_empty();
+                    };
+                    if group.seen.len() > settings.threshold {
+                        alerts.push(settings.alert(labels, starts_at, self.ends_at()));
+                        return true;
+                    }
+                    info!("Alert {} resolved for {:?}", settings.name, labels);
+                    let alert = settings.alert(labels, starts_at, clock::now());
+                    if self.pagerduty.is_some() {
+                        resolved.push(alert.labels.clone());
+                    }
+                    alerts.push(alert);
+                    group.firing = None;
+                    !group.seen.is_empty()
+                });
+            }
+            if let Some(ref alertmanager) = self.alertmanager {
+                alertmanager.post(&alerts).await;
+            }
+            if let Some(ref pagerduty) = self.pagerduty {
+                for labels in resolved.iter() {
+                    pagerduty.resolve(labels).await;
+                }
+            }
+            for fired in fired {
+                if self.fired.send(fired).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl Group {
+    // forgets the events that left the window
+    fn prune(&mut self, now: Instant, rule: &AlertRule) {
+        while self
+            .seen
+            .front()
+            .is_some_and(|seen| now.duration_since(*seen) > rule.window)
+        {
+            self.seen.pop_front();
+        }
+        // one more than the threshold is enough to know it's crossed
+        while self.seen.len() > rule.threshold + 1 {
+            self.seen.pop_front();
+        }
+    }
+}
+
+impl AlertRule {
+    fn matches(&self, record: &EventRecord) -> bool {
+        let allowed =
+            |allowed: &[String], value: &String| allowed.is_empty() || allowed.contains(value);
+        allowed(&self.kinds, &record.kind)
+            && allowed(&self.reasons, &record.reason)
+            && allowed(&self.types, &record.type_)
+    }
+
+    // what tells the groups of the rule apart, they end up in the alert's labels
+    fn group_labels(&self, record: &EventRecord) -> GroupLabels {
+        let mut labels = GroupLabels::new();
+        let object = || format!("{}/{}", record.kind, record.object_name);
+        match self.group_by {
+            AlertGrouping::Object => {
+                labels.insert("namespace".to_string(), record.namespace.clone());
+                labels.insert("object".to_string(), object());
+            }
+            AlertGrouping::Workload => {
+                labels.insert("namespace".to_string(), record.namespace.clone());
+                let workload = record.owner.clone().unwrap_or_else(object);
+                labels.insert("workload".to_string(), workload);
+            }
+            AlertGrouping::Namespace => {
+                labels.insert("namespace".to_string(), record.namespace.clone());
+            }
+            AlertGrouping::Cluster => {}
+        }
+        labels
+    }
+
+    fn summary(&self) -> String {
+        if let Some(ref expr) = self.expr {
+            return expr.clone();
+        }
+        let events = match self.reasons.as_slice() {
+            [] => "events".to_string(),
+            reasons => format!("{} events", reasons.join("/")),
+        };
+        format!(
+            "more than {} {} within {}",
+            self.threshold,
+            events,
+            humantime::format_duration(self.window)
+        )
+    }
+
+    fn alert(
+        &self,
+        group: &GroupLabels,
+        starts_at: DateTime<Utc>,
+        ends_at: DateTime<Utc>,
+    ) -> Alert {
+        let mut labels = self.labels.clone();
+        labels.extend(group.clone());
+        labels.insert("alertname".to_string(), self.name.clone());
+        let mut annotations = self.annotations.clone();
+        annotations
+            .entry("summary".to_string())
+            .or_insert_with(|| self.summary());
+        Alert {
+            labels,
+            annotations,
+            starts_at,
+            ends_at,
+        }
+    }
+
+    // what the sinks get when the alert fires: the event that tipped it over,
+    // re-labeled as the alert
+    fn record(&self, record: &EventRecord) -> EventRecord {
+        let mut labels = record.labels.clone();
+        labels.extend(self.labels.clone());
+        EventRecord {
+            kind: "Alert".to_string(),
+            name: self.name.clone(),
+            reason: self.name.clone(),
+            message: self
+                .annotations
+                .get("summary")
+                .cloned()
+                .unwrap_or_else(|| self.summary()),
+            type_: "Warning".to_string(),
+            source: "k8rs".to_string(),
+            count: (self.threshold + 1).try_into().unwrap_or(i32::MAX),
+            labels,
+            // the summary above is the message
+            summary: None,
+            ..record.clone()
+        }
+    }
+
+    // what the sinks get when an expr starts holding for a series, labeled with it
+    fn expression_record(&self, group: &GroupLabels, value: f64) -> EventRecord {
+        let now = clock::now().to_rfc3339_opts(SecondsFormat::Millis, false);
+        let mut labels = group.clone();
+        labels.extend(self.labels.clone());
+        let summary = self
+            .annotations
+            .get("summary")
+            .cloned()
+            .unwrap_or_else(|| self.summary());
+        EventRecord {
+            uid: format!("{}/{}", self.name, now),
+            namespace: group.get("namespace").cloned().unwrap_or_default(),
+            name: self.name.clone(),
+            kind: "Alert".to_string(),
+            object_name: self.name.clone(),
+            reason: self.name.clone(),
+            message: format!("{} (at {})", summary, value),
+            type_: "Warning".to_string(),
+            source: "k8rs".to_string(),
+            first_timestamp: Some(now.clone()),
+            last_timestamp: Some(now),
+            count: 1,
+            labels,
+            ..EventRecord::default()
+        }
+    }
+}
@@ -0,0 +1,490 @@
+use super::GroupLabels;
+use crate::{metrics, registry::Labels};
+use regex::Regex;
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+// what an alert rule's expr says, like `rate(pod_crashloop_total{workload="x"}[10m]) > 0.2`:
+// a selector of our own series, a rate or increase over a window of it or its value as
+// it is, compared to a number. Every series selected is an alert group of its own.
+#[derive(Debug)]
+pub struct Expression {
+    function: Option<Function>,
+    name: String,
+    matchers: Vec<Matcher>,
+    window: Duration,
+    comparison: Comparison,
+    threshold: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Function {
+    // per second
+    Rate,
+    Increase,
+}
+
+#[derive(Debug)]
+struct Matcher {
+    label: String,
+    value: Value,
+    negated: bool,
+}
+
+#[derive(Debug)]
+enum Value {
+    Exact(String),
+    // anchored, like prometheus' ones
+    Regex(Regex),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Comparison {
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+}
+
+// the samples of a series seen so far, within the window
+pub type Samples = VecDeque<(Instant, f64)>;
+
+impl Expression {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            expression,
+            rest: expression,
+        };
+        let parsed = parser.expression()?;
+        parser.skip_whitespace();
+        if !parser.rest.is_empty() {
+            return Err(parser.error("the end"));
+        }
+        Ok(parsed)
+    }
+
+    // the name of the series, as /metrics has it
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // the series of ours it selects, with their current values
+    pub fn select(&self) -> HashMap<GroupLabels, f64> {
+        self.matching(metrics::select(&self.name))
+    }
+
+    fn matching(&self, series: Vec<(Labels, f64)>) -> HashMap<GroupLabels, f64> {
+        series
+            .into_iter()
+            .map(|(labels, value)| (labels.into_iter().collect::<GroupLabels>(), value))
+            .filter(|(labels, _)| self.matchers.iter().all(|matcher| matcher.matches(labels)))
+            .collect()
+    }
+
+    // adds the latest sample of a series, forgetting the ones that left the window
+    pub fn record(&self, samples: &mut Samples, now: Instant, value: f64) {
+        samples.push_back((now, value));
+        while samples
+            .front()
+            .is_some_and(|(seen, _)| now.duration_since(*seen) > self.window)
+        {
+            samples.pop_front();
+        }
+    }
+
+    // the value of the series compared to the threshold. A rate or increase needs two
+    // samples, and is over what was seen of the window: a counter going down started
+    // over, like prometheus takes it.
+    pub fn value(&self, samples: &Samples) -> Option<f64> {
+        let (last_seen, last) = *samples.back()?;
+        let Some(function) = self.function else {
+            return Some(last);
+        };
+        let (first_seen, _) = *samples.front()?;
+        let elapsed = last_seen.duration_since(first_seen).as_secs_f64();
+        if samples.len() < 2 || elapsed == 0.0 {
+            return None;
+        }
+        let increase = samples
+            .iter()
+            .zip(samples.iter().skip(1))
+            .map(|((_, before), (_, after))| {
+                if after < before {
+                    *after
+                } else {
+                    after - before
+                }
+            })
+            .sum::<f64>();
+        Some(match function {
+            Function::Rate => increase / elapsed,
+            Function::Increase => increase,
+        })
+    }
+
+    pub fn holds(&self, value: f64) -> bool {
+        match self.comparison {
+            Comparison::Greater => value > self.threshold,
+            Comparison::GreaterOrEqual => value >= self.threshold,
+            Comparison::Less => value < self.threshold,
+            Comparison::LessOrEqual => value <= self.threshold,
+            Comparison::Equal => value == self.threshold,
+            Comparison::NotEqual => value != self.threshold,
+        }
+    }
+}
+
+impl Matcher {
+    // a label the series doesn't have is empty, like for prometheus
+    fn matches(&self, labels: &GroupLabels) -> bool {
+        let value = labels.get(&self.label).map_or("", String::as_str);
+        let matches = match self.value {
+            Value::Exact(ref expected) => value == expected,
+            Value::Regex(ref regex) => regex.is_match(value),
+        };
+        matches != self.negated
+    }
+}
+
+struct Parser<'a> {
+    expression: &'a str,
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    // function(selector[window]) comparison number, or selector comparison number
+    fn expression(&mut self) -> Result<Expression, String> {
+        let name = self.identifier()?;
+        let function = match name.as_str() {
+            "rate" => Some(Function::Rate),
+            "increase" => Some(Function::Increase),
+            _ => None,
+        };
+        let (name, matchers, window) = match function {
+            Some(_) if self.eat("(") => {
+                let name = self.identifier()?;
+                let matchers = self.matchers()?;
+                self.expect("[")?;
+                let window = self.window()?;
+                self.expect("]")?;
+                self.expect(")")?;
+                (name, matchers, window)
+            }
+            _ => (name, self.matchers()?, Duration::ZERO),
+        };
+        let comparison = self.comparison()?;
+        let threshold = self.number()?;
+        Ok(Expression {
+            function,
+            name,
+            matchers,
+            window,
+            comparison,
+            threshold,
+        })
+    }
+
+    fn matchers(&mut self) -> Result<Vec<Matcher>, String> {
+        let mut matchers = Vec::new();
+        if !self.eat("{") {
+            return Ok(matchers);
+        }
+        while !self.eat("}") {
+            let label = self.identifier()?;
+            let (negated, regex) = if self.eat("!~") {
+                (true, true)
+            } else if self.eat("=~") {
+                (false, true)
+            } else if self.eat("!=") {
+                (true, false)
+            } else {
+                self.expect("=")?;
+                (false, false)
+            };
+            let value = self.string()?;
+            let value = if regex {
+                let regex = Regex::new(&format!("^(?:{})$", value)).map_err(|err| {
+                    format!(
+                        "invalid regex of {} in {:?}: {}",
+                        label, self.expression, err
+                    )
+                })?;
+                Value::Regex(regex)
+            } else {
+                Value::Exact(value)
+            };
+            matchers.push(Matcher {
+                label,
+                value,
+                negated,
+            });
+            if !self.eat(",") {
+                self.expect("}")?;
+                break;
+            }
+        }
+        Ok(matchers)
+    }
+
+    fn comparison(&mut self) -> Result<Comparison, String> {
+        for (operator, comparison) in [
+            (">=", Comparison::GreaterOrEqual),
+            ("<=", Comparison::LessOrEqual),
+            ("==", Comparison::Equal),
+            ("!=", Comparison::NotEqual),
+            (">", Comparison::Greater),
+            ("<", Comparison::Less),
+        ] {
+            if self.eat(operator) {
+                return Ok(comparison);
+            }
+        }
+        Err(self.error("a comparison (>, >=, <, <=, == or !=)"))
+    }
+
+    fn identifier(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        let end = self
+            .rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == ':'))
+            .unwrap_or(self.rest.len());
+        if end == 0 || self.rest.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(self.error("a name"));
+        }
+        let (identifier, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Ok(identifier.to_string())
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        if !self.rest.starts_with('"') {
+            return Err(self.error("a quoted value"));
+        }
+        let mut value = String::new();
+        let mut chars = self.rest.char_indices().skip(1);
+        while let Some((at, c)) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, c)) => value.push(c),
+                    None => break,
+                },
+                '"' => {
+                    self.rest = &self.rest[at + 1..];
+                    return Ok(value);
+                }
+                c => value.push(c),
+            }
+        }
+        Err(self.error("the closing quote"))
+    }
+
+    fn window(&mut self) -> Result<Duration, String> {
+        self.skip_whitespace();
+        let end = self.rest.find(']').unwrap_or(self.rest.len());
+        let window = humantime::parse_duration(self.rest[..end].trim())
+            .map_err(|_| self.error("a window like 10m"))?;
+        if window.is_zero() {
+            return Err(self.error("a positive window"));
+        }
+        self.rest = &self.rest[end..];
+        Ok(window)
+    }
+
+    fn number(&mut self) -> Result<f64, String> {
+        self.skip_whitespace();
+        let end = self
+            .rest
+            .find(|c: char| c.is_whitespace())
+            .unwrap_or(self.rest.len());
+        let number = self.rest[..end]
+            .parse::<f64>()
+            .map_err(|_| self.error("a number"))?;
+        self.rest = &self.rest[end..];
+        Ok(number)
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        match self.rest.strip_prefix(token) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("{:?}", token)))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    // like `expected a number at 27 of "rate(x[10m]) > y"`
+    fn error(&self, expected: &str) -> String {
+        format!(
+            "expected {} at {} of {:?}",
+            expected,
+            self.expression.len() - self.rest.len() + 1,
+            self.expression
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Expression, Samples};
+    use std::time::{Duration, Instant};
+
+    fn labels(labels: &[(&str, &str)]) -> Vec<(String, String)> {
+        labels
+            .iter()
+            .map(|(label, value)| (label.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn says_where_it_does_not_parse() {
+        let error = |expression: &str| Expression::parse(expression).unwrap_err();
+        assert_eq!(
+            error("rate(x[10m]) > "),
+            r#"expected a number at 16 of "rate(x[10m]) > ""#
+        );
+        assert_eq!(
+            error("x 1"),
+            r#"expected a comparison (>, >=, <, <=, == or !=) at 3 of "x 1""#
+        );
+        assert_eq!(
+            error("x > 1 or y > 2"),
+            r#"expected the end at 7 of "x > 1 or y > 2""#
+        );
+        assert_eq!(
+            error(r#"x{a="b" > 1"#),
+            r#"expected "}" at 9 of "x{a=\"b\" > 1""#
+        );
+        assert_eq!(
+            error("rate(x[0s]) > 1"),
+            r#"expected a positive window at 8 of "rate(x[0s]) > 1""#
+        );
+        assert_eq!(
+            error("increase(x) > 1"),
+            r#"expected "[" at 11 of "increase(x) > 1""#
+        );
+        assert!(error(r#"x{a=~"("} > 1"#).starts_with("invalid regex of a"));
+    }
+
+    // the longest operator wins, >= isn't > followed by garbage
+    #[test]
+    fn compares_with_the_longest_operator() {
+        let holds =
+            |expression: &str, value: f64| Expression::parse(expression).unwrap().holds(value);
+        assert!(holds("x >= 1", 1.0));
+        assert!(!holds("x > 1", 1.0));
+        assert!(holds("x <= 1", 1.0));
+        assert!(!holds("x < 1", 1.0));
+        assert!(holds("x == 1", 1.0));
+        assert!(holds("x != 1", 2.0));
+        assert!(!holds("x != 1", 1.0));
+        assert!(holds("x>0.5", 0.6));
+    }
+
+    #[test]
+    fn selects_by_the_labels() {
+        let expression = Expression::parse(
+            r#"pod_restarts_total{namespace!="kube-system", workload=~"web-.*", zone=""} > 0"#,
+        )
+        .unwrap();
+        let selected = expression.matching(vec![
+            (
+                labels(&[("namespace", "shop"), ("workload", "web-api")]),
+                1.0,
+            ),
+            (
+                labels(&[("namespace", "kube-system"), ("workload", "web-dns")]),
+                2.0,
+            ),
+            // anchored
+            (
+                labels(&[("namespace", "shop"), ("workload", "old-web-api")]),
+                3.0,
+            ),
+            // a label it doesn't have is empty
+            (
+                labels(&[("namespace", "shop"), ("workload", "web-db"), ("zone", "a")]),
+                4.0,
+            ),
+        ]);
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected.values().next(), Some(&1.0));
+
+        let exact = Expression::parse(r#"x{namespace="shop"} > 0"#).unwrap();
+        let negated = Expression::parse(r#"x{namespace!~"sh.*"} > 0"#).unwrap();
+        let series = || {
+            vec![
+                (labels(&[("namespace", "shop")]), 1.0),
+                (labels(&[("namespace", "web")]), 2.0),
+            ]
+        };
+        assert_eq!(exact.matching(series()).values().sum::<f64>(), 1.0);
+        assert_eq!(negated.matching(series()).values().sum::<f64>(), 2.0);
+    }
+
+    // a counter going down started over, from 0
+    #[test]
+    fn takes_counter_resets_as_restarts() {
+        let start = Instant::now();
+        let at = |seconds: u64| start + Duration::from_secs(seconds);
+        let increase = Expression::parse("increase(x[10m]) > 10").unwrap();
+        let rate = Expression::parse("rate(x[10m]) > 0.2").unwrap();
+        let mut samples = Samples::new();
+        for (seconds, value) in [(0, 10.0), (30, 20.0), (60, 5.0)] {
+            increase.record(&mut samples, at(seconds), value);
+        }
+        assert_eq!(increase.value(&samples), Some(15.0));
+        assert!(increase.holds(15.0));
+        assert_eq!(rate.value(&samples), Some(0.25));
+        assert!(rate.holds(0.25));
+    }
+
+    #[test]
+    fn forgets_the_samples_out_of_the_window() {
+        let start = Instant::now();
+        let at = |seconds: u64| start + Duration::from_secs(seconds);
+        let increase = Expression::parse("increase(x[1m]) > 0").unwrap();
+        let mut samples = Samples::new();
+        for (seconds, value) in [(0, 100.0), (30, 101.0), (90, 103.0)] {
+            increase.record(&mut samples, at(seconds), value);
+        }
+        assert_eq!(samples.len(), 2);
+        assert_eq!(increase.value(&samples), Some(2.0));
+    }
+
+    // no value, so neither firing nor resolving on its own
+    #[test]
+    fn has_no_value_without_enough_samples() {
+        let rate = Expression::parse("rate(x[10m]) < 1").unwrap();
+        let mut samples = Samples::new();
+        assert_eq!(rate.value(&samples), None);
+        rate.record(&mut samples, Instant::now(), 3.0);
+        assert_eq!(rate.value(&samples), None);
+
+        let gauge = Expression::parse("x < 1").unwrap();
+        assert_eq!(gauge.value(&Samples::new()), None);
+        gauge.record(&mut samples, Instant::now(), 0.0);
+        assert_eq!(gauge.value(&samples), Some(0.0));
+        // a series that isn't there isn't selected, it isn't 0
+        assert!(gauge.matching(Vec::new()).is_empty());
+    }
+}
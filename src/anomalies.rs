@@ -0,0 +1,260 @@
+use crate::{
+    clock,
+    config::AnomalySettings,
+    metrics::{ANOMALY_SCORE_GAUGE, NAMESPACE_LABEL, SIGNAL_LABEL, WORKLOAD_LABEL},
+    record::EventRecord,
+    sinks::SinkRegistry,
+};
+use axum_prometheus::metrics::gauge;
+use k8s_openapi::chrono::SecondsFormat;
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{debug, info, warn};
+
+// the deviation a baseline is taken to have at least, so that the first restart of a
+// workload that never restarts isn't infinitely anomalous, while a few of them are
+const MIN_DEVIATION: f64 = 0.5;
+
+// the restarts and warnings of each workload against their own moving average. The pod
+// tracker and the workers count them, so like the incidents it's a global.
+pub static ANOMALIES: LazyLock<Anomalies> = LazyLock::new(Anomalies::default);
+
+#[derive(Default)]
+pub struct Anomalies {
+    state: Mutex<State>,
+}
+
+#[derive(Clone, Copy)]
+pub enum Signal {
+    Restarts,
+    Warnings,
+}
+
+const SIGNALS: [Signal; 2] = [Signal::Restarts, Signal::Warnings];
+
+#[derive(Default)]
+struct State {
+    settings: AnomalySettings,
+    // by namespace and workload, like "Deployment/api"
+    workloads: HashMap<(String, String), Baselines>,
+}
+
+struct Baselines {
+    // by signal
+    series: [Series; 2],
+    // the last restart or warning
+    seen: Instant,
+}
+
+#[derive(Default)]
+struct Series {
+    // within the current interval
+    count: f64,
+    // the exponentially weighted moving average and variance of the counts
+    mean: f64,
+    variance: f64,
+    samples: u32,
+    anomalous: bool,
+}
+
+impl Signal {
+    fn name(&self) -> &'static str {
+        match self {
+            Signal::Restarts => "restarts",
+            Signal::Warnings => "warnings",
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self {
+            Signal::Restarts => "AnomalousRestarts",
+            Signal::Warnings => "AnomalousWarnings",
+        }
+    }
+}
+
+impl Anomalies {
+    pub fn configure(&self, settings: &AnomalySettings) {
+        let mut state = self.state.lock().expect("anomalies lock poisoned");
+        state.settings = settings.clone();
+        if !settings.enabled {
+            state.workloads.clear();
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.state
+            .lock()
+            .expect("anomalies lock poisoned")
+            .settings
+            .enabled
+    }
+
+    // restarts of a workload's containers, or Warning events about it. The workload's
+    // baselines start with its first ones, both signals at once.
+    pub fn observe(&self, namespace: &str, workload: &str, signal: Signal, count: u64) {
+        let mut state = self.state.lock().expect("anomalies lock poisoned");
+        if !state.settings.enabled {
+            return;
+        }
+        let key = (namespace.to_string(), workload.to_string());
+        if !state.workloads.contains_key(&key)
+            && state.workloads.len() >= state.settings.max_workloads
+        {
+            debug!(
+                "Not following {} in {}, anomalies.max_workloads reached",
+                workload, namespace
+            );
+            return;
+        }
+        let baselines = state.workloads.entry(key).or_insert_with(|| Baselines {
+            series: Default::default(),
+            seen: clock::instant(),
+        });
+        baselines.series[signal as usize].count += count as f64;
+        baselines.seen = clock::instant();
+    }
+
+    // scores the counts of every interval, delivering the workloads that became
+    // anomalous to the sinks
+    pub async fn run(&self, sinks: SinkRegistry) {
+        let period = self
+            .state
+            .lock()
+            .expect("anomalies lock poisoned")
+            .settings
+            .interval;
+        let mut interval = tokio::time::interval(period);
+        // the first tick is right away, with nothing counted yet
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let (records, names) = self.score();
+            if records.is_empty() || names.is_empty() {
+                continue;
+            }
+            // taken from the registry every time, so reloaded sinks are picked up
+            match sinks.dispatcher(&names) {
+                Ok(dispatcher) => {
+                    for record in records.iter() {
+                        dispatcher.dispatch(record).await;
+                    }
+                }
+                Err(err) => warn!("Could not deliver the anomalies: {}", err),
+            }
+        }
+    }
+
+    fn score(&self) -> (Vec<EventRecord>, Vec<String>) {
+        let mut state = self.state.lock().expect("anomalies lock poisoned");
+        let settings = state.settings.clone();
+        let mut records = Vec::new();
+        state.workloads.retain(|(namespace, workload), baselines| {
+            let forgotten = clock::instant().duration_since(baselines.seen) > settings.retention;
+            for signal in SIGNALS {
+                let series = &mut baselines.series[signal as usize];
+                let labels = [
+                    (NAMESPACE_LABEL, namespace.clone()),
+                    (WORKLOAD_LABEL, workload.clone()),
+                    (SIGNAL_LABEL, signal.name().to_string()),
+                ];
+                if forgotten {
+                    gauge!(ANOMALY_SCORE_GAUGE, &labels).set(0.0);
+                    continue;
+                }
+                let count = std::mem::take(&mut series.count);
+                let mean = series.mean;
+                if let Some(score) = series.score(count, &settings) {
+                    gauge!(ANOMALY_SCORE_GAUGE, &labels).set(score);
+                    let anomalous = score >= settings.threshold;
+                    if anomalous && !series.anomalous {
+                        let record = record(
+                            namespace,
+                            workload,
+                            signal,
+                            count,
+                            mean,
+                            score,
+                            settings.interval,
+                        );
+                        warn!(
+                            "{} in {} is anomalous: {}",
+                            workload, namespace, record.message
+                        );
+                        records.push(record);
+                    } else if !anomalous && series.anomalous {
+                        info!("{} in {} is no longer anomalous", workload, namespace);
+                    }
+                    series.anomalous = anomalous;
+                }
+            }
+            !forgotten
+        });
+        (records, settings.sinks.clone())
+    }
+}
+
+impl Series {
+    // the z-score of the count, once the baseline had its warmup. The count is part of
+    // the baseline afterwards.
+    fn score(&mut self, count: f64, settings: &AnomalySettings) -> Option<f64> {
+        let score = (self.samples >= settings.warmup)
+            .then(|| (count - self.mean) / self.variance.sqrt().max(MIN_DEVIATION));
+        self.add(count, settings.alpha);
+        score
+    }
+
+    fn add(&mut self, count: f64, alpha: f64) {
+        if self.samples == 0 {
+            self.mean = count;
+        } else {
+            let diff = count - self.mean;
+            let increment = alpha * diff;
+            self.mean += increment;
+            self.variance = (1.0 - alpha) * (self.variance + diff * increment);
+        }
+        self.samples = self.samples.saturating_add(1);
+    }
+}
+
+// what the sinks get when a workload becomes anomalous
+fn record(
+    namespace: &str,
+    workload: &str,
+    signal: Signal,
+    count: f64,
+    mean: f64,
+    score: f64,
+    interval: Duration,
+) -> EventRecord {
+    let now = clock::now().to_rfc3339_opts(SecondsFormat::Millis, false);
+    let (kind, name) = workload.split_once('/').unwrap_or(("", workload));
+    EventRecord {
+        uid: format!("{}/{}/{}/{}", namespace, workload, signal.name(), now),
+        namespace: namespace.to_string(),
+        name: format!("{}/{}", workload, signal.name()),
+        kind: "Anomaly".to_string(),
+        object_name: name.to_string(),
+        reason: signal.reason().to_string(),
+        message: format!(
+            "{} {}: {} {} in {} against {:.1} usually (z-score {:.1})",
+            kind,
+            name,
+            count,
+            signal.name(),
+            humantime::format_duration(interval),
+            mean,
+            score
+        ),
+        type_: "Warning".to_string(),
+        source: "k8rs".to_string(),
+        first_timestamp: Some(now.clone()),
+        last_timestamp: Some(now),
+        count: count as i32,
+        owner: Some(workload.to_string()),
+        ..EventRecord::default()
+    }
+}
@@ -0,0 +1,464 @@
+use crate::{
+    config::{RbacAuditSettings, WatcherSettings},
+    error::Error,
+    metrics::{ACTION_LABEL, KIND_LABEL, NAMESPACE_LABEL, RBAC_CHANGES_COUNTER},
+    record::EventRecord,
+    schema::AuditDetails,
+    sinks::SinkRegistry,
+    snapshot::SNAPSHOT,
+    watch::{pausable, watcher_config, WatcherBackoff},
+};
+use axum_prometheus::metrics::counter;
+use futures::{Stream, StreamExt};
+use k8s_openapi::{
+    api::{
+        core::v1::Secret,
+        rbac::v1::{ClusterRole, ClusterRoleBinding, PolicyRule, Role, RoleBinding, Subject},
+    },
+    chrono::{SecondsFormat, Utc},
+    NamespaceResourceScope,
+};
+use kube::{
+    core::PartialObjectMeta,
+    runtime::{metadata_watcher, watcher, WatchStreamExt},
+    Api, Client, Resource, ResourceExt,
+};
+use serde::de::DeserializeOwned;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+};
+use tracing::{info, warn};
+
+// the Secrets holding the long-lived tokens of the service accounts
+const TOKENS: &str = "type=kubernetes.io/service-account-token";
+
+// the objects whose changes are audited, by what they grant, a line for every rule or
+// subject. A change that grants nothing new or less, like a label, isn't one.
+trait Audited: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug + Send + 'static {
+    fn grants(&self) -> Vec<String>;
+}
+
+impl Audited for Role {
+    fn grants(&self) -> Vec<String> {
+        self.rules.iter().flatten().map(rule).collect()
+    }
+}
+
+impl Audited for ClusterRole {
+    fn grants(&self) -> Vec<String> {
+        let mut grants = self.rules.iter().flatten().map(rule).collect::<Vec<_>>();
+        // the rules of an aggregated one are the ones its selectors pick
+        let selectors = self
+            .aggregation_rule
+            .iter()
+            .flat_map(|rule| rule.cluster_role_selectors.iter().flatten());
+        for selector in selectors {
+            let labels = selector
+                .match_labels
+                .iter()
+                .flatten()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect::<Vec<_>>();
+            grants.push(format!("aggregates {}", labels.join(",")));
+        }
+        grants
+    }
+}
+
+impl Audited for RoleBinding {
+    fn grants(&self) -> Vec<String> {
+        binding(&self.role_ref.kind, &self.role_ref.name, &self.subjects)
+    }
+}
+
+impl Audited for ClusterRoleBinding {
+    fn grants(&self) -> Vec<String> {
+        binding(&self.role_ref.kind, &self.role_ref.name, &self.subjects)
+    }
+}
+
+// only the metadata of the tokens is ever fetched, never the tokens themselves
+impl Audited for PartialObjectMeta<Secret> {
+    fn grants(&self) -> Vec<String> {
+        let account = self
+            .annotations()
+            .get("kubernetes.io/service-account.name")
+            .cloned()
+            .unwrap_or_default();
+        let namespace = self.namespace().unwrap_or_default();
+        vec![format!("token of ServiceAccount {}/{}", namespace, account)]
+    }
+}
+
+// like "get,list,watch apps/deployments,apps/replicasets"
+fn rule(rule: &PolicyRule) -> String {
+    let groups = rule.api_groups.clone().unwrap_or_default();
+    let mut resources = Vec::new();
+    for resource in rule.resources.iter().flatten() {
+        for group in groups.iter() {
+            resources.push(match group.is_empty() {
+                true => resource.clone(),
+                false => format!("{}/{}", group, resource),
+            });
+        }
+    }
+    resources.extend(rule.non_resource_urls.iter().flatten().cloned());
+    let mut line = format!("{} {}", rule.verbs.join(","), resources.join(","));
+    if let Some(names) = rule
+        .resource_names
+        .as_ref()
+        .filter(|names| !names.is_empty())
+    {
+        line.push_str(&format!(" named {}", names.join(",")));
+    }
+    line
+}
+
+// the role and every subject it's bound to, like "ServiceAccount ci/deployer"
+fn binding(kind: &str, name: &str, subjects: &Option<Vec<Subject>>) -> Vec<String> {
+    let mut grants = vec![format!("binds {}/{}", kind, name)];
+    for subject in subjects.iter().flatten() {
+        grants.push(match subject.namespace.as_deref() {
+            Some(namespace) => format!("to {} {}/{}", subject.kind, namespace, subject.name),
+            None => format!("to {} {}", subject.kind, subject.name),
+        });
+    }
+    grants
+}
+
+#[derive(Clone, Copy)]
+enum Action {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl Action {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Action::Created => "created",
+            Action::Updated => "updated",
+            Action::Deleted => "deleted",
+        }
+    }
+}
+
+// what was known of every object, by namespace and name
+struct Known {
+    uid: String,
+    grants: Vec<String>,
+}
+
+// watches the Roles, RoleBindings, ClusterRoles and ClusterRoleBindings, and the service
+// account tokens with rbac_audit.tokens, for a trail of who was granted what without the
+// api server's audit log. Every change is counted and sent to the sinks, the objects of
+// the first list having been there before we started.
+pub async fn watch_rbac(
+    client: Client,
+    watcher_settings: &WatcherSettings,
+    settings: &RbacAuditSettings,
+    all_namespaces: bool,
+    sinks: SinkRegistry,
+) {
+    let roles = audit(
+        "roles",
+        watcher(
+            namespaced::<Role>(client.clone(), all_namespaces),
+            watcher_config(watcher_settings),
+        ),
+        watcher_settings,
+        settings,
+        &sinks,
+    );
+    let role_bindings = audit(
+        "rolebindings",
+        watcher(
+            namespaced::<RoleBinding>(client.clone(), all_namespaces),
+            watcher_config(watcher_settings),
+        ),
+        watcher_settings,
+        settings,
+        &sinks,
+    );
+    let cluster_roles = audit(
+        "clusterroles",
+        watcher(
+            Api::<ClusterRole>::all(client.clone()),
+            watcher_config(watcher_settings),
+        ),
+        watcher_settings,
+        settings,
+        &sinks,
+    );
+    let cluster_role_bindings = audit(
+        "clusterrolebindings",
+        watcher(
+            Api::<ClusterRoleBinding>::all(client.clone()),
+            watcher_config(watcher_settings),
+        ),
+        watcher_settings,
+        settings,
+        &sinks,
+    );
+    let tokens = async {
+        if settings.tokens {
+            audit(
+                "tokens",
+                metadata_watcher(
+                    namespaced::<Secret>(client, all_namespaces),
+                    watcher_config(watcher_settings).fields(TOKENS),
+                ),
+                watcher_settings,
+                settings,
+                &sinks,
+            )
+            .await
+        }
+    };
+    futures::join!(
+        roles,
+        role_bindings,
+        cluster_roles,
+        cluster_role_bindings,
+        tokens
+    );
+}
+
+// the namespaced objects are watched where the pods are
+fn namespaced<K>(client: Client, all_namespaces: bool) -> Api<K>
+where
+    K: Resource<DynamicType = (), Scope = NamespaceResourceScope>,
+{
+    match all_namespaces {
+        true => Api::all(client),
+        false => Api::default_namespaced(client),
+    }
+}
+
+async fn audit<K: Audited>(
+    name: &str,
+    stream: impl Stream<Item = Result<watcher::Event<K>, watcher::Error>> + Send,
+    watcher_settings: &WatcherSettings,
+    settings: &RbacAuditSettings,
+    sinks: &SinkRegistry,
+) {
+    let backoff = WatcherBackoff::new(name, &watcher_settings.backoff);
+    let mut stream = Box::pin(pausable(
+        name,
+        stream
+            .backoff(backoff)
+            .inspect(|event| SNAPSHOT.watcher(name, event)),
+    ));
+
+    let mut known = HashMap::<(String, String), Known>::new();
+    let mut relisted = HashSet::new();
+    // the objects of the first list aren't changes
+    let mut listed = false;
+    while let Some(event) = stream.next().await {
+        match event {
+            Ok(watcher::Event::Init) => relisted.clear(),
+            Ok(watcher::Event::InitApply(object)) => {
+                relisted.insert(key(&object));
+                match listed {
+                    true => apply(&mut known, &object, settings, sinks).await,
+                    false => {
+                        known.insert(
+                            key(&object),
+                            Known {
+                                uid: object.uid().unwrap_or_default(),
+                                grants: object.grants(),
+                            },
+                        );
+                    }
+                }
+            }
+            Ok(watcher::Event::InitDone) => {
+                // the objects deleted while we were away
+                let gone = known
+                    .keys()
+                    .filter(|key| !relisted.contains(*key))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for key in gone {
+                    if let Some(object) = known.remove(&key) {
+                        let kind = K::kind(&()).to_string();
+                        let change = Change::deleted(&kind, &key, object);
+                        change.deliver(settings, sinks).await;
+                    }
+                }
+                listed = true;
+            }
+            Ok(watcher::Event::Apply(object)) => apply(&mut known, &object, settings, sinks).await,
+            Ok(watcher::Event::Delete(object)) => {
+                let key = key(&object);
+                let uid = object.uid().unwrap_or_default();
+                known.remove(&key);
+                let change = Change::deleted(
+                    &K::kind(&()),
+                    &key,
+                    Known {
+                        uid,
+                        grants: object.grants(),
+                    },
+                );
+                change.deliver(settings, sinks).await;
+            }
+            Err(err) => Error::new("audit", err).on(name).report(),
+        }
+    }
+}
+
+fn key<K: Resource>(object: &K) -> (String, String) {
+    (object.namespace().unwrap_or_default(), object.name_any())
+}
+
+// an object created, or recreated under the same name, or granting something else
+async fn apply<K: Audited>(
+    known: &mut HashMap<(String, String), Known>,
+    object: &K,
+    settings: &RbacAuditSettings,
+    sinks: &SinkRegistry,
+) {
+    let key = key(object);
+    let uid = object.uid().unwrap_or_default();
+    let grants = object.grants();
+    let (action, added, removed) = match known.get(&key) {
+        Some(old) if old.uid == uid => {
+            if old.grants == grants {
+                return;
+            }
+            let added = grants.iter().filter(|grant| !old.grants.contains(grant));
+            let removed = old.grants.iter().filter(|grant| !grants.contains(grant));
+            (
+                Action::Updated,
+                added.cloned().collect(),
+                removed.cloned().collect(),
+            )
+        }
+        _ => (Action::Created, grants.clone(), Vec::new()),
+    };
+    let change = Change {
+        kind: K::kind(&()).to_string(),
+        namespace: key.0.clone(),
+        name: key.1.clone(),
+        uid: uid.clone(),
+        action,
+        manager: manager(object.meta()),
+        added,
+        removed,
+    };
+    known.insert(key, Known { uid, grants });
+    change.deliver(settings, sinks).await;
+}
+
+// the field manager of the last change, like "kubectl-client-side-apply" or "helm"
+fn manager(meta: &k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta) -> Option<String> {
+    meta.managed_fields
+        .iter()
+        .flatten()
+        .max_by_key(|fields| fields.time.as_ref().map(|time| time.0))
+        .and_then(|fields| fields.manager.clone())
+}
+
+struct Change {
+    kind: String,
+    namespace: String,
+    name: String,
+    uid: String,
+    action: Action,
+    manager: Option<String>,
+    added: Vec<String>,
+    removed: Vec<String>,
+}
+
+impl Change {
+    // who deleted it the objects don't say
+    fn deleted(kind: &str, key: &(String, String), known: Known) -> Self {
+        Change {
+            kind: kind.to_string(),
+            namespace: key.0.clone(),
+            name: key.1.clone(),
+            uid: known.uid,
+            action: Action::Deleted,
+            manager: None,
+            added: Vec::new(),
+            removed: known.grants,
+        }
+    }
+
+    // like "RoleBinding shop/deployers updated by kubectl-edit: +to User alice"
+    fn message(&self) -> String {
+        let object = match self.namespace.is_empty() {
+            true => format!("{} {}", self.kind, self.name),
+            false => format!("{} {}/{}", self.kind, self.namespace, self.name),
+        };
+        let mut message = format!("{} {}", object, self.action.as_str());
+        if let Some(manager) = self.manager.as_ref() {
+            message.push_str(&format!(" by {}", manager));
+        }
+        let grants = self
+            .added
+            .iter()
+            .map(|grant| format!("+{}", grant))
+            .chain(self.removed.iter().map(|grant| format!("-{}", grant)))
+            .collect::<Vec<_>>();
+        if !grants.is_empty() {
+            message.push_str(&format!(": {}", grants.join("; ")));
+        }
+        message
+    }
+
+    async fn deliver(self, settings: &RbacAuditSettings, sinks: &SinkRegistry) {
+        counter!(
+            RBAC_CHANGES_COUNTER,
+            &[
+                (NAMESPACE_LABEL, self.namespace.clone()),
+                (KIND_LABEL, self.kind.clone()),
+                (ACTION_LABEL, self.action.as_str().to_string()),
+            ]
+        )
+        .increment(1);
+        let message = self.message();
+        info!("{}", message);
+        if settings.sinks.is_empty() {
+            return;
+        }
+        let now = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, false);
+        let reason = match self.action {
+            Action::Created => "RbacCreated",
+            Action::Updated => "RbacUpdated",
+            Action::Deleted => "RbacDeleted",
+        };
+        let record = EventRecord {
+            uid: format!("{}/{}/{}", self.uid, self.action.as_str(), now),
+            namespace: self.namespace.clone(),
+            name: self.name.clone(),
+            kind: self.kind.clone(),
+            object_name: self.name.clone(),
+            object_uid: self.uid.clone(),
+            reason: reason.to_string(),
+            message,
+            type_: "Normal".to_string(),
+            source: "k8rs".to_string(),
+            first_timestamp: Some(now.clone()),
+            last_timestamp: Some(now),
+            count: 1,
+            audit: Some(AuditDetails {
+                action: self.action.as_str().to_string(),
+                manager: self.manager,
+                added: self.added,
+                removed: self.removed,
+            }),
+            ..EventRecord::default()
+        };
+        // taken from the registry every time, so reloaded sinks are picked up
+        match sinks.dispatcher(&settings.sinks) {
+            Ok(dispatcher) => dispatcher.dispatch(&record).await,
+            Err(err) => warn!(
+                "Could not deliver the change of {} {}: {}",
+                record.kind, record.name, err
+            ),
+        }
+    }
+}
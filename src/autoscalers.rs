@@ -0,0 +1,81 @@
+use crate::metrics::{
+    DIRECTION_LABEL, HPA_FAILURES_COUNTER, HPA_LABEL, HPA_RESCALES_COUNTER, NAMESPACE_LABEL,
+    REASON_LABEL,
+};
+use axum_prometheus::metrics::counter;
+use k8s_openapi::api::core::v1::Event;
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+};
+
+// what the hpa controller reports when it can't work out the replicas it wants
+const FAILURES: &[&str] = &[
+    "FailedGetResourceMetric",
+    "FailedGetPodsMetric",
+    "FailedGetObjectMetric",
+    "FailedGetExternalMetric",
+    "FailedGetContainerResourceMetric",
+    "FailedComputeMetricsReplicas",
+    "FailedRescale",
+];
+
+// the last size each autoscaler rescaled to, by uid.
+// The events only tell the new size, so that's what the next one is compared to.
+static SIZES: LazyLock<Mutex<HashMap<String, u32>>> = LazyLock::new(Default::default);
+
+// the events about HorizontalPodAutoscalers: their rescales, by direction, and
+// the failures to get the metrics they scale on
+pub fn handle_event(event: &Event) {
+    let object = &event.involved_object;
+    let reason = event.reason.clone().unwrap_or_default();
+    let labels = |name: &'static str, value: String| {
+        [
+            (
+                NAMESPACE_LABEL,
+                object.namespace.clone().unwrap_or_default(),
+            ),
+            (HPA_LABEL, object.name.clone().unwrap_or_default()),
+            (name, value),
+        ]
+    };
+
+    if reason == "SuccessfulRescale" {
+        let message = event.message.as_deref().unwrap_or_default();
+        let size = new_size(message);
+        let previous = match size {
+            Some(size) => SIZES
+                .lock()
+                .expect("autoscalers lock poisoned")
+                .insert(object.uid.clone().unwrap_or_default(), size),
+            None => None,
+        };
+        counter!(
+            HPA_RESCALES_COUNTER,
+            &labels(DIRECTION_LABEL, direction(previous, size, message).into())
+        )
+        .increment(1);
+    } else if FAILURES.contains(&reason.as_str()) {
+        counter!(HPA_FAILURES_COUNTER, &labels(REASON_LABEL, reason)).increment(1);
+    }
+}
+
+// the controller's message looks like
+// "New size: 5; reason: cpu resource utilization (percentage of request) above target"
+fn new_size(message: &str) -> Option<u32> {
+    let (_, rest) = message.split_once("New size:")?;
+    let size = rest.split(';').next()?;
+    size.trim().parse().ok()
+}
+
+// up or down from the size we saw last, or from the reason when it's
+// the first rescale we see since starting
+fn direction(previous: Option<u32>, size: Option<u32>, message: &str) -> &'static str {
+    match (previous, size) {
+        (Some(previous), Some(size)) if size > previous => "up",
+        (Some(previous), Some(size)) if size < previous => "down",
+        _ if message.contains("above target") => "up",
+        _ if message.contains("below target") => "down",
+        _ => "unknown",
+    }
+}
@@ -0,0 +1,187 @@
+use crate::{
+    config::{Cli, Config},
+    demo::{Fleet, Generator, DEFAULT_RATES},
+    discovery::Kinds,
+    error::ErrorKind,
+    logging::{LogHandle, LogSampler},
+    memory, messages,
+    metrics::{initialize_counters, install_recorder},
+    namespaces::NamespaceFilter,
+    outbound, payloads,
+    pipeline::handle_event,
+    pods::PodTracker,
+    record::EventRecord,
+    severity,
+    sinks::SinkRegistry,
+};
+use clap::Args;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::info;
+
+// pushes synthetic events through the pod pipeline to measure it
+#[derive(Args, Debug, Clone)]
+pub struct BenchArgs {
+    /// The events pushed a second, as many as the pipeline takes when unset
+    #[arg(long)]
+    pub rate: Option<f64>,
+
+    /// For how long the events are pushed
+    #[arg(long, default_value = "10s", value_parser = humantime::parse_duration)]
+    pub duration: Duration,
+
+    /// How many pods the events are about
+    #[arg(long, default_value_t = 100)]
+    pub pods: usize,
+
+    /// Deliver the events to these sinks instead of the pipeline's sinks
+    #[arg(long)]
+    pub sink: Vec<String>,
+
+    /// Don't deliver the events to any sink, only measure the filters and the metrics
+    #[arg(long, conflicts_with = "sink")]
+    pub no_sinks: bool,
+}
+
+// the stages every event goes through, in order
+const STAGES: [&str; 4] = ["filter", "metrics", "record", "sinks"];
+
+// runs the events of the demo through the filters, the metrics and the sinks of the
+// config at the asked rate, then prints the throughput and how long each stage took
+pub async fn run(cli: &Cli, args: &BenchArgs, log: &LogHandle) -> Result<(), ErrorKind> {
+    if args
+        .rate
+        .is_some_and(|rate| !rate.is_finite() || rate <= 0.0)
+    {
+        return Err("--rate must be a positive number of events a second".into());
+    }
+    if args.pods == 0 {
+        return Err("--pods must be at least 1".into());
+    }
+    // the log line of every event would be most of what's measured, and drown the
+    // report. RUST_LOG brings them back.
+    if std::env::var_os("RUST_LOG").is_none() {
+        log.set("warn")?;
+    }
+
+    let config = Config::load(cli)?;
+    install_recorder(&config.metrics)?;
+    initialize_counters();
+    messages::configure(&config.messages)?;
+    severity::configure(&config.severities);
+    memory::configure(&config.memory);
+    payloads::configure(&config.payloads);
+    outbound::configure(&config.outbound)?;
+    let sinks = SinkRegistry::from_settings(&config.sinks, &config.suppression)?;
+    let dispatcher = if args.no_sinks {
+        sinks.dispatcher(&[])?
+    } else if !args.sink.is_empty() {
+        sinks.dispatcher(&args.sink)?
+    } else {
+        sinks.dispatcher(&config.pipeline.sinks)?
+    };
+
+    let fleet = Arc::new(Fleet::new(args.pods));
+    let enricher = fleet.enricher(&PodTracker::default(), &config.enrichment);
+    let sampler = LogSampler::new(&config.pipeline.log_sampling);
+    let kinds = Kinds::new(config.event_kinds());
+    let namespaces = NamespaceFilter::new(&config.namespaces);
+    let mut generator = Generator::new(fleet, DEFAULT_RATES);
+
+    // the events are made up before they're due, so making them isn't measured
+    let total = args
+        .rate
+        .map(|rate| (rate * args.duration.as_secs_f64()).ceil() as usize);
+    let mut stages: [Vec<Duration>; 4] = Default::default();
+    let mut pushed = 0;
+    let mut kept = 0;
+    let mut behind = Duration::ZERO;
+    let started = Instant::now();
+    loop {
+        let event = generator.next();
+        match (args.rate, total) {
+            (Some(rate), Some(total)) => {
+                if pushed >= total {
+                    break;
+                }
+                let due = started + Duration::from_secs_f64(pushed as f64 / rate);
+                let now = Instant::now();
+                if due > now {
+                    tokio::time::sleep_until(due.into()).await;
+                } else {
+                    behind = behind.max(now - due);
+                }
+            }
+            _ if started.elapsed() >= args.duration => break,
+            _ => {}
+        }
+        pushed += 1;
+
+        // the events left out by the filters only count in the filter stage
+        let start = Instant::now();
+        let allowed = kinds.contains(&event.involved_object) && namespaces.allows_event(&event);
+        stages[0].push(start.elapsed());
+        if !allowed {
+            continue;
+        }
+        kept += 1;
+        let start = Instant::now();
+        handle_event(&event, &enricher, &sampler);
+        stages[1].push(start.elapsed());
+        let start = Instant::now();
+        let record = EventRecord::new(&event, &enricher);
+        stages[2].push(start.elapsed());
+        let start = Instant::now();
+        dispatcher.dispatch(&record).await;
+        stages[3].push(start.elapsed());
+    }
+    let elapsed = started.elapsed();
+    let flushing = Instant::now();
+    dispatcher.flush().await;
+    let flushed = flushing.elapsed();
+    info!("Pushed {} events through the pipeline", pushed);
+
+    println!(
+        "pushed {} events in {:.2?}, {:.0} a second{}, {} kept by the filters",
+        pushed,
+        elapsed,
+        pushed as f64 / elapsed.as_secs_f64(),
+        match args.rate {
+            Some(rate) => format!(" ({} asked)", rate),
+            None => String::new(),
+        },
+        kept
+    );
+    if behind > Duration::ZERO {
+        println!("the pipeline fell behind the rate by up to {:.2?}", behind);
+    }
+    println!(
+        "{:<8} {:>10} {:>10} {:>10} {:>10} {:>10}",
+        "stage", "mean", "p50", "p90", "p99", "max"
+    );
+    for (name, times) in STAGES.iter().zip(stages.iter_mut()) {
+        times.sort();
+        let mean = times.iter().sum::<Duration>() / times.len().max(1) as u32;
+        println!(
+            "{:<8} {:>10.2?} {:>10.2?} {:>10.2?} {:>10.2?} {:>10.2?}",
+            name,
+            mean,
+            percentile(times, 0.5),
+            percentile(times, 0.9),
+            percentile(times, 0.99),
+            times.last().copied().unwrap_or_default()
+        );
+    }
+    println!("flushing the sinks took {:.2?}", flushed);
+    Ok(())
+}
+
+// of sorted times
+fn percentile(times: &[Duration], quantile: f64) -> Duration {
+    if times.is_empty() {
+        return Duration::ZERO;
+    }
+    times[((times.len() - 1) as f64 * quantile).round() as usize]
+}
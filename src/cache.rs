@@ -0,0 +1,158 @@
+use crate::{
+    config::WatcherSettings,
+    error::Error,
+    namespaces::NamespaceFilter,
+    nodes::NodeTracker,
+    pods::PodTracker,
+    snapshot::SNAPSHOT,
+    watch::{pausable, resyncing, watcher_config, Gaps, WatcherBackoff},
+};
+use futures::StreamExt;
+use k8s_openapi::api::{
+    apps::v1::ReplicaSet,
+    core::v1::{Namespace, Node, Pod, Service},
+};
+use kube::{
+    runtime::{
+        reflector::{self, Store},
+        watcher, WatchStreamExt,
+    },
+    Api, Client, Resource, ResourceExt,
+};
+use serde::de::DeserializeOwned;
+use std::{fmt::Debug, future::Future, hash::Hash, sync::Arc};
+
+// a local copy of the pods we care about, kept up to date by a watcher,
+// so we can look pods up without asking the api server every time.
+pub type PodStore = Arc<Store<Pod>>;
+// the same, for the nodes of the cluster
+pub type NodeStore = Arc<Store<Node>>;
+// and for the namespaces, for their labels
+pub type NamespaceStore = Arc<Store<Namespace>>;
+// and for the replicasets, for the deployments owning them
+pub type ReplicaSetStore = Arc<Store<ReplicaSet>>;
+// and for the services, for the pods their selectors pick
+pub type ServiceStore = Arc<Store<Service>>;
+
+// creates the pod cache and the future that keeps it up to date.
+// The store is empty until the future is running and done with its initial list.
+// The tracker sees every change to the pods on their way in, but for the ones of the
+// namespaces the filter leaves out. The pods of every namespace are cached when the
+// events of discovered namespaces are watched.
+pub fn pod_cache(
+    client: Client,
+    settings: &WatcherSettings,
+    tracker: Arc<PodTracker>,
+    all_namespaces: bool,
+    filter: NamespaceFilter,
+) -> (PodStore, impl Future<Output = ()> + Send + 'static) {
+    let api = match all_namespaces {
+        true => Api::all(client),
+        false => Api::default_namespaced(client),
+    };
+    cache(api, "pods", settings, move |event| {
+        let left_out = match event {
+            watcher::Event::Apply(pod)
+            | watcher::Event::InitApply(pod)
+            | watcher::Event::Delete(pod) => pod
+                .namespace()
+                .is_some_and(|namespace| !filter.allows(&namespace)),
+            watcher::Event::Init | watcher::Event::InitDone => false,
+        };
+        if !left_out {
+            tracker.observe(event)
+        }
+    })
+}
+
+// nodes aren't namespaced, so this one always watches the whole cluster.
+// The tracker exports what the nodes can run.
+pub fn node_cache(
+    client: Client,
+    settings: &WatcherSettings,
+    tracker: Arc<NodeTracker>,
+) -> (NodeStore, impl Future<Output = ()> + Send + 'static) {
+    cache(Api::all(client), "nodes", settings, move |event| {
+        tracker.observe(event)
+    })
+}
+
+// the same goes for namespaces
+pub fn namespace_cache(
+    client: Client,
+    settings: &WatcherSettings,
+) -> (NamespaceStore, impl Future<Output = ()> + Send + 'static) {
+    cache(Api::all(client), "namespace-cache", settings, |_| {})
+}
+
+// the replicasets are watched where the pods are
+pub fn replicaset_cache(
+    client: Client,
+    settings: &WatcherSettings,
+    all_namespaces: bool,
+) -> (ReplicaSetStore, impl Future<Output = ()> + Send + 'static) {
+    let api = match all_namespaces {
+        true => Api::all(client),
+        false => Api::default_namespaced(client),
+    };
+    cache(api, "replicasets", settings, |_| {})
+}
+
+// and so are the services
+pub fn service_cache(
+    client: Client,
+    settings: &WatcherSettings,
+    all_namespaces: bool,
+) -> (ServiceStore, impl Future<Output = ()> + Send + 'static) {
+    let api = match all_namespaces {
+        true => Api::all(client),
+        false => Api::default_namespaced(client),
+    };
+    cache(api, "services", settings, |_| {})
+}
+
+fn cache<K>(
+    api: Api<K>,
+    name: &'static str,
+    settings: &WatcherSettings,
+    observe: impl Fn(&watcher::Event<K>) + Send + 'static,
+) -> (Arc<Store<K>>, impl Future<Output = ()> + Send + 'static)
+where
+    K: Resource + Clone + DeserializeOwned + Debug + Send + Sync + 'static,
+    K::DynamicType: Default + Eq + Hash + Clone + Send + Sync,
+{
+    let (reader, writer) = reflector::store();
+
+    let settings = settings.clone();
+    let resync = settings.resync;
+    let mut gaps = Gaps::new(name);
+    let stream = pausable(
+        name,
+        resyncing(name, resync, move || {
+            watcher(api.clone(), watcher_config(&settings))
+                .backoff(WatcherBackoff::new(name, &settings.backoff))
+        }),
+    )
+    .inspect(move |event| {
+        SNAPSHOT.watcher(name, event);
+        if let Ok(event) = event {
+            gaps.observe(event);
+            observe(event);
+        }
+    })
+    // we never look at these, so no need to keep them in memory
+    .modify(|object| object.managed_fields_mut().clear())
+    .reflect(writer)
+    .touched_objects();
+
+    let reflector = async move {
+        let mut stream = Box::pin(stream);
+        while let Some(object) = stream.next().await {
+            if let Err(err) = object {
+                Error::new(name, err).report();
+            }
+        }
+    };
+
+    (Arc::new(reader), reflector)
+}
@@ -0,0 +1,293 @@
+use crate::{
+    config::{CertificateSettings, WatcherSettings},
+    error::Error,
+    metrics::{NAMESPACE_LABEL, SECRET_LABEL},
+    record::EventRecord,
+    registry::CERTIFICATE_EXPIRY,
+    sinks::SinkRegistry,
+    snapshot::SNAPSHOT,
+    watch::{pausable, watcher_config, WatcherBackoff},
+};
+use futures::StreamExt;
+use k8s_openapi::{
+    api::core::v1::Secret,
+    chrono::{DateTime, NaiveDateTime, Utc},
+};
+use kube::{
+    runtime::{watcher, WatchStreamExt},
+    Api, Client, ResourceExt,
+};
+use std::collections::{HashMap, HashSet};
+use tracing::warn;
+
+// the DER tags on the way to the certificate's validity
+const SEQUENCE: u8 = 0x30;
+const INTEGER: u8 = 0x02;
+const VERSION: u8 = 0xa0;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+
+type Key = (String, String);
+
+struct Expiry {
+    uid: String,
+    not_after: DateTime<Utc>,
+    // whether the sinks were told it's expiring, once for every certificate
+    notified: bool,
+}
+
+// watches the Secrets of type kubernetes.io/tls for when their certificate expires,
+// telling the sinks once it's about to. Only tls.crt is read, never the key.
+pub async fn watch_certificates(
+    client: Client,
+    watcher_settings: &WatcherSettings,
+    settings: &CertificateSettings,
+    sinks: SinkRegistry,
+) {
+    let api: Api<Secret> = Api::default_namespaced(client);
+    let backoff = WatcherBackoff::new("certificates", &watcher_settings.backoff);
+    let mut stream = Box::pin(pausable(
+        "certificates",
+        watcher(
+            api,
+            watcher_config(watcher_settings).fields("type=kubernetes.io/tls"),
+        )
+        .backoff(backoff)
+        .inspect(|event| SNAPSHOT.watcher("certificates", event)),
+    ));
+
+    let mut expiries = HashMap::<Key, Expiry>::new();
+    let mut relisted = HashSet::new();
+    let mut interval = tokio::time::interval(settings.check_interval);
+    loop {
+        let event = tokio::select! {
+            event = stream.next() => match event {
+                Some(event) => event,
+                None => return,
+            },
+            _ = interval.tick() => {
+                notify(&mut expiries, settings, &sinks).await;
+                continue;
+            }
+        };
+        match event {
+            Ok(watcher::Event::Init) => relisted.clear(),
+            Ok(watcher::Event::InitApply(secret)) => {
+                relisted.insert(key(&secret));
+                apply(&mut expiries, &secret);
+            }
+            Ok(watcher::Event::InitDone) => {
+                // forget the secrets deleted while we were away
+                let gone = expiries
+                    .keys()
+                    .filter(|key| !relisted.contains(*key))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for key in gone.iter() {
+                    forget(&mut expiries, key);
+                }
+                notify(&mut expiries, settings, &sinks).await;
+            }
+            Ok(watcher::Event::Apply(secret)) => {
+                apply(&mut expiries, &secret);
+                notify(&mut expiries, settings, &sinks).await;
+            }
+            Ok(watcher::Event::Delete(secret)) => forget(&mut expiries, &key(&secret)),
+            Err(err) => Error::new("certificates", err).report(),
+        }
+    }
+}
+
+fn key(secret: &Secret) -> Key {
+    (secret.namespace().unwrap_or_default(), secret.name_any())
+}
+
+fn apply(expiries: &mut HashMap<Key, Expiry>, secret: &Secret) {
+    let key = key(secret);
+    let certificate = secret
+        .data
+        .as_ref()
+        .and_then(|data| data.get("tls.crt"))
+        .ok_or("there's no tls.crt")
+        .and_then(|certificate| not_after(&certificate.0));
+    let not_after = match certificate {
+        Ok(not_after) => not_after,
+        // only says what's wrong with it, nothing of what's in it
+        Err(err) => {
+            warn!(
+                "Could not read the certificate of Secret {}/{}: {}",
+                key.0, key.1, err
+            );
+            forget(expiries, &key);
+            return;
+        }
+    };
+    CERTIFICATE_EXPIRY.set(
+        &[
+            (NAMESPACE_LABEL, key.0.clone()),
+            (SECRET_LABEL, key.1.clone()),
+        ],
+        not_after.timestamp() as f64,
+    );
+    // a renewed certificate gets told about again
+    if expiries
+        .get(&key)
+        .is_some_and(|known| known.not_after == not_after)
+    {
+        return;
+    }
+    expiries.insert(
+        key,
+        Expiry {
+            uid: secret.uid().unwrap_or_default(),
+            not_after,
+            notified: false,
+        },
+    );
+}
+
+fn forget(expiries: &mut HashMap<Key, Expiry>, key: &Key) {
+    CERTIFICATE_EXPIRY.remove_where(&[(NAMESPACE_LABEL, &key.0), (SECRET_LABEL, &key.1)]);
+    expiries.remove(key);
+}
+
+// logs the certificates expiring within settings.warn_before, and sends them to the sinks
+async fn notify(
+    expiries: &mut HashMap<Key, Expiry>,
+    settings: &CertificateSettings,
+    sinks: &SinkRegistry,
+) {
+    let now = Utc::now();
+    for ((namespace, name), expiry) in expiries.iter_mut() {
+        let left = (expiry.not_after - now).to_std().unwrap_or_default();
+        if expiry.notified || left >= settings.warn_before {
+            continue;
+        }
+        expiry.notified = true;
+        let (reason, message) = match left.is_zero() {
+            true => (
+                "CertificateExpired",
+                format!(
+                    "The certificate of Secret {}/{} expired at {}",
+                    namespace,
+                    name,
+                    expiry.not_after.to_rfc3339()
+                ),
+            ),
+            false => (
+                "CertificateExpiring",
+                format!(
+                    "The certificate of Secret {}/{} expires in {} (at {})",
+                    namespace,
+                    name,
+                    humantime::format_duration(std::time::Duration::from_secs(left.as_secs())),
+                    expiry.not_after.to_rfc3339()
+                ),
+            ),
+        };
+        warn!("{}", message);
+        if settings.sinks.is_empty() {
+            continue;
+        }
+        let record = EventRecord {
+            namespace: namespace.clone(),
+            name: name.clone(),
+            kind: "Secret".to_string(),
+            object_name: name.clone(),
+            object_uid: expiry.uid.clone(),
+            reason: reason.to_string(),
+            message,
+            type_: "Warning".to_string(),
+            source: "k8rs".to_string(),
+            last_timestamp: Some(now.to_rfc3339()),
+            count: 1,
+            ..EventRecord::default()
+        };
+        // taken from the registry every time, so reloaded sinks are picked up
+        match sinks.dispatcher(&settings.sinks) {
+            Ok(dispatcher) => dispatcher.dispatch(&record).await,
+            Err(err) => warn!(
+                "Could not deliver the expiry of {}/{}: {}",
+                namespace, name, err
+            ),
+        }
+    }
+}
+
+// the notAfter of the first certificate of a PEM bundle, the server's own. The DER is
+// walked by hand, the validity is all we need out of it:
+// Certificate ::= SEQUENCE { tbsCertificate SEQUENCE { [0] version OPTIONAL,
+//     serialNumber INTEGER, signature SEQUENCE, issuer SEQUENCE,
+//     validity SEQUENCE { notBefore Time, notAfter Time }, ... }, ... }
+fn not_after(pem: &[u8]) -> Result<DateTime<Utc>, &'static str> {
+    let certificate = rustls_pemfile::certs(&mut &pem[..])
+        .next()
+        .ok_or("tls.crt has no certificate")?
+        .map_err(|_| "tls.crt isn't valid PEM")?;
+    let (certificate, _) = element(certificate.as_ref(), SEQUENCE)?;
+    let (tbs, _) = element(certificate, SEQUENCE)?;
+    let mut rest = tbs;
+    if rest.first() == Some(&VERSION) {
+        rest = element(rest, VERSION)?.1;
+    }
+    for tag in [INTEGER, SEQUENCE, SEQUENCE] {
+        rest = element(rest, tag)?.1;
+    }
+    let (validity, _) = element(rest, SEQUENCE)?;
+    let (_, rest) = time(validity)?;
+    let (not_after, _) = time(rest)?;
+    Ok(not_after)
+}
+
+// the contents of the element with the given tag at the start of the input, and what's after
+fn element(input: &[u8], tag: u8) -> Result<(&[u8], &[u8]), &'static str> {
+    const INVALID: &str = "the certificate isn't valid DER";
+    if input.first() != Some(&tag) {
+        return Err(INVALID);
+    }
+    let first = *input.get(1).ok_or(INVALID)?;
+    let (length, start) = match first {
+        length if length < 0x80 => (length as usize, 2),
+        // the long form, with that many bytes of length
+        bytes @ 0x81..=0x84 => {
+            let bytes = (bytes & 0x7f) as usize;
+            let length = input
+                .get(2..2 + bytes)
+                .ok_or(INVALID)?
+                .iter()
+                .fold(0usize, |length, byte| (length << 8) | *byte as usize);
+            (length, 2 + bytes)
+        }
+        _ => return Err(INVALID),
+    };
+    let end = start.checked_add(length).ok_or(INVALID)?;
+    let contents = input.get(start..end).ok_or(INVALID)?;
+    Ok((contents, &input[end..]))
+}
+
+// a UTCTime (YYMMDDHHMMSSZ, from 1950 to 2049) or a GeneralizedTime (YYYYMMDDHHMMSSZ)
+fn time(input: &[u8]) -> Result<(DateTime<Utc>, &[u8]), &'static str> {
+    const INVALID: &str = "the certificate has an invalid validity";
+    let (tag, contents, rest) = match input.first() {
+        Some(&UTC_TIME) => {
+            let (contents, rest) = element(input, UTC_TIME)?;
+            (UTC_TIME, contents, rest)
+        }
+        Some(&GENERALIZED_TIME) => {
+            let (contents, rest) = element(input, GENERALIZED_TIME)?;
+            (GENERALIZED_TIME, contents, rest)
+        }
+        _ => return Err(INVALID),
+    };
+    let text = std::str::from_utf8(contents).map_err(|_| INVALID)?;
+    let text = match tag {
+        UTC_TIME => match text.get(..2).and_then(|year| year.parse::<u32>().ok()) {
+            Some(year) if year < 50 => format!("20{}", text),
+            Some(_) => format!("19{}", text),
+            None => return Err(INVALID),
+        },
+        _ => text.to_string(),
+    };
+    let time = NaiveDateTime::parse_from_str(&text, "%Y%m%d%H%M%SZ").map_err(|_| INVALID)?;
+    Ok((time.and_utc(), rest))
+}
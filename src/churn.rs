@@ -0,0 +1,260 @@
+use crate::{
+    clock,
+    metrics::{
+        CAUSE_LABEL, NAMESPACE_LABEL, WORKLOAD_CHURN_GAUGE, WORKLOAD_LABEL,
+        WORKLOAD_PODS_CREATED_COUNTER, WORKLOAD_PODS_DELETED_COUNTER,
+        WORKLOAD_VOLUNTARY_DELETIONS_GAUGE,
+    },
+    pods::workload,
+};
+use axum_prometheus::metrics::{counter, gauge};
+use k8s_openapi::api::core::v1::Pod;
+use kube::ResourceExt;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// the window of workload_pod_churn and workload_pod_voluntary_deletions_ratio, the
+// counters are there for the others
+pub const WINDOW: Duration = Duration::from_secs(3600);
+
+// why a pod was deleted, from its last state
+#[derive(Clone, Copy, PartialEq)]
+enum Cause {
+    // by its controller, rolling out or scaling down
+    Rollout,
+    // a pod of no controller, deleted by someone
+    Deleted,
+    Evicted,
+    Preempted,
+    OomKilled,
+    NodeFailure,
+    // deleted while we were away
+    Unknown,
+}
+
+impl Cause {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Cause::Rollout => "rollout",
+            Cause::Deleted => "deleted",
+            Cause::Evicted => "evicted",
+            Cause::Preempted => "preempted",
+            Cause::OomKilled => "oom_killed",
+            Cause::NodeFailure => "node_failure",
+            Cause::Unknown => "unknown",
+        }
+    }
+
+    // unknown when we don't know
+    fn voluntary(&self) -> Option<bool> {
+        match self {
+            Cause::Rollout | Cause::Deleted => Some(true),
+            Cause::Unknown => None,
+            _ => Some(false),
+        }
+    }
+
+    fn of(pod: &Pod) -> Self {
+        let status = pod.status.as_ref();
+        // since 1.26 the one deleting a pod for a disruption says why in a condition
+        let disruption = status
+            .and_then(|status| status.conditions.as_ref())
+            .and_then(|conditions| {
+                conditions
+                    .iter()
+                    .find(|condition| condition.type_ == "DisruptionTarget")
+            })
+            .filter(|condition| condition.status == "True")
+            .and_then(|condition| condition.reason.as_deref());
+        match disruption {
+            Some("PreemptionByScheduler") => return Cause::Preempted,
+            Some("DeletionByTaintManager" | "DeletionByPodGC") => return Cause::NodeFailure,
+            Some("EvictionByEvictionAPI" | "TerminationByKubelet") => return Cause::Evicted,
+            _ => {}
+        }
+        match status.and_then(|status| status.reason.as_deref()) {
+            Some("Evicted") => return Cause::Evicted,
+            Some("NodeLost" | "Terminated" | "Shutdown" | "NodeShutdown") => {
+                return Cause::NodeFailure
+            }
+            _ => {}
+        }
+        // a container that stopped on an OOM and isn't running again
+        let oom_killed = status
+            .and_then(|status| status.container_statuses.as_ref())
+            .into_iter()
+            .flatten()
+            .any(|container| {
+                let running = container
+                    .state
+                    .as_ref()
+                    .is_some_and(|state| state.running.is_some());
+                let terminated = container
+                    .state
+                    .as_ref()
+                    .and_then(|state| state.terminated.as_ref())
+                    .or(container
+                        .last_state
+                        .as_ref()
+                        .and_then(|state| state.terminated.as_ref()));
+                !running
+                    && terminated
+                        .is_some_and(|terminated| terminated.reason.as_deref() == Some("OOMKilled"))
+            });
+        if oom_killed {
+            return Cause::OomKilled;
+        }
+        match pod
+            .owner_references()
+            .iter()
+            .any(|owner| owner.controller == Some(true))
+        {
+            true => Cause::Rollout,
+            false => Cause::Deleted,
+        }
+    }
+}
+
+// the pods of each workload created and deleted, the deletions by cause, to tell a
+// deployment flapping from one whose pods are evicted or OOM killed
+#[derive(Default)]
+pub struct Churn {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    // the namespace and workload of each pod, by uid
+    pods: HashMap<String, (String, String)>,
+    windows: HashMap<(String, String), Window>,
+}
+
+#[derive(Default)]
+struct Window {
+    created: VecDeque<Instant>,
+    // and whether they were voluntary
+    deleted: VecDeque<(Instant, Option<bool>)>,
+}
+
+impl Churn {
+    // a pod of the pod cache, new ones only counted when counted is set: those of the
+    // first list were there before we started
+    pub fn pod(&self, pod: &Pod, counted: bool) {
+        let Some(uid) = pod.uid() else {
+            return;
+        };
+        let mut state = self.lock();
+        if state.pods.contains_key(&uid) {
+            return;
+        }
+        let key = (pod.namespace().unwrap_or_default(), workload(pod));
+        if counted {
+            counter!(
+                WORKLOAD_PODS_CREATED_COUNTER,
+                &[
+                    (NAMESPACE_LABEL, key.0.clone()),
+                    (WORKLOAD_LABEL, key.1.clone()),
+                ]
+            )
+            .increment(1);
+            let window = state.windows.entry(key.clone()).or_default();
+            window.created.push_back(clock::instant());
+        }
+        state.pods.insert(uid, key);
+    }
+
+    // a pod deleted, as it was last
+    pub fn gone(&self, pod: &Pod) {
+        let Some(uid) = pod.uid() else {
+            return;
+        };
+        self.lock().deleted(&uid, Cause::of(pod));
+    }
+
+    // a pod the flag no longer follows
+    pub fn forget(&self, uid: &str) {
+        self.lock().pods.remove(uid);
+    }
+
+    // after a re-list, the pods that weren't listed again were deleted while we were away
+    pub fn retain(&self, listed: &HashSet<String>) {
+        let mut state = self.lock();
+        let gone = state
+            .pods
+            .keys()
+            .filter(|uid| !listed.contains(*uid))
+            .cloned()
+            .collect::<Vec<_>>();
+        for uid in gone {
+            state.deleted(&uid, Cause::Unknown);
+        }
+    }
+
+    // the gauges of the last WINDOW, the workloads without churn in it being let go of
+    pub fn refresh(&self) {
+        let mut state = self.lock();
+        let now = clock::instant();
+        state.windows.retain(|(namespace, workload), window| {
+            while window
+                .created
+                .front()
+                .is_some_and(|at| now.duration_since(*at) > WINDOW)
+            {
+                window.created.pop_front();
+            }
+            while window
+                .deleted
+                .front()
+                .is_some_and(|(at, _)| now.duration_since(*at) > WINDOW)
+            {
+                window.deleted.pop_front();
+            }
+            let labels = [
+                (NAMESPACE_LABEL, namespace.clone()),
+                (WORKLOAD_LABEL, workload.clone()),
+            ];
+            gauge!(WORKLOAD_CHURN_GAUGE, &labels)
+                .set((window.created.len() + window.deleted.len()) as f64);
+            // of the deletions whose cause we know
+            let known = window
+                .deleted
+                .iter()
+                .filter_map(|(_, voluntary)| *voluntary)
+                .collect::<Vec<_>>();
+            let voluntary = known.iter().filter(|voluntary| **voluntary).count();
+            gauge!(WORKLOAD_VOLUNTARY_DELETIONS_GAUGE, &labels).set(match known.len() {
+                0 => 1.0,
+                deleted => voluntary as f64 / deleted as f64,
+            });
+            !window.created.is_empty() || !window.deleted.is_empty()
+        });
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("churn lock poisoned")
+    }
+}
+
+impl State {
+    fn deleted(&mut self, uid: &str, cause: Cause) {
+        let Some(key) = self.pods.remove(uid) else {
+            return;
+        };
+        counter!(
+            WORKLOAD_PODS_DELETED_COUNTER,
+            &[
+                (NAMESPACE_LABEL, key.0.clone()),
+                (WORKLOAD_LABEL, key.1.clone()),
+                (CAUSE_LABEL, cause.as_str().to_string()),
+            ]
+        )
+        .increment(1);
+        let window = self.windows.entry(key).or_default();
+        window
+            .deleted
+            .push_back((clock::instant(), cause.voluntary()));
+    }
+}
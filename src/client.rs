@@ -0,0 +1,172 @@
+mod metrics;
+mod rate_limit;
+mod throttle;
+
+use crate::{
+    config::{ClusterMode, KubeSettings},
+    error::ErrorKind,
+};
+use backoff::{backoff::Backoff, ExponentialBackoffBuilder};
+use kube::{
+    client::ClientBuilder,
+    config::{KubeConfigOptions, Kubeconfig},
+    Client, Config,
+};
+use metrics::ApiMetricsLayer;
+use rate_limit::RateLimitLayer;
+use std::time::{Duration, Instant};
+use throttle::ThrottleLayer;
+use tracing::{info, warn};
+
+// the burst used when only the qps is configured
+const DEFAULT_BURST: u32 = 10;
+
+// builds the client and makes sure the api server answers. When it can't be reached, or
+// the client can't be built yet (like without the service account token mounted), it's
+// tried again for up to kube.startup.timeout. Returns how long that took.
+pub async fn connect(settings: &KubeSettings) -> Result<(Client, Duration), ErrorKind> {
+    let startup = &settings.startup;
+    let mut backoff = ExponentialBackoffBuilder::new()
+        .with_initial_interval(startup.backoff.initial)
+        .with_max_interval(startup.backoff.max)
+        .with_randomization_factor(startup.backoff.jitter)
+        .with_multiplier(startup.backoff.multiplier)
+        .with_max_elapsed_time(startup.timeout)
+        .build();
+    let started = Instant::now();
+    let mut attempts = 1;
+    loop {
+        let err = match build_client(settings).await {
+            Ok(client) => match client.apiserver_version().await {
+                Ok(version) => {
+                    if attempts > 1 {
+                        info!(
+                            "The api server ({}.{}) answered after {} attempts, in {}",
+                            version.major,
+                            version.minor,
+                            attempts,
+                            humantime::format_duration(Duration::from_secs(
+                                started.elapsed().as_secs()
+                            ))
+                        );
+                    }
+                    return Ok((client, started.elapsed()));
+                }
+                Err(err) => err.into(),
+            },
+            Err(err) => err,
+        };
+        let delay = match startup.timeout {
+            Some(_) => backoff.next_backoff(),
+            None => None,
+        };
+        let Some(delay) = delay else {
+            return match startup.timeout {
+                Some(timeout) => Err(err.context(format!(
+                    "the api server couldn't be reached within {}",
+                    humantime::format_duration(timeout)
+                ))),
+                None => Err(err),
+            };
+        };
+        warn!(
+            "The api server can't be reached yet (attempt {}): {}, trying again in {:?}",
+            attempts, err, delay
+        );
+        drop(err);
+        tokio::time::sleep(delay).await;
+        attempts += 1;
+    }
+}
+
+// builds the k8s client according to what was asked in the config,
+// logging where we're connecting to so there are no surprises.
+pub async fn build_client(settings: &KubeSettings) -> Result<Client, ErrorKind> {
+    let mut config = build_config(settings).await?;
+    info!(
+        "Connecting to cluster {} (default namespace \"{}\")",
+        config.cluster_url, config.default_namespace
+    );
+
+    if settings.connect_timeout.is_some() {
+        config.connect_timeout = settings.connect_timeout;
+    }
+    if settings.read_timeout.is_some() {
+        config.read_timeout = settings.read_timeout;
+    }
+    if settings.write_timeout.is_some() {
+        config.write_timeout = settings.write_timeout;
+    }
+
+    // every request is timed and counted, without what it waited for the rate limit,
+    // each attempt of the throttled ones too
+    let builder = ClientBuilder::try_from(config)?
+        .with_layer(&ApiMetricsLayer)
+        .with_layer(&ThrottleLayer::new(&settings.throttling));
+    // the qps was already checked when loading the config
+    match settings.qps {
+        Some(qps) => {
+            let burst = settings.burst.unwrap_or(DEFAULT_BURST);
+            info!(
+                "Limiting api server requests to {} qps (burst of {})",
+                qps, burst
+            );
+            Ok(builder.with_layer(&RateLimitLayer::new(qps, burst)).build())
+        }
+        None => Ok(builder.build()),
+    }
+}
+
+async fn build_config(settings: &KubeSettings) -> Result<Config, ErrorKind> {
+    let wants_kubeconfig = settings.kubeconfig.is_some() || settings.context.is_some();
+
+    match settings.mode {
+        ClusterMode::InCluster if wants_kubeconfig => {
+            Err("a kubeconfig/context can't be used together with the in-cluster mode".into())
+        }
+        ClusterMode::InCluster => {
+            info!("Using the in-cluster service account config");
+            Config::incluster().map_err(ErrorKind::other)
+        }
+        ClusterMode::OutOfCluster => from_kubeconfig(settings).await,
+        // if the user pointed us to a kubeconfig, they surely want to use it
+        ClusterMode::Auto if wants_kubeconfig => from_kubeconfig(settings).await,
+        ClusterMode::Auto => {
+            info!("No cluster mode selected, inferring the config (kubeconfig first, then in-cluster)");
+            Config::infer().await.map_err(ErrorKind::other)
+        }
+    }
+}
+
+async fn from_kubeconfig(settings: &KubeSettings) -> Result<Config, ErrorKind> {
+    // we read the file ourselves so we can tell which context ended up being used
+    let kubeconfig = match settings.kubeconfig {
+        Some(ref path) => Kubeconfig::read_from(path),
+        None => Kubeconfig::read(),
+    }
+    .map_err(ErrorKind::other)?;
+
+    let context = settings
+        .context
+        .clone()
+        .or_else(|| kubeconfig.current_context.clone());
+    info!(
+        "Using kubeconfig {} with context {}",
+        settings
+            .kubeconfig
+            .as_ref()
+            .map_or("from the environment".to_string(), |path| format!(
+                "{:?}",
+                path
+            )),
+        context.as_deref().unwrap_or("<none>")
+    );
+
+    let options = KubeConfigOptions {
+        context,
+        ..Default::default()
+    };
+    Config::from_custom_kubeconfig(kubeconfig, &options)
+        .await
+        .map_err(ErrorKind::other)
+}
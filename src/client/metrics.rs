@@ -0,0 +1,122 @@
+use crate::metrics::{
+    API_REQUESTS_COUNTER, API_REQUEST_HISTOGRAM, CODE_LABEL, RESOURCE_LABEL, VERB_LABEL,
+};
+use axum::http::{Method, Request, Response};
+use axum_prometheus::metrics::{counter, histogram};
+use futures::future::BoxFuture;
+use std::{
+    task::{Context, Poll},
+    time::Instant,
+};
+use tower::{Layer, Service};
+
+// a tower layer timing every request the client sends to the api server and counting
+// their status codes, by verb and resource. A watch is timed until its response
+// starts, what it streams afterwards can take as long as it wants.
+#[derive(Clone, Default)]
+pub struct ApiMetricsLayer;
+
+impl<S> Layer<S> for ApiMetricsLayer {
+    type Service = ApiMetrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ApiMetrics { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct ApiMetrics<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for ApiMetrics<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let (verb, resource) = describe(req.method(), req.uri().path(), req.uri().query());
+        let started = Instant::now();
+        let response = self.inner.call(req);
+        Box::pin(async move {
+            let response = response.await;
+            let code = match response {
+                Ok(ref response) => response.status().as_str().to_string(),
+                // it didn't even get an answer
+                Err(_) => "error".to_string(),
+            };
+            histogram!(
+                API_REQUEST_HISTOGRAM,
+                &[
+                    (VERB_LABEL, verb.to_string()),
+                    (RESOURCE_LABEL, resource.clone())
+                ]
+            )
+            .record(started.elapsed().as_secs_f64());
+            counter!(
+                API_REQUESTS_COUNTER,
+                &[
+                    (VERB_LABEL, verb.to_string()),
+                    (RESOURCE_LABEL, resource),
+                    (CODE_LABEL, code)
+                ]
+            )
+            .increment(1);
+            response
+        })
+    }
+}
+
+// the verb and resource of a request like the api server has them, like
+// (watch, events) or (get, pods/log). The names of the namespaces and objects
+// are left out, they'd make a series per object.
+pub(super) fn describe(method: &Method, path: &str, query: Option<&str>) -> (&'static str, String) {
+    let segments = path
+        .trim_matches('/')
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+    // past /api/v1 or /apis/group/version
+    let rest = match segments.as_slice() {
+        ["api", _, rest @ ..] => rest,
+        ["apis", _, _, rest @ ..] => rest,
+        _ => &[][..],
+    };
+    let rest = match rest {
+        // the namespaces themselves are a resource too
+        ["namespaces", _, rest @ ..] if !rest.is_empty() => rest,
+        rest => rest,
+    };
+    let (resource, named) = match rest {
+        [] => ("other".to_string(), false),
+        [resource] => (resource.to_string(), false),
+        [resource, _] => (resource.to_string(), true),
+        [resource, _, subresource, ..] => (format!("{}/{}", resource, subresource), true),
+    };
+
+    let watching = query.is_some_and(|query| {
+        query
+            .split('&')
+            .any(|param| param == "watch=true" || param == "watch=1")
+    });
+    let verb = match *method {
+        Method::GET if watching => "watch",
+        Method::GET if named => "get",
+        Method::GET => "list",
+        Method::POST => "create",
+        Method::PUT => "update",
+        Method::PATCH => "patch",
+        Method::DELETE if named => "delete",
+        Method::DELETE => "deletecollection",
+        _ => "other",
+    };
+    (verb, resource)
+}
@@ -0,0 +1,114 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::time::{sleep, Instant, Sleep};
+use tower::{Layer, Service};
+
+// a token bucket shared by every request the client makes.
+// It holds at most `burst` tokens and refills at `qps` tokens per second.
+struct TokenBucket {
+    qps: f64,
+    burst: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    // takes a token if there's one, otherwise tells how long until there is
+    fn try_acquire(&mut self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.qps).min(self.burst);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.qps))
+        }
+    }
+}
+
+// a tower layer that limits how many requests per second reach the api server.
+// Unlike tower's own RateLimit, this one allows bursts.
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    bucket: Arc<Mutex<TokenBucket>>,
+}
+
+impl RateLimitLayer {
+    pub fn new(qps: f64, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+        RateLimitLayer {
+            bucket: Arc::new(Mutex::new(TokenBucket {
+                qps,
+                burst,
+                tokens: burst,
+                last_refill: Instant::now(),
+            })),
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            bucket: self.bucket.clone(),
+            sleep: None,
+            has_token: false,
+        }
+    }
+}
+
+pub struct RateLimit<S> {
+    inner: S,
+    bucket: Arc<Mutex<TokenBucket>>,
+    sleep: Option<Pin<Box<Sleep>>>,
+    // whether we already took a token for the next call
+    has_token: bool,
+}
+
+impl<S, Req> Service<Req> for RateLimit<S>
+where
+    S: Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        while !self.has_token {
+            // wait out the previous estimate before asking the bucket again
+            if let Some(ref mut sleep) = self.sleep {
+                if sleep.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                self.sleep = None;
+            }
+
+            let acquired = self
+                .bucket
+                .lock()
+                .expect("rate limit bucket lock poisoned")
+                .try_acquire();
+            match acquired {
+                Ok(()) => self.has_token = true,
+                Err(wait) => self.sleep = Some(Box::pin(sleep(wait))),
+            }
+        }
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        self.has_token = false;
+        self.inner.call(req)
+    }
+}
@@ -0,0 +1,130 @@
+use super::metrics::describe;
+use crate::{
+    config::ThrottleSettings,
+    metrics::{API_THROTTLED_COUNTER, API_THROTTLE_WAIT_HISTOGRAM, RESOURCE_LABEL, VERB_LABEL},
+};
+use axum::http::{header::RETRY_AFTER, Request, Response, StatusCode};
+use axum_prometheus::metrics::{counter, histogram};
+use futures::future::{poll_fn, BoxFuture};
+use kube::client::Body;
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::sync::Mutex;
+use tower::{Layer, Service};
+use tracing::debug;
+
+// the wait when a 429 has no Retry-After, doubled for each of the next ones
+const DEFAULT_WAIT: Duration = Duration::from_secs(1);
+
+// a tower layer sending a request again when the api server answered 429 Too Many
+// Requests, which API Priority and Fairness does when the request's priority level is
+// full, after the Retry-After it asked for. Every 429 is counted, and the waits are
+// timed, by verb and resource. The requests are sent again as they were, their bodies
+// being kept for that, and the watchers only back off once the retries ran out.
+#[derive(Clone)]
+pub struct ThrottleLayer {
+    settings: ThrottleSettings,
+}
+
+impl ThrottleLayer {
+    pub fn new(settings: &ThrottleSettings) -> Self {
+        ThrottleLayer {
+            settings: settings.clone(),
+        }
+    }
+}
+
+impl<S> Layer<S> for ThrottleLayer {
+    type Service = Throttle<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Throttle {
+            // the inner service of kube's stack is boxed and can't be cloned into the
+            // futures of the retries
+            inner: Arc::new(Mutex::new(inner)),
+            settings: self.settings.clone(),
+        }
+    }
+}
+
+pub struct Throttle<S> {
+    inner: Arc<Mutex<S>>,
+    settings: ThrottleSettings,
+}
+
+impl<S, ResBody> Service<Request<Body>> for Throttle<S>
+where
+    S: Service<Request<Body>, Response = Response<ResBody>> + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: From<kube::Error> + Send,
+    ResBody: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    // the inner service is made ready along with each attempt
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let inner = self.inner.clone();
+        let settings = self.settings.clone();
+        Box::pin(async move {
+            let (verb, resource) = describe(req.method(), req.uri().path(), req.uri().query());
+            let (parts, body) = req.into_parts();
+            let body = body.collect_bytes().await?;
+            let mut wait = DEFAULT_WAIT;
+            let mut attempt = 0;
+            loop {
+                let request = Request::from_parts(parts.clone(), Body::from(body.clone()));
+                let response = {
+                    let mut inner = inner.lock().await;
+                    poll_fn(|cx| inner.poll_ready(cx)).await?;
+                    inner.call(request)
+                }
+                .await?;
+                if response.status() != StatusCode::TOO_MANY_REQUESTS {
+                    return Ok(response);
+                }
+                counter!(
+                    API_THROTTLED_COUNTER,
+                    &[
+                        (VERB_LABEL, verb.to_string()),
+                        (RESOURCE_LABEL, resource.clone())
+                    ]
+                )
+                .increment(1);
+                if attempt >= settings.retries {
+                    return Ok(response);
+                }
+                attempt += 1;
+                let asked = response
+                    .headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.trim().parse::<u64>().ok())
+                    .map(Duration::from_secs);
+                let delay = asked.unwrap_or(wait).min(settings.max_wait);
+                wait = (wait * 2).min(settings.max_wait);
+                debug!(
+                    "The api server throttled a {} of {}, trying again in {:?} ({}/{})",
+                    verb, resource, delay, attempt, settings.retries
+                );
+                histogram!(
+                    API_THROTTLE_WAIT_HISTOGRAM,
+                    &[
+                        (VERB_LABEL, verb.to_string()),
+                        (RESOURCE_LABEL, resource.clone())
+                    ]
+                )
+                .record(delay.as_secs_f64());
+                tokio::time::sleep(delay).await;
+            }
+        })
+    }
+}
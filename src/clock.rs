@@ -0,0 +1,140 @@
+use k8s_openapi::chrono::{DateTime, Utc};
+use std::{
+    sync::{Arc, LazyLock, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+// what the windows, cooldowns, TTLs and deduplication take the time from, the wall
+// clock unless something set another one, like the replay following the recorded
+// timestamps. The tokio timers (intervals, sleeps) stay on the wall clock.
+static CLOCK: LazyLock<RwLock<Arc<dyn Clock>>> =
+    LazyLock::new(|| RwLock::new(Arc::new(SystemClock)));
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+    fn instant(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+// a clock only moving when told to, both its times going forward together
+pub struct ManualClock {
+    started: Instant,
+    start: DateTime<Utc>,
+    elapsed: Mutex<Duration>,
+}
+
+impl ManualClock {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        ManualClock {
+            started: Instant::now(),
+            start,
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    // to that time, unless it's past it already: the clock never goes back
+    pub fn advance_to(&self, time: DateTime<Utc>) {
+        let Ok(elapsed) = (time - self.start).to_std() else {
+            return;
+        };
+        let mut current = self.lock();
+        *current = (*current).max(elapsed);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Duration> {
+        self.elapsed.lock().expect("clock lock poisoned")
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.start + *self.lock()
+    }
+
+    fn instant(&self) -> Instant {
+        self.started + *self.lock()
+    }
+}
+
+pub fn set(clock: Arc<dyn Clock>) {
+    *CLOCK.write().expect("clock lock poisoned") = clock;
+}
+
+// the tests run side by side, each on a thread of its own, so each fast-forwards a clock
+// of its own rather than the one of the whole process
+#[cfg(test)]
+thread_local! {
+    static LOCAL: std::cell::RefCell<Option<Arc<dyn Clock>>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+#[cfg(test)]
+pub fn set_local(clock: Arc<dyn Clock>) {
+    LOCAL.with(|local| *local.borrow_mut() = Some(clock));
+}
+
+fn current() -> Arc<dyn Clock> {
+    #[cfg(test)]
+    if let Some(clock) = LOCAL.with(|local| local.borrow().clone()) {
+        return clock;
+    }
+    CLOCK.read().expect("clock lock poisoned").clone()
+}
+
+pub fn now() -> DateTime<Utc> {
+    current().now()
+}
+
+pub fn instant() -> Instant {
+    current().instant()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::chrono::TimeDelta;
+
+    #[test]
+    fn moves_both_times_together() {
+        let start = Utc::now();
+        let manual = Arc::new(ManualClock::new(start));
+        set_local(manual.clone());
+        let started = instant();
+        assert_eq!(now(), start);
+
+        manual.advance_to(start + TimeDelta::minutes(10));
+        assert_eq!(now(), start + TimeDelta::minutes(10));
+        assert_eq!(instant() - started, Duration::from_secs(600));
+    }
+
+    #[test]
+    fn never_goes_back() {
+        let start = Utc::now();
+        let manual = Arc::new(ManualClock::new(start));
+        set_local(manual.clone());
+        manual.advance_to(start + TimeDelta::minutes(10));
+
+        manual.advance_to(start + TimeDelta::minutes(5));
+        assert_eq!(now(), start + TimeDelta::minutes(10));
+        manual.advance_to(start - TimeDelta::minutes(5));
+        assert_eq!(now(), start + TimeDelta::minutes(10));
+    }
+
+    #[test]
+    fn stays_on_the_wall_clock_unless_set() {
+        let before = Utc::now();
+        assert!(now() >= before);
+        assert!(now() <= Utc::now());
+    }
+}
@@ -0,0 +1,156 @@
+use crate::{
+    config::WatcherSettings,
+    error::Error,
+    metrics::{HASH_LABEL, KIND_LABEL, NAMESPACE_LABEL, NAME_LABEL},
+    registry::{CONFIG_CHANGES, CONFIG_INFO},
+    snapshot::SNAPSHOT,
+    watch::{pausable, watcher_config, WatcherBackoff},
+};
+use futures::StreamExt;
+use k8s_openapi::{
+    api::core::v1::{ConfigMap, Secret},
+    NamespaceResourceScope,
+};
+use kube::{
+    core::PartialObjectMeta,
+    runtime::{metadata_watcher, watcher, WatchStreamExt},
+    Api, Client, Resource, ResourceExt,
+};
+use serde::de::DeserializeOwned;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+};
+
+// watches the ConfigMaps and Secrets as they change, to tell when a pod restarted right
+// after its config did. Only their metadata is ever fetched: the values of the Secrets
+// are never read, let alone logged.
+pub async fn watch_configs(client: Client, settings: &WatcherSettings, secrets: bool) {
+    let config_maps = watch::<ConfigMap>(client.clone(), settings, "configmaps");
+    if secrets {
+        futures::join!(config_maps, watch::<Secret>(client, settings, "secrets"));
+    } else {
+        config_maps.await;
+    }
+}
+
+// the uid and resource version of every object, by name
+#[derive(Default)]
+struct Versions {
+    kind: String,
+    known: HashMap<(String, String), String>,
+}
+
+impl Versions {
+    // a change is counted for the objects known already, not those just created
+    fn apply<K: Resource>(&mut self, object: &PartialObjectMeta<K>) {
+        let key = (object.namespace().unwrap_or_default(), object.name_any());
+        let hash = hash(
+            &object.uid().unwrap_or_default(),
+            &object.resource_version().unwrap_or_default(),
+        );
+        let labels = |key: &(String, String)| {
+            [
+                (NAMESPACE_LABEL, key.0.clone()),
+                (KIND_LABEL, self.kind.clone()),
+                (NAME_LABEL, key.1.clone()),
+            ]
+        };
+        match self.known.get(&key) {
+            Some(known) if *known == hash => return,
+            Some(_) => {
+                self.forget_info(&key);
+                CONFIG_CHANGES.increment(&labels(&key), 1);
+            }
+            None => {}
+        }
+        let mut info = labels(&key).to_vec();
+        info.push((HASH_LABEL, hash.clone()));
+        CONFIG_INFO.set(&info, 1.0);
+        self.known.insert(key, hash);
+    }
+
+    fn delete(&mut self, key: &(String, String)) {
+        self.forget_info(key);
+        CONFIG_CHANGES.remove_where(&[
+            (NAMESPACE_LABEL, &key.0),
+            (KIND_LABEL, &self.kind),
+            (NAME_LABEL, &key.1),
+        ]);
+        self.known.remove(key);
+    }
+
+    fn forget_info(&self, key: &(String, String)) {
+        CONFIG_INFO.remove_where(&[
+            (NAMESPACE_LABEL, &key.0),
+            (KIND_LABEL, &self.kind),
+            (NAME_LABEL, &key.1),
+        ]);
+    }
+}
+
+async fn watch<K>(client: Client, settings: &WatcherSettings, name: &str)
+where
+    K: Resource<DynamicType = (), Scope = NamespaceResourceScope>
+        + Clone
+        + DeserializeOwned
+        + Debug
+        + Send
+        + 'static,
+{
+    let api: Api<K> = Api::default_namespaced(client);
+    let backoff = WatcherBackoff::new(name, &settings.backoff);
+    let mut stream = Box::pin(pausable(
+        name,
+        metadata_watcher(api, watcher_config(settings))
+            .backoff(backoff)
+            .inspect(|event| SNAPSHOT.watcher(name, event)),
+    ));
+
+    let mut versions = Versions {
+        kind: K::kind(&()).to_string(),
+        ..Versions::default()
+    };
+    let mut relisted = HashSet::new();
+    while let Some(event) = stream.next().await {
+        match event {
+            Ok(watcher::Event::Init) => relisted.clear(),
+            Ok(watcher::Event::InitApply(object)) => {
+                relisted.insert((object.namespace().unwrap_or_default(), object.name_any()));
+                versions.apply(&object);
+            }
+            Ok(watcher::Event::InitDone) => {
+                // forget the objects deleted while we were away
+                let gone = versions
+                    .known
+                    .keys()
+                    .filter(|key| !relisted.contains(*key))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for key in gone.iter() {
+                    versions.delete(key);
+                }
+            }
+            Ok(watcher::Event::Apply(object)) => versions.apply(&object),
+            Ok(watcher::Event::Delete(object)) => {
+                versions.delete(&(object.namespace().unwrap_or_default(), object.name_any()))
+            }
+            Err(err) => Error::new("configs", err).on(name).report(),
+        }
+    }
+}
+
+// a short fnv-1a hash of the object's version, the same for as long as it doesn't change
+// (and across restarts), without anything of its contents. Mixed some more at the end,
+// versions a digit apart would hash a digit apart too.
+fn hash(uid: &str, resource_version: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in uid.bytes().chain([b'/']).chain(resource_version.bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    format!("{:012x}", hash >> 16)
+}
@@ -0,0 +1,125 @@
+use crate::{
+    config::ContainerLogSettings,
+    metrics::{CONTAINER_LOGS_COUNTER, SINK_RESULT_LABEL},
+    record::EventRecord,
+};
+use axum_prometheus::metrics::counter;
+use k8s_openapi::api::core::v1::{Event, Pod};
+use kube::{api::LogParams, Api, Client};
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::debug;
+
+// what the fetches are limited over
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+// fetches the last lines of the container an event of the configured reasons is about,
+// like a BackOff, and puts them in its record. A crash loop makes lots of those events,
+// the fetches are limited to so many a minute.
+pub struct ContainerLogs {
+    client: Client,
+    settings: ContainerLogSettings,
+    // when the fetches within the last minute were made
+    fetched: Mutex<VecDeque<Instant>>,
+}
+
+impl ContainerLogs {
+    pub fn new(client: Client, settings: &ContainerLogSettings) -> Self {
+        ContainerLogs {
+            client,
+            settings: settings.clone(),
+            fetched: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub async fn attach(&self, event: &Event, record: &mut EventRecord) {
+        let reason = event.reason.as_deref().unwrap_or_default();
+        let object = &event.involved_object;
+        if object.kind.as_deref() != Some("Pod")
+            || !self.settings.reasons.iter().any(|r| r == reason)
+        {
+            return;
+        }
+        let (Some(namespace), Some(pod)) = (object.namespace.as_deref(), object.name.as_deref())
+        else {
+            return;
+        };
+        let result = if !self.allowed() {
+            "limited"
+        } else {
+            match tokio::time::timeout(self.settings.timeout, self.fetch(namespace, pod, event))
+                .await
+            {
+                Ok(Ok(logs)) => {
+                    record.logs = Some(logs);
+                    "success"
+                }
+                Ok(Err(err)) => {
+                    debug!("Could not fetch the logs of Pod {}: {}", pod, err);
+                    "failure"
+                }
+                Err(_) => {
+                    debug!("Fetching the logs of Pod {} timed out", pod);
+                    "failure"
+                }
+            }
+        };
+        counter!(
+            CONTAINER_LOGS_COUNTER,
+            &[(SINK_RESULT_LABEL, result.to_string())]
+        )
+        .increment(1);
+    }
+
+    // takes one of the fetches of the minute, if there's any left
+    fn allowed(&self) -> bool {
+        let now = Instant::now();
+        let mut fetched = self.fetched.lock().expect("container logs lock poisoned");
+        while fetched
+            .front()
+            .is_some_and(|at| now.duration_since(*at) >= RATE_WINDOW)
+        {
+            fetched.pop_front();
+        }
+        if fetched.len() >= self.settings.per_minute as usize {
+            return false;
+        }
+        fetched.push_back(now);
+        true
+    }
+
+    // the previous instance of the container is the one that crashed, when there's one
+    async fn fetch(
+        &self,
+        namespace: &str,
+        pod: &str,
+        event: &Event,
+    ) -> Result<String, kube::Error> {
+        let api: Api<Pod> = Api::namespaced(self.client.clone(), namespace);
+        let mut params = LogParams {
+            container: container(event),
+            tail_lines: Some(self.settings.lines),
+            limit_bytes: Some(self.settings.max_bytes),
+            previous: self.settings.previous,
+            ..LogParams::default()
+        };
+        match api.logs(pod, &params).await {
+            Err(_) if params.previous => {
+                params.previous = false;
+                api.logs(pod, &params).await
+            }
+            logs => logs,
+        }
+    }
+}
+
+// the container the event's about, like spec.containers{api}.
+// Without one, the api server picks the pod's only container.
+fn container(event: &Event) -> Option<String> {
+    let field_path = event.involved_object.field_path.as_deref()?;
+    let (field, name) = field_path.strip_suffix('}')?.split_once('{')?;
+    matches!(field, "spec.containers" | "spec.initContainers").then(|| name.to_string())
+}
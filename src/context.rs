@@ -0,0 +1,153 @@
+use crate::{
+    clock,
+    config::{ArtifactSettings, FailureContextSettings},
+    enrich::Enricher,
+    memory::{Lru, FAILURE_CONTEXTS_STORE},
+    pods,
+    record::EventRecord,
+    schema::PodDescription,
+    sinks::Routing,
+};
+use k8s_openapi::api::core::v1::{ContainerStatus, Event, Pod};
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// past this many pods, the ones without an event for a while are forgotten
+const PRUNE_AT: usize = 4096;
+const FORGET_AFTER: Duration = Duration::from_secs(3600);
+
+// keeps the last events of every pod, to describe it when one of them is a Warning, and
+// sends the records of the failures of artifacts.reasons to its sinks
+pub struct FailureContexts {
+    settings: FailureContextSettings,
+    artifacts: ArtifactSettings,
+    // by namespace/name, along with when the last one came
+    recent: Mutex<Lru<String, (Instant, VecDeque<String>)>>,
+}
+
+impl FailureContexts {
+    pub fn new(settings: &FailureContextSettings, artifacts: &ArtifactSettings) -> Self {
+        FailureContexts {
+            settings: settings.clone(),
+            artifacts: artifacts.clone(),
+            recent: Mutex::new(Lru::new(FAILURE_CONTEXTS_STORE, |limits| {
+                limits.failure_contexts
+            })),
+        }
+    }
+
+    // called with every record of the pod pipeline, only the Warning ones get a context
+    pub fn attach(&self, event: &Event, enricher: &Enricher, record: &mut EventRecord) {
+        if !self.settings.enabled || record.kind != "Pod" {
+            return;
+        }
+        let recent = self.remember(record);
+        if record.type_ != "Warning" {
+            return;
+        }
+        let pod = enricher.pod(event);
+        record.context = Some(describe(pod.as_deref(), recent));
+    }
+
+    // whether some records go to the artifact sinks
+    pub fn routes(&self) -> bool {
+        !self.artifacts.reasons.is_empty() && !self.artifacts.sinks.is_empty()
+    }
+
+    // once the record has its context and logs, so the artifact has them too
+    pub fn route(&self, record: &EventRecord, routing: &mut Routing) {
+        if self.artifacts.reasons.contains(&record.reason) {
+            routing.sinks.extend(self.artifacts.sinks.iter().cloned());
+        }
+    }
+
+    // the pod's recent events, this one included
+    fn remember(&self, record: &EventRecord) -> Vec<String> {
+        let now = clock::instant();
+        let key = format!("{}/{}", record.namespace, record.object_name);
+        let line = match record.count {
+            count if count > 1 => format!(
+                "{} {} (x{}): {}",
+                record.type_, record.reason, count, record.message
+            ),
+            _ => format!("{} {}: {}", record.type_, record.reason, record.message),
+        };
+
+        let mut recent = self.recent.lock().expect("failure contexts lock poisoned");
+        if recent.len() >= PRUNE_AT && !recent.contains_key(&key) {
+            recent.retain(|_, (at, _)| now.duration_since(*at) < FORGET_AFTER);
+        }
+        let (at, events) = recent.get_or_insert_with(key, || (now, VecDeque::new()));
+        *at = now;
+        events.push_front(line);
+        events.truncate(self.settings.recent_events);
+        events.iter().cloned().collect()
+    }
+}
+
+fn describe(pod: Option<&Pod>, recent_events: Vec<String>) -> PodDescription {
+    let Some(status) = pod.and_then(|pod| pod.status.as_ref()) else {
+        return PodDescription {
+            recent_events,
+            ..PodDescription::default()
+        };
+    };
+    let conditions = status
+        .conditions
+        .iter()
+        .flatten()
+        .map(|condition| match condition.reason {
+            Some(ref reason) if condition.status != "True" => {
+                format!("{}={} ({})", condition.type_, condition.status, reason)
+            }
+            _ => format!("{}={}", condition.type_, condition.status),
+        })
+        .collect();
+    // the init containers and sidecars say so, so their failures aren't taken for the app's
+    let spec = pod.and_then(|pod| pod.spec.as_ref());
+    let containers = status
+        .init_container_statuses
+        .iter()
+        .flatten()
+        .chain(status.container_statuses.iter().flatten())
+        .map(|status| match pods::container_type(spec, &status.name) {
+            pods::MAIN_CONTAINER => container(status),
+            container_type => format!("{} {}", container_type, container(status)),
+        })
+        .collect();
+    PodDescription {
+        phase: status.phase.clone(),
+        conditions,
+        containers,
+        recent_events,
+    }
+}
+
+fn container(status: &ContainerStatus) -> String {
+    let state = status.state.as_ref();
+    let state = if let Some(waiting) = state.and_then(|state| state.waiting.as_ref()) {
+        match waiting.reason {
+            Some(ref reason) => format!("waiting ({})", reason),
+            None => "waiting".to_string(),
+        }
+    } else if let Some(terminated) = state.and_then(|state| state.terminated.as_ref()) {
+        format!(
+            "terminated ({}, exit code {})",
+            terminated.reason.as_deref().unwrap_or("Unknown"),
+            terminated.exit_code
+        )
+    } else if status.ready {
+        "running, ready".to_string()
+    } else {
+        "running, not ready".to_string()
+    };
+    let restarts = match status.restart_count {
+        1 => ", 1 restart".to_string(),
+        count if count > 1 => format!(", {} restarts", count),
+        _ => String::new(),
+    };
+    format!("{}: {}{}", status.name, state, restarts)
+}
@@ -0,0 +1,154 @@
+use crate::{
+    config::MonitorSettings,
+    monitor::{monitor_key, EventMonitor, EventMonitorStatus, MonitorPipelines},
+};
+use futures::StreamExt;
+use kube::{
+    api::{Patch, PatchParams},
+    runtime::{
+        controller::{Action, Controller},
+        finalizer::{self, finalizer},
+        watcher,
+    },
+    Api, Client, ResourceExt,
+};
+use serde_json::json;
+use std::{fmt, sync::Arc, time::Duration};
+use tracing::{info, warn};
+
+// everything the reconcilers need
+pub struct Context {
+    pub client: Client,
+    pub pipelines: MonitorPipelines,
+    pub requeue_after: Duration,
+    pub error_requeue_after: Duration,
+}
+
+impl Context {
+    pub fn new(client: Client, pipelines: MonitorPipelines, settings: &MonitorSettings) -> Self {
+        Context {
+            client,
+            pipelines,
+            requeue_after: settings.resync,
+            error_requeue_after: settings.error_requeue,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ReconcileError {
+    // talking to the api server failed, this is worth retrying soon
+    Kube(kube::Error),
+    // adding/removing the finalizer failed, or the apply/cleanup inside it did
+    Finalizer(Box<finalizer::Error<ReconcileError>>),
+}
+
+impl fmt::Display for ReconcileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReconcileError::Kube(err) => write!(f, "kube error: {}", err),
+            ReconcileError::Finalizer(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ReconcileError {}
+
+impl From<kube::Error> for ReconcileError {
+    fn from(err: kube::Error) -> Self {
+        ReconcileError::Kube(err)
+    }
+}
+
+// runs the EventMonitor controller until the process stops
+pub async fn run(ctx: Arc<Context>) {
+    let monitors: Api<EventMonitor> = Api::all(ctx.client.clone());
+    let controller = Controller::new(monitors, watcher::Config::default());
+
+    info!("Starting the EventMonitor controller");
+    controller
+        .shutdown_on_signal()
+        .run(reconcile, error_policy, ctx)
+        .for_each(|result| async move {
+            if let Err(err) = result {
+                warn!("EventMonitor reconcile failed: {}", err);
+            }
+        })
+        .await;
+    info!("EventMonitor controller stopped");
+}
+
+// the finalizer we put on every EventMonitor, so we get to clean up before it's gone
+const FINALIZER: &str = "k8rs.io/cleanup";
+
+async fn reconcile(
+    monitor: Arc<EventMonitor>,
+    ctx: Arc<Context>,
+) -> Result<Action, ReconcileError> {
+    let api: Api<EventMonitor> =
+        Api::namespaced(ctx.client.clone(), &monitor.namespace().unwrap_or_default());
+
+    finalizer(&api, FINALIZER, monitor, |event| async {
+        match event {
+            finalizer::Event::Apply(monitor) => apply(monitor, &api, &ctx).await,
+            finalizer::Event::Cleanup(monitor) => cleanup(monitor, &ctx).await,
+        }
+    })
+    .await
+    .map_err(|err| ReconcileError::Finalizer(Box::new(err)))
+}
+
+// brings the monitor's pipeline in line with its spec and reports back on its status
+async fn apply(
+    monitor: Arc<EventMonitor>,
+    api: &Api<EventMonitor>,
+    ctx: &Context,
+) -> Result<Action, ReconcileError> {
+    let key = monitor_key(&monitor);
+    let status = match ctx.pipelines.ensure(&monitor) {
+        Ok(()) => EventMonitorStatus {
+            active: true,
+            message: None,
+            observed_generation: monitor.metadata.generation,
+        },
+        Err(err) => {
+            warn!("EventMonitor {} can't run: {}", key, err);
+            EventMonitorStatus {
+                active: false,
+                message: Some(err),
+                observed_generation: monitor.metadata.generation,
+            }
+        }
+    };
+
+    // patching the same status again would only wake us up for nothing
+    if monitor.status.as_ref() != Some(&status) {
+        api.patch_status(
+            &monitor.name_any(),
+            &PatchParams::default(),
+            &Patch::Merge(json!({ "status": status })),
+        )
+        .await?;
+    }
+
+    Ok(Action::requeue(ctx.requeue_after))
+}
+
+// the monitor is being deleted: stop its pipeline, flush its sinks and drop its
+// metric series, only then the finalizer goes away and the deletion completes
+async fn cleanup(monitor: Arc<EventMonitor>, ctx: &Context) -> Result<Action, ReconcileError> {
+    let key = monitor_key(&monitor);
+    ctx.pipelines.stop(&key).await;
+    info!("EventMonitor {} cleaned up", key);
+    Ok(Action::await_change())
+}
+
+fn error_policy(monitor: Arc<EventMonitor>, err: &ReconcileError, ctx: Arc<Context>) -> Action {
+    warn!(
+        "Error reconciling EventMonitor {}, retrying in {:?}: {}",
+        monitor_key(&monitor),
+        ctx.error_requeue_after,
+        err
+    );
+    Action::requeue(ctx.error_requeue_after)
+}
@@ -0,0 +1,185 @@
+use crate::{
+    clock,
+    config::DegradationSettings,
+    enrich::Enricher,
+    metrics::{EventLabels, LABEL_DETAIL_GAUGE},
+    pods::workload,
+};
+use axum_prometheus::metrics::gauge;
+use k8s_openapi::api::core::v1::Event;
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+// back to more detail once the pods (or workloads) are down to this much of max_series
+const RESTORE_BELOW: f64 = 0.8;
+
+// how often the pods and workloads out of the window are let go of
+const PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+// keeps the series of created_pods, deleted_pods and last_event_timestamp_seconds from
+// exploding during an event storm: past max_series pods with events in the window, their
+// pod_id is the pod's workload instead, and past as many workloads it's the namespace.
+// The pipeline's workers all go through it, so like the stale series it's a global.
+pub static DEGRADATION: LazyLock<Degradation> = LazyLock::new(Degradation::default);
+
+#[derive(Default)]
+pub struct Degradation {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    settings: DegradationSettings,
+    level: Level,
+    // when the pods and workloads with events were last seen
+    pods: HashMap<String, Instant>,
+    workloads: HashMap<String, Instant>,
+    pruned: Option<Instant>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Level {
+    #[default]
+    Pod,
+    Workload,
+    Namespace,
+}
+
+impl Level {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Level::Pod => "pod",
+            Level::Workload => "workload",
+            Level::Namespace => "namespace",
+        }
+    }
+}
+
+impl Degradation {
+    pub fn configure(&self, settings: &DegradationSettings) {
+        let mut state = self.lock();
+        state.settings = settings.clone();
+        if settings.max_series.is_none() {
+            state.level = Level::Pod;
+            state.pods.clear();
+            state.workloads.clear();
+        }
+        gauge!(LABEL_DETAIL_GAUGE).set(level_value(state.level));
+    }
+
+    // what the per pod series of the event's pod are under, None for the pod itself
+    pub fn aggregate(&self, event: &Event, enricher: &Enricher) -> Option<Aggregate> {
+        let object = &event.involved_object;
+        let mut state = self.lock();
+        let max = state.settings.max_series?;
+        let uid = object.uid.clone()?;
+        let now = clock::instant();
+        let workload = enricher.pod(event).map_or_else(
+            || format!("Pod/{}", object.name.as_deref().unwrap_or_default()),
+            |pod| workload(&pod),
+        );
+        let namespace = object.namespace.clone().unwrap_or_default();
+        state.pods.insert(uid, now);
+        state
+            .workloads
+            .insert(format!("{}/{}", namespace, workload), now);
+        if state
+            .pruned
+            .is_none_or(|pruned| now.duration_since(pruned) >= PRUNE_INTERVAL)
+        {
+            state.prune(now);
+        }
+        state.adjust(max);
+        match state.level {
+            Level::Pod => None,
+            Level::Workload => Some(Aggregate::Workload(workload)),
+            Level::Namespace => Some(Aggregate::Namespace(namespace)),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("degradation lock poisoned")
+    }
+}
+
+// the per pod series of a pod during a storm: its workload's, like Deployment/api, or
+// its namespace's
+pub enum Aggregate {
+    Workload(String),
+    Namespace(String),
+}
+
+impl Aggregate {
+    // what's the pod_id (and the name of last_event_timestamp_seconds) instead of the pod's
+    pub fn value(&self) -> &str {
+        match self {
+            Aggregate::Workload(value) | Aggregate::Namespace(value) => value,
+        }
+    }
+
+    // a namespace's series are one per namespace, not one per node and zone the pods
+    // of its workloads happen to be on
+    pub fn apply(&self, labels: &mut EventLabels) {
+        labels.object_id = self.value().to_string();
+        if let Aggregate::Namespace(_) = self {
+            labels.node.clear();
+            labels.zone.clear();
+            labels
+                .pod_labels
+                .iter_mut()
+                .for_each(|(_, value)| value.clear());
+        }
+    }
+}
+
+impl State {
+    fn prune(&mut self, now: Instant) {
+        let window = self.settings.window;
+        self.pods
+            .retain(|_, seen| now.duration_since(*seen) < window);
+        self.workloads
+            .retain(|_, seen| now.duration_since(*seen) < window);
+        self.pruned = Some(now);
+    }
+
+    // one more step up as soon as there are too many, and down once there are a lot
+    // less, so a storm hovering around max_series doesn't flip the labels every event
+    fn adjust(&mut self, max: usize) {
+        let up = |count: usize| count > max;
+        let down = |count: usize| (count as f64) < max as f64 * RESTORE_BELOW;
+        let (pods, workloads) = (self.pods.len(), self.workloads.len());
+        let level = match self.level {
+            Level::Pod if up(pods) && up(workloads) => Level::Namespace,
+            Level::Pod if up(pods) => Level::Workload,
+            Level::Workload if up(workloads) => Level::Namespace,
+            Level::Workload if down(pods) => Level::Pod,
+            Level::Namespace if down(workloads) && down(pods) => Level::Pod,
+            Level::Namespace if down(workloads) => Level::Workload,
+            level => level,
+        };
+        if level == self.level {
+            return;
+        }
+        warn!(
+            "The per pod series are by {} now, with {} pods and {} workloads with events in the last {}",
+            level.as_str(),
+            pods,
+            workloads,
+            humantime::format_duration(self.settings.window)
+        );
+        self.level = level;
+        gauge!(LABEL_DETAIL_GAUGE).set(level_value(level));
+    }
+}
+
+fn level_value(level: Level) -> f64 {
+    match level {
+        Level::Pod => 0.0,
+        Level::Workload => 1.0,
+        Level::Namespace => 2.0,
+    }
+}
@@ -0,0 +1,582 @@
+use crate::{
+    alerts::{self, Alerts},
+    anomalies::ANOMALIES,
+    config::{Cli, Config, EnrichmentSettings},
+    container_logs::ContainerLogs,
+    context::FailureContexts,
+    degrade,
+    discovery::Kinds,
+    enrich::{Enricher, ZONE_LABEL},
+    error::ErrorKind,
+    features, grafana, graph, grpc, images, incidents, maintenance, memory, messages,
+    metrics::{self, initialize_counters, install_recorder},
+    namespaces::NamespaceFilter,
+    outbound, payloads,
+    pipeline::{self, EventHandlers, RecordHandlers},
+    plugins::Plugins,
+    pods::PodTracker,
+    readiness::Readiness,
+    rollups::{self, ROLLUPS},
+    scripts::Scripts,
+    server::Listeners,
+    severity,
+    sinks::SinkRegistry,
+    slos::Slos,
+    snapshot, stale,
+    tenants::Tenants,
+    top, version,
+    watch::{watch_events, EventSource},
+};
+use axum::{routing::get, Json, Router};
+use clap::Args;
+use futures::{stream, Stream, StreamExt};
+use k8s_openapi::{
+    api::core::v1::{
+        Container, Event, EventSource as Source, Node, ObjectReference, Pod, PodSpec, Service,
+        ServiceSpec,
+    },
+    apimachinery::pkg::apis::meta::v1::{ObjectMeta, OwnerReference, Time},
+    chrono::{DateTime, Utc},
+};
+use kube::runtime::{reflector, watcher};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::task;
+use tracing::{info, warn};
+
+// the made up cluster: namespaces with their deployments, and nodes with their zones
+const NAMESPACES: [(&str, [&str; 2]); 3] = [
+    ("demo-shop", ["frontend", "cart"]),
+    ("demo-payments", ["api", "ledger"]),
+    ("demo-search", ["indexer", "query"]),
+];
+const NODES: [(&str, &str); 3] = [
+    ("demo-node-a", "demo-zone-1"),
+    ("demo-node-b", "demo-zone-2"),
+    ("demo-node-c", "demo-zone-3"),
+];
+
+// one pod in this many is crash looping, the back-offs are about those
+const CRASH_LOOPING_EVERY: usize = 4;
+
+// of the replicaset of every deployment, so the pods are told to be the deployments'
+const POD_TEMPLATE_HASH: &str = "6d4cf56db6";
+
+// the rates of the synthetic events of --demo, in events a second
+#[derive(Args, Debug, Clone)]
+pub struct DemoArgs {
+    /// The containers created a second in --demo mode
+    #[arg(
+        long = "demo-created",
+        value_name = "RATE",
+        default_value_t = 1.0,
+        requires = "demo"
+    )]
+    pub created: f64,
+
+    /// The containers killed a second in --demo mode
+    #[arg(
+        long = "demo-killing",
+        value_name = "RATE",
+        default_value_t = 0.2,
+        requires = "demo"
+    )]
+    pub killing: f64,
+
+    /// The back-offs of the crash looping containers a second in --demo mode
+    #[arg(
+        long = "demo-backoff",
+        value_name = "RATE",
+        default_value_t = 0.5,
+        requires = "demo"
+    )]
+    pub backoff: f64,
+
+    /// How many pods the events of --demo mode are about
+    #[arg(long = "demo-pods", default_value_t = 24, requires = "demo")]
+    pub pods: usize,
+}
+
+// runs the pod pipeline of the config (metrics, sinks, alert rules) on synthetic events
+// instead of the cluster's, serving the metrics until the kill signal. What only a cluster
+// can give (the scripts' ConfigMap, the containers' logs, the other watchers) is left out.
+pub async fn run(cli: &Cli) -> Result<(), ErrorKind> {
+    let args = &cli.demo_args;
+    for (name, rate) in [
+        ("--demo-created", args.created),
+        ("--demo-killing", args.killing),
+        ("--demo-backoff", args.backoff),
+    ] {
+        if !rate.is_finite() || rate < 0.0 {
+            return Err(format!("{} must be a positive number of events a second", name).into());
+        }
+    }
+    if args.created + args.killing + args.backoff <= 0.0 {
+        return Err("at least one of the --demo rates must be above 0".into());
+    }
+    if args.pods == 0 {
+        return Err("--demo-pods must be at least 1".into());
+    }
+
+    let mut config = Config::load(cli)?;
+    if config.scripts.config_map.take().is_some() {
+        warn!("Not running the scripts in demo mode, their ConfigMap is in the cluster");
+    }
+    if !config.container_logs.reasons.is_empty() {
+        warn!("Not fetching the containers' logs in demo mode, there are no containers");
+        config.container_logs.reasons.clear();
+    }
+    if config.server.metrics_auth.enabled {
+        warn!("Anyone can scrape the demo, there's no api server to review the tokens");
+    }
+    if config.tenants.needs_namespaces() {
+        warn!("The demo namespaces have no labels, the tenants matching them won't match");
+    }
+
+    let listeners = Listeners::bind(&config.server)?;
+    let grpc = grpc::GrpcListener::bind(&config.server)?;
+    let recorder = install_recorder(&config.metrics)?;
+    alerts::watch(&config.alerts);
+    initialize_counters();
+    version::export();
+    messages::configure(&config.messages)?;
+    severity::configure(&config.severities);
+    features::configure(&config.features);
+    memory::configure(&config.memory);
+    payloads::configure(&config.payloads);
+    maintenance::configure(&config.maintenance)?;
+    task::spawn(maintenance::run());
+    outbound::configure(&config.outbound)?;
+    snapshot::SNAPSHOT.configure(&config.snapshot, false);
+    top::TOP.configure(&config.top);
+    ROLLUPS.configure(&config.rollups);
+    incidents::INCIDENTS.configure(&config.incidents);
+    if config.incidents.enabled {
+        warn!("There are no replicasets in the demo, the incidents only have the pods' events");
+    }
+    ANOMALIES.configure(&config.anomalies);
+    stale::STALE_SERIES.configure(&config.metrics.stale_series);
+    degrade::DEGRADATION.configure(&config.metrics.degradation);
+    task::spawn(stale::STALE_SERIES.run());
+
+    let sinks = SinkRegistry::from_settings(&config.sinks, &config.suppression)?;
+    // nothing reloads the config here, but the pipeline follows a dispatcher
+    let (_dispatcher_tx, dispatcher) =
+        tokio::sync::watch::channel(sinks.dispatcher(&config.pipeline.sinks)?);
+    let alerts = Arc::new(Alerts::new(&config.alerts, sinks.clone())?);
+    if !alerts.is_empty() {
+        task::spawn(alerts.clone().run());
+    }
+    let slos = Arc::new(Slos::new(&config.slos));
+    if !slos.is_empty() {
+        task::spawn(slos.clone().run());
+    }
+
+    let fleet = Arc::new(Fleet::new(args.pods));
+    let tracker = Arc::new(PodTracker::default());
+    task::spawn(tracker.clone().run());
+    let enricher = fleet.enricher(&tracker, &config.enrichment);
+
+    // with the scripts and the container logs off, nothing ever calls the api server,
+    // they only want a client to hold on to
+    let client = kube::Client::try_from(kube::Config::new(
+        "http://127.0.0.1:1".parse().map_err(ErrorKind::other)?,
+    ))?;
+    let tenants = Arc::new(Tenants::new(&config.tenants, None));
+    let records = Arc::new(RecordHandlers::new(
+        tenants.clone(),
+        Arc::new(Scripts::load(client.clone(), &config.scripts).await?),
+        Plugins::load(&config.plugins).await?,
+        slos,
+        sinks.clone(),
+        ContainerLogs::new(client, &config.container_logs),
+        FailureContexts::new(&config.failure_context, &config.artifacts),
+    ));
+    if config.incidents.enabled {
+        task::spawn(incidents::INCIDENTS.run(sinks.clone()));
+    }
+    if config.anomalies.enabled {
+        task::spawn(ANOMALIES.run(sinks.clone()));
+    }
+    let handlers = Arc::new(EventHandlers::new(&config, tracker, None, None, None));
+
+    let (prom_layer, prom_handler) = metrics::http_metrics_layer(&config.metrics.http, recorder);
+    let mut app = metrics::endpoint(prom_handler, tenants)
+        .route("/ping", get(|| async move { "pong" }))
+        .merge(version::router())
+        .merge(images::router());
+    if config.snapshot.enabled {
+        app = app.route(
+            "/api/v1/snapshot",
+            get(|| async { Json(snapshot::SNAPSHOT.render()) }),
+        );
+    }
+    if config.top.enabled {
+        app = app.merge(top::router());
+    }
+    if config.graph.enabled {
+        app = app.merge(graph::router());
+    }
+    if config.rollups.enabled {
+        app = app.merge(rollups::router()).merge(grafana::router());
+    }
+    if config.incidents.enabled {
+        app = app.merge(incidents::router());
+    }
+    let app: Router = app.layer(prom_layer);
+    task::spawn(listeners.serve(app));
+    if let Some(grpc) = grpc {
+        let readiness = Arc::new(Readiness::new(sinks.clone(), &config.server.readiness));
+        task::spawn(readiness.clone().run());
+        task::spawn(grpc.serve(readiness));
+    }
+
+    let (sender, receiver) = pipeline::channel(&config.pipeline, &config.alerts.rules);
+    let kinds = Kinds::new(config.event_kinds());
+    let filter = NamespaceFilter::new(&config.namespaces);
+    let events = SyntheticEvents {
+        fleet,
+        rates: [
+            (Reason::Created, args.created),
+            (Reason::Killing, args.killing),
+            (Reason::BackOff, args.backoff),
+        ],
+    };
+    task::spawn(async move { watch_events(events, kinds, filter, sender).await });
+    info!(
+        "Running the demo: {} created, {} killed and {} back-offs a second over {} pods",
+        args.created, args.killing, args.backoff, args.pods
+    );
+    task::spawn(async move {
+        pipeline::process_events(
+            receiver,
+            &config.pipeline,
+            enricher,
+            dispatcher,
+            alerts,
+            handlers,
+            records,
+        )
+        .await
+    });
+
+    let _ = tokio::signal::ctrl_c().await;
+    info!("Kill signal received, stopping the demo...");
+    sinks.flush().await;
+    Ok(())
+}
+
+// the rates of the reasons, like the demo's defaults
+pub const DEFAULT_RATES: [(Reason, f64); 3] = [
+    (Reason::Created, 1.0),
+    (Reason::Killing, 0.2),
+    (Reason::BackOff, 0.5),
+];
+
+struct DemoPod {
+    namespace: &'static str,
+    deployment: &'static str,
+    name: String,
+    uid: String,
+    node: &'static str,
+    crash_looping: bool,
+}
+
+impl DemoPod {
+    fn replica_set(&self) -> String {
+        format!("{}-{}", self.deployment, POD_TEMPLATE_HASH)
+    }
+}
+
+// the pods the events are about, spread over the namespaces' deployments and the nodes
+pub struct Fleet {
+    pods: Vec<DemoPod>,
+}
+
+impl Fleet {
+    pub fn new(size: usize) -> Self {
+        let mut rng = StdRng::from_entropy();
+        let pods = (0..size)
+            .map(|index| {
+                let (namespace, deployments) = NAMESPACES[index % NAMESPACES.len()];
+                let deployment = deployments[index / NAMESPACES.len() % deployments.len()];
+                let suffix = (0..5)
+                    .map(|_| char::from(b"bcdfghjklmnpqrstvwxz2456789"[rng.gen_range(0..27)]))
+                    .collect::<String>();
+                DemoPod {
+                    namespace,
+                    deployment,
+                    name: format!("{}-6d4cf56db6-{}", deployment, suffix),
+                    uid: format!("00000000-0000-4000-8000-{:012x}", index),
+                    node: NODES[index % NODES.len()].0,
+                    crash_looping: index % CRASH_LOOPING_EVERY == CRASH_LOOPING_EVERY - 1,
+                }
+            })
+            .collect();
+        Fleet { pods }
+    }
+
+    // an enricher whose caches hold the fleet's pods and nodes, like the reflectors
+    // would, the tracker following the pods too
+    pub fn enricher(&self, tracker: &PodTracker, settings: &EnrichmentSettings) -> Enricher {
+        let (pods, mut pod_writer) = reflector::store::<Pod>();
+        let (nodes, mut node_writer) = reflector::store::<Node>();
+        for pod in self.pods() {
+            let event = watcher::Event::Apply(pod);
+            tracker.observe(&event);
+            pod_writer.apply_watcher_event(&event);
+        }
+        for node in self.nodes() {
+            node_writer.apply_watcher_event(&watcher::Event::Apply(node));
+        }
+        let (pods, nodes) = (Arc::new(pods), Arc::new(nodes));
+        let (services, mut service_writer) = reflector::store::<Service>();
+        for service in services_of_deployments() {
+            service_writer.apply_watcher_event(&watcher::Event::Apply(service));
+        }
+        graph::GRAPH.attach(pods.clone(), nodes.clone(), Arc::new(services));
+        Enricher::new(pods, nodes, settings)
+    }
+
+    fn pods(&self) -> impl Iterator<Item = Pod> + '_ {
+        self.pods.iter().map(|pod| Pod {
+            metadata: ObjectMeta {
+                name: Some(pod.name.clone()),
+                namespace: Some(pod.namespace.to_string()),
+                uid: Some(pod.uid.clone()),
+                labels: Some(BTreeMap::from([
+                    ("app".to_string(), pod.deployment.to_string()),
+                    (
+                        "pod-template-hash".to_string(),
+                        POD_TEMPLATE_HASH.to_string(),
+                    ),
+                ])),
+                owner_references: Some(vec![OwnerReference {
+                    api_version: "apps/v1".to_string(),
+                    kind: "ReplicaSet".to_string(),
+                    name: pod.replica_set(),
+                    controller: Some(true),
+                    ..OwnerReference::default()
+                }]),
+                ..ObjectMeta::default()
+            },
+            spec: Some(PodSpec {
+                node_name: Some(pod.node.to_string()),
+                containers: vec![Container {
+                    name: "app".to_string(),
+                    image: Some(format!("registry.example.com/demo/{}:1.0", pod.deployment)),
+                    ..Container::default()
+                }],
+                ..PodSpec::default()
+            }),
+            ..Pod::default()
+        })
+    }
+
+    fn nodes(&self) -> impl Iterator<Item = Node> {
+        NODES.iter().map(|(name, zone)| Node {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                labels: Some(BTreeMap::from([(ZONE_LABEL.to_string(), zone.to_string())])),
+                ..ObjectMeta::default()
+            },
+            ..Node::default()
+        })
+    }
+}
+
+// a service for every deployment, selecting its pods by their app label
+fn services_of_deployments() -> impl Iterator<Item = Service> {
+    NAMESPACES.iter().flat_map(|(namespace, deployments)| {
+        deployments.iter().map(|deployment| Service {
+            metadata: ObjectMeta {
+                name: Some(deployment.to_string()),
+                namespace: Some(namespace.to_string()),
+                ..ObjectMeta::default()
+            },
+            spec: Some(ServiceSpec {
+                selector: Some(BTreeMap::from([(
+                    "app".to_string(),
+                    deployment.to_string(),
+                )])),
+                ..ServiceSpec::default()
+            }),
+            ..Service::default()
+        })
+    })
+}
+
+#[derive(Clone, Copy)]
+pub enum Reason {
+    Created,
+    Killing,
+    BackOff,
+}
+
+// what the kubelet would say about the pods of the fleet, at the configured rates
+struct SyntheticEvents {
+    fleet: Arc<Fleet>,
+    rates: [(Reason, f64); 3],
+}
+
+impl EventSource for SyntheticEvents {
+    fn events(&self) -> impl Stream<Item = Result<watcher::Event<Event>, watcher::Error>> + Send {
+        let generator = Generator::new(self.fleet.clone(), self.rates);
+        // an initial list with nothing in it, then the events as they're made up
+        stream::iter([Ok(watcher::Event::Init), Ok(watcher::Event::InitDone)]).chain(
+            stream::unfold(generator, |mut generator| async move {
+                tokio::time::sleep(generator.wait()).await;
+                let event = generator.next();
+                Some((Ok(watcher::Event::Apply(event)), generator))
+            }),
+        )
+    }
+
+    fn name(&self) -> String {
+        "demo".to_string()
+    }
+}
+
+// makes up the events, in a mix of reasons following their rates
+pub struct Generator {
+    fleet: Arc<Fleet>,
+    rates: [(Reason, f64); 3],
+    rng: StdRng,
+    // the back-off event of each crash looping pod, by pod: its name, count and
+    // first time, the kubelet counting them up on the same event
+    backoffs: HashMap<usize, (String, i32, DateTime<Utc>)>,
+    sequence: u64,
+}
+
+impl Generator {
+    pub fn new(fleet: Arc<Fleet>, rates: [(Reason, f64); 3]) -> Self {
+        Generator {
+            fleet,
+            rates,
+            rng: StdRng::from_entropy(),
+            backoffs: HashMap::new(),
+            sequence: 0,
+        }
+    }
+
+    // how long until the next event: the events of every reason together come at the
+    // sum of their rates, the time between two of them being that of a poisson process
+    pub fn wait(&mut self) -> Duration {
+        let total = self.rates.iter().map(|(_, rate)| rate).sum::<f64>();
+        let wait = -(1.0 - self.rng.gen::<f64>()).ln() / total;
+        Duration::from_secs_f64(wait.min(3600.0))
+    }
+
+    // the next event, of a reason as often as its share of the rates
+    pub fn next(&mut self) -> Event {
+        let total = self.rates.iter().map(|(_, rate)| rate).sum::<f64>();
+        let mut pick = self.rng.gen::<f64>() * total;
+        let mut reason = Reason::Created;
+        for (candidate, rate) in self.rates {
+            reason = candidate;
+            if pick < rate {
+                break;
+            }
+            pick -= rate;
+        }
+        let crash_looping = self
+            .fleet
+            .pods
+            .iter()
+            .enumerate()
+            .filter(|(_, pod)| pod.crash_looping)
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        let index = match reason {
+            Reason::BackOff if !crash_looping.is_empty() => {
+                crash_looping[self.rng.gen_range(0..crash_looping.len())]
+            }
+            _ => self.rng.gen_range(0..self.fleet.pods.len()),
+        };
+        self.event(index, reason)
+    }
+
+    fn event(&mut self, index: usize, reason: Reason) -> Event {
+        self.sequence += 1;
+        let now = Utc::now();
+        let pod = &self.fleet.pods[index];
+        let new_name = || {
+            format!(
+                "{}.{:016x}",
+                pod.name,
+                now.timestamp_nanos_opt().unwrap_or(0)
+            )
+        };
+        let (name, count, first, type_, reason, message) = match reason {
+            Reason::Created => (
+                new_name(),
+                1,
+                now,
+                "Normal",
+                "Created",
+                "Created container app".to_string(),
+            ),
+            Reason::Killing => (
+                new_name(),
+                1,
+                now,
+                "Normal",
+                "Killing",
+                "Stopping container app".to_string(),
+            ),
+            Reason::BackOff => {
+                let backoff = self
+                    .backoffs
+                    .entry(index)
+                    .or_insert_with(|| (new_name(), 0, now));
+                backoff.1 += 1;
+                (
+                    backoff.0.clone(),
+                    backoff.1,
+                    backoff.2,
+                    "Warning",
+                    "BackOff",
+                    format!(
+                        "Back-off restarting failed container app in pod {}_{}({})",
+                        pod.name, pod.namespace, pod.uid
+                    ),
+                )
+            }
+        };
+        Event {
+            metadata: ObjectMeta {
+                uid: Some(format!("demo-{}", name)),
+                name: Some(name),
+                namespace: Some(pod.namespace.to_string()),
+                resource_version: Some(self.sequence.to_string()),
+                ..ObjectMeta::default()
+            },
+            involved_object: ObjectReference {
+                api_version: Some("v1".to_string()),
+                kind: Some("Pod".to_string()),
+                name: Some(pod.name.clone()),
+                namespace: Some(pod.namespace.to_string()),
+                uid: Some(pod.uid.clone()),
+                field_path: Some("spec.containers{app}".to_string()),
+                ..ObjectReference::default()
+            },
+            reason: Some(reason.to_string()),
+            message: Some(message),
+            type_: Some(type_.to_string()),
+            source: Some(Source {
+                component: Some("kubelet".to_string()),
+                host: Some(pod.node.to_string()),
+            }),
+            reporting_component: Some("kubelet".to_string()),
+            reporting_instance: Some(pod.node.to_string()),
+            first_timestamp: Some(Time(first)),
+            last_timestamp: Some(Time(now)),
+            count: Some(count),
+            ..Event::default()
+        }
+    }
+}
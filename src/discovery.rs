@@ -0,0 +1,133 @@
+use crate::{
+    config::DiscoverySettings,
+    metrics::{DISCOVERED_KINDS_GAUGE, GROUP_LABEL},
+};
+use axum_prometheus::metrics::gauge;
+use k8s_openapi::api::core::v1::ObjectReference;
+use kube::{discovery::Discovery, Client};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, RwLock},
+};
+use tracing::{info, warn};
+
+// the kinds of objects whose events are kept: the ones of the config, by name alone, and
+// the ones discovered in discovery.groups, by group and name so a custom resource
+// doesn't take in the events of a built-in kind named the same
+#[derive(Clone, Default)]
+pub struct Kinds {
+    configured: Arc<Vec<String>>,
+    discovered: Arc<RwLock<BTreeSet<(String, String)>>>,
+}
+
+impl Kinds {
+    pub fn new(configured: Vec<String>) -> Self {
+        Kinds {
+            configured: Arc::new(configured),
+            ..Kinds::default()
+        }
+    }
+
+    pub fn contains(&self, object: &ObjectReference) -> bool {
+        let Some(kind) = object.kind.as_ref() else {
+            return false;
+        };
+        if self.configured.contains(kind) {
+            return true;
+        }
+        // "argoproj.io/v1alpha1", the core group's being just "v1"
+        let group = object
+            .api_version
+            .as_deref()
+            .and_then(|api_version| api_version.split_once('/'))
+            .map_or("", |(group, _)| group);
+        self.discovered
+            .read()
+            .expect("discovered kinds lock poisoned")
+            .contains(&(group.to_string(), kind.clone()))
+    }
+}
+
+// whether a group is one of the config's, which can start with "*." for all the groups
+// under a domain
+fn matches(pattern: &str, group: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => group
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.ends_with('.')),
+        None => pattern == group,
+    }
+}
+
+// runs the api discovery every settings.interval, for the kinds of the groups the config
+// asks for. The custom resources installed after we started are picked up the next time.
+pub async fn run(client: Client, settings: &DiscoverySettings, kinds: Kinds) {
+    let wildcards = settings.groups.iter().any(|group| group.starts_with("*."));
+    let mut interval = tokio::time::interval(settings.interval);
+    loop {
+        interval.tick().await;
+        let mut discovery = Discovery::new(client.clone());
+        // the whole cluster is only gone through for the wildcards, it's a request per group
+        if !wildcards {
+            let groups = settings
+                .groups
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>();
+            discovery = discovery.filter(&groups);
+        }
+        let discovery = match discovery.run().await {
+            Ok(discovery) => discovery,
+            Err(err) => {
+                warn!("Could not discover the api groups: {}", err);
+                continue;
+            }
+        };
+        let mut by_group = BTreeMap::<String, BTreeSet<String>>::new();
+        for group in discovery.groups() {
+            if !settings
+                .groups
+                .iter()
+                .any(|pattern| matches(pattern, group.name()))
+            {
+                continue;
+            }
+            let group_kinds = by_group.entry(group.name().to_string()).or_default();
+            for (resource, _) in group.recommended_resources() {
+                group_kinds.insert(resource.kind);
+            }
+        }
+        let found = by_group
+            .iter()
+            .flat_map(|(group, group_kinds)| {
+                group_kinds.iter().map(|kind| (group.clone(), kind.clone()))
+            })
+            .collect::<BTreeSet<_>>();
+        let mut discovered = kinds
+            .discovered
+            .write()
+            .expect("discovered kinds lock poisoned");
+        if *discovered != found {
+            info!(
+                "Watching the events of {} discovered kinds: {}",
+                found.len(),
+                found
+                    .iter()
+                    .map(|(group, kind)| format!("{}/{}", group, kind))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        // the groups gone since the last time go to 0
+        for (group, _) in discovered.iter() {
+            if !by_group.contains_key(group) {
+                gauge!(DISCOVERED_KINDS_GAUGE, &[(GROUP_LABEL, group.clone())]).set(0.0);
+            }
+        }
+        for (group, group_kinds) in by_group.iter() {
+            gauge!(DISCOVERED_KINDS_GAUGE, &[(GROUP_LABEL, group.clone())])
+                .set(group_kinds.len() as f64);
+        }
+        *discovered = found;
+    }
+}
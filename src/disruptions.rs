@@ -0,0 +1,208 @@
+use crate::{
+    cache::PodStore,
+    config::WatcherSettings,
+    enrich::Enricher,
+    error::Error,
+    metrics::{
+        EVICTIONS_BLOCKED_COUNTER, NAMESPACE_LABEL, PDB_LABEL, REASON_LABEL, WORKLOAD_LABEL,
+    },
+    pods::workload,
+    registry::DISRUPTIONS_ALLOWED,
+    snapshot::SNAPSHOT,
+    watch::{pausable, watcher_config, WatcherBackoff},
+};
+use axum_prometheus::metrics::counter;
+use futures::StreamExt;
+use k8s_openapi::{
+    api::{core::v1::Event, policy::v1::PodDisruptionBudget},
+    apimachinery::pkg::apis::meta::v1::LabelSelector,
+};
+use kube::{
+    runtime::{watcher, WatchStreamExt},
+    Api, Client, ResourceExt,
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{LazyLock, Mutex},
+};
+
+// the workload of the pods each PodDisruptionBudget covers, by namespace and name, for
+// its events and the drains. The watcher keeps it and the pipeline reads it, so it's a
+// global.
+static WORKLOADS: LazyLock<Mutex<HashMap<(String, String), Budget>>> =
+    LazyLock::new(Default::default);
+
+struct Budget {
+    workload: String,
+    allowed: i32,
+}
+
+// the budgets of a workload that let none of its pods go right now, by name
+pub fn blocking(namespace: &str, workload: &str) -> Vec<String> {
+    let mut budgets = WORKLOADS
+        .lock()
+        .expect("disruption budgets lock poisoned")
+        .iter()
+        .filter(|((known, _), budget)| {
+            known == namespace && budget.workload == workload && budget.allowed <= 0
+        })
+        .map(|((_, name), _)| name.clone())
+        .collect::<Vec<_>>();
+    budgets.sort();
+    budgets
+}
+
+// counts the events telling a node drain (or anything else evicting) that a budget
+// didn't let it, by the workload of the pod or of the budget the event is about.
+// Whoever is evicting says so its own way: the eviction api's "would violate the pod's
+// disruption budget", the cluster-autoscaler's "not enough pod disruption budget",
+// Karpenter's "PDB ... prevents pod evictions".
+pub fn handle_event(event: &Event, enricher: &Enricher) {
+    let message = event.message.as_deref().unwrap_or_default().to_lowercase();
+    let blocked = message.contains("disruption budget")
+        || message.contains("poddisruptionbudget")
+        || message
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .any(|word| word == "pdb" || word == "pdbs");
+    if !blocked {
+        return;
+    }
+    let object = &event.involved_object;
+    let namespace = object.namespace.clone().unwrap_or_default();
+    let workload = match object.kind.as_deref() {
+        Some("PodDisruptionBudget") => WORKLOADS
+            .lock()
+            .expect("disruption budgets lock poisoned")
+            .get(&(namespace.clone(), object.name.clone().unwrap_or_default()))
+            .map(|budget| budget.workload.clone()),
+        _ => enricher.pod(event).map(|pod| workload(&pod)),
+    };
+    counter!(
+        EVICTIONS_BLOCKED_COUNTER,
+        &[
+            (NAMESPACE_LABEL, namespace),
+            (WORKLOAD_LABEL, workload.unwrap_or_default()),
+            (REASON_LABEL, event.reason.clone().unwrap_or_default()),
+        ]
+    )
+    .increment(1);
+}
+
+// watches the PodDisruptionBudgets, for how many more pods each lets go right now
+pub async fn watch_budgets(client: Client, settings: &WatcherSettings, pods: PodStore) {
+    let api: Api<PodDisruptionBudget> = Api::default_namespaced(client);
+    let backoff = WatcherBackoff::new("poddisruptionbudgets", &settings.backoff);
+    let mut stream = Box::pin(pausable(
+        "poddisruptionbudgets",
+        watcher(api, watcher_config(settings))
+            .backoff(backoff)
+            .inspect(|event| SNAPSHOT.watcher("poddisruptionbudgets", event)),
+    ));
+
+    let mut relisted = HashSet::new();
+    while let Some(event) = stream.next().await {
+        match event {
+            Ok(watcher::Event::Init) => relisted.clear(),
+            Ok(watcher::Event::InitApply(budget)) => {
+                relisted.insert(key(&budget));
+                apply(&budget, &pods);
+            }
+            Ok(watcher::Event::InitDone) => {
+                // forget the budgets deleted while we were away
+                let gone = WORKLOADS
+                    .lock()
+                    .expect("disruption budgets lock poisoned")
+                    .keys()
+                    .filter(|key| !relisted.contains(*key))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for key in gone.iter() {
+                    forget(key);
+                }
+            }
+            Ok(watcher::Event::Apply(budget)) => apply(&budget, &pods),
+            Ok(watcher::Event::Delete(budget)) => forget(&key(&budget)),
+            Err(err) => Error::new("disruptions", err).report(),
+        }
+    }
+}
+
+fn key(budget: &PodDisruptionBudget) -> (String, String) {
+    (budget.namespace().unwrap_or_default(), budget.name_any())
+}
+
+fn apply(budget: &PodDisruptionBudget, pods: &PodStore) {
+    let key = key(budget);
+    // the pods of a budget all belong to a single workload, usually
+    let workload = budget
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.selector.as_ref())
+        .and_then(|selector| {
+            pods.state()
+                .into_iter()
+                .filter(|pod| pod.namespace().as_deref() == Some(key.0.as_str()))
+                .find(|pod| selects(selector, pod.labels()))
+                .map(|pod| workload(&pod))
+        })
+        .unwrap_or_default();
+    let allowed = budget
+        .status
+        .as_ref()
+        .map_or(0, |status| status.disruptions_allowed);
+
+    let previous = WORKLOADS
+        .lock()
+        .expect("disruption budgets lock poisoned")
+        .insert(
+            key.clone(),
+            Budget {
+                workload: workload.clone(),
+                allowed,
+            },
+        );
+    if previous.is_some_and(|previous| previous.workload != workload) {
+        DISRUPTIONS_ALLOWED.remove_where(&[(NAMESPACE_LABEL, &key.0), (PDB_LABEL, &key.1)]);
+    }
+    DISRUPTIONS_ALLOWED.set(
+        &[
+            (NAMESPACE_LABEL, key.0),
+            (PDB_LABEL, key.1),
+            (WORKLOAD_LABEL, workload),
+        ],
+        allowed as f64,
+    );
+}
+
+fn forget(key: &(String, String)) {
+    WORKLOADS
+        .lock()
+        .expect("disruption budgets lock poisoned")
+        .remove(key);
+    DISRUPTIONS_ALLOWED.remove_where(&[(NAMESPACE_LABEL, &key.0), (PDB_LABEL, &key.1)]);
+}
+
+// whether a label selector selects the labels, the empty one selecting everything
+fn selects(selector: &LabelSelector, labels: &BTreeMap<String, String>) -> bool {
+    let match_labels = selector
+        .match_labels
+        .iter()
+        .flatten()
+        .all(|(key, value)| labels.get(key) == Some(value));
+    match_labels
+        && selector
+            .match_expressions
+            .iter()
+            .flatten()
+            .all(|expression| {
+                let value = labels.get(&expression.key);
+                let values = expression.values.as_deref().unwrap_or_default();
+                match expression.operator.as_str() {
+                    "In" => value.is_some_and(|value| values.contains(value)),
+                    "NotIn" => value.is_none_or(|value| !values.contains(value)),
+                    "Exists" => value.is_some(),
+                    "DoesNotExist" => value.is_none(),
+                    _ => false,
+                }
+            })
+}
@@ -0,0 +1,253 @@
+use crate::{
+    cache::PodStore,
+    clock,
+    config::DrainSettings,
+    disruptions,
+    enrich::Enricher,
+    metrics::{NODE_DRAIN_HISTOGRAM, NODE_LABEL},
+    pods::workload,
+    record::EventRecord,
+    registry::NODE_DRAIN_REMAINING,
+    sinks::SinkRegistry,
+};
+use axum_prometheus::metrics::histogram;
+use k8s_openapi::{
+    api::core::v1::{Event, Node, Pod},
+    chrono::{SecondsFormat, Utc},
+};
+use kube::ResourceExt;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+// how often the pods left on the nodes being drained are counted
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+// the annotation of the static pods' mirrors, which a drain leaves alone
+const MIRROR_ANNOTATION: &str = "kubernetes.io/config.mirror";
+
+// follows the nodes being drained: cordoned, and then their pods being killed. The node
+// cache tells it which are cordoned, the pipeline when their pods start going, and it
+// counts what's left in the pod cache on a task of its own.
+pub struct Drains {
+    settings: DrainSettings,
+    pods: PodStore,
+    sinks: SinkRegistry,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    cordoned: HashSet<String>,
+    drains: HashMap<String, Drain>,
+}
+
+struct Drain {
+    started: Instant,
+    // the pods left the last time some went, and when that was
+    remaining: usize,
+    progressed: Instant,
+    // whether the sinks were told, once for every drain
+    stalled: bool,
+}
+
+impl Drains {
+    pub fn new(settings: &DrainSettings, pods: PodStore, sinks: SinkRegistry) -> Self {
+        Drains {
+            settings: settings.clone(),
+            pods,
+            sinks,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    // called with every node of the node cache. An uncordoned node wasn't drained
+    // after all, or not all the way.
+    pub fn node(&self, node: &Node) {
+        let name = node.name_any();
+        let cordoned = node
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.unschedulable)
+            .unwrap_or(false);
+        let mut state = self.lock();
+        if cordoned {
+            state.cordoned.insert(name);
+            return;
+        }
+        state.cordoned.remove(&name);
+        if state.drains.remove(&name).is_some() {
+            info!("Node {} was uncordoned before it was drained", name);
+            NODE_DRAIN_REMAINING.remove(NODE_LABEL, &name);
+        }
+    }
+
+    // a node deleted while being drained is done with it
+    pub fn gone(&self, name: &str) {
+        let mut state = self.lock();
+        state.cordoned.remove(name);
+        if let Some(drain) = state.drains.remove(name) {
+            drained(name, &drain);
+        }
+    }
+
+    // called with every Pod event of the pipeline, the drain of a cordoned node starting
+    // with the first of its pods killed
+    pub fn event(&self, event: &Event, enricher: &Enricher) {
+        if event.reason.as_deref() != Some("Killing") {
+            return;
+        }
+        let (node, _) = enricher.node_and_zone(event);
+        let mut state = self.lock();
+        if !state.cordoned.contains(&node) || state.drains.contains_key(&node) {
+            return;
+        }
+        let remaining = self.remaining(&node).len();
+        info!("Node {} is being drained, {} pods left", node, remaining);
+        NODE_DRAIN_REMAINING.set(&[(NODE_LABEL, node.clone())], remaining as f64);
+        state.drains.insert(
+            node,
+            Drain {
+                started: clock::instant(),
+                remaining,
+                progressed: clock::instant(),
+                stalled: false,
+            },
+        );
+    }
+
+    // counts the pods every CHECK_INTERVAL, the drains without any left being done
+    pub async fn run(&self) {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            for record in self.check() {
+                if self.settings.sinks.is_empty() {
+                    continue;
+                }
+                // taken from the registry every time, so reloaded sinks are picked up
+                match self.sinks.dispatcher(&self.settings.sinks) {
+                    Ok(dispatcher) => dispatcher.dispatch(&record).await,
+                    Err(err) => warn!(
+                        "Could not deliver the stalled drain of node {}: {}",
+                        record.object_name, err
+                    ),
+                }
+            }
+        }
+    }
+
+    // the records of the drains that just stalled
+    fn check(&self) -> Vec<EventRecord> {
+        let mut state = self.lock();
+        let mut records = Vec::new();
+        state.drains.retain(|node, drain| {
+            let remaining = self.remaining(node);
+            if remaining.is_empty() {
+                drained(node, drain);
+                return false;
+            }
+            NODE_DRAIN_REMAINING.set(&[(NODE_LABEL, node.clone())], remaining.len() as f64);
+            if remaining.len() < drain.remaining {
+                drain.progressed = clock::instant();
+            }
+            drain.remaining = remaining.len();
+            if !drain.stalled
+                && clock::instant().duration_since(drain.progressed) >= self.settings.stall_after
+            {
+                drain.stalled = true;
+                let record = stalled(node, drain, &remaining);
+                warn!("{}", record.message);
+                records.push(record);
+            }
+            true
+        });
+        records
+    }
+
+    // the pods a drain evicts, the ones of the DaemonSets and the static ones staying
+    fn remaining(&self, node: &str) -> Vec<std::sync::Arc<Pod>> {
+        // there's no index of the pods by node, the cache is gone through once per node
+        self.pods
+            .state()
+            .into_iter()
+            .filter(|pod| {
+                pod.spec.as_ref().and_then(|spec| spec.node_name.as_deref()) == Some(node)
+            })
+            .filter(|pod| {
+                let done = pod
+                    .status
+                    .as_ref()
+                    .and_then(|status| status.phase.as_deref())
+                    .is_some_and(|phase| phase == "Succeeded" || phase == "Failed");
+                let daemon = pod
+                    .owner_references()
+                    .iter()
+                    .any(|owner| owner.kind == "DaemonSet");
+                !done && !daemon && !pod.annotations().contains_key(MIRROR_ANNOTATION)
+            })
+            .collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("drains lock poisoned")
+    }
+}
+
+fn drained(node: &str, drain: &Drain) {
+    let took = clock::instant().duration_since(drain.started);
+    info!(
+        "Node {} was drained in {}",
+        node,
+        humantime::format_duration(Duration::from_secs(took.as_secs()))
+    );
+    histogram!(NODE_DRAIN_HISTOGRAM).record(took.as_secs_f64());
+    NODE_DRAIN_REMAINING.remove(NODE_LABEL, node);
+}
+
+// what the sinks get of a drain no pod left for stall_after, the pods whose budgets let
+// none of them go named with their budget
+fn stalled(node: &str, drain: &Drain, remaining: &[std::sync::Arc<Pod>]) -> EventRecord {
+    let now = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, false);
+    let mut pods = remaining
+        .iter()
+        .map(|pod| {
+            let namespace = pod.namespace().unwrap_or_default();
+            let name = format!("{}/{}", namespace, pod.name_any());
+            match disruptions::blocking(&namespace, &workload(pod)).first() {
+                Some(budget) => format!("{} (PodDisruptionBudget {} allows none)", name, budget),
+                None => name,
+            }
+        })
+        .collect::<Vec<_>>();
+    pods.sort();
+    let seconds = |duration: Duration| {
+        humantime::format_duration(Duration::from_secs(duration.as_secs())).to_string()
+    };
+    let message = format!(
+        "Node {} has been draining for {}, its last {} pods not going for {}: {}",
+        node,
+        seconds(clock::instant().duration_since(drain.started)),
+        remaining.len(),
+        seconds(clock::instant().duration_since(drain.progressed)),
+        pods.join(", ")
+    );
+    EventRecord {
+        uid: format!("{}/DrainStalled/{}", node, now),
+        name: node.to_string(),
+        kind: "Node".to_string(),
+        object_name: node.to_string(),
+        reason: "DrainStalled".to_string(),
+        message,
+        type_: "Warning".to_string(),
+        source: "k8rs".to_string(),
+        first_timestamp: Some(now.clone()),
+        last_timestamp: Some(now),
+        count: 1,
+        node: Some(node.to_string()),
+        ..EventRecord::default()
+    }
+}
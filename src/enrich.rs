@@ -0,0 +1,173 @@
+use crate::{
+    cache::{NodeStore, PodStore},
+    config::EnrichmentSettings,
+    metrics::sanitize_label_name,
+};
+use k8s_openapi::api::core::v1::{Event, Node, Pod};
+use kube::{runtime::reflector::ObjectRef, ResourceExt};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+// the well-known node label with the node's availability zone
+pub const ZONE_LABEL: &str = "topology.kubernetes.io/zone";
+
+// what we know about the pod an event is about, taken from the pod cache
+pub struct PodContext {
+    pub node: Option<String>,
+    // the controlling owner, like "ReplicaSet/nginx-7c5ddbdf54"
+    pub owner: Option<String>,
+}
+
+// the pod label/annotation keys to copy, along with their prometheus label names
+struct EnrichmentKeys {
+    pod_labels: Vec<(String, String)>,
+    pod_annotations: Vec<(String, String)>,
+}
+
+impl EnrichmentKeys {
+    fn new(settings: &EnrichmentSettings) -> Self {
+        let named = |prefix: &str, keys: &[String]| {
+            keys.iter()
+                .map(|key| (key.clone(), sanitize_label_name(prefix, key)))
+                .collect::<Vec<_>>()
+        };
+        EnrichmentKeys {
+            pod_labels: named("label", &settings.pod_labels),
+            pod_annotations: named("annotation", &settings.pod_annotations),
+        }
+    }
+}
+
+// looks up extra information about the objects events are about
+#[derive(Clone)]
+pub struct Enricher {
+    pods: PodStore,
+    nodes: NodeStore,
+    // shared by every clone, so a reload reaches all the pipelines at once
+    keys: Arc<RwLock<Arc<EnrichmentKeys>>>,
+}
+
+impl Enricher {
+    pub fn new(pods: PodStore, nodes: NodeStore, settings: &EnrichmentSettings) -> Self {
+        Enricher {
+            pods,
+            nodes,
+            keys: Arc::new(RwLock::new(Arc::new(EnrichmentKeys::new(settings)))),
+        }
+    }
+
+    // switches to new label/annotation keys, for the events processed from now on
+    pub fn reload(&self, settings: &EnrichmentSettings) {
+        *self.keys.write().expect("enrichment keys lock poisoned") =
+            Arc::new(EnrichmentKeys::new(settings));
+    }
+
+    fn keys(&self) -> Arc<EnrichmentKeys> {
+        self.keys
+            .read()
+            .expect("enrichment keys lock poisoned")
+            .clone()
+    }
+
+    // finds the pod the event is about, if it's still around
+    pub fn pod(&self, event: &Event) -> Option<Arc<Pod>> {
+        let object = &event.involved_object;
+        let name = object.name.as_ref()?;
+        let mut key = ObjectRef::<Pod>::new(name);
+        if let Some(ref namespace) = object.namespace {
+            key = key.within(namespace);
+        }
+
+        // a pod with the same name may have replaced the one the event is about
+        self.pods
+            .get(&key)
+            .filter(|pod| object.uid.is_none() || pod.uid() == object.uid)
+    }
+
+    pub fn pod_context(&self, event: &Event) -> Option<PodContext> {
+        let pod = self.pod(event)?;
+        let owner = pod
+            .owner_references()
+            .iter()
+            .find(|owner| owner.controller == Some(true))
+            .map(|owner| format!("{}/{}", owner.kind, owner.name));
+
+        Some(PodContext {
+            node: pod.spec.as_ref().and_then(|spec| spec.node_name.clone()),
+            owner,
+        })
+    }
+
+    // the configured pod labels and annotations of the event's pod, ready to be used
+    // as metric labels. Every configured key is always there (empty when the pod or the
+    // key is missing), so all series of a metric have the same label names.
+    pub fn pod_labels(&self, event: &Event) -> Vec<(String, String)> {
+        let keys = self.keys();
+        let pod = if keys.pod_labels.is_empty() && keys.pod_annotations.is_empty() {
+            None
+        } else {
+            self.pod(event)
+        };
+
+        let value = |values: Option<&BTreeMap<String, String>>, key: &String| {
+            values
+                .and_then(|values| values.get(key))
+                .cloned()
+                .unwrap_or_default()
+        };
+        let labels = pod.as_ref().map(|pod| pod.labels());
+        let annotations = pod.as_ref().map(|pod| pod.annotations());
+
+        keys.pod_labels
+            .iter()
+            .map(|(key, name)| (name.clone(), value(labels, key)))
+            .chain(
+                keys.pod_annotations
+                    .iter()
+                    .map(|(key, name)| (name.clone(), value(annotations, key))),
+            )
+            .collect()
+    }
+
+    // the node the event's pod runs on and that node's zone,
+    // empty when we don't know (yet) where the pod is
+    pub fn node_and_zone(&self, event: &Event) -> (String, String) {
+        let Some(node_name) = self
+            .pod(event)
+            .and_then(|pod| pod.spec.as_ref().and_then(|spec| spec.node_name.clone()))
+        else {
+            return (String::new(), String::new());
+        };
+
+        let zone = self
+            .nodes
+            .get(&ObjectRef::<Node>::new(&node_name))
+            .and_then(|node| node.labels().get(ZONE_LABEL).cloned())
+            .unwrap_or_default();
+        (node_name, zone)
+    }
+
+    // the configured pod labels and annotations of the event's pod by their original key,
+    // only the ones the pod actually has
+    pub fn record_labels(&self, event: &Event) -> BTreeMap<String, String> {
+        let mut labels = BTreeMap::new();
+        let Some(pod) = self.pod(event) else {
+            return labels;
+        };
+
+        let keys = self.keys();
+        for (key, _) in keys.pod_labels.iter() {
+            if let Some(value) = pod.labels().get(key) {
+                labels.insert(key.clone(), value.clone());
+            }
+        }
+        for (key, _) in keys.pod_annotations.iter() {
+            if let Some(value) = pod.annotations().get(key) {
+                labels.insert(key.clone(), value.clone());
+            }
+        }
+        labels
+    }
+}
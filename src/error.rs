@@ -0,0 +1,357 @@
+use crate::{
+    clock,
+    metrics::{COMPONENT_LABEL, ERRORS_COUNTER, KIND_LABEL},
+};
+use axum_prometheus::metrics::counter;
+use k8s_openapi::chrono::SecondsFormat;
+use kube::runtime::watcher;
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    sync::{LazyLock, Mutex},
+};
+use tracing::error;
+
+// the errors reported so far by component, for /admin/state
+static REPORTED: LazyLock<Mutex<BTreeMap<&'static str, Reported>>> =
+    LazyLock::new(Default::default);
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// what went wrong, and where: the component it happened in (a watcher, the reloader,
+// the startup) and the resource it was about, like the ConfigMap of the scripts.
+// They're all reported the same way, see report. The kinds are what the startup
+// returns, so the kube errors in them are boxed, they're big.
+#[derive(Debug, thiserror::Error)]
+#[error("{}{kind}", .resource.as_ref().map(|resource| format!("{}: ", resource)).unwrap_or_default())]
+pub struct Error {
+    pub component: &'static str,
+    pub resource: Option<String>,
+    #[source]
+    pub kind: Box<ErrorKind>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ErrorKind {
+    // a watcher's stream, which backs off and goes on
+    #[error("{0}")]
+    Watch(#[source] Box<watcher::Error>),
+    // a request to the api server
+    #[error("{0}")]
+    Kube(#[source] Box<kube::Error>),
+    // the os, like the signal handlers
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    // a request to something else than the api server, like a sink's or a registry's
+    #[error("{0}")]
+    Http(#[from] reqwest::Error),
+    // a yaml or json document that isn't what it should be, like a recording
+    #[error("{0}")]
+    Parse(#[source] BoxError),
+    // a config, or a part of it like the scripts, that can't be used until it's fixed
+    #[error("{0}")]
+    Config(String),
+    // what the operator couldn't start without
+    #[error("{0}")]
+    Startup(String),
+    // what was being done when it happened, like the file being read. It's of the kind
+    // of the error underneath.
+    #[error("{context}: {source}")]
+    Context {
+        context: String,
+        #[source]
+        source: Box<ErrorKind>,
+    },
+    // the errors of the other libraries, like the tls' or the wasm runtime's
+    #[error("{0}")]
+    Other(#[source] BoxError),
+}
+
+impl From<watcher::Error> for ErrorKind {
+    fn from(err: watcher::Error) -> Self {
+        ErrorKind::Watch(Box::new(err))
+    }
+}
+
+impl From<kube::Error> for ErrorKind {
+    fn from(err: kube::Error) -> Self {
+        ErrorKind::Kube(Box::new(err))
+    }
+}
+
+impl From<serde_yaml::Error> for ErrorKind {
+    fn from(err: serde_yaml::Error) -> Self {
+        ErrorKind::Parse(Box::new(err))
+    }
+}
+
+impl From<serde_json::Error> for ErrorKind {
+    fn from(err: serde_json::Error) -> Self {
+        ErrorKind::Parse(Box::new(err))
+    }
+}
+
+// the messages of the startup, like a sink declared twice
+impl From<String> for ErrorKind {
+    fn from(message: String) -> Self {
+        ErrorKind::Startup(message)
+    }
+}
+
+impl From<&str> for ErrorKind {
+    fn from(message: &str) -> Self {
+        ErrorKind::Startup(message.to_string())
+    }
+}
+
+impl ErrorKind {
+    pub fn other(err: impl Into<BoxError>) -> Self {
+        ErrorKind::Other(err.into())
+    }
+
+    pub fn context(self, context: impl Display) -> Self {
+        ErrorKind::Context {
+            context: context.to_string(),
+            source: Box::new(self),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::Watch(_) => "watch",
+            ErrorKind::Kube(_) => "kube",
+            ErrorKind::Io(_) => "io",
+            ErrorKind::Http(_) => "http",
+            ErrorKind::Parse(_) => "parse",
+            ErrorKind::Config(_) => "config",
+            ErrorKind::Startup(_) => "startup",
+            ErrorKind::Context { source, .. } => source.name(),
+            ErrorKind::Other(_) => "other",
+        }
+    }
+
+    // whether trying again may work without anyone doing anything, like after the api
+    // server was unavailable for a while
+    pub fn retryable(&self) -> bool {
+        match self {
+            ErrorKind::Watch(err) => match **err {
+                // the resource version that's too old is relisted
+                watcher::Error::WatchError(ref response) => {
+                    response.code == 410 || retryable_code(response.code)
+                }
+                watcher::Error::InitialListFailed(ref err)
+                | watcher::Error::WatchStartFailed(ref err)
+                | watcher::Error::WatchFailed(ref err) => kube_retryable(err),
+                watcher::Error::NoResourceVersion => false,
+            },
+            ErrorKind::Kube(err) => kube_retryable(err),
+            ErrorKind::Io(_) => true,
+            ErrorKind::Http(err) => {
+                err.is_timeout()
+                    || err.is_connect()
+                    || err
+                        .status()
+                        .is_some_and(|status| retryable_code(status.as_u16()))
+            }
+            ErrorKind::Context { source, .. } => source.retryable(),
+            ErrorKind::Parse(_) | ErrorKind::Config(_) | ErrorKind::Startup(_) => false,
+            ErrorKind::Other(_) => false,
+        }
+    }
+}
+
+// the context of the errors of the startup, like `.context("could not read ...")`
+pub trait Context<T> {
+    fn context(self, context: impl Display) -> Result<T, ErrorKind>;
+
+    fn with_context<C: Display>(self, context: impl FnOnce() -> C) -> Result<T, ErrorKind>;
+}
+
+impl<T, E: Into<ErrorKind>> Context<T> for Result<T, E> {
+    fn context(self, context: impl Display) -> Result<T, ErrorKind> {
+        self.map_err(|err| Into::<ErrorKind>::into(err).context(context))
+    }
+
+    fn with_context<C: Display>(self, context: impl FnOnce() -> C) -> Result<T, ErrorKind> {
+        self.map_err(|err| Into::<ErrorKind>::into(err).context(context()))
+    }
+}
+
+fn retryable_code(code: u16) -> bool {
+    code == 429 || code >= 500
+}
+
+fn kube_retryable(err: &kube::Error) -> bool {
+    match err {
+        kube::Error::Api(response) => retryable_code(response.code),
+        kube::Error::HyperError(_) | kube::Error::Service(_) | kube::Error::ReadEvents(_) => true,
+        _ => false,
+    }
+}
+
+// the last error of a component, and how many it had
+struct Reported {
+    count: u64,
+    kind: &'static str,
+    resource: Option<String>,
+    message: String,
+    retryable: bool,
+    at: String,
+}
+
+impl Error {
+    pub fn new(component: &'static str, kind: impl Into<ErrorKind>) -> Self {
+        Error {
+            component,
+            resource: None,
+            kind: Box::new(kind.into()),
+        }
+    }
+
+    pub fn config(component: &'static str, message: impl ToString) -> Self {
+        Error::new(component, ErrorKind::Config(message.to_string()))
+    }
+
+    pub fn on(mut self, resource: impl Into<String>) -> Self {
+        self.resource = Some(resource.into());
+        self
+    }
+
+    pub fn retryable(&self) -> bool {
+        self.kind.retryable()
+    }
+
+    // with the causes underneath it that the errors above don't tell already, like what
+    // a kube client's connection failed with
+    fn message(&self) -> String {
+        let mut message = self.to_string();
+        let mut source = std::error::Error::source(self);
+        while let Some(err) = source {
+            let cause = err.to_string();
+            if !message.contains(&cause) {
+                message = format!("{}: {}", message, cause);
+            }
+            source = err.source();
+        }
+        message
+    }
+
+    // logged with its context as fields, counted on errors_total{component, kind} and
+    // kept as the component's last error for /admin/state
+    pub fn report(&self) {
+        let kind = self.kind.name();
+        let message = self.message();
+        error!(
+            component = self.component,
+            kind,
+            resource = self.resource.as_deref(),
+            retryable = self.retryable(),
+            "{}",
+            message
+        );
+        counter!(
+            ERRORS_COUNTER,
+            &[
+                (COMPONENT_LABEL, self.component.to_string()),
+                (KIND_LABEL, kind.to_string()),
+            ]
+        )
+        .increment(1);
+        let mut reported = REPORTED.lock().expect("errors lock poisoned");
+        let count = reported.get(self.component).map_or(0, |last| last.count);
+        reported.insert(
+            self.component,
+            Reported {
+                count: count + 1,
+                kind,
+                resource: self.resource.clone(),
+                message,
+                retryable: self.retryable(),
+                at: clock::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            },
+        );
+    }
+}
+
+// reported first, for main to exit with. The error keeps its kind, a kube error that
+// made the startup fail is still a kube error.
+pub fn fatal<T, E: Into<ErrorKind>>(
+    component: &'static str,
+    result: Result<T, E>,
+) -> Result<T, Error> {
+    result.map_err(|err| {
+        let err = Error::new(component, err);
+        err.report();
+        err
+    })
+}
+
+pub fn reported() -> Value {
+    let reported = REPORTED.lock().expect("errors lock poisoned");
+    reported
+        .iter()
+        .map(|(component, last)| {
+            (
+                component.to_string(),
+                json!({
+                    "count": last.count,
+                    "kind": last.kind,
+                    "resource": last.resource,
+                    "message": last.message,
+                    "retryable": last.retryable,
+                    "at": last.at,
+                }),
+            )
+        })
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("could not connect")]
+    struct Connect(#[source] io::Error);
+
+    #[test]
+    fn keeps_the_kind_of_what_failed_the_startup() {
+        let refused = io::Error::from(io::ErrorKind::ConnectionRefused);
+        let err = fatal::<(), _>("test", Err(refused)).unwrap_err();
+        assert_eq!(err.kind.name(), "io");
+        assert!(err.retryable());
+
+        let err = fatal::<(), _>("test", Err("--pods must be at least 1")).unwrap_err();
+        assert_eq!(err.kind.name(), "startup");
+        assert!(!err.retryable());
+    }
+
+    #[test]
+    fn keeps_the_kind_under_the_context() {
+        let missing = io::Error::new(io::ErrorKind::NotFound, "no such file");
+        let err = Err::<(), _>(missing)
+            .context("could not read \"config.yaml\"")
+            .unwrap_err();
+        assert_eq!(err.name(), "io");
+        assert!(err.retryable());
+        assert_eq!(
+            err.to_string(),
+            "could not read \"config.yaml\": no such file"
+        );
+        assert!(std::error::Error::source(&err).is_some());
+    }
+
+    #[test]
+    fn tells_the_causes_underneath() {
+        let refused = io::Error::new(io::ErrorKind::ConnectionRefused, "connection refused");
+        let err = Error::new("test", ErrorKind::other(Connect(refused))).on("lease");
+        assert_eq!(err.to_string(), "lease: could not connect");
+        assert_eq!(
+            err.message(),
+            "lease: could not connect: connection refused"
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use crate::{
+    config::FeatureSettings,
+    metrics::{FEATURE_ENABLED_GAUGE, FEATURE_LABEL, STAGE_LABEL},
+};
+use axum_prometheus::metrics::gauge;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{LazyLock, RwLock},
+};
+
+pub const POD_LIFECYCLE: &str = "pod_lifecycle";
+pub const POD_RESOURCES: &str = "pod_resources";
+pub const POD_PRIORITIES: &str = "pod_priorities";
+pub const POD_CHURN: &str = "pod_churn";
+pub const IMAGE_INVENTORY: &str = "image_inventory";
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    // might change or go away, off unless listed
+    Experimental,
+    // on unless switched off, for the clusters where it's too much
+    Beta,
+}
+
+impl Stage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::Experimental => "experimental",
+            Stage::Beta => "beta",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Flag {
+    pub name: &'static str,
+    pub stage: Stage,
+    pub default: bool,
+    pub description: &'static str,
+}
+
+impl Flag {
+    // the flags the config doesn't mention keep their default
+    pub fn on(&self, switches: &BTreeMap<String, bool>) -> bool {
+        switches.get(self.name).copied().unwrap_or(self.default)
+    }
+}
+
+// the subsystems that can be switched on and off per cluster without another build.
+// A new one starts out experimental, off by default.
+pub const FLAGS: [Flag; 5] = [
+    Flag {
+        name: POD_LIFECYCLE,
+        stage: Stage::Beta,
+        default: true,
+        description: "the states pods go through, their scheduling and termination times",
+    },
+    Flag {
+        name: POD_RESOURCES,
+        stage: Stage::Beta,
+        default: true,
+        description: "the resources requested and limited by namespace and workload",
+    },
+    Flag {
+        name: POD_PRIORITIES,
+        stage: Stage::Experimental,
+        default: false,
+        description: "the pods pending and preempted by priority class",
+    },
+    Flag {
+        name: POD_CHURN,
+        stage: Stage::Experimental,
+        default: false,
+        description: "the pods created and deleted by workload, the deletions by cause",
+    },
+    Flag {
+        name: IMAGE_INVENTORY,
+        stage: Stage::Experimental,
+        default: false,
+        description: "the images the pods run by namespace, tag and digest, on /api/v1/images",
+    },
+];
+
+// the flags switched on, changed on a reload
+static ENABLED: LazyLock<RwLock<BTreeSet<&'static str>>> = LazyLock::new(|| {
+    RwLock::new(
+        FLAGS
+            .iter()
+            .filter(|flag| flag.default)
+            .map(|flag| flag.name)
+            .collect(),
+    )
+});
+
+pub fn enabled(name: &str) -> bool {
+    ENABLED
+        .read()
+        .expect("features lock poisoned")
+        .contains(name)
+}
+
+// the names of the config that aren't flags, for the validation
+pub fn unknown(settings: &FeatureSettings) -> Vec<String> {
+    settings
+        .switches()
+        .into_keys()
+        .filter(|name| !FLAGS.iter().any(|flag| flag.name == name))
+        .collect()
+}
+
+// switches to the flags of a config
+pub fn configure(settings: &FeatureSettings) {
+    let switches = settings.switches();
+    let enabled = FLAGS
+        .iter()
+        .filter(|flag| flag.on(&switches))
+        .map(|flag| flag.name)
+        .collect::<BTreeSet<_>>();
+    for flag in FLAGS.iter() {
+        gauge!(
+            FEATURE_ENABLED_GAUGE,
+            &[
+                (FEATURE_LABEL, flag.name.to_string()),
+                (STAGE_LABEL, flag.stage.as_str().to_string())
+            ]
+        )
+        .set(if enabled.contains(flag.name) {
+            1.0
+        } else {
+            0.0
+        });
+    }
+    *ENABLED.write().expect("features lock poisoned") = enabled;
+}
+
+// every flag and whether it's on, for /admin/features
+pub fn status() -> Value {
+    let enabled = ENABLED.read().expect("features lock poisoned").clone();
+    json!(FLAGS
+        .iter()
+        .map(|flag| json!({
+            "name": flag.name,
+            "stage": flag.stage,
+            "default": flag.default,
+            "enabled": enabled.contains(flag.name),
+            "description": flag.description,
+        }))
+        .collect::<Vec<_>>())
+}
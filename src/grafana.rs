@@ -0,0 +1,148 @@
+use crate::rollups::{Filter, ROLLUPS};
+use axum::{
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+use k8s_openapi::chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+// the metric of every target, filtered like events{namespace="shop",reason="BackOff"}
+const EVENTS: &str = "events";
+
+// the filters a target can have, in this order in /search
+const FIELDS: [&str; 3] = ["namespace", "reason", "workload"];
+
+#[derive(Deserialize)]
+struct SearchRequest {
+    #[serde(default)]
+    target: String,
+}
+
+#[derive(Deserialize)]
+struct QueryRequest {
+    range: Range,
+    #[serde(default)]
+    targets: Vec<Target>,
+}
+
+#[derive(Deserialize)]
+struct Range {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    #[serde(default)]
+    target: String,
+    // timeserie or table
+    #[serde(rename = "type", default)]
+    type_: Option<String>,
+}
+
+// the endpoints of Grafana's JSON datasources (SimpleJSON and the ones following it)
+// over the rollups, for charting and tabling the events without a log store. The
+// datasource's url is http://k8rs:8080/api/v1/grafana.
+pub fn router() -> Router {
+    Router::new()
+        .route("/api/v1/grafana", get(|| async { "ok" }))
+        .route("/api/v1/grafana/", get(|| async { "ok" }))
+        .route("/api/v1/grafana/search", post(search))
+        .route("/api/v1/grafana/query", post(query))
+}
+
+// the targets there are, the ones containing what was typed so far
+async fn search(Json(request): Json<SearchRequest>) -> Json<Vec<String>> {
+    let mut targets = vec![EVENTS.to_string()];
+    for (field, values) in FIELDS.iter().zip(ROLLUPS.values()) {
+        for value in values {
+            targets.push(format!("{}{{{}=\"{}\"}}", EVENTS, field, value));
+        }
+    }
+    targets.retain(|target| target.contains(&request.target));
+    Json(targets)
+}
+
+async fn query(Json(request): Json<QueryRequest>) -> impl IntoResponse {
+    let since = request.range.from.timestamp();
+    let until = request.range.to.timestamp();
+    let (resolution, since) = ROLLUPS.resolution(since);
+    let length = resolution.length();
+    let mut answers = Vec::new();
+    for target in request.targets.iter() {
+        let Some(filter) = parse(&target.target) else {
+            let message = format!(
+                "invalid target {:?}, like events{{namespace=\"shop\",reason=\"BackOff\"}}\n",
+                target.target
+            );
+            return (StatusCode::BAD_REQUEST, message).into_response();
+        };
+        let rows = ROLLUPS.rows(resolution, since, until, &filter);
+        if target.type_.as_deref() == Some("table") {
+            answers.push(json!({
+                "type": "table",
+                "columns": [
+                    {"text": "Time", "type": "time"},
+                    {"text": "Namespace", "type": "string"},
+                    {"text": "Reason", "type": "string"},
+                    {"text": "Workload", "type": "string"},
+                    {"text": "Events", "type": "number"},
+                ],
+                "rows": rows
+                    .iter()
+                    .map(|row| json!([
+                        row.start.timestamp_millis(),
+                        row.key.namespace,
+                        row.key.reason,
+                        row.key.workload,
+                        row.events,
+                    ]))
+                    .collect::<Vec<_>>(),
+            }));
+            continue;
+        }
+        // every hour or day of the range, the ones without events being 0
+        let mut totals = BTreeMap::<i64, u64>::new();
+        let mut start = since - since.rem_euclid(length);
+        while start <= until {
+            totals.insert(start, 0);
+            start += length;
+        }
+        for row in rows.iter() {
+            *totals.entry(row.start.timestamp()).or_default() += row.events;
+        }
+        answers.push(json!({
+            "target": target.target,
+            "datapoints": totals
+                .into_iter()
+                .map(|(start, events)| json!([events, start * 1000]))
+                .collect::<Vec<_>>(),
+        }));
+    }
+    Json(Value::Array(answers)).into_response()
+}
+
+// events, or events{namespace="shop",reason="BackOff"}, the quotes being optional
+fn parse(target: &str) -> Option<Filter> {
+    let filters = target.trim().strip_prefix(EVENTS)?.trim();
+    let mut filter = Filter::default();
+    if filters.is_empty() {
+        return Some(filter);
+    }
+    let filters = filters.strip_prefix('{')?.strip_suffix('}')?;
+    for pair in filters.split(',').filter(|pair| !pair.trim().is_empty()) {
+        let (field, value) = pair.split_once('=')?;
+        let value = value.trim().trim_matches('"').to_string();
+        match field.trim() {
+            "namespace" => filter.namespace = Some(value),
+            "reason" => filter.reason = Some(value),
+            "workload" => filter.workload = Some(value),
+            _ => return None,
+        }
+    }
+    Some(filter)
+}
@@ -0,0 +1,179 @@
+use crate::{
+    cache::{NodeStore, PodStore, ServiceStore},
+    enrich::ZONE_LABEL,
+    pods::workload,
+};
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use k8s_openapi::api::core::v1::{Node, Pod, Service};
+use kube::{runtime::reflector::ObjectRef, ResourceExt};
+use serde::Serialize;
+use std::sync::{LazyLock, RwLock};
+
+// what a pod is related to, worked out from the caches when asked: its owners, its node,
+// the claims of its volumes and the services selecting it. The caches are only there once
+// the watchers started, after the http server, so they're attached to a global.
+pub static GRAPH: LazyLock<Graph> = LazyLock::new(Graph::default);
+
+#[derive(Default)]
+pub struct Graph {
+    caches: RwLock<Option<Caches>>,
+}
+
+struct Caches {
+    pods: PodStore,
+    nodes: NodeStore,
+    services: ServiceStore,
+}
+
+#[derive(Serialize)]
+pub struct Neighborhood {
+    pub pod: PodSummary,
+    pub edges: Vec<Edge>,
+}
+
+#[derive(Serialize)]
+pub struct PodSummary {
+    pub namespace: String,
+    pub name: String,
+    pub uid: Option<String>,
+    pub phase: Option<String>,
+}
+
+// one of the pod's relationships, like {relation: service, kind: Service, name: api}
+#[derive(Serialize)]
+pub struct Edge {
+    pub relation: &'static str,
+    pub kind: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+}
+
+impl Graph {
+    pub fn attach(&self, pods: PodStore, nodes: NodeStore, services: ServiceStore) {
+        *self.caches.write().expect("graph lock poisoned") = Some(Caches {
+            pods,
+            nodes,
+            services,
+        });
+    }
+
+    // None when the pod isn't in the cache, or the caches aren't there yet
+    pub fn neighborhood(&self, namespace: &str, name: &str) -> Option<Neighborhood> {
+        let caches = self.caches.read().expect("graph lock poisoned");
+        let caches = caches.as_ref()?;
+        let pod = caches
+            .pods
+            .get(&ObjectRef::<Pod>::new(name).within(namespace))?;
+        let mut edges = Vec::new();
+        let edge = |relation, kind: &str, name: &str| Edge {
+            relation,
+            kind: kind.to_string(),
+            name: name.to_string(),
+            zone: None,
+        };
+        for owner in pod.owner_references() {
+            edges.push(edge("owner", &owner.kind, &owner.name));
+        }
+        // the deployment behind the replicaset
+        let workload = workload(&pod);
+        if let Some((kind, workload)) = workload.split_once('/') {
+            if kind != "Pod"
+                && !edges
+                    .iter()
+                    .any(|edge| edge.kind == kind && edge.name == workload)
+            {
+                edges.push(edge("workload", kind, workload));
+            }
+        }
+        let spec = pod.spec.as_ref();
+        if let Some(node) = spec.and_then(|spec| spec.node_name.as_deref()) {
+            let zone = caches
+                .nodes
+                .get(&ObjectRef::<Node>::new(node))
+                .and_then(|node| node.labels().get(ZONE_LABEL).cloned());
+            edges.push(Edge {
+                zone,
+                ..edge("node", "Node", node)
+            });
+        }
+        for volume in spec
+            .and_then(|spec| spec.volumes.as_ref())
+            .into_iter()
+            .flatten()
+        {
+            if let Some(ref claim) = volume.persistent_volume_claim {
+                edges.push(edge("claim", "PersistentVolumeClaim", &claim.claim_name));
+            }
+        }
+        let mut services = caches
+            .services
+            .state()
+            .into_iter()
+            .filter(|service| service.namespace().as_deref() == Some(namespace))
+            .filter(|service| selects(service, &pod))
+            .map(|service| service.name_any())
+            .collect::<Vec<_>>();
+        services.sort();
+        for service in services {
+            edges.push(edge("service", "Service", &service));
+        }
+        Some(Neighborhood {
+            pod: PodSummary {
+                namespace: namespace.to_string(),
+                name: name.to_string(),
+                uid: pod.uid(),
+                phase: pod.status.as_ref().and_then(|status| status.phase.clone()),
+            },
+            edges,
+        })
+    }
+
+    fn attached(&self) -> bool {
+        self.caches.read().expect("graph lock poisoned").is_some()
+    }
+}
+
+// a service without a selector (its endpoints managed by hand) selects no pod
+fn selects(service: &Service, pod: &Pod) -> bool {
+    let Some(selector) = service
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.selector.as_ref())
+        .filter(|selector| !selector.is_empty())
+    else {
+        return false;
+    };
+    let labels = pod.labels();
+    selector
+        .iter()
+        .all(|(key, value)| labels.get(key) == Some(value))
+}
+
+pub fn router() -> Router {
+    Router::new().route("/api/v1/graph/:namespace/:pod", get(neighborhood))
+}
+
+async fn neighborhood(Path((namespace, pod)): Path<(String, String)>) -> Response {
+    if !GRAPH.attached() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "the caches aren't there yet\n",
+        )
+            .into_response();
+    }
+    match GRAPH.neighborhood(&namespace, &pod) {
+        Some(neighborhood) => Json(neighborhood).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            format!("no pod {}/{} in the cache\n", namespace, pod),
+        )
+            .into_response(),
+    }
+}
@@ -0,0 +1,120 @@
+// the grpc listener next to the http ones. There's no grpc api of ours on it, only the
+// standard grpc.health.v1 and reflection services, so the load balancers checking grpc
+// health and grpcurl work out of the box. Only built with the grpc feature.
+#[cfg(feature = "grpc")]
+pub use enabled::GrpcListener;
+
+#[cfg(not(feature = "grpc"))]
+pub use disabled::GrpcListener;
+
+#[cfg(feature = "grpc")]
+mod enabled {
+    use crate::{
+        config::ServerSettings,
+        error::{Context, ErrorKind},
+        handover,
+        readiness::Readiness,
+        server::bind_tcp,
+    };
+    use std::{sync::Arc, time::Duration};
+    use tokio::net::TcpListener;
+    use tonic::transport::{server::TcpIncoming, Server};
+    use tonic_health::{server::HealthReporter, ServingStatus};
+    use tracing::{info, warn};
+
+    // how often the health follows /readyz
+    const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+    pub struct GrpcListener {
+        listener: TcpListener,
+    }
+
+    impl GrpcListener {
+        // bound with the http sockets, a taken port keeping the operator from starting too
+        pub fn bind(settings: &ServerSettings) -> Result<Option<Self>, ErrorKind> {
+            let Some(address) = settings.grpc_listen else {
+                return Ok(None);
+            };
+            let listener =
+                bind_tcp(address).with_context(|| format!("could not listen on {}", address))?;
+            Ok(Some(GrpcListener { listener }))
+        }
+
+        // serves until the kill signal, like the http server
+        pub async fn serve(self, readiness: Arc<Readiness>) {
+            let address = self.listener.local_addr().ok();
+            let (reporter, health) = tonic_health::server::health_reporter();
+            // both versions of reflection, grpcurl and the older clients asking for v1alpha
+            let reflection = || {
+                tonic_reflection::server::Builder::configure()
+                    .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+            };
+            let (v1, v1alpha) = match (reflection().build_v1(), reflection().build_v1alpha()) {
+                (Ok(v1), Ok(v1alpha)) => (v1, v1alpha),
+                (Err(err), _) | (_, Err(err)) => {
+                    warn!("Could not serve grpc reflection: {}", err);
+                    return;
+                }
+            };
+            let incoming = match TcpIncoming::from_listener(self.listener, true, None) {
+                Ok(incoming) => incoming,
+                Err(err) => {
+                    warn!("Could not serve grpc: {}", err);
+                    return;
+                }
+            };
+            if let Some(address) = address {
+                info!("Serving grpc on {}", address);
+            }
+            let _serving = handover::serving();
+            let server = Server::builder()
+                .add_service(health)
+                .add_service(v1)
+                .add_service(v1alpha)
+                .serve_with_incoming_shutdown(incoming, crate::shutdown_signal());
+            tokio::select! {
+                result = server => {
+                    if let Err(err) = result {
+                        warn!("The grpc server stopped: {}", err);
+                    }
+                }
+                _ = report(reporter, readiness) => {}
+            }
+        }
+    }
+
+    // the health of the server as a whole (the "" service), serving while /readyz is ready
+    async fn report(mut reporter: HealthReporter, readiness: Arc<Readiness>) {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let status = if readiness.drowning().is_empty() {
+                ServingStatus::Serving
+            } else {
+                ServingStatus::NotServing
+            };
+            reporter.set_service_status("", status).await;
+        }
+    }
+}
+
+#[cfg(not(feature = "grpc"))]
+mod disabled {
+    use crate::{config::ServerSettings, error::ErrorKind, readiness::Readiness};
+    use std::sync::Arc;
+
+    // without tonic there's nothing to serve, but a config asking for grpc shouldn't go
+    // unnoticed
+    pub struct GrpcListener;
+
+    impl GrpcListener {
+        pub fn bind(settings: &ServerSettings) -> Result<Option<Self>, ErrorKind> {
+            if settings.grpc_listen.is_some() {
+                return Err("server.grpc_listen needs k8rs built with the grpc feature".into());
+            }
+            Ok(None)
+        }
+
+        pub async fn serve(self, _readiness: Arc<Readiness>) {}
+    }
+}
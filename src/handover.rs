@@ -0,0 +1,222 @@
+use socket2::{Socket, Type};
+use std::{
+    io,
+    net::SocketAddr,
+    os::{
+        fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::{net::UnixListener, process::CommandExt},
+    },
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+// where systemd's socket activation puts the first socket, the others follow
+const FIRST_FD: RawFd = 3;
+
+// the listening sockets the operator was started with, by systemd's socket activation or
+// by the operator before it restarting itself. The servers take theirs rather than bind
+// again, so the connections coming in meanwhile wait in the socket's backlog instead of
+// being refused.
+static INHERITED: LazyLock<Mutex<Vec<Inherited>>> = LazyLock::new(Default::default);
+
+// a copy of every socket served on, what a restart hands over
+static LISTENING: LazyLock<Mutex<Vec<OwnedFd>>> = LazyLock::new(Default::default);
+
+// set once a restart is asked for, the servers stop accepting then
+static RESTARTING: LazyLock<watch::Sender<bool>> = LazyLock::new(|| watch::channel(false).0);
+
+// how many servers are still going, the restart waits for them to finish their requests
+static SERVING: LazyLock<watch::Sender<usize>> = LazyLock::new(|| watch::channel(0).0);
+
+enum Inherited {
+    Tcp(SocketAddr, OwnedFd),
+    Unix(PathBuf, OwnedFd),
+}
+
+// takes the sockets from the environment, to be called before the runtime starts its
+// threads as the variables are removed (the processes we start aren't to take them).
+// That's before the logs are set up, so what went wrong is returned to be logged then.
+pub fn inherit() -> Vec<String> {
+    let mut warnings = Vec::new();
+    *INHERITED.lock().expect("handover lock poisoned") = inherited(&mut warnings);
+    warnings
+}
+
+fn inherited(warnings: &mut Vec<String>) -> Vec<Inherited> {
+    let pid = std::env::var("LISTEN_PID").ok();
+    let count = std::env::var("LISTEN_FDS").ok();
+    for variable in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(variable);
+    }
+    // the sockets are only ours if they were passed to this process
+    let Some(pid) = pid.and_then(|pid| pid.parse::<u32>().ok()) else {
+        return Vec::new();
+    };
+    let Some(count) = count.and_then(|count| count.parse::<RawFd>().ok()) else {
+        return Vec::new();
+    };
+    if pid != std::process::id() {
+        return Vec::new();
+    }
+    (FIRST_FD..FIRST_FD + count)
+        .filter_map(|fd| {
+            // also tells whether it's open at all
+            if let Err(err) = close_on_exec(fd) {
+                warnings.push(format!("Could not inherit socket {}: {}", fd, err));
+                return None;
+            }
+            // safety: they were left open for us, and nothing else took them
+            let socket = Socket::from(unsafe { OwnedFd::from_raw_fd(fd) });
+            let address = socket.local_addr().ok()?;
+            if socket.r#type().ok()? != Type::STREAM {
+                warnings.push(format!("Socket {} isn't a stream socket, closing it", fd));
+                return None;
+            }
+            match (address.as_socket(), address.as_pathname()) {
+                (Some(address), _) => Some(Inherited::Tcp(address, socket.into())),
+                (None, Some(path)) => Some(Inherited::Unix(path.to_path_buf(), socket.into())),
+                (None, None) => None,
+            }
+        })
+        .collect()
+}
+
+// the inherited socket listening on the address, if any
+pub fn tcp(address: SocketAddr) -> Option<std::net::TcpListener> {
+    let mut inherited = INHERITED.lock().expect("handover lock poisoned");
+    let position = inherited.iter().position(
+        |socket| matches!(socket, Inherited::Tcp(inherited, _) if *inherited == address),
+    )?;
+    match inherited.remove(position) {
+        Inherited::Tcp(_, fd) => Some(fd.into()),
+        Inherited::Unix(..) => None,
+    }
+}
+
+pub fn unix(path: &Path) -> Option<UnixListener> {
+    let mut inherited = INHERITED.lock().expect("handover lock poisoned");
+    let position = inherited
+        .iter()
+        .position(|socket| matches!(socket, Inherited::Unix(inherited, _) if inherited == path))?;
+    match inherited.remove(position) {
+        Inherited::Unix(_, fd) => Some(fd.into()),
+        Inherited::Tcp(..) => None,
+    }
+}
+
+// closes the inherited sockets none of the servers listen on anymore, like after the
+// addresses changed, their connections would wait forever
+pub fn release() {
+    for socket in INHERITED.lock().expect("handover lock poisoned").drain(..) {
+        match socket {
+            Inherited::Tcp(address, _) => info!("Closing the inherited socket of {}", address),
+            Inherited::Unix(path, _) => info!("Closing the inherited socket of {:?}", path),
+        }
+    }
+}
+
+// keeps a copy of a socket served on, to hand it over
+pub fn keep(socket: &impl AsFd) -> io::Result<()> {
+    let socket = socket.as_fd().try_clone_to_owned()?;
+    LISTENING
+        .lock()
+        .expect("handover lock poisoned")
+        .push(socket);
+    Ok(())
+}
+
+// held by the servers while they're going
+pub struct Serving(());
+
+pub fn serving() -> Serving {
+    SERVING.send_modify(|count| *count += 1);
+    Serving(())
+}
+
+impl Drop for Serving {
+    fn drop(&mut self) {
+        SERVING.send_modify(|count| *count -= 1);
+    }
+}
+
+// the servers stop accepting and finish the requests they have
+pub fn request() {
+    RESTARTING.send_replace(true);
+}
+
+pub fn requested_now() -> bool {
+    *RESTARTING.borrow()
+}
+
+pub async fn requested() {
+    let mut restarting = RESTARTING.subscribe();
+    let _ = restarting.wait_for(|restarting| *restarting).await;
+}
+
+// waits for the servers to be done (for the drain timeout at most), then runs the binary
+// again in this process with the same arguments and the sockets. It keeps its pid, which
+// matters to a container's first process. Only returns when that failed.
+pub async fn restart(drain: Duration) -> io::Error {
+    let mut serving = SERVING.subscribe();
+    let drained = tokio::time::timeout(drain, serving.wait_for(|count| *count == 0)).await;
+    if drained.is_err() {
+        warn!(
+            "The servers still had requests after {}, restarting anyway",
+            humantime::format_duration(drain)
+        );
+    }
+    let executable = match std::env::current_exe() {
+        Ok(executable) => executable,
+        Err(err) => return err,
+    };
+    let sockets = LISTENING
+        .lock()
+        .expect("handover lock poisoned")
+        .iter()
+        .map(|socket| socket.as_raw_fd())
+        .collect::<Vec<_>>();
+    info!("Restarting with {} listening socket(s)", sockets.len());
+    let mut command = Command::new(executable);
+    command
+        .args(std::env::args_os().skip(1))
+        .env("LISTEN_PID", std::process::id().to_string())
+        .env("LISTEN_FDS", sockets.len().to_string());
+    // safety: only fcntl and dup2 run in between, on the sockets we hold
+    unsafe {
+        command.pre_exec(move || place(&sockets));
+    }
+    command.exec()
+}
+
+// puts the sockets from FIRST_FD on, where they're expected. Copied out of the way first,
+// one of them could be where another goes.
+fn place(sockets: &[RawFd]) -> io::Result<()> {
+    let past = FIRST_FD + sockets.len() as RawFd;
+    let moved = sockets
+        .iter()
+        .map(|socket| check(unsafe { libc::fcntl(*socket, libc::F_DUPFD_CLOEXEC, past) }))
+        .collect::<io::Result<Vec<_>>>()?;
+    for (index, socket) in moved.into_iter().enumerate() {
+        // the copies dup2 makes are kept open through exec
+        check(unsafe { libc::dup2(socket, FIRST_FD + index as RawFd) })?;
+    }
+    Ok(())
+}
+
+// the processes we start don't get them
+fn close_on_exec(fd: RawFd) -> io::Result<()> {
+    let flags = check(unsafe { libc::fcntl(fd, libc::F_GETFD) })?;
+    check(unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) })?;
+    Ok(())
+}
+
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    match result {
+        -1 => Err(io::Error::last_os_error()),
+        result => Ok(result),
+    }
+}
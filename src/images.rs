@@ -0,0 +1,312 @@
+use crate::{
+    features::{self, IMAGE_INVENTORY},
+    metrics::{DIGEST_LABEL, IMAGE_LABEL, NAMESPACE_LABEL, TAG_LABEL},
+    pods::workload,
+    registry::IMAGE_INFO,
+};
+use axum::{extract::Query, http::StatusCode, response::IntoResponse, routing::get, Json, Router};
+use k8s_openapi::api::core::v1::{Event, Pod};
+use kube::ResourceExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::{LazyLock, Mutex},
+};
+
+// the reasons the kubelet gives up pulling an image for, as they show up in the
+// "Error: <reason>" message of its Failed events
+const PULL_FAILURES: &[&str] = &["ErrImagePull", "ImagePullBackOff", "InvalidImageName"];
+
+// the image pull failure an event is about, if it's one.
+// The kubelet also sends "Failed to pull image..." and "Back-off pulling image..."
+// events around each failure, only the "Error:" one is counted so nothing counts twice.
+pub fn pull_failure(event: &Event) -> Option<&'static str> {
+    if event.reason.as_deref() != Some("Failed") {
+        return None;
+    }
+    let reason = event.message.as_deref()?.strip_prefix("Error: ")?.trim();
+    PULL_FAILURES
+        .iter()
+        .find(|failure| **failure == reason)
+        .copied()
+}
+
+// the image of the container an event is about, from its field path,
+// like "spec.containers{nginx}"
+pub fn container_image(pod: &Pod, event: &Event) -> Option<String> {
+    let field_path = event.involved_object.field_path.as_deref()?;
+    let (field, name) = field_path.strip_suffix('}')?.split_once('{')?;
+    let spec = pod.spec.as_ref()?;
+    let containers = match field {
+        "spec.containers" => &spec.containers,
+        "spec.initContainers" => spec.init_containers.as_ref()?,
+        _ => return None,
+    };
+    containers
+        .iter()
+        .find(|container| container.name == name)
+        .and_then(|container| container.image.clone())
+}
+
+// the image without its tag or digest, like "ghcr.io/grsaiago/k8rs",
+// so every version of an image ends up in the same series
+pub fn repository(image: &str) -> &str {
+    let image = image.split_once('@').map_or(image, |(image, _)| image);
+    // a colon before the last slash is a registry's port
+    match image.rfind(':') {
+        Some(colon) if !image[colon..].contains('/') => &image[..colon],
+        _ => image,
+    }
+}
+
+// the images the pods of the pod cache run, for where an image is still running when one
+// of its versions has a CVE. The pod tracker fills it in and /api/v1/images reads it, so
+// like the top objects it's a global.
+pub static INVENTORY: LazyLock<Inventory> = LazyLock::new(Inventory::default);
+
+#[derive(Serialize, Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ImageRef {
+    pub namespace: String,
+    // the repository, like "ghcr.io/grsaiago/k8rs"
+    pub image: String,
+    // latest when there's neither a tag nor a digest, empty for a digest alone
+    pub tag: String,
+    // the one the container runs when the kubelet said so, else the one asked for
+    pub digest: String,
+}
+
+struct Running {
+    name: String,
+    workload: String,
+    // one for every container
+    images: Vec<ImageRef>,
+}
+
+#[derive(Default)]
+struct State {
+    pods: HashMap<String, Running>,
+    containers: HashMap<ImageRef, usize>,
+}
+
+#[derive(Default)]
+pub struct Inventory {
+    state: Mutex<State>,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    #[serde(flatten)]
+    image: ImageRef,
+    containers: usize,
+    workloads: BTreeSet<String>,
+    pods: BTreeSet<String>,
+}
+
+#[derive(Deserialize)]
+struct ImageQuery {
+    image: Option<String>,
+    namespace: Option<String>,
+}
+
+impl Inventory {
+    // the containers of the pods not done yet, the sidecars among the init ones too
+    pub fn pod(&self, pod: &Pod) {
+        let Some(uid) = pod.uid() else {
+            return;
+        };
+        let done = pod
+            .status
+            .as_ref()
+            .and_then(|status| status.phase.as_deref())
+            .is_some_and(|phase| phase == "Succeeded" || phase == "Failed");
+        let mut state = self.lock();
+        if done {
+            state.remove(&uid);
+            return;
+        }
+        let images = images(pod);
+        if state
+            .pods
+            .get(&uid)
+            .is_some_and(|known| known.images == images)
+        {
+            return;
+        }
+        state.remove(&uid);
+        for image in images.iter() {
+            state.add(image, 1);
+        }
+        state.pods.insert(
+            uid,
+            Running {
+                name: pod.name_any(),
+                workload: workload(pod),
+                images,
+            },
+        );
+    }
+
+    pub fn gone(&self, uid: &str) {
+        self.lock().remove(uid);
+    }
+
+    // after a re-list, the pods that weren't listed again were deleted while we were away
+    pub fn retain(&self, listed: &HashSet<String>) {
+        let mut state = self.lock();
+        let gone = state
+            .pods
+            .keys()
+            .filter(|uid| !listed.contains(*uid))
+            .cloned()
+            .collect::<Vec<_>>();
+        for uid in gone {
+            state.remove(&uid);
+        }
+    }
+
+    // the images containing the one asked for, like "nginx:1.25" or a digest, the
+    // pods running them being namespace/name
+    fn entries(&self, query: &ImageQuery) -> Vec<Entry> {
+        let state = self.lock();
+        let mut entries = BTreeMap::<&ImageRef, Entry>::new();
+        for running in state.pods.values() {
+            for image in running.images.iter() {
+                let reference = format!("{}:{}@{}", image.image, image.tag, image.digest);
+                let wanted = query
+                    .image
+                    .as_deref()
+                    .is_none_or(|wanted| reference.contains(wanted))
+                    && query
+                        .namespace
+                        .as_deref()
+                        .is_none_or(|wanted| wanted == image.namespace);
+                if !wanted {
+                    continue;
+                }
+                let entry = entries.entry(image).or_insert_with(|| Entry {
+                    image: image.clone(),
+                    containers: 0,
+                    workloads: BTreeSet::new(),
+                    pods: BTreeSet::new(),
+                });
+                entry.containers += 1;
+                entry.workloads.insert(running.workload.clone());
+                entry
+                    .pods
+                    .insert(format!("{}/{}", image.namespace, running.name));
+            }
+        }
+        entries.into_values().collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("image inventory lock poisoned")
+    }
+}
+
+impl State {
+    fn remove(&mut self, uid: &str) {
+        if let Some(running) = self.pods.remove(uid) {
+            for image in running.images.iter() {
+                self.add(image, -1);
+            }
+        }
+    }
+
+    // the containers running an image, its series going away with the last of them
+    fn add(&mut self, image: &ImageRef, containers: isize) {
+        let count = self.containers.entry(image.clone()).or_default();
+        *count = count.saturating_add_signed(containers);
+        let labels = [
+            (NAMESPACE_LABEL, image.namespace.clone()),
+            (IMAGE_LABEL, image.image.clone()),
+            (TAG_LABEL, image.tag.clone()),
+            (DIGEST_LABEL, image.digest.clone()),
+        ];
+        match *count {
+            0 => {
+                self.containers.remove(image);
+                IMAGE_INFO.remove_where(&[
+                    (NAMESPACE_LABEL, &image.namespace),
+                    (IMAGE_LABEL, &image.image),
+                    (TAG_LABEL, &image.tag),
+                    (DIGEST_LABEL, &image.digest),
+                ]);
+            }
+            count => IMAGE_INFO.set(&labels, count as f64),
+        }
+    }
+}
+
+// the images of a pod's containers, with the digest of the container's status
+fn images(pod: &Pod) -> Vec<ImageRef> {
+    let Some(spec) = pod.spec.as_ref() else {
+        return Vec::new();
+    };
+    let namespace = pod.namespace().unwrap_or_default();
+    let statuses = pod
+        .status
+        .iter()
+        .flat_map(|status| {
+            status
+                .container_statuses
+                .iter()
+                .chain(status.init_container_statuses.iter())
+                .flatten()
+        })
+        .map(|status| (status.name.as_str(), status.image_id.as_str()))
+        .collect::<HashMap<_, _>>();
+    let sidecars = spec
+        .init_containers
+        .iter()
+        .flatten()
+        .filter(|container| container.restart_policy.as_deref() == Some("Always"));
+    let mut images = spec
+        .containers
+        .iter()
+        .chain(sidecars)
+        .filter_map(|container| {
+            let image = container.image.as_deref()?;
+            let repository = repository(image);
+            let (reference, asked) = match image.split_once('@') {
+                Some((reference, digest)) => (reference, digest),
+                None => (image, ""),
+            };
+            let tag = match reference[repository.len()..].strip_prefix(':') {
+                Some(tag) => tag,
+                None if asked.is_empty() => "latest",
+                None => "",
+            };
+            // like docker-pullable://nginx@sha256:..., or the digest alone
+            let digest = statuses
+                .get(container.name.as_str())
+                .map(|id| id.rsplit_once('@').map_or(*id, |(_, digest)| digest))
+                .filter(|digest| digest.starts_with("sha256:"))
+                .unwrap_or(asked);
+            Some(ImageRef {
+                namespace: namespace.clone(),
+                image: repository.to_string(),
+                tag: tag.to_string(),
+                digest: digest.to_string(),
+            })
+        })
+        .collect::<Vec<_>>();
+    images.sort();
+    images
+}
+
+pub fn router() -> Router {
+    Router::new().route("/api/v1/images", get(inventory))
+}
+
+// like /api/v1/images?image=nginx:1.25&namespace=shop, every image by default
+async fn inventory(query: Query<ImageQuery>) -> impl IntoResponse {
+    if !features::enabled(IMAGE_INVENTORY) {
+        return (
+            StatusCode::NOT_FOUND,
+            "the image inventory is off, see features.image_inventory\n",
+        )
+            .into_response();
+    }
+    Json(serde_json::json!({ "images": INVENTORY.entries(&query) })).into_response()
+}
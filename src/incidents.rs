@@ -0,0 +1,367 @@
+use crate::{
+    cache::ReplicaSetStore,
+    clock,
+    config::IncidentSettings,
+    enrich::Enricher,
+    metrics::{INCIDENTS_COUNTER, NAMESPACE_LABEL},
+    pods,
+    record::EventRecord,
+    schema::IncidentDetails,
+    sinks::SinkRegistry,
+};
+use axum::{routing::get, Json, Router};
+use axum_prometheus::metrics::counter;
+use k8s_openapi::{
+    api::{apps::v1::ReplicaSet, core::v1::Event},
+    chrono::{DateTime, SecondsFormat, Utc},
+};
+use kube::{runtime::reflector::ObjectRef, ResourceExt};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+use tracing::{info, warn};
+
+// how often the quiet incidents are closed
+const CLOSE_EVERY: Duration = Duration::from_secs(10);
+
+// the events of a workload and of its pods and replicasets correlated into incidents,
+// for /api/v1/incidents and the sinks. The workers fill it in with every record, so
+// like the top objects it's a global.
+pub static INCIDENTS: LazyLock<Incidents> = LazyLock::new(Incidents::default);
+
+#[derive(Default)]
+pub struct Incidents {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    settings: IncidentSettings,
+    // the last events of every workload, the ones before a Warning being part of its
+    // incident, like the rollout that started it
+    recent: HashMap<Workload, VecDeque<Line>>,
+    open: HashMap<Workload, Incident>,
+    // the last ones first
+    closed: VecDeque<Incident>,
+}
+
+#[derive(Serialize, Debug, Clone, Hash, PartialEq, Eq)]
+struct Workload {
+    namespace: String,
+    // like "Deployment/api"
+    workload: String,
+}
+
+#[derive(Serialize, Clone)]
+struct Incident {
+    id: String,
+    #[serde(flatten)]
+    workload: Workload,
+    started: DateTime<Utc>,
+    last: DateTime<Utc>,
+    // every event of it, with the ones past max_events
+    events: u64,
+    warnings: u64,
+    reasons: BTreeMap<String, u64>,
+    // the first max_events of them
+    lines: Vec<Line>,
+}
+
+#[derive(Serialize, Clone)]
+struct Line {
+    at: DateTime<Utc>,
+    // like "Pod/api-7c5ddbdf54-x2x9z"
+    object: String,
+    #[serde(rename = "type")]
+    type_: String,
+    reason: String,
+    message: String,
+    count: i32,
+}
+
+impl Incidents {
+    pub fn configure(&self, settings: &IncidentSettings) {
+        let mut state = self.state.lock().expect("incidents lock poisoned");
+        state.settings = settings.clone();
+        if !settings.enabled {
+            state.recent.clear();
+            state.open.clear();
+            state.closed.clear();
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.state
+            .lock()
+            .expect("incidents lock poisoned")
+            .settings
+            .enabled
+    }
+
+    // a record of a workload's pods and replicasets, or of the workload itself. A
+    // Warning opens an incident, which takes the workload's events until it's quiet.
+    pub fn observe(&self, record: &EventRecord, workload: String) {
+        let now = clock::now();
+        let key = Workload {
+            namespace: record.namespace.clone(),
+            workload,
+        };
+        let line = Line {
+            at: now,
+            object: format!("{}/{}", record.kind, record.object_name),
+            type_: record.type_.clone(),
+            reason: record.reason.clone(),
+            message: record.message.clone(),
+            count: record.count,
+        };
+        let mut state = self.state.lock().expect("incidents lock poisoned");
+        let settings = state.settings.clone();
+        if !settings.enabled {
+            return;
+        }
+        let since = now - settings.window;
+        let warning = record.type_ == "Warning";
+        if let Some(incident) = state.open.get_mut(&key) {
+            incident.add(line, settings.max_events);
+            return;
+        }
+        let recent = state.recent.entry(key.clone()).or_default();
+        while recent.front().is_some_and(|line| line.at < since) {
+            recent.pop_front();
+        }
+        if !warning {
+            recent.push_back(line);
+            while recent.len() > settings.max_events {
+                recent.pop_front();
+            }
+            return;
+        }
+        let before = state.recent.remove(&key).unwrap_or_default();
+        let mut incident = Incident {
+            id: format!(
+                "{}/{}/{}",
+                key.namespace,
+                key.workload,
+                before.front().map_or(now, |line| line.at).timestamp()
+            ),
+            workload: key.clone(),
+            started: before.front().map_or(now, |line| line.at),
+            last: now,
+            events: 0,
+            warnings: 0,
+            reasons: BTreeMap::new(),
+            lines: Vec::new(),
+        };
+        for line in before.into_iter().chain([line]) {
+            incident.add(line, settings.max_events);
+        }
+        state.open.insert(key, incident);
+    }
+
+    // closes the incidents quiet for the window, delivering the ones with at least
+    // min_events to the sinks
+    pub async fn run(&self, sinks: SinkRegistry) {
+        let mut interval = tokio::time::interval(CLOSE_EVERY);
+        loop {
+            interval.tick().await;
+            let (closed, names) = self.close(clock::now());
+            if closed.is_empty() || names.is_empty() {
+                continue;
+            }
+            // taken from the registry every time, so reloaded sinks are picked up
+            match sinks.dispatcher(&names) {
+                Ok(dispatcher) => {
+                    for incident in closed.iter() {
+                        dispatcher.dispatch(&incident.record()).await;
+                    }
+                }
+                Err(err) => warn!("Could not deliver the incidents: {}", err),
+            }
+        }
+    }
+
+    fn close(&self, now: DateTime<Utc>) -> (Vec<Incident>, Vec<String>) {
+        let mut state = self.state.lock().expect("incidents lock poisoned");
+        let settings = state.settings.clone();
+        let since = now - settings.window;
+        state
+            .recent
+            .retain(|_, lines| lines.back().is_some_and(|line| line.at >= since));
+        let quiet = state
+            .open
+            .iter()
+            .filter(|(_, incident)| incident.last < since)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        let mut closed = Vec::new();
+        for key in quiet {
+            let Some(incident) = state.open.remove(&key) else {
+                continue;
+            };
+            if incident.events < settings.min_events as u64 {
+                continue;
+            }
+            info!(
+                "Incident of {} in {} closed: {}",
+                incident.workload.workload,
+                incident.workload.namespace,
+                incident.summary()
+            );
+            counter!(
+                INCIDENTS_COUNTER,
+                &[(NAMESPACE_LABEL, incident.workload.namespace.clone())]
+            )
+            .increment(1);
+            state.closed.push_front(incident.clone());
+            closed.push(incident);
+        }
+        state.closed.truncate(settings.keep);
+        (closed, settings.sinks.clone())
+    }
+
+    fn render(&self) -> serde_json::Value {
+        let state = self.state.lock().expect("incidents lock poisoned");
+        let min_events = state.settings.min_events as u64;
+        let mut open = state
+            .open
+            .values()
+            .filter(|incident| incident.events >= min_events)
+            .collect::<Vec<_>>();
+        open.sort_by_key(|incident| std::cmp::Reverse(incident.last));
+        serde_json::json!({
+            "window": humantime::format_duration(state.settings.window).to_string(),
+            "open": open,
+            "closed": state.closed,
+        })
+    }
+}
+
+impl Incident {
+    fn add(&mut self, line: Line, max_events: usize) {
+        self.events += 1;
+        if line.type_ == "Warning" {
+            self.warnings += 1;
+        }
+        *self.reasons.entry(line.reason.clone()).or_default() += 1;
+        self.last = self.last.max(line.at);
+        if self.lines.len() < max_events {
+            self.lines.push(line);
+        }
+    }
+
+    // the reasons in the order they first came, like "ScalingReplicaSet, FailedScheduling
+    // (x3), BackOff (x12) over 4m"
+    fn summary(&self) -> String {
+        let mut reasons = Vec::<&str>::new();
+        for line in self.lines.iter() {
+            if !reasons.contains(&line.reason.as_str()) {
+                reasons.push(&line.reason);
+            }
+        }
+        for reason in self.reasons.keys() {
+            if !reasons.contains(&reason.as_str()) {
+                reasons.push(reason);
+            }
+        }
+        let reasons = reasons
+            .into_iter()
+            .map(|reason| match self.reasons.get(reason) {
+                Some(count) if *count > 1 => format!("{} (x{})", reason, count),
+                _ => reason.to_string(),
+            })
+            .collect::<Vec<_>>();
+        let lasted = (self.last - self.started).to_std().unwrap_or_default();
+        format!(
+            "{} over {}",
+            reasons.join(", "),
+            humantime::format_duration(Duration::from_secs(lasted.as_secs()))
+        )
+    }
+
+    // what the sinks get, their record having the events in its incident
+    fn record(&self) -> EventRecord {
+        let timestamp = |time: DateTime<Utc>| time.to_rfc3339_opts(SecondsFormat::Millis, false);
+        let (kind, name) = self
+            .workload
+            .workload
+            .split_once('/')
+            .unwrap_or(("", &self.workload.workload));
+        EventRecord {
+            uid: self.id.clone(),
+            namespace: self.workload.namespace.clone(),
+            name: self.id.clone(),
+            kind: "Incident".to_string(),
+            object_name: name.to_string(),
+            reason: "Incident".to_string(),
+            message: format!("{} {}: {}", kind, name, self.summary()),
+            type_: "Warning".to_string(),
+            source: "k8rs".to_string(),
+            first_timestamp: Some(timestamp(self.started)),
+            last_timestamp: Some(timestamp(self.last)),
+            count: self.events.try_into().unwrap_or(i32::MAX),
+            owner: Some(self.workload.workload.clone()),
+            incident: Some(IncidentDetails {
+                workload: self.workload.workload.clone(),
+                started: timestamp(self.started),
+                ended: timestamp(self.last),
+                reasons: self.reasons.clone(),
+                events: self
+                    .lines
+                    .iter()
+                    .map(|line| {
+                        let count = match line.count {
+                            count if count > 1 => format!(" (x{})", count),
+                            _ => String::new(),
+                        };
+                        format!(
+                            "{} {} {}{}: {}",
+                            line.object, line.type_, line.reason, count, line.message
+                        )
+                    })
+                    .collect(),
+            }),
+            ..EventRecord::default()
+        }
+    }
+}
+
+// the workload an event is about, like "Deployment/api": the pod's, the replicaset's
+// deployment from the owner cache, or the deployment itself. None for the other kinds.
+pub fn workload(
+    event: &Event,
+    enricher: &Enricher,
+    replicasets: Option<&ReplicaSetStore>,
+) -> Option<String> {
+    let object = &event.involved_object;
+    let name = object.name.clone()?;
+    match object.kind.as_deref()? {
+        "Pod" => enricher.pod(event).map(|pod| pods::workload(&pod)),
+        "ReplicaSet" => {
+            let owner = replicasets.and_then(|replicasets| {
+                let mut key = ObjectRef::<ReplicaSet>::new(&name);
+                if let Some(ref namespace) = object.namespace {
+                    key = key.within(namespace);
+                }
+                let replicaset = replicasets.get(&key)?;
+                let owner = replicaset
+                    .owner_references()
+                    .iter()
+                    .find(|owner| owner.controller == Some(true))?;
+                Some(format!("{}/{}", owner.kind, owner.name))
+            });
+            Some(owner.unwrap_or_else(|| format!("ReplicaSet/{}", name)))
+        }
+        "Deployment" => Some(format!("Deployment/{}", name)),
+        _ => None,
+    }
+}
+
+pub fn router() -> Router {
+    Router::new().route(
+        "/api/v1/incidents",
+        get(|| async { Json(INCIDENTS.render()) }),
+    )
+}
@@ -0,0 +1,175 @@
+use crate::{
+    clock,
+    config::NodeEventSettings,
+    metrics::{NODE_EVENTS_COUNTER, NODE_FLAPPING_GAUGE, NODE_LABEL, REASON_LABEL},
+};
+use axum_prometheus::metrics::{counter, gauge};
+use k8s_openapi::api::core::v1::Event;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+// what the kubelet (and the node controller) tell about a node's own lifecycle
+const REASONS: &[&str] = &[
+    "Starting",
+    "NodeReady",
+    "NodeNotReady",
+    "Rebooted",
+    "InvalidDiskCapacity",
+];
+
+// how often the flapping nodes are looked at again, for the ones that calmed down
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+// the nodes going from Ready to NotReady and back, told by their events apart from the
+// node cache so a node flapping faster than it's relisted is still caught. Its settings
+// are the config's, like the snapshot it's a global.
+pub static NODE_FLAPS: LazyLock<NodeFlaps> = LazyLock::new(NodeFlaps::default);
+
+#[derive(Default)]
+pub struct NodeFlaps {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    settings: NodeEventSettings,
+    nodes: HashMap<String, Readiness>,
+}
+
+#[derive(Default)]
+struct Readiness {
+    ready: bool,
+    // when it toggled within settings.flap_window
+    toggles: VecDeque<Instant>,
+    flapping: bool,
+}
+
+// counts the node lifecycle events by node, and follows their readiness
+pub fn handle_event(event: &Event) {
+    let reason = event.reason.as_deref().unwrap_or_default();
+    if !REASONS.contains(&reason) {
+        return;
+    }
+    let node = event.involved_object.name.clone().unwrap_or_default();
+    counter!(
+        NODE_EVENTS_COUNTER,
+        &[
+            (NODE_LABEL, node.clone()),
+            (REASON_LABEL, reason.to_string()),
+        ]
+    )
+    .increment(1);
+    match reason {
+        "NodeReady" => NODE_FLAPS.ready(&node, true),
+        "NodeNotReady" => NODE_FLAPS.ready(&node, false),
+        _ => {}
+    }
+}
+
+impl NodeFlaps {
+    pub fn configure(&self, settings: &NodeEventSettings) {
+        self.lock().settings = settings.clone();
+    }
+
+    fn ready(&self, node: &str, ready: bool) {
+        let mut state = self.lock();
+        let settings = state.settings.clone();
+        let known = state.nodes.contains_key(node);
+        let readiness = state.nodes.entry(node.to_string()).or_default();
+        // the first we hear of a node isn't a toggle
+        if known && readiness.ready != ready {
+            readiness.toggles.push_back(clock::instant());
+        }
+        readiness.ready = ready;
+        readiness.update(node, &settings);
+    }
+
+    pub async fn run(&self) {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let mut state = self.lock();
+            let settings = state.settings.clone();
+            for (node, readiness) in state.nodes.iter_mut() {
+                readiness.update(node, &settings);
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("node flaps lock poisoned")
+    }
+}
+
+impl Readiness {
+    // the node flaps while it toggled more than settings.flap_threshold times within the window
+    fn update(&mut self, node: &str, settings: &NodeEventSettings) {
+        let now = clock::instant();
+        while self
+            .toggles
+            .front()
+            .is_some_and(|toggled| now.duration_since(*toggled) > settings.flap_window)
+        {
+            self.toggles.pop_front();
+        }
+        let flapping = self.toggles.len() > settings.flap_threshold as usize;
+        if flapping == self.flapping {
+            return;
+        }
+        self.flapping = flapping;
+        match flapping {
+            true => warn!(
+                "Node {} is flapping, its readiness changed {} times in {}",
+                node,
+                self.toggles.len(),
+                humantime::format_duration(settings.flap_window)
+            ),
+            false => info!("Node {} stopped flapping", node),
+        }
+        gauge!(NODE_FLAPPING_GAUGE, &[(NODE_LABEL, node.to_string())]).set(if flapping {
+            1.0
+        } else {
+            0.0
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use k8s_openapi::chrono::{TimeDelta, Utc};
+    use std::sync::Arc;
+
+    #[test]
+    fn stops_flapping_once_the_toggles_leave_the_window() {
+        let start = Utc::now();
+        let manual = Arc::new(ManualClock::new(start));
+        clock::set_local(manual.clone());
+        let flaps = NodeFlaps::default();
+        // more than 3 toggles within an hour
+        flaps.configure(&NodeEventSettings::default());
+        let flapping = || flaps.lock().nodes["node-1"].flapping;
+
+        flaps.ready("node-1", true);
+        for (minute, ready) in [(1, false), (2, true), (3, false), (4, true)] {
+            manual.advance_to(start + TimeDelta::minutes(minute));
+            flaps.ready("node-1", ready);
+        }
+        assert!(flapping());
+
+        // hearing it's still ready isn't a toggle, the window still has all 4
+        manual.advance_to(start + TimeDelta::minutes(30));
+        flaps.ready("node-1", true);
+        assert!(flapping());
+
+        // the first toggle is past the window
+        manual.advance_to(start + TimeDelta::minutes(62));
+        flaps.ready("node-1", true);
+        assert!(!flapping());
+    }
+}
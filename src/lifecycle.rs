@@ -0,0 +1,422 @@
+use crate::{
+    memory::{Lru, LIFECYCLE_STORE},
+    metrics::{
+        FROM_STATE_LABEL, GRACE_EXCEEDED_COUNTER, LIFECYCLE_STATE_HISTOGRAM,
+        LIFECYCLE_TRANSITIONS_COUNTER, NAMESPACE_LABEL, PRIORITY_CLASS_LABEL, SCHEDULING_HISTOGRAM,
+        STATE_LABEL, TERMINATION_HISTOGRAM, TO_STATE_LABEL, WORKLOAD_LABEL,
+    },
+    pods::workload,
+};
+use axum_prometheus::metrics::{counter, histogram};
+use k8s_openapi::{
+    api::core::v1::{Event, Pod},
+    chrono::{DateTime, Utc},
+};
+use kube::ResourceExt;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, sync::Mutex};
+use tracing::warn;
+
+// what kubernetes gives pods to stop when they don't say
+const DEFAULT_GRACE_PERIOD: i64 = 30;
+
+// where a pod is in its life. Pods only move forward, except for Ready and Started
+// as a pod's readiness comes and goes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PodState {
+    Pending,
+    Scheduled,
+    Started,
+    Ready,
+    Terminating,
+    Gone,
+}
+
+impl PodState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PodState::Pending => "Pending",
+            PodState::Scheduled => "Scheduled",
+            PodState::Started => "Started",
+            PodState::Ready => "Ready",
+            PodState::Terminating => "Terminating",
+            PodState::Gone => "Gone",
+        }
+    }
+
+    // what the pod's status says
+    fn of(pod: &Pod) -> Self {
+        if pod.metadata.deletion_timestamp.is_some() {
+            return PodState::Terminating;
+        }
+        let status = pod.status.as_ref();
+        let condition = |type_: &str| {
+            status
+                .and_then(|status| status.conditions.as_ref())
+                .is_some_and(|conditions| {
+                    conditions
+                        .iter()
+                        .any(|condition| condition.type_ == type_ && condition.status == "True")
+                })
+        };
+        let started = status
+            .and_then(|status| status.container_statuses.as_ref())
+            .is_some_and(|statuses| {
+                statuses.iter().any(|status| {
+                    status.started == Some(true)
+                        || status.state.as_ref().is_some_and(|state| {
+                            state.running.is_some() || state.terminated.is_some()
+                        })
+                })
+            });
+        if condition("Ready") {
+            PodState::Ready
+        } else if started {
+            PodState::Started
+        } else if condition("PodScheduled")
+            || pod
+                .spec
+                .as_ref()
+                .is_some_and(|spec| spec.node_name.is_some())
+        {
+            PodState::Scheduled
+        } else {
+            PodState::Pending
+        }
+    }
+
+    // what an event says the pod just went through. Killing isn't one of them, the
+    // kubelet also kills the containers it restarts, only the pod tells it's being deleted.
+    fn from_event(event: &Event) -> Option<Self> {
+        match event.reason.as_deref()? {
+            "Scheduled" => Some(PodState::Scheduled),
+            "Started" => Some(PodState::Started),
+            _ => None,
+        }
+    }
+
+    fn can_move_to(&self, to: PodState) -> bool {
+        to > *self || (*self == PodState::Ready && to == PodState::Started)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Tracked {
+    state: PodState,
+    // unknown for the pods that were already there when we started
+    since: Option<DateTime<Utc>>,
+    namespace: String,
+    workload: String,
+    priority_class: String,
+    // how long the pod was given to stop, in seconds
+    grace_period: i64,
+    // when its containers were killed, once it's terminating
+    killed_at: Option<DateTime<Utc>>,
+}
+
+impl Tracked {
+    fn new(pod: &Pod, state: PodState, since: Option<DateTime<Utc>>) -> Self {
+        let mut tracked = Tracked {
+            state,
+            since,
+            namespace: String::new(),
+            workload: String::new(),
+            priority_class: String::new(),
+            grace_period: DEFAULT_GRACE_PERIOD,
+            killed_at: None,
+        };
+        tracked.update(pod);
+        tracked
+    }
+
+    // what a pod listed again says, its state aside
+    fn refresh(&mut self, listed: Tracked) {
+        self.namespace = listed.namespace;
+        self.workload = listed.workload;
+        self.priority_class = listed.priority_class;
+        self.grace_period = listed.grace_period;
+    }
+
+    fn update(&mut self, pod: &Pod) {
+        self.namespace = pod.namespace().unwrap_or_default();
+        self.workload = workload(pod);
+        self.priority_class = pod
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.priority_class_name.clone())
+            .unwrap_or_default();
+        // the one of the deletion, if it's been asked for, can be shorter
+        self.grace_period = pod
+            .metadata
+            .deletion_grace_period_seconds
+            .or_else(|| {
+                pod.spec
+                    .as_ref()
+                    .and_then(|spec| spec.termination_grace_period_seconds)
+            })
+            .unwrap_or(DEFAULT_GRACE_PERIOD);
+    }
+
+    // how long it took from killing the pod's containers to the pod being gone,
+    // from when it started terminating if the Killing event was late or missed
+    fn terminated(&self) {
+        let Some(start) = self
+            .killed_at
+            .or(self.since)
+            .filter(|_| self.state == PodState::Terminating)
+        else {
+            return;
+        };
+        let seconds = seconds_since(start);
+        histogram!(
+            TERMINATION_HISTOGRAM,
+            &[(NAMESPACE_LABEL, self.namespace.clone())]
+        )
+        .record(seconds);
+        if seconds > self.grace_period as f64 {
+            warn!(
+                "A pod of {} in {} took {:.1}s to stop, its grace period is {}s",
+                self.workload, self.namespace, seconds, self.grace_period
+            );
+            counter!(
+                GRACE_EXCEEDED_COUNTER,
+                &[
+                    (NAMESPACE_LABEL, self.namespace.clone()),
+                    (WORKLOAD_LABEL, self.workload.clone()),
+                ]
+            )
+            .increment(1);
+        }
+    }
+}
+
+fn seconds_since(since: DateTime<Utc>) -> f64 {
+    (Utc::now() - since).num_milliseconds().max(0) as f64 / 1000.0
+}
+
+// a pod of the watcher's list, as it was then
+struct Listed {
+    uid: String,
+    created: Option<DateTime<Utc>>,
+    tracked: Tracked,
+}
+
+// the pods the lifecycle followed, saved with the counters to carry on after a restart
+#[derive(Serialize, Deserialize)]
+pub struct SavedLifecycle {
+    as_of: Option<DateTime<Utc>>,
+    pods: Vec<(String, Tracked)>,
+}
+
+// a state machine for each pod, fed by the pod watcher and the pod events,
+// counting the transitions and how long the pods spent in each state
+// as many pods as memory.lifecycle allows, the ones we heard of last going first
+pub struct Lifecycle {
+    pods: Mutex<Lru<String, Tracked>>,
+    // the pods of the list going on, compared to the ones above once it's done
+    listing: Mutex<Option<Vec<Listed>>>,
+    // when the pods above were all there was, those created since then aren't among
+    // them. Unknown before the first list, unless they were restored.
+    as_of: Mutex<Option<DateTime<Utc>>>,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Lifecycle {
+            pods: Mutex::new(Lru::new(LIFECYCLE_STORE, |limits| limits.lifecycle)),
+            listing: Mutex::new(None),
+            as_of: Mutex::new(None),
+        }
+    }
+}
+
+impl Lifecycle {
+    // the watcher (re-)lists the pods, they're only compared to what we knew once
+    // they're all there
+    pub fn list_started(&self) {
+        *self.listing.lock().expect("lifecycle lock poisoned") = Some(Vec::new());
+    }
+
+    pub fn listed(&self, pod: &Pod) {
+        let Some(uid) = pod.uid() else {
+            return;
+        };
+        let mut listing = self.listing.lock().expect("lifecycle lock poisoned");
+        let Some(listing) = listing.as_mut() else {
+            drop(listing);
+            return self.pod(pod);
+        };
+        listing.push(Listed {
+            uid,
+            created: pod.creation_timestamp().map(|time| time.0),
+            tracked: Tracked::new(pod, PodState::of(pod), None),
+        });
+    }
+
+    // the pods that changed while we weren't watching move to their new state, without
+    // how long they spent in the old one since we don't know when they moved. The new
+    // ones are counted from their creation, the others that were there before we knew
+    // of them get their state without counting anything.
+    pub fn list_done(&self) {
+        let Some(listed) = self.listing.lock().expect("lifecycle lock poisoned").take() else {
+            return;
+        };
+        let mut as_of = self.as_of.lock().expect("lifecycle lock poisoned");
+        let mut pods = self.lock();
+        // the pods that weren't listed again were deleted while we were away
+        let uids = listed
+            .iter()
+            .map(|listed| listed.uid.clone())
+            .collect::<HashSet<_>>();
+        pods.retain(|uid, tracked| {
+            if uids.contains(uid) {
+                return true;
+            }
+            // we don't know when
+            tracked.since = None;
+            transition(tracked, PodState::Gone);
+            false
+        });
+        for listed in listed {
+            let state = listed.tracked.state;
+            match pods.get_mut(&listed.uid) {
+                Some(tracked) => {
+                    if tracked.state != state {
+                        tracked.since = None;
+                    }
+                    tracked.refresh(listed.tracked);
+                    transition(tracked, state);
+                }
+                None if as_of
+                    .zip(listed.created)
+                    .is_some_and(|(as_of, created)| created > as_of) =>
+                {
+                    let mut tracked = listed.tracked;
+                    tracked.state = PodState::Pending;
+                    tracked.since = listed.created;
+                    transition(&mut tracked, state);
+                    pods.insert(listed.uid, tracked);
+                }
+                None => pods.insert(listed.uid, listed.tracked),
+            }
+        }
+        *as_of = Some(Utc::now());
+    }
+
+    pub fn save(&self) -> SavedLifecycle {
+        let as_of = self.as_of.lock().expect("lifecycle lock poisoned");
+        let pods = self.lock();
+        SavedLifecycle {
+            // the pods we heard of since the list are in there too
+            as_of: as_of.map(|_| Utc::now()),
+            pods: pods
+                .iter()
+                .map(|(uid, tracked)| (uid.clone(), tracked.clone()))
+                .collect(),
+        }
+    }
+
+    // the pods of the previous run, for the first list to be compared to
+    pub fn restore(&self, saved: SavedLifecycle) {
+        let mut as_of = self.as_of.lock().expect("lifecycle lock poisoned");
+        let mut pods = self.lock();
+        for (uid, tracked) in saved.pods {
+            pods.insert(uid, tracked);
+        }
+        *as_of = saved.as_of;
+    }
+
+    // a pod as the watcher sees it, after the list
+    pub fn pod(&self, pod: &Pod) {
+        let Some(uid) = pod.uid() else {
+            return;
+        };
+        let state = PodState::of(pod);
+        let mut pods = self.lock();
+        match pods.get_mut(&uid) {
+            Some(tracked) => {
+                tracked.update(pod);
+                transition(tracked, state);
+            }
+            None => {
+                // it's been pending since it was created
+                let created = pod.creation_timestamp().map(|time| time.0);
+                let since = created.or_else(|| Some(Utc::now()));
+                let mut tracked = Tracked::new(pod, PodState::Pending, since);
+                transition(&mut tracked, state);
+                pods.insert(uid, tracked);
+            }
+        }
+    }
+
+    // events often tell before the pod status does
+    pub fn event(&self, event: &Event) {
+        let Some(uid) = event.involved_object.uid.as_ref() else {
+            return;
+        };
+        let mut pods = self.lock();
+        let Some(tracked) = pods.get_mut(uid) else {
+            return;
+        };
+        if event.reason.as_deref() == Some("Killing") && tracked.state == PodState::Terminating {
+            tracked.killed_at.get_or_insert_with(Utc::now);
+        }
+        if let Some(state) = PodState::from_event(event) {
+            transition(tracked, state);
+        }
+    }
+
+    pub fn gone(&self, uid: &str) {
+        if let Some(mut tracked) = self.lock().remove(uid) {
+            tracked.terminated();
+            transition(&mut tracked, PodState::Gone);
+        }
+    }
+
+    // a pod that succeeded is done with its life, even though it stays until it's deleted
+    pub fn completed(&self, uid: &str) {
+        if let Some(mut tracked) = self.lock().remove(uid) {
+            transition(&mut tracked, PodState::Gone);
+        }
+    }
+
+    // stops following the pod without it going anywhere, when switched off
+    pub fn forget(&self, uid: &str) {
+        self.lock().remove(uid);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Lru<String, Tracked>> {
+        self.pods.lock().expect("lifecycle lock poisoned")
+    }
+}
+
+fn transition(tracked: &mut Tracked, to: PodState) {
+    let from = tracked.state;
+    if !from.can_move_to(to) {
+        return;
+    }
+    let now = Utc::now();
+    counter!(
+        LIFECYCLE_TRANSITIONS_COUNTER,
+        &[
+            (FROM_STATE_LABEL, from.as_str()),
+            (TO_STATE_LABEL, to.as_str())
+        ]
+    )
+    .increment(1);
+    if let Some(since) = tracked.since {
+        let seconds = seconds_since(since);
+        histogram!(LIFECYCLE_STATE_HISTOGRAM, &[(STATE_LABEL, from.as_str())]).record(seconds);
+        // a pod is pending from its creation until it's scheduled, the ones first seen
+        // past that, started or failed already, didn't tell when it was
+        if from == PodState::Pending && to == PodState::Scheduled {
+            histogram!(
+                SCHEDULING_HISTOGRAM,
+                &[(PRIORITY_CLASS_LABEL, tracked.priority_class.clone())]
+            )
+            .record(seconds);
+        }
+    }
+    tracked.state = to;
+    tracked.since = Some(now);
+}
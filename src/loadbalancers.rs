@@ -0,0 +1,81 @@
+use crate::{
+    clock,
+    metrics::{
+        KIND_LABEL, LB_FAILURES_COUNTER, LB_PROVISIONING_HISTOGRAM, NAMESPACE_LABEL, NAME_LABEL,
+        REASON_LABEL,
+    },
+};
+use axum_prometheus::metrics::{counter, histogram};
+use k8s_openapi::api::core::v1::Event;
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+// what the cloud controller reports while it provisions a Service's load balancer
+const FAILURES: &[&str] = &[
+    "CreatingLoadBalancerFailed",
+    "SyncLoadBalancerFailed",
+    "UpdateLoadBalancerFailed",
+    "DeletingLoadBalancerFailed",
+];
+
+// a load balancer that never got ensured isn't waited for forever
+const GIVE_UP_AFTER: Duration = Duration::from_secs(60 * 60);
+
+// when the cloud controller started ensuring each Service's load balancer, by uid
+static ENSURING: LazyLock<Mutex<HashMap<String, Instant>>> = LazyLock::new(Default::default);
+
+// the events about Services of type LoadBalancer and Ingresses.
+// Services get the time from EnsuringLoadBalancer to EnsuredLoadBalancer, Ingress
+// controllers all have their own reasons, so only their warnings are counted.
+pub fn handle_event(event: &Event) {
+    let object = &event.involved_object;
+    let kind = object.kind.clone().unwrap_or_default();
+    let reason = event.reason.clone().unwrap_or_default();
+    let uid = object.uid.clone().unwrap_or_default();
+
+    let failed = match kind.as_str() {
+        "Service" => FAILURES.contains(&reason.as_str()),
+        "Ingress" => event.type_.as_deref() == Some("Warning"),
+        _ => return,
+    };
+    if failed {
+        ENSURING
+            .lock()
+            .expect("load balancers lock poisoned")
+            .remove(&uid);
+        counter!(
+            LB_FAILURES_COUNTER,
+            &[
+                (KIND_LABEL, kind),
+                (
+                    NAMESPACE_LABEL,
+                    object.namespace.clone().unwrap_or_default()
+                ),
+                (NAME_LABEL, object.name.clone().unwrap_or_default()),
+                (REASON_LABEL, reason),
+            ]
+        )
+        .increment(1);
+        return;
+    }
+
+    let mut ensuring = ENSURING.lock().expect("load balancers lock poisoned");
+    match reason.as_str() {
+        "EnsuringLoadBalancer" => {
+            let now = clock::instant();
+            ensuring.retain(|_, since| now.duration_since(*since) < GIVE_UP_AFTER);
+            // the same sync can report it more than once
+            ensuring.entry(uid).or_insert(now);
+        }
+        "EnsuredLoadBalancer" => {
+            if let Some(since) = ensuring.remove(&uid) {
+                histogram!(LB_PROVISIONING_HISTOGRAM, &[(KIND_LABEL, kind)])
+                    .record(clock::instant().duration_since(since).as_secs_f64());
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,135 @@
+use crate::config::{LogFormat, LogSampling};
+use k8s_openapi::api::core::v1::Event;
+use kube::ResourceExt;
+use std::{collections::HashMap, sync::Mutex, time::Instant};
+use tracing::{field, info_span, Span};
+use tracing_subscriber::{fmt, prelude::*, reload, EnvFilter, Layer, Registry};
+
+// lets the log filter be changed while running
+#[derive(Clone)]
+pub struct LogHandle {
+    filter: reload::Handle<EnvFilter, Registry>,
+}
+
+impl LogHandle {
+    // the filter in use, like "info,k8rs::watch=debug"
+    pub fn current(&self) -> Result<String, String> {
+        self.filter
+            .with_current(|filter| filter.to_string())
+            .map_err(|err| err.to_string())
+    }
+
+    // switches to a new filter, in the RUST_LOG syntax
+    pub fn set(&self, directives: &str) -> Result<(), String> {
+        let filter = EnvFilter::try_new(directives).map_err(|err| err.to_string())?;
+        self.filter.reload(filter).map_err(|err| err.to_string())
+    }
+}
+
+// sets up the global subscriber, starting with RUST_LOG or everything from info up
+pub fn init(format: LogFormat) -> LogHandle {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let (filter, handle) = reload::Layer::new(filter);
+    let logs: Box<dyn Layer<Registry> + Send + Sync> = match format {
+        LogFormat::Text => Box::new(fmt::layer()),
+        // one json object per line, with the fields of the event span (if any) under "span"
+        LogFormat::Json => Box::new(
+            fmt::layer()
+                .json()
+                .with_current_span(true)
+                .with_span_list(false),
+        ),
+    };
+    // the filter only applies to the logs, tokio-console needs to see everything
+    let subscriber = tracing_subscriber::registry().with(logs.with_filter(filter));
+    #[cfg(feature = "console")]
+    let subscriber = subscriber.with(
+        console_subscriber::ConsoleLayer::builder()
+            .with_default_env()
+            .spawn(),
+    );
+    subscriber.init();
+    LogHandle { filter: handle }
+}
+
+// the span an event is processed in, so every line logged for it carries the same fields
+pub fn event_span(event: &Event) -> Span {
+    let object = &event.involved_object;
+    let span = info_span!(
+        "event",
+        namespace = event.namespace().unwrap_or_default(),
+        pod = field::Empty,
+        reason = event.reason.as_deref().unwrap_or_default(),
+        event_uid = event.uid().unwrap_or_default(),
+    );
+    if object.kind.as_deref() == Some("Pod") {
+        span.record("pod", object.name.as_deref().unwrap_or_default());
+    }
+    span
+}
+
+// decides which log lines of noisy event reasons are actually written
+pub struct LogSampler {
+    reasons: HashMap<String, Mutex<Sampler>>,
+}
+
+enum Sampler {
+    OneIn {
+        n: u64,
+        seen: u64,
+    },
+    // a token bucket holding up to a second's worth of lines
+    PerSecond {
+        rate: f64,
+        tokens: f64,
+        last: Instant,
+    },
+}
+
+impl LogSampler {
+    pub fn new(settings: &HashMap<String, LogSampling>) -> Self {
+        let reasons = settings
+            .iter()
+            .map(|(reason, sampling)| {
+                let sampler = match *sampling {
+                    LogSampling::OneIn { one_in } => Sampler::OneIn { n: one_in, seen: 0 },
+                    LogSampling::PerSecond { per_second } => Sampler::PerSecond {
+                        rate: per_second,
+                        tokens: per_second,
+                        last: Instant::now(),
+                    },
+                };
+                (reason.clone(), Mutex::new(sampler))
+            })
+            .collect();
+        LogSampler { reasons }
+    }
+
+    // whether the next log line for an event with this reason should be written
+    pub fn allows(&self, reason: &str) -> bool {
+        let Some(sampler) = self.reasons.get(reason) else {
+            return true;
+        };
+        match *sampler.lock().expect("log sampler lock poisoned") {
+            Sampler::OneIn { n, ref mut seen } => {
+                *seen += 1;
+                (*seen - 1) % n == 0
+            }
+            Sampler::PerSecond {
+                rate,
+                ref mut tokens,
+                ref mut last,
+            } => {
+                let now = Instant::now();
+                *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * rate).min(rate);
+                *last = now;
+                if *tokens >= 1.0 {
+                    *tokens -= 1.0;
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+}
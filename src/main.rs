@@ -1,193 +1,678 @@
-use axum::{routing::get, Router};
-use axum_prometheus::{
-    metrics::{counter, describe_counter, Unit},
-    PrometheusMetricLayerBuilder,
-};
-use futures::StreamExt;
-use k8s_openapi::api::core::v1::Event;
-use kube::{
-    runtime::{
-        watcher::{self, watcher, Config},
-        WatchStreamExt,
-    },
-    Api, Client, ResourceExt,
-};
-use std::error::Error;
-use tokio::{net::TcpListener, task};
-use tracing::{error, info};
-
-// the names for our counters
-const POD_DELETE_COUNTER: &str = "deleted_pods";
-const POD_CREATE_COUNTER: &str = "created_pods";
-
-// the names for our labels
-const TIME_METRIC_LABEL: &str = "event_time";
-const POD_ID_LABEL: &str = "pod_id";
-
-// a struct for our metrics label
-struct EventLabels {
-    pub time: String,
-    pub object_id: String,
-}
+mod admin;
+mod admission;
+mod aggregate;
+mod alerts;
+mod anomalies;
+mod audit;
+mod autoscalers;
+mod bench;
+mod cache;
+mod certificates;
+mod churn;
+mod client;
+mod clock;
+mod config;
+mod configs;
+mod container_logs;
+mod context;
+mod controller;
+mod degrade;
+mod demo;
+mod discovery;
+mod disruptions;
+mod drains;
+mod enrich;
+mod error;
+mod features;
+mod grafana;
+mod graph;
+mod grpc;
+mod handover;
+mod images;
+mod incidents;
+mod kubelet;
+mod lifecycle;
+mod loadbalancers;
+mod logging;
+mod maintenance;
+mod manifests;
+mod memory;
+mod messages;
+mod metrics;
+mod monitor;
+mod namespaces;
+mod nodes;
+mod outbound;
+mod payloads;
+mod persistence;
+mod pipeline;
+mod plugins;
+mod pods;
+mod policies;
+mod priorities;
+mod process;
+mod quotas;
+mod rbac;
+mod readiness;
+mod record;
+mod recording;
+mod registry;
+mod relabel;
+mod reload;
+mod replay;
+mod resources;
+mod resume;
+mod retention;
+mod rollouts;
+mod rollups;
+mod scaling;
+mod scheduling;
+mod schema;
+mod scripts;
+mod server;
+mod services;
+mod severity;
+mod sharding;
+mod sinks;
+mod slos;
+mod snapshot;
+mod spot;
+mod stale;
+mod tenants;
+// only the tests use the mock api server
+#[cfg(feature = "testing")]
+#[cfg_attr(not(test), allow(dead_code))]
+mod testing;
+mod throttle;
+mod tls;
+mod top;
+mod validate;
+mod version;
+mod watch;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    // initialize tracing for cool and shinny log.
-    tracing_subscriber::fmt().init();
+use axum::{http::StatusCode, routing::get, Json, Router};
+use axum_prometheus::metrics::gauge;
+use clap::Parser;
+use config::{Cli, Command, ConfigCommand, RuntimeFlavor};
+use enrich::Enricher;
+use kube::CustomResourceExt;
+use metrics::{initialize_counters, install_recorder, STARTUP_WAIT_GAUGE};
+use monitor::{EventMonitor, MonitorPipelines};
+use sinks::SinkRegistry;
+use std::{process::ExitCode, sync::Arc};
+use tokio::task;
+use tracing::{error, info, warn};
 
-    // we'll initialize both the axum server socket and the k8s client first,
-    // because if one of those fails, we souldn't do nothing else
-    let listener = match TcpListener::bind("0.0.0.0:8080").await {
-        Ok(conn) => conn,
-        Err(err) => {
-            error!("{:?}", err);
-            return Err(Box::from(err));
-        }
+// with its stats exported along with the process' own
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static ALLOCATOR: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    // before the runtime's threads, the sockets are taken from the environment
+    let inherited = handover::inherit();
+
+    // the runtime is built before anything runs on it, so from the config as well.
+    // A config that doesn't load fails in run, once the logs are set up.
+    let settings = config::Config::load(&cli)
+        .map(|config| config.runtime)
+        .unwrap_or_default();
+    let mut runtime = match settings.flavor {
+        RuntimeFlavor::MultiThread => tokio::runtime::Builder::new_multi_thread(),
+        RuntimeFlavor::CurrentThread => tokio::runtime::Builder::new_current_thread(),
     };
-    let client = match Client::try_default().await {
-        Ok(c) => c,
+    if let Some(threads) = settings.worker_threads {
+        runtime.worker_threads(threads);
+    }
+    if let Some(threads) = settings.max_blocking_threads {
+        runtime.max_blocking_threads(threads);
+    }
+    // before the logs, there's only stderr to tell
+    let runtime = match runtime.enable_all().build() {
+        Ok(runtime) => runtime,
         Err(err) => {
-            error!("{:?}", err);
-            return Err(Box::from(err));
+            eprintln!("Could not build the runtime: {}", err);
+            return ExitCode::FAILURE;
         }
     };
+    // already reported by run, with its context
+    match runtime.block_on(run(cli, inherited)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(_) => ExitCode::FAILURE,
+    }
+}
+
+async fn run(cli: Cli, inherited: Vec<String>) -> Result<(), error::Error> {
+    // initialize tracing for cool and shinny log.
+    // The filter can be changed later on through the admin endpoints.
+    let log = logging::init(cli.log_format);
+
+    match cli.command {
+        Some(Command::Crd) => {
+            print!(
+                "{}",
+                error::fatal("crd", serde_yaml::to_string(&EventMonitor::crd()))?
+            );
+            return Ok(());
+        }
+        Some(Command::Schema) => {
+            let document = serde_json::to_string_pretty(&schema::document());
+            println!("{}", error::fatal("schema", document)?);
+            return Ok(());
+        }
+        Some(Command::Config {
+            command: ConfigCommand::Schema,
+        }) => {
+            let document = serde_json::to_string_pretty(&config::schema());
+            println!("{}", error::fatal("schema", document)?);
+            return Ok(());
+        }
+        Some(Command::ValidateConfig) => {
+            return error::fatal("validate", validate::run(&cli));
+        }
+        Some(Command::Manifests(ref args)) => {
+            return error::fatal("manifests", manifests::run(&cli, args));
+        }
+        Some(Command::Replay(ref args)) => {
+            return error::fatal("replay", replay::run(&cli, args).await);
+        }
+        Some(Command::Bench(ref args)) => {
+            return error::fatal("bench", bench::run(&cli, args, &log).await);
+        }
+        None if cli.demo => {
+            return error::fatal("demo", demo::run(&cli).await);
+        }
+        None => {}
+    }
+    info!(
+        "Starting k8rs {} ({}, {})",
+        version::VERSION,
+        version::GIT_SHA,
+        version::RUSTC
+    );
+    // what went wrong taking the sockets over, before there were logs
+    for warning in inherited {
+        warn!("{}", warning);
+    }
+
+    let config = error::fatal("config", config::Config::load(&cli))?;
+    let record_watch = cli.record_watch.clone();
+
+    // we'll initialize both the axum server socket and the k8s client first,
+    // because if one of those fails, we souldn't do nothing else
+    let listeners = error::fatal("server", server::Listeners::bind(&config.server))?;
+    let grpc = error::fatal("grpc", grpc::GrpcListener::bind(&config.server))?;
+    // and the webhook's, a handover restart refusing it would hold up the pods it admits
+    let webhook = config
+        .admission
+        .enabled
+        .then(|| error::fatal("admission", server::bind_tcp(config.admission.listen)))
+        .transpose()?;
+    handover::release();
+    let (client, waited) = error::fatal("client", client::connect(&config.kube).await)?;
+    // a missing permission would only show up as a watcher backing off forever
+    error::fatal("rbac", rbac::check(&client, &config).await)?;
+    let sinks = error::fatal(
+        "sinks",
+        SinkRegistry::from_settings(&config.sinks, &config.suppression),
+    )?;
+
+    // the admission webhooks refuse EventMonitors the controller couldn't run
+    // and annotate new pods
+    if let Some(webhook) = webhook {
+        let server = admission::server(&config.admission, webhook, sinks.clone()).await;
+        task::spawn(error::fatal("admission", server)?);
+    }
+
+    snapshot::SNAPSHOT.configure(&config.snapshot, config.admin.enabled);
+    top::TOP.configure(&config.top);
+    rollups::ROLLUPS.configure(&config.rollups);
+    incidents::INCIDENTS.configure(&config.incidents);
+    anomalies::ANOMALIES.configure(&config.anomalies);
+    stale::STALE_SERIES.configure(&config.metrics.stale_series);
+    degrade::DEGRADATION.configure(&config.metrics.degradation);
+    // the templates were already checked when loading the config
+    error::fatal("messages", messages::configure(&config.messages))?;
+    severity::configure(&config.severities);
+    features::configure(&config.features);
+    memory::configure(&config.memory);
+    payloads::configure(&config.payloads);
+    error::fatal("maintenance", maintenance::configure(&config.maintenance))?;
+    task::spawn(maintenance::run());
+    error::fatal("outbound", outbound::configure(&config.outbound))?;
+    admin::set_filters("events", admin::pipeline_filters(&config));
+
+    let recorder = error::fatal("metrics", install_recorder(&config.metrics))?;
+    alerts::watch(&config.alerts);
+
+    // the counters carry on from their last run's values, before anything counts
+    let lifecycle = persistence::restore(&config.metrics.persistence);
+    let rendered = recorder.clone();
 
     // Initialize our counters with metadata
     // *Those are the counters that'll be exported to prometheus
     initialize_counters();
+    gauge!(STARTUP_WAIT_GAUGE).set(waited.as_secs_f64());
+    version::export();
+    task::spawn(version::heartbeat());
+    task::spawn(stale::STALE_SERIES.run());
+
+    // the namespace labels are only needed to tell the tenants apart,
+    // for their sinks and their metrics
+    let namespaces = config.tenants.needs_namespaces().then(|| {
+        let (namespaces, reflector) = cache::namespace_cache(client.clone(), &config.watcher);
+        task::spawn(reflector);
+        namespaces
+    });
+    let tenants = Arc::new(tenants::Tenants::new(&config.tenants, namespaces));
+
+    // using axum-prometheus to crete the prometheus metrics exporter
+    let (prom_layer, prom_handler) = metrics::http_metrics_layer(&config.metrics.http, recorder);
 
     // we'll spin up an http axum server to talk to prometheus
+    let admin = config
+        .admin
+        .enabled
+        .then(|| admin::router(log, prom_handler.clone()));
+    let snapshot = config.snapshot.enabled.then(|| {
+        Router::new().route(
+            "/api/v1/snapshot",
+            get(|| async { Json(snapshot::SNAPSHOT.render()) }),
+        )
+    });
+    let top = config.top.enabled.then(top::router);
+    let graph = config.graph.enabled.then(graph::router);
+    // the grafana datasource is over the rollups
+    let rollups = config
+        .rollups
+        .enabled
+        .then(|| rollups::router().merge(grafana::router()));
+    let incidents = config.incidents.enabled.then(incidents::router);
+    let mut prom_endpoint = metrics::endpoint(prom_handler, tenants.clone());
+    if config.server.metrics_auth.enabled {
+        let reviewer = Arc::new(server::TokenReviewer::new(
+            client.clone(),
+            &config.server.metrics_auth,
+        ));
+        prom_endpoint = reviewer.protect(prom_endpoint);
+    }
+    // /readyz follows the sinks' backlogs
+    let readiness = Arc::new(readiness::Readiness::new(
+        sinks.clone(),
+        &config.server.readiness,
+    ));
+    task::spawn(readiness.clone().run());
+    // the grpc health follows it too
+    if let Some(grpc) = grpc {
+        task::spawn(grpc.serve(readiness.clone()));
+    }
     task::spawn(async {
-        // using axum-prometheus to crete the prometheus metrics exporter
-        let (prom_layer, prom_handler) = PrometheusMetricLayerBuilder::new()
-            .with_prefix("pods_operator")
-            .with_default_metrics()
-            .with_ignore_patterns(&["/ping", "/metrics", "/favicon.ico"]) // to reduce noise
-            .build_pair();
-
         // create the axum router
-        let app = Router::new()
-            .route("/metrics", get(|| async move { prom_handler.render() }))
+        let mut app = Router::new()
+            .merge(prom_endpoint)
             .route("/ping", get(|| async move { "pong" })) // a healthcheck
-            .layer(prom_layer);
+            .route(
+                "/readyz",
+                get(|| async move {
+                    let drowning = readiness.drowning();
+                    if drowning.is_empty() {
+                        return (StatusCode::OK, "ready".to_string());
+                    }
+                    (
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        format!("sinks backing up: {}", drowning.join(", ")),
+                    )
+                }),
+            );
+        if let Some(admin) = admin {
+            app = app.merge(admin);
+        }
+        if let Some(snapshot) = snapshot {
+            app = app.merge(snapshot);
+        }
+        if let Some(top) = top {
+            app = app.merge(top);
+        }
+        if let Some(graph) = graph {
+            app = app.merge(graph);
+        }
+        if let Some(rollups) = rollups {
+            app = app.merge(rollups);
+        }
+        if let Some(incidents) = incidents {
+            app = app.merge(incidents);
+        }
+        app = app.merge(version::router()).merge(images::router());
+        let app = app.layer(prom_layer);
 
-        // serve the constructed router on the created socket
-        let _ = axum::serve(listener, app)
-            .with_graceful_shutdown(shutdown_signal())
-            .await;
+        // serve the constructed router on the created sockets
+        listeners.serve(app).await;
     });
 
-    // this is the main routine, where we'll observe events and filter them into
-    // only what we want to listen.
-    task::spawn(async move {
-        // we create a serializable way of communicating over a specific resource.
-        // In this case, all events that happen on the cluster's "default" namespace.
-        let pods: Api<Event> = Api::<Event>::default_namespaced(client.clone());
-
-        // we pin the stream for 'async rust' reasons
-        let mut event_stream = Box::pin(watcher(pods.clone(), Config::default()).default_backoff());
-
-        loop {
-            if let Some(event) = event_stream.next().await {
-                // this match is kinda self explanatory
-                match event {
-                    Ok(watcher::Event::Apply(event)) | Ok(watcher::Event::Delete(event))
-                        if event
-                            .involved_object
-                            .kind
-                            .as_ref()
-                            .is_some_and(|kind| kind == "Pod") =>
-                    {
-                        if let Some(ref reason) = event.reason {
-                            match reason.as_ref() {
-                                "Pulled" => info!("image for Pod {} pulled", event.name_any()),
-                                "Created" => {
-                                    let labels = extract_label_values_from_event(&event);
-                                    // we get the counter with our labels and increment it
-                                    counter!(
-                                        POD_CREATE_COUNTER,
-                                        &[
-                                            (TIME_METRIC_LABEL, labels.time),
-                                            (POD_ID_LABEL, labels.object_id)
-                                        ]
-                                    )
-                                    .increment(1);
-                                    info!("Pod {} created", event.name_any());
-                                }
-                                "Scheduled" => {
-                                    info!("Pod {} scheduled", event.name_any())
-                                }
-                                "Started" => {
-                                    info!("Pod {} allocated and started", event.name_any())
-                                }
-                                "Updated" => info!("Pod {} updated", event.name_any()),
-                                "Killing" => {
-                                    let labels = extract_label_values_from_event(&event);
-                                    // we get the counter with our labels and increment it
-                                    counter!(
-                                        POD_DELETE_COUNTER,
-                                        &[
-                                            (TIME_METRIC_LABEL, labels.time),
-                                            (POD_ID_LABEL, labels.object_id)
-                                        ]
-                                    )
-                                    .increment(1);
-                                    info!("Killing Pod {}", event.name_any());
-                                }
-                                _ => {}
-                            }
-                        }
-                    }
-                    Ok(watcher::Event::Init) => {
-                        info!("Starting the watch stream...")
-                    }
-                    Ok(watcher::Event::InitDone) => {
-                        info!("Watch stream up and running!")
-                    }
-                    Ok(_) => {} // we're not interested in init apply
-                    Err(err) => {
-                        error!("Error on receiving update: {:?}", err);
-                    }
-                }
-            }
-        }
+    // the watcher and the event processing are decoupled by a bounded queue,
+    // so a slow processing side never stalls the watch stream directly
+    let (sender, receiver) = pipeline::channel(&config.pipeline, &config.alerts.rules);
+
+    // the pod cache lets the pipeline know more about the pods than what's in the events
+    // and the pod tracker follows what the events don't tell, like container restarts
+    let tracker = Arc::new(pods::PodTracker::default());
+    task::spawn(tracker.clone().run());
+    // the first list is compared to the pods of the last run
+    if let Some(lifecycle) = lifecycle {
+        tracker.restore_lifecycle(lifecycle);
+    }
+    let persistence = Arc::new(persistence::Persistence::new(
+        &config.metrics.persistence,
+        rendered,
+        tracker.clone(),
+    ));
+    task::spawn(persistence.clone().run());
+    let (pods, pod_reflector) = cache::pod_cache(
+        client.clone(),
+        &config.watcher,
+        tracker.clone(),
+        config.namespaces.selector.is_some(),
+        namespaces::NamespaceFilter::new(&config.namespaces),
+    );
+    persistence::INITIAL_SYNC.started();
+    task::spawn(pod_reflector);
+    // the spot interruptions come from both the nodes and their events
+    let spot = config.spot.enabled.then(|| {
+        let (interruptions, notify) =
+            spot::interruptions(&config.spot, pods.clone(), sinks.clone());
+        task::spawn(notify);
+        Arc::new(interruptions)
     });
+    // and so do the drains
+    let drains = config.drains.enabled.then(|| {
+        let drains = Arc::new(drains::Drains::new(
+            &config.drains,
+            pods.clone(),
+            sinks.clone(),
+        ));
+        let run = drains.clone();
+        task::spawn(async move { run.run().await });
+        drains
+    });
+    let (nodes, node_reflector) = cache::node_cache(
+        client.clone(),
+        &config.watcher,
+        Arc::new(nodes::NodeTracker::new(spot.clone(), drains.clone())),
+    );
+    task::spawn(node_reflector);
+    if config.graph.enabled {
+        let (services, reflector) = cache::service_cache(
+            client.clone(),
+            &config.watcher,
+            config.namespaces.selector.is_some(),
+        );
+        task::spawn(reflector);
+        graph::GRAPH.attach(pods.clone(), nodes.clone(), services);
+    }
+    let enricher = Enricher::new(pods.clone(), nodes, &config.enrichment);
 
-    // This is just a cancelation point for the operator.
-    // It waits for a kill signal
-    let _ = tokio::signal::ctrl_c().await;
-    info!("Kill signal received, stopping...");
+    // EventMonitors get their own pipelines, reconciled as the custom resources come and go
+    let monitors = if config.monitors.enabled {
+        let pipelines = MonitorPipelines::new(
+            client.clone(),
+            config.watcher.clone(),
+            sinks.clone(),
+            enricher.clone(),
+        );
+        let ctx = Arc::new(controller::Context::new(
+            client.clone(),
+            pipelines,
+            &config.monitors,
+        ));
+        task::spawn(controller::run(ctx.clone()));
+        Some(ctx)
+    } else {
+        None
+    };
 
-    Ok(())
-}
+    // the sinks were already checked when loading the config
+    let (dispatcher_tx, dispatcher) = tokio::sync::watch::channel(error::fatal(
+        "sinks",
+        sinks.dispatcher(&config.pipeline.sinks),
+    )?);
 
-fn initialize_counters() {
-    describe_counter!(
-        POD_DELETE_COUNTER,
-        Unit::Count,
-        "The number of deleted pods"
-    );
-    describe_counter!(
-        POD_CREATE_COUNTER,
-        Unit::Count,
-        "The number of created pods"
+    // the alert rules see every event of the pod pipeline
+    let alerts = Arc::new(error::fatal(
+        "alerts",
+        alerts::Alerts::new(&config.alerts, sinks.clone()),
+    )?);
+    if !alerts.is_empty() {
+        task::spawn(alerts.clone().run());
+    }
+
+    // SIGHUP or a change to the config's ConfigMap reload what can be reloaded
+    let reloader = reload::Reloader::new(
+        cli,
+        config.clone(),
+        sinks.clone(),
+        enricher.clone(),
+        dispatcher_tx,
+        monitors,
     );
-}
+    task::spawn(reloader.run(client.clone()));
+
+    // the user's scripts see every record of the pod pipeline
+    let scripts = Arc::new(error::fatal(
+        "scripts",
+        scripts::Scripts::load(client.clone(), &config.scripts).await,
+    )?);
+    if !scripts.is_empty() {
+        let scripts = scripts.clone();
+        let client = client.clone();
+        let watcher = config.watcher.clone();
+        task::spawn(async move { scripts.watch(client, &watcher).await });
+    }
+
+    // and so do the plugins
+    let plugins = error::fatal("plugins", plugins::Plugins::load(&config.plugins).await)?;
+    let slos = Arc::new(slos::Slos::new(&config.slos));
+    if !slos.is_empty() {
+        task::spawn(slos.clone().run());
+    }
+
+    // the incidents correlate the pods' events with their deployments', through the
+    // replicasets in between
+    if config.anomalies.enabled {
+        task::spawn(anomalies::ANOMALIES.run(sinks.clone()));
+    }
+    let replicasets = config.incidents.enabled.then(|| {
+        let (replicasets, reflector) = cache::replicaset_cache(
+            client.clone(),
+            &config.watcher,
+            config.namespaces.selector.is_some(),
+        );
+        task::spawn(reflector);
+        task::spawn(incidents::INCIDENTS.run(sinks.clone()));
+        replicasets
+    });
+    let records = Arc::new(pipeline::RecordHandlers::new(
+        tenants,
+        scripts,
+        plugins,
+        slos,
+        sinks.clone(),
+        container_logs::ContainerLogs::new(client.clone(), &config.container_logs),
+        context::FailureContexts::new(&config.failure_context, &config.artifacts),
+    ));
+
+    let kinds = discovery::Kinds::new(config.event_kinds());
+    if !config.discovery.groups.is_empty() {
+        let client = client.clone();
+        let settings = config.discovery.clone();
+        let kinds = kinds.clone();
+        task::spawn(async move { discovery::run(client, &settings, kinds).await });
+    }
+    let handlers = Arc::new(pipeline::EventHandlers::new(
+        &config,
+        tracker,
+        spot,
+        drains,
+        replicasets,
+    ));
+
+    // the namespaces are watched for themselves, their events go through the pipeline
+    if config.namespaces.enabled {
+        let client = client.clone();
+        let watcher = config.watcher.clone();
+        let settings = config.namespaces.clone();
+        task::spawn(async move { namespaces::watch_namespaces(client, &watcher, &settings).await });
+    }
+
+    if config.services.enabled {
+        let client = client.clone();
+        let watcher = config.watcher.clone();
+        task::spawn(async move { services::watch_endpoint_slices(client, &watcher).await });
+    }
+
+    if config.config_changes.enabled {
+        let client = client.clone();
+        let watcher = config.watcher.clone();
+        let secrets = config.config_changes.secrets;
+        task::spawn(async move { configs::watch_configs(client, &watcher, secrets).await });
+    }
+
+    if config.certificates.enabled {
+        let client = client.clone();
+        let watcher = config.watcher.clone();
+        let settings = config.certificates.clone();
+        let sinks = sinks.clone();
+        task::spawn(async move {
+            certificates::watch_certificates(client, &watcher, &settings, sinks).await
+        });
+    }
+
+    if config.rbac_audit.enabled {
+        let client = client.clone();
+        let watcher = config.watcher.clone();
+        let settings = config.rbac_audit.clone();
+        let all_namespaces = config.namespaces.selector.is_some();
+        let sinks = sinks.clone();
+        task::spawn(async move {
+            audit::watch_rbac(client, &watcher, &settings, all_namespaces, sinks).await
+        });
+    }
+
+    if config.disruption_budgets.enabled {
+        let client = client.clone();
+        let watcher = config.watcher.clone();
+        let pods = pods.clone();
+        task::spawn(async move { disruptions::watch_budgets(client, &watcher, pods).await });
+    }
+
+    if config.node_events.enabled {
+        kubelet::NODE_FLAPS.configure(&config.node_events);
+        task::spawn(kubelet::NODE_FLAPS.run());
+    }
+
+    if config.quotas.enabled {
+        let client = client.clone();
+        let watcher = config.watcher.clone();
+        task::spawn(async move { quotas::watch_quotas(client, &watcher).await });
+    }
+
+    if config.rollouts.enabled {
+        let client = client.clone();
+        let watcher = config.watcher.clone();
+        let settings = config.rollouts.clone();
+        task::spawn(async move { rollouts::watch_rollouts(client, &watcher, &settings).await });
+    }
 
-fn extract_label_values_from_event(ev: &Event) -> EventLabels {
-    let time = ev.first_timestamp.as_ref().map_or("".to_string(), |date| {
-        date.0
-            .to_rfc3339_opts(k8s_openapi::chrono::SecondsFormat::Millis, false)
+    task::spawn(async move {
+        pipeline::process_events(
+            receiver,
+            &config.pipeline,
+            enricher,
+            dispatcher,
+            alerts,
+            handlers,
+            records,
+        )
+        .await
     });
-    let object_id = ev
-        .involved_object
-        .uid
-        .as_ref()
-        .map_or("".to_string(), |val| val.clone());
+    retention::RETENTION.configure(&config.watcher);
+    let events = watch::KubeEvents::new(client.clone(), &config.watcher);
+    let resume_point = events.resume_point();
+    if let Some(ref point) = resume_point {
+        task::spawn(point.clone().run());
+    }
+    let filter = namespaces::NamespaceFilter::new(&config.namespaces);
+    let mut membership = None;
+    match (record_watch, config.namespaces.selector.clone()) {
+        // the discovered namespaces take the place of the operator's own
+        (record_watch, Some(_)) => {
+            if record_watch.is_some() {
+                warn!("Not recording the watch stream, the events of discovered namespaces aren't");
+            }
+            let shard = match config.sharding.enabled {
+                true => {
+                    let (shard, joined) = error::fatal(
+                        "sharding",
+                        sharding::join(client.clone(), &config.sharding).await,
+                    )?;
+                    task::spawn(joined.clone().run());
+                    membership = Some(joined);
+                    Some(shard)
+                }
+                false => None,
+            };
+            let (watcher, settings) = (config.watcher.clone(), config.namespaces.clone());
+            task::spawn(async move {
+                namespaces::discover(client, &watcher, &settings, shard, kinds, filter, sender)
+                    .await
+            });
+        }
+        (Some(path), None) => {
+            let events = error::fatal("recording", recording::WatchRecorder::new(events, &path))?;
+            info!("Recording the events watch stream to {:?}", path);
+            task::spawn(async move { watch::watch_events(events, kinds, filter, sender).await });
+        }
+        (None, None) => {
+            task::spawn(async move { watch::watch_events(events, kinds, filter, sender).await });
+        }
+    }
 
-    EventLabels { time, object_id }
+    // This is just a cancelation point for the operator.
+    // It waits for a kill signal, or a config only a restart applies
+    let restarting = tokio::select! {
+        _ = tokio::signal::ctrl_c() => false,
+        _ = handover::requested() => true,
+    };
+    if restarting {
+        info!("Restarting with the new config...");
+    } else {
+        info!("Kill signal received, stopping...");
+    }
+    // the batching sinks still hold some records
+    sinks.flush().await;
+    if let Some(point) = resume_point {
+        point.leave().await;
+    }
+    // the restarted operator renews the same lease
+    if let Some(membership) = membership.filter(|_| !restarting) {
+        membership.leave().await;
+    }
+    persistence.save();
+    if restarting {
+        let err = handover::restart(config.server.handover.drain_timeout).await;
+        return error::fatal(
+            "handover",
+            Err(error::ErrorKind::from(err).context("could not restart")),
+        );
+    }
+
+    Ok(())
 }
 
-async fn shutdown_signal() {
-    let _ = tokio::signal::ctrl_c().await;
+// the servers stop on the kill signal, and before a restart
+pub async fn shutdown_signal() {
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = handover::requested() => {}
+    }
 }
@@ -0,0 +1,257 @@
+use crate::{
+    clock,
+    config::{MaintenanceSettings, MaintenanceWindow},
+    error::ErrorKind,
+    metrics::{MAINTENANCE_ACTIVE_GAUGE, MAINTENANCE_HELD_COUNTER, SINK_LABEL, WINDOW_LABEL},
+    record::EventRecord,
+};
+use axum_prometheus::metrics::{counter, gauge};
+use k8s_openapi::chrono::{DateTime, Datelike, DurationRound, TimeDelta, Timelike, Utc};
+use std::{
+    sync::{Arc, LazyLock, Mutex, RwLock},
+    time::Duration,
+};
+
+// how often the gauges follow the windows opening and closing, without any record
+const EXPORT_INTERVAL: Duration = Duration::from_secs(30);
+
+// the windows of planned work, like node upgrades, during which the sinks are held back
+// from the records they match. Only the deliveries are, the metrics still count every
+// event. A reload replaces them.
+static WINDOWS: LazyLock<RwLock<Arc<Vec<Window>>>> = LazyLock::new(RwLock::default);
+
+struct Window {
+    settings: MaintenanceWindow,
+    schedule: Schedule,
+    // whether it was open, for the minute it was last looked at
+    open: Mutex<Option<(DateTime<Utc>, bool)>>,
+}
+
+impl Window {
+    fn is_open(&self, now: DateTime<Utc>) -> bool {
+        let Ok(minute) = now.duration_trunc(TimeDelta::minutes(1)) else {
+            return false;
+        };
+        let mut open = self.open.lock().expect("maintenance lock poisoned");
+        if let Some((at, open)) = *open {
+            if at == minute {
+                return open;
+            }
+        }
+        // open if it started at one of the minutes of the last duration, which are gone
+        // through once a minute: a week long window (the longest) is 10080 of them
+        let duration = TimeDelta::from_std(self.settings.duration).unwrap_or_default();
+        let mut start = minute;
+        let mut is_open = false;
+        while start + duration > now {
+            if self.schedule.matches(start) {
+                is_open = true;
+                break;
+            }
+            start -= TimeDelta::minutes(1);
+        }
+        *open = Some((minute, is_open));
+        is_open
+    }
+
+    fn matches(&self, sink: &str, record: &EventRecord) -> bool {
+        let settings = &self.settings;
+        (settings.sinks.is_empty() || settings.sinks.iter().any(|name| name == sink))
+            && (settings.namespaces.is_empty() || settings.namespaces.contains(&record.namespace))
+            && settings
+                .labels
+                .iter()
+                .all(|(key, value)| record.labels.get(key) == Some(value))
+    }
+}
+
+pub fn configure(settings: &MaintenanceSettings) -> Result<(), ErrorKind> {
+    let windows = settings
+        .windows
+        .iter()
+        .map(|window| {
+            Ok::<_, String>(Window {
+                settings: window.clone(),
+                schedule: Schedule::parse(&window.schedule)
+                    .map_err(|err| format!("maintenance window {}: {}", window.name, err))?,
+                open: Mutex::new(None),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut current = WINDOWS.write().expect("maintenance lock poisoned");
+    // the windows of the previous config aren't open anymore
+    for window in current.iter() {
+        if !windows
+            .iter()
+            .any(|new| new.settings.name == window.settings.name)
+        {
+            gauge!(
+                MAINTENANCE_ACTIVE_GAUGE,
+                &[(WINDOW_LABEL, window.settings.name.clone())]
+            )
+            .set(0.0);
+        }
+    }
+    *current = Arc::new(windows);
+    drop(current);
+    export();
+    Ok(())
+}
+
+fn windows() -> Arc<Vec<Window>> {
+    WINDOWS.read().expect("maintenance lock poisoned").clone()
+}
+
+// whether a window holds the record back from the sink, which is then counted
+pub fn holds(sink: &str, record: &EventRecord) -> bool {
+    let windows = windows();
+    if windows.is_empty() {
+        return false;
+    }
+    let now = clock::now();
+    let Some(window) = windows
+        .iter()
+        .find(|window| window.matches(sink, record) && window.is_open(now))
+    else {
+        return false;
+    };
+    counter!(
+        MAINTENANCE_HELD_COUNTER,
+        &[
+            (WINDOW_LABEL, window.settings.name.clone()),
+            (SINK_LABEL, sink.to_string()),
+        ]
+    )
+    .increment(1);
+    true
+}
+
+fn export() {
+    let now = clock::now();
+    for window in windows().iter() {
+        gauge!(
+            MAINTENANCE_ACTIVE_GAUGE,
+            &[(WINDOW_LABEL, window.settings.name.clone())]
+        )
+        .set(if window.is_open(now) { 1.0 } else { 0.0 });
+    }
+}
+
+pub async fn run() {
+    let mut interval = tokio::time::interval(EXPORT_INTERVAL);
+    loop {
+        interval.tick().await;
+        export();
+    }
+}
+
+// a cron schedule, the usual five fields: minute, hour, day of the month, month and day of
+// the week. Each of them is a * or a list of values and ranges, with a /step, and the
+// months and days can go by their names, like "0 2 * * sat" or "30 22 1-7 * mon-fri".
+pub struct Schedule {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    // with both restricted, either of the days will do, like cron does
+    any_day: bool,
+    any_weekday: bool,
+}
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+impl Schedule {
+    pub fn parse(schedule: &str) -> Result<Self, String> {
+        let fields = schedule.split_whitespace().collect::<Vec<_>>();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!(
+                "schedule {:?} needs 5 fields: minute, hour, day of the month, month and day of the week",
+                schedule
+            ));
+        };
+        let mut parsed_weekdays = field(weekdays, 0, 7, &WEEKDAYS, 0)?;
+        // 7 is sunday too
+        if parsed_weekdays[7] {
+            parsed_weekdays[0] = true;
+        }
+        parsed_weekdays.truncate(7);
+        Ok(Schedule {
+            minutes: field(minutes, 0, 59, &[], 0)?,
+            hours: field(hours, 0, 23, &[], 0)?,
+            days: field(days, 1, 31, &[], 0)?,
+            months: field(months, 1, 12, &MONTHS, 1)?,
+            weekdays: parsed_weekdays,
+            any_day: days == "*",
+            any_weekday: weekdays == "*",
+        })
+    }
+
+    fn matches(&self, time: DateTime<Utc>) -> bool {
+        let day = self.days[time.day() as usize];
+        let weekday = self.weekdays[time.weekday().num_days_from_sunday() as usize];
+        let day = match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        };
+        self.minutes[time.minute() as usize]
+            && self.hours[time.hour() as usize]
+            && self.months[time.month() as usize]
+            && day
+    }
+}
+
+// the values a field allows, indexed by value. The names are those of the values from
+// first on.
+fn field(
+    text: &str,
+    min: usize,
+    max: usize,
+    names: &[&str],
+    first: usize,
+) -> Result<Vec<bool>, String> {
+    let value = |value: &str| {
+        let parsed = match names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(value))
+        {
+            Some(position) => position + first,
+            None => value
+                .parse::<usize>()
+                .map_err(|_| format!("{:?} isn't a number", value))?,
+        };
+        if parsed < min || parsed > max {
+            return Err(format!("{} isn't within {}-{}", parsed, min, max));
+        }
+        Ok(parsed)
+    };
+    let mut allowed = vec![false; max + 1];
+    for part in text.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<usize>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(format!("{:?} isn't a valid step", step)),
+            },
+            None => (part, 1),
+        };
+        let (from, to) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((from, to)) => (value(from)?, value(to)?),
+                // like 5/15, from 5 on
+                None if step > 1 => (value(range)?, max),
+                None => (value(range)?, value(range)?),
+            },
+        };
+        if from > to {
+            return Err(format!("{:?} goes backwards", range));
+        }
+        for allowed in allowed.iter_mut().take(to + 1).skip(from).step_by(step) {
+            *allowed = true;
+        }
+    }
+    Ok(allowed)
+}
@@ -0,0 +1,551 @@
+use crate::{
+    config::{Cli, Config, ResumeStore},
+    error::{Context, ErrorKind},
+    monitor::EventMonitor,
+};
+use clap::Args;
+use k8s_openapi::{
+    api::{
+        apps::v1::{Deployment, DeploymentSpec},
+        core::v1::{
+            ConfigMap, ConfigMapVolumeSource, Container, ContainerPort, EnvVar, EnvVarSource,
+            HTTPGetAction, KeyToPath, ObjectFieldSelector, PodSpec, PodTemplateSpec, Probe,
+            SecretVolumeSource, Service, ServiceAccount, ServicePort, ServiceSpec, Volume,
+            VolumeMount,
+        },
+        rbac::v1::{
+            ClusterRole, ClusterRoleBinding, PolicyRule, Role, RoleBinding, RoleRef, Subject,
+        },
+    },
+    apimachinery::pkg::{apis::meta::v1::LabelSelector, util::intstr::IntOrString},
+};
+use kube::{api::ObjectMeta, CustomResourceExt};
+use serde::Serialize;
+use serde_json::json;
+use std::{collections::BTreeMap, fs};
+
+// where the config file is mounted in the operator's pod,
+// not /etc/k8rs itself so it doesn't hide the default admission.cert_dir
+const CONFIG_DIR: &str = "/etc/k8rs/config";
+const CONFIG_FILE: &str = "config.yaml";
+
+#[derive(Args, Debug, Clone)]
+pub struct ManifestsArgs {
+    /// The namespace the operator is deployed to
+    #[arg(long, default_value = "default")]
+    pub namespace: String,
+
+    /// The name of every generated object
+    #[arg(long, default_value = "k8rs")]
+    pub name: String,
+
+    /// The operator's container image
+    #[arg(long, default_value = "ghcr.io/grsaiago/k8rs:latest")]
+    pub image: String,
+
+    /// How many replicas run, more than one only with sharding
+    #[arg(long, default_value_t = 1)]
+    pub replicas: i32,
+}
+
+// something the operator's service account must be allowed to do
+pub struct Permission {
+    pub api_group: &'static str,
+    pub resource: &'static str,
+    pub verbs: &'static [&'static str],
+    // granted in every namespace (or for a cluster-wide resource), not only the operator's
+    pub cluster: bool,
+}
+
+impl Permission {
+    // like kubectl shows them: eventmonitors.k8rs.io/status
+    pub fn name(&self) -> String {
+        let (resource, subresource) = match self.resource.split_once('/') {
+            Some((resource, subresource)) => (resource, format!("/{}", subresource)),
+            None => (self.resource, String::new()),
+        };
+        let group = match self.api_group {
+            "" => String::new(),
+            group => format!(".{}", group),
+        };
+        format!("{}{}{}", resource, group, subresource)
+    }
+
+    // the rule granting some of its verbs
+    pub fn rule(&self, verbs: &[&str]) -> PolicyRule {
+        PolicyRule {
+            api_groups: Some(vec![self.api_group.to_string()]),
+            resources: Some(vec![self.resource.to_string()]),
+            verbs: verbs.iter().map(|verb| verb.to_string()).collect(),
+            ..PolicyRule::default()
+        }
+    }
+}
+
+// exactly what the watchers and controllers enabled in the config need
+pub fn permissions(config: &Config) -> Vec<Permission> {
+    let watch = &["list", "watch"];
+    let mut permissions = vec![
+        Permission {
+            api_group: "",
+            resource: "events",
+            verbs: watch,
+            cluster: false,
+        },
+        Permission {
+            api_group: "",
+            resource: "pods",
+            verbs: watch,
+            cluster: false,
+        },
+        Permission {
+            api_group: "",
+            resource: "nodes",
+            verbs: watch,
+            cluster: true,
+        },
+    ];
+    if config.monitors.enabled {
+        permissions.extend([
+            // patch is for the finalizer
+            Permission {
+                api_group: "k8rs.io",
+                resource: "eventmonitors",
+                verbs: &["list", "watch", "patch"],
+                cluster: true,
+            },
+            Permission {
+                api_group: "k8rs.io",
+                resource: "eventmonitors/status",
+                verbs: &["patch"],
+                cluster: true,
+            },
+            // each monitor watches the events of its own namespace
+            Permission {
+                api_group: "",
+                resource: "events",
+                verbs: watch,
+                cluster: true,
+            },
+        ]);
+    }
+    // the events and pods of the discovered namespaces
+    if config.namespaces.selector.is_some() {
+        permissions.extend([
+            Permission {
+                api_group: "",
+                resource: "events",
+                verbs: watch,
+                cluster: true,
+            },
+            Permission {
+                api_group: "",
+                resource: "pods",
+                verbs: watch,
+                cluster: true,
+            },
+        ]);
+    }
+    if config.namespaces.enabled
+        || config.tenants.needs_namespaces()
+        || config.namespaces.selector.is_some()
+    {
+        permissions.push(Permission {
+            api_group: "",
+            resource: "namespaces",
+            verbs: watch,
+            cluster: true,
+        });
+    }
+    if config.rollouts.enabled {
+        for resource in ["statefulsets", "daemonsets"] {
+            permissions.push(Permission {
+                api_group: "apps",
+                resource,
+                verbs: watch,
+                cluster: false,
+            });
+        }
+    }
+    if config.disruption_budgets.enabled {
+        permissions.push(Permission {
+            api_group: "policy",
+            resource: "poddisruptionbudgets",
+            verbs: watch,
+            cluster: false,
+        });
+    }
+    if config.quotas.enabled {
+        permissions.push(Permission {
+            api_group: "",
+            resource: "resourcequotas",
+            verbs: watch,
+            cluster: false,
+        });
+    }
+    // the deployments owning the replicasets
+    if config.incidents.enabled {
+        permissions.push(Permission {
+            api_group: "apps",
+            resource: "replicasets",
+            verbs: watch,
+            cluster: config.namespaces.selector.is_some(),
+        });
+    }
+    // the services selecting the pods
+    if config.graph.enabled {
+        permissions.push(Permission {
+            api_group: "",
+            resource: "services",
+            verbs: watch,
+            cluster: config.namespaces.selector.is_some(),
+        });
+    }
+    if config.services.enabled {
+        permissions.push(Permission {
+            api_group: "discovery.k8s.io",
+            resource: "endpointslices",
+            verbs: watch,
+            cluster: false,
+        });
+    }
+    if !config.container_logs.reasons.is_empty() {
+        permissions.push(Permission {
+            api_group: "",
+            resource: "pods/log",
+            verbs: &["get"],
+            cluster: false,
+        });
+    }
+    if matches!(config.watcher.resume, Some(ResumeStore::ConfigMap(_))) {
+        permissions.push(Permission {
+            api_group: "",
+            resource: "configmaps",
+            verbs: &["get", "create", "patch"],
+            cluster: false,
+        });
+    }
+    // only their metadata is fetched, but there's no granting that alone
+    if config.config_changes.enabled {
+        let resources = match config.config_changes.secrets {
+            true => &["configmaps", "secrets"][..],
+            false => &["configmaps"][..],
+        };
+        permissions.extend(resources.iter().map(|resource| Permission {
+            api_group: "",
+            resource,
+            verbs: watch,
+            cluster: false,
+        }));
+    }
+    if config.certificates.enabled {
+        permissions.push(Permission {
+            api_group: "",
+            resource: "secrets",
+            verbs: watch,
+            cluster: false,
+        });
+    }
+    if config.rbac_audit.enabled {
+        let cluster = config.namespaces.selector.is_some();
+        for (resource, cluster) in [
+            ("roles", cluster),
+            ("rolebindings", cluster),
+            ("clusterroles", true),
+            ("clusterrolebindings", true),
+        ] {
+            permissions.push(Permission {
+                api_group: "rbac.authorization.k8s.io",
+                resource,
+                verbs: watch,
+                cluster,
+            });
+        }
+        // only their metadata is fetched here too
+        if config.rbac_audit.tokens {
+            permissions.push(Permission {
+                api_group: "",
+                resource: "secrets",
+                verbs: watch,
+                cluster,
+            });
+        }
+    }
+    // the scrapers' tokens are reviewed by the api server
+    if config.server.metrics_auth.enabled {
+        permissions.push(Permission {
+            api_group: "authentication.k8s.io",
+            resource: "tokenreviews",
+            verbs: &["create"],
+            cluster: true,
+        });
+    }
+    // every replica renews its own lease and looks at the others'
+    if config.sharding.enabled {
+        permissions.push(Permission {
+            api_group: "coordination.k8s.io",
+            resource: "leases",
+            verbs: &["get", "list", "create", "patch", "delete"],
+            cluster: false,
+        });
+    }
+    if config.reload.config_map.is_some() || config.scripts.config_map.is_some() {
+        permissions.push(Permission {
+            api_group: "",
+            resource: "configmaps",
+            verbs: watch,
+            cluster: false,
+        });
+    }
+    permissions
+}
+
+// prints every object needed to run the operator with the config, as a yaml stream
+pub fn run(cli: &Cli, args: &ManifestsArgs) -> Result<(), ErrorKind> {
+    let config = Config::load(cli)?;
+    if args.replicas < 1 || (args.replicas > 1 && !config.sharding.enabled) {
+        return Err("--replicas must be 1, or more with sharding.enabled".into());
+    }
+    let name = args.name.as_str();
+    let namespace = args.namespace.as_str();
+    let labels = BTreeMap::from([("app.kubernetes.io/name".to_string(), name.to_string())]);
+    let meta = |name: &str| ObjectMeta {
+        name: Some(name.to_string()),
+        namespace: Some(namespace.to_string()),
+        labels: Some(labels.clone()),
+        ..ObjectMeta::default()
+    };
+    let cluster_meta = |name: &str| ObjectMeta {
+        name: Some(name.to_string()),
+        labels: Some(labels.clone()),
+        ..ObjectMeta::default()
+    };
+
+    let mut documents = Vec::new();
+    if config.monitors.enabled {
+        documents.push(yaml(&EventMonitor::crd())?);
+    }
+    documents.push(yaml(&ServiceAccount {
+        metadata: meta(name),
+        ..ServiceAccount::default()
+    })?);
+
+    let subject = Subject {
+        kind: "ServiceAccount".to_string(),
+        name: name.to_string(),
+        namespace: Some(namespace.to_string()),
+        ..Subject::default()
+    };
+    let rules = |cluster: bool| {
+        permissions(&config)
+            .into_iter()
+            .filter(|permission| permission.cluster == cluster)
+            .map(|permission| permission.rule(permission.verbs))
+            .collect::<Vec<_>>()
+    };
+    documents.push(yaml(&Role {
+        metadata: meta(name),
+        rules: Some(rules(false)),
+    })?);
+    documents.push(yaml(&RoleBinding {
+        metadata: meta(name),
+        role_ref: RoleRef {
+            api_group: "rbac.authorization.k8s.io".to_string(),
+            kind: "Role".to_string(),
+            name: name.to_string(),
+        },
+        subjects: Some(vec![subject.clone()]),
+    })?);
+    // ClusterRoles aren't namespaced, so the namespace keeps their names apart
+    let cluster_name = format!("{}-{}", namespace, name);
+    documents.push(yaml(&ClusterRole {
+        metadata: cluster_meta(&cluster_name),
+        rules: Some(rules(true)),
+        ..ClusterRole::default()
+    })?);
+    documents.push(yaml(&ClusterRoleBinding {
+        metadata: cluster_meta(&cluster_name),
+        role_ref: RoleRef {
+            api_group: "rbac.authorization.k8s.io".to_string(),
+            kind: "ClusterRole".to_string(),
+            name: cluster_name.clone(),
+        },
+        subjects: Some(vec![subject]),
+    })?);
+
+    // the config file becomes a ConfigMap mounted into the pod
+    let mut args_list = Vec::new();
+    let mut volumes = Vec::new();
+    let mut mounts = Vec::new();
+    if let Some(ref path) = cli.config {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("could not read config file {:?}", path))?;
+        let config_map = config
+            .reload
+            .config_map
+            .clone()
+            .unwrap_or_else(|| format!("{}-config", name));
+        let key = config.reload.key.clone();
+        documents.push(yaml(&ConfigMap {
+            metadata: meta(&config_map),
+            data: Some(BTreeMap::from([(key.clone(), contents)])),
+            ..ConfigMap::default()
+        })?);
+        args_list.push(format!("--config={}/{}", CONFIG_DIR, CONFIG_FILE));
+        volumes.push(Volume {
+            name: "config".to_string(),
+            config_map: Some(ConfigMapVolumeSource {
+                name: config_map,
+                items: Some(vec![KeyToPath {
+                    key,
+                    path: CONFIG_FILE.to_string(),
+                    ..KeyToPath::default()
+                }]),
+                ..ConfigMapVolumeSource::default()
+            }),
+            ..Volume::default()
+        });
+        mounts.push(VolumeMount {
+            name: "config".to_string(),
+            mount_path: CONFIG_DIR.to_string(),
+            read_only: Some(true),
+            ..VolumeMount::default()
+        });
+    }
+
+    // validated to have at least one address
+    let metrics_port = config
+        .server
+        .listen
+        .first()
+        .map(|address| address.port())
+        .unwrap_or(8080);
+    let mut ports = vec![ContainerPort {
+        name: Some("metrics".to_string()),
+        container_port: metrics_port.into(),
+        ..ContainerPort::default()
+    }];
+    let mut service_ports = vec![ServicePort {
+        name: Some("metrics".to_string()),
+        port: metrics_port.into(),
+        target_port: Some(IntOrString::String("metrics".to_string())),
+        ..ServicePort::default()
+    }];
+    let admission = &config.admission;
+    if admission.enabled {
+        ports.push(ContainerPort {
+            name: Some("webhook".to_string()),
+            container_port: admission.listen.port().into(),
+            ..ContainerPort::default()
+        });
+        // the api server always calls webhook services on 443 unless told otherwise
+        service_ports.push(ServicePort {
+            name: Some("webhook".to_string()),
+            port: 443,
+            target_port: Some(IntOrString::String("webhook".to_string())),
+            ..ServicePort::default()
+        });
+        volumes.push(Volume {
+            name: "webhook-tls".to_string(),
+            secret: Some(SecretVolumeSource {
+                secret_name: Some(format!("{}-webhook-tls", name)),
+                ..SecretVolumeSource::default()
+            }),
+            ..Volume::default()
+        });
+        mounts.push(VolumeMount {
+            name: "webhook-tls".to_string(),
+            mount_path: admission.cert_dir.to_string_lossy().into_owned(),
+            read_only: Some(true),
+            ..VolumeMount::default()
+        });
+    }
+
+    documents.push(yaml(&Deployment {
+        metadata: meta(name),
+        spec: Some(DeploymentSpec {
+            // every replica would count the same events, unless they shard them
+            replicas: Some(args.replicas),
+            selector: LabelSelector {
+                match_labels: Some(labels.clone()),
+                ..LabelSelector::default()
+            },
+            template: PodTemplateSpec {
+                metadata: Some(ObjectMeta {
+                    labels: Some(labels.clone()),
+                    ..ObjectMeta::default()
+                }),
+                spec: Some(PodSpec {
+                    service_account_name: Some(name.to_string()),
+                    containers: vec![Container {
+                        name: name.to_string(),
+                        image: Some(args.image.clone()),
+                        args: Some(args_list).filter(|args| !args.is_empty()),
+                        ports: Some(ports),
+                        readiness_probe: Some(Probe {
+                            http_get: Some(HTTPGetAction {
+                                path: Some("/readyz".to_string()),
+                                port: IntOrString::String("metrics".to_string()),
+                                ..HTTPGetAction::default()
+                            }),
+                            ..Probe::default()
+                        }),
+                        volume_mounts: Some(mounts).filter(|mounts| !mounts.is_empty()),
+                        // the shard's identity
+                        env: config.sharding.enabled.then(|| {
+                            vec![EnvVar {
+                                name: "POD_NAME".to_string(),
+                                value_from: Some(EnvVarSource {
+                                    field_ref: Some(ObjectFieldSelector {
+                                        field_path: "metadata.name".to_string(),
+                                        ..ObjectFieldSelector::default()
+                                    }),
+                                    ..EnvVarSource::default()
+                                }),
+                                ..EnvVar::default()
+                            }]
+                        }),
+                        ..Container::default()
+                    }],
+                    volumes: Some(volumes).filter(|volumes| !volumes.is_empty()),
+                    ..PodSpec::default()
+                }),
+            },
+            ..DeploymentSpec::default()
+        }),
+        ..Deployment::default()
+    })?);
+
+    documents.push(yaml(&Service {
+        metadata: meta(name),
+        spec: Some(ServiceSpec {
+            selector: Some(labels.clone()),
+            ports: Some(service_ports),
+            ..ServiceSpec::default()
+        }),
+        ..Service::default()
+    })?);
+
+    // the prometheus-operator CRD, there's no rust type for it. Prometheus presents its
+    // own service account's token when the scrapes need one.
+    let mut endpoint = json!({ "port": "metrics", "path": "/metrics" });
+    if config.server.metrics_auth.enabled {
+        endpoint["bearerTokenFile"] = json!("/var/run/secrets/kubernetes.io/serviceaccount/token");
+    }
+    documents.push(yaml(&json!({
+        "apiVersion": "monitoring.coreos.com/v1",
+        "kind": "ServiceMonitor",
+        "metadata": meta(name),
+        "spec": {
+            "selector": { "matchLabels": labels },
+            "endpoints": [endpoint],
+        },
+    }))?);
+
+    print!("{}", documents.join("---\n"));
+    Ok(())
+}
+
+pub fn yaml(object: &impl Serialize) -> Result<String, serde_yaml::Error> {
+    serde_yaml::to_string(object)
+}
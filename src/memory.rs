@@ -0,0 +1,120 @@
+use crate::{
+    config::MemorySettings,
+    metrics::{MEMORY_EVICTIONS_COUNTER, STORE_LABEL},
+};
+use axum_prometheus::metrics::counter;
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    sync::{LazyLock, RwLock},
+};
+
+// the stores, by their name on the metrics
+pub const SNAPSHOT_STORE: &str = "snapshot";
+pub const SUPPRESSION_STORE: &str = "suppression";
+pub const PER_OBJECT_STORE: &str = "per_object";
+pub const FAILURE_CONTEXTS_STORE: &str = "failure_contexts";
+pub const LIFECYCLE_STORE: &str = "lifecycle";
+pub const TOKEN_REVIEWS_STORE: &str = "token_reviews";
+
+// the limits of the stores, changed on a reload
+static LIMITS: LazyLock<RwLock<MemorySettings>> = LazyLock::new(Default::default);
+
+pub fn configure(settings: &MemorySettings) {
+    *LIMITS.write().expect("memory limits lock poisoned") = settings.clone();
+}
+
+// a map keeping at most its store's limit of entries, dropping the least recently
+// used ones past it and counting them on memory_evictions_total. The limit is looked
+// up on every insert, so a lower one after a reload shrinks it on the next one.
+pub struct Lru<K, V> {
+    store: &'static str,
+    limit: fn(&MemorySettings) -> usize,
+    // the entries with when they were last used, and the other way around
+    entries: HashMap<K, (u64, V)>,
+    order: BTreeMap<u64, K>,
+    tick: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> Lru<K, V> {
+    pub fn new(store: &'static str, limit: fn(&MemorySettings) -> usize) -> Self {
+        Lru {
+            store,
+            limit,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    // the entry, now the most recently used one
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.tick += 1;
+        let (used, value) = self.entries.get_mut(key)?;
+        self.order.remove(used);
+        self.order.insert(self.tick, key.clone());
+        *used = self.tick;
+        Some(value)
+    }
+
+    pub fn get_or_insert_with(&mut self, key: K, value: impl FnOnce() -> V) -> &mut V {
+        if !self.entries.contains_key(&key) {
+            self.insert(key.clone(), value());
+        }
+        self.get_mut(&key).expect("the entry was just inserted")
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        self.tick += 1;
+        if let Some((used, _)) = self.entries.insert(key.clone(), (self.tick, value)) {
+            self.order.remove(&used);
+        }
+        self.order.insert(self.tick, key);
+        let limit = (self.limit)(&LIMITS.read().expect("memory limits lock poisoned")).max(1);
+        let mut evicted = 0;
+        while self.entries.len() > limit {
+            let Some((_, key)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&key);
+            evicted += 1;
+        }
+        if evicted > 0 {
+            counter!(MEMORY_EVICTIONS_COUNTER, &[(STORE_LABEL, self.store)]).increment(evicted);
+        }
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (used, value) = self.entries.remove(key)?;
+        self.order.remove(&used);
+        Some(value)
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&K, &mut V) -> bool) {
+        let order = &mut self.order;
+        self.entries.retain(|key, (used, value)| {
+            let kept = keep(key, value);
+            if !kept {
+                order.remove(used);
+            }
+            kept
+        });
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(key, (_, value))| (key, value))
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{config::MessageSettings, enrich::Enricher, record::EventRecord};
+use k8s_openapi::api::core::v1::Event;
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, RwLock},
+};
+
+// the templates of the config by reason, switched on a reload
+static TEMPLATES: LazyLock<RwLock<HashMap<String, Template>>> = LazyLock::new(Default::default);
+
+// what a record's fields can be put in a message as
+const FIELDS: [&str; 16] = [
+    "namespace",
+    "name",
+    "kind",
+    "object",
+    "pod",
+    "uid",
+    "reason",
+    "message",
+    "type",
+    "severity",
+    "source",
+    "node",
+    "zone",
+    "owner",
+    "count",
+    "last_timestamp",
+];
+
+// a message like "{{namespace}}/{{pod}} was killed by {{source}}", with the fields of
+// a record in between the braces. labels.<name> is one of the labels copied from the pod.
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Field(String),
+    Label(String),
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let Some(end) = rest[start..].find("}}") else {
+                return Err(format!("unclosed {{{{ in {:?}", template));
+            };
+            let field = rest[start + 2..start + end].trim();
+            match field.strip_prefix("labels.") {
+                Some(label) if !label.is_empty() => parts.push(Part::Label(label.to_string())),
+                _ if FIELDS.contains(&field) => parts.push(Part::Field(field.to_string())),
+                _ => {
+                    return Err(format!(
+                        "unknown field {:?} in {:?}, it can be labels.<name> or one of {}",
+                        field,
+                        template,
+                        FIELDS.join(", ")
+                    ))
+                }
+            }
+            rest = &rest[start + end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        Ok(Template { parts })
+    }
+
+    // the fields the record doesn't have are left empty
+    pub fn render(&self, record: &EventRecord) -> String {
+        let mut message = String::new();
+        for part in self.parts.iter() {
+            match part {
+                Part::Text(text) => message.push_str(text),
+                Part::Label(label) => {
+                    message.push_str(record.labels.get(label).map_or("", String::as_str))
+                }
+                Part::Field(field) => {
+                    let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+                    let value = match field.as_str() {
+                        "namespace" => record.namespace.clone(),
+                        "name" => record.name.clone(),
+                        "kind" => record.kind.clone(),
+                        "object" | "pod" => record.object_name.clone(),
+                        "uid" => record.object_uid.clone(),
+                        "reason" => record.reason.clone(),
+                        "message" => record.message.clone(),
+                        "type" => record.type_.clone(),
+                        "severity" => optional(&record.severity),
+                        "source" => record.source.clone(),
+                        "node" => optional(&record.node),
+                        "zone" => optional(&record.zone),
+                        "owner" => optional(&record.owner),
+                        "count" => record.count.to_string(),
+                        "last_timestamp" => optional(&record.last_timestamp),
+                        _ => String::new(),
+                    };
+                    message.push_str(&value);
+                }
+            }
+        }
+        message
+    }
+}
+
+// switches to the templates of a config, which were already checked when loading it
+pub fn configure(settings: &MessageSettings) -> Result<(), String> {
+    let templates = settings
+        .templates
+        .iter()
+        .map(|(reason, template)| Ok((reason.clone(), Template::parse(template)?)))
+        .collect::<Result<HashMap<_, _>, String>>()?;
+    *TEMPLATES.write().expect("message templates lock poisoned") = templates;
+    Ok(())
+}
+
+// the configured message of the record's reason
+pub fn render(record: &EventRecord) -> Option<String> {
+    let templates = TEMPLATES.read().expect("message templates lock poisoned");
+    templates
+        .get(&record.reason)
+        .map(|template| template.render(record))
+}
+
+// the same for an event, only building its record when there's a template for it
+pub fn render_event(event: &Event, enricher: &Enricher) -> Option<String> {
+    let reason = event.reason.as_deref()?;
+    if !TEMPLATES
+        .read()
+        .expect("message templates lock poisoned")
+        .contains_key(reason)
+    {
+        return None;
+    }
+    EventRecord::new(event, enricher).summary
+}
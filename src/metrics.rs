@@ -0,0 +1,1305 @@
+use crate::{
+    config::{EndpointLabelKind, HttpMetricSettings, MetricSettings},
+    error::ErrorKind,
+    persistence::INITIAL_SYNC,
+    process, registry,
+    relabel::Relabeling,
+    tenants::Tenants,
+};
+use axum::{
+    extract::{Path, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use axum_prometheus::{
+    metrics::{
+        describe_counter, describe_gauge, describe_histogram, Counter, CounterFn, Gauge, GaugeFn,
+        Histogram, Key, KeyName, Label, Level, Metadata, Recorder, SharedString, Unit,
+    },
+    metrics_exporter_prometheus::{
+        Matcher, PrometheusBuilder, PrometheusHandle, PrometheusRecorder,
+    },
+    utils::SECONDS_DURATION_BUCKETS,
+    EndpointLabel, PrometheusMetricLayer, PrometheusMetricLayerBuilder,
+};
+use k8s_openapi::api::core::v1::Event;
+use serde::Deserialize;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, LazyLock, Mutex, OnceLock,
+    },
+    time::Duration,
+};
+use tower_http::compression::CompressionLayer;
+
+// the names for our counters
+pub const POD_DELETE_COUNTER: &str = "deleted_pods";
+pub const POD_CREATE_COUNTER: &str = "created_pods";
+pub const WATCHER_BACKOFF_COUNTER: &str = "watcher_backoffs_total";
+pub const WATCH_GAPS_COUNTER: &str = "watch_gaps_total";
+pub const MISSED_EVENTS_COUNTER: &str = "missed_events_estimated_total";
+pub const STALE_SERIES_COUNTER: &str = "stale_series_removed_total";
+pub const MEMORY_EVICTIONS_COUNTER: &str = "memory_evictions_total";
+pub const PROCESS_CPU_COUNTER: &str = "process_cpu_seconds_total";
+pub const CONFIG_CHANGES_COUNTER: &str = "config_changes_total";
+pub const EVICTIONS_BLOCKED_COUNTER: &str = "evictions_blocked_total";
+pub const QUOTA_DENIALS_COUNTER: &str = "quota_denials_total";
+pub const POLICY_DENIALS_COUNTER: &str = "policy_denials_total";
+pub const RBAC_CHANGES_COUNTER: &str = "rbac_changes_total";
+pub const PREEMPTIONS_COUNTER: &str = "pod_preemptions_total";
+pub const WORKLOAD_PODS_CREATED_COUNTER: &str = "workload_pods_created_total";
+pub const WORKLOAD_PODS_DELETED_COUNTER: &str = "workload_pods_deleted_total";
+pub const SPOT_INTERRUPTIONS_COUNTER: &str = "spot_interruptions_total";
+pub const NODE_SCALE_UPS_COUNTER: &str = "node_scale_ups_triggered_total";
+pub const NODE_SCALE_DOWNS_COUNTER: &str = "node_scale_downs_total";
+pub const NODE_EVENTS_COUNTER: &str = "node_events_total";
+pub const RATE_LIMITED_COUNTER: &str = "rate_limited_events_total";
+pub const UPTIME_COUNTER: &str = "uptime_seconds_total";
+pub const PROCESS_RESTARTS_COUNTER: &str = "process_restarts_total";
+pub const DROPPED_EVENTS_COUNTER: &str = "events_dropped_total";
+pub const URGENT_EVENTS_COUNTER: &str = "pipeline_urgent_events_total";
+pub const SINK_DELIVERY_COUNTER: &str = "sink_deliveries_total";
+pub const MONITOR_EVENTS_COUNTER: &str = "monitor_events_total";
+pub const ALERTS_FIRED_COUNTER: &str = "alerts_fired_total";
+pub const INCIDENTS_COUNTER: &str = "incidents_total";
+pub const CONTAINER_RESTARTS_COUNTER: &str = "container_restarts_total";
+pub const PODS_COMPLETED_COUNTER: &str = "pods_completed_total";
+pub const LIFECYCLE_TRANSITIONS_COUNTER: &str = "pod_lifecycle_transitions_total";
+pub const GRACE_EXCEEDED_COUNTER: &str = "pod_termination_grace_exceeded_total";
+pub const IMAGE_PULL_FAILURES_COUNTER: &str = "image_pull_failures_total";
+pub const SCHEDULING_FAILURES_COUNTER: &str = "scheduling_failures_total";
+pub const CONTAINER_LOGS_COUNTER: &str = "container_log_fetches_total";
+pub const NAMESPACES_CREATED_COUNTER: &str = "namespaces_created_total";
+pub const NAMESPACES_DELETED_COUNTER: &str = "namespaces_deleted_total";
+pub const NAMESPACE_EVENTS_COUNTER: &str = "namespace_events_total";
+pub const SERVICE_EVENTS_COUNTER: &str = "service_events_total";
+pub const LB_FAILURES_COUNTER: &str = "load_balancer_failures_total";
+pub const HPA_RESCALES_COUNTER: &str = "hpa_rescales_total";
+pub const HPA_FAILURES_COUNTER: &str = "hpa_failures_total";
+pub const ROLLOUT_EVENTS_COUNTER: &str = "rollout_events_total";
+pub const EVENTS_COUNTER: &str = "events_total";
+pub const EVENTS_RECEIVED_COUNTER: &str = "events_received_total";
+pub const SUPPRESSED_COUNTER: &str = "sink_deliveries_suppressed_total";
+pub const ERRORS_COUNTER: &str = "errors_total";
+pub const MAINTENANCE_HELD_COUNTER: &str = "maintenance_held_deliveries_total";
+pub const SINK_DEAD_LETTERS_COUNTER: &str = "sink_dead_letters_total";
+pub const SINK_DRY_RUN_COUNTER: &str = "sink_dry_run_total";
+pub const PAYLOAD_TRUNCATIONS_COUNTER: &str = "payload_truncations_total";
+pub const SINK_BATCHES_COUNTER: &str = "sink_batches_total";
+pub const API_REQUESTS_COUNTER: &str = "kube_api_requests_total";
+pub const API_THROTTLED_COUNTER: &str = "kube_api_throttled_requests_total";
+pub const SINK_BACKLOG_GAUGE: &str = "sink_backlog";
+pub const SINK_BREAKER_GAUGE: &str = "sink_circuit_breaker_state";
+pub const READY_GAUGE: &str = "ready";
+pub const SCRIPT_ERRORS_COUNTER: &str = "script_errors_total";
+pub const PLUGIN_ERRORS_COUNTER: &str = "plugin_errors_total";
+pub const TENANT_ROUTED_COUNTER: &str = "tenant_routed_records_total";
+
+// the names for our gauges
+pub const BUILD_INFO_GAUGE: &str = "k8rs_build_info";
+pub const INITIAL_SYNC_GAUGE: &str = "k8rs_initial_sync_in_progress";
+pub const FEATURE_ENABLED_GAUGE: &str = "feature_enabled";
+pub const STARTUP_WAIT_GAUGE: &str = "startup_api_wait_seconds";
+pub const NAMESPACES_STUCK_GAUGE: &str = "namespaces_stuck_terminating";
+pub const NAMESPACES_WATCHED_GAUGE: &str = "namespaces_watched";
+pub const LAST_EVENT_GAUGE: &str = "last_event_timestamp_seconds";
+pub const ANOMALY_SCORE_GAUGE: &str = "anomaly_score";
+pub const CONFIG_INFO_GAUGE: &str = "config_info";
+pub const NODE_DRAIN_REMAINING_GAUGE: &str = "node_drain_pods_remaining";
+pub const IMAGE_INFO_GAUGE: &str = "container_image_info";
+pub const NAMESPACE_WATCHER_AGE_GAUGE: &str = "namespace_watcher_last_event_age_seconds";
+pub const NAMESPACE_WATCHER_HEALTHY_GAUGE: &str = "namespace_watcher_healthy";
+pub const MAINTENANCE_ACTIVE_GAUGE: &str = "maintenance_window_active";
+pub const EVENT_TTL_GAUGE: &str = "event_ttl_seconds";
+pub const SHARD_MEMBERS_GAUGE: &str = "shard_members";
+pub const SHARD_REBALANCES_COUNTER: &str = "shard_rebalances_total";
+pub const EVENT_TTL_REMAINING_GAUGE: &str = "event_ttl_remaining_seconds";
+pub const EVENT_TTL_MISMATCH_GAUGE: &str = "event_ttl_mismatch";
+pub const LABEL_DETAIL_GAUGE: &str = "pod_series_degradation_level";
+pub const NAMESPACE_WATCHER_ERRORS_COUNTER: &str = "namespace_watcher_errors_total";
+pub const NAMESPACE_WATCHER_RESTARTS_COUNTER: &str = "namespace_watcher_restarts_total";
+pub const CERTIFICATE_EXPIRY_GAUGE: &str = "certificate_expiry_timestamp_seconds";
+pub const DISRUPTIONS_ALLOWED_GAUGE: &str = "pdb_disruptions_allowed";
+pub const QUOTA_HARD_GAUGE: &str = "resource_quota_hard";
+pub const QUOTA_USED_GAUGE: &str = "resource_quota_used";
+pub const SERVICES_WITHOUT_ENDPOINTS_GAUGE: &str = "services_without_ready_endpoints";
+pub const PENDING_PODS_GAUGE: &str = "pending_pods";
+pub const WORKLOAD_CHURN_GAUGE: &str = "workload_pod_churn";
+pub const WORKLOAD_VOLUNTARY_DELETIONS_GAUGE: &str = "workload_pod_voluntary_deletions_ratio";
+pub const ROLLOUT_DESIRED_GAUGE: &str = "rollout_desired_replicas";
+pub const ROLLOUT_UPDATED_GAUGE: &str = "rollout_updated_replicas";
+pub const ROLLOUT_STUCK_GAUGE: &str = "rollout_stuck";
+pub const NAMESPACE_REQUESTS_GAUGE: &str = "namespace_resource_requests";
+pub const NAMESPACE_LIMITS_GAUGE: &str = "namespace_resource_limits";
+pub const WORKLOAD_REQUESTS_GAUGE: &str = "workload_resource_requests";
+pub const WORKLOAD_LIMITS_GAUGE: &str = "workload_resource_limits";
+pub const NODE_CAPACITY_GAUGE: &str = "node_capacity";
+pub const NODE_ALLOCATABLE_GAUGE: &str = "node_allocatable";
+pub const NODE_CONDITION_GAUGE: &str = "node_condition";
+pub const NODE_FLAPPING_GAUGE: &str = "node_flapping";
+pub const DISCOVERED_KINDS_GAUGE: &str = "discovered_kinds";
+pub const SLO_EVENTS_GAUGE: &str = "slo_events";
+pub const SLO_BUDGET_REMAINING_GAUGE: &str = "slo_error_budget_remaining";
+pub const SLO_BURN_RATE_GAUGE: &str = "slo_burn_rate";
+pub const WATCHER_PAUSED_GAUGE: &str = "watcher_paused";
+pub const PROCESS_RESIDENT_MEMORY_GAUGE: &str = "process_resident_memory_bytes";
+pub const PROCESS_VIRTUAL_MEMORY_GAUGE: &str = "process_virtual_memory_bytes";
+pub const PROCESS_THREADS_GAUGE: &str = "process_threads";
+pub const PROCESS_START_TIME_GAUGE: &str = "process_start_time_seconds";
+pub const PROCESS_OPEN_FDS_GAUGE: &str = "process_open_fds";
+pub const PROCESS_MAX_FDS_GAUGE: &str = "process_max_fds";
+pub const TOKIO_WORKERS_GAUGE: &str = "tokio_workers";
+pub const TOKIO_ALIVE_TASKS_GAUGE: &str = "tokio_alive_tasks";
+pub const TOKIO_GLOBAL_QUEUE_GAUGE: &str = "tokio_global_queue_depth";
+pub const JEMALLOC_BYTES_GAUGE: &str = "jemalloc_bytes";
+
+// the names for our histograms
+pub const LIFECYCLE_STATE_HISTOGRAM: &str = "pod_lifecycle_state_duration_seconds";
+pub const TERMINATION_HISTOGRAM: &str = "pod_termination_duration_seconds";
+pub const SCHEDULING_HISTOGRAM: &str = "pod_scheduling_duration_seconds";
+pub const LB_PROVISIONING_HISTOGRAM: &str = "load_balancer_provisioning_duration_seconds";
+pub const NODE_SCALE_UP_HISTOGRAM: &str = "node_scale_up_duration_seconds";
+pub const NODE_DRAIN_HISTOGRAM: &str = "node_drain_duration_seconds";
+pub const EVENT_DELAY_HISTOGRAM: &str = "event_processing_delay_seconds";
+pub const API_REQUEST_HISTOGRAM: &str = "kube_api_request_duration_seconds";
+pub const API_THROTTLE_WAIT_HISTOGRAM: &str = "kube_api_throttle_wait_seconds";
+
+// the ones whose buckets metrics.buckets can change
+pub const HISTOGRAMS: [&str; 9] = [
+    LIFECYCLE_STATE_HISTOGRAM,
+    TERMINATION_HISTOGRAM,
+    SCHEDULING_HISTOGRAM,
+    LB_PROVISIONING_HISTOGRAM,
+    NODE_SCALE_UP_HISTOGRAM,
+    NODE_DRAIN_HISTOGRAM,
+    EVENT_DELAY_HISTOGRAM,
+    API_REQUEST_HISTOGRAM,
+    API_THROTTLE_WAIT_HISTOGRAM,
+];
+
+// the names for our labels
+pub const POD_ID_LABEL: &str = "pod_id";
+pub const NODE_LABEL: &str = "node";
+pub const ZONE_LABEL: &str = "zone";
+pub const REPORTER_LABEL: &str = "reporting_controller";
+pub const WATCHER_LABEL: &str = "watcher";
+pub const DROP_REASON_LABEL: &str = "reason";
+pub const SINK_LABEL: &str = "sink";
+pub const SINK_RESULT_LABEL: &str = "result";
+pub const MONITOR_LABEL: &str = "monitor";
+pub const NAMESPACE_LABEL: &str = "namespace";
+pub const KIND_LABEL: &str = "kind";
+pub const COMPONENT_LABEL: &str = "component";
+pub const REASON_LABEL: &str = "reason";
+pub const ALERT_LABEL: &str = "alert";
+pub const WORKLOAD_LABEL: &str = "workload";
+pub const CONTAINER_LABEL: &str = "container";
+pub const CONTAINER_TYPE_LABEL: &str = "container_type";
+pub const RESOURCE_LABEL: &str = "resource";
+pub const CONDITION_LABEL: &str = "condition";
+pub const SLO_LABEL: &str = "slo";
+pub const WINDOW_LABEL: &str = "window";
+pub const FROM_STATE_LABEL: &str = "from";
+pub const TO_STATE_LABEL: &str = "to";
+pub const STATE_LABEL: &str = "state";
+pub const PRIORITY_CLASS_LABEL: &str = "priority_class";
+pub const VICTIM_PRIORITY_CLASS_LABEL: &str = "victim_priority_class";
+pub const PREEMPTOR_PRIORITY_CLASS_LABEL: &str = "preemptor_priority_class";
+pub const REPOSITORY_LABEL: &str = "repository";
+pub const SERVICE_LABEL: &str = "service";
+pub const NAME_LABEL: &str = "name";
+pub const HPA_LABEL: &str = "hpa";
+pub const DIRECTION_LABEL: &str = "direction";
+pub const TYPE_LABEL: &str = "type";
+pub const SEVERITY_LABEL: &str = "severity";
+pub const SCRIPT_LABEL: &str = "script";
+#[cfg_attr(not(feature = "plugins"), allow(dead_code))]
+pub const PLUGIN_LABEL: &str = "plugin";
+pub const TENANT_LABEL: &str = "tenant";
+pub const VERB_LABEL: &str = "verb";
+pub const CODE_LABEL: &str = "code";
+pub const VERSION_LABEL: &str = "version";
+pub const GIT_SHA_LABEL: &str = "git_sha";
+pub const RUSTC_LABEL: &str = "rustc";
+pub const TLS_LABEL: &str = "tls";
+pub const FEATURE_LABEL: &str = "feature";
+pub const STAGE_LABEL: &str = "stage";
+pub const HASH_LABEL: &str = "hash";
+pub const SECRET_LABEL: &str = "secret";
+pub const PDB_LABEL: &str = "pdb";
+pub const QUOTA_LABEL: &str = "quota";
+pub const POLICY_LABEL: &str = "policy";
+pub const ACTION_LABEL: &str = "action";
+pub const IMAGE_LABEL: &str = "image";
+pub const TAG_LABEL: &str = "tag";
+pub const DIGEST_LABEL: &str = "digest";
+pub const CAUSE_LABEL: &str = "cause";
+pub const SOURCE_LABEL: &str = "source";
+pub const GROUP_LABEL: &str = "group";
+pub const STORE_LABEL: &str = "store";
+pub const SIGNAL_LABEL: &str = "signal";
+pub const FIELD_LABEL: &str = "field";
+
+// a struct for our metrics label. When the event happened isn't one of them, that would
+// make a new series of every event: it's on last_event_timestamp_seconds instead.
+pub struct EventLabels {
+    pub object_id: String,
+    pub node: String,
+    pub zone: String,
+    // who reported the event, like kubelet or node-controller
+    pub reporter: String,
+    // the pod labels/annotations we were asked to copy, already with prometheus names
+    pub pod_labels: Vec<(String, String)>,
+}
+
+impl EventLabels {
+    pub fn to_metric_labels(&self) -> Vec<Label> {
+        let mut labels = vec![
+            Label::new(POD_ID_LABEL, self.object_id.clone()),
+            Label::new(NODE_LABEL, self.node.clone()),
+            Label::new(ZONE_LABEL, self.zone.clone()),
+            Label::new(REPORTER_LABEL, self.reporter.clone()),
+        ];
+        labels.extend(
+            self.pod_labels
+                .iter()
+                .map(|(name, value)| Label::new(name.clone(), value.clone())),
+        );
+        labels
+    }
+}
+
+// turns a k8s label key like "app.kubernetes.io/name" into a valid prometheus
+// label name like "label_app_kubernetes_io_name", the same way kube-state-metrics does
+pub fn sanitize_label_name(prefix: &str, key: &str) -> String {
+    let sanitized: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("{}_{}", prefix, sanitized)
+}
+
+// what prometheus accepts as a metric name
+pub fn is_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+// and as a label name, those starting with __ are reserved for prometheus itself
+pub fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    !name.starts_with("__")
+        && chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// the http metrics' prefix when the config doesn't set one
+const DEFAULT_HTTP_PREFIX: &str = "pods_operator";
+
+static NAMING: OnceLock<Naming> = OnceLock::new();
+
+// how the config wants our metrics and labels to be named. The code keeps using the
+// default names, they're only changed on their way to the prometheus recorder.
+#[derive(Default)]
+pub struct Naming {
+    prefix: Option<String>,
+    names: BTreeMap<String, String>,
+    labels: BTreeMap<String, String>,
+    relabeling: Relabeling,
+}
+
+impl Naming {
+    pub fn new(settings: &MetricSettings) -> Result<Self, ErrorKind> {
+        Ok(Naming {
+            prefix: settings.prefix.clone(),
+            names: settings.names.clone(),
+            labels: settings.labels.clone(),
+            relabeling: Relabeling::new(&settings.relabel).map_err(ErrorKind::other)?,
+        })
+    }
+
+    fn is_default(&self) -> bool {
+        self.prefix.is_none()
+            && self.names.is_empty()
+            && self.labels.is_empty()
+            && self.relabeling.is_empty()
+    }
+
+    // axum-prometheus puts it in front of its own metrics
+    pub fn http_prefix(&self) -> &str {
+        self.prefix.as_deref().unwrap_or(DEFAULT_HTTP_PREFIX)
+    }
+
+    pub fn metric<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        let http = name
+            .strip_prefix(self.http_prefix())
+            .is_some_and(|rest| rest.starts_with("_http_"));
+        let renamed = self.names.get(name).map(String::as_str);
+        match (&self.prefix, renamed) {
+            // the http metrics already got the prefix
+            (Some(prefix), renamed) if !http => {
+                Cow::Owned(format!("{}_{}", prefix, renamed.unwrap_or(name)))
+            }
+            (_, Some(renamed)) => Cow::Owned(renamed.to_string()),
+            _ => Cow::Borrowed(name),
+        }
+    }
+
+    pub fn label<'a>(&self, label: &'a str) -> Cow<'a, str> {
+        match self.labels.get(label) {
+            Some(renamed) => Cow::Owned(renamed.clone()),
+            None => Cow::Borrowed(label),
+        }
+    }
+
+    fn key_name(&self, name: KeyName) -> KeyName {
+        match self.metric(name.as_str()) {
+            Cow::Owned(renamed) => KeyName::from(renamed),
+            Cow::Borrowed(_) => name,
+        }
+    }
+
+    // the renamed labels of a series after metrics.relabel, None when it's dropped
+    pub fn relabel(&self, name: &str, labels: Vec<Label>) -> Option<Vec<Label>> {
+        match self.relabeling.is_empty() {
+            true => Some(labels),
+            false => self.relabeling.apply(name, labels),
+        }
+    }
+
+    // None for the series the relabeling drops
+    fn key<'a>(&self, key: &'a Key) -> Option<Cow<'a, Key>> {
+        if self.is_default() {
+            return Some(Cow::Borrowed(key));
+        }
+        let name = self.metric(key.name()).into_owned();
+        let labels = key
+            .labels()
+            .map(|label| {
+                Label::new(
+                    self.label(label.key()).into_owned(),
+                    label.value().to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let labels = self.relabel(&name, labels)?;
+        Some(Cow::Owned(Key::from_parts(name, labels)))
+    }
+}
+
+// the naming of the running config, the default one until the recorder is installed
+pub fn naming() -> &'static Naming {
+    NAMING.get_or_init(Naming::default)
+}
+
+// the recorder behind the renaming one, for the counters restored with their names
+// already changed
+static RECORDER: OnceLock<Arc<PrometheusRecorder>> = OnceLock::new();
+
+// sets a counter to a value it had before a restart, by the name and labels /metrics
+// had it under
+pub fn restore_counter(key: &Key, value: u64) {
+    if let Some(recorder) = RECORDER.get() {
+        let metadata = Metadata::new(module_path!(), Level::INFO, Some(module_path!()));
+        recorder.register_counter(key, &metadata).absolute(value);
+    }
+    if let Some(watched) = WATCHED.watch(key, Watch::Counter) {
+        CounterFn::absolute(&*watched, value);
+    }
+}
+
+// the series of the names the alert rules' exprs select, as /metrics has them, kept
+// along with the recorder's for the rules to read rather than render /metrics
+static WATCHED: LazyLock<Watched> = LazyLock::new(Watched::default);
+
+#[derive(Default)]
+struct Watched {
+    names: OnceLock<HashSet<String>>,
+    series: Mutex<HashMap<Key, Watch>>,
+}
+
+enum Watch {
+    Counter(Arc<AtomicU64>),
+    // the bits of an f64
+    Gauge(Arc<AtomicU64>),
+}
+
+impl Watched {
+    // the value of the series, when its name is watched
+    fn watch(&self, key: &Key, kind: fn(Arc<AtomicU64>) -> Watch) -> Option<Arc<AtomicU64>> {
+        if !self.names.get()?.contains(key.name()) {
+            return None;
+        }
+        let mut series = self.series.lock().expect("watched series lock poisoned");
+        match *series
+            .entry(key.clone())
+            .or_insert_with(|| kind(Default::default()))
+        {
+            Watch::Counter(ref value) | Watch::Gauge(ref value) => Some(value.clone()),
+        }
+    }
+
+    fn counter(&self, key: &Key, counter: Counter) -> Counter {
+        match self.watch(key, Watch::Counter) {
+            Some(watched) => Counter::from_arc(Arc::new(Tee {
+                inner: counter,
+                watched,
+            })),
+            None => counter,
+        }
+    }
+
+    fn gauge(&self, key: &Key, gauge: Gauge) -> Gauge {
+        match self.watch(key, Watch::Gauge) {
+            Some(watched) => Gauge::from_arc(Arc::new(Tee {
+                inner: gauge,
+                watched,
+            })),
+            None => gauge,
+        }
+    }
+}
+
+// a series of the recorder that's also kept in WATCHED
+struct Tee<T> {
+    inner: T,
+    watched: Arc<AtomicU64>,
+}
+
+impl CounterFn for Tee<Counter> {
+    fn increment(&self, value: u64) {
+        self.inner.increment(value);
+        CounterFn::increment(&*self.watched, value);
+    }
+
+    fn absolute(&self, value: u64) {
+        self.inner.absolute(value);
+        CounterFn::absolute(&*self.watched, value);
+    }
+}
+
+impl GaugeFn for Tee<Gauge> {
+    fn increment(&self, value: f64) {
+        self.inner.increment(value);
+        GaugeFn::increment(&*self.watched, value);
+    }
+
+    fn decrement(&self, value: f64) {
+        self.inner.decrement(value);
+        GaugeFn::decrement(&*self.watched, value);
+    }
+
+    fn set(&self, value: f64) {
+        self.inner.set(value);
+        GaugeFn::set(&*self.watched, value);
+    }
+}
+
+// the names the alert rules' exprs select, their series being kept from now on. Called
+// before the counters are restored, once.
+pub fn watch(names: impl IntoIterator<Item = String>) {
+    let _ = WATCHED.names.set(names.into_iter().collect());
+}
+
+// the counters and gauges /metrics has under the name, with their current values
+pub fn select(name: &str) -> Vec<(registry::Labels, f64)> {
+    let mut selected = WATCHED
+        .series
+        .lock()
+        .expect("watched series lock poisoned")
+        .iter()
+        .filter(|(key, _)| key.name() == name)
+        .map(|(key, watch)| {
+            let labels = key
+                .labels()
+                .map(|label| (label.key().to_string(), label.value().to_string()))
+                .collect();
+            let value = match *watch {
+                Watch::Counter(ref value) => value.load(Ordering::Relaxed) as f64,
+                Watch::Gauge(ref value) => f64::from_bits(value.load(Ordering::Relaxed)),
+            };
+            (labels, value)
+        })
+        .collect::<Vec<_>>();
+    selected.extend(registry::select(name));
+    selected
+}
+
+// renames everything recorded before handing it to the prometheus recorder
+struct RenamingRecorder {
+    inner: Arc<PrometheusRecorder>,
+}
+
+impl Recorder for RenamingRecorder {
+    fn describe_counter(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.inner
+            .describe_counter(naming().key_name(key), unit, description)
+    }
+
+    fn describe_gauge(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.inner
+            .describe_gauge(naming().key_name(key), unit, description)
+    }
+
+    fn describe_histogram(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.inner
+            .describe_histogram(naming().key_name(key), unit, description)
+    }
+
+    fn register_counter(&self, key: &Key, metadata: &Metadata<'_>) -> Counter {
+        match naming().key(key) {
+            Some(key) => WATCHED.counter(&key, self.inner.register_counter(&key, metadata)),
+            None => Counter::noop(),
+        }
+    }
+
+    fn register_gauge(&self, key: &Key, metadata: &Metadata<'_>) -> Gauge {
+        match naming().key(key) {
+            Some(key) => WATCHED.gauge(&key, self.inner.register_gauge(&key, metadata)),
+            None => Gauge::noop(),
+        }
+    }
+
+    fn register_histogram(&self, key: &Key, metadata: &Metadata<'_>) -> Histogram {
+        match naming().key(key) {
+            Some(key) => self.inner.register_histogram(&key, metadata),
+            None => Histogram::noop(),
+        }
+    }
+}
+
+// installs the recorder behind /metrics with the naming of the config, only once
+pub fn install_recorder(settings: &MetricSettings) -> Result<PrometheusHandle, ErrorKind> {
+    NAMING
+        .set(Naming::new(settings)?)
+        .map_err(|_| "the metrics recorder is only installed once")?;
+    let recorder = Arc::new(prometheus_builder(settings).build_recorder());
+    let handle = recorder.handle();
+    let _ = RECORDER.set(recorder.clone());
+    axum_prometheus::metrics::set_global_recorder(RenamingRecorder { inner: recorder })
+        .map_err(|_| "the metrics recorder is only installed once")?;
+    if settings.exemplars {
+        crate::registry::EXEMPLARS.enable();
+    }
+    Ok(handle)
+}
+
+// the tests' recorder, installed once for all of them with the default settings. The
+// ones rendering metrics get it first, the names can't be changed once they're used.
+#[cfg(test)]
+pub fn test_recorder() -> PrometheusHandle {
+    static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+    HANDLE
+        .get_or_init(|| install_recorder(&Default::default()).expect("the first recorder"))
+        .clone()
+}
+
+// pods take a lot longer than http requests
+const POD_DURATION_BUCKETS: &[f64] = &[
+    0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0,
+];
+
+// the recorder behind /metrics. Histograms get buckets instead of being summaries, the
+// ones of the config over the defaults.
+fn prometheus_builder(settings: &MetricSettings) -> PrometheusBuilder {
+    let http = &settings.http;
+    let http_duration = format!("{}_http_requests_duration_seconds", naming().http_prefix());
+    let mut builder = PrometheusBuilder::new()
+        .upkeep_timeout(Duration::from_secs(5))
+        .set_buckets(SECONDS_DURATION_BUCKETS)
+        .expect("the buckets aren't empty")
+        .set_buckets_for_metric(
+            Matcher::Full(naming().metric(&http_duration).into_owned()),
+            &http.buckets,
+        )
+        .expect("the buckets are validated with the config");
+    for histogram in [
+        LIFECYCLE_STATE_HISTOGRAM,
+        TERMINATION_HISTOGRAM,
+        SCHEDULING_HISTOGRAM,
+        LB_PROVISIONING_HISTOGRAM,
+        NODE_SCALE_UP_HISTOGRAM,
+        NODE_DRAIN_HISTOGRAM,
+        EVENT_DELAY_HISTOGRAM,
+    ] {
+        builder = builder
+            .set_buckets_for_metric(
+                Matcher::Full(naming().metric(histogram).into_owned()),
+                POD_DURATION_BUCKETS,
+            )
+            .expect("the buckets aren't empty");
+    }
+    for (histogram, buckets) in settings.buckets.iter() {
+        builder = builder
+            .set_buckets_for_metric(
+                Matcher::Full(naming().metric(histogram).into_owned()),
+                buckets,
+            )
+            .expect("the buckets are validated with the config");
+    }
+    builder
+}
+
+// the layer counting the requests to our http server, into the installed recorder.
+// It borrows its patterns for as long as the server runs, which is until we exit.
+pub fn http_metrics_layer(
+    settings: &HttpMetricSettings,
+    recorder: PrometheusHandle,
+) -> (PrometheusMetricLayer<'static>, PrometheusHandle) {
+    fn leak(patterns: &[String]) -> &'static [&'static str] {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| &*pattern.clone().leak())
+            .collect::<Vec<_>>();
+        patterns.leak()
+    }
+    let mut builder = PrometheusMetricLayerBuilder::new()
+        .with_prefix(naming().http_prefix())
+        .with_ignore_patterns(leak(&settings.ignore_patterns))
+        .with_endpoint_label_type(match settings.endpoint_label {
+            EndpointLabelKind::MatchedPath => EndpointLabel::MatchedPath,
+            EndpointLabelKind::Exact => EndpointLabel::Exact,
+        });
+    for (group, patterns) in settings.groups.iter() {
+        builder = builder.with_group_patterns_as(group.clone().leak(), leak(patterns));
+    }
+    builder.with_metrics_from_fn(|| recorder).build_pair()
+}
+
+#[derive(Deserialize)]
+struct MetricsQuery {
+    tenant: Option<String>,
+}
+
+// the /metrics endpoint, what the recorder and our registry hold, and the
+// /metrics/<tenant> ones with only the series of a tenant's namespaces
+pub fn endpoint(handle: PrometheusHandle, tenants: Arc<Tenants>) -> Router {
+    let (all, each) = (handle.clone(), tenants.clone());
+    Router::new()
+        .route(
+            "/metrics",
+            get(
+                |headers: HeaderMap, Query(query): Query<MetricsQuery>| async move {
+                    render(&all, &tenants, query.tenant.as_deref(), &headers)
+                },
+            ),
+        )
+        .route(
+            "/metrics/:tenant",
+            get(
+                |headers: HeaderMap, Path(tenant): Path<String>| async move {
+                    render(&handle, &each, Some(&tenant), &headers)
+                },
+            ),
+        )
+        // gzipped when the scraper accepts it, which big registries need
+        .layer(CompressionLayer::new())
+}
+
+fn render(
+    handle: &PrometheusHandle,
+    tenants: &Tenants,
+    tenant: Option<&str>,
+    headers: &HeaderMap,
+) -> Response {
+    // our own process' usage is as of now
+    process::collect();
+    let mut metrics = handle.render() + registry::render().as_str();
+    metrics += &INITIAL_SYNC.render(&metrics);
+    if let Some(tenant) = tenant {
+        match tenants.metrics(tenant, &metrics) {
+            Some(theirs) => metrics = theirs,
+            None => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    format!("no metrics for tenant {}", tenant),
+                )
+                    .into_response()
+            }
+        }
+    }
+    // what we render, unless there are exemplars to go along with it
+    // and the scraper takes openmetrics (prometheus falls back to it)
+    let openmetrics = registry::EXEMPLARS.enabled()
+        && headers
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| accept.contains("application/openmetrics-text"));
+    let (content_type, metrics) = if openmetrics {
+        (
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+            registry::openmetrics(&metrics),
+        )
+    } else {
+        ("text/plain; version=0.0.4; charset=utf-8", metrics)
+    };
+    (
+        [
+            (header::CONTENT_TYPE, content_type),
+            // every scrape has to see the current values
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        metrics,
+    )
+        .into_response()
+}
+
+pub fn initialize_counters() {
+    describe_counter!(
+        EVENTS_RECEIVED_COUNTER,
+        Unit::Count,
+        "The number of events received from the watch stream, before any filtering"
+    );
+    describe_histogram!(
+        API_REQUEST_HISTOGRAM,
+        Unit::Seconds,
+        "The time the api server took to answer each request, by verb and resource"
+    );
+    describe_counter!(
+        API_REQUESTS_COUNTER,
+        Unit::Count,
+        "The number of requests sent to the api server, by verb, resource and status code"
+    );
+    describe_counter!(
+        API_THROTTLED_COUNTER,
+        Unit::Count,
+        "The number of requests the api server answered 429 to, by verb and resource"
+    );
+    describe_histogram!(
+        API_THROTTLE_WAIT_HISTOGRAM,
+        Unit::Seconds,
+        "The waits before sending a throttled request again, by verb and resource"
+    );
+    describe_histogram!(
+        EVENT_DELAY_HISTOGRAM,
+        Unit::Seconds,
+        "The time between an event's last timestamp and the end of its processing"
+    );
+    describe_counter!(
+        EVENTS_COUNTER,
+        Unit::Count,
+        "The number of events processed, by type, severity, kind and namespace"
+    );
+    describe_counter!(
+        DROPPED_EVENTS_COUNTER,
+        Unit::Count,
+        "The number of events dropped before being processed"
+    );
+    describe_counter!(
+        URGENT_EVENTS_COUNTER,
+        Unit::Count,
+        "The number of events queued ahead of the others, with pipeline.priority on"
+    );
+    describe_counter!(
+        SCRIPT_ERRORS_COUNTER,
+        Unit::Count,
+        "The number of times each script failed on an event"
+    );
+    describe_counter!(
+        PLUGIN_ERRORS_COUNTER,
+        Unit::Count,
+        "The number of times each plugin failed on an event"
+    );
+    describe_counter!(
+        TENANT_ROUTED_COUNTER,
+        Unit::Count,
+        "The number of records delivered to each tenant's sinks"
+    );
+    describe_counter!(
+        SUPPRESSED_COUNTER,
+        Unit::Count,
+        "The number of records held back from the sinks by the suppression cooldown"
+    );
+    describe_counter!(
+        ERRORS_COUNTER,
+        Unit::Count,
+        "The number of errors each component of the operator reported, by kind"
+    );
+    describe_counter!(
+        MAINTENANCE_HELD_COUNTER,
+        Unit::Count,
+        "The number of records held back from each sink by each maintenance window"
+    );
+    describe_counter!(
+        SINK_DRY_RUN_COUNTER,
+        Unit::Count,
+        "The number of records and alerts each sink in dry run would have sent"
+    );
+    describe_counter!(
+        PAYLOAD_TRUNCATIONS_COUNTER,
+        Unit::Count,
+        "The number of times a field of a record was cut down to its size limit for the sinks"
+    );
+    describe_gauge!(
+        MAINTENANCE_ACTIVE_GAUGE,
+        "Whether each maintenance window is open, holding back the sinks"
+    );
+    describe_gauge!(
+        SHARD_MEMBERS_GAUGE,
+        "The number of replicas splitting the discovered namespaces between them"
+    );
+    describe_counter!(
+        SHARD_REBALANCES_COUNTER,
+        Unit::Count,
+        "The number of times the discovered namespaces were split again, as replicas joined or left"
+    );
+    describe_gauge!(
+        EVENT_TTL_GAUGE,
+        Unit::Seconds,
+        "How long the api server keeps the events, configured or estimated from the oldest listed"
+    );
+    describe_gauge!(
+        EVENT_TTL_REMAINING_GAUGE,
+        Unit::Seconds,
+        "How long until the first events a failing events watcher missed expire, the whole ttl while it's fine"
+    );
+    describe_gauge!(
+        EVENT_TTL_MISMATCH_GAUGE,
+        "Whether each events watcher re-lists less often (resync) or was last down for longer (downtime) than the event ttl"
+    );
+    describe_counter!(
+        SINK_DELIVERY_COUNTER,
+        Unit::Count,
+        "The number of event deliveries to each sink, by result"
+    );
+    describe_counter!(
+        SINK_BATCHES_COUNTER,
+        Unit::Count,
+        "The number of batches of records sent to each batching sink, by result"
+    );
+    describe_gauge!(
+        SINK_BACKLOG_GAUGE,
+        Unit::Count,
+        "The number of records each sink holds that weren't delivered yet, batched or being retried"
+    );
+    describe_gauge!(
+        SINK_BREAKER_GAUGE,
+        "The state of each sink's circuit breaker: 0 closed, 1 open, 2 half-open"
+    );
+    describe_gauge!(
+        READY_GAUGE,
+        "Whether /readyz reports the exporter as ready, 0 while a sink is backing up"
+    );
+    describe_counter!(
+        SINK_DEAD_LETTERS_COUNTER,
+        Unit::Count,
+        "The number of records each sink failed to deliver, handed to its dead letter destination"
+    );
+    describe_counter!(
+        ALERTS_FIRED_COUNTER,
+        Unit::Count,
+        "The number of times each alert rule started firing"
+    );
+    describe_counter!(
+        INCIDENTS_COUNTER,
+        Unit::Count,
+        "The number of incidents closed, by namespace"
+    );
+    describe_gauge!(
+        ANOMALY_SCORE_GAUGE,
+        "The z-score of each workload's restarts and warnings against its own baseline"
+    );
+    describe_counter!(
+        CONTAINER_RESTARTS_COUNTER,
+        Unit::Count,
+        "The number of container restarts, from the pods' restart counts"
+    );
+    describe_counter!(
+        PODS_COMPLETED_COUNTER,
+        Unit::Count,
+        "The number of pods that succeeded, by namespace and the kind of their owner"
+    );
+    describe_counter!(
+        LIFECYCLE_TRANSITIONS_COUNTER,
+        Unit::Count,
+        "The number of times pods went from one lifecycle state to another"
+    );
+    describe_histogram!(
+        LIFECYCLE_STATE_HISTOGRAM,
+        Unit::Seconds,
+        "How long pods spent in each lifecycle state"
+    );
+    describe_histogram!(
+        TERMINATION_HISTOGRAM,
+        Unit::Seconds,
+        "How long pods took to go away once their containers were killed"
+    );
+    describe_histogram!(
+        SCHEDULING_HISTOGRAM,
+        Unit::Seconds,
+        "How long pods waited from their creation to being scheduled"
+    );
+    describe_counter!(
+        GRACE_EXCEEDED_COUNTER,
+        Unit::Count,
+        "The number of pods that took longer than their grace period to stop"
+    );
+    describe_counter!(
+        CONTAINER_LOGS_COUNTER,
+        Unit::Count,
+        "The number of times the logs of a container were fetched for a failure event, by result"
+    );
+    describe_counter!(
+        IMAGE_PULL_FAILURES_COUNTER,
+        Unit::Count,
+        "The number of times the kubelet failed to pull an image, by image repository"
+    );
+    describe_counter!(
+        SCHEDULING_FAILURES_COUNTER,
+        Unit::Count,
+        "The number of times the scheduler couldn't find a node for a pod, by why the nodes didn't fit"
+    );
+    describe_counter!(
+        NAMESPACES_CREATED_COUNTER,
+        Unit::Count,
+        "The number of namespaces created"
+    );
+    describe_counter!(
+        NAMESPACES_DELETED_COUNTER,
+        Unit::Count,
+        "The number of namespaces deleted"
+    );
+    describe_counter!(
+        NAMESPACE_EVENTS_COUNTER,
+        Unit::Count,
+        "The number of events about each namespace, by reason"
+    );
+    describe_gauge!(
+        NAMESPACES_STUCK_GAUGE,
+        Unit::Count,
+        "The number of namespaces terminating for longer than namespaces.stuck_after"
+    );
+    describe_gauge!(
+        NAMESPACES_WATCHED_GAUGE,
+        Unit::Count,
+        "The number of namespaces matching namespaces.selector whose events are watched"
+    );
+    describe_counter!(
+        SERVICE_EVENTS_COUNTER,
+        Unit::Count,
+        "The number of events about each service and its endpoints, by reason"
+    );
+    describe_gauge!(
+        SERVICES_WITHOUT_ENDPOINTS_GAUGE,
+        Unit::Count,
+        "The number of services without any ready endpoint"
+    );
+    describe_histogram!(
+        LB_PROVISIONING_HISTOGRAM,
+        Unit::Seconds,
+        "How long the cloud controller took to ensure a Service's load balancer"
+    );
+    describe_counter!(
+        LB_FAILURES_COUNTER,
+        Unit::Count,
+        "The number of load balancer provisioning failures, by object and reason"
+    );
+    describe_counter!(
+        HPA_RESCALES_COUNTER,
+        Unit::Count,
+        "The number of rescales of each HorizontalPodAutoscaler, by direction"
+    );
+    describe_counter!(
+        HPA_FAILURES_COUNTER,
+        Unit::Count,
+        "The number of times each HorizontalPodAutoscaler failed to get its metrics or rescale"
+    );
+    describe_counter!(
+        ROLLOUT_EVENTS_COUNTER,
+        Unit::Count,
+        "The number of events about each StatefulSet and DaemonSet, by reason"
+    );
+    describe_gauge!(
+        ROLLOUT_DESIRED_GAUGE,
+        Unit::Count,
+        "The number of replicas each StatefulSet and DaemonSet should have"
+    );
+    describe_gauge!(
+        ROLLOUT_UPDATED_GAUGE,
+        Unit::Count,
+        "The number of replicas of each StatefulSet and DaemonSet already on its latest revision"
+    );
+    describe_gauge!(
+        ROLLOUT_STUCK_GAUGE,
+        "Whether the rollout of a StatefulSet or DaemonSet has been going on for longer than rollouts.stuck_after"
+    );
+    describe_gauge!(
+        NAMESPACE_REQUESTS_GAUGE,
+        "The cpu cores and memory bytes requested by the running pods of each namespace"
+    );
+    describe_gauge!(
+        NAMESPACE_LIMITS_GAUGE,
+        "The cpu cores and memory bytes the running pods of each namespace are limited to"
+    );
+    describe_gauge!(
+        WORKLOAD_REQUESTS_GAUGE,
+        "The cpu cores and memory bytes requested by the running pods of each workload"
+    );
+    describe_gauge!(
+        WORKLOAD_LIMITS_GAUGE,
+        "The cpu cores and memory bytes the running pods of each workload are limited to"
+    );
+    describe_gauge!(
+        NODE_CAPACITY_GAUGE,
+        "The cpu cores, memory bytes and pods each node has"
+    );
+    describe_gauge!(
+        NODE_ALLOCATABLE_GAUGE,
+        "The cpu cores, memory bytes and pods of each node left for the pods"
+    );
+    describe_gauge!(
+        NODE_CONDITION_GAUGE,
+        "Whether each node is Ready or under DiskPressure or MemoryPressure"
+    );
+    describe_counter!(
+        WATCH_GAPS_COUNTER,
+        Unit::Count,
+        "The number of changes and deletes each watcher only found out about when re-listing"
+    );
+    describe_counter!(
+        MISSED_EVENTS_COUNTER,
+        Unit::Count,
+        "The number of event occurrences each events watcher missed while reconnecting, from the events' counts"
+    );
+    describe_counter!(
+        EVICTIONS_BLOCKED_COUNTER,
+        Unit::Count,
+        "The number of events telling an eviction was blocked by a PodDisruptionBudget"
+    );
+    describe_counter!(
+        QUOTA_DENIALS_COUNTER,
+        Unit::Count,
+        "The number of pods a controller couldn't create because of a ResourceQuota"
+    );
+    describe_counter!(
+        POLICY_DENIALS_COUNTER,
+        Unit::Count,
+        "The number of requests denied by PodSecurity, an admission policy or a policy engine's webhook, by policy"
+    );
+    describe_counter!(
+        RBAC_CHANGES_COUNTER,
+        Unit::Count,
+        "The number of Roles, RoleBindings, ClusterRoles, ClusterRoleBindings and service account tokens created, changed and deleted"
+    );
+    describe_counter!(
+        PREEMPTIONS_COUNTER,
+        Unit::Count,
+        "The number of pods preempted, by their priority class and the one of the pod they made room for"
+    );
+    describe_counter!(
+        WORKLOAD_PODS_CREATED_COUNTER,
+        Unit::Count,
+        "The number of pods of each workload created"
+    );
+    describe_counter!(
+        WORKLOAD_PODS_DELETED_COUNTER,
+        Unit::Count,
+        "The number of pods of each workload deleted, by cause"
+    );
+    describe_gauge!(
+        WORKLOAD_CHURN_GAUGE,
+        Unit::Count,
+        "The number of pods of each workload created and deleted in the last hour"
+    );
+    describe_gauge!(
+        WORKLOAD_VOLUNTARY_DELETIONS_GAUGE,
+        "The ratio of the pods of each workload deleted in the last hour that were rolled out or deleted, not evicted, preempted, OOM killed or lost with their node"
+    );
+    describe_gauge!(
+        PENDING_PODS_GAUGE,
+        Unit::Count,
+        "The number of pods not scheduled on a node yet"
+    );
+    describe_counter!(
+        SPOT_INTERRUPTIONS_COUNTER,
+        Unit::Count,
+        "The number of times a spot node was about to be taken back, by the taints put on it or its events"
+    );
+    describe_counter!(
+        NODE_SCALE_UPS_COUNTER,
+        Unit::Count,
+        "The number of unschedulable pods a node autoscaler said it's adding a node for"
+    );
+    describe_counter!(
+        NODE_SCALE_DOWNS_COUNTER,
+        Unit::Count,
+        "The number of nodes a node autoscaler took away"
+    );
+    describe_histogram!(
+        NODE_SCALE_UP_HISTOGRAM,
+        Unit::Seconds,
+        "How long the pods a node autoscaler added a node for took to be scheduled, since the first time they couldn't be"
+    );
+    describe_histogram!(
+        NODE_DRAIN_HISTOGRAM,
+        Unit::Seconds,
+        "How long the nodes took to be drained, from the first of their pods killed once cordoned to the last gone"
+    );
+    describe_counter!(
+        NODE_EVENTS_COUNTER,
+        Unit::Count,
+        "The number of events of the kubelet and the node controller about each node's lifecycle"
+    );
+    describe_gauge!(
+        NODE_FLAPPING_GAUGE,
+        "Whether the node's readiness changed more than node_events.flap_threshold times within node_events.flap_window"
+    );
+    describe_gauge!(
+        DISCOVERED_KINDS_GAUGE,
+        Unit::Count,
+        "The number of kinds of each api group of discovery.groups whose events are watched"
+    );
+    describe_counter!(
+        STALE_SERIES_COUNTER,
+        Unit::Count,
+        "The number of series of single pods removed, because the pod was gone or they weren't updated for a while"
+    );
+    describe_gauge!(
+        PROCESS_RESIDENT_MEMORY_GAUGE,
+        Unit::Bytes,
+        "The resident memory of the operator's process"
+    );
+    describe_gauge!(
+        PROCESS_VIRTUAL_MEMORY_GAUGE,
+        Unit::Bytes,
+        "The virtual memory of the operator's process"
+    );
+    describe_gauge!(
+        PROCESS_THREADS_GAUGE,
+        Unit::Count,
+        "The number of threads of the operator's process"
+    );
+    describe_gauge!(
+        PROCESS_START_TIME_GAUGE,
+        Unit::Seconds,
+        "When the operator's process started, in seconds since the epoch"
+    );
+    describe_gauge!(
+        PROCESS_OPEN_FDS_GAUGE,
+        Unit::Count,
+        "The number of file descriptors the operator's process has open"
+    );
+    describe_gauge!(
+        PROCESS_MAX_FDS_GAUGE,
+        Unit::Count,
+        "The most file descriptors the operator's process can have open"
+    );
+    describe_gauge!(
+        TOKIO_WORKERS_GAUGE,
+        Unit::Count,
+        "The number of worker threads of the tokio runtime"
+    );
+    describe_gauge!(
+        TOKIO_ALIVE_TASKS_GAUGE,
+        Unit::Count,
+        "The number of tasks alive in the tokio runtime"
+    );
+    describe_gauge!(
+        TOKIO_GLOBAL_QUEUE_GAUGE,
+        Unit::Count,
+        "The number of tasks waiting in the tokio runtime's global queue"
+    );
+    describe_gauge!(
+        JEMALLOC_BYTES_GAUGE,
+        Unit::Bytes,
+        "What jemalloc holds, by state (allocated, active, resident, mapped, retained, metadata), with the jemalloc feature"
+    );
+    describe_counter!(
+        MEMORY_EVICTIONS_COUNTER,
+        Unit::Count,
+        "The number of entries an in-memory store dropped to stay within its memory limit, by store"
+    );
+    describe_counter!(
+        RATE_LIMITED_COUNTER,
+        Unit::Count,
+        "The number of events over pipeline.per_object's limit, neither counted elsewhere nor delivered"
+    );
+    describe_gauge!(
+        INITIAL_SYNC_GAUGE,
+        Unit::Count,
+        "1 until the pods are listed for the first time, the gauges being the last run's meanwhile"
+    );
+    describe_gauge!(
+        BUILD_INFO_GAUGE,
+        Unit::Count,
+        "Always 1, with the version, commit, compiler and tls backend of the running build"
+    );
+    describe_gauge!(
+        FEATURE_ENABLED_GAUGE,
+        Unit::Count,
+        "Whether each feature flag is on (1) or off (0)"
+    );
+    describe_gauge!(
+        STARTUP_WAIT_GAUGE,
+        Unit::Seconds,
+        "How long the operator waited for the api server to answer when starting"
+    );
+    describe_counter!(
+        PROCESS_RESTARTS_COUNTER,
+        Unit::Count,
+        "The number of times the operator started again with the counters of its previous run"
+    );
+    describe_counter!(
+        UPTIME_COUNTER,
+        Unit::Seconds,
+        "The seconds the operator has been up, moving every 10s while its runtime isn't stalled"
+    );
+    describe_gauge!(
+        LABEL_DETAIL_GAUGE,
+        "How the per pod series are aggregated to get through an event storm: 0 by pod, 1 by workload, 2 by namespace"
+    );
+    describe_gauge!(
+        WATCHER_PAUSED_GAUGE,
+        "Whether each watcher was paused through the admin endpoints"
+    );
+    describe_gauge!(
+        SLO_EVENTS_GAUGE,
+        Unit::Count,
+        "The number of events counted against each objective within its period"
+    );
+    describe_gauge!(
+        SLO_BUDGET_REMAINING_GAUGE,
+        "The share of each objective's error budget left within its period, negative once blown"
+    );
+    describe_gauge!(
+        SLO_BURN_RATE_GAUGE,
+        "How fast each objective's error budget is spent within a window, 1 spending all of it over the period"
+    );
+}
+
+pub fn extract_label_values_from_event(
+    ev: &Event,
+    (node, zone): (String, String),
+    pod_labels: Vec<(String, String)>,
+) -> EventLabels {
+    let object_id = ev
+        .involved_object
+        .uid
+        .as_ref()
+        .map_or("".to_string(), |val| val.clone());
+    // the older events only have a source, the newer ones only a reporting controller
+    let reporter = ev
+        .source
+        .as_ref()
+        .and_then(|source| source.component.clone())
+        .or_else(|| ev.reporting_component.clone())
+        .unwrap_or_default();
+
+    EventLabels {
+        object_id,
+        node,
+        zone,
+        reporter,
+        pod_labels,
+    }
+}
@@ -0,0 +1,317 @@
+use crate::{
+    admin::{remove_filters, set_filters},
+    config::WatcherSettings,
+    enrich::Enricher,
+    error::Error,
+    logging::event_span,
+    metrics::{KIND_LABEL, MONITOR_LABEL, NAMESPACE_LABEL, REASON_LABEL, WATCHER_LABEL},
+    record::EventRecord,
+    registry::{MONITOR_EVENTS, WATCHER_BACKOFFS},
+    sinks::{Dispatcher, SinkRegistry},
+    snapshot::SNAPSHOT,
+    watch::{pausable, watcher_config, WatcherBackoff, PAUSES},
+};
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::Event;
+use kube::{
+    runtime::{
+        watcher::{self, watcher},
+        WatchStreamExt,
+    },
+    Api, Client, CustomResource, ResourceExt,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+use tokio::task::JoinHandle;
+use tracing::{info, warn, Instrument};
+
+// an EventMonitor declares a set of events to watch in its namespace
+// and the sinks they should be delivered to.
+// The `///` docs end up as descriptions in the CRD schema.
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+#[kube(
+    group = "k8rs.io",
+    version = "v1alpha1",
+    kind = "EventMonitor",
+    namespaced,
+    status = "EventMonitorStatus",
+    shortname = "evm",
+    printcolumn = r#"{"name":"Active", "type":"boolean", "jsonPath":".status.active"}"#,
+    printcolumn = r#"{"name":"Message", "type":"string", "jsonPath":".status.message"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct EventMonitorSpec {
+    /// The kinds of the objects the events are about, like "Pod". Everything when empty
+    #[serde(default)]
+    pub kinds: Vec<String>,
+    /// The event reasons, like "BackOff". Everything when empty
+    #[serde(default)]
+    pub reasons: Vec<String>,
+    /// "Normal" and/or "Warning". Both when empty
+    #[serde(default)]
+    pub types: Vec<String>,
+    /// A field selector for the events, like "involvedObject.name=my-pod"
+    pub field_selector: Option<String>,
+    /// The names of the sinks (from the operator's config) to deliver the events to
+    #[serde(default)]
+    pub sinks: Vec<String>,
+}
+
+// what the controller found out about an EventMonitor
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EventMonitorStatus {
+    /// Whether the monitor's pipeline is running
+    pub active: bool,
+    /// Why the pipeline isn't running, if it isn't
+    pub message: Option<String>,
+    /// The generation of the spec the status refers to
+    pub observed_generation: Option<i64>,
+}
+
+impl EventMonitorSpec {
+    pub fn matches(&self, event: &Event) -> bool {
+        let allowed = |allowed: &[String], value: &Option<String>| {
+            allowed.is_empty() || value.as_ref().is_some_and(|value| allowed.contains(value))
+        };
+        allowed(&self.kinds, &event.involved_object.kind)
+            && allowed(&self.reasons, &event.reason)
+            && allowed(&self.types, &event.type_)
+    }
+}
+
+// a pipeline started for an EventMonitor.
+// The watcher is stopped as soon as this is dropped.
+struct RunningMonitor {
+    generation: Option<i64>,
+    namespace: String,
+    spec: EventMonitorSpec,
+    handle: JoinHandle<()>,
+    // so we can still flush the sinks after stopping the pipeline
+    dispatcher: Dispatcher,
+}
+
+impl Drop for RunningMonitor {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+// keeps one pipeline running for each EventMonitor in the cluster.
+// It's driven by the EventMonitor controller.
+pub struct MonitorPipelines {
+    client: Client,
+    settings: WatcherSettings,
+    sinks: SinkRegistry,
+    enricher: Enricher,
+    running: Mutex<HashMap<String, RunningMonitor>>,
+}
+
+impl MonitorPipelines {
+    pub fn new(
+        client: Client,
+        settings: WatcherSettings,
+        sinks: SinkRegistry,
+        enricher: Enricher,
+    ) -> Self {
+        MonitorPipelines {
+            client,
+            settings,
+            sinks,
+            enricher,
+            running: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // makes sure the monitor's pipeline is running with its current spec,
+    // (re)starting it if needed. Fails if the monitor refers to sinks we don't have.
+    pub fn ensure(&self, monitor: &EventMonitor) -> Result<(), String> {
+        let key = monitor_key(monitor);
+        let generation = monitor.metadata.generation;
+        let mut running = self
+            .running
+            .lock()
+            .expect("monitor pipelines lock poisoned");
+        // status updates and such don't change the generation, so there's nothing to do
+        if running
+            .get(&key)
+            .is_some_and(|running| running.generation.is_some() && running.generation == generation)
+        {
+            return Ok(());
+        }
+
+        self.start(
+            &mut running,
+            key,
+            monitor.namespace().unwrap_or_default(),
+            monitor.spec.clone(),
+            generation,
+        )
+    }
+
+    // restarts the pipelines delivering to any of the given sinks,
+    // so they pick up the sinks of a reloaded config
+    pub fn restart_with_sinks(&self, sinks: &HashSet<&String>) {
+        let mut running = self
+            .running
+            .lock()
+            .expect("monitor pipelines lock poisoned");
+        let affected = running
+            .iter()
+            .filter(|(_, monitor)| monitor.spec.sinks.iter().any(|sink| sinks.contains(sink)))
+            .map(|(key, monitor)| {
+                (
+                    key.clone(),
+                    monitor.namespace.clone(),
+                    monitor.spec.clone(),
+                    monitor.generation,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        for (key, namespace, spec, generation) in affected {
+            if let Err(err) = self.start(&mut running, key.clone(), namespace, spec, generation) {
+                warn!("EventMonitor {} can't run after the reload: {}", key, err);
+            }
+        }
+    }
+
+    // (re)starts a monitor's pipeline, replacing the one that may be running
+    fn start(
+        &self,
+        running: &mut HashMap<String, RunningMonitor>,
+        key: String,
+        namespace: String,
+        spec: EventMonitorSpec,
+        generation: Option<i64>,
+    ) -> Result<(), String> {
+        let dispatcher = match self.sinks.dispatcher(&spec.sinks) {
+            Ok(dispatcher) => dispatcher,
+            Err(err) => {
+                if running.remove(&key).is_some() {
+                    info!("EventMonitor {} pipeline stopped", key);
+                }
+                remove_filters(&monitor_watcher_name(&key));
+                return Err(err);
+            }
+        };
+        set_filters(&monitor_watcher_name(&key), json!(spec));
+
+        let events: Api<Event> = Api::namespaced(self.client.clone(), &namespace);
+        let mut config = watcher_config(&self.settings);
+        config.field_selector = spec.field_selector.clone();
+
+        let handle = tokio::spawn(run_monitor(
+            key.clone(),
+            spec.clone(),
+            events,
+            config,
+            WatcherBackoff::new(monitor_watcher_name(&key), &self.settings.backoff),
+            dispatcher.clone(),
+            self.enricher.clone(),
+        ));
+        // replacing the old one stops it
+        if running
+            .insert(
+                key.clone(),
+                RunningMonitor {
+                    generation,
+                    namespace,
+                    spec,
+                    handle,
+                    dispatcher,
+                },
+            )
+            .is_some()
+        {
+            info!("EventMonitor {} changed, restarted its pipeline", key);
+        } else {
+            info!("EventMonitor {} pipeline started", key);
+        }
+        Ok(())
+    }
+
+    // stops the monitor's pipeline, flushes what it sent to its sinks
+    // and forgets its metric series
+    pub async fn stop(&self, key: &str) {
+        let stopped = self
+            .running
+            .lock()
+            .expect("monitor pipelines lock poisoned")
+            .remove(key);
+        if let Some(stopped) = stopped {
+            stopped.handle.abort();
+            stopped.dispatcher.flush().await;
+            info!("EventMonitor {} pipeline stopped", key);
+        }
+
+        MONITOR_EVENTS.remove(MONITOR_LABEL, key);
+        WATCHER_BACKOFFS.remove(WATCHER_LABEL, &monitor_watcher_name(key));
+        SNAPSHOT.remove_watcher(&monitor_watcher_name(key));
+        PAUSES.remove(&monitor_watcher_name(key));
+        remove_filters(&monitor_watcher_name(key));
+    }
+}
+
+// the name of the monitor's watcher on the watcher metrics
+fn monitor_watcher_name(key: &str) -> String {
+    format!("eventmonitor/{}", key)
+}
+
+pub fn monitor_key(monitor: &EventMonitor) -> String {
+    format!(
+        "{}/{}",
+        monitor.namespace().unwrap_or_default(),
+        monitor.name_any()
+    )
+}
+
+async fn run_monitor(
+    key: String,
+    spec: EventMonitorSpec,
+    events: Api<Event>,
+    config: watcher::Config,
+    backoff: WatcherBackoff,
+    dispatcher: Dispatcher,
+    enricher: Enricher,
+) {
+    let name = monitor_watcher_name(&key);
+    let mut stream = Box::pin(pausable(
+        &name,
+        watcher(events, config)
+            .backoff(backoff)
+            .inspect(|event| SNAPSHOT.watcher(&name, event)),
+    ));
+    while let Some(event) = stream.next().await {
+        match event {
+            // deletes are just events expiring, they didn't happen again
+            Ok(watcher::Event::Apply(event)) if spec.matches(&event) => {
+                let span = event_span(&event);
+                let record = EventRecord::new(&event, &enricher);
+                MONITOR_EVENTS.increment(
+                    &[
+                        (MONITOR_LABEL, key.clone()),
+                        (NAMESPACE_LABEL, record.namespace.clone()),
+                        (KIND_LABEL, record.kind.clone()),
+                        (REASON_LABEL, record.reason.clone()),
+                    ],
+                    1,
+                );
+                span.in_scope(|| {
+                    info!(
+                        "[{}] {} {}/{}: {}",
+                        key, record.reason, record.kind, record.object_name, record.message
+                    )
+                });
+                dispatcher.dispatch(&record).instrument(span).await;
+            }
+            Ok(_) => {}
+            Err(err) => Error::new("monitors", err).on(key.clone()).report(),
+        }
+    }
+}
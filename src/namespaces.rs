@@ -0,0 +1,445 @@
+use crate::{
+    clock,
+    config::{NamespaceSettings, WatcherSettings},
+    discovery::Kinds,
+    error::Error,
+    metrics::{
+        NAMESPACES_CREATED_COUNTER, NAMESPACES_DELETED_COUNTER, NAMESPACES_STUCK_GAUGE,
+        NAMESPACES_WATCHED_GAUGE, NAMESPACE_EVENTS_COUNTER, NAMESPACE_LABEL, REASON_LABEL,
+        WATCHER_LABEL,
+    },
+    pipeline::EventSender,
+    registry::{
+        NAMESPACE_WATCHER_AGE, NAMESPACE_WATCHER_ERRORS, NAMESPACE_WATCHER_HEALTHY,
+        NAMESPACE_WATCHER_RESTARTS, WATCHER_BACKOFFS,
+    },
+    sharding::Shard,
+    snapshot::SNAPSHOT,
+    watch::{pausable, watch_events, watcher_config, KubeEvents, WatcherBackoff, PAUSES},
+};
+use axum_prometheus::metrics::{counter, gauge};
+use futures::StreamExt;
+use k8s_openapi::{
+    api::core::v1::{Event, Namespace},
+    chrono::{DateTime, Utc},
+};
+use kube::{
+    runtime::{watcher, WatchStreamExt},
+    Api, Client, ResourceExt,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+// the events about namespaces, like the namespace controller failing to delete
+// what's left in one. Their involved object is the namespace itself.
+pub fn handle_event(event: &Event) {
+    counter!(
+        NAMESPACE_EVENTS_COUNTER,
+        &[
+            (
+                NAMESPACE_LABEL,
+                event.involved_object.name.clone().unwrap_or_default()
+            ),
+            (REASON_LABEL, event.reason.clone().unwrap_or_default()),
+        ]
+    )
+    .increment(1);
+}
+
+// watches the namespaces of the cluster, counting the ones created and deleted
+// and keeping track of the ones that take too long to terminate
+pub async fn watch_namespaces(
+    client: Client,
+    watcher_settings: &WatcherSettings,
+    settings: &NamespaceSettings,
+) {
+    let api: Api<Namespace> = Api::all(client);
+    let backoff = WatcherBackoff::new("namespaces", &watcher_settings.backoff);
+    let mut stream = Box::pin(pausable(
+        "namespaces",
+        watcher(api, watcher_config(watcher_settings))
+            .backoff(backoff)
+            .inspect(|event| SNAPSHOT.watcher("namespaces", event)),
+    ));
+    // stuck namespaces don't change, so they have to be checked now and then
+    let mut checks = tokio::time::interval(settings.check_interval);
+
+    // when each namespace started terminating, if it did
+    let mut namespaces = HashMap::<String, Option<DateTime<Utc>>>::new();
+    // the namespaces listed before we started don't count as created
+    let mut listed = false;
+    let mut relisted = HashSet::new();
+    // the stuck ones we already warned about
+    let mut reported = HashSet::new();
+    loop {
+        tokio::select! {
+            Some(event) = stream.next() => match event {
+                Ok(watcher::Event::Init) => relisted.clear(),
+                Ok(watcher::Event::InitApply(namespace)) => {
+                    relisted.insert(namespace.name_any());
+                    if !namespaces.contains_key(&namespace.name_any()) && listed {
+                        counter!(NAMESPACES_CREATED_COUNTER).increment(1);
+                    }
+                    namespaces.insert(namespace.name_any(), deletion(&namespace));
+                }
+                Ok(watcher::Event::InitDone) => {
+                    // the ones that weren't listed again were deleted meanwhile
+                    let before = namespaces.len();
+                    namespaces.retain(|name, _| relisted.contains(name));
+                    if listed {
+                        counter!(NAMESPACES_DELETED_COUNTER)
+                            .increment((before - namespaces.len()) as u64);
+                    }
+                    listed = true;
+                }
+                Ok(watcher::Event::Apply(namespace)) => {
+                    if namespaces
+                        .insert(namespace.name_any(), deletion(&namespace))
+                        .is_none()
+                    {
+                        counter!(NAMESPACES_CREATED_COUNTER).increment(1);
+                    }
+                }
+                Ok(watcher::Event::Delete(namespace)) => {
+                    if namespaces.remove(&namespace.name_any()).is_some() {
+                        counter!(NAMESPACES_DELETED_COUNTER).increment(1);
+                    }
+                }
+                Err(err) => Error::new("namespaces", err).report(),
+            },
+            _ = checks.tick() => {
+                let now = Utc::now();
+                let stuck = namespaces
+                    .iter()
+                    .filter_map(|(name, deletion)| {
+                        let since = (*deletion)?;
+                        (now - since).to_std().ok().filter(|terminating| {
+                            *terminating > settings.stuck_after
+                        })?;
+                        Some(name.clone())
+                    })
+                    .collect::<HashSet<_>>();
+                for name in stuck.difference(&reported) {
+                    warn!(
+                        "Namespace {} has been terminating for more than {}",
+                        name,
+                        humantime::format_duration(settings.stuck_after)
+                    );
+                }
+                gauge!(NAMESPACES_STUCK_GAUGE).set(stuck.len() as f64);
+                reported = stuck;
+            }
+        }
+    }
+}
+
+// which namespaces' events and pods go through, like everything but kube-*
+#[derive(Clone, Debug, Default)]
+pub struct NamespaceFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl NamespaceFilter {
+    pub fn new(settings: &NamespaceSettings) -> Self {
+        NamespaceFilter {
+            include: settings.include.clone(),
+            exclude: settings.exclude.clone(),
+        }
+    }
+
+    pub fn allows(&self, namespace: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|glob| matches(glob, namespace)))
+            && !self.exclude.iter().any(|glob| matches(glob, namespace))
+    }
+
+    // the events about cluster-wide objects always go through, but for the namespaces
+    // themselves, which are in their own namespace as far as this goes
+    pub fn allows_event(&self, event: &Event) -> bool {
+        let object = &event.involved_object;
+        let namespace = match object.kind.as_deref() {
+            Some("Namespace") => object.name.as_deref(),
+            _ => object.namespace.as_deref().filter(|ns| !ns.is_empty()),
+        };
+        namespace.is_none_or(|namespace| self.allows(namespace))
+    }
+}
+
+// a glob where * is any run of characters and ? any one of them
+fn matches(glob: &str, name: &str) -> bool {
+    let (glob, name) = (glob.as_bytes(), name.as_bytes());
+    let (mut g, mut n) = (0, 0);
+    // where the last * was, and where in the name it started matching
+    let mut star = None;
+    while n < name.len() {
+        match glob.get(g) {
+            Some(b'*') => {
+                star = Some((g, n));
+                g += 1;
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                g += 1;
+                n += 1;
+            }
+            // the last * takes one more character
+            _ => match star {
+                Some((star_g, star_n)) => {
+                    star = Some((star_g, star_n + 1));
+                    g = star_g + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|c| *c == b'*')
+}
+
+// how the events watcher of every discovered namespace is doing, from what its stream
+// hands out
+pub static WATCHERS: LazyLock<WatcherHealth> = LazyLock::new(WatcherHealth::default);
+
+#[derive(Default)]
+pub struct WatcherHealth {
+    namespaces: Mutex<HashMap<String, Health>>,
+}
+
+struct Health {
+    // the last event, or when the watcher started before there was one
+    last_event: Instant,
+    // the first error since the watch last handed out something
+    failing_since: Option<Instant>,
+}
+
+impl WatcherHealth {
+    pub fn observe<K>(&self, namespace: &str, event: &Result<watcher::Event<K>, watcher::Error>) {
+        let now = clock::instant();
+        let mut namespaces = self.lock();
+        let Some(health) = namespaces.get_mut(namespace) else {
+            return;
+        };
+        match event {
+            Ok(
+                watcher::Event::InitApply(_) | watcher::Event::Apply(_) | watcher::Event::Delete(_),
+            ) => {
+                health.last_event = now;
+                health.failing_since = None;
+            }
+            Ok(_) => health.failing_since = None,
+            Err(_) => {
+                health.failing_since.get_or_insert(now);
+                NAMESPACE_WATCHER_ERRORS.increment(&[(NAMESPACE_LABEL, namespace.to_string())], 1);
+            }
+        }
+    }
+
+    // a watcher started, or started again
+    fn started(&self, namespace: &str) {
+        let health = Health {
+            last_event: clock::instant(),
+            failing_since: None,
+        };
+        self.lock().insert(namespace.to_string(), health);
+    }
+
+    fn forget(&self, namespace: &str) {
+        self.lock().remove(namespace);
+        for family in [
+            &*NAMESPACE_WATCHER_AGE,
+            &*NAMESPACE_WATCHER_HEALTHY,
+            &*NAMESPACE_WATCHER_ERRORS,
+            &*NAMESPACE_WATCHER_RESTARTS,
+        ] {
+            family.remove(NAMESPACE_LABEL, namespace);
+        }
+    }
+
+    // sets the gauges, returning the namespaces whose watcher has been failing for
+    // restart_after
+    fn check(&self, restart_after: Option<Duration>) -> Vec<String> {
+        let now = clock::instant();
+        let mut unhealthy = Vec::new();
+        for (namespace, health) in self.lock().iter() {
+            let labels = [(NAMESPACE_LABEL, namespace.clone())];
+            let age = now.duration_since(health.last_event);
+            NAMESPACE_WATCHER_AGE.set(&labels, age.as_secs_f64());
+            NAMESPACE_WATCHER_HEALTHY.set(
+                &labels,
+                if health.failing_since.is_some() {
+                    0.0
+                } else {
+                    1.0
+                },
+            );
+            let failing = health.failing_since.map(|since| now.duration_since(since));
+            if let (Some(failing), Some(after)) = (failing, restart_after) {
+                if failing >= after {
+                    unhealthy.push(namespace.clone());
+                }
+            }
+        }
+        unhealthy
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Health>> {
+        self.namespaces
+            .lock()
+            .expect("watcher health lock poisoned")
+    }
+}
+
+// the events watcher of a discovered namespace, stopped when it's dropped
+struct Discovered {
+    handle: JoinHandle<()>,
+}
+
+impl Drop for Discovered {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+// watches the namespaces matching the selector, running an events watcher for each of
+// them into the pipeline. One that's unlabeled, or deleted, stops having its events
+// watched, the watch with a selector tells it like a delete. A watcher failing for
+// restart_after is started again, the others going on undisturbed. With a shard, only
+// its share of the namespaces is watched, the watchers moving as the replicas come and go.
+pub async fn discover(
+    client: Client,
+    watcher_settings: &WatcherSettings,
+    settings: &NamespaceSettings,
+    shard: Option<Shard>,
+    kinds: Kinds,
+    filter: NamespaceFilter,
+    sender: EventSender,
+) {
+    let api: Api<Namespace> = Api::all(client.clone());
+    let backoff = WatcherBackoff::new("namespace-discovery", &watcher_settings.backoff);
+    let selector = settings.selector.as_deref().unwrap_or_default();
+    let mut stream = Box::pin(pausable(
+        "namespace-discovery",
+        watcher(api, watcher_config(watcher_settings).labels(selector))
+            .backoff(backoff)
+            .inspect(|event| SNAPSHOT.watcher("namespace-discovery", event)),
+    ));
+
+    let mut watched = HashMap::<String, Discovered>::new();
+    // the ones matching, watched when they're this replica's share
+    let mut matching = HashSet::<String>::new();
+    let mut relisted = HashSet::new();
+    let owned = |namespace: &str| shard.as_ref().is_none_or(|shard| shard.owns(namespace));
+    let start = |watched: &mut HashMap<String, Discovered>, namespace: String| {
+        if watched.contains_key(&namespace) || !filter.allows(&namespace) || !owned(&namespace) {
+            return;
+        }
+        info!("Watching the events of namespace {}", namespace);
+        WATCHERS.started(&namespace);
+        let source = KubeEvents::namespaced(client.clone(), &namespace, watcher_settings);
+        let handle = tokio::spawn(watch_events(
+            source,
+            kinds.clone(),
+            filter.clone(),
+            sender.clone(),
+        ));
+        watched.insert(namespace, Discovered { handle });
+    };
+    let mut checks = tokio::time::interval(settings.check_interval);
+    let mut changes = shard.clone();
+    loop {
+        let event = tokio::select! {
+            event = stream.next() => match event {
+                Some(event) => event,
+                None => return,
+            },
+            _ = rebalanced(&mut changes) => {
+                let gone = watched
+                    .keys()
+                    .filter(|namespace| !owned(namespace))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for namespace in gone {
+                    forget(&mut watched, &namespace);
+                }
+                for namespace in matching.iter() {
+                    start(&mut watched, namespace.clone());
+                }
+                gauge!(NAMESPACES_WATCHED_GAUGE).set(watched.len() as f64);
+                continue;
+            }
+            _ = checks.tick() => {
+                for namespace in WATCHERS.check(settings.restart_after) {
+                    warn!("The events watcher of namespace {} keeps failing, restarting it", namespace);
+                    watched.remove(&namespace);
+                    NAMESPACE_WATCHER_RESTARTS
+                        .increment(&[(NAMESPACE_LABEL, namespace.clone())], 1);
+                    start(&mut watched, namespace);
+                }
+                continue;
+            }
+        };
+        match event {
+            Ok(watcher::Event::Init) => relisted.clear(),
+            Ok(watcher::Event::InitApply(namespace)) => {
+                relisted.insert(namespace.name_any());
+                start(&mut watched, namespace.name_any());
+            }
+            Ok(watcher::Event::InitDone) => {
+                matching.clone_from(&relisted);
+                // the ones that weren't listed again stopped matching meanwhile
+                let gone = watched
+                    .keys()
+                    .filter(|namespace| !relisted.contains(*namespace))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for namespace in gone {
+                    forget(&mut watched, &namespace);
+                }
+            }
+            Ok(watcher::Event::Apply(namespace)) => {
+                matching.insert(namespace.name_any());
+                start(&mut watched, namespace.name_any());
+            }
+            Ok(watcher::Event::Delete(namespace)) => {
+                matching.remove(&namespace.name_any());
+                forget(&mut watched, &namespace.name_any());
+            }
+            Err(err) => Error::new("namespace watchers", err).report(),
+        }
+        gauge!(NAMESPACES_WATCHED_GAUGE).set(watched.len() as f64);
+    }
+}
+
+// waits for the replicas sharing the namespaces to change, never without sharding
+async fn rebalanced(shard: &mut Option<Shard>) {
+    if let Some(shard) = shard {
+        if shard.changed().await {
+            return;
+        }
+    }
+    std::future::pending().await
+}
+
+fn forget(watched: &mut HashMap<String, Discovered>, namespace: &str) {
+    if watched.remove(namespace).is_none() {
+        return;
+    }
+    info!("Not watching the events of namespace {} anymore", namespace);
+    WATCHERS.forget(namespace);
+    let name = format!("events/{}", namespace);
+    WATCHER_BACKOFFS.remove(WATCHER_LABEL, &name);
+    SNAPSHOT.remove_watcher(&name);
+    PAUSES.remove(&name);
+}
+
+fn deletion(namespace: &Namespace) -> Option<DateTime<Utc>> {
+    namespace
+        .metadata
+        .deletion_timestamp
+        .as_ref()
+        .map(|time| time.0)
+}
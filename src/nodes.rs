@@ -0,0 +1,160 @@
+use crate::{
+    drains::Drains,
+    metrics::{
+        CONDITION_LABEL, NODE_ALLOCATABLE_GAUGE, NODE_CAPACITY_GAUGE, NODE_CONDITION_GAUGE,
+        NODE_LABEL, RESOURCE_LABEL,
+    },
+    resources::quantity,
+    spot::Interruptions,
+};
+use axum_prometheus::metrics::gauge;
+use k8s_openapi::{api::core::v1::Node, apimachinery::pkg::api::resource::Quantity};
+use kube::{runtime::watcher, ResourceExt};
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+// what's exported of the nodes' capacity and allocatable, cpu in cores and memory in bytes
+const RESOURCES: [&str; 3] = ["cpu", "memory", "pods"];
+
+// the conditions telling why pods can't be scheduled on a node
+const CONDITIONS: [&str; 3] = ["Ready", "DiskPressure", "MemoryPressure"];
+
+// follows the nodes of the node cache, exporting what they can run
+// so the scheduling failures of the pipeline can be told apart
+#[derive(Default)]
+pub struct NodeTracker {
+    state: Mutex<NodeState>,
+    // the spot interruptions and the drains, when they're looked for
+    spot: Option<Arc<Interruptions>>,
+    drains: Option<Arc<Drains>>,
+}
+
+#[derive(Default)]
+struct NodeState {
+    nodes: HashSet<String>,
+    // the nodes seen since the watcher started (re-)listing
+    relisted: HashSet<String>,
+}
+
+impl NodeTracker {
+    pub fn new(spot: Option<Arc<Interruptions>>, drains: Option<Arc<Drains>>) -> Self {
+        NodeTracker {
+            spot,
+            drains,
+            ..NodeTracker::default()
+        }
+    }
+
+    // called with every event of the node watcher, before it reaches the cache
+    pub fn observe(&self, event: &watcher::Event<Node>) {
+        let mut state = self.state.lock().expect("node tracker lock poisoned");
+        match event {
+            watcher::Event::Init => state.relisted.clear(),
+            watcher::Event::InitApply(node) => {
+                state.relisted.insert(node.name_any());
+                state.nodes.insert(node.name_any());
+                export(node);
+                self.track(node);
+            }
+            watcher::Event::InitDone => {
+                // what wasn't listed again is gone, we missed its delete while away
+                let relisted = std::mem::take(&mut state.relisted);
+                for name in state.nodes.difference(&relisted) {
+                    forget(name);
+                    self.gone(name);
+                }
+                state.nodes = relisted;
+            }
+            watcher::Event::Apply(node) => {
+                state.nodes.insert(node.name_any());
+                export(node);
+                self.track(node);
+            }
+            watcher::Event::Delete(node) => {
+                if state.nodes.remove(&node.name_any()) {
+                    forget(&node.name_any());
+                    self.gone(&node.name_any());
+                }
+            }
+        }
+    }
+
+    fn track(&self, node: &Node) {
+        if let Some(spot) = self.spot.as_ref() {
+            spot.node(node);
+        }
+        if let Some(drains) = self.drains.as_ref() {
+            drains.node(node);
+        }
+    }
+
+    fn gone(&self, name: &str) {
+        if let Some(spot) = self.spot.as_ref() {
+            spot.gone(name);
+        }
+        if let Some(drains) = self.drains.as_ref() {
+            drains.gone(name);
+        }
+    }
+}
+
+fn export(node: &Node) {
+    let name = node.name_any();
+    let status = node.status.as_ref();
+    let resources = |of: Option<&BTreeMap<String, Quantity>>| {
+        RESOURCES.map(|resource| {
+            of.and_then(|values| values.get(resource))
+                .map(quantity)
+                .unwrap_or(0.0)
+        })
+    };
+    let capacity = resources(status.and_then(|status| status.capacity.as_ref()));
+    let allocatable = resources(status.and_then(|status| status.allocatable.as_ref()));
+    for (i, resource) in RESOURCES.iter().enumerate() {
+        let labels = [
+            (NODE_LABEL, name.clone()),
+            (RESOURCE_LABEL, resource.to_string()),
+        ];
+        gauge!(NODE_CAPACITY_GAUGE, &labels).set(capacity[i]);
+        gauge!(NODE_ALLOCATABLE_GAUGE, &labels).set(allocatable[i]);
+    }
+
+    let conditions = status.and_then(|status| status.conditions.as_ref());
+    for condition in CONDITIONS {
+        let on = conditions
+            .and_then(|conditions| conditions.iter().find(|known| known.type_ == condition))
+            .is_some_and(|known| known.status == "True");
+        gauge!(
+            NODE_CONDITION_GAUGE,
+            &[
+                (NODE_LABEL, name.clone()),
+                (CONDITION_LABEL, condition.to_string())
+            ]
+        )
+        .set(if on { 1.0 } else { 0.0 });
+    }
+}
+
+// a deleted node can't run anything anymore
+fn forget(name: &str) {
+    for resource in RESOURCES {
+        let labels = [
+            (NODE_LABEL, name.to_string()),
+            (RESOURCE_LABEL, resource.to_string()),
+        ];
+        gauge!(NODE_CAPACITY_GAUGE, &labels).set(0.0);
+        gauge!(NODE_ALLOCATABLE_GAUGE, &labels).set(0.0);
+    }
+    for condition in CONDITIONS {
+        gauge!(
+            NODE_CONDITION_GAUGE,
+            &[
+                (NODE_LABEL, name.to_string()),
+                (CONDITION_LABEL, condition.to_string())
+            ]
+        )
+        .set(0.0);
+    }
+}
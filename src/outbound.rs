@@ -0,0 +1,62 @@
+use crate::{
+    config::OutboundSettings,
+    error::{Context, ErrorKind},
+    tls,
+};
+use reqwest::{Certificate, ClientBuilder, NoProxy, Proxy};
+use std::sync::{LazyLock, RwLock};
+use tracing::warn;
+
+// what the http clients of the sinks, the alerts and the plugin registries are made
+// with, set once when starting
+static OUTBOUND: LazyLock<RwLock<Outbound>> = LazyLock::new(Default::default);
+
+#[derive(Default)]
+struct Outbound {
+    proxy: Option<Proxy>,
+    roots: Vec<Certificate>,
+    insecure_skip_verify: bool,
+}
+
+// reads the CA bundle, the proxy url was already checked when loading the config
+pub fn configure(settings: &OutboundSettings) -> Result<(), ErrorKind> {
+    let proxy = match settings.proxy {
+        Some(ref url) => {
+            Some(Proxy::all(url)?.no_proxy(NoProxy::from_string(&settings.no_proxy.join(","))))
+        }
+        None => None,
+    };
+    let roots = match settings.ca_bundle {
+        Some(ref path) => {
+            let pem = std::fs::read(path)
+                .with_context(|| format!("could not read the CA bundle {:?}", path))?;
+            Certificate::from_pem_bundle(&pem)
+                .with_context(|| format!("could not parse the CA bundle {:?}", path))?
+        }
+        None => Vec::new(),
+    };
+    if settings.insecure_skip_verify {
+        warn!("The certificates of the outbound https requests aren't verified");
+    }
+    *OUTBOUND.write().expect("outbound settings lock poisoned") = Outbound {
+        proxy,
+        roots,
+        insecure_skip_verify: settings.insecure_skip_verify,
+    };
+    Ok(())
+}
+
+// a builder of a client going through the proxy and trusting the CA bundle, on the tls
+// backend built. Without a proxy in the config reqwest's own one of HTTPS_PROXY and
+// NO_PROXY is used.
+pub fn client() -> ClientBuilder {
+    let outbound = OUTBOUND.read().expect("outbound settings lock poisoned");
+    let mut builder = tls::http(reqwest::Client::builder());
+    if let Some(ref proxy) = outbound.proxy {
+        builder = builder.proxy(proxy.clone());
+    }
+    for root in outbound.roots.iter() {
+        builder = builder.add_root_certificate(root.clone());
+    }
+    builder.danger_accept_invalid_certs(outbound.insecure_skip_verify)
+}
@@ -0,0 +1,142 @@
+use crate::{
+    config::PayloadSettings,
+    metrics::{FIELD_LABEL, PAYLOAD_TRUNCATIONS_COUNTER},
+    record::EventRecord,
+    schema::PodDescription,
+};
+use axum_prometheus::metrics::counter;
+use std::{
+    borrow::Cow,
+    sync::{LazyLock, RwLock},
+};
+
+// how big the free text of the records handed to the sinks can get: a multi-megabyte
+// event message would have a webhook or a broker refuse it, along with the rest of its
+// batch. Only what the sinks get is cut, the metrics, scripts and snapshot see it all.
+static LIMITS: LazyLock<RwLock<PayloadSettings>> = LazyLock::new(Default::default);
+
+pub fn configure(settings: &PayloadSettings) {
+    *LIMITS.write().expect("payload limits lock poisoned") = settings.clone();
+}
+
+// the record as the sinks get it, only copied when something has to be cut
+pub fn limited(record: &EventRecord) -> Cow<'_, EventRecord> {
+    let limits = LIMITS.read().expect("payload limits lock poisoned").clone();
+    let over = |text: &str, limit: Option<usize>| limit.is_some_and(|limit| text.len() > limit);
+    let message = over(&record.message, limits.max_message_bytes)
+        || record
+            .summary
+            .as_deref()
+            .is_some_and(|summary| over(summary, limits.max_message_bytes));
+    let logs = record
+        .logs
+        .as_deref()
+        .is_some_and(|logs| over(logs, limits.max_logs_bytes));
+    let context = record.context.as_ref().is_some_and(|context| {
+        limits
+            .max_context_bytes
+            .is_some_and(|limit| size(context) > limit)
+    });
+    if !message && !logs && !context {
+        return Cow::Borrowed(record);
+    }
+    let mut record = record.clone();
+    if let Some(limit) = limits.max_message_bytes {
+        truncate(&mut record.message, limit, "message");
+        if let Some(ref mut summary) = record.summary {
+            truncate(summary, limit, "summary");
+        }
+    }
+    if let (Some(limit), Some(ref mut logs)) = (limits.max_logs_bytes, &mut record.logs) {
+        truncate_start(logs, limit);
+    }
+    if let (Some(limit), Some(ref mut context)) = (limits.max_context_bytes, &mut record.context) {
+        truncate_context(context, limit);
+    }
+    Cow::Owned(record)
+}
+
+// the end of the text is cut, the marker saying how long it was takes its place
+fn truncate(text: &mut String, limit: usize, field: &'static str) {
+    if text.len() <= limit {
+        return;
+    }
+    let marker = format!("... [truncated from {} bytes]", text.len());
+    let mut keep = limit.saturating_sub(marker.len());
+    while !text.is_char_boundary(keep) {
+        keep -= 1;
+    }
+    text.truncate(keep);
+    text.push_str(&marker);
+    truncated(field);
+}
+
+// the logs lose their first lines, the last ones tell why the container stopped
+fn truncate_start(text: &mut String, limit: usize) {
+    if text.len() <= limit {
+        return;
+    }
+    let marker = format!("[truncated from {} bytes] ...\n", text.len());
+    let mut start = text.len() - limit.saturating_sub(marker.len()).min(text.len());
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    // from the start of a line, when there's one in what's kept
+    if let Some(line) = text[start..].find('\n') {
+        if start + line + 1 < text.len() {
+            start += line + 1;
+        }
+    }
+    text.replace_range(..start, &marker);
+    truncated("logs");
+}
+
+// the lines of the description, the events going first and the conditions last, with a
+// line saying how many of them are gone
+fn truncate_context(context: &mut PodDescription, limit: usize) {
+    if size(context) <= limit {
+        return;
+    }
+    let mut budget = limit;
+    for lines in [
+        &mut context.conditions,
+        &mut context.containers,
+        &mut context.recent_events,
+    ] {
+        let kept = lines
+            .iter()
+            .take_while(|line| {
+                let fits = line.len() <= budget;
+                if fits {
+                    budget -= line.len();
+                }
+                fits
+            })
+            .count();
+        if kept < lines.len() {
+            let gone = lines.len() - kept;
+            lines.truncate(kept);
+            lines.push(format!("... {} more truncated", gone));
+            budget = 0;
+        }
+    }
+    truncated("context");
+}
+
+fn size(context: &PodDescription) -> usize {
+    context
+        .conditions
+        .iter()
+        .chain(context.containers.iter())
+        .chain(context.recent_events.iter())
+        .map(String::len)
+        .sum()
+}
+
+fn truncated(field: &'static str) {
+    counter!(
+        PAYLOAD_TRUNCATIONS_COUNTER,
+        &[(FIELD_LABEL, field.to_string())]
+    )
+    .increment(1);
+}
@@ -0,0 +1,354 @@
+use crate::{
+    config::CounterPersistenceSettings,
+    error::{Context, ErrorKind},
+    lifecycle::SavedLifecycle,
+    metrics::{
+        naming, restore_counter, BUILD_INFO_GAUGE, INITIAL_SYNC_GAUGE, PROCESS_RESTARTS_COUNTER,
+        UPTIME_COUNTER,
+    },
+    pods::PodTracker,
+    registry,
+    rollups::{SavedRollups, ROLLUPS},
+};
+use axum_prometheus::{
+    metrics::{counter, gauge, Key, Label},
+    metrics_exporter_prometheus::PrometheusHandle,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Write,
+    path::Path,
+    sync::{Arc, LazyLock, Mutex},
+};
+use tracing::{info, warn};
+
+// the counters of /metrics, by their series like `events_total{type="Normal"}`
+type Counters = BTreeMap<String, u64>;
+// and the gauges, like `pending_pods{namespace="shop",reason="Unschedulable"}`
+type Gauges = BTreeMap<String, f64>;
+
+// what's saved of a run for the next one
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct Saved {
+    counters: Counters,
+    // the pods the lifecycle followed, for the first list to be compared to
+    lifecycle: Option<SavedLifecycle>,
+    // the hours and days counted, when the rollups are on
+    rollups: Option<SavedRollups>,
+    // the gauges, with cold_start on
+    gauges: Gauges,
+}
+
+// puts the counters saved by the previous run back, before anything is counted, and
+// counts the restart. With cold_start its gauges are served until the pods are
+// listed. Returns what the lifecycle knew of the pods then. Nothing to restore the
+// first time, or when the file can't be read.
+pub fn restore(settings: &CounterPersistenceSettings) -> Option<SavedLifecycle> {
+    counter!(PROCESS_RESTARTS_COUNTER).absolute(0);
+    let path = settings.path.as_ref()?;
+    let saved = match load(path) {
+        Ok(saved) => saved?,
+        Err(err) => {
+            warn!("Not restoring the counters: {}", err);
+            return None;
+        }
+    };
+    let mut restored = 0;
+    for (series, value) in saved.counters {
+        match parse(&series) {
+            Some(key) => {
+                restore_counter(&key, value);
+                restored += 1;
+            }
+            None => warn!("Not restoring the invalid series {:?}", series),
+        }
+    }
+    if let Some(rollups) = saved.rollups {
+        ROLLUPS.restore(rollups);
+    }
+    if settings.cold_start && !saved.gauges.is_empty() {
+        info!(
+            "Serving {} gauges of the last run until the pods are listed",
+            saved.gauges.len()
+        );
+        INITIAL_SYNC.restore(saved.gauges);
+    }
+    // restored along with the others
+    counter!(PROCESS_RESTARTS_COUNTER).increment(1);
+    info!("Restored {} counters from {:?}", restored, path);
+    saved.lifecycle
+}
+
+// keeps the counters' values in a file, on a volume that outlives the pod, so they
+// carry on from where they were after a restart instead of starting over from 0. So
+// does what the lifecycle knows of the pods, for the pods that changed meanwhile to
+// be counted.
+pub struct Persistence {
+    settings: CounterPersistenceSettings,
+    handle: PrometheusHandle,
+    tracker: Arc<PodTracker>,
+}
+
+impl Persistence {
+    pub fn new(
+        settings: &CounterPersistenceSettings,
+        handle: PrometheusHandle,
+        tracker: Arc<PodTracker>,
+    ) -> Self {
+        Persistence {
+            settings: settings.clone(),
+            handle,
+            tracker,
+        }
+    }
+
+    // saves everything every interval, until the kill signal
+    pub async fn run(self: Arc<Self>) {
+        if self.settings.path.is_none() {
+            return;
+        }
+        let mut interval = tokio::time::interval(self.settings.interval);
+        // the first tick is right away
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            self.save();
+        }
+    }
+
+    pub fn save(&self) {
+        let Some(ref path) = self.settings.path else {
+            return;
+        };
+        let rendered = self.handle.render();
+        let gauges = match self.settings.cold_start {
+            // our registry's too, and the ones of the last run still served
+            true => {
+                let current = rendered.clone() + registry::render().as_str();
+                gauges(&(INITIAL_SYNC.render(&current) + current.as_str()))
+            }
+            false => Gauges::new(),
+        };
+        let saved = Saved {
+            counters: counters(&rendered),
+            lifecycle: Some(self.tracker.save_lifecycle()),
+            rollups: ROLLUPS.enabled().then(|| ROLLUPS.save()),
+            gauges,
+        };
+        let written = serde_json::to_string(&saved)
+            .map_err(ErrorKind::from)
+            .and_then(|contents| {
+                // written next to it then renamed, so a crash never leaves half a file
+                let partial = path.with_extension("partial");
+                std::fs::write(&partial, contents)?;
+                std::fs::rename(&partial, path)?;
+                Ok(())
+            });
+        if let Err(err) = written {
+            warn!("Could not save the counters to {:?}: {}", path, err);
+        }
+    }
+}
+
+fn load(path: &Path) -> Result<Option<Saved>, ErrorKind> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .map(Some)
+            .with_context(|| format!("invalid {:?}", path)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(ErrorKind::from(err).context(format!("could not read {:?}", path))),
+    }
+}
+
+// the series of the counter families of a rendered /metrics. The uptime is the
+// current run's, it isn't carried over.
+fn counters(metrics: &str) -> Counters {
+    let uptime = naming().metric(UPTIME_COUNTER);
+    series(metrics, "counter", &[&*uptime])
+        .into_iter()
+        .map(|(series, value)| (series, value as u64))
+        .collect()
+}
+
+// the same for the gauges, but for the ones telling about the current run
+fn gauges(metrics: &str) -> Gauges {
+    let build = naming().metric(BUILD_INFO_GAUGE);
+    let syncing = naming().metric(INITIAL_SYNC_GAUGE);
+    series(metrics, "gauge", &[&*build, &*syncing])
+}
+
+fn series(metrics: &str, type_: &str, excluded: &[&str]) -> BTreeMap<String, f64> {
+    let mut found = BTreeMap::new();
+    let mut family = None;
+    for line in metrics.lines() {
+        if let Some(rest) = line.strip_prefix("# TYPE ") {
+            family = match rest.split_once(' ') {
+                Some((name, kind)) if kind == type_ && !excluded.contains(&name) => Some(name),
+                _ => None,
+            };
+            continue;
+        }
+        let Some(family) = family else {
+            continue;
+        };
+        if line.starts_with('#') || !line.starts_with(family) {
+            continue;
+        }
+        if let Some((name, value)) = line.rsplit_once(' ') {
+            if let Ok(value) = value.parse::<f64>() {
+                found.insert(name.to_string(), value);
+            }
+        }
+    }
+    found
+}
+
+// the gauges of the last run, served while the pods are listed for the first time so
+// a deploy doesn't look like everything dropped to zero. The families the current run
+// has already are its own, and once the list is done only its own are left.
+// k8rs_initial_sync_in_progress is 1 from when the pod watcher starts until then.
+pub static INITIAL_SYNC: LazyLock<InitialSync> = LazyLock::new(InitialSync::default);
+
+#[derive(Default)]
+pub struct InitialSync {
+    restored: Mutex<Option<Gauges>>,
+}
+
+impl InitialSync {
+    fn restore(&self, gauges: Gauges) {
+        *self.restored.lock().expect("initial sync lock poisoned") = Some(gauges);
+    }
+
+    // the pod watcher is starting, without one there's no list to wait for
+    pub fn started(&self) {
+        gauge!(INITIAL_SYNC_GAUGE).set(1.0);
+    }
+
+    // the pods were listed, what's on /metrics is up to date from now on
+    pub fn done(&self) {
+        gauge!(INITIAL_SYNC_GAUGE).set(0.0);
+        let restored = self
+            .restored
+            .lock()
+            .expect("initial sync lock poisoned")
+            .take();
+        if restored.is_some() {
+            info!("Done with the initial list, no longer serving the gauges of the last run");
+        }
+    }
+
+    // the restored series of the families the rendered metrics don't have, as /metrics
+    // has them
+    pub fn render(&self, metrics: &str) -> String {
+        let restored = self.restored.lock().expect("initial sync lock poisoned");
+        let Some(ref gauges) = *restored else {
+            return String::new();
+        };
+        let current = metrics
+            .lines()
+            .filter_map(|line| line.strip_prefix("# TYPE "))
+            .filter_map(|rest| rest.split_once(' ').map(|(name, _)| name))
+            .collect::<HashSet<_>>();
+        let mut out = String::new();
+        let mut last = None;
+        for (series, value) in gauges.iter() {
+            let family = series
+                .split_once('{')
+                .map_or(series.as_str(), |(name, _)| name);
+            if current.contains(family) {
+                continue;
+            }
+            if last != Some(family) {
+                let _ = writeln!(out, "# TYPE {} gauge", family);
+                last = Some(family);
+            }
+            let _ = writeln!(out, "{} {}", series, value);
+        }
+        out
+    }
+}
+
+// the key of a series like `events_total{type="Normal",kind="Pod"}`, its label values
+// escaped as prometheus does
+fn parse(series: &str) -> Option<Key> {
+    let Some((name, labels)) = series.split_once('{') else {
+        return Some(Key::from_name(series.to_string()));
+    };
+    let mut rest = labels.strip_suffix('}')?;
+    let mut parsed = Vec::new();
+    while !rest.is_empty() {
+        let (label, value) = rest.split_once("=\"")?;
+        let mut unescaped = String::new();
+        let mut chars = value.char_indices();
+        let end = loop {
+            match chars.next()? {
+                (_, '\\') => match chars.next()?.1 {
+                    'n' => unescaped.push('\n'),
+                    c => unescaped.push(c),
+                },
+                (at, '"') => break at,
+                (_, c) => unescaped.push(c),
+            }
+        };
+        parsed.push(Label::new(label.to_string(), unescaped));
+        rest = &value[end + 1..];
+        rest = rest.strip_prefix(',').unwrap_or(rest);
+    }
+    Some(Key::from_parts(name.to_string(), parsed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{gauges, Gauges, InitialSync};
+    use crate::metrics::test_recorder;
+
+    #[test]
+    fn saves_the_gauges_but_the_ones_of_the_run() {
+        test_recorder();
+        let metrics = "\
+# TYPE events_total counter
+events_total{type=\"Normal\"} 4
+# TYPE pending_pods gauge
+pending_pods{namespace=\"shop\"} 2
+# TYPE k8rs_initial_sync_in_progress gauge
+k8rs_initial_sync_in_progress 1
+";
+        assert_eq!(
+            gauges(metrics),
+            Gauges::from([("pending_pods{namespace=\"shop\"}".to_string(), 2.0)])
+        );
+    }
+
+    // a family the current run has is all its own, even with fewer series
+    #[test]
+    fn serves_the_restored_families_the_run_does_not_have() {
+        let sync = InitialSync::default();
+        sync.restore(Gauges::from([
+            ("pending_pods{namespace=\"shop\"}".to_string(), 3.0),
+            ("pending_pods{namespace=\"web\"}".to_string(), 1.0),
+            ("node_allocatable_pods{node=\"a\"}".to_string(), 110.0),
+        ]));
+        let current = "\
+# TYPE pending_pods gauge
+pending_pods{namespace=\"shop\"} 0
+";
+        assert_eq!(
+            sync.render(current),
+            "# TYPE node_allocatable_pods gauge\nnode_allocatable_pods{node=\"a\"} 110\n"
+        );
+        assert_eq!(
+            sync.render(""),
+            "\
+# TYPE node_allocatable_pods gauge
+node_allocatable_pods{node=\"a\"} 110
+# TYPE pending_pods gauge
+pending_pods{namespace=\"shop\"} 3
+pending_pods{namespace=\"web\"} 1
+"
+        );
+        sync.done();
+        assert_eq!(sync.render(""), "");
+    }
+}
@@ -0,0 +1,679 @@
+use crate::{
+    aggregate::Aggregator,
+    alerts::Alerts,
+    anomalies::{Signal, ANOMALIES},
+    autoscalers,
+    cache::ReplicaSetStore,
+    config::{AlertRule, Config, OverflowPolicy, PipelineSettings},
+    container_logs::ContainerLogs,
+    context::FailureContexts,
+    degrade::DEGRADATION,
+    disruptions,
+    drains::Drains,
+    enrich::Enricher,
+    images,
+    incidents::{self, INCIDENTS},
+    kubelet, loadbalancers,
+    logging::{event_span, LogSampler},
+    messages,
+    metrics::{
+        extract_label_values_from_event, CAUSE_LABEL, CONTAINER_TYPE_LABEL, DROPPED_EVENTS_COUNTER,
+        DROP_REASON_LABEL, EVENTS_COUNTER, EVENT_DELAY_HISTOGRAM, IMAGE_PULL_FAILURES_COUNTER,
+        KIND_LABEL, NAMESPACE_LABEL, NAME_LABEL, POD_CREATE_COUNTER, POD_DELETE_COUNTER,
+        POD_ID_LABEL, REASON_LABEL, REPOSITORY_LABEL, SCHEDULING_FAILURES_COUNTER, SEVERITY_LABEL,
+        TYPE_LABEL, URGENT_EVENTS_COUNTER,
+    },
+    namespaces,
+    plugins::Plugins,
+    pods,
+    pods::PodTracker,
+    policies, quotas,
+    record::EventRecord,
+    registry::{CREATED_PODS, DELETED_PODS, EXEMPLARS, LAST_EVENT},
+    rollouts,
+    rollups::ROLLUPS,
+    scaling, scheduling,
+    scripts::Scripts,
+    services, severity,
+    sinks::{Dispatcher, Routing, SinkRegistry},
+    slos::Slos,
+    snapshot::SNAPSHOT,
+    spot::Interruptions,
+    tenants::Tenants,
+    throttle::ObjectThrottle,
+    top::TOP,
+};
+use axum_prometheus::metrics::{counter, histogram};
+use k8s_openapi::{
+    api::core::v1::Event,
+    chrono::{DateTime, Utc},
+};
+use kube::ResourceExt;
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+use tokio::{
+    sync::{
+        mpsc::{self, error::TrySendError},
+        watch,
+    },
+    task::JoinSet,
+};
+use tracing::{debug, info, Instrument};
+
+// how small the workers' queues are with priority on, the backlog has to wait in the
+// main queues to be taken in order of priority
+const URGENT_WORKER_QUEUE: usize = 8;
+
+// the sending half of the pipeline, used by the watchers.
+// It knows what to do when the processing side can't keep up.
+#[derive(Clone)]
+pub struct EventSender {
+    tx: mpsc::Sender<Event>,
+    // the queue of the events handled first, with priority on
+    urgent: Option<(mpsc::Sender<Event>, Arc<Urgency>)>,
+    overflow: OverflowPolicy,
+}
+
+impl EventSender {
+    // hands an event to the processing side, according to the overflow policy.
+    // Returns false when the processing side is gone, meaning we should stop.
+    pub async fn send(&self, event: Event) -> bool {
+        let tx = match self.urgent {
+            Some((ref tx, ref urgency)) if urgency.is_urgent(&event) => {
+                counter!(URGENT_EVENTS_COUNTER).increment(1);
+                tx
+            }
+            _ => &self.tx,
+        };
+        match self.overflow {
+            // wait for a free slot, which also slows down how fast we read the watch stream
+            OverflowPolicy::Block => tx.send(event).await.is_ok(),
+            // don't wait at all, just count what didn't fit
+            OverflowPolicy::Drop => match tx.try_send(event) {
+                Ok(()) => true,
+                Err(TrySendError::Full(event)) => {
+                    counter!(DROPPED_EVENTS_COUNTER, &[(DROP_REASON_LABEL, "queue_full")])
+                        .increment(1);
+                    debug!("Pipeline full, dropping event {}", event.name_any());
+                    true
+                }
+                Err(TrySendError::Closed(_)) => false,
+            },
+        }
+    }
+}
+
+// the processing half, taking the urgent events first while there are any
+pub struct EventReceiver {
+    rx: mpsc::Receiver<Event>,
+    urgent: Option<mpsc::Receiver<Event>>,
+}
+
+impl EventReceiver {
+    pub async fn recv(&mut self) -> Option<Event> {
+        let Some(ref mut urgent) = self.urgent else {
+            return self.rx.recv().await;
+        };
+        tokio::select! {
+            biased;
+            Some(event) = urgent.recv() => Some(event),
+            Some(event) = self.rx.recv() => Some(event),
+            else => None,
+        }
+    }
+}
+
+// which events go ahead: the Warnings, the reasons of pipeline.priority and the events
+// the alert rules count. A rule counting every event doesn't make them all urgent.
+pub struct Urgency {
+    reasons: HashSet<String>,
+    rules: Vec<AlertRule>,
+}
+
+impl Urgency {
+    fn new(reasons: &[String], rules: &[AlertRule]) -> Self {
+        Urgency {
+            reasons: reasons.iter().cloned().collect(),
+            rules: rules
+                .iter()
+                .filter(|rule| {
+                    !rule.kinds.is_empty() || !rule.reasons.is_empty() || !rule.types.is_empty()
+                })
+                .cloned()
+                .collect(),
+        }
+    }
+
+    fn is_urgent(&self, event: &Event) -> bool {
+        let type_ = event.type_.as_deref().unwrap_or_default();
+        let reason = event.reason.as_deref().unwrap_or_default();
+        let kind = event.involved_object.kind.as_deref().unwrap_or_default();
+        let allowed = |allowed: &[String], value: &str| {
+            allowed.is_empty() || allowed.iter().any(|allowed| allowed == value)
+        };
+        type_ == "Warning"
+            || self.reasons.contains(reason)
+            || self.rules.iter().any(|rule| {
+                allowed(&rule.kinds, kind)
+                    && allowed(&rule.reasons, reason)
+                    && allowed(&rule.types, type_)
+            })
+    }
+}
+
+// creates the bounded queue between the watch streams and the event processing, and the
+// one of the urgent events with priority on
+pub fn channel(settings: &PipelineSettings, rules: &[AlertRule]) -> (EventSender, EventReceiver) {
+    let (tx, rx) = mpsc::channel(settings.capacity);
+    let (urgent_tx, urgent_rx) = match settings.priority.enabled {
+        true => {
+            let (tx, rx) = mpsc::channel(settings.priority.capacity);
+            let urgency = Urgency::new(&settings.priority.reasons, rules);
+            (Some((tx, Arc::new(urgency))), Some(rx))
+        }
+        false => (None, None),
+    };
+    SNAPSHOT.track_queue(
+        tx.downgrade(),
+        urgent_tx.as_ref().map(|(tx, _)| tx.downgrade()),
+    );
+    let sender = EventSender {
+        tx,
+        urgent: urgent_tx,
+        overflow: settings.overflow,
+    };
+    let receiver = EventReceiver {
+        rx,
+        urgent: urgent_rx,
+    };
+    (sender, receiver)
+}
+
+// what's done with the events of each kind of object, besides delivering them.
+// Only the kinds enabled in the config get this far.
+pub struct EventHandlers {
+    tracker: Arc<PodTracker>,
+    spot: Option<Arc<Interruptions>>,
+    drains: Option<Arc<Drains>>,
+    // the owners of the replicasets, for the incidents
+    replicasets: Option<ReplicaSetStore>,
+    throttle: ObjectThrottle,
+    // Service events are wanted by both, so are StatefulSet and DaemonSet events
+    services: bool,
+    load_balancers: bool,
+    disruption_budgets: bool,
+    rollouts: bool,
+    quotas: bool,
+    node_autoscaling: bool,
+    node_events: bool,
+}
+
+impl EventHandlers {
+    pub fn new(
+        config: &Config,
+        tracker: Arc<PodTracker>,
+        spot: Option<Arc<Interruptions>>,
+        drains: Option<Arc<Drains>>,
+        replicasets: Option<ReplicaSetStore>,
+    ) -> Self {
+        EventHandlers {
+            tracker,
+            spot,
+            drains,
+            replicasets,
+            throttle: ObjectThrottle::new(&config.pipeline.per_object),
+            services: config.services.enabled,
+            load_balancers: config.load_balancers.enabled,
+            disruption_budgets: config.disruption_budgets.enabled,
+            rollouts: config.rollouts.enabled,
+            quotas: config.quotas.enabled,
+            node_autoscaling: config.node_autoscaling.enabled,
+            node_events: config.node_events.enabled,
+        }
+    }
+
+    fn handle(&self, event: &Event, enricher: &Enricher, sampler: &LogSampler) {
+        // whatever the reason, so the warnings can be told from the rest at a glance
+        let object = &event.involved_object;
+        let type_ = event.type_.clone().unwrap_or_default();
+        let severity = severity::of(event.reason.as_deref().unwrap_or_default(), &type_);
+        counter!(
+            EVENTS_COUNTER,
+            &[
+                (TYPE_LABEL, type_),
+                (SEVERITY_LABEL, severity.as_str().to_string()),
+                (KIND_LABEL, object.kind.clone().unwrap_or_default()),
+                (
+                    NAMESPACE_LABEL,
+                    object.namespace.clone().unwrap_or_default()
+                ),
+            ]
+        )
+        .increment(1);
+        // about the controllers' pods, or the constraints of Gatekeeper
+        policies::handle_event(event);
+        match event.involved_object.kind.as_deref() {
+            Some("Pod") => {
+                handle_event(event, enricher, sampler);
+                self.tracker.event(event);
+                if let Some(drains) = self.drains.as_ref() {
+                    drains.event(event, enricher);
+                }
+                if self.disruption_budgets {
+                    disruptions::handle_event(event, enricher);
+                }
+                if self.node_autoscaling {
+                    scaling::handle_event(event);
+                }
+            }
+            Some("PodDisruptionBudget") => disruptions::handle_event(event, enricher),
+            Some("Namespace") => namespaces::handle_event(event),
+            Some("Node") => {
+                if let Some(spot) = self.spot.as_ref() {
+                    spot.event(event);
+                }
+                if self.node_autoscaling {
+                    scaling::handle_event(event);
+                }
+                if self.node_events {
+                    kubelet::handle_event(event);
+                }
+            }
+            Some("NodeClaim") => scaling::handle_event(event),
+            Some("Service") => {
+                if self.services {
+                    services::handle_event(event);
+                }
+                if self.load_balancers {
+                    loadbalancers::handle_event(event);
+                }
+            }
+            Some("Endpoints" | "EndpointSlice") => services::handle_event(event),
+            Some("Ingress") => loadbalancers::handle_event(event),
+            Some("HorizontalPodAutoscaler") => autoscalers::handle_event(event),
+            Some("StatefulSet" | "DaemonSet") => {
+                if self.rollouts {
+                    rollouts::handle_event(event);
+                }
+                if self.quotas {
+                    quotas::handle_event(event);
+                }
+            }
+            Some("ReplicaSet" | "Job") if self.quotas => quotas::handle_event(event),
+            _ => {}
+        }
+    }
+}
+
+// what the user added to the processing of the records: the tenants' sinks, their
+// scripts and plugins, which can also send records to more sinks or keep them from
+// the pipeline's, and the objectives counting them
+pub struct RecordHandlers {
+    tenants: Arc<Tenants>,
+    scripts: Arc<Scripts>,
+    plugins: Plugins,
+    slos: Arc<Slos>,
+    sinks: SinkRegistry,
+    logs: ContainerLogs,
+    contexts: FailureContexts,
+}
+
+impl RecordHandlers {
+    pub fn new(
+        tenants: Arc<Tenants>,
+        scripts: Arc<Scripts>,
+        plugins: Plugins,
+        slos: Arc<Slos>,
+        sinks: SinkRegistry,
+        logs: ContainerLogs,
+        contexts: FailureContexts,
+    ) -> Self {
+        RecordHandlers {
+            tenants,
+            scripts,
+            plugins,
+            slos,
+            sinks,
+            logs,
+            contexts,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tenants.is_empty()
+            && self.scripts.is_empty()
+            && self.plugins.is_empty()
+            && self.slos.is_empty()
+            && !self.contexts.routes()
+    }
+
+    // the plugins see the labels the scripts added. The objectives count even
+    // what's dropped, like the metrics do.
+    fn handle(&self, record: &mut EventRecord) -> Routing {
+        let mut routing = Routing::default();
+        self.contexts.route(record, &mut routing);
+        self.tenants.route(record, &mut routing);
+        self.scripts.run(record, &mut routing);
+        self.plugins.run(record, &mut routing);
+        self.slos.observe(record);
+        routing
+    }
+}
+
+// the consuming side of the pipeline, it goes on until every sender is dropped.
+// Events are spread over a pool of workers, but all events of the same object
+// always go to the same worker, so they're still processed in order.
+// The dispatcher can be swapped while running, when the config is reloaded.
+pub async fn process_events(
+    mut rx: EventReceiver,
+    settings: &PipelineSettings,
+    enricher: Enricher,
+    dispatcher: watch::Receiver<Dispatcher>,
+    alerts: Arc<Alerts>,
+    handlers: Arc<EventHandlers>,
+    records: Arc<RecordHandlers>,
+) {
+    let sampler = Arc::new(LogSampler::new(&settings.log_sampling));
+    let aggregator = Arc::new(Aggregator::new(&settings.aggregation, dispatcher));
+    let rollups = tokio::spawn(aggregator.clone().run());
+    let mut workers = JoinSet::new();
+    let mut worker_queues = Vec::with_capacity(settings.workers);
+    for _ in 0..settings.workers {
+        // the main queue already holds most of the backlog, so these can be small
+        let capacity = match settings.priority.enabled {
+            true => URGENT_WORKER_QUEUE,
+            false => settings.capacity.div_ceil(settings.workers),
+        };
+        let (tx, worker_rx) = mpsc::channel(capacity);
+        worker_queues.push(tx);
+        workers.spawn(run_worker(
+            worker_rx,
+            enricher.clone(),
+            aggregator.clone(),
+            sampler.clone(),
+            alerts.clone(),
+            handlers.clone(),
+            records.clone(),
+        ));
+    }
+    info!("Processing events with {} worker(s)", settings.workers);
+
+    while let Some(event) = rx.recv().await {
+        let worker = worker_for(&event, worker_queues.len());
+        // waiting here keeps the backpressure going all the way up to the watchers
+        if worker_queues[worker].send(event).await.is_err() {
+            break;
+        }
+    }
+
+    // closing the worker queues lets the workers finish what they have and leave
+    drop(worker_queues);
+    while workers.join_next().await.is_some() {}
+    // what's still rolled up is delivered before the sinks are flushed
+    rollups.abort();
+    aggregator.drain().await;
+    aggregator.dispatcher().flush().await;
+    info!("Pipeline closed, no more events to process");
+}
+
+async fn run_worker(
+    mut rx: mpsc::Receiver<Event>,
+    enricher: Enricher,
+    aggregator: Arc<Aggregator>,
+    sampler: Arc<LogSampler>,
+    alerts: Arc<Alerts>,
+    handlers: Arc<EventHandlers>,
+    records: Arc<RecordHandlers>,
+) {
+    while let Some(event) = rx.recv().await {
+        // the noisiest objects are the ones over their limit
+        TOP.observe(&event);
+        // the events of an object over its limit are only counted as such
+        if !handlers.throttle.admits(&event) {
+            continue;
+        }
+        let span = event_span(&event);
+        span.in_scope(|| handlers.handle(&event, &enricher, &sampler));
+        if event.type_.as_deref() == Some("Warning") && ANOMALIES.enabled() {
+            if let Some(workload) =
+                incidents::workload(&event, &enricher, handlers.replicasets.as_ref())
+            {
+                let namespace = event
+                    .involved_object
+                    .namespace
+                    .as_deref()
+                    .unwrap_or_default();
+                ANOMALIES.observe(namespace, &workload, Signal::Warnings, 1);
+            }
+        }
+        if ROLLUPS.enabled() {
+            let object = &event.involved_object;
+            let workload = incidents::workload(&event, &enricher, handlers.replicasets.as_ref())
+                .unwrap_or_else(|| {
+                    format!(
+                        "{}/{}",
+                        object.kind.as_deref().unwrap_or_default(),
+                        object.name.as_deref().unwrap_or_default()
+                    )
+                });
+            ROLLUPS.observe(&event, workload);
+        }
+        let dispatcher = aggregator.dispatcher();
+        if !dispatcher.is_empty()
+            || !alerts.is_empty()
+            || !records.is_empty()
+            || SNAPSHOT.enabled()
+            || INCIDENTS.enabled()
+        {
+            let mut record = EventRecord::new(&event, &enricher);
+            records.contexts.attach(&event, &enricher, &mut record);
+            // before the scripts and plugins, so they can look at them
+            records
+                .logs
+                .attach(&event, &mut record)
+                .instrument(span.clone())
+                .await;
+            let routing = span.in_scope(|| records.handle(&mut record));
+            SNAPSHOT.event(&record);
+            if let Some(workload) =
+                incidents::workload(&event, &enricher, handlers.replicasets.as_ref())
+            {
+                INCIDENTS.observe(&record, workload);
+            }
+            if !alerts.is_empty() {
+                span.in_scope(|| alerts.observe(&record));
+            }
+            records
+                .sinks
+                .route(&record, &routing)
+                .instrument(span.clone())
+                .await;
+            if !dispatcher.is_empty() && !routing.drop {
+                aggregator.deliver(record).instrument(span).await;
+            }
+        }
+        record_delay(&event);
+    }
+}
+
+// when the event last happened. The timestamps only have seconds, and the clocks can be
+// a bit off.
+pub fn happened(event: &Event) -> Option<DateTime<Utc>> {
+    event
+        .event_time
+        .as_ref()
+        .map(|time| time.0)
+        .or_else(|| event.last_timestamp.as_ref().map(|time| time.0))
+        .or_else(|| event.first_timestamp.as_ref().map(|time| time.0))
+}
+
+// how far behind the cluster we are, from when the event last happened to now
+fn record_delay(event: &Event) {
+    if let Some(happened) = happened(event) {
+        let delay = (Utc::now() - happened).to_std().unwrap_or_default();
+        histogram!(
+            EVENT_DELAY_HISTOGRAM,
+            &[(
+                KIND_LABEL,
+                event.involved_object.kind.clone().unwrap_or_default()
+            )]
+        )
+        .record(delay.as_secs_f64());
+    }
+}
+
+// picks a worker by hashing the involved object's uid
+fn worker_for(event: &Event, workers: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    event.involved_object.uid.hash(&mut hasher);
+    (hasher.finish() % workers as u64) as usize
+}
+
+// counts and logs a pod event
+pub fn handle_event(event: &Event, enricher: &Enricher, sampler: &LogSampler) {
+    let object = &event.involved_object;
+    // during a storm the series below are the workload's or namespace's, not the pod's
+    let aggregate = DEGRADATION.aggregate(event, enricher);
+    if let (Some(uid), Some(happened)) = (object.uid.clone(), happened(event)) {
+        let (name, uid) = match aggregate {
+            Some(ref aggregate) => (aggregate.value().to_string(), aggregate.value().to_string()),
+            None => (object.name.clone().unwrap_or_default(), uid),
+        };
+        LAST_EVENT.set_max(
+            &[
+                (
+                    NAMESPACE_LABEL,
+                    object.namespace.clone().unwrap_or_default(),
+                ),
+                (NAME_LABEL, name),
+                (POD_ID_LABEL, uid),
+            ],
+            happened.timestamp_millis() as f64 / 1000.0,
+        );
+    }
+    if let Some(ref reason) = event.reason {
+        // the counters below still count every event, only the logs are sampled
+        let mut log = sampler.allows(reason);
+        // a configured message replaces the ones below
+        if log {
+            if let Some(message) = messages::render_event(event, enricher) {
+                info!("{}", message);
+                log = false;
+            }
+        }
+        match reason.as_ref() {
+            "Pulled" if log => info!("image for Pod {} pulled", event.name_any()),
+            "Created" => {
+                let mut labels = extract_label_values_from_event(
+                    event,
+                    enricher.node_and_zone(event),
+                    enricher.pod_labels(event),
+                );
+                if let Some(ref aggregate) = aggregate {
+                    aggregate.apply(&mut labels);
+                }
+                // we get the counter with our labels and increment it
+                let labels = labels.to_metric_labels();
+                EXEMPLARS.record(
+                    POD_CREATE_COUNTER,
+                    &labels,
+                    &event.uid().unwrap_or_default(),
+                );
+                CREATED_PODS.increment_labels(&labels, 1);
+                if log {
+                    info!("Pod {} created", event.name_any());
+                }
+            }
+            "Scheduled" if log => match enricher.pod_context(event).and_then(|pod| pod.node) {
+                Some(node) => info!("Pod {} scheduled on node {}", event.name_any(), node),
+                None => info!("Pod {} scheduled", event.name_any()),
+            },
+            "Started" if log => {
+                info!("Pod {} allocated and started", event.name_any())
+            }
+            "Updated" if log => info!("Pod {} updated", event.name_any()),
+            "Failed" => {
+                if let Some(failure) = images::pull_failure(event) {
+                    let pod = enricher.pod(event);
+                    let image = pod
+                        .as_ref()
+                        .and_then(|pod| images::container_image(pod, event))
+                        .unwrap_or_default();
+                    let container_type = pods::event_container(event, pod.as_deref())
+                        .map_or(pods::MAIN_CONTAINER, |(_, container_type)| container_type);
+                    counter!(
+                        IMAGE_PULL_FAILURES_COUNTER,
+                        &[
+                            (NAMESPACE_LABEL, event.namespace().unwrap_or_default()),
+                            (REPOSITORY_LABEL, images::repository(&image).to_string()),
+                            (REASON_LABEL, failure.to_string()),
+                            (CONTAINER_TYPE_LABEL, container_type.to_string()),
+                        ]
+                    )
+                    .increment(1);
+                    if log {
+                        info!(
+                            "Pod {} could not pull {}: {}",
+                            event.name_any(),
+                            image,
+                            failure
+                        );
+                    }
+                }
+            }
+            "FailedScheduling" => {
+                let causes = scheduling::failure_causes(event);
+                for cause in causes.iter() {
+                    counter!(
+                        SCHEDULING_FAILURES_COUNTER,
+                        &[
+                            (NAMESPACE_LABEL, event.namespace().unwrap_or_default()),
+                            (CAUSE_LABEL, cause.to_string()),
+                        ]
+                    )
+                    .increment(1);
+                }
+                if log {
+                    info!(
+                        "Pod {} could not be scheduled: {}",
+                        event.name_any(),
+                        causes.join(", ")
+                    );
+                }
+            }
+            "Killing" => {
+                // the pods that succeeded (a job's, its sidecars killed) count as
+                // completed, not deleted
+                let completed = enricher.pod(event).is_some_and(|pod| pods::succeeded(&pod));
+                if !completed {
+                    let mut labels = extract_label_values_from_event(
+                        event,
+                        enricher.node_and_zone(event),
+                        enricher.pod_labels(event),
+                    );
+                    if let Some(ref aggregate) = aggregate {
+                        aggregate.apply(&mut labels);
+                    }
+                    // we get the counter with our labels and increment it
+                    let labels = labels.to_metric_labels();
+                    EXEMPLARS.record(
+                        POD_DELETE_COUNTER,
+                        &labels,
+                        &event.uid().unwrap_or_default(),
+                    );
+                    DELETED_PODS.increment_labels(&labels, 1);
+                }
+                if log {
+                    match enricher.pod_context(event).and_then(|pod| pod.owner) {
+                        Some(owner) => info!("Killing Pod {} of {}", event.name_any(), owner),
+                        None => info!("Killing Pod {}", event.name_any()),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
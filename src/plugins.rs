@@ -0,0 +1,36 @@
+// the wasm components implementing wit/processor.wit, run on every record of the
+// pod pipeline. The runtime is big, so it's only built with the plugins feature.
+#[cfg(feature = "plugins")]
+mod oci;
+#[cfg(feature = "plugins")]
+mod wasm;
+
+#[cfg(feature = "plugins")]
+pub use wasm::Plugins;
+
+#[cfg(not(feature = "plugins"))]
+pub use disabled::Plugins;
+
+#[cfg(not(feature = "plugins"))]
+mod disabled {
+    use crate::{config::PluginSettings, error::ErrorKind, record::EventRecord, sinks::Routing};
+
+    // without the runtime there's nothing to run, but a config asking for plugins
+    // shouldn't go unnoticed
+    pub struct Plugins;
+
+    impl Plugins {
+        pub async fn load(settings: &PluginSettings) -> Result<Self, ErrorKind> {
+            if !settings.modules.is_empty() {
+                return Err("plugins.modules needs k8rs built with the plugins feature".into());
+            }
+            Ok(Plugins)
+        }
+
+        pub fn is_empty(&self) -> bool {
+            true
+        }
+
+        pub fn run(&self, _record: &EventRecord, _routing: &mut Routing) {}
+    }
+}
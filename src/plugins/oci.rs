@@ -0,0 +1,141 @@
+use crate::{error::ErrorKind, outbound};
+use reqwest::{header, Client, Response, StatusCode};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+// what the registries accept for a wasm artifact's manifest
+const MANIFEST_TYPES: &str =
+    "application/vnd.oci.image.manifest.v1+json, application/vnd.docker.distribution.manifest.v2+json";
+
+#[derive(Deserialize)]
+struct Manifest {
+    layers: Vec<Layer>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Layer {
+    media_type: String,
+    digest: String,
+}
+
+#[derive(Deserialize)]
+struct Token {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+// a repository of a registry, with the token it gave us if it asked for one
+struct Repository {
+    client: Client,
+    registry: String,
+    name: String,
+    token: Option<String>,
+}
+
+// pulls the wasm layer of an artifact like "ghcr.io/acme/plugin:1.0", anonymously
+pub async fn pull(reference: &str, timeout: Duration) -> Result<Vec<u8>, ErrorKind> {
+    let (registry, rest) = reference.split_once('/').ok_or_else(|| {
+        format!(
+            "{} has no registry, like ghcr.io/acme/plugin:1.0",
+            reference
+        )
+    })?;
+    let (name, tag) = match rest.split_once('@') {
+        Some((name, digest)) => (name, digest),
+        None => rest.rsplit_once(':').unwrap_or((rest, "latest")),
+    };
+    let mut repository = Repository {
+        client: outbound::client().timeout(timeout).build()?,
+        // docker hub's api isn't served where its images say
+        registry: match registry {
+            "docker.io" => "registry-1.docker.io".to_string(),
+            registry => registry.to_string(),
+        },
+        name: name.to_string(),
+        token: None,
+    };
+
+    let manifest: Manifest = repository
+        .get(&format!("manifests/{}", tag), MANIFEST_TYPES)
+        .await?
+        .json()
+        .await?;
+    let layer = match manifest.layers.as_slice() {
+        [layer] => layer,
+        layers => layers
+            .iter()
+            .find(|layer| layer.media_type.ends_with("wasm"))
+            .ok_or_else(|| format!("{} has no wasm layer", reference))?,
+    };
+
+    let blob = repository
+        .get(&format!("blobs/{}", layer.digest), "*/*")
+        .await?
+        .bytes()
+        .await?;
+    let digest = format!("sha256:{:x}", Sha256::digest(&blob));
+    if digest != layer.digest {
+        return Err(format!("{} should be {} but is {}", reference, layer.digest, digest).into());
+    }
+    Ok(blob.to_vec())
+}
+
+impl Repository {
+    async fn get(&mut self, path: &str, accept: &str) -> Result<Response, ErrorKind> {
+        let url = format!("https://{}/v2/{}/{}", self.registry, self.name, path);
+        let response = self.send(&url, accept).await?;
+        if response.status() != StatusCode::UNAUTHORIZED || self.token.is_some() {
+            return Ok(response.error_for_status()?);
+        }
+        // the registry wants a token, even for public artifacts
+        let challenge = response
+            .headers()
+            .get(header::WWW_AUTHENTICATE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| format!("{} needs credentials", self.registry))?;
+        self.token = Some(self.token(challenge).await?);
+        Ok(self.send(&url, accept).await?.error_for_status()?)
+    }
+
+    async fn send(&self, url: &str, accept: &str) -> Result<Response, reqwest::Error> {
+        let mut request = self.client.get(url).header(header::ACCEPT, accept);
+        if let Some(ref token) = self.token {
+            request = request.bearer_auth(token);
+        }
+        request.send().await
+    }
+
+    // asks for an anonymous token, from a challenge like
+    // realm="https://ghcr.io/token",service="ghcr.io",scope="repository:acme/plugin:pull"
+    async fn token(&self, challenge: &str) -> Result<String, ErrorKind> {
+        let mut realm = None;
+        let mut query = Vec::new();
+        for param in challenge.split("\",") {
+            let Some((key, value)) = param.split_once('=') else {
+                continue;
+            };
+            let value = value.trim_matches('"');
+            match key.trim() {
+                "realm" => realm = Some(value),
+                key => query.push((key, value)),
+            }
+        }
+        let realm = realm.ok_or_else(|| format!("{} sent no token realm", self.registry))?;
+        let token: Token = self
+            .client
+            .get(realm)
+            .query(&query)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        token
+            .token
+            .or(token.access_token)
+            .ok_or_else(|| format!("{} sent no token", self.registry).into())
+    }
+}
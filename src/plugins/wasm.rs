@@ -0,0 +1,132 @@
+use super::oci;
+use crate::{
+    config::{PluginSettings, PluginSource},
+    error::{Context, ErrorKind},
+    metrics::{sanitize_label_name, PLUGIN_ERRORS_COUNTER, PLUGIN_LABEL},
+    record::EventRecord,
+    sinks::Routing,
+};
+use axum_prometheus::metrics::{counter, Label};
+use tracing::{info, warn};
+use wasmtime::{
+    component::{Component, Linker},
+    Config, Engine, Store,
+};
+
+// also brings the world's Action
+wasmtime::component::bindgen!({ path: "wit", world: "processor" });
+
+struct Plugin {
+    name: String,
+    // compiled and linked once, each event gets a fresh instance
+    processor: ProcessorPre<()>,
+}
+
+// the plugins, run on every record in the order they're declared in. They can't
+// touch anything but the record they get, and they're stopped once they're out of fuel.
+pub struct Plugins {
+    engine: Engine,
+    plugins: Vec<Plugin>,
+    fuel: u64,
+}
+
+impl Plugins {
+    // reads or pulls every plugin and compiles it, refusing to start with plugins
+    // that can't be loaded like we refuse invalid configs
+    pub async fn load(settings: &PluginSettings) -> Result<Self, ErrorKind> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).map_err(ErrorKind::other)?;
+        // plugins get no imports
+        let linker = Linker::<()>::new(&engine);
+
+        let mut plugins = Vec::new();
+        for module in settings.modules.iter() {
+            let bytes = match module.source {
+                PluginSource::Path(ref path) => tokio::fs::read(path)
+                    .await
+                    .with_context(|| format!("could not read plugin {}", module.name))?,
+                PluginSource::Oci(ref reference) => oci::pull(reference, settings.fetch_timeout)
+                    .await
+                    .with_context(|| format!("could not pull plugin {}", module.name))?,
+            };
+            let component = Component::new(&engine, &bytes).map_err(|err| {
+                format!("plugin {} isn't a wasm component: {:#}", module.name, err)
+            })?;
+            let processor = linker
+                .instantiate_pre(&component)
+                .and_then(ProcessorPre::new)
+                .map_err(|err| {
+                    format!(
+                        "plugin {} doesn't implement the processor world: {:#}",
+                        module.name, err
+                    )
+                })?;
+            info!("Loaded plugin {}", module.name);
+            plugins.push(Plugin {
+                name: module.name.clone(),
+                processor,
+            });
+        }
+        Ok(Plugins {
+            engine,
+            plugins,
+            fuel: settings.fuel,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    pub fn run(&self, record: &EventRecord, routing: &mut Routing) {
+        let event = match serde_json::to_string(record) {
+            Ok(event) => event,
+            Err(err) => {
+                warn!(
+                    "Could not serialize event {} for the plugins: {}",
+                    record.name, err
+                );
+                return;
+            }
+        };
+        for plugin in self.plugins.iter() {
+            let actions = match self.process(plugin, &event) {
+                Ok(actions) => actions,
+                Err(err) => {
+                    counter!(
+                        PLUGIN_ERRORS_COUNTER,
+                        &[(PLUGIN_LABEL, plugin.name.clone())]
+                    )
+                    .increment(1);
+                    warn!(
+                        "Plugin {} failed on event {}: {:#}",
+                        plugin.name, record.name, err
+                    );
+                    continue;
+                }
+            };
+            for action in actions {
+                match action {
+                    Action::Increment(metric) => {
+                        let labels = metric
+                            .labels
+                            .into_iter()
+                            .map(|(key, value)| Label::new(key, value))
+                            .collect::<Vec<_>>();
+                        counter!(sanitize_label_name("plugin", &metric.name), labels).increment(1);
+                    }
+                    Action::Route(sink) => routing.sinks.push(sink),
+                    Action::Drop => routing.drop = true,
+                }
+            }
+        }
+    }
+
+    fn process(&self, plugin: &Plugin, event: &str) -> wasmtime::Result<Vec<Action>> {
+        let mut store = Store::new(&self.engine, ());
+        store.set_fuel(self.fuel)?;
+        let processor = plugin.processor.instantiate(&mut store)?;
+        processor.call_process(&mut store, event)
+    }
+}
@@ -0,0 +1,334 @@
+use crate::{
+    anomalies::{Signal, ANOMALIES},
+    churn::{self, Churn},
+    features::{self, IMAGE_INVENTORY, POD_CHURN, POD_LIFECYCLE, POD_PRIORITIES, POD_RESOURCES},
+    images::INVENTORY,
+    lifecycle::{Lifecycle, SavedLifecycle},
+    metrics::{
+        CONTAINER_LABEL, CONTAINER_RESTARTS_COUNTER, CONTAINER_TYPE_LABEL, KIND_LABEL,
+        NAMESPACE_LABEL, PODS_COMPLETED_COUNTER, WORKLOAD_LABEL,
+    },
+    persistence::INITIAL_SYNC,
+    priorities::Priorities,
+    resources::Resources,
+    stale::STALE_SERIES,
+};
+use axum_prometheus::metrics::counter;
+use k8s_openapi::api::core::v1::{Event, Pod, PodSpec};
+use kube::{runtime::watcher, ResourceExt};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+// the label deployments put on their pods (and replicasets' names)
+const POD_TEMPLATE_HASH_LABEL: &str = "pod-template-hash";
+
+// what a container is to its pod, the container_type label
+pub const MAIN_CONTAINER: &str = "main";
+pub const INIT_CONTAINER: &str = "init";
+// an init container that keeps running along with the main ones, with restartPolicy:
+// Always (1.28 and later)
+pub const SIDECAR_CONTAINER: &str = "sidecar";
+pub const EPHEMERAL_CONTAINER: &str = "ephemeral";
+
+// follows the pods of the pod cache as they change, for what the events don't tell.
+// Events about restarts only go out now and then ("Back-off restarting..."),
+// the restart counts in the pod status are the real thing.
+#[derive(Default)]
+pub struct PodTracker {
+    state: Mutex<TrackerState>,
+    lifecycle: Lifecycle,
+    resources: Resources,
+    priorities: Priorities,
+    churn: Churn,
+}
+
+#[derive(Default)]
+struct TrackerState {
+    // the last restart count of each container, by pod uid
+    restarts: HashMap<String, BTreeMap<String, i32>>,
+    // done with the first list: pods seen before that restarted before we started
+    listed: bool,
+    // the pods seen since the watcher started (re-)listing
+    relisted: HashSet<String>,
+    // the pods that succeeded, counted once
+    completed: HashSet<String>,
+}
+
+impl PodTracker {
+    // called with every event of the pod watcher, before it reaches the cache
+    pub fn observe(&self, event: &watcher::Event<Pod>) {
+        let mut state = self.state.lock().expect("pod tracker lock poisoned");
+        match event {
+            watcher::Event::Init => {
+                state.relisted.clear();
+                self.lifecycle.list_started();
+            }
+            watcher::Event::InitApply(pod) => {
+                if let Some(uid) = pod.uid() {
+                    state.relisted.insert(uid);
+                }
+                state.restarts(pod);
+                state.completed(pod);
+                self.subsystems(pod, true);
+                // the pods new since the last list are ours, not those of the first
+                self.churn(pod, state.listed);
+            }
+            watcher::Event::InitDone => {
+                // what wasn't listed again is gone, we missed its delete while away
+                let relisted = std::mem::take(&mut state.relisted);
+                for uid in state.restarts.keys().filter(|uid| !relisted.contains(*uid)) {
+                    STALE_SERIES.pod_deleted(uid);
+                }
+                state.restarts.retain(|uid, _| relisted.contains(uid));
+                state.completed.retain(|uid| relisted.contains(uid));
+                self.lifecycle.list_done();
+                self.resources.retain(&relisted);
+                self.priorities.retain(&relisted);
+                self.churn.retain(&relisted);
+                INVENTORY.retain(&relisted);
+                state.listed = true;
+                INITIAL_SYNC.done();
+            }
+            watcher::Event::Apply(pod) => {
+                state.restarts(pod);
+                state.completed(pod);
+                self.subsystems(pod, false);
+                self.churn(pod, true);
+            }
+            watcher::Event::Delete(pod) => {
+                if let Some(uid) = pod.uid() {
+                    state.restarts.remove(&uid);
+                    state.completed.remove(&uid);
+                    STALE_SERIES.pod_deleted(&uid);
+                    self.lifecycle.gone(&uid);
+                    self.resources.gone(&uid);
+                    self.priorities.gone(&uid);
+                    self.churn.gone(pod);
+                    INVENTORY.gone(&uid);
+                }
+            }
+        }
+    }
+
+    // called with every pod event of the pipeline
+    pub fn event(&self, event: &Event) {
+        if features::enabled(POD_LIFECYCLE) {
+            self.lifecycle.event(event);
+        }
+        if features::enabled(POD_PRIORITIES) {
+            self.priorities.event(event);
+        }
+    }
+
+    // the churn of the last window, which the pods don't update once they're gone
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(churn::WINDOW / 120);
+        loop {
+            interval.tick().await;
+            self.churn.refresh();
+        }
+    }
+
+    // what the lifecycle knew of the pods, to carry on from after a restart
+    pub fn save_lifecycle(&self) -> SavedLifecycle {
+        self.lifecycle.save()
+    }
+
+    pub fn restore_lifecycle(&self, saved: SavedLifecycle) {
+        self.lifecycle.restore(saved)
+    }
+
+    fn churn(&self, pod: &Pod, counted: bool) {
+        match features::enabled(POD_CHURN) {
+            true => self.churn.pod(pod, counted),
+            false => self.churn.forget(&pod.uid().unwrap_or_default()),
+        }
+    }
+
+    // the ones switched off by their feature flag let go of the pod, when they were
+    // following it before a reload
+    fn subsystems(&self, pod: &Pod, listing: bool) {
+        let uid = pod.uid().unwrap_or_default();
+        match (features::enabled(POD_LIFECYCLE), listing) {
+            // the pods of the jobs can stay around long after they're done
+            (true, _) if succeeded(pod) => self.lifecycle.completed(&uid),
+            (true, true) => self.lifecycle.listed(pod),
+            (true, false) => self.lifecycle.pod(pod),
+            (false, _) => self.lifecycle.forget(&uid),
+        }
+        match features::enabled(POD_RESOURCES) {
+            true => self.resources.pod(pod),
+            false => self.resources.gone(&uid),
+        }
+        match features::enabled(POD_PRIORITIES) {
+            true => self.priorities.pod(pod),
+            false => self.priorities.gone(&uid),
+        }
+        match features::enabled(IMAGE_INVENTORY) {
+            true => INVENTORY.pod(pod),
+            false => INVENTORY.gone(&uid),
+        }
+    }
+}
+
+impl TrackerState {
+    // counts the pods that succeeded, like the ones of the jobs, apart from the deleted
+    // ones. Those that were done before we started aren't ours to count.
+    fn completed(&mut self, pod: &Pod) {
+        let Some(uid) = pod.uid().filter(|_| succeeded(pod)) else {
+            return;
+        };
+        if !self.completed.insert(uid) || !self.listed {
+            return;
+        }
+        let kind = pod
+            .owner_references()
+            .iter()
+            .find(|owner| owner.controller == Some(true))
+            .map_or("Pod".to_string(), |owner| owner.kind.clone());
+        counter!(
+            PODS_COMPLETED_COUNTER,
+            &[
+                (NAMESPACE_LABEL, pod.namespace().unwrap_or_default()),
+                (KIND_LABEL, kind),
+            ]
+        )
+        .increment(1);
+    }
+
+    // counts the restarts since the last time we saw the pod. A pod seen again after a
+    // re-list only counts what changed meanwhile, a new pod counts all its restarts.
+    fn restarts(&mut self, pod: &Pod) {
+        let Some(uid) = pod.uid() else {
+            return;
+        };
+        let statuses = pod
+            .status
+            .iter()
+            .flat_map(|status| {
+                status
+                    .init_container_statuses
+                    .iter()
+                    .chain(status.container_statuses.iter())
+                    .flatten()
+            })
+            .map(|status| (status.name.clone(), status.restart_count))
+            .collect::<BTreeMap<_, _>>();
+        let spec = pod.spec.as_ref();
+
+        let known = self.restarts.get(&uid);
+        // the restarts from before we started aren't ours to count
+        let baseline = known.is_none() && !self.listed;
+        if !baseline {
+            let namespace = pod.namespace().unwrap_or_default();
+            let workload = workload(pod);
+            for (container, count) in statuses.iter() {
+                let last = known
+                    .and_then(|known| known.get(container))
+                    .copied()
+                    .unwrap_or(0);
+                // only the restarts we haven't counted yet
+                if *count > last {
+                    counter!(
+                        CONTAINER_RESTARTS_COUNTER,
+                        &[
+                            (NAMESPACE_LABEL, namespace.clone()),
+                            (WORKLOAD_LABEL, workload.clone()),
+                            (CONTAINER_LABEL, container.clone()),
+                            (
+                                CONTAINER_TYPE_LABEL,
+                                container_type(spec, container).to_string()
+                            ),
+                        ]
+                    )
+                    .increment((*count - last) as u64);
+                    ANOMALIES.observe(
+                        &namespace,
+                        &workload,
+                        Signal::Restarts,
+                        (*count - last) as u64,
+                    );
+                }
+            }
+        }
+        self.restarts.insert(uid, statuses);
+    }
+}
+
+// whether all of the pod's containers are done and exited with 0, like a job's
+pub fn succeeded(pod: &Pod) -> bool {
+    pod.status
+        .as_ref()
+        .and_then(|status| status.phase.as_deref())
+        == Some("Succeeded")
+}
+
+// what the container of that name is to its pod, a main one when the spec doesn't tell
+pub fn container_type(spec: Option<&PodSpec>, name: &str) -> &'static str {
+    let Some(spec) = spec else {
+        return MAIN_CONTAINER;
+    };
+    if let Some(init) = spec
+        .init_containers
+        .iter()
+        .flatten()
+        .find(|container| container.name == name)
+    {
+        return match init.restart_policy.as_deref() {
+            Some("Always") => SIDECAR_CONTAINER,
+            _ => INIT_CONTAINER,
+        };
+    }
+    let ephemeral = spec
+        .ephemeral_containers
+        .iter()
+        .flatten()
+        .any(|container| container.name == name);
+    match ephemeral {
+        true => EPHEMERAL_CONTAINER,
+        false => MAIN_CONTAINER,
+    }
+}
+
+// the container an event is about and what it is to the pod, from its field path like
+// spec.initContainers{migrate}. Without the pod an init container can't be told from a
+// sidecar, and counts as an init one.
+pub fn event_container(event: &Event, pod: Option<&Pod>) -> Option<(String, &'static str)> {
+    let field_path = event.involved_object.field_path.as_deref()?;
+    let (field, name) = field_path.strip_suffix('}')?.split_once('{')?;
+    let container_type = match field {
+        "spec.containers" => MAIN_CONTAINER,
+        "spec.initContainers" => match pod.and_then(|pod| pod.spec.as_ref()) {
+            Some(spec) => container_type(Some(spec), name),
+            None => INIT_CONTAINER,
+        },
+        "spec.ephemeralContainers" => EPHEMERAL_CONTAINER,
+        _ => return None,
+    };
+    Some((name.to_string(), container_type))
+}
+
+// what the pod belongs to, like "Deployment/nginx". Pods of a deployment are owned by
+// one of its replicasets, whose names change with every rollout, so those are named
+// after the deployment. Pods without an owner are their own workload.
+pub fn workload(pod: &Pod) -> String {
+    let Some(owner) = pod
+        .owner_references()
+        .iter()
+        .find(|owner| owner.controller == Some(true))
+    else {
+        return format!("Pod/{}", pod.name_any());
+    };
+    if owner.kind == "ReplicaSet" {
+        if let Some(deployment) = pod
+            .labels()
+            .get(POD_TEMPLATE_HASH_LABEL)
+            .and_then(|hash| owner.name.strip_suffix(&format!("-{}", hash)))
+        {
+            return format!("Deployment/{}", deployment);
+        }
+    }
+    format!("{}/{}", owner.kind, owner.name)
+}
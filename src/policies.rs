@@ -0,0 +1,107 @@
+use crate::metrics::{NAMESPACE_LABEL, POLICY_DENIALS_COUNTER, POLICY_LABEL};
+use axum_prometheus::metrics::counter;
+use k8s_openapi::api::core::v1::Event;
+
+// the webhooks of the policy engines whose denials name the policies that denied
+const GATEKEEPER: &str = "validation.gatekeeper.sh";
+const KYVERNO: &str = "validate.kyverno.svc";
+
+// the controllers the FailedCreate events already count the denials of
+const CONTROLLERS: &str = "system:serviceaccount:kube-system:";
+
+// counts the requests a policy denied, by the policy and the namespace of what was
+// denied. The controllers say so in the FailedCreate events about the pods they couldn't
+// create, like `Error creating: pods "api-7d9c" is forbidden: violates PodSecurity
+// "restricted:latest": ...`, and Gatekeeper in its own FailedAdmission events, with
+// --emit-admission-events.
+pub fn handle_event(event: &Event) {
+    let (namespace, policies) = match event.reason.as_deref() {
+        Some("FailedCreate") => (
+            event.involved_object.namespace.clone().unwrap_or_default(),
+            denials(event.message.as_deref().unwrap_or_default()),
+        ),
+        Some("FailedAdmission") => match gatekeeper(event) {
+            Some(denial) => denial,
+            None => return,
+        },
+        _ => return,
+    };
+    for policy in policies {
+        counter!(
+            POLICY_DENIALS_COUNTER,
+            &[(NAMESPACE_LABEL, namespace.clone()), (POLICY_LABEL, policy)]
+        )
+        .increment(1);
+    }
+}
+
+// the policies a FailedCreate message says denied the pod, one of:
+// `violates PodSecurity "restricted:latest": ...`, as PodSecurity:restricted
+// `ValidatingAdmissionPolicy 'require-team' with binding 'require-team' denied request: ...`
+// `admission webhook "validation.gatekeeper.sh" denied the request: [require-team] ...`,
+// a line for every constraint
+// `admission webhook "validate.kyverno.svc-fail" denied the request: ...`, followed by
+// a `require-team:` line for every policy and indented ones for their rules
+// `admission webhook "policy.example.com" denied the request: ...`, as the webhook
+fn denials(message: &str) -> Vec<String> {
+    if let Some((_, rest)) = message.split_once("violates PodSecurity \"") {
+        let level = rest.split(['"', ':']).next().unwrap_or_default();
+        return vec![format!("PodSecurity:{}", level)];
+    }
+    if let Some((_, rest)) = message.split_once("ValidatingAdmissionPolicy '") {
+        return rest
+            .split_once('\'')
+            .map(|(policy, _)| vec![policy.to_string()])
+            .unwrap_or_default();
+    }
+    let Some((_, rest)) = message.split_once("admission webhook \"") else {
+        return Vec::new();
+    };
+    let Some((webhook, rest)) = rest.split_once('"') else {
+        return Vec::new();
+    };
+    let Some((_, reasons)) = rest.split_once("denied the request: ") else {
+        return Vec::new();
+    };
+    let policies = match webhook {
+        GATEKEEPER => reasons
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix('['))
+            .filter_map(|line| line.split_once(']').map(|(constraint, _)| constraint))
+            .map(str::to_string)
+            .collect::<Vec<_>>(),
+        webhook if webhook.starts_with(KYVERNO) => reasons
+            .lines()
+            .skip_while(|line| !line.contains("blocked due to the following policies"))
+            .skip(1)
+            .filter(|line| !line.starts_with(char::is_whitespace))
+            .filter_map(|line| line.trim_end().strip_suffix(':'))
+            .map(str::to_string)
+            .collect(),
+        _ => Vec::new(),
+    };
+    match policies.is_empty() {
+        true => vec![webhook.to_string()],
+        false => policies,
+    }
+}
+
+// the constraint and the namespace of the request a Gatekeeper admission event is
+// about, from its annotations. The requests of the controllers are left out, their
+// FailedCreate events being counted already.
+fn gatekeeper(event: &Event) -> Option<(String, Vec<String>)> {
+    let annotations = event.metadata.annotations.as_ref()?;
+    let annotation = |name: &str| annotations.get(name).map(String::as_str);
+    // the warn and dryrun constraints let the request through
+    if annotation("process") != Some("admission")
+        || annotation("constraint_action").is_some_and(|action| action != "deny")
+    {
+        return None;
+    }
+    if annotation("request_username").is_some_and(|user| user.starts_with(CONTROLLERS)) {
+        return None;
+    }
+    let constraint = annotation("constraint_name")?.to_string();
+    let namespace = annotation("resource_namespace").unwrap_or_default();
+    Some((namespace.to_string(), vec![constraint]))
+}
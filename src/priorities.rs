@@ -0,0 +1,151 @@
+use crate::metrics::{
+    PENDING_PODS_GAUGE, PREEMPTIONS_COUNTER, PREEMPTOR_PRIORITY_CLASS_LABEL, PRIORITY_CLASS_LABEL,
+    VICTIM_PRIORITY_CLASS_LABEL,
+};
+use axum_prometheus::metrics::{counter, gauge};
+use k8s_openapi::api::core::v1::{Event, Pod};
+use kube::ResourceExt;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+struct Tracked {
+    namespace: String,
+    name: String,
+    priority_class: String,
+    // not scheduled on a node yet
+    pending: bool,
+}
+
+#[derive(Default)]
+struct State {
+    pods: HashMap<String, Tracked>,
+    // the pending pods of each priority class, a class being kept at 0 once it's had some
+    pending: HashMap<String, usize>,
+}
+
+// who gets to run when the cluster is full: the pods waiting for a node by priority
+// class, and which classes preempt which. The priority class of both pods of a
+// preemption comes from here, the preemptor's event only has its uid.
+#[derive(Default)]
+pub struct Priorities {
+    state: Mutex<State>,
+}
+
+impl Priorities {
+    pub fn pod(&self, pod: &Pod) {
+        let Some(uid) = pod.uid() else {
+            return;
+        };
+        let spec = pod.spec.as_ref();
+        let phase = pod
+            .status
+            .as_ref()
+            .and_then(|status| status.phase.as_deref());
+        let tracked = Tracked {
+            namespace: pod.namespace().unwrap_or_default(),
+            name: pod.name_any(),
+            priority_class: spec
+                .and_then(|spec| spec.priority_class_name.clone())
+                .unwrap_or_default(),
+            pending: phase == Some("Pending")
+                && spec.is_none_or(|spec| spec.node_name.is_none())
+                && pod.metadata.deletion_timestamp.is_none(),
+        };
+        let mut state = self.lock();
+        state.remove(&uid);
+        state.add(uid, tracked);
+    }
+
+    pub fn gone(&self, uid: &str) {
+        self.lock().remove(uid);
+    }
+
+    // after a re-list, the pods that weren't listed again were deleted while we were away
+    pub fn retain(&self, listed: &HashSet<String>) {
+        let mut state = self.lock();
+        let gone = state
+            .pods
+            .keys()
+            .filter(|uid| !listed.contains(*uid))
+            .cloned()
+            .collect::<Vec<_>>();
+        for uid in gone {
+            state.remove(&uid);
+        }
+    }
+
+    // the scheduler tells the pod it's preempted who it's for, as one of
+    // "Preempted by pod <uid> on node <node>", "Preempted by <namespace>/<name> on node <node>"
+    // (before 1.26) or "Preempted by a pod on node <node>"
+    pub fn event(&self, event: &Event) {
+        if event.reason.as_deref() != Some("Preempted") {
+            return;
+        }
+        let message = event.message.as_deref().unwrap_or_default();
+        let preemptor = message
+            .strip_prefix("Preempted by ")
+            .and_then(|rest| rest.split_once(" on node"))
+            .map(|(preemptor, _)| preemptor)
+            .unwrap_or_default();
+        let state = self.lock();
+        let victim = event
+            .involved_object
+            .uid
+            .as_ref()
+            .and_then(|uid| state.pods.get(uid))
+            .map(|tracked| tracked.priority_class.clone());
+        let preemptor = match preemptor.strip_prefix("pod ") {
+            Some(uid) => state.pods.get(uid),
+            None => preemptor.split_once('/').and_then(|(namespace, name)| {
+                state
+                    .pods
+                    .values()
+                    .find(|tracked| tracked.namespace == namespace && tracked.name == name)
+            }),
+        }
+        .map(|tracked| tracked.priority_class.clone());
+        // a class can be empty too, unknown is for the pods we don't know
+        let class = |class: Option<String>| class.unwrap_or_else(|| "unknown".to_string());
+        counter!(
+            PREEMPTIONS_COUNTER,
+            &[
+                (VICTIM_PRIORITY_CLASS_LABEL, class(victim)),
+                (PREEMPTOR_PRIORITY_CLASS_LABEL, class(preemptor)),
+            ]
+        )
+        .increment(1);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("priorities lock poisoned")
+    }
+}
+
+impl State {
+    fn add(&mut self, uid: String, tracked: Tracked) {
+        if tracked.pending {
+            self.count(&tracked.priority_class, 1);
+        }
+        self.pods.insert(uid, tracked);
+    }
+
+    fn remove(&mut self, uid: &str) {
+        if let Some(tracked) = self.pods.remove(uid) {
+            if tracked.pending {
+                self.count(&tracked.priority_class, -1);
+            }
+        }
+    }
+
+    fn count(&mut self, priority_class: &str, change: isize) {
+        let pending = self.pending.entry(priority_class.to_string()).or_default();
+        *pending = pending.saturating_add_signed(change);
+        gauge!(
+            PENDING_PODS_GAUGE,
+            &[(PRIORITY_CLASS_LABEL, priority_class.to_string())]
+        )
+        .set(*pending as f64);
+    }
+}
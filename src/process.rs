@@ -0,0 +1,120 @@
+use crate::{
+    metrics::{
+        PROCESS_MAX_FDS_GAUGE, PROCESS_OPEN_FDS_GAUGE, PROCESS_RESIDENT_MEMORY_GAUGE,
+        PROCESS_START_TIME_GAUGE, PROCESS_THREADS_GAUGE, PROCESS_VIRTUAL_MEMORY_GAUGE,
+        TOKIO_ALIVE_TASKS_GAUGE, TOKIO_GLOBAL_QUEUE_GAUGE, TOKIO_WORKERS_GAUGE,
+    },
+    registry::PROCESS_CPU,
+};
+use axum_prometheus::metrics::gauge;
+use std::fs;
+
+// the clock ticks of /proc's cpu times, USER_HZ is 100 on every architecture
+const TICKS_PER_SECOND: f64 = 100.0;
+
+// what the operator's own process takes, read when /metrics is scraped so the pod's
+// resources can be sized from its own metrics. Only linux has a /proc, elsewhere
+// there are only the runtime's metrics.
+pub fn collect() {
+    if let Ok(status) = fs::read_to_string("/proc/self/status") {
+        for (field, metric) in [
+            ("VmRSS:", PROCESS_RESIDENT_MEMORY_GAUGE),
+            ("VmSize:", PROCESS_VIRTUAL_MEMORY_GAUGE),
+        ] {
+            if let Some(kib) = status_field(&status, field) {
+                gauge!(metric).set(kib * 1024.0);
+            }
+        }
+        if let Some(threads) = status_field(&status, "Threads:") {
+            gauge!(PROCESS_THREADS_GAUGE).set(threads);
+        }
+    }
+    if let Ok(stat) = fs::read_to_string("/proc/self/stat") {
+        // the fields after the command, which is in parentheses and can have spaces
+        let fields = stat
+            .rsplit_once(')')
+            .map(|(_, fields)| fields.split_whitespace().collect::<Vec<_>>())
+            .unwrap_or_default();
+        // utime and stime are the 14th and 15th fields, starttime the 22nd
+        let field = |number: usize| fields.get(number - 3).and_then(|f| f.parse::<f64>().ok());
+        if let (Some(user), Some(system)) = (field(14), field(15)) {
+            PROCESS_CPU.set(&[], (user + system) / TICKS_PER_SECOND);
+        }
+        if let (Some(start), Some(boot)) = (field(22), boot_time()) {
+            gauge!(PROCESS_START_TIME_GAUGE).set(boot + start / TICKS_PER_SECOND);
+        }
+    }
+    if let Ok(fds) = fs::read_dir("/proc/self/fd") {
+        gauge!(PROCESS_OPEN_FDS_GAUGE).set(fds.count() as f64);
+    }
+    if let Some(max) = max_fds() {
+        gauge!(PROCESS_MAX_FDS_GAUGE).set(max);
+    }
+
+    if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+        let metrics = runtime.metrics();
+        gauge!(TOKIO_WORKERS_GAUGE).set(metrics.num_workers() as f64);
+        gauge!(TOKIO_ALIVE_TASKS_GAUGE).set(metrics.num_alive_tasks() as f64);
+        gauge!(TOKIO_GLOBAL_QUEUE_GAUGE).set(metrics.global_queue_depth() as f64);
+    }
+
+    #[cfg(feature = "jemalloc")]
+    jemalloc();
+}
+
+// a field of /proc/self/status, like "VmRSS:   10240 kB"
+fn status_field(status: &str, field: &str) -> Option<f64> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix(field))?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+// when the machine booted, in seconds since the epoch
+fn boot_time() -> Option<f64> {
+    fs::read_to_string("/proc/stat")
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))?
+        .trim()
+        .parse()
+        .ok()
+}
+
+// the soft limit of open files, "Max open files  1048576  1048576  files"
+fn max_fds() -> Option<f64> {
+    fs::read_to_string("/proc/self/limits")
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("Max open files"))?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+// what the allocator holds, its stats being refreshed by advancing its epoch
+#[cfg(feature = "jemalloc")]
+fn jemalloc() {
+    use crate::metrics::{JEMALLOC_BYTES_GAUGE, STATE_LABEL};
+    use tikv_jemalloc_ctl::{epoch, stats};
+
+    if epoch::advance().is_err() {
+        return;
+    }
+    for (state, bytes) in [
+        ("allocated", stats::allocated::read()),
+        ("active", stats::active::read()),
+        ("resident", stats::resident::read()),
+        ("mapped", stats::mapped::read()),
+        ("retained", stats::retained::read()),
+        ("metadata", stats::metadata::read()),
+    ] {
+        if let Ok(bytes) = bytes {
+            gauge!(JEMALLOC_BYTES_GAUGE, &[(STATE_LABEL, state)]).set(bytes as f64);
+        }
+    }
+}
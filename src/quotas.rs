@@ -0,0 +1,139 @@
+use crate::{
+    config::WatcherSettings,
+    error::Error,
+    metrics::{NAMESPACE_LABEL, QUOTA_DENIALS_COUNTER, QUOTA_LABEL, RESOURCE_LABEL},
+    registry::{QUOTA_HARD, QUOTA_USED},
+    resources::quantity,
+    snapshot::SNAPSHOT,
+    watch::{pausable, watcher_config, WatcherBackoff},
+};
+use axum_prometheus::metrics::counter;
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::{Event, ResourceQuota};
+use kube::{
+    runtime::{watcher, WatchStreamExt},
+    Api, Client, ResourceExt,
+};
+use std::collections::HashSet;
+
+// counts the pods a controller couldn't create because of a ResourceQuota, by the
+// resources that got them denied. The quota admission says so in the FailedCreate
+// events of the controller, one of:
+// `exceeded quota: compute, requested: limits.cpu=2,limits.memory=1Gi, used: ..., limited: ...`
+// `failed quota: compute: must specify limits.cpu,limits.memory`
+pub fn handle_event(event: &Event) {
+    if event.reason.as_deref() != Some("FailedCreate") {
+        return;
+    }
+    let message = event.message.as_deref().unwrap_or_default();
+    let Some((quota, resources)) = denial(message) else {
+        return;
+    };
+    let namespace = event.involved_object.namespace.clone().unwrap_or_default();
+    for resource in resources {
+        counter!(
+            QUOTA_DENIALS_COUNTER,
+            &[
+                (NAMESPACE_LABEL, namespace.clone()),
+                (QUOTA_LABEL, quota.to_string()),
+                (RESOURCE_LABEL, resource.to_string()),
+            ]
+        )
+        .increment(1);
+    }
+}
+
+// the quota denying the pod and the resources it was denied for
+fn denial(message: &str) -> Option<(&str, Vec<&str>)> {
+    if let Some((_, rest)) = message.split_once("exceeded quota: ") {
+        let (quota, rest) = rest.split_once(", ")?;
+        let requested = rest.strip_prefix("requested: ")?;
+        let requested = requested.split(", ").next().unwrap_or_default();
+        let resources = requested
+            .split(',')
+            .filter_map(|resource| resource.split_once('=').map(|(name, _)| name))
+            .collect();
+        return Some((quota, resources));
+    }
+    let (_, rest) = message.split_once("failed quota: ")?;
+    let (quota, rest) = rest.split_once(": ")?;
+    let missing = rest.strip_prefix("must specify ")?;
+    Some((quota, missing.split(',').map(str::trim).collect()))
+}
+
+// watches the ResourceQuotas, for how much of each resource every namespace is using
+// out of what it's allowed
+pub async fn watch_quotas(client: Client, settings: &WatcherSettings) {
+    let api: Api<ResourceQuota> = Api::default_namespaced(client);
+    let backoff = WatcherBackoff::new("resourcequotas", &settings.backoff);
+    let mut stream = Box::pin(pausable(
+        "resourcequotas",
+        watcher(api, watcher_config(settings))
+            .backoff(backoff)
+            .inspect(|event| SNAPSHOT.watcher("resourcequotas", event)),
+    ));
+
+    let mut known = HashSet::new();
+    let mut relisted = HashSet::new();
+    while let Some(event) = stream.next().await {
+        match event {
+            Ok(watcher::Event::Init) => relisted.clear(),
+            Ok(watcher::Event::InitApply(quota)) => {
+                relisted.insert(key(&quota));
+                known.insert(key(&quota));
+                apply(&quota);
+            }
+            Ok(watcher::Event::InitDone) => {
+                // forget the quotas deleted while we were away
+                known.retain(|key| {
+                    if relisted.contains(key) {
+                        return true;
+                    }
+                    forget(key);
+                    false
+                });
+            }
+            Ok(watcher::Event::Apply(quota)) => {
+                known.insert(key(&quota));
+                apply(&quota);
+            }
+            Ok(watcher::Event::Delete(quota)) => {
+                known.remove(&key(&quota));
+                forget(&key(&quota));
+            }
+            Err(err) => Error::new("quotas", err).report(),
+        }
+    }
+}
+
+fn key(quota: &ResourceQuota) -> (String, String) {
+    (quota.namespace().unwrap_or_default(), quota.name_any())
+}
+
+// the status has what the quota controller last counted, the resources a quota
+// doesn't limit anymore going away with it
+fn apply(quota: &ResourceQuota) {
+    let key = key(quota);
+    forget(&key);
+    let Some(status) = quota.status.as_ref() else {
+        return;
+    };
+    for (family, values) in [(&*QUOTA_HARD, &status.hard), (&*QUOTA_USED, &status.used)] {
+        for (resource, value) in values.iter().flatten() {
+            family.set(
+                &[
+                    (NAMESPACE_LABEL, key.0.clone()),
+                    (QUOTA_LABEL, key.1.clone()),
+                    (RESOURCE_LABEL, resource.clone()),
+                ],
+                quantity(value),
+            );
+        }
+    }
+}
+
+fn forget(key: &(String, String)) {
+    for family in [&*QUOTA_HARD, &*QUOTA_USED] {
+        family.remove_where(&[(NAMESPACE_LABEL, &key.0), (QUOTA_LABEL, &key.1)]);
+    }
+}
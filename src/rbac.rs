@@ -0,0 +1,138 @@
+use crate::{
+    config::{Config, PermissionCheck},
+    error::ErrorKind,
+    manifests::{permissions, yaml, Permission},
+};
+use k8s_openapi::api::{
+    authorization::v1::{ResourceAttributes, SelfSubjectAccessReview, SelfSubjectAccessReviewSpec},
+    rbac::v1::{ClusterRole, Role},
+};
+use kube::{
+    api::{ObjectMeta, PostParams},
+    Api, Client,
+};
+use tracing::{error, info, warn};
+
+// asks the api server whether the operator's service account can do everything the
+// config needs, before anything is watched: a missing permission is a clear error
+// naming it then, rather than the watcher it breaks backing off forever
+pub async fn check(client: &Client, config: &Config) -> Result<(), ErrorKind> {
+    if config.kube.permission_check == PermissionCheck::Off {
+        return Ok(());
+    }
+    let namespace = client.default_namespace().to_string();
+    let mut missing = Vec::new();
+    for permission in permissions(config) {
+        let mut verbs = Vec::new();
+        for verb in permission.verbs {
+            match allowed(client, &permission, verb, &namespace).await {
+                Ok(true) => {}
+                Ok(false) => verbs.push(*verb),
+                // the reviews themselves are allowed to everyone, something else is wrong
+                Err(err) => {
+                    warn!("Could not check the operator's permissions: {}", err);
+                    return Ok(());
+                }
+            }
+        }
+        if !verbs.is_empty() {
+            missing.push((permission, verbs));
+        }
+    }
+    if missing.is_empty() {
+        info!("The service account has every permission the config needs");
+        return Ok(());
+    }
+
+    let names = missing
+        .iter()
+        .map(|(permission, verbs)| {
+            let scope = match permission.cluster {
+                true => "cluster-wide".to_string(),
+                false => format!("in namespace {}", namespace),
+            };
+            format!("{} {} ({})", verbs.join(", "), permission.name(), scope)
+        })
+        .collect::<Vec<_>>();
+    let message = format!(
+        "the service account is not allowed to {}, `k8rs manifests` prints the RBAC the config needs",
+        names.join("; ")
+    );
+    if config.kube.print_missing_role {
+        error!(
+            "Missing permissions, granted by:\n{}",
+            roles(&missing, &namespace)?
+        );
+    }
+    match config.kube.permission_check {
+        PermissionCheck::Warn => {
+            warn!("{}", message);
+            Ok(())
+        }
+        _ => Err(message.into()),
+    }
+}
+
+async fn allowed(
+    client: &Client,
+    permission: &Permission,
+    verb: &str,
+    namespace: &str,
+) -> Result<bool, kube::Error> {
+    let (resource, subresource) = match permission.resource.split_once('/') {
+        Some((resource, subresource)) => (resource, Some(subresource.to_string())),
+        None => (permission.resource, None),
+    };
+    let review = SelfSubjectAccessReview {
+        spec: SelfSubjectAccessReviewSpec {
+            resource_attributes: Some(ResourceAttributes {
+                group: Some(permission.api_group.to_string()),
+                resource: Some(resource.to_string()),
+                subresource,
+                verb: Some(verb.to_string()),
+                // every namespace for the cluster-wide ones
+                namespace: (!permission.cluster).then(|| namespace.to_string()),
+                ..ResourceAttributes::default()
+            }),
+            ..SelfSubjectAccessReviewSpec::default()
+        },
+        ..SelfSubjectAccessReview::default()
+    };
+    let api: Api<SelfSubjectAccessReview> = Api::all(client.clone());
+    let review = api.create(&PostParams::default(), &review).await?;
+    Ok(review.status.is_some_and(|status| status.allowed))
+}
+
+// a Role and a ClusterRole with only the missing rules, as a yaml stream
+fn roles(missing: &[(Permission, Vec<&str>)], namespace: &str) -> Result<String, ErrorKind> {
+    let rules = |cluster: bool| {
+        missing
+            .iter()
+            .filter(|(permission, _)| permission.cluster == cluster)
+            .map(|(permission, verbs)| permission.rule(verbs))
+            .collect::<Vec<_>>()
+    };
+    let mut documents = Vec::new();
+    let (rules_in_namespace, cluster_rules) = (rules(false), rules(true));
+    if !rules_in_namespace.is_empty() {
+        documents.push(yaml(&Role {
+            metadata: ObjectMeta {
+                name: Some("k8rs".to_string()),
+                namespace: Some(namespace.to_string()),
+                ..ObjectMeta::default()
+            },
+            rules: Some(rules_in_namespace),
+        })?);
+    }
+    if !cluster_rules.is_empty() {
+        documents.push(yaml(&ClusterRole {
+            metadata: ObjectMeta {
+                name: Some(format!("{}-k8rs", namespace)),
+                ..ObjectMeta::default()
+            },
+            rules: Some(cluster_rules),
+            ..ClusterRole::default()
+        })?);
+    }
+    Ok(documents.join("---\n"))
+}
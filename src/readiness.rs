@@ -0,0 +1,86 @@
+use crate::{
+    clock,
+    config::ReadinessSettings,
+    metrics::{READY_GAUGE, SINK_BACKLOG_GAUGE, SINK_LABEL},
+    sinks::SinkRegistry,
+};
+use axum_prometheus::metrics::gauge;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// how often the sinks' backlogs are looked at
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// what /readyz answers: an exporter whose sinks can't keep up is not ready, so
+// kubernetes sends the traffic to the others until it caught up again
+pub struct Readiness {
+    sinks: SinkRegistry,
+    settings: ReadinessSettings,
+    // every sink seen, and since when its backlog is over the threshold
+    backlogged: Mutex<HashMap<String, Option<Instant>>>,
+}
+
+impl Readiness {
+    pub fn new(sinks: SinkRegistry, settings: &ReadinessSettings) -> Self {
+        Readiness {
+            sinks,
+            settings: settings.clone(),
+            backlogged: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // exports the backlogs every interval, keeping track of the sinks over the threshold
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.check(clock::instant());
+            let ready = self.drowning().is_empty();
+            gauge!(READY_GAUGE).set(if ready { 1.0 } else { 0.0 });
+        }
+    }
+
+    fn check(&self, now: Instant) {
+        let backlogs = self.sinks.backlogs();
+        let mut backlogged = self.backlogged.lock().expect("readiness lock poisoned");
+        // the sinks a reload removed don't hold anything anymore
+        backlogged.retain(|name, _| {
+            let kept = backlogs.iter().any(|(sink, _)| sink == name);
+            if !kept {
+                gauge!(SINK_BACKLOG_GAUGE, &[(SINK_LABEL, name.clone())]).set(0.0);
+            }
+            kept
+        });
+        for (name, backlog) in backlogs {
+            gauge!(SINK_BACKLOG_GAUGE, &[(SINK_LABEL, name.clone())]).set(backlog as f64);
+            let over = self
+                .settings
+                .max_sink_backlog
+                .is_some_and(|max| backlog > max);
+            let since = backlogged.entry(name).or_default();
+            *since = match since {
+                Some(since) if over => Some(*since),
+                _ if over => Some(now),
+                _ => None,
+            };
+        }
+    }
+
+    // the sinks over the threshold for longer than the grace, sorted
+    pub fn drowning(&self) -> Vec<String> {
+        let now = clock::instant();
+        let backlogged = self.backlogged.lock().expect("readiness lock poisoned");
+        let mut drowning = backlogged
+            .iter()
+            .filter(|(_, since)| {
+                since.is_some_and(|since| now.duration_since(since) >= self.settings.grace)
+            })
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        drowning.sort();
+        drowning
+    }
+}
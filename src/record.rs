@@ -0,0 +1,64 @@
+use crate::{enrich::Enricher, messages, pods, severity};
+use k8s_openapi::api::core::v1::Event;
+use kube::ResourceExt;
+
+// the records are built here, what's in them is up to the schema
+pub use crate::schema::EventRecord;
+
+impl EventRecord {
+    pub fn new(event: &Event, enricher: &Enricher) -> Self {
+        let object = &event.involved_object;
+        let timestamp = |time: Option<&k8s_openapi::apimachinery::pkg::apis::meta::v1::Time>| {
+            time.map(|time| {
+                time.0
+                    .to_rfc3339_opts(k8s_openapi::chrono::SecondsFormat::Millis, false)
+            })
+        };
+        let (node, zone) = enricher.node_and_zone(event);
+        let container = match object.field_path {
+            Some(_) => pods::event_container(event, enricher.pod(event).as_deref()),
+            None => None,
+        };
+        let non_empty = |value: String| Some(value).filter(|value| !value.is_empty());
+        let reason = event.reason.clone().unwrap_or_default();
+        let type_ = event.type_.clone().unwrap_or_default();
+        let severity = severity::of(&reason, &type_);
+
+        let mut record = EventRecord {
+            schema_version: Default::default(),
+            uid: event.uid().unwrap_or_default(),
+            namespace: event.namespace().unwrap_or_default(),
+            name: event.name_any(),
+            kind: object.kind.clone().unwrap_or_default(),
+            object_name: object.name.clone().unwrap_or_default(),
+            object_uid: object.uid.clone().unwrap_or_default(),
+            reason,
+            message: event.message.clone().unwrap_or_default(),
+            type_,
+            source: event
+                .source
+                .as_ref()
+                .and_then(|source| source.component.clone())
+                .or_else(|| event.reporting_component.clone())
+                .unwrap_or_default(),
+            first_timestamp: timestamp(event.first_timestamp.as_ref()),
+            last_timestamp: timestamp(event.last_timestamp.as_ref()),
+            count: event.count.unwrap_or(1),
+            node: non_empty(node),
+            zone: non_empty(zone),
+            owner: enricher.pod_context(event).and_then(|pod| pod.owner),
+            container: container.as_ref().map(|(name, _)| name.clone()),
+            container_type: container.map(|(_, container_type)| container_type.to_string()),
+            labels: enricher.record_labels(event),
+            aggregated: None,
+            summary: None,
+            logs: None,
+            context: None,
+            incident: None,
+            audit: None,
+            severity: Some(severity.as_str().to_string()),
+        };
+        record.summary = messages::render(&record);
+        record
+    }
+}
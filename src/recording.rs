@@ -0,0 +1,175 @@
+use crate::{
+    error::{Context, ErrorKind},
+    watch::EventSource,
+};
+use futures::{Stream, StreamExt};
+use k8s_openapi::{
+    api::core::v1::Event,
+    chrono::{DateTime, SecondsFormat, Utc},
+};
+use kube::runtime::watcher;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::warn;
+
+// a watcher::Event as it's written down, the errors aren't
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", content = "object")]
+enum Recorded {
+    Init,
+    InitApply(Event),
+    InitDone,
+    Apply(Event),
+    Delete(Event),
+}
+
+impl Recorded {
+    fn new(event: &watcher::Event<Event>) -> Self {
+        match event {
+            watcher::Event::Init => Recorded::Init,
+            watcher::Event::InitApply(event) => Recorded::InitApply(event.clone()),
+            watcher::Event::InitDone => Recorded::InitDone,
+            watcher::Event::Apply(event) => Recorded::Apply(event.clone()),
+            watcher::Event::Delete(event) => Recorded::Delete(event.clone()),
+        }
+    }
+
+    fn into_event(self) -> watcher::Event<Event> {
+        match self {
+            Recorded::Init => watcher::Event::Init,
+            Recorded::InitApply(event) => watcher::Event::InitApply(event),
+            Recorded::InitDone => watcher::Event::InitDone,
+            Recorded::Apply(event) => watcher::Event::Apply(event),
+            Recorded::Delete(event) => watcher::Event::Delete(event),
+        }
+    }
+}
+
+// a line of a recording: when the watcher handed out the event, and the event
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Line {
+    at: String,
+    event: Recorded,
+}
+
+// writes down everything the source's watch stream hands out, as it goes,
+// so what the operator saw can be fed back to it later
+pub struct WatchRecorder<S> {
+    source: S,
+    file: Arc<Mutex<File>>,
+}
+
+impl<S: EventSource> WatchRecorder<S> {
+    pub fn new(source: S, path: &Path) -> Result<Self, ErrorKind> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("could not open the watch recording {:?}", path))?;
+        Ok(WatchRecorder {
+            source,
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+}
+
+impl<S: EventSource> EventSource for WatchRecorder<S> {
+    fn events(&self) -> impl Stream<Item = Result<watcher::Event<Event>, watcher::Error>> + Send {
+        let file = self.file.clone();
+        self.source.events().inspect(move |event| {
+            let Ok(event) = event else {
+                return;
+            };
+            let line = Line {
+                at: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                event: Recorded::new(event),
+            };
+            let written = serde_json::to_string(&line)
+                .map_err(|err| err.to_string())
+                .and_then(|line| {
+                    let mut file = file.lock().expect("watch recording lock poisoned");
+                    writeln!(file, "{}", line).map_err(|err| err.to_string())
+                });
+            if let Err(err) = written {
+                warn!("Could not record a watch event: {}", err);
+            }
+        })
+    }
+
+    fn name(&self) -> String {
+        self.source.name()
+    }
+}
+
+// a recording fed back like the watcher handed it out, with the same time between
+// the events divided by the speed, or as fast as possible without one
+pub struct RecordedWatch {
+    lines: Vec<(Option<DateTime<Utc>>, Recorded)>,
+    speed: Option<f64>,
+}
+
+impl RecordedWatch {
+    pub fn load(path: &Path, speed: Option<f64>) -> Result<Self, ErrorKind> {
+        let file = File::open(path)
+            .with_context(|| format!("could not open the watch recording {:?}", path))?;
+        let mut lines = Vec::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<Line>(&line) {
+                Ok(line) => {
+                    let at = DateTime::parse_from_rfc3339(&line.at)
+                        .ok()
+                        .map(|at| at.to_utc());
+                    lines.push((at, line.event));
+                }
+                Err(err) => warn!("Skipping line {} of the recording: {}", number + 1, err),
+            }
+        }
+        Ok(RecordedWatch { lines, speed })
+    }
+
+    // how many events were recorded
+    pub fn count(&self) -> usize {
+        self.lines.len()
+    }
+
+    // when the first of them was
+    pub fn started(&self) -> Option<DateTime<Utc>> {
+        self.lines.iter().find_map(|(at, _)| *at)
+    }
+}
+
+impl EventSource for RecordedWatch {
+    fn events(&self) -> impl Stream<Item = Result<watcher::Event<Event>, watcher::Error>> + Send {
+        let speed = self.speed.filter(|speed| *speed > 0.0);
+        let mut previous: Option<DateTime<Utc>> = None;
+        let lines = self
+            .lines
+            .iter()
+            .cloned()
+            .map(move |(at, event)| {
+                let wait = match (speed, previous, at) {
+                    (Some(speed), Some(previous), Some(at)) => {
+                        (at - previous).to_std().unwrap_or_default().div_f64(speed)
+                    }
+                    _ => Duration::ZERO,
+                };
+                previous = at.or(previous);
+                (wait, event)
+            })
+            .collect::<Vec<_>>();
+        futures::stream::iter(lines).then(|(wait, event)| async move {
+            tokio::time::sleep(wait).await;
+            Ok(event.into_event())
+        })
+    }
+}
@@ -0,0 +1,615 @@
+use crate::{clock, metrics::Naming};
+use axum_prometheus::metrics::Label;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        LazyLock, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+// the global `counter!` macros can't forget a series once it's been created,
+// so the series we need to remove later (like those of a deleted EventMonitor)
+// live here instead, and get rendered along with the rest on /metrics
+pub static MONITOR_EVENTS: LazyLock<Family> = LazyLock::new(|| {
+    Family::counter(
+        crate::metrics::MONITOR_EVENTS_COUNTER,
+        "The number of events matched by each EventMonitor",
+    )
+});
+pub static WATCHER_BACKOFFS: LazyLock<Family> = LazyLock::new(|| {
+    Family::counter(
+        crate::metrics::WATCHER_BACKOFF_COUNTER,
+        "The number of times a watcher backed off after an api error",
+    )
+});
+// a series per pod, forgotten some time after the pod is gone (see stale.rs)
+pub static CREATED_PODS: LazyLock<Family> = LazyLock::new(|| {
+    Family::counter(
+        crate::metrics::POD_CREATE_COUNTER,
+        "The number of created pods",
+    )
+});
+pub static DELETED_PODS: LazyLock<Family> = LazyLock::new(|| {
+    Family::counter(
+        crate::metrics::POD_DELETE_COUNTER,
+        "The number of deleted pods",
+    )
+});
+// a series per ConfigMap and Secret, forgotten when it's deleted
+pub static CONFIG_CHANGES: LazyLock<Family> = LazyLock::new(|| {
+    Family::counter(
+        crate::metrics::CONFIG_CHANGES_COUNTER,
+        "The number of times each ConfigMap and Secret changed",
+    )
+});
+pub static CONFIG_INFO: LazyLock<Family> = LazyLock::new(|| {
+    Family::gauge(
+        crate::metrics::CONFIG_INFO_GAUGE,
+        "Always 1, with a hash of the current version of each ConfigMap and Secret",
+    )
+});
+// a series per tls Secret, forgotten when it's deleted
+pub static CERTIFICATE_EXPIRY: LazyLock<Family> = LazyLock::new(|| {
+    Family::gauge(
+        crate::metrics::CERTIFICATE_EXPIRY_GAUGE,
+        "When the certificate of each tls Secret expires, in seconds since the epoch",
+    )
+});
+// a series per PodDisruptionBudget, forgotten when it's deleted
+pub static DISRUPTIONS_ALLOWED: LazyLock<Family> = LazyLock::new(|| {
+    Family::gauge(
+        crate::metrics::DISRUPTIONS_ALLOWED_GAUGE,
+        "How many more pods each PodDisruptionBudget lets be evicted right now",
+    )
+});
+// a series per resource of each ResourceQuota, forgotten when it's deleted
+pub static QUOTA_HARD: LazyLock<Family> = LazyLock::new(|| {
+    Family::gauge(
+        crate::metrics::QUOTA_HARD_GAUGE,
+        "How much of each resource a ResourceQuota allows its namespace",
+    )
+});
+pub static QUOTA_USED: LazyLock<Family> = LazyLock::new(|| {
+    Family::gauge(
+        crate::metrics::QUOTA_USED_GAUGE,
+        "How much of each resource of a ResourceQuota its namespace uses",
+    )
+});
+// a series per node being drained, forgotten once it's drained (or uncordoned)
+pub static NODE_DRAIN_REMAINING: LazyLock<Family> = LazyLock::new(|| {
+    Family::gauge(
+        crate::metrics::NODE_DRAIN_REMAINING_GAUGE,
+        "The number of pods left to evict from each node being drained",
+    )
+});
+// a series per image of each namespace, forgotten with the last container running it
+pub static IMAGE_INFO: LazyLock<Family> = LazyLock::new(|| {
+    Family::gauge(
+        crate::metrics::IMAGE_INFO_GAUGE,
+        "The number of containers running each image, by tag and digest",
+    )
+});
+// the events watchers of the discovered namespaces, forgotten with them
+pub static NAMESPACE_WATCHER_AGE: LazyLock<Family> = LazyLock::new(|| {
+    Family::gauge(
+        crate::metrics::NAMESPACE_WATCHER_AGE_GAUGE,
+        "How long ago the events watcher of each discovered namespace last saw an event",
+    )
+});
+pub static NAMESPACE_WATCHER_HEALTHY: LazyLock<Family> = LazyLock::new(|| {
+    Family::gauge(
+        crate::metrics::NAMESPACE_WATCHER_HEALTHY_GAUGE,
+        "Whether the events watcher of each discovered namespace is healthy (1) or failing (0)",
+    )
+});
+pub static NAMESPACE_WATCHER_ERRORS: LazyLock<Family> = LazyLock::new(|| {
+    Family::counter(
+        crate::metrics::NAMESPACE_WATCHER_ERRORS_COUNTER,
+        "The number of api errors of the events watcher of each discovered namespace",
+    )
+});
+pub static NAMESPACE_WATCHER_RESTARTS: LazyLock<Family> = LazyLock::new(|| {
+    Family::counter(
+        crate::metrics::NAMESPACE_WATCHER_RESTARTS_COUNTER,
+        "The number of times the events watcher of each discovered namespace was restarted",
+    )
+});
+pub static LAST_EVENT: LazyLock<Family> = LazyLock::new(|| {
+    Family::gauge(
+        crate::metrics::LAST_EVENT_GAUGE,
+        "When the last event of each pod happened, in seconds since the epoch",
+    )
+});
+
+// the counter! macros only count whole numbers
+pub static PROCESS_CPU: LazyLock<Family> = LazyLock::new(|| {
+    Family::counter(
+        crate::metrics::PROCESS_CPU_COUNTER,
+        "The cpu time the operator's process used, in seconds",
+    )
+});
+pub static EXEMPLARS: LazyLock<Exemplars> = LazyLock::new(Exemplars::default);
+
+pub type Labels = Vec<(String, String)>;
+
+// a counter or a gauge with a set of series we can add to and remove from
+pub struct Family {
+    name: &'static str,
+    help: &'static str,
+    kind: &'static str,
+    series: Mutex<BTreeMap<Labels, Series>>,
+}
+
+struct Series {
+    value: f64,
+    touched: Instant,
+}
+
+impl Family {
+    pub fn counter(name: &'static str, help: &'static str) -> Self {
+        Family {
+            name,
+            help,
+            kind: "counter",
+            series: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn gauge(name: &'static str, help: &'static str) -> Self {
+        Family {
+            kind: "gauge",
+            ..Family::counter(name, help)
+        }
+    }
+
+    pub fn increment(&self, labels: &[(&str, String)], value: u64) {
+        self.update(labels, |series| series.value += value as f64);
+    }
+
+    pub fn set(&self, labels: &[(&str, String)], value: f64) {
+        self.update(labels, |series| series.value = value);
+    }
+
+    // for the gauges that only go up, like a timestamp, whatever order the updates come in
+    pub fn set_max(&self, labels: &[(&str, String)], value: f64) {
+        self.update(labels, |series| series.value = series.value.max(value));
+    }
+
+    fn update(&self, labels: &[(&str, String)], update: impl FnOnce(&mut Series)) {
+        let labels = labels
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect::<Labels>();
+        let mut series = self.lock();
+        let series = series.entry(labels).or_insert_with(|| Series {
+            value: 0.0,
+            touched: clock::instant(),
+        });
+        update(series);
+        series.touched = clock::instant();
+    }
+
+    // the same with the labels of the `counter!` macros
+    pub fn increment_labels(&self, labels: &[Label], value: u64) {
+        let labels = labels
+            .iter()
+            .map(|label| (label.key(), label.value().to_string()))
+            .collect::<Vec<_>>();
+        self.increment(&labels, value);
+    }
+
+    // removes every series that has the given label value, returning how many
+    pub fn remove(&self, label: &str, value: &str) -> usize {
+        self.remove_where(&[(label, value)])
+    }
+
+    // the same for the series having all of the given label values
+    pub fn remove_where(&self, values: &[(&str, &str)]) -> usize {
+        let mut series = self.lock();
+        let before = series.len();
+        series.retain(|labels, _| {
+            !values
+                .iter()
+                .all(|(label, value)| labels.iter().any(|(name, v)| name == label && v == value))
+        });
+        before - series.len()
+    }
+
+    // removes the series that weren't updated for so long, returning their labels
+    pub fn remove_idle(&self, idle: Duration) -> Vec<Labels> {
+        let now = clock::instant();
+        let mut series = self.lock();
+        let mut removed = Vec::new();
+        series.retain(|labels, series| {
+            if now.duration_since(series.touched) < idle {
+                return true;
+            }
+            removed.push(labels.clone());
+            false
+        });
+        removed
+    }
+
+    // the series as /metrics has them, under their renamed labels. Those metrics.relabel
+    // gives the same labels are added up.
+    fn relabeled(&self, naming: &Naming, name: &str) -> BTreeMap<Labels, f64> {
+        let mut relabeled = BTreeMap::<Labels, f64>::new();
+        for (labels, Series { value, .. }) in self.lock().iter() {
+            let labels = labels
+                .iter()
+                .map(|(label, value)| Label::new(naming.label(label).into_owned(), value.clone()))
+                .collect();
+            let Some(labels) = naming.relabel(name, labels) else {
+                continue;
+            };
+            let labels = labels
+                .iter()
+                .map(|label| (label.key().to_string(), label.value().to_string()))
+                .collect();
+            *relabeled.entry(labels).or_default() += value;
+        }
+        relabeled
+    }
+
+    fn render(&self, naming: &Naming, out: &mut String) {
+        let name = naming.metric(self.name);
+        let relabeled = self
+            .relabeled(naming, &name)
+            .into_iter()
+            .map(|(labels, value)| {
+                let labels = labels
+                    .iter()
+                    .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                (labels, value)
+            })
+            .collect::<BTreeMap<_, _>>();
+        if relabeled.is_empty() {
+            return;
+        }
+        let _ = writeln!(out, "# HELP {} {}", name, self.help);
+        let _ = writeln!(out, "# TYPE {} {}", name, self.kind);
+        for (labels, value) in relabeled {
+            let _ = match labels.is_empty() {
+                true => writeln!(out, "{} {}", name, value),
+                false => writeln!(out, "{}{{{}}} {}", name, labels, value),
+            };
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<Labels, Series>> {
+        self.series.lock().expect("metric family lock poisoned")
+    }
+}
+
+// the last event counted on each series of some counters, served as their exemplars in
+// the openmetrics format. Only kept once enabled, with metrics.exemplars.
+#[derive(Default)]
+pub struct Exemplars {
+    enabled: AtomicBool,
+    // by metric and labels, as they are rendered (sorted, with escaped values)
+    series: Mutex<HashMap<(String, Labels), Exemplar>>,
+}
+
+struct Exemplar {
+    event_uid: String,
+    // the seconds since the epoch
+    timestamp: f64,
+}
+
+impl Exemplars {
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    // called along with the counter's increment, with the same labels
+    pub fn record(&self, metric: &str, labels: &[Label], event_uid: &str) {
+        if !self.enabled() || event_uid.is_empty() {
+            return;
+        }
+        let naming = crate::metrics::naming();
+        let metric = naming.metric(metric).into_owned();
+        let labels = labels
+            .iter()
+            .map(|label| {
+                Label::new(
+                    naming.label(label.key()).into_owned(),
+                    label.value().to_string(),
+                )
+            })
+            .collect();
+        let Some(labels) = naming.relabel(&metric, labels) else {
+            return;
+        };
+        let mut labels = labels
+            .iter()
+            .map(|label| (label.key().to_string(), escape(label.value())))
+            .collect::<Labels>();
+        labels.sort();
+        let exemplar = Exemplar {
+            event_uid: escape(event_uid),
+            timestamp: clock::now().timestamp_millis() as f64 / 1000.0,
+        };
+        self.lock().insert((metric, labels), exemplar);
+    }
+
+    // forgets the exemplars of the series with the given label value, along with them
+    pub fn remove(&self, label: &str, value: &str) {
+        let naming = crate::metrics::naming();
+        let (label, value) = (naming.label(label), escape(value));
+        self.lock()
+            .retain(|(_, labels), _| !labels.iter().any(|(name, v)| *name == label && *v == value));
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<(String, Labels), Exemplar>> {
+        self.series.lock().expect("exemplars lock poisoned")
+    }
+}
+
+// the prometheus text format of /metrics as openmetrics, with the exemplars on their
+// counters. Openmetrics counters end in _total, so those that don't get it added.
+pub fn openmetrics(text: &str) -> String {
+    let counters = text
+        .lines()
+        .filter_map(|line| line.strip_prefix("# TYPE "))
+        .filter_map(|line| line.strip_suffix(" counter"))
+        .collect::<Vec<_>>();
+    let exemplars = EXEMPLARS.lock();
+    let mut out = String::with_capacity(text.len());
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(comment) = line.strip_prefix("# ") {
+            // the family of a counter is named without its _total
+            let mut words = comment.splitn(3, ' ');
+            let (Some(kind), Some(name)) = (words.next(), words.next()) else {
+                continue;
+            };
+            let rest = words.next().map(|rest| format!(" {}", rest));
+            let name = if counters.contains(&name) {
+                name.strip_suffix("_total").unwrap_or(name)
+            } else {
+                name
+            };
+            let _ = writeln!(out, "# {} {}{}", kind, name, rest.unwrap_or_default());
+            continue;
+        }
+        let name_end = line.find(['{', ' ']).unwrap_or(line.len());
+        let (name, rest) = line.split_at(name_end);
+        if !counters.contains(&name) {
+            let _ = writeln!(out, "{}", line);
+            continue;
+        }
+        let family = name.strip_suffix("_total").unwrap_or(name);
+        let _ = write!(out, "{}_total{}", family, rest);
+        let mut labels = parse_labels(rest);
+        labels.sort();
+        match exemplars.get(&(family.to_string(), labels)) {
+            Some(exemplar) => {
+                let _ = writeln!(
+                    out,
+                    " # {{event_uid=\"{}\"}} 1 {:.3}",
+                    exemplar.event_uid, exemplar.timestamp
+                );
+            }
+            None => out.push('\n'),
+        }
+    }
+    out.push_str("# EOF\n");
+    out
+}
+
+// the labels of a sample like {a="x",b="y\"z"} 1, with their values still escaped
+fn parse_labels(sample: &str) -> Labels {
+    let mut labels = Labels::new();
+    let Some(mut rest) = sample.strip_prefix('{') else {
+        return labels;
+    };
+    while let Some((name, after)) = rest.split_once("=\"") {
+        let mut end = None;
+        let mut escaped = false;
+        for (i, c) in after.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => {
+                    end = Some(i);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let Some(end) = end else {
+            break;
+        };
+        labels.push((
+            name.trim_start_matches(',').to_string(),
+            after[..end].to_string(),
+        ));
+        rest = &after[end + 1..];
+        if rest.starts_with('}') {
+            break;
+        }
+    }
+    labels
+}
+
+// every family, in the order /metrics has them
+fn families() -> [&'static Family; 18] {
+    [
+        &*MONITOR_EVENTS,
+        &*WATCHER_BACKOFFS,
+        &*CREATED_PODS,
+        &*DELETED_PODS,
+        &*LAST_EVENT,
+        &*CONFIG_CHANGES,
+        &*CONFIG_INFO,
+        &*CERTIFICATE_EXPIRY,
+        &*DISRUPTIONS_ALLOWED,
+        &*QUOTA_HARD,
+        &*QUOTA_USED,
+        &*IMAGE_INFO,
+        &*NODE_DRAIN_REMAINING,
+        &*NAMESPACE_WATCHER_AGE,
+        &*NAMESPACE_WATCHER_HEALTHY,
+        &*NAMESPACE_WATCHER_ERRORS,
+        &*NAMESPACE_WATCHER_RESTARTS,
+        &*PROCESS_CPU,
+    ]
+}
+
+pub fn render() -> String {
+    let mut out = String::new();
+    for family in families() {
+        family.render(crate::metrics::naming(), &mut out);
+    }
+    out
+}
+
+// the series of the family /metrics has under the name, for the alert rules' exprs
+pub fn select(name: &str) -> Vec<(Labels, f64)> {
+    families()
+        .into_iter()
+        .filter(|family| crate::metrics::naming().metric(family.name) == name)
+        .flat_map(|family| family.relabeled(crate::metrics::naming(), name))
+        .collect()
+}
+
+// label values can't have raw backslashes, quotes or newlines
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::ManualClock, config::MetricSettings, metrics::test_recorder};
+    use k8s_openapi::chrono::{DateTime, TimeDelta, Utc};
+    use std::sync::Arc;
+
+    #[test]
+    fn removes_the_series_idle_for_so_long() {
+        let start = Utc::now();
+        let manual = Arc::new(ManualClock::new(start));
+        clock::set_local(manual.clone());
+        let family = Family::counter("test_events_total", "Events of the test");
+        family.increment(&[("reason", "BackOff".to_string())], 1);
+        manual.advance_to(start + TimeDelta::minutes(10));
+        family.increment(&[("reason", "Pulled".to_string())], 1);
+
+        manual.advance_to(start + TimeDelta::minutes(15));
+        let removed = family.remove_idle(Duration::from_secs(600));
+        assert_eq!(
+            removed,
+            vec![vec![("reason".to_string(), "BackOff".to_string())]]
+        );
+        assert!(family.remove_idle(Duration::from_secs(600)).is_empty());
+
+        manual.advance_to(start + TimeDelta::minutes(20));
+        assert_eq!(family.remove_idle(Duration::from_secs(600)).len(), 1);
+    }
+
+    #[test]
+    fn serves_the_exemplars_over_openmetrics() {
+        test_recorder();
+        let at = "2024-05-01T10:00:00.250Z".parse::<DateTime<Utc>>().unwrap();
+        clock::set_local(Arc::new(ManualClock::new(at)));
+        EXEMPLARS.enable();
+        let labels = |pod: &str| {
+            [
+                Label::new("namespace", "shop"),
+                Label::new("pod", pod.to_string()),
+            ]
+        };
+        EXEMPLARS.record("test_exemplar_pods", &labels("web-\"1\""), "5d1c9a2e");
+        // the events without a uid don't make one
+        EXEMPLARS.record("test_exemplar_pods", &labels("web-2"), "");
+
+        let text = r#"# HELP test_exemplar_pods Pods of the test
+# TYPE test_exemplar_pods counter
+test_exemplar_pods{namespace="shop",pod="web-\"1\""} 3
+test_exemplar_pods{namespace="shop",pod="web-2"} 1
+
+# HELP test_exemplar_restarts_total Restarts of the test
+# TYPE test_exemplar_restarts_total counter
+test_exemplar_restarts_total 4
+# HELP test_exemplar_nodes Nodes of the test
+# TYPE test_exemplar_nodes gauge
+test_exemplar_nodes 2
+"#;
+        assert_eq!(
+            openmetrics(text),
+            r#"# HELP test_exemplar_pods Pods of the test
+# TYPE test_exemplar_pods counter
+test_exemplar_pods_total{namespace="shop",pod="web-\"1\""} 3 # {event_uid="5d1c9a2e"} 1 1714557600.250
+test_exemplar_pods_total{namespace="shop",pod="web-2"} 1
+# HELP test_exemplar_restarts Restarts of the test
+# TYPE test_exemplar_restarts counter
+test_exemplar_restarts_total 4
+# HELP test_exemplar_nodes Nodes of the test
+# TYPE test_exemplar_nodes gauge
+test_exemplar_nodes 2
+# EOF
+"#
+        );
+
+        // gone with the pod
+        EXEMPLARS.remove("pod", "web-\"1\"");
+        assert!(!openmetrics(text).contains("event_uid"));
+    }
+
+    #[test]
+    fn renders_the_series_as_relabeled() {
+        let settings = serde_yaml::from_str::<MetricSettings>(
+            r#"
+            prefix: k8rs
+            labels: {namespace: ns}
+            relabel:
+              - {action: drop, source_labels: [ns], regex: kube-system}
+              - {action: labeldrop, regex: pod}
+              - {action: replace, source_labels: [ns], regex: shop, target_label: team, replacement: payments}
+            "#,
+        )
+        .unwrap();
+        let naming = Naming::new(&settings).unwrap();
+        let family = Family::counter("test_relabel_pods", "Pods of the test");
+        let pod = |namespace: &str, pod: &str| {
+            [
+                ("namespace", namespace.to_string()),
+                ("pod", pod.to_string()),
+            ]
+        };
+        family.increment(&pod("shop", "web-1"), 2);
+        family.increment(&pod("shop", "web-2"), 3);
+        family.increment(&pod("kube-system", "dns"), 1);
+
+        let mut out = String::new();
+        family.render(&naming, &mut out);
+        // the pods added up, without the dropped ones
+        assert_eq!(
+            out,
+            r#"# HELP k8rs_test_relabel_pods Pods of the test
+# TYPE k8rs_test_relabel_pods counter
+k8rs_test_relabel_pods{ns="shop",team="payments"} 5
+"#
+        );
+
+        // nothing at all once every series is dropped
+        family.remove("namespace", "shop");
+        let mut out = String::new();
+        family.render(&naming, &mut out);
+        assert!(out.is_empty());
+    }
+}
@@ -0,0 +1,118 @@
+use crate::{
+    config::{RelabelAction, RelabelRule},
+    metrics::is_label_name,
+};
+use axum_prometheus::metrics::Label;
+use regex::Regex;
+
+// the label the source labels can take the metric's name from
+const NAME_LABEL: &str = "__name__";
+
+// the rules of metrics.relabel, compiled
+#[derive(Default)]
+pub struct Relabeling {
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    action: RelabelAction,
+    source_labels: Vec<String>,
+    separator: String,
+    regex: Regex,
+    target_label: String,
+    replacement: String,
+}
+
+impl Relabeling {
+    // the regexes were already checked when loading the config
+    pub fn new(rules: &[RelabelRule]) -> Result<Self, regex::Error> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                Ok(Rule {
+                    action: rule.action,
+                    source_labels: rule.source_labels.clone(),
+                    separator: rule.separator.clone(),
+                    // like prometheus, the whole value has to match
+                    regex: Regex::new(&format!("^(?:{})$", rule.regex))?,
+                    target_label: rule.target_label.clone().unwrap_or_default(),
+                    replacement: rule.replacement.clone(),
+                })
+            })
+            .collect::<Result<Vec<_>, regex::Error>>()?;
+        Ok(Relabeling { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // the labels of a series after the rules, None when it's dropped. The labels a
+    // rule would give an invalid name to are left as they were.
+    pub fn apply(&self, name: &str, mut labels: Vec<Label>) -> Option<Vec<Label>> {
+        for rule in self.rules.iter() {
+            match rule.action {
+                RelabelAction::Replace => {
+                    let value = rule.source(name, &labels);
+                    let Some(captures) = rule.regex.captures(&value) else {
+                        continue;
+                    };
+                    let mut replaced = String::new();
+                    captures.expand(&rule.replacement, &mut replaced);
+                    labels.retain(|label| label.key() != rule.target_label);
+                    if !replaced.is_empty() {
+                        labels.push(Label::new(rule.target_label.clone(), replaced));
+                    }
+                }
+                RelabelAction::Labelmap => {
+                    let mut mapped = Vec::new();
+                    for label in labels.iter() {
+                        let Some(captures) = rule.regex.captures(label.key()) else {
+                            continue;
+                        };
+                        let mut renamed = String::new();
+                        captures.expand(&rule.replacement, &mut renamed);
+                        if is_label_name(&renamed) && renamed != label.key() {
+                            mapped.push(Label::new(renamed, label.value().to_string()));
+                        }
+                    }
+                    for label in mapped {
+                        labels.retain(|known| known.key() != label.key());
+                        labels.push(label);
+                    }
+                }
+                RelabelAction::Labeldrop => {
+                    labels.retain(|label| !rule.regex.is_match(label.key()));
+                }
+                RelabelAction::Keep => {
+                    if !rule.regex.is_match(&rule.source(name, &labels)) {
+                        return None;
+                    }
+                }
+                RelabelAction::Drop => {
+                    if rule.regex.is_match(&rule.source(name, &labels)) {
+                        return None;
+                    }
+                }
+            }
+        }
+        Some(labels)
+    }
+}
+
+impl Rule {
+    // the values of the source labels joined, empty for the ones the series doesn't have
+    fn source(&self, name: &str, labels: &[Label]) -> String {
+        self.source_labels
+            .iter()
+            .map(|source| match source.as_str() {
+                NAME_LABEL => name,
+                source => labels
+                    .iter()
+                    .find(|label| label.key() == source)
+                    .map_or("", |label| label.value()),
+            })
+            .collect::<Vec<_>>()
+            .join(&self.separator)
+    }
+}
@@ -0,0 +1,331 @@
+use crate::{
+    admin,
+    config::{Cli, Config},
+    controller,
+    enrich::Enricher,
+    error::Error,
+    features, handover, maintenance, memory, messages, payloads, severity,
+    sinks::{Dispatcher, SinkRegistry},
+    snapshot::SNAPSHOT,
+    watch::{pausable, watcher_config, WatcherBackoff},
+};
+use futures::{stream, StreamExt};
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::{
+    runtime::{watcher, WatchStreamExt},
+    Api, Client,
+};
+use std::{collections::HashSet, sync::Arc};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+use tracing::{info, warn};
+
+// applies new configs to the running operator, when we get a SIGHUP or the config's
+// ConfigMap changes. Only the enrichment, the sinks and the messages can change on the fly,
+// everything else still needs a restart.
+pub struct Reloader {
+    cli: Cli,
+    // the config we're running with
+    config: Config,
+    sinks: SinkRegistry,
+    enricher: Enricher,
+    // where the pod pipeline picks its dispatcher from
+    dispatcher: watch::Sender<Dispatcher>,
+    monitors: Option<Arc<controller::Context>>,
+}
+
+impl Reloader {
+    pub fn new(
+        cli: Cli,
+        config: Config,
+        sinks: SinkRegistry,
+        enricher: Enricher,
+        dispatcher: watch::Sender<Dispatcher>,
+        monitors: Option<Arc<controller::Context>>,
+    ) -> Self {
+        Reloader {
+            cli,
+            config,
+            sinks,
+            enricher,
+            dispatcher,
+            monitors,
+        }
+    }
+
+    pub async fn run(mut self, client: Client) {
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(err) => {
+                Error::new("reload", err).on("SIGHUP").report();
+                warn!("Reloads are disabled");
+                return;
+            }
+        };
+
+        // the watcher sends the ConfigMap as it is when it starts, that's the same config
+        // we're already running with (if it's the one mounted as the config file)
+        let reload = self.config.reload.clone();
+        let mut config_maps = match reload.config_map {
+            Some(ref name) => {
+                let api: Api<ConfigMap> = Api::default_namespaced(client);
+                let config =
+                    watcher_config(&self.config.watcher).fields(&format!("metadata.name={}", name));
+                pausable(
+                    "configmap",
+                    watcher(api, config)
+                        .backoff(WatcherBackoff::new(
+                            "configmap",
+                            &self.config.watcher.backoff,
+                        ))
+                        .inspect(|event| SNAPSHOT.watcher("configmap", event)),
+                )
+                .applied_objects()
+                .boxed()
+            }
+            None => stream::pending().boxed(),
+        };
+
+        loop {
+            tokio::select! {
+                _ = hangups.recv() => {
+                    info!("SIGHUP received, reloading the config");
+                    // the error isn't Send, so it can't be kept around while applying
+                    match Config::load(&self.cli).map_err(|err| err.to_string()) {
+                        Ok(config) => self.apply(config).await,
+                        Err(err) => Error::config("reload", err).on("config").report(),
+                    }
+                }
+                Some(config_map) = config_maps.next() => {
+                    let config_map = match config_map {
+                        Ok(config_map) => config_map,
+                        Err(err) => {
+                            Error::new("reload", err).on(reload.config_map.clone().unwrap_or_default()).report();
+                            continue;
+                        }
+                    };
+                    let name = reload.config_map.clone().unwrap_or_default();
+                    let Some(contents) = config_map
+                        .data
+                        .as_ref()
+                        .and_then(|data| data.get(&reload.key))
+                    else {
+                        warn!("ConfigMap {} has no {} key, ignoring it", name, reload.key);
+                        continue;
+                    };
+                    let origin = format!("ConfigMap {} key {}", name, reload.key);
+                    match Config::from_yaml(contents, &origin, &self.cli).map_err(|err| err.to_string()) {
+                        Ok(config) => self.apply(config).await,
+                        Err(err) => Error::config("reload", err).on(origin).report(),
+                    }
+                }
+            }
+        }
+    }
+
+    // switches the running operator to the new config, touching only what changed
+    async fn apply(&mut self, mut config: Config) {
+        let old = &self.config;
+        if config == *old {
+            return;
+        }
+
+        if config.enrichment != old.enrichment {
+            self.enricher.reload(&config.enrichment);
+            info!("Reloaded the enrichment settings");
+        }
+
+        if config.features != old.features {
+            features::configure(&config.features);
+            info!("Reloaded the feature flags");
+        }
+
+        if config.memory != old.memory {
+            memory::configure(&config.memory);
+            info!("Reloaded the memory limits");
+        }
+
+        if config.payloads != old.payloads {
+            payloads::configure(&config.payloads);
+            info!("Reloaded the payload limits");
+        }
+
+        if config.maintenance != old.maintenance {
+            match maintenance::configure(&config.maintenance) {
+                Ok(()) => info!("Reloaded the maintenance windows"),
+                Err(err) => {
+                    Error::config("reload", keeping(err))
+                        .on("maintenance")
+                        .report();
+                    config.maintenance = old.maintenance.clone();
+                }
+            }
+        }
+
+        if config.messages != old.messages {
+            match messages::configure(&config.messages) {
+                Ok(()) => info!("Reloaded the message templates"),
+                Err(err) => {
+                    Error::config("reload", keeping(err))
+                        .on("messages")
+                        .report();
+                    config.messages = old.messages.clone();
+                }
+            }
+        }
+
+        if config.severities != old.severities {
+            severity::configure(&config.severities);
+            info!("Reloaded the severities");
+        }
+
+        let mut replaced = Dispatcher::default();
+        if config.sinks != old.sinks {
+            match self.sinks.reload(&old.sinks, &config.sinks) {
+                Ok(sinks) => {
+                    replaced = sinks;
+                    info!("Reloaded the sinks");
+                }
+                Err(err) => {
+                    Error::config("reload", keeping(err)).on("sinks").report();
+                    config.sinks = old.sinks.clone();
+                }
+            }
+        }
+
+        // the pod pipeline needs a new dispatcher if it's sending to a sink that changed
+        let changed = replaced.names().collect::<HashSet<_>>();
+        if config.pipeline.sinks != old.pipeline.sinks
+            || config
+                .pipeline
+                .sinks
+                .iter()
+                .any(|sink| changed.contains(sink))
+        {
+            match self.sinks.dispatcher(&config.pipeline.sinks) {
+                Ok(dispatcher) => {
+                    self.dispatcher.send_replace(dispatcher);
+                }
+                Err(err) => {
+                    Error::config("reload", keeping(err))
+                        .on("pipeline.sinks")
+                        .report();
+                    config.pipeline.sinks = old.pipeline.sinks.clone();
+                }
+            }
+        }
+        if let Some(ref monitors) = self.monitors {
+            monitors.pipelines.restart_with_sinks(&changed);
+        }
+        // nothing is going to deliver to the old sinks anymore
+        replaced.flush().await;
+        admin::set_filters("events", admin::pipeline_filters(&config));
+
+        let restarts = [
+            ("kube", config.kube != old.kube),
+            ("watcher", config.watcher != old.watcher),
+            (
+                "pipeline",
+                config.pipeline.capacity != old.pipeline.capacity
+                    || config.pipeline.overflow != old.pipeline.overflow
+                    || config.pipeline.workers != old.pipeline.workers
+                    || config.pipeline.log_sampling != old.pipeline.log_sampling
+                    || config.pipeline.aggregation != old.pipeline.aggregation
+                    || config.pipeline.per_object != old.pipeline.per_object
+                    || config.pipeline.priority != old.pipeline.priority,
+            ),
+            ("suppression", config.suppression != old.suppression),
+            ("monitors", config.monitors != old.monitors),
+            ("admission", config.admission != old.admission),
+            ("server", config.server != old.server),
+            ("reload", config.reload != old.reload),
+            ("admin", config.admin != old.admin),
+            ("snapshot", config.snapshot != old.snapshot),
+            ("top", config.top != old.top),
+            ("graph", config.graph != old.graph),
+            ("rollups", config.rollups != old.rollups),
+            ("alerts", config.alerts != old.alerts),
+            ("incidents", config.incidents != old.incidents),
+            ("anomalies", config.anomalies != old.anomalies),
+            ("namespaces", config.namespaces != old.namespaces),
+            ("services", config.services != old.services),
+            (
+                "config_changes",
+                config.config_changes != old.config_changes,
+            ),
+            ("certificates", config.certificates != old.certificates),
+            ("rbac_audit", config.rbac_audit != old.rbac_audit),
+            (
+                "disruption_budgets",
+                config.disruption_budgets != old.disruption_budgets,
+            ),
+            ("quotas", config.quotas != old.quotas),
+            ("spot", config.spot != old.spot),
+            ("drains", config.drains != old.drains),
+            (
+                "node_autoscaling",
+                config.node_autoscaling != old.node_autoscaling,
+            ),
+            ("node_events", config.node_events != old.node_events),
+            ("discovery", config.discovery != old.discovery),
+            (
+                "load_balancers",
+                config.load_balancers != old.load_balancers,
+            ),
+            ("autoscalers", config.autoscalers != old.autoscalers),
+            ("rollouts", config.rollouts != old.rollouts),
+            ("scripts", config.scripts != old.scripts),
+            ("plugins", config.plugins != old.plugins),
+            ("tenants", config.tenants != old.tenants),
+            ("sharding", config.sharding != old.sharding),
+            ("slos", config.slos != old.slos),
+            ("metrics", config.metrics != old.metrics),
+            (
+                "container_logs",
+                config.container_logs != old.container_logs,
+            ),
+            (
+                "failure_context",
+                config.failure_context != old.failure_context,
+            ),
+            ("artifacts", config.artifacts != old.artifacts),
+            ("runtime", config.runtime != old.runtime),
+            ("outbound", config.outbound != old.outbound),
+        ]
+        .into_iter()
+        .filter_map(|(section, restart)| restart.then_some(section))
+        .collect::<Vec<_>>();
+        if !restarts.is_empty() && config.server.handover.enabled {
+            // the restarted operator loads the config file, it has to be the one reloaded
+            // or it would restart again from the ConfigMap
+            match Config::load(&self.cli) {
+                Ok(loaded) if loaded == config => {
+                    info!(
+                        "The {} settings changed, restarting to apply them",
+                        restarts.join(", ")
+                    );
+                    handover::request();
+                }
+                _ => warn!(
+                    "The {} settings changed, but the config file isn't the config reloaded, they're only applied after a restart",
+                    restarts.join(", ")
+                ),
+            }
+        } else {
+            for section in restarts {
+                warn!(
+                    "The {} settings changed, they're only applied after a restart",
+                    section
+                );
+            }
+        }
+        self.config = config;
+    }
+}
+
+// a part of the new config that failed leaves the running one as it was
+fn keeping(err: impl std::fmt::Display) -> String {
+    format!("keeping the old ones: {}", err)
+}
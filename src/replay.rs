@@ -0,0 +1,355 @@
+use crate::{
+    clock::{self, ManualClock},
+    config::{Cli, Config, PipelineSettings, PrioritySettings},
+    degrade,
+    discovery::Kinds,
+    enrich::{Enricher, ZONE_LABEL},
+    error::{Context, ErrorKind},
+    logging::LogSampler,
+    maintenance, messages,
+    metrics::{
+        initialize_counters, install_recorder, KIND_LABEL, MONITOR_LABEL, NAMESPACE_LABEL,
+        REASON_LABEL,
+    },
+    monitor::{monitor_key, EventMonitor},
+    namespaces::NamespaceFilter,
+    outbound, payloads,
+    pipeline::{channel, handle_event, happened},
+    record::EventRecord,
+    recording::RecordedWatch,
+    registry::{self, MONITOR_EVENTS},
+    severity,
+    sinks::{Dispatcher, SinkRegistry},
+    watch::watch_events,
+};
+use clap::Args;
+use k8s_openapi::{
+    api::core::v1::{Event, EventSource, Node, ObjectReference, Pod, PodSpec},
+    apimachinery::pkg::apis::meta::v1::{ObjectMeta, OwnerReference, Time},
+    chrono::{DateTime, Utc},
+};
+use kube::runtime::{reflector, watcher};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    sync::Arc,
+};
+use tracing::{info, warn};
+
+// re-processes the event records archived by a file sink, without a cluster
+#[derive(Args, Debug, Clone)]
+pub struct ReplayArgs {
+    /// The JSONL archive written by a file sink
+    pub archive: PathBuf,
+
+    /// EventMonitor manifests whose filters the events are run through,
+    /// matches are counted on monitor_events_total
+    #[arg(long)]
+    pub monitor: Vec<PathBuf>,
+
+    /// Deliver the events to these sinks instead of the pipeline's sinks
+    #[arg(long)]
+    pub sink: Vec<String>,
+
+    /// Don't deliver the events to any sink, only compute the metrics
+    #[arg(long, conflicts_with = "sink")]
+    pub no_sinks: bool,
+
+    /// The archive is a watch stream recorded with --record-watch
+    /// instead of what a file sink wrote
+    #[arg(long)]
+    pub watch: bool,
+
+    /// Feed the recorded watch stream back this many times faster than it happened
+    /// (1 being the original speed), instead of as fast as possible
+    #[arg(long, requires = "watch")]
+    pub speed: Option<f64>,
+
+    /// Take the time of the windows, cooldowns and expiries from the wall clock
+    /// instead of the timestamps of the replayed events
+    #[arg(long)]
+    pub wall_clock: bool,
+}
+
+// replays the archive through the pod pipeline (metrics and sinks) of the config,
+// then prints the resulting metrics
+pub async fn run(cli: &Cli, args: &ReplayArgs) -> Result<(), ErrorKind> {
+    let config = Config::load(cli)?;
+    let metrics = install_recorder(&config.metrics)?;
+    initialize_counters();
+    messages::configure(&config.messages)?;
+    severity::configure(&config.severities);
+    outbound::configure(&config.outbound)?;
+    degrade::DEGRADATION.configure(&config.metrics.degradation);
+    maintenance::configure(&config.maintenance)?;
+    payloads::configure(&config.payloads);
+
+    let sinks = SinkRegistry::from_settings(&config.sinks, &config.suppression)?;
+    let dispatcher = if args.no_sinks {
+        sinks.dispatcher(&[])?
+    } else if !args.sink.is_empty() {
+        sinks.dispatcher(&args.sink)?
+    } else {
+        sinks.dispatcher(&config.pipeline.sinks)?
+    };
+
+    let monitors = args
+        .monitor
+        .iter()
+        .map(|path| {
+            let contents = std::fs::read_to_string(path)
+                .with_context(|| format!("could not read {:?}", path))?;
+            let mut monitor = serde_yaml::from_str::<EventMonitor>(&contents)
+                .with_context(|| format!("invalid EventMonitor {:?}", path))?;
+            // like kubectl apply would do
+            monitor
+                .metadata
+                .namespace
+                .get_or_insert_with(|| "default".to_string());
+            Ok::<_, ErrorKind>(monitor)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if args.watch {
+        // the events go through the same filtering as the cluster's, but nothing
+        // tells about their pods, so they aren't enriched
+        let recording = RecordedWatch::load(&args.archive, args.speed)?;
+        let (pods, _) = reflector::store::<Pod>();
+        let (nodes, _) = reflector::store::<Node>();
+        let enricher = Enricher::new(Arc::new(pods), Arc::new(nodes), &config.enrichment);
+        let clock = recorded_clock(args, recording.started());
+        let replay = Replay::new(&config, enricher, monitors, dispatcher, clock);
+        // in the order they were recorded, the urgent ones don't go ahead
+        let settings = PipelineSettings {
+            priority: PrioritySettings::default(),
+            ..config.pipeline.clone()
+        };
+        let (sender, mut events) = channel(&settings, &[]);
+        let recorded = recording.count();
+        tokio::spawn(watch_events(
+            recording,
+            Kinds::new(config.event_kinds()),
+            NamespaceFilter::new(&config.namespaces),
+            sender,
+        ));
+        let mut replayed = 0;
+        while let Some(event) = events.recv().await {
+            replay.event(&event).await;
+            replayed += 1;
+        }
+        replay.dispatcher.flush().await;
+        info!(
+            "Replayed {} of the {} watch events recorded in {:?}",
+            replayed, recorded, args.archive
+        );
+        print!("{}{}", metrics.render(), registry::render());
+        return Ok(());
+    }
+
+    let archive =
+        File::open(&args.archive).with_context(|| format!("could not open {:?}", args.archive))?;
+    let records = BufReader::new(archive)
+        .lines()
+        .enumerate()
+        .filter_map(|(number, line)| match line {
+            Ok(line) if line.trim().is_empty() => None,
+            Ok(line) => match serde_json::from_str::<EventRecord>(&line) {
+                Ok(record) => Some(record),
+                Err(err) => {
+                    warn!("Skipping line {} of the archive: {}", number + 1, err);
+                    None
+                }
+            },
+            Err(err) => {
+                warn!("Could not read line {} of the archive: {}", number + 1, err);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // there's no cluster to ask, so the caches are filled with what the records tell
+    // about the pods and their nodes, which is what the enricher found back then
+    let (pods, mut pod_writer) = reflector::store::<Pod>();
+    let (nodes, mut node_writer) = reflector::store::<Node>();
+    for record in records.iter() {
+        if let Some(pod) = pod_from_record(record) {
+            pod_writer.apply_watcher_event(&watcher::Event::Apply(pod));
+        }
+        if let Some(node) = node_from_record(record) {
+            node_writer.apply_watcher_event(&watcher::Event::Apply(node));
+        }
+    }
+    let enricher = Enricher::new(Arc::new(pods), Arc::new(nodes), &config.enrichment);
+    let started = records
+        .iter()
+        .filter_map(|record| time(&record.last_timestamp).or(time(&record.first_timestamp)))
+        .map(|time| time.0)
+        .min();
+    let clock = recorded_clock(args, started);
+    let replay = Replay::new(&config, enricher, monitors, dispatcher, clock);
+
+    for record in records.iter() {
+        replay.event(&event_from_record(record)).await;
+    }
+    replay.dispatcher.flush().await;
+    info!("Replayed {} events from {:?}", records.len(), args.archive);
+
+    print!("{}{}", metrics.render(), registry::render());
+    Ok(())
+}
+
+// the clock the replay moves along with the events' timestamps, from the first of them,
+// so the windows and cooldowns see the archive's hours go by rather than its seconds
+fn recorded_clock(args: &ReplayArgs, started: Option<DateTime<Utc>>) -> Option<Arc<ManualClock>> {
+    if args.wall_clock {
+        return None;
+    }
+    let clock = Arc::new(ManualClock::new(started?));
+    clock::set(clock.clone());
+    Some(clock)
+}
+
+// what the pod pipeline does with each replayed event
+struct Replay {
+    enricher: Enricher,
+    sampler: LogSampler,
+    monitors: Vec<EventMonitor>,
+    dispatcher: Dispatcher,
+    clock: Option<Arc<ManualClock>>,
+}
+
+impl Replay {
+    fn new(
+        config: &Config,
+        enricher: Enricher,
+        monitors: Vec<EventMonitor>,
+        dispatcher: Dispatcher,
+        clock: Option<Arc<ManualClock>>,
+    ) -> Self {
+        Replay {
+            enricher,
+            sampler: LogSampler::new(&config.pipeline.log_sampling),
+            monitors,
+            dispatcher,
+            clock,
+        }
+    }
+
+    async fn event(&self, event: &Event) {
+        if let (Some(clock), Some(time)) = (self.clock.as_ref(), happened(event)) {
+            clock.advance_to(time);
+        }
+        handle_event(event, &self.enricher, &self.sampler);
+        let record = EventRecord::new(event, &self.enricher);
+        for monitor in self.monitors.iter() {
+            if monitor.spec.matches(event) {
+                MONITOR_EVENTS.increment(
+                    &[
+                        (MONITOR_LABEL, monitor_key(monitor)),
+                        (NAMESPACE_LABEL, record.namespace.clone()),
+                        (KIND_LABEL, record.kind.clone()),
+                        (REASON_LABEL, record.reason.clone()),
+                    ],
+                    1,
+                );
+            }
+        }
+        if !self.dispatcher.is_empty() {
+            self.dispatcher.dispatch(&record).await;
+        }
+    }
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    Some(value.to_string()).filter(|value| !value.is_empty())
+}
+
+fn time(value: &Option<String>) -> Option<Time> {
+    value
+        .as_ref()
+        .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+        .map(|time| Time(time.to_utc()))
+}
+
+// the event the record was made from, as far as the record can tell
+fn event_from_record(record: &EventRecord) -> Event {
+    Event {
+        metadata: ObjectMeta {
+            name: non_empty(&record.name),
+            namespace: non_empty(&record.namespace),
+            uid: non_empty(&record.uid),
+            ..ObjectMeta::default()
+        },
+        involved_object: ObjectReference {
+            kind: non_empty(&record.kind),
+            name: non_empty(&record.object_name),
+            namespace: non_empty(&record.namespace),
+            uid: non_empty(&record.object_uid),
+            ..ObjectReference::default()
+        },
+        reason: non_empty(&record.reason),
+        message: non_empty(&record.message),
+        type_: non_empty(&record.type_),
+        source: Some(EventSource {
+            component: non_empty(&record.source),
+            host: None,
+        }),
+        first_timestamp: time(&record.first_timestamp),
+        last_timestamp: time(&record.last_timestamp),
+        count: Some(record.count),
+        ..Event::default()
+    }
+}
+
+// the pod the record was about. The record doesn't say which of its labels were
+// labels and which were annotations, so they're put on both.
+fn pod_from_record(record: &EventRecord) -> Option<Pod> {
+    if record.kind != "Pod" || record.object_name.is_empty() {
+        return None;
+    }
+    let owner = record.owner.as_ref().and_then(|owner| {
+        let (kind, name) = owner.split_once('/')?;
+        Some(OwnerReference {
+            kind: kind.to_string(),
+            name: name.to_string(),
+            controller: Some(true),
+            ..OwnerReference::default()
+        })
+    });
+    let labels = record.labels.clone();
+
+    Some(Pod {
+        metadata: ObjectMeta {
+            name: Some(record.object_name.clone()),
+            namespace: non_empty(&record.namespace),
+            uid: non_empty(&record.object_uid),
+            labels: Some(labels.clone()),
+            annotations: Some(labels),
+            owner_references: owner.map(|owner| vec![owner]),
+            ..ObjectMeta::default()
+        },
+        spec: Some(PodSpec {
+            node_name: record.node.clone(),
+            ..PodSpec::default()
+        }),
+        ..Pod::default()
+    })
+}
+
+fn node_from_record(record: &EventRecord) -> Option<Node> {
+    let name = record.node.clone()?;
+    let labels = record
+        .zone
+        .clone()
+        .map(|zone| BTreeMap::from([(ZONE_LABEL.to_string(), zone)]));
+    Some(Node {
+        metadata: ObjectMeta {
+            name: Some(name),
+            labels,
+            ..ObjectMeta::default()
+        },
+        ..Node::default()
+    })
+}
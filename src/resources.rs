@@ -0,0 +1,269 @@
+use crate::{
+    metrics::{
+        NAMESPACE_LABEL, NAMESPACE_LIMITS_GAUGE, NAMESPACE_REQUESTS_GAUGE, RESOURCE_LABEL,
+        WORKLOAD_LABEL, WORKLOAD_LIMITS_GAUGE, WORKLOAD_REQUESTS_GAUGE,
+    },
+    pods::workload,
+};
+use axum_prometheus::metrics::gauge;
+use k8s_openapi::{
+    api::core::v1::{Container, Pod, PodSpec},
+    apimachinery::pkg::api::resource::Quantity,
+};
+use kube::ResourceExt;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Mutex,
+};
+
+// what's added up, cpu in cores and memory in bytes
+const RESOURCES: [&str; 2] = ["cpu", "memory"];
+
+// the requests and then the limits of each of RESOURCES
+type Usage = [f64; 4];
+
+struct Tracked {
+    namespace: String,
+    workload: String,
+    usage: Usage,
+}
+
+// a sum of pods, which is exactly 0 once the last one is gone
+#[derive(Default)]
+struct Total {
+    pods: usize,
+    usage: Usage,
+}
+
+#[derive(Default)]
+struct State {
+    pods: HashMap<String, Tracked>,
+    namespaces: HashMap<String, Total>,
+    workloads: HashMap<(String, String), Total>,
+}
+
+// adds up what the pods of each namespace and workload request and are limited to,
+// from their specs. Only the pods holding on to their resources (not done yet) count.
+#[derive(Default)]
+pub struct Resources {
+    state: Mutex<State>,
+}
+
+impl Resources {
+    pub fn pod(&self, pod: &Pod) {
+        let Some(uid) = pod.uid() else {
+            return;
+        };
+        let done = pod
+            .status
+            .as_ref()
+            .and_then(|status| status.phase.as_deref())
+            .is_some_and(|phase| phase == "Succeeded" || phase == "Failed");
+        let mut state = self.lock();
+        if done {
+            state.remove(&uid);
+            return;
+        }
+        let tracked = Tracked {
+            namespace: pod.namespace().unwrap_or_default(),
+            workload: workload(pod),
+            usage: usage(pod),
+        };
+        // the specs hardly ever change, the statuses do all the time
+        if state
+            .pods
+            .get(&uid)
+            .is_some_and(|known| known.usage == tracked.usage && known.workload == tracked.workload)
+        {
+            return;
+        }
+        state.remove(&uid);
+        state.add(uid, tracked);
+    }
+
+    pub fn gone(&self, uid: &str) {
+        self.lock().remove(uid);
+    }
+
+    // after a re-list, the pods that weren't listed again were deleted while we were away
+    pub fn retain(&self, listed: &HashSet<String>) {
+        let mut state = self.lock();
+        let gone = state
+            .pods
+            .keys()
+            .filter(|uid| !listed.contains(*uid))
+            .cloned()
+            .collect::<Vec<_>>();
+        for uid in gone {
+            state.remove(&uid);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("resources lock poisoned")
+    }
+}
+
+impl State {
+    fn add(&mut self, uid: String, tracked: Tracked) {
+        self.update(&tracked, 1.0);
+        self.pods.insert(uid, tracked);
+    }
+
+    fn remove(&mut self, uid: &str) {
+        if let Some(tracked) = self.pods.remove(uid) {
+            self.update(&tracked, -1.0);
+        }
+    }
+
+    // adds (or takes away) a pod from its namespace's and workload's totals
+    fn update(&mut self, tracked: &Tracked, sign: f64) {
+        let namespace = self
+            .namespaces
+            .entry(tracked.namespace.clone())
+            .or_default();
+        namespace.apply(&tracked.usage, sign);
+        let labels = [(NAMESPACE_LABEL, tracked.namespace.clone())];
+        set(
+            NAMESPACE_REQUESTS_GAUGE,
+            NAMESPACE_LIMITS_GAUGE,
+            &labels,
+            &namespace.usage,
+        );
+        if namespace.pods == 0 {
+            self.namespaces.remove(&tracked.namespace);
+        }
+
+        let key = (tracked.namespace.clone(), tracked.workload.clone());
+        let workload = self.workloads.entry(key.clone()).or_default();
+        workload.apply(&tracked.usage, sign);
+        let labels = [
+            (NAMESPACE_LABEL, tracked.namespace.clone()),
+            (WORKLOAD_LABEL, tracked.workload.clone()),
+        ];
+        set(
+            WORKLOAD_REQUESTS_GAUGE,
+            WORKLOAD_LIMITS_GAUGE,
+            &labels,
+            &workload.usage,
+        );
+        if workload.pods == 0 {
+            self.workloads.remove(&key);
+        }
+    }
+}
+
+impl Total {
+    fn apply(&mut self, usage: &Usage, sign: f64) {
+        if sign > 0.0 {
+            self.pods += 1;
+        } else {
+            self.pods = self.pods.saturating_sub(1);
+        }
+        if self.pods == 0 {
+            self.usage = Usage::default();
+            return;
+        }
+        for (total, value) in self.usage.iter_mut().zip(usage) {
+            *total += sign * value;
+        }
+    }
+}
+
+fn set(
+    requests: &'static str,
+    limits: &'static str,
+    labels: &[(&'static str, String)],
+    usage: &Usage,
+) {
+    for (i, resource) in RESOURCES.iter().enumerate() {
+        let mut labels = labels
+            .iter()
+            .map(|(key, value)| (*key, value.clone()))
+            .collect::<Vec<_>>();
+        labels.push((RESOURCE_LABEL, resource.to_string()));
+        gauge!(requests, &labels).set(usage[i]);
+        gauge!(limits, &labels).set(usage[RESOURCES.len() + i]);
+    }
+}
+
+// what the pod gets as the scheduler sees it: its containers run together, but its
+// init containers one at a time, before them. The overhead comes on top.
+fn usage(pod: &Pod) -> Usage {
+    let mut usage = Usage::default();
+    let Some(ref spec) = pod.spec else {
+        return usage;
+    };
+    for (i, resource) in RESOURCES.iter().enumerate() {
+        usage[i] = total(spec, resource, requests);
+        usage[RESOURCES.len() + i] = total(spec, resource, limits);
+    }
+    usage
+}
+
+fn total(spec: &PodSpec, resource: &str, of: ContainerResources) -> f64 {
+    let value = |container: &Container| {
+        of(container)
+            .and_then(|values| values.get(resource))
+            .map(quantity)
+            .unwrap_or(0.0)
+    };
+    let containers = spec.containers.iter().map(value).sum::<f64>();
+    let init = spec
+        .init_containers
+        .iter()
+        .flatten()
+        .map(value)
+        .fold(0.0, f64::max);
+    let overhead = spec
+        .overhead
+        .as_ref()
+        .and_then(|overhead| overhead.get(resource))
+        .map(quantity)
+        .unwrap_or(0.0);
+    containers.max(init) + overhead
+}
+
+type ContainerResources = fn(&Container) -> Option<&BTreeMap<String, Quantity>>;
+
+fn requests(container: &Container) -> Option<&BTreeMap<String, Quantity>> {
+    container.resources.as_ref()?.requests.as_ref()
+}
+
+fn limits(container: &Container) -> Option<&BTreeMap<String, Quantity>> {
+    container.resources.as_ref()?.limits.as_ref()
+}
+
+// a quantity like "250m", "1.5", "128Mi" or "1e9" as a plain number,
+// 0 when it doesn't make sense
+pub fn quantity(quantity: &Quantity) -> f64 {
+    let value = quantity.0.trim();
+    // plain numbers, including the exponent ones
+    if let Ok(number) = value.parse::<f64>() {
+        return number;
+    }
+    const SUFFIXES: [(&str, f64); 15] = [
+        ("Ki", 1024.0),
+        ("Mi", 1048576.0),
+        ("Gi", 1073741824.0),
+        ("Ti", 1099511627776.0),
+        ("Pi", 1125899906842624.0),
+        ("Ei", 1152921504606846976.0),
+        ("n", 1e-9),
+        ("u", 1e-6),
+        ("m", 1e-3),
+        ("k", 1e3),
+        ("M", 1e6),
+        ("G", 1e9),
+        ("T", 1e12),
+        ("P", 1e15),
+        ("E", 1e18),
+    ];
+    SUFFIXES
+        .iter()
+        .find_map(|(suffix, multiplier)| {
+            let number = value.strip_suffix(suffix)?.parse::<f64>().ok()?;
+            Some(number * multiplier)
+        })
+        .unwrap_or(0.0)
+}
@@ -0,0 +1,318 @@
+use crate::{
+    clock,
+    config::ResumeStore,
+    error::{Context, ErrorKind},
+    retention::RETENTION,
+};
+use futures::{stream::BoxStream, Stream, StreamExt};
+use k8s_openapi::{
+    api::core::v1::{ConfigMap, Event},
+    chrono::{DateTime, SecondsFormat},
+};
+use kube::{
+    api::{Patch, PatchParams, WatchEvent, WatchParams},
+    runtime::watcher,
+    Api, Client, ResourceExt,
+};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::{info, warn};
+
+// how often the resource version a watcher got to is saved, when it moved
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+// the resource version a watcher got to, kept in a file or a ConfigMap (under the
+// watcher's name) so it can carry on from there after a restart. When it was saved is
+// kept next to it (under <watcher>.saved_at), to tell how long the watcher was down.
+pub struct ResumePoint {
+    watcher: String,
+    client: Client,
+    store: ResumeStore,
+    latest: Mutex<Option<String>>,
+    saved: Mutex<Option<String>>,
+}
+
+impl ResumePoint {
+    pub fn new(watcher: &str, client: Client, store: &ResumeStore) -> Self {
+        ResumePoint {
+            watcher: watcher.to_string(),
+            client,
+            store: store.clone(),
+            latest: Mutex::new(None),
+            saved: Mutex::new(None),
+        }
+    }
+
+    // nothing to resume from the first time, or when the store can't be read
+    pub async fn load(&self) -> Option<String> {
+        let loaded = match self.store {
+            ResumeStore::File(ref path) => match std::fs::read_to_string(path) {
+                Ok(contents) => serde_json::from_str::<BTreeMap<String, String>>(&contents)
+                    .with_context(|| format!("invalid {:?}", path)),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+                Err(err) => Err(ErrorKind::from(err).context(format!("could not read {:?}", path))),
+            },
+            ResumeStore::ConfigMap(ref name) => {
+                let api: Api<ConfigMap> = Api::default_namespaced(self.client.clone());
+                api.get_opt(name)
+                    .await
+                    .map(|config_map| {
+                        config_map
+                            .and_then(|config_map| config_map.data)
+                            .unwrap_or_default()
+                    })
+                    .map_err(ErrorKind::from)
+            }
+        };
+        match loaded {
+            Ok(mut saved) => {
+                let version = saved.remove(&self.watcher);
+                let saved_at = saved
+                    .remove(&self.saved_at_key())
+                    .and_then(|at| DateTime::parse_from_rfc3339(&at).ok());
+                if let Some(at) = saved_at.filter(|_| version.is_some()) {
+                    RETENTION.resumed(&self.watcher, at.to_utc());
+                }
+                *self.saved.lock().expect("resume point lock poisoned") = version.clone();
+                version
+            }
+            Err(err) => {
+                warn!(
+                    "Could not load where watcher {} left off, starting over: {}",
+                    self.watcher, err
+                );
+                None
+            }
+        }
+    }
+
+    pub fn advance(&self, version: String) {
+        *self.latest.lock().expect("resume point lock poisoned") = Some(version);
+    }
+
+    // saves the resource version every interval, when it moved
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(SAVE_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.save().await;
+        }
+    }
+
+    pub async fn save(&self) {
+        self.save_if(false).await
+    }
+
+    // when stopping, saved even when the version didn't move, so the next run knows it
+    // was only down from then on
+    pub async fn leave(&self) {
+        self.save_if(true).await
+    }
+
+    async fn save_if(&self, always: bool) {
+        let latest = self
+            .latest
+            .lock()
+            .expect("resume point lock poisoned")
+            .clone();
+        let Some(version) = latest else {
+            return;
+        };
+        if !always
+            && self
+                .saved
+                .lock()
+                .expect("resume point lock poisoned")
+                .as_ref()
+                == Some(&version)
+        {
+            return;
+        }
+        match self.store(&version).await {
+            Ok(()) => *self.saved.lock().expect("resume point lock poisoned") = Some(version),
+            Err(err) => warn!(
+                "Could not save where watcher {} got to: {}",
+                self.watcher, err
+            ),
+        }
+    }
+
+    fn saved_at_key(&self) -> String {
+        format!("{}.saved_at", self.watcher)
+    }
+
+    async fn store(&self, version: &str) -> Result<(), ErrorKind> {
+        let saved_at = clock::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        match self.store {
+            ResumeStore::File(ref path) => {
+                // the other watchers' versions are kept
+                let mut versions = std::fs::read_to_string(path)
+                    .ok()
+                    .and_then(|contents| {
+                        serde_json::from_str::<BTreeMap<String, String>>(&contents).ok()
+                    })
+                    .unwrap_or_default();
+                versions.insert(self.watcher.clone(), version.to_string());
+                versions.insert(self.saved_at_key(), saved_at);
+                // written aside first, a restart in the middle leaves the last one whole
+                let partial = path.with_extension("partial");
+                std::fs::write(&partial, serde_json::to_string(&versions)?)?;
+                std::fs::rename(&partial, path)?;
+            }
+            ResumeStore::ConfigMap(ref name) => {
+                let api: Api<ConfigMap> = Api::default_namespaced(self.client.clone());
+                let config_map = serde_json::json!({
+                    "apiVersion": "v1",
+                    "kind": "ConfigMap",
+                    "metadata": { "name": name },
+                    "data": { self.watcher.as_str(): version, self.saved_at_key(): saved_at },
+                });
+                api.patch(
+                    name,
+                    &PatchParams::apply("k8rs").force(),
+                    &Patch::Apply(config_map),
+                )
+                .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// the events watch carrying on from where the last run left off: a watch from the
+// saved resource version, for as long as the api server still has what happened since,
+// then the watcher as usual. Its first list hands out the events newer than the last one
+// seen as if they were watched, those were missed when the version was too old already
+// (or in between the two). Every event seen moves the resume point.
+pub fn resuming<S>(
+    api: Api<Event>,
+    point: Option<Arc<ResumePoint>>,
+    timeout: u32,
+    watcher: S,
+) -> impl Stream<Item = Result<watcher::Event<Event>, watcher::Error>> + Send
+where
+    S: Stream<Item = Result<watcher::Event<Event>, watcher::Error>> + Send + 'static,
+{
+    futures::stream::once(async move {
+        let from = match point {
+            Some(ref point) => point.load().await,
+            None => None,
+        };
+        if let Some(ref from) = from {
+            info!("Resuming the events watch from resource version {}", from);
+        }
+        let mut last = from.as_deref().and_then(number);
+        let mut listing = None;
+        watch_from(api, from, timeout)
+            .chain(watcher)
+            .map(move |event| {
+                let event = match event {
+                    Ok(watcher::Event::Init) if listing.is_none() => {
+                        listing = Some(true);
+                        Ok(watcher::Event::Init)
+                    }
+                    Ok(watcher::Event::InitApply(event))
+                        if listing == Some(true)
+                            && event
+                                .resource_version()
+                                .as_deref()
+                                .and_then(number)
+                                .zip(last)
+                                .is_some_and(|(version, last)| version > last) =>
+                    {
+                        Ok(watcher::Event::Apply(event))
+                    }
+                    Ok(watcher::Event::InitDone) if listing == Some(true) => {
+                        listing = Some(false);
+                        Ok(watcher::Event::InitDone)
+                    }
+                    event => event,
+                };
+                if let Ok(watcher::Event::Apply(ref event) | watcher::Event::Delete(ref event)) =
+                    event
+                {
+                    if let Some(version) = event.resource_version() {
+                        last = last.max(number(&version));
+                        if let Some(ref point) = point {
+                            point.advance(version);
+                        }
+                    }
+                }
+                event
+            })
+    })
+    .flatten()
+}
+
+// resource versions are opaque, but they're etcd's revisions in practice. One that
+// isn't a number is never compared.
+fn number(version: &str) -> Option<u64> {
+    version.parse().ok()
+}
+
+// watches from a resource version, starting over from the last one seen every time a
+// watch call times out, until the api server doesn't have that one anymore
+fn watch_from(
+    api: Api<Event>,
+    from: Option<String>,
+    timeout: u32,
+) -> impl Stream<Item = Result<watcher::Event<Event>, watcher::Error>> + Send {
+    type Watch = BoxStream<'static, kube::Result<WatchEvent<Event>>>;
+    futures::stream::unfold(
+        (api, from, None::<Watch>),
+        move |(api, from, stream)| async move {
+            let mut from = from?;
+            let params = WatchParams::default().timeout(timeout);
+            let mut stream = match stream {
+                Some(stream) => stream,
+                None => match api.watch(&params, &from).await {
+                    Ok(stream) => stream.boxed(),
+                    Err(err) => return stop(&from, err),
+                },
+            };
+            loop {
+                let event = match stream.next().await {
+                    Some(Ok(WatchEvent::Added(event) | WatchEvent::Modified(event))) => {
+                        watcher::Event::Apply(event)
+                    }
+                    Some(Ok(WatchEvent::Deleted(event))) => watcher::Event::Delete(event),
+                    Some(Ok(WatchEvent::Bookmark(bookmark))) => {
+                        from = bookmark.metadata.resource_version;
+                        continue;
+                    }
+                    Some(Ok(WatchEvent::Error(err))) => return stop(&from, kube::Error::Api(err)),
+                    Some(Err(err)) => return stop(&from, err),
+                    // the watch call timed out
+                    None => {
+                        stream = match api.watch(&params, &from).await {
+                            Ok(stream) => stream.boxed(),
+                            Err(err) => return stop(&from, err),
+                        };
+                        continue;
+                    }
+                };
+                if let watcher::Event::Apply(ref object) | watcher::Event::Delete(ref object) =
+                    event
+                {
+                    from = object.resource_version().unwrap_or(from);
+                }
+                return Some((Ok(event), (api, Some(from), Some(stream))));
+            }
+        },
+    )
+}
+
+// a 410 Gone means the api server compacted its history past the resource version
+fn stop<T>(from: &str, err: kube::Error) -> Option<T> {
+    match err {
+        kube::Error::Api(ref response) if response.code == 410 => info!(
+            "Resource version {} is too old to resume from, listing the events",
+            from
+        ),
+        err => warn!("Resuming the events watch failed, listing them: {}", err),
+    }
+    None
+}
@@ -0,0 +1,232 @@
+use crate::{
+    clock,
+    config::WatcherSettings,
+    metrics::{
+        CAUSE_LABEL, EVENT_TTL_GAUGE, EVENT_TTL_MISMATCH_GAUGE, EVENT_TTL_REMAINING_GAUGE,
+        WATCHER_LABEL,
+    },
+};
+use axum_prometheus::metrics::gauge;
+use k8s_openapi::{
+    api::core::v1::Event,
+    chrono::{DateTime, Utc},
+};
+use kube::runtime::watcher;
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+use tracing::{info, warn};
+
+// how long the api server keeps the events, its --event-ttl (an hour by default). Unless
+// watcher.event_ttl tells, it's the age of the oldest event the watchers listed, a bit
+// under it on a cluster with events all the time. A watcher re-listing less often than
+// that, or down for longer, loses what happened meanwhile for good: it expired before a
+// list could bring it.
+pub static RETENTION: LazyLock<Retention> = LazyLock::new(Retention::default);
+
+#[derive(Default)]
+pub struct Retention {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    configured: Option<Duration>,
+    resync: Option<Duration>,
+    estimated: Option<Duration>,
+    announced: bool,
+    watchers: HashMap<String, Watcher>,
+}
+
+#[derive(Default)]
+struct Watcher {
+    // the age of the oldest event of the list it's going through
+    listing: Option<Duration>,
+    // since when it isn't getting its events, failing or stopped (when resumed)
+    down_since: Option<DateTime<Utc>>,
+    // how long it was last down, and whether that was held against the ttl yet
+    downtime: Option<(Duration, bool)>,
+    resync_exceeded: bool,
+}
+
+impl Retention {
+    pub fn configure(&self, settings: &WatcherSettings) {
+        let mut state = self.lock();
+        state.configured = settings.event_ttl;
+        state.resync = settings.resync;
+    }
+
+    // a resumed watcher was stopped since it last saved where it got to
+    pub fn resumed(&self, watcher: &str, saved_at: DateTime<Utc>) {
+        self.lock()
+            .watchers
+            .entry(watcher.to_string())
+            .or_default()
+            .down_since
+            .get_or_insert(saved_at);
+    }
+
+    pub fn observe(&self, name: &str, event: &Result<watcher::Event<Event>, watcher::Error>) {
+        let now = clock::now();
+        let mut state = self.lock();
+        let watcher = state.watchers.entry(name.to_string()).or_default();
+        match event {
+            Err(_) => {
+                watcher.down_since.get_or_insert(now);
+            }
+            Ok(watcher::Event::Init) => watcher.listing = Some(Duration::ZERO),
+            Ok(watcher::Event::InitApply(event)) => {
+                let age = written(event)
+                    .and_then(|at| (now - at).to_std().ok())
+                    .unwrap_or_default();
+                if let Some(ref mut oldest) = watcher.listing {
+                    *oldest = (*oldest).max(age);
+                }
+            }
+            Ok(watcher::Event::InitDone) => {
+                watcher.back(now);
+                let oldest = watcher.listing.take().filter(|oldest| !oldest.is_zero());
+                if let Some(oldest) = oldest {
+                    state.estimated = Some(state.estimated.unwrap_or_default().max(oldest));
+                }
+                state.check_resync(name);
+            }
+            Ok(watcher::Event::Apply(_) | watcher::Event::Delete(_)) => watcher.back(now),
+        }
+        state.check_downtime(name);
+        state.export(name, now);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("retention lock poisoned")
+    }
+}
+
+impl Watcher {
+    fn back(&mut self, now: DateTime<Utc>) {
+        if let Some(since) = self.down_since.take() {
+            self.downtime = Some(((now - since).to_std().unwrap_or_default(), false));
+        }
+    }
+}
+
+impl State {
+    fn ttl(&self) -> Option<Duration> {
+        self.configured.or(self.estimated)
+    }
+
+    fn check_resync(&mut self, name: &str) {
+        let Some(ttl) = self.ttl() else {
+            return;
+        };
+        if !self.announced {
+            self.announced = true;
+            match self.configured {
+                Some(_) => info!("The api server keeps the events for {}", format(ttl)),
+                None => info!(
+                    "The api server keeps the events for about {}, from the oldest listed",
+                    format(ttl)
+                ),
+            }
+        }
+        let resync = self.resync;
+        let Some(watcher) = self.watchers.get_mut(name) else {
+            return;
+        };
+        let exceeded = resync.is_some_and(|resync| resync > ttl);
+        if exceeded && !watcher.resync_exceeded {
+            warn!(
+                "Watcher {} re-lists every {} but the events expire after {}, what its watch misses in between can be lost for good",
+                name,
+                format(resync.unwrap_or_default()),
+                format(ttl)
+            );
+        }
+        watcher.resync_exceeded = exceeded;
+        mismatch(name, "resync", exceeded);
+    }
+
+    fn check_downtime(&mut self, name: &str) {
+        let Some(ttl) = self.ttl() else {
+            return;
+        };
+        let Some((downtime, checked)) = self
+            .watchers
+            .get_mut(name)
+            .and_then(|watcher| watcher.downtime.as_mut())
+        else {
+            return;
+        };
+        if *checked {
+            return;
+        }
+        *checked = true;
+        let exceeded = *downtime > ttl;
+        if exceeded {
+            warn!(
+                "Watcher {} was down for {} but the events expire after {}, those of the first {} are lost for good",
+                name,
+                format(*downtime),
+                format(ttl),
+                format(*downtime - ttl)
+            );
+        }
+        mismatch(name, "downtime", exceeded);
+    }
+
+    fn export(&self, name: &str, now: DateTime<Utc>) {
+        let Some(ttl) = self.ttl() else {
+            return;
+        };
+        gauge!(EVENT_TTL_GAUGE).set(ttl.as_secs_f64());
+        let down = self
+            .watchers
+            .get(name)
+            .and_then(|watcher| watcher.down_since)
+            .and_then(|since| (now - since).to_std().ok())
+            .unwrap_or_default();
+        gauge!(
+            EVENT_TTL_REMAINING_GAUGE,
+            &[(WATCHER_LABEL, name.to_string())]
+        )
+        .set(ttl.saturating_sub(down).as_secs_f64());
+    }
+}
+
+fn mismatch(name: &str, cause: &'static str, exceeded: bool) {
+    gauge!(
+        EVENT_TTL_MISMATCH_GAUGE,
+        &[
+            (WATCHER_LABEL, name.to_string()),
+            (CAUSE_LABEL, cause.to_string()),
+        ]
+    )
+    .set(if exceeded { 1.0 } else { 0.0 });
+}
+
+// the api server starts over an event's ttl every time it's written, its last occurrence
+fn written(event: &Event) -> Option<DateTime<Utc>> {
+    [
+        event
+            .series
+            .as_ref()
+            .and_then(|series| series.last_observed_time.as_ref())
+            .map(|time| time.0),
+        event.event_time.as_ref().map(|time| time.0),
+        event.last_timestamp.as_ref().map(|time| time.0),
+        event
+            .metadata
+            .creation_timestamp
+            .as_ref()
+            .map(|time| time.0),
+    ]
+    .into_iter()
+    .flatten()
+    .max()
+}
+
+fn format(duration: Duration) -> humantime::FormattedDuration {
+    humantime::format_duration(Duration::from_secs(duration.as_secs()))
+}
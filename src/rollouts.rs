@@ -0,0 +1,264 @@
+use crate::{
+    clock,
+    config::{RolloutSettings, WatcherSettings},
+    error::Error,
+    metrics::{
+        KIND_LABEL, NAMESPACE_LABEL, NAME_LABEL, REASON_LABEL, ROLLOUT_DESIRED_GAUGE,
+        ROLLOUT_EVENTS_COUNTER, ROLLOUT_STUCK_GAUGE, ROLLOUT_UPDATED_GAUGE,
+    },
+    snapshot::SNAPSHOT,
+    watch::{pausable, watcher_config, WatcherBackoff},
+};
+use axum_prometheus::metrics::{counter, gauge};
+use futures::StreamExt;
+use k8s_openapi::api::{
+    apps::v1::{DaemonSet, StatefulSet},
+    core::v1::Event,
+};
+use kube::{
+    runtime::{watcher, WatchStreamExt},
+    Api, Client, Resource, ResourceExt,
+};
+use serde::de::DeserializeOwned;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+// the events about StatefulSets and DaemonSets, like FailedCreate
+pub fn handle_event(event: &Event) {
+    let object = &event.involved_object;
+    counter!(
+        ROLLOUT_EVENTS_COUNTER,
+        &[
+            (KIND_LABEL, object.kind.clone().unwrap_or_default()),
+            (
+                NAMESPACE_LABEL,
+                object.namespace.clone().unwrap_or_default()
+            ),
+            (NAME_LABEL, object.name.clone().unwrap_or_default()),
+            (REASON_LABEL, event.reason.clone().unwrap_or_default()),
+        ]
+    )
+    .increment(1);
+}
+
+// how far along the rollout of a workload is
+struct Progress {
+    desired: i32,
+    updated: i32,
+    // every replica is updated and ready, and the controller saw the latest spec
+    done: bool,
+}
+
+fn statefulset_progress(statefulset: &StatefulSet) -> Progress {
+    let desired = statefulset
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.replicas)
+        .unwrap_or(1);
+    let status = statefulset.status.clone().unwrap_or_default();
+    let updated = status.updated_replicas.unwrap_or_default();
+    let done = status.observed_generation >= statefulset.metadata.generation
+        && updated == desired
+        && status.ready_replicas.unwrap_or_default() == desired
+        // the controller moves current_revision to the update one once every pod is on it
+        && (status.update_revision.is_none() || status.update_revision == status.current_revision);
+    Progress {
+        desired,
+        updated,
+        done,
+    }
+}
+
+fn daemonset_progress(daemonset: &DaemonSet) -> Progress {
+    let status = daemonset.status.clone().unwrap_or_default();
+    let desired = status.desired_number_scheduled;
+    let updated = status.updated_number_scheduled.unwrap_or_default();
+    let done = status.observed_generation >= daemonset.metadata.generation
+        && updated == desired
+        && status.number_available.unwrap_or_default() == desired;
+    Progress {
+        desired,
+        updated,
+        done,
+    }
+}
+
+// a workload we follow, and since when its rollout is going on, if it is
+struct Rollout {
+    progress: Progress,
+    since: Option<Instant>,
+}
+
+// watches the StatefulSets and DaemonSets, for how far along their rollouts are
+// and the ones that take too long
+pub async fn watch_rollouts(
+    client: Client,
+    watcher_settings: &WatcherSettings,
+    settings: &RolloutSettings,
+) {
+    tokio::join!(
+        watch_kind(
+            Api::<StatefulSet>::default_namespaced(client.clone()),
+            "statefulsets",
+            statefulset_progress,
+            watcher_settings,
+            settings,
+        ),
+        watch_kind(
+            Api::<DaemonSet>::default_namespaced(client),
+            "daemonsets",
+            daemonset_progress,
+            watcher_settings,
+            settings,
+        ),
+    );
+}
+
+async fn watch_kind<K>(
+    api: Api<K>,
+    name: &'static str,
+    progress: fn(&K) -> Progress,
+    watcher_settings: &WatcherSettings,
+    settings: &RolloutSettings,
+) where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug + Send + 'static,
+{
+    let backoff = WatcherBackoff::new(name, &watcher_settings.backoff);
+    let mut stream = Box::pin(pausable(
+        name,
+        watcher(api, watcher_config(watcher_settings))
+            .backoff(backoff)
+            .inspect(move |event| SNAPSHOT.watcher(name, event)),
+    ));
+    // stuck rollouts don't change, so they have to be checked now and then
+    let mut checks = tokio::time::interval(settings.check_interval);
+
+    let kind = K::kind(&()).to_string();
+    let mut rollouts = HashMap::<(String, String), Rollout>::new();
+    let mut relisted = HashSet::new();
+    // the stuck ones we already warned about
+    let mut reported = HashSet::new();
+    loop {
+        tokio::select! {
+            Some(event) = stream.next() => match event {
+                Ok(watcher::Event::Init) => relisted.clear(),
+                Ok(watcher::Event::InitApply(object)) => {
+                    relisted.insert(key(&object));
+                    apply(&kind, &mut rollouts, &object, progress(&object));
+                }
+                Ok(watcher::Event::InitDone) => {
+                    // the ones that weren't listed again were deleted meanwhile
+                    let gone = rollouts
+                        .keys()
+                        .filter(|key| !relisted.contains(*key))
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    for key in gone {
+                        rollouts.remove(&key);
+                        forget(&kind, &key);
+                    }
+                }
+                Ok(watcher::Event::Apply(object)) => {
+                    apply(&kind, &mut rollouts, &object, progress(&object));
+                }
+                Ok(watcher::Event::Delete(object)) => {
+                    if rollouts.remove(&key(&object)).is_some() {
+                        forget(&kind, &key(&object));
+                    }
+                }
+                Err(err) => Error::new("rollouts", err).on(name).report(),
+            },
+            _ = checks.tick() => {
+                let now = clock::instant();
+                let stuck = rollouts
+                    .iter()
+                    .filter(|(_, rollout)| {
+                        rollout
+                            .since
+                            .is_some_and(|since| now.duration_since(since) > settings.stuck_after)
+                    })
+                    .map(|(key, _)| key.clone())
+                    .collect::<HashSet<_>>();
+                for (namespace, name) in stuck.difference(&reported) {
+                    warn!(
+                        "The rollout of {} {}/{} has been going on for more than {}",
+                        kind,
+                        namespace,
+                        name,
+                        humantime::format_duration(settings.stuck_after)
+                    );
+                }
+                for key in rollouts.keys() {
+                    gauge!(ROLLOUT_STUCK_GAUGE, &labels(&kind, key))
+                        .set(if stuck.contains(key) { 1.0 } else { 0.0 });
+                }
+                reported = stuck;
+            }
+        }
+    }
+}
+
+fn key<K: Resource>(object: &K) -> (String, String) {
+    (object.namespace().unwrap_or_default(), object.name_any())
+}
+
+fn labels(kind: &str, (namespace, name): &(String, String)) -> [(&'static str, String); 3] {
+    [
+        (KIND_LABEL, kind.to_string()),
+        (NAMESPACE_LABEL, namespace.clone()),
+        (NAME_LABEL, name.clone()),
+    ]
+}
+
+fn apply<K: Resource>(
+    kind: &str,
+    rollouts: &mut HashMap<(String, String), Rollout>,
+    object: &K,
+    progress: Progress,
+) {
+    let key = key(object);
+    gauge!(ROLLOUT_DESIRED_GAUGE, &labels(kind, &key)).set(progress.desired as f64);
+    gauge!(ROLLOUT_UPDATED_GAUGE, &labels(kind, &key)).set(progress.updated as f64);
+    let rollout = rollouts.entry(key.clone()).or_insert(Rollout {
+        progress: Progress {
+            desired: 0,
+            updated: 0,
+            done: true,
+        },
+        since: None,
+    });
+    match (rollout.progress.done, progress.done) {
+        (true, false) => rollout.since = Some(clock::instant()),
+        (false, true) => {
+            if let Some(since) = rollout.since.take() {
+                info!(
+                    "The rollout of {} {}/{} finished after {}",
+                    kind,
+                    key.0,
+                    key.1,
+                    humantime::format_duration(Duration::from_secs(
+                        clock::instant().duration_since(since).as_secs()
+                    ))
+                );
+            }
+        }
+        _ => {}
+    }
+    rollout.progress = progress;
+}
+
+// the gauges of a workload that's gone can't be removed, but they can stop
+// telling it's there
+fn forget(kind: &str, key: &(String, String)) {
+    for name in [
+        ROLLOUT_DESIRED_GAUGE,
+        ROLLOUT_UPDATED_GAUGE,
+        ROLLOUT_STUCK_GAUGE,
+    ] {
+        gauge!(name, &labels(kind, key)).set(0.0);
+    }
+}
@@ -0,0 +1,311 @@
+use crate::{clock, config::RollupSettings};
+use axum::{extract::Query, http::StatusCode, response::IntoResponse, routing::get, Json, Router};
+use k8s_openapi::{
+    api::core::v1::Event,
+    chrono::{DateTime, Utc},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+const HOUR: i64 = 3600;
+const DAY: i64 = 24 * HOUR;
+
+// the workload of the series over rollups.max_series
+const OTHER: &str = "other";
+
+// the events counted by the hour and by the day, for the trends of weeks the events
+// themselves are long gone for. The workers fill it in with every event, so like the
+// top objects it's a global, and it's saved along with the counters.
+pub static ROLLUPS: LazyLock<Rollups> = LazyLock::new(Rollups::default);
+
+#[derive(Default)]
+pub struct Rollups {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    settings: RollupSettings,
+    // by the unix time they start at
+    hours: BTreeMap<i64, HashMap<Key, u64>>,
+    days: BTreeMap<i64, HashMap<Key, u64>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Key {
+    pub namespace: String,
+    pub reason: String,
+    // like "Deployment/api", or the object itself when it's not part of one
+    pub workload: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Row {
+    pub start: DateTime<Utc>,
+    #[serde(flatten)]
+    pub key: Key,
+    pub events: u64,
+}
+
+// the series asked for, every one of them for the fields left out
+#[derive(Deserialize, Default, Clone)]
+pub struct Filter {
+    pub namespace: Option<String>,
+    pub reason: Option<String>,
+    pub workload: Option<String>,
+}
+
+// what's saved of the rollups for the next run
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct SavedRollups {
+    hours: Vec<Row>,
+    days: Vec<Row>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolution {
+    Hour,
+    Day,
+}
+
+impl Resolution {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Resolution::Hour => "hour",
+            Resolution::Day => "day",
+        }
+    }
+
+    // in seconds
+    pub fn length(&self) -> i64 {
+        match self {
+            Resolution::Hour => HOUR,
+            Resolution::Day => DAY,
+        }
+    }
+}
+
+impl Rollups {
+    pub fn configure(&self, settings: &RollupSettings) {
+        let mut state = self.lock();
+        state.settings = settings.clone();
+        if !settings.enabled {
+            state.hours.clear();
+            state.days.clear();
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.lock().settings.enabled
+    }
+
+    pub fn observe(&self, event: &Event, workload: String) {
+        let now = clock::now().timestamp();
+        let mut state = self.lock();
+        if !state.settings.enabled {
+            return;
+        }
+        let key = Key {
+            namespace: event.involved_object.namespace.clone().unwrap_or_default(),
+            reason: event.reason.clone().unwrap_or_default(),
+            workload,
+        };
+        let max_series = state.settings.max_series;
+        let started = !state.hours.contains_key(&(now - now % HOUR));
+        add(
+            state.hours.entry(now - now % HOUR).or_default(),
+            key.clone(),
+            max_series,
+        );
+        add(
+            state.days.entry(now - now % DAY).or_default(),
+            key,
+            max_series,
+        );
+        // the old ones are let go of with every new hour
+        if started {
+            state.expire(now);
+        }
+    }
+
+    // the rows of the hours or days from the one since is in to the one until is in,
+    // unix times both
+    pub fn rows(
+        &self,
+        resolution: Resolution,
+        since: i64,
+        until: i64,
+        filter: &Filter,
+    ) -> Vec<Row> {
+        let mut state = self.lock();
+        state.expire(clock::now().timestamp());
+        let buckets = match resolution {
+            Resolution::Hour => &state.hours,
+            Resolution::Day => &state.days,
+        };
+        let length = resolution.length();
+        let mut rows = Vec::new();
+        for (start, counts) in buckets.range(since - since.rem_euclid(length)..=until) {
+            let mut bucket = counts
+                .iter()
+                .filter(|(key, _)| filter.matches(key))
+                .map(|(key, events)| Row {
+                    start: DateTime::from_timestamp(*start, 0).unwrap_or_default(),
+                    key: key.clone(),
+                    events: *events,
+                })
+                .collect::<Vec<_>>();
+            bucket.sort_by(|a, b| b.events.cmp(&a.events).then_with(|| a.key.cmp(&b.key)));
+            rows.extend(bucket);
+        }
+        rows
+    }
+
+    // the hours when they go back to since, else the days, with since moved up to the
+    // oldest of them kept
+    pub fn resolution(&self, since: i64) -> (Resolution, i64) {
+        let now = clock::now().timestamp();
+        let settings = &self.lock().settings;
+        let hours = now - settings.hourly_retention.as_secs() as i64;
+        let days = now - settings.daily_retention.as_secs() as i64;
+        match since >= hours {
+            true => (Resolution::Hour, since),
+            false => (Resolution::Day, since.max(days)),
+        }
+    }
+
+    // the namespaces, reasons and workloads counted in the days kept
+    pub fn values(&self) -> [BTreeSet<String>; 3] {
+        let state = self.lock();
+        let mut values: [BTreeSet<String>; 3] = Default::default();
+        for key in state.days.values().flat_map(|counts| counts.keys()) {
+            values[0].insert(key.namespace.clone());
+            values[1].insert(key.reason.clone());
+            values[2].insert(key.workload.clone());
+        }
+        values
+    }
+
+    pub fn save(&self) -> SavedRollups {
+        let state = self.lock();
+        SavedRollups {
+            hours: saved(&state.hours),
+            days: saved(&state.days),
+        }
+    }
+
+    // what the last run counted, before anything is counted in this one
+    pub fn restore(&self, saved: SavedRollups) {
+        let state = &mut *self.lock();
+        for (buckets, rows) in [
+            (&mut state.hours, saved.hours),
+            (&mut state.days, saved.days),
+        ] {
+            for row in rows {
+                *buckets
+                    .entry(row.start.timestamp())
+                    .or_default()
+                    .entry(row.key)
+                    .or_default() += row.events;
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("rollups lock poisoned")
+    }
+}
+
+impl State {
+    // the hours and days past their retention
+    fn expire(&mut self, now: i64) {
+        let hours = now - self.settings.hourly_retention.as_secs() as i64;
+        self.hours.retain(|start, _| start + HOUR > hours);
+        let days = now - self.settings.daily_retention.as_secs() as i64;
+        self.days.retain(|start, _| start + DAY > days);
+    }
+}
+
+// counts an event in an hour or a day, with the other ones once it has max_series
+fn add(counts: &mut HashMap<Key, u64>, key: Key, max_series: usize) {
+    let key = match counts.contains_key(&key) || counts.len() < max_series {
+        true => key,
+        false => Key {
+            workload: OTHER.to_string(),
+            ..key
+        },
+    };
+    *counts.entry(key).or_default() += 1;
+}
+
+fn saved(buckets: &BTreeMap<i64, HashMap<Key, u64>>) -> Vec<Row> {
+    buckets
+        .iter()
+        .flat_map(|(start, counts)| {
+            counts.iter().map(|(key, events)| Row {
+                start: DateTime::from_timestamp(*start, 0).unwrap_or_default(),
+                key: key.clone(),
+                events: *events,
+            })
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct RollupQuery {
+    resolution: Option<Resolution>,
+    #[serde(default, with = "humantime_serde")]
+    since: Option<Duration>,
+    namespace: Option<String>,
+    reason: Option<String>,
+    workload: Option<String>,
+}
+
+impl Filter {
+    fn matches(&self, key: &Key) -> bool {
+        let matches = |wanted: &Option<String>, value: &str| {
+            wanted.as_deref().is_none_or(|wanted| wanted == value)
+        };
+        matches(&self.namespace, &key.namespace)
+            && matches(&self.reason, &key.reason)
+            && matches(&self.workload, &key.workload)
+    }
+}
+
+pub fn router() -> Router {
+    Router::new().route("/api/v1/rollups", get(rollups))
+}
+
+// like /api/v1/rollups?resolution=day&since=30d&namespace=shop&reason=BackOff, every
+// hour kept by default
+async fn rollups(query: Option<Query<RollupQuery>>) -> impl IntoResponse {
+    let Some(Query(query)) = query else {
+        return (
+            StatusCode::BAD_REQUEST,
+            "the resolution must be hour or day, since a duration like 30d\n",
+        )
+            .into_response();
+    };
+    let resolution = query.resolution.unwrap_or(Resolution::Hour);
+    let now = clock::now().timestamp();
+    let since = query
+        .since
+        .map(|since| now - since.as_secs() as i64)
+        .unwrap_or(0);
+    let filter = Filter {
+        namespace: query.namespace,
+        reason: query.reason,
+        workload: query.workload,
+    };
+    Json(serde_json::json!({
+        "resolution": resolution.as_str(),
+        "rows": ROLLUPS.rows(resolution, since, now, &filter),
+    }))
+    .into_response()
+}
@@ -0,0 +1,117 @@
+use crate::{
+    clock,
+    metrics::{
+        CAUSE_LABEL, NODE_SCALE_DOWNS_COUNTER, NODE_SCALE_UPS_COUNTER, NODE_SCALE_UP_HISTOGRAM,
+        SOURCE_LABEL,
+    },
+    pipeline::happened,
+    scheduling,
+};
+use axum_prometheus::metrics::{counter, histogram};
+use k8s_openapi::{api::core::v1::Event, chrono::DateTime, chrono::Utc};
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+// the pods waiting for a node are forgotten after a while, they might never get one
+const GIVE_UP_AFTER: Duration = Duration::from_secs(60 * 60);
+
+// a pod the scheduler couldn't find a node for
+struct Unschedulable {
+    // when that first happened, and why
+    since: DateTime<Utc>,
+    cause: &'static str,
+    // the node autoscaler making room for it, once one does
+    scaled_by: Option<&'static str>,
+    seen: Instant,
+}
+
+// the unschedulable pods by uid, from their first FailedScheduling to being scheduled
+static UNSCHEDULABLE: LazyLock<Mutex<HashMap<String, Unschedulable>>> =
+    LazyLock::new(Default::default);
+
+// the events of the cluster-autoscaler and Karpenter. How long a scale up takes is told
+// from the pods: from the first FailedScheduling of a pod to it being Scheduled, when
+// an autoscaler said it's adding a node for it in between (TriggeredScaleUp for the
+// cluster-autoscaler, Nominated for Karpenter).
+pub fn handle_event(event: &Event) {
+    let object = &event.involved_object;
+    let reason = event.reason.as_deref().unwrap_or_default();
+    let uid = object.uid.clone().unwrap_or_default();
+    match (object.kind.as_deref(), reason) {
+        (Some("Pod"), "FailedScheduling") => {
+            let now = clock::instant();
+            let mut unschedulable = lock();
+            unschedulable.retain(|_, pod| now.duration_since(pod.seen) < GIVE_UP_AFTER);
+            let Some(since) = happened(event) else {
+                return;
+            };
+            unschedulable.entry(uid).or_insert(Unschedulable {
+                since,
+                cause: scheduling::failure_causes(event)
+                    .first()
+                    .copied()
+                    .unwrap_or("other"),
+                scaled_by: None,
+                seen: now,
+            });
+        }
+        (Some("Pod"), "TriggeredScaleUp" | "Nominated") => {
+            let source = source(reason);
+            if let Some(pod) = lock().get_mut(&uid) {
+                if pod.scaled_by.is_none() {
+                    counter!(
+                        NODE_SCALE_UPS_COUNTER,
+                        &[(SOURCE_LABEL, source.to_string())]
+                    )
+                    .increment(1);
+                }
+                pod.scaled_by = Some(source);
+            }
+        }
+        (Some("Pod"), "Scheduled") => {
+            let Some(pod) = lock().remove(&uid) else {
+                return;
+            };
+            let (Some(source), Some(scheduled)) = (pod.scaled_by, happened(event)) else {
+                return;
+            };
+            let took = (scheduled - pod.since).to_std().unwrap_or_default();
+            histogram!(
+                NODE_SCALE_UP_HISTOGRAM,
+                &[
+                    (SOURCE_LABEL, source.to_string()),
+                    (CAUSE_LABEL, pod.cause.to_string()),
+                ]
+            )
+            .record(took.as_secs_f64());
+        }
+        (Some("Pod"), _) => {}
+        // the nodes taken away: ScaleDown on the Node for the cluster-autoscaler,
+        // DisruptionTerminating on the Node or NodeClaim for Karpenter
+        (_, "ScaleDown" | "DisruptionTerminating") => {
+            counter!(
+                NODE_SCALE_DOWNS_COUNTER,
+                &[(SOURCE_LABEL, source(reason).to_string())]
+            )
+            .increment(1);
+        }
+        _ => {}
+    }
+}
+
+// which of the autoscalers it's from, by the reasons only each has
+fn source(reason: &str) -> &'static str {
+    match reason {
+        "Nominated" | "DisruptionTerminating" => "karpenter",
+        _ => "cluster-autoscaler",
+    }
+}
+
+fn lock() -> std::sync::MutexGuard<'static, HashMap<String, Unschedulable>> {
+    UNSCHEDULABLE
+        .lock()
+        .expect("node autoscaling lock poisoned")
+}
@@ -0,0 +1,78 @@
+use k8s_openapi::api::core::v1::Event;
+
+// what the scheduler's filters say about the nodes they ruled out, by cause, each asking
+// for a different fix: more nodes, tolerations, other selectors...
+const CAUSES: &[(&str, &[&str])] = &[
+    (
+        "insufficient_resources",
+        &["Insufficient ", "Too many pods"],
+    ),
+    (
+        "taints",
+        &[
+            "untolerated taint",
+            "taint(s) that the pod didn't tolerate",
+            "taint {",
+        ],
+    ),
+    (
+        "node_affinity",
+        &[
+            "didn't match Pod's node affinity",
+            "didn't match node selector",
+        ],
+    ),
+    (
+        "pod_affinity",
+        &[
+            "pod affinity",
+            "pod anti-affinity",
+            "existing pods anti-affinity",
+        ],
+    ),
+    ("topology_spread", &["topology spread constraints"]),
+    (
+        "volumes",
+        &[
+            "volume node affinity conflict",
+            "unbound immediate PersistentVolumeClaims",
+            "persistentvolumeclaim",
+            "exceed max volume count",
+        ],
+    ),
+    ("ports", &["didn't have free ports"]),
+    (
+        "unschedulable",
+        &["were unschedulable", "was unschedulable"],
+    ),
+];
+
+// the causes of a FailedScheduling event, from its predicate summary like
+// "0/5 nodes are available: 1 node(s) had untolerated taint {node-role.kubernetes.io/control-plane: },
+// 4 Insufficient cpu. preemption: 0/5 nodes are available: ..." (the part about
+// preemption is left out, it says the same nodes again). The nodes not fitting for a
+// reason we don't know are "other".
+pub fn failure_causes(event: &Event) -> Vec<&'static str> {
+    if event.reason.as_deref() != Some("FailedScheduling") {
+        return Vec::new();
+    }
+    let message = event.message.as_deref().unwrap_or_default();
+    let Some((_, summary)) = message.split_once("nodes are available: ") else {
+        return vec!["other"];
+    };
+    let summary = summary
+        .split_once(" preemption:")
+        .map_or(summary, |(summary, _)| summary)
+        .trim_end_matches('.');
+    let mut causes = Vec::new();
+    for predicate in summary.split(", ") {
+        let cause = CAUSES
+            .iter()
+            .find(|(_, phrases)| phrases.iter().any(|phrase| predicate.contains(phrase)))
+            .map_or("other", |(cause, _)| *cause);
+        if !causes.contains(&cause) {
+            causes.push(cause);
+        }
+    }
+    causes
+}
@@ -0,0 +1,155 @@
+use schemars::{schema::RootSchema, JsonSchema};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// The payload every sink, script, plugin and API gets for an event. It's ours rather
+// than the k8s Event, so it doesn't change along with k8s-openapi.
+//
+// Within a version fields are only ever added, and only optional ones: none is renamed,
+// removed or changes type, so a consumer of version N can read every record of it.
+// Anything else is a new version. The `///` docs end up as descriptions in the json
+// schema printed by `k8rs schema`.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The version of the record's schema
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(transparent)]
+pub struct SchemaVersion(pub u32);
+
+// the records written before there was a version are the first one
+impl Default for SchemaVersion {
+    fn default() -> Self {
+        SchemaVersion(SCHEMA_VERSION)
+    }
+}
+
+/// What k8rs delivers for each event: the interesting bits of the Kubernetes event,
+/// plus whatever it found out about the object it's about
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
+pub struct EventRecord {
+    #[serde(default)]
+    pub schema_version: SchemaVersion,
+    /// The event's uid
+    pub uid: String,
+    /// The event's namespace, empty for the cluster scoped objects
+    pub namespace: String,
+    /// The event's name
+    pub name: String,
+    /// The kind of the object the event is about, like "Pod"
+    pub kind: String,
+    pub object_name: String,
+    pub object_uid: String,
+    /// Like "BackOff"
+    pub reason: String,
+    pub message: String,
+    /// "Normal" or "Warning"
+    #[serde(rename = "type")]
+    pub type_: String,
+    /// The component that reported the event, like "kubelet"
+    pub source: String,
+    /// RFC 3339, with milliseconds
+    pub first_timestamp: Option<String>,
+    /// RFC 3339, with milliseconds
+    pub last_timestamp: Option<String>,
+    /// How many times the event happened
+    pub count: i32,
+    /// The node of the pod, or the node the event is about
+    pub node: Option<String>,
+    /// The node's topology.kubernetes.io/zone
+    pub zone: Option<String>,
+    /// The pod's owner, like "Deployment/api"
+    pub owner: Option<String>,
+    /// The container of the pod the event is about, like "api"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    /// What that container is to the pod: "main", "init", "sidecar" (an init container
+    /// with restartPolicy: Always) or "ephemeral"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container_type: Option<String>,
+    /// The pod labels and annotations of enrichment.pod_labels and pod_annotations
+    pub labels: BTreeMap<String, String>,
+    /// How many identical events were rolled up into this record, see pipeline.aggregation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregated: Option<u64>,
+    /// The message of messages.templates for its reason, if there's one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// The last lines of the container's logs, see container_logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logs: Option<String>,
+    /// The pod of a Warning event, see failure_context
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<PodDescription>,
+    /// The correlated events of an Incident record, see incidents
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub incident: Option<IncidentDetails>,
+    /// The change of an RBAC object or service account token, see rbac_audit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit: Option<AuditDetails>,
+    /// "info", "warning" or "critical", the one of severities.reasons for its reason or
+    /// else the one of its type
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<String>,
+}
+
+/// The events of a workload, its pods and its replicasets from a Warning until it was
+/// quiet for a while, the ones just before the Warning included
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
+pub struct IncidentDetails {
+    /// Like "Deployment/api"
+    pub workload: String,
+    /// RFC 3339, when its first event came
+    pub started: String,
+    /// RFC 3339, when its last event came
+    pub ended: String,
+    /// How many events of each reason it had
+    pub reasons: BTreeMap<String, u64>,
+    /// The first ones first, like "Pod/api-7c5ddbdf54-x2x9z Warning BackOff (x3): Back-off
+    /// restarting failed container"
+    pub events: Vec<String>,
+}
+
+/// What a change to a Role, RoleBinding, ClusterRole, ClusterRoleBinding or service
+/// account token granted and took away
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
+pub struct AuditDetails {
+    /// "created", "updated" or "deleted"
+    pub action: String,
+    /// The field manager of the change, like "kubectl-client-side-apply", unknown for the
+    /// deletions
+    pub manager: Option<String>,
+    /// The rules or subjects it grants now, like "get,list apps/deployments" or "to User
+    /// alice"
+    pub added: Vec<String>,
+    /// The ones it no longer grants
+    pub removed: Vec<String>,
+}
+
+/// What kubectl describe would have said about the pod of a Warning event, from the
+/// pod cache and the pod's last events. The node and owner are in the record already
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
+pub struct PodDescription {
+    pub phase: Option<String>,
+    /// Like "Ready=False (ContainersNotReady)"
+    pub conditions: Vec<String>,
+    /// Like "api: waiting (CrashLoopBackOff), 5 restarts", the init containers, sidecars
+    /// and ephemeral ones starting with "init ", "sidecar " or "ephemeral "
+    pub containers: Vec<String>,
+    /// The last ones first, like "Warning BackOff (x12): Back-off restarting failed container"
+    pub recent_events: Vec<String>,
+}
+
+// where the schema of this version says it's from, the dataschema of the CloudEvents
+pub fn id() -> String {
+    format!(
+        "https://k8rs.io/schemas/event-record/v{}.json",
+        SCHEMA_VERSION
+    )
+}
+
+// the json schema of the records, for `k8rs schema`
+pub fn document() -> RootSchema {
+    let mut schema = schemars::schema_for!(EventRecord);
+    schema.schema.metadata().id = Some(id());
+    schema
+}
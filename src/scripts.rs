@@ -0,0 +1,207 @@
+use crate::{
+    config::{ScriptSettings, WatcherSettings},
+    error::{self, Context, ErrorKind},
+    metrics::{sanitize_label_name, SCRIPT_ERRORS_COUNTER, SCRIPT_LABEL},
+    record::EventRecord,
+    sinks::Routing,
+    snapshot::SNAPSHOT,
+    watch::{pausable, watcher_config, WatcherBackoff},
+};
+use axum_prometheus::metrics::{counter, Label};
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::{
+    api::ListParams,
+    runtime::{watcher, WatchStreamExt},
+    Api, Client,
+};
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use serde::Deserialize;
+use std::{collections::BTreeMap, sync::RwLock};
+use tracing::{info, warn};
+
+// the function every script has to define, it gets the event record as a map
+const ENTRY_POINT: &str = "on_event";
+
+// what a script can return, anything left out changes nothing
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ScriptResult {
+    // added to the record's labels
+    labels: BTreeMap<String, String>,
+    // sinks the record is delivered to, besides the pipeline's
+    sinks: Vec<String>,
+    // keeps the record from the pipeline's sinks
+    drop: bool,
+}
+
+struct Script {
+    name: String,
+    ast: AST,
+}
+
+// the user's scripts, run on every record of the pod pipeline in the order of their
+// ConfigMap keys. They can add labels to the record, send it somewhere else, keep it
+// from the sinks and count whatever they want with `count(name, labels)`.
+pub struct Scripts {
+    engine: Engine,
+    scripts: RwLock<Vec<Script>>,
+    config_map: Option<String>,
+}
+
+impl Scripts {
+    // compiles the scripts of the ConfigMap as it is now, refusing to start with
+    // scripts that don't compile like we refuse invalid configs
+    pub async fn load(client: Client, settings: &ScriptSettings) -> Result<Self, ErrorKind> {
+        let mut engine = Engine::new();
+        // a runaway script shouldn't hold up the pipeline forever
+        engine.set_max_operations(settings.max_operations);
+        engine.register_fn("count", |name: &str, labels: Map| {
+            let labels = labels
+                .into_iter()
+                .map(|(key, value)| Label::new(key.to_string(), value.to_string()))
+                .collect::<Vec<_>>();
+            counter!(sanitize_label_name("script", name), labels).increment(1);
+        });
+
+        let scripts = Scripts {
+            engine,
+            scripts: RwLock::new(Vec::new()),
+            config_map: settings.config_map.clone(),
+        };
+        if let Some(ref name) = settings.config_map {
+            let api: Api<ConfigMap> = Api::default_namespaced(client);
+            let list = api
+                .list(&ListParams::default().fields(&format!("metadata.name={}", name)))
+                .await
+                .with_context(|| format!("could not get the scripts ConfigMap {}", name))?;
+            let Some(config_map) = list.items.into_iter().next() else {
+                return Err(format!("the scripts ConfigMap {} doesn't exist", name).into());
+            };
+            let compiled = scripts.compile(&config_map)?;
+            info!(
+                "Loaded {} script(s) from ConfigMap {}",
+                compiled.len(),
+                name
+            );
+            *scripts.scripts.write().expect("scripts lock poisoned") = compiled;
+        }
+        Ok(scripts)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.config_map.is_none()
+    }
+
+    // every `.rhai` key of the ConfigMap is a script
+    fn compile(&self, config_map: &ConfigMap) -> Result<Vec<Script>, String> {
+        let Some(ref data) = config_map.data else {
+            return Ok(Vec::new());
+        };
+        let mut scripts = Vec::new();
+        for (key, source) in data.iter() {
+            let Some(name) = key.strip_suffix(".rhai") else {
+                continue;
+            };
+            let ast = self
+                .engine
+                .compile(source)
+                .map_err(|err| format!("script {} doesn't compile: {}", key, err))?;
+            if !ast
+                .iter_functions()
+                .any(|function| function.name == ENTRY_POINT && function.params.len() == 1)
+            {
+                return Err(format!(
+                    "script {} has no {}(event) function",
+                    key, ENTRY_POINT
+                ));
+            }
+            scripts.push(Script {
+                name: name.to_string(),
+                ast,
+            });
+        }
+        Ok(scripts)
+    }
+
+    // runs the scripts on a record, each one seeing the labels the ones before it added
+    pub fn run(&self, record: &mut EventRecord, routing: &mut Routing) {
+        let scripts = self.scripts.read().expect("scripts lock poisoned");
+        for script in scripts.iter() {
+            let result = rhai::serde::to_dynamic(&*record)
+                .and_then(|event| {
+                    self.engine.call_fn::<Dynamic>(
+                        &mut Scope::new(),
+                        &script.ast,
+                        ENTRY_POINT,
+                        (event,),
+                    )
+                })
+                .and_then(|result| match result.is_unit() {
+                    true => Ok(ScriptResult::default()),
+                    false => rhai::serde::from_dynamic::<ScriptResult>(&result),
+                });
+            match result {
+                Ok(result) => {
+                    record.labels.extend(result.labels);
+                    routing.sinks.extend(result.sinks);
+                    routing.drop |= result.drop;
+                }
+                Err(err) => {
+                    counter!(
+                        SCRIPT_ERRORS_COUNTER,
+                        &[(SCRIPT_LABEL, script.name.clone())]
+                    )
+                    .increment(1);
+                    warn!(
+                        "Script {} failed on event {}: {}",
+                        script.name, record.name, err
+                    );
+                }
+            }
+        }
+    }
+
+    // recompiles the scripts whenever their ConfigMap changes. Scripts that don't
+    // compile are refused and the ones we had are kept.
+    pub async fn watch(&self, client: Client, settings: &WatcherSettings) {
+        let Some(ref name) = self.config_map else {
+            return;
+        };
+        let api: Api<ConfigMap> = Api::default_namespaced(client);
+        let config = watcher_config(settings).fields(&format!("metadata.name={}", name));
+        let mut config_maps = Box::pin(
+            pausable(
+                "scripts",
+                watcher(api, config)
+                    .backoff(WatcherBackoff::new("scripts", &settings.backoff))
+                    .inspect(|event| SNAPSHOT.watcher("scripts", event)),
+            )
+            .applied_objects(),
+        );
+        while let Some(config_map) = config_maps.next().await {
+            let config_map = match config_map {
+                Ok(config_map) => config_map,
+                Err(err) => {
+                    error::Error::new("scripts", err).on(name.clone()).report();
+                    continue;
+                }
+            };
+            match self.compile(&config_map) {
+                Ok(compiled) => {
+                    info!(
+                        "Reloaded {} script(s) from ConfigMap {}",
+                        compiled.len(),
+                        name
+                    );
+                    *self.scripts.write().expect("scripts lock poisoned") = compiled;
+                }
+                Err(err) => {
+                    error::Error::config("scripts", format!("keeping the old ones: {}", err))
+                        .on(name.clone())
+                        .report()
+                }
+            }
+        }
+    }
+}
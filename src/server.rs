@@ -0,0 +1,352 @@
+use crate::{
+    clock,
+    config::{MetricsAuthSettings, ServerSettings},
+    error::{Context, ErrorKind},
+    handover,
+    memory::{Lru, TOKEN_REVIEWS_STORE},
+};
+use axum::{
+    extract::Request,
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::{conn::auto, graceful::GracefulShutdown},
+    service::TowerToHyperService,
+};
+use k8s_openapi::api::authentication::v1::{TokenReview, TokenReviewSpec};
+use kube::{
+    api::{ObjectMeta, PostParams},
+    Api, Client,
+};
+use socket2::{Domain, Socket, Type};
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tokio::{
+    net::{TcpListener, UnixListener},
+    sync::Semaphore,
+};
+use tracing::{debug, info, warn};
+
+// the sockets of the http server, bound before anything else so a taken port
+// keeps the operator from starting
+pub struct Listeners {
+    tcp: Vec<TcpListener>,
+    unix: Option<(PathBuf, UnixListener)>,
+}
+
+impl Listeners {
+    pub fn bind(settings: &ServerSettings) -> Result<Self, ErrorKind> {
+        let tcp = settings
+            .listen
+            .iter()
+            .map(|address| {
+                bind_tcp(*address).with_context(|| format!("could not listen on {}", address))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let unix = match settings.unix_socket {
+            Some(ref path) => {
+                let listener =
+                    bind_unix(path).with_context(|| format!("could not listen on {:?}", path))?;
+                Some((path.clone(), listener))
+            }
+            None => None,
+        };
+        Ok(Listeners { tcp, unix })
+    }
+
+    // serves the app on every socket until the kill signal
+    pub async fn serve(self, app: Router) {
+        let mut servers = tokio::task::JoinSet::new();
+        for listener in self.tcp {
+            if let Ok(address) = listener.local_addr() {
+                info!("Listening on {}", address);
+            }
+            let app = app.clone();
+            let serving = handover::serving();
+            servers.spawn(async move {
+                let _serving = serving;
+                let _ = axum::serve(listener, app)
+                    .with_graceful_shutdown(crate::shutdown_signal())
+                    .await;
+            });
+        }
+        if let Some((path, listener)) = self.unix {
+            info!("Listening on {:?}", path);
+            let serving = handover::serving();
+            servers.spawn(async move {
+                let _serving = serving;
+                serve_unix(listener, app).await;
+                // still listened on after a restart
+                if !handover::requested_now() {
+                    let _ = std::fs::remove_file(path);
+                }
+            });
+        }
+        while servers.join_next().await.is_some() {}
+    }
+}
+
+// the ipv6 sockets only take ipv6, so [::] and 0.0.0.0 can be listened on together.
+// One inherited (see handover) is taken rather than bound again.
+pub fn bind_tcp(address: SocketAddr) -> std::io::Result<TcpListener> {
+    let listener = match handover::tcp(address) {
+        Some(listener) => {
+            info!("Took over the socket of {}", address);
+            listener
+        }
+        None => {
+            let socket = Socket::new(Domain::for_address(address), Type::STREAM, None)?;
+            if address.is_ipv6() {
+                socket.set_only_v6(true)?;
+            }
+            socket.set_reuse_address(true)?;
+            socket.bind(&address.into())?;
+            socket.listen(1024)?;
+            socket.into()
+        }
+    };
+    listener.set_nonblocking(true)?;
+    handover::keep(&listener)?;
+    TcpListener::from_std(listener)
+}
+
+fn bind_unix(path: &Path) -> std::io::Result<UnixListener> {
+    let listener = match handover::unix(path) {
+        Some(listener) => {
+            info!("Took over the socket of {:?}", path);
+            listener
+        }
+        None => {
+            // left behind by a previous run
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+            std::os::unix::net::UnixListener::bind(path)?
+        }
+    };
+    listener.set_nonblocking(true)?;
+    handover::keep(&listener)?;
+    UnixListener::from_std(listener)
+}
+
+// axum only serves tcp listeners, so the connections are handed to hyper ourselves.
+// Like axum's, once the kill signal (or a restart) comes they finish the requests they
+// have before it returns, the scrapes in flight aren't cut off by the restart.
+async fn serve_unix(listener: UnixListener, app: Router) {
+    let builder = auto::Builder::new(TokioExecutor::new());
+    let connections = GracefulShutdown::new();
+    let shutdown = crate::shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = &mut shutdown => break,
+        };
+        let socket = match accepted {
+            Ok((socket, _)) => socket,
+            Err(err) => {
+                debug!("Could not accept a connection on the unix socket: {}", err);
+                continue;
+            }
+        };
+        let service = TowerToHyperService::new(app.clone());
+        let connection = builder
+            .serve_connection_with_upgrades(TokioIo::new(socket), service)
+            .into_owned();
+        let connection = connections.watch(connection);
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                debug!("Connection on the unix socket failed: {}", err);
+            }
+        });
+    }
+    drop(listener);
+    connections.shutdown().await;
+}
+
+// the most tokens whose review is kept, the scrapers are a handful but anyone reaching
+// /metrics can make up new ones
+const MAX_REVIEWED: usize = 1024;
+
+// the most reviews waiting on the api server at once, the scrapes past it get a 503
+const MAX_REVIEWING: usize = 8;
+
+// checks the bearer tokens of the scrapes with the api server, so only the in-cluster
+// prometheus (or whichever service accounts are allowed) can read the metrics
+pub struct TokenReviewer {
+    client: Client,
+    settings: MetricsAuthSettings,
+    // whether each token was allowed and when it was reviewed
+    reviewed: Mutex<Lru<String, (Instant, bool)>>,
+    reviewing: Semaphore,
+}
+
+impl TokenReviewer {
+    pub fn new(client: Client, settings: &MetricsAuthSettings) -> Self {
+        TokenReviewer {
+            client,
+            settings: settings.clone(),
+            reviewed: Mutex::new(Lru::new(TOKEN_REVIEWS_STORE, |_| MAX_REVIEWED)),
+            reviewing: Semaphore::new(MAX_REVIEWING),
+        }
+    }
+
+    // the routes only answer the requests with a token that's allowed
+    pub fn protect(self: Arc<Self>, router: Router) -> Router {
+        router.layer(middleware::from_fn(move |request: Request, next: Next| {
+            let reviewer = self.clone();
+            async move { reviewer.authorize(request, next).await }
+        }))
+    }
+
+    async fn authorize(&self, request: Request, next: Next) -> Response {
+        let token = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(bearer)
+            .map(str::to_string);
+        let Some(token) = token else {
+            return (StatusCode::UNAUTHORIZED, "a bearer token is needed").into_response();
+        };
+        match self.allowed(&token).await {
+            Ok(Some(true)) => next.run(request).await,
+            Ok(Some(false)) => (StatusCode::FORBIDDEN, "the token isn't allowed").into_response(),
+            Ok(None) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "too many tokens being reviewed",
+            )
+                .into_response(),
+            Err(err) => {
+                warn!("Could not review the token of a scrape: {}", err);
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "could not review the token",
+                )
+                    .into_response()
+            }
+        }
+    }
+
+    // none when there are too many reviews going on already
+    async fn allowed(&self, token: &str) -> Result<Option<bool>, kube::Error> {
+        if let Some(allowed) = self.cached(token) {
+            return Ok(Some(allowed));
+        }
+        let Ok(_reviewing) = self.reviewing.try_acquire() else {
+            return Ok(None);
+        };
+        let review = TokenReview {
+            metadata: ObjectMeta::default(),
+            spec: TokenReviewSpec {
+                token: Some(token.to_string()),
+                audiences: Some(self.settings.audiences.clone())
+                    .filter(|audiences| !audiences.is_empty()),
+            },
+            status: None,
+        };
+        let reviewed = Api::<TokenReview>::all(self.client.clone())
+            .create(&PostParams::default(), &review)
+            .await?;
+        let status = reviewed.status.unwrap_or_default();
+        let user = status
+            .user
+            .and_then(|user| user.username)
+            .unwrap_or_default();
+        // the service accounts authenticate as system:serviceaccount:<namespace>:<name>
+        let allowed = status.authenticated == Some(true)
+            && (self.settings.service_accounts.is_empty()
+                || self.settings.service_accounts.iter().any(|account| {
+                    user == format!("system:serviceaccount:{}", account.replacen('/', ":", 1))
+                }));
+        if !allowed {
+            debug!("Refused a scrape by {:?}", user);
+        }
+        self.reviewed
+            .lock()
+            .expect("token reviews lock poisoned")
+            .insert(token.to_string(), (clock::instant(), allowed));
+        Ok(Some(allowed))
+    }
+
+    // the review of the token, while it's kept
+    fn cached(&self, token: &str) -> Option<bool> {
+        let mut reviewed = self.reviewed.lock().expect("token reviews lock poisoned");
+        let (at, allowed) = *reviewed.get_mut(&token.to_string())?;
+        if clock::instant().duration_since(at) < self.settings.cache {
+            return Some(allowed);
+        }
+        reviewed.remove(token);
+        None
+    }
+}
+
+// the token of an authorization header, whose scheme is case insensitive (RFC 7235)
+fn bearer(authorization: &str) -> Option<&str> {
+    let (scheme, token) = authorization.trim().split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use k8s_openapi::chrono::{TimeDelta, Utc};
+
+    #[test]
+    fn takes_the_token_of_any_case_of_bearer() {
+        assert_eq!(bearer("Bearer abc"), Some("abc"));
+        assert_eq!(bearer("bearer  abc "), Some("abc"));
+        assert_eq!(bearer("BEARER abc"), Some("abc"));
+        assert_eq!(bearer("Basic abc"), None);
+        assert_eq!(bearer("Bearer "), None);
+        assert_eq!(bearer("Bearer"), None);
+    }
+
+    // nothing answers on the api server's port, the reviews that aren't kept fail
+    #[tokio::test]
+    async fn keeps_the_reviews_for_the_cache_and_so_many_at_once() {
+        let start = Utc::now();
+        let manual = Arc::new(ManualClock::new(start));
+        clock::set_local(manual.clone());
+        let url = "http://127.0.0.1:1".parse().unwrap();
+        let client = Client::try_from(kube::Config::new(url)).unwrap();
+        let reviewer = TokenReviewer::new(client, &MetricsAuthSettings::default());
+        reviewer
+            .reviewed
+            .lock()
+            .unwrap()
+            .insert("prometheus".to_string(), (clock::instant(), true));
+        assert_eq!(reviewer.allowed("prometheus").await.unwrap(), Some(true));
+
+        // the other tokens wait for a review, when there's room for one
+        let _busy = reviewer.reviewing.acquire_many(MAX_REVIEWING as u32).await;
+        assert_eq!(reviewer.allowed("made-up").await.unwrap(), None);
+        assert!(!reviewer
+            .reviewed
+            .lock()
+            .unwrap()
+            .contains_key(&"made-up".to_string()));
+
+        manual.advance_to(start + TimeDelta::from_std(reviewer.settings.cache).unwrap());
+        assert_eq!(reviewer.allowed("prometheus").await.unwrap(), None);
+
+        for token in 0..MAX_REVIEWED + 10 {
+            reviewer
+                .reviewed
+                .lock()
+                .unwrap()
+                .insert(token.to_string(), (clock::instant(), false));
+        }
+        assert_eq!(reviewer.reviewed.lock().unwrap().len(), MAX_REVIEWED);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::{
+    config::WatcherSettings,
+    error::Error,
+    metrics::{
+        NAMESPACE_LABEL, REASON_LABEL, SERVICES_WITHOUT_ENDPOINTS_GAUGE, SERVICE_EVENTS_COUNTER,
+        SERVICE_LABEL,
+    },
+    snapshot::SNAPSHOT,
+    watch::{pausable, watcher_config, WatcherBackoff},
+};
+use axum_prometheus::metrics::{counter, gauge};
+use futures::StreamExt;
+use k8s_openapi::api::{core::v1::Event, discovery::v1::EndpointSlice};
+use kube::{
+    runtime::{watcher, WatchStreamExt},
+    Api, Client, ResourceExt,
+};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+// the label the endpointslice controller puts on the slices of a service
+const SERVICE_NAME_LABEL: &str = "kubernetes.io/service-name";
+
+// the events about services and their endpoints, like FailedToUpdateEndpoint or
+// SyncLoadBalancerFailed. Services, Endpoints and EndpointSlices are named after
+// their service (slices get a suffix), so they're all counted under it.
+pub fn handle_event(event: &Event) {
+    let object = &event.involved_object;
+    let name = object.name.clone().unwrap_or_default();
+    let service = match object.kind.as_deref() {
+        Some("EndpointSlice") => match name.rsplit_once('-') {
+            Some((service, _)) => service.to_string(),
+            None => name,
+        },
+        _ => name,
+    };
+    counter!(
+        SERVICE_EVENTS_COUNTER,
+        &[
+            (
+                NAMESPACE_LABEL,
+                object.namespace.clone().unwrap_or_default()
+            ),
+            (SERVICE_LABEL, service),
+            (REASON_LABEL, event.reason.clone().unwrap_or_default()),
+        ]
+    )
+    .increment(1);
+}
+
+// the ready endpoints of every slice, by namespace and service
+#[derive(Default)]
+struct Slices {
+    ready: BTreeMap<(String, String), BTreeMap<String, usize>>,
+    // the namespaces we've set a gauge for, so they can go back to 0
+    namespaces: BTreeSet<String>,
+}
+
+impl Slices {
+    fn apply(&mut self, slice: &EndpointSlice) {
+        let Some(service) = slice.labels().get(SERVICE_NAME_LABEL).cloned() else {
+            return;
+        };
+        // endpoints without a ready condition are ready
+        let ready = slice
+            .endpoints
+            .iter()
+            .filter(|endpoint| {
+                endpoint
+                    .conditions
+                    .as_ref()
+                    .and_then(|conditions| conditions.ready)
+                    .unwrap_or(true)
+            })
+            .count();
+        self.ready
+            .entry((slice.namespace().unwrap_or_default(), service))
+            .or_default()
+            .insert(slice.name_any(), ready);
+    }
+
+    fn delete(&mut self, slice: &EndpointSlice) {
+        let Some(service) = slice.labels().get(SERVICE_NAME_LABEL).cloned() else {
+            return;
+        };
+        let key = (slice.namespace().unwrap_or_default(), service);
+        if let Some(slices) = self.ready.get_mut(&key) {
+            slices.remove(&slice.name_any());
+            // no slices left means the service is gone
+            if slices.is_empty() {
+                self.ready.remove(&key);
+            }
+        }
+    }
+
+    fn export(&mut self) {
+        let mut without_endpoints = BTreeMap::<&String, u64>::new();
+        for ((namespace, _), slices) in self.ready.iter() {
+            let count = without_endpoints.entry(namespace).or_default();
+            if slices.values().sum::<usize>() == 0 {
+                *count += 1;
+            }
+        }
+        for namespace in self.namespaces.iter() {
+            if !without_endpoints.contains_key(namespace) {
+                gauge!(
+                    SERVICES_WITHOUT_ENDPOINTS_GAUGE,
+                    &[(NAMESPACE_LABEL, namespace.clone())]
+                )
+                .set(0.0);
+            }
+        }
+        for (namespace, count) in without_endpoints.iter() {
+            gauge!(
+                SERVICES_WITHOUT_ENDPOINTS_GAUGE,
+                &[(NAMESPACE_LABEL, (*namespace).clone())]
+            )
+            .set(*count as f64);
+        }
+        self.namespaces = without_endpoints.into_keys().cloned().collect();
+    }
+}
+
+// watches the endpointslices, to tell which services have no ready endpoints
+pub async fn watch_endpoint_slices(client: Client, settings: &WatcherSettings) {
+    let api: Api<EndpointSlice> = Api::default_namespaced(client);
+    let backoff = WatcherBackoff::new("endpointslices", &settings.backoff);
+    let mut stream = Box::pin(pausable(
+        "endpointslices",
+        watcher(api, watcher_config(settings))
+            .backoff(backoff)
+            .inspect(|event| SNAPSHOT.watcher("endpointslices", event)),
+    ));
+
+    let mut slices = Slices::default();
+    let mut relisted = HashSet::new();
+    while let Some(event) = stream.next().await {
+        match event {
+            Ok(watcher::Event::Init) => relisted.clear(),
+            Ok(watcher::Event::InitApply(slice)) => {
+                relisted.insert((slice.namespace().unwrap_or_default(), slice.name_any()));
+                slices.apply(&slice);
+            }
+            Ok(watcher::Event::InitDone) => {
+                // forget the slices deleted while we were away
+                for ((namespace, _), names) in slices.ready.iter_mut() {
+                    names.retain(|name, _| relisted.contains(&(namespace.clone(), name.clone())));
+                }
+                slices.ready.retain(|_, names| !names.is_empty());
+                slices.export();
+            }
+            Ok(watcher::Event::Apply(slice)) => {
+                slices.apply(&slice);
+                slices.export();
+            }
+            Ok(watcher::Event::Delete(slice)) => {
+                slices.delete(&slice);
+                slices.export();
+            }
+            Err(err) => Error::new("services", err).report(),
+        }
+    }
+}
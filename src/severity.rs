@@ -0,0 +1,64 @@
+use crate::{
+    config::{Severity, SeveritySettings},
+    record::EventRecord,
+};
+use std::sync::{LazyLock, RwLock};
+
+// the severities of the config by reason, switched on a reload
+static SEVERITIES: LazyLock<RwLock<SeveritySettings>> = LazyLock::new(Default::default);
+
+pub fn configure(settings: &SeveritySettings) {
+    *SEVERITIES.write().expect("severities lock poisoned") = settings.clone();
+}
+
+// the configured severity of the reason, or the one of the event's type
+pub fn of(reason: &str, type_: &str) -> Severity {
+    let severities = SEVERITIES.read().expect("severities lock poisoned");
+    match severities.reasons.get(reason) {
+        Some(severity) => *severity,
+        None if type_ == "Warning" => Severity::Warning,
+        None => Severity::Info,
+    }
+}
+
+// the severity a record was given, the one of its reason for those that weren't
+pub fn of_record(record: &EventRecord) -> Severity {
+    [Severity::Info, Severity::Warning, Severity::Critical]
+        .into_iter()
+        .find(|severity| record.severity.as_deref() == Some(severity.as_str()))
+        .unwrap_or_else(|| of(&record.reason, &record.type_))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{configure, of, of_record};
+    use crate::{
+        config::{Severity, SeveritySettings},
+        record::EventRecord,
+    };
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn takes_the_severity_of_the_reason_or_of_the_type() {
+        configure(&SeveritySettings {
+            reasons: BTreeMap::from([
+                ("OOMKilling".to_string(), Severity::Critical),
+                ("BackOff".to_string(), Severity::Info),
+            ]),
+        });
+        assert_eq!(of("OOMKilling", "Warning"), Severity::Critical);
+        // even a Warning, when it's said so
+        assert_eq!(of("BackOff", "Warning"), Severity::Info);
+        assert_eq!(of("FailedMount", "Warning"), Severity::Warning);
+        assert_eq!(of("Pulled", "Normal"), Severity::Info);
+
+        let record = |severity: Option<&str>| EventRecord {
+            reason: "OOMKilling".to_string(),
+            type_: "Warning".to_string(),
+            severity: severity.map(str::to_string),
+            ..EventRecord::default()
+        };
+        assert_eq!(of_record(&record(Some("info"))), Severity::Info);
+        assert_eq!(of_record(&record(None)), Severity::Critical);
+    }
+}
@@ -0,0 +1,218 @@
+use crate::{
+    clock,
+    config::ShardingSettings,
+    error::{self, Context, ErrorKind},
+    metrics::{SHARD_MEMBERS_GAUGE, SHARD_REBALANCES_COUNTER},
+};
+use axum_prometheus::metrics::{counter, gauge};
+use k8s_openapi::{
+    api::coordination::v1::Lease, apimachinery::pkg::apis::meta::v1::MicroTime, chrono::TimeDelta,
+};
+use kube::{
+    api::{DeleteParams, ListParams, Patch, PatchParams},
+    Api, Client,
+};
+use std::sync::Arc;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+// the label of the leases telling which replicas shard together
+const GROUP_LABEL: &str = "k8rs.io/shard-group";
+
+// this replica's share of the discovered namespaces. Every namespace goes to one of the
+// members by rendezvous hashing: the member it hashes highest with. A replica joining
+// only takes the namespaces it now hashes highest with, one leaving only hands over what
+// it had, the others keep theirs.
+#[derive(Clone)]
+pub struct Shard {
+    identity: String,
+    members: watch::Receiver<Arc<Vec<String>>>,
+}
+
+impl Shard {
+    pub fn owns(&self, namespace: &str) -> bool {
+        let members = self.members.borrow();
+        members
+            .iter()
+            .max_by_key(|member| weight(member, namespace))
+            .is_some_and(|owner| *owner == self.identity)
+    }
+
+    // waits for the members to change, false once the membership stopped
+    pub async fn changed(&mut self) -> bool {
+        self.members.changed().await.is_ok()
+    }
+}
+
+// the replica's lease, renewed every heartbeat, and the members of its group: the
+// replicas whose lease hasn't run out
+pub struct Membership {
+    api: Api<Lease>,
+    settings: ShardingSettings,
+    identity: String,
+    lease: String,
+    members: watch::Sender<Arc<Vec<String>>>,
+}
+
+// joins the group, with the members known already so the first namespaces discovered
+// go where they belong. A lease that can't be renewed keeps the operator from starting.
+pub async fn join(
+    client: Client,
+    settings: &ShardingSettings,
+) -> Result<(Shard, Arc<Membership>), ErrorKind> {
+    let identity = match settings.identity {
+        Some(ref identity) => identity.clone(),
+        None => std::env::var("POD_NAME")
+            .or_else(|_| std::env::var("HOSTNAME"))
+            .map_err(|_| "sharding needs sharding.identity, or POD_NAME or HOSTNAME set")?
+            .to_lowercase(),
+    };
+    let (sender, receiver) = watch::channel(Arc::new(Vec::new()));
+    let membership = Membership {
+        api: Api::default_namespaced(client),
+        settings: settings.clone(),
+        lease: format!("{}-shard-{}", settings.group, identity),
+        identity: identity.clone(),
+        members: sender,
+    };
+    membership
+        .renew()
+        .await
+        .with_context(|| format!("could not renew lease {}", membership.lease))?;
+    let members = membership
+        .members()
+        .await
+        .context("could not list the leases of the shards")?;
+    info!(
+        "Sharding the namespaces as {} between {} replica(s): {}",
+        identity,
+        members.len(),
+        members.join(", ")
+    );
+    gauge!(SHARD_MEMBERS_GAUGE).set(members.len() as f64);
+    membership.members.send_replace(Arc::new(members));
+    let shard = Shard {
+        identity,
+        members: receiver,
+    };
+    Ok((shard, Arc::new(membership)))
+}
+
+impl Membership {
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(self.settings.heartbeat);
+        interval.tick().await;
+        let mut renewed = clock::instant();
+        loop {
+            interval.tick().await;
+            match self.renew().await {
+                Ok(()) => renewed = clock::instant(),
+                Err(err) => error::Error::new("sharding", err)
+                    .on(self.lease.clone())
+                    .report(),
+            }
+            let members = match self.members().await {
+                // a replica that couldn't renew for as long as its lease lasts lets go of
+                // its share, the others took it over
+                Ok(members)
+                    if renewed.elapsed() < self.settings.lease_duration
+                        || !members.contains(&self.identity) =>
+                {
+                    members
+                }
+                Ok(_) => {
+                    warn!(
+                        "Could not renew lease {} for {}, letting go of the namespaces",
+                        self.lease,
+                        humantime::format_duration(self.settings.lease_duration)
+                    );
+                    Vec::new()
+                }
+                Err(err) => {
+                    error::Error::new("sharding", err).report();
+                    continue;
+                }
+            };
+            if **self.members.borrow() == members {
+                continue;
+            }
+            info!(
+                "The replicas sharding the namespaces are now {}",
+                members.join(", ")
+            );
+            counter!(SHARD_REBALANCES_COUNTER).increment(1);
+            gauge!(SHARD_MEMBERS_GAUGE).set(members.len() as f64);
+            self.members.send_replace(Arc::new(members));
+        }
+    }
+
+    // when stopping, so the others take the share over right away
+    pub async fn leave(&self) {
+        if let Err(err) = self.api.delete(&self.lease, &DeleteParams::default()).await {
+            warn!("Could not delete lease {}: {}", self.lease, err);
+        }
+    }
+
+    async fn renew(&self) -> Result<(), kube::Error> {
+        let lease = serde_json::json!({
+            "apiVersion": "coordination.k8s.io/v1",
+            "kind": "Lease",
+            "metadata": {
+                "name": self.lease,
+                "labels": { GROUP_LABEL: self.settings.group },
+            },
+            "spec": {
+                "holderIdentity": self.identity,
+                "leaseDurationSeconds": self.settings.lease_duration.as_secs(),
+                "renewTime": MicroTime(clock::now()),
+            },
+        });
+        self.api
+            .patch(
+                &self.lease,
+                &PatchParams::apply("k8rs").force(),
+                &Patch::Apply(lease),
+            )
+            .await?;
+        Ok(())
+    }
+
+    // sorted, so they compare the same whatever order they're listed in
+    async fn members(&self) -> Result<Vec<String>, kube::Error> {
+        let params =
+            ListParams::default().labels(&format!("{}={}", GROUP_LABEL, self.settings.group));
+        let now = clock::now();
+        let mut members = self
+            .api
+            .list(&params)
+            .await?
+            .items
+            .into_iter()
+            .filter_map(|lease| {
+                let spec = lease.spec?;
+                let renewed = spec.renew_time?.0;
+                let duration = TimeDelta::seconds(spec.lease_duration_seconds?.into());
+                (renewed + duration > now)
+                    .then_some(spec.holder_identity)
+                    .flatten()
+            })
+            .collect::<Vec<_>>();
+        members.sort();
+        members.dedup();
+        Ok(members)
+    }
+}
+
+// fnv-1a of the member and the namespace, the same on every replica whatever its build.
+// Mixed some more at the end, names a letter apart would weigh alike.
+fn weight(member: &str, namespace: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in member.bytes().chain([b'/']).chain(namespace.bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash
+}
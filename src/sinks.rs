@@ -0,0 +1,700 @@
+mod amqp;
+mod auth;
+#[cfg(feature = "aws")]
+mod aws;
+mod batch;
+mod breaker;
+mod bucket;
+mod cloudevents;
+mod dry_run;
+mod email;
+mod file;
+mod log;
+mod pubsub;
+mod retry;
+mod teams;
+mod webhook;
+
+use crate::{
+    clock,
+    config::{BatchSettings, DeadLetter, SinkFormat, SinkKind, SinkSettings, SuppressionSettings},
+    error::{BoxError, ErrorKind},
+    maintenance,
+    memory::{Lru, SUPPRESSION_STORE},
+    metrics::{SINK_DELIVERY_COUNTER, SINK_LABEL, SINK_RESULT_LABEL, SUPPRESSED_COUNTER},
+    payloads,
+    record::EventRecord,
+    snapshot::SNAPSHOT,
+};
+use async_trait::async_trait;
+use axum_prometheus::metrics::counter;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+    time::Instant,
+};
+use tracing::warn;
+
+pub use amqp::Address as AmqpAddress;
+pub use dry_run::not_sent;
+
+pub type SinkError = BoxError;
+
+// the most records in an email digest, a storm of them gets a mail before the interval is over
+const MAX_DIGEST: usize = 1000;
+
+// a record as the sink's format asks for
+fn encode(format: SinkFormat, record: &EventRecord) -> serde_json::Result<serde_json::Value> {
+    match format {
+        SinkFormat::Json => serde_json::to_value(record),
+        SinkFormat::CloudEvents => serde_json::to_value(cloudevents::envelope(record)),
+    }
+}
+
+// somewhere we can send event records to
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    async fn deliver(&self, record: &EventRecord) -> Result<(), SinkError>;
+
+    // delivers a batch of records at once, those that can send them together do
+    async fn deliver_batch(&self, records: &[EventRecord]) -> Result<(), SinkError> {
+        for record in records {
+            self.deliver(record).await?;
+        }
+        Ok(())
+    }
+
+    // makes sure everything delivered so far actually left the process
+    async fn flush(&self) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    // how many of the records handed to it aren't out yet
+    fn backlog(&self) -> usize {
+        0
+    }
+}
+
+type Sinks = HashMap<String, Arc<dyn EventSink>>;
+
+// all the sinks declared in the config, by name.
+// Clones share the same sinks, so they all see a reload.
+#[derive(Clone, Default)]
+pub struct SinkRegistry {
+    sinks: Arc<RwLock<Sinks>>,
+    // how each encodes the records, for the dispatchers in dry run to log them so
+    formats: Arc<RwLock<HashMap<String, SinkFormat>>>,
+    // what every dispatcher gets, each with its own cooldowns
+    suppression: SuppressionSettings,
+    // the cooldowns of each set of sinks, kept for the dispatchers made for every record
+    // (the routes of the scripts and plugins, the alerts') and across the reloads
+    suppressors: Arc<Mutex<HashMap<Vec<String>, Arc<Suppressor>>>>,
+}
+
+impl SinkRegistry {
+    pub fn from_settings(
+        settings: &[SinkSettings],
+        suppression: &SuppressionSettings,
+    ) -> Result<Self, ErrorKind> {
+        let mut sinks = Sinks::new();
+        for sink in settings {
+            if sinks
+                .insert(sink.name.clone(), build(sink, settings)?)
+                .is_some()
+            {
+                return Err(format!("sink {} is declared more than once", sink.name).into());
+            }
+        }
+        Ok(SinkRegistry {
+            sinks: Arc::new(RwLock::new(sinks)),
+            formats: Arc::new(RwLock::new(formats(settings))),
+            suppression: suppression.clone(),
+            suppressors: Default::default(),
+        })
+    }
+
+    // switches to the sinks of a new config. The ones whose settings didn't change are
+    // kept as they are (along with their open files and connections), the others are
+    // built again. Returns the sinks that were replaced or removed, so whatever uses them
+    // can be restarted and they can be flushed.
+    pub fn reload(
+        &self,
+        previous: &[SinkSettings],
+        settings: &[SinkSettings],
+    ) -> Result<Dispatcher, ErrorKind> {
+        let current = self.sinks.read().expect("sinks lock poisoned").clone();
+        let mut sinks = Sinks::new();
+        for sink in settings {
+            // a sink has its own instance of its dead letter sink
+            let unchanged = |sink: &SinkSettings| previous.iter().any(|old| old == sink);
+            let dead_letter_unchanged = match sink.dead_letter {
+                Some(DeadLetter::Sink(ref name)) => settings
+                    .iter()
+                    .find(|other| other.name == *name)
+                    .is_some_and(unchanged),
+                _ => true,
+            };
+            let built = match current.get(&sink.name) {
+                Some(existing) if unchanged(sink) && dead_letter_unchanged => existing.clone(),
+                _ => build(sink, settings)?,
+            };
+            if sinks.insert(sink.name.clone(), built).is_some() {
+                return Err(format!("sink {} is declared more than once", sink.name).into());
+            }
+        }
+
+        let replaced = current
+            .into_iter()
+            .filter(|(name, old)| !sinks.get(name).is_some_and(|new| Arc::ptr_eq(old, new)))
+            .collect();
+        *self.sinks.write().expect("sinks lock poisoned") = sinks;
+        *self.formats.write().expect("sinks lock poisoned") = formats(settings);
+        // only there to be flushed
+        Ok(Dispatcher {
+            sinks: replaced,
+            ..Dispatcher::default()
+        })
+    }
+
+    // sends out whatever every sink still holds, before shutting down
+    pub async fn flush(&self) {
+        let sinks = self.sinks.read().expect("sinks lock poisoned").clone();
+        for (name, sink) in sinks {
+            if let Err(err) = sink.flush().await {
+                warn!("Could not flush sink {}: {}", name, err);
+            }
+        }
+    }
+
+    // how many records each sink holds on to
+    pub fn backlogs(&self) -> Vec<(String, usize)> {
+        let sinks = self.sinks.read().expect("sinks lock poisoned");
+        sinks
+            .iter()
+            .map(|(name, sink)| (name.clone(), sink.backlog()))
+            .collect()
+    }
+
+    // delivers a record to the sinks the scripts and plugins picked for it
+    pub async fn route(&self, record: &EventRecord, routing: &Routing) {
+        let mut sinks = Vec::new();
+        for sink in routing.sinks.iter() {
+            if !sinks.contains(sink) {
+                sinks.push(sink.clone());
+            }
+        }
+        if sinks.is_empty() {
+            return;
+        }
+        match self.dispatcher(&sinks) {
+            Ok(dispatcher) => dispatcher.dispatch(record).await,
+            Err(err) => warn!("Event {} was routed to an {}", record.name, err),
+        }
+    }
+
+    // a dispatcher sending to the given sinks, all of which must exist
+    pub fn dispatcher(&self, names: &[String]) -> Result<Dispatcher, String> {
+        let current = self.sinks.read().expect("sinks lock poisoned");
+        let sinks = names
+            .iter()
+            .map(|name| match current.get(name) {
+                Some(sink) => Ok((name.clone(), sink.clone())),
+                None => Err(format!("unknown sink {}", name)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let formats = self.formats.read().expect("sinks lock poisoned");
+        let formats = names
+            .iter()
+            .filter_map(|name| Some((name.clone(), *formats.get(name)?)))
+            .collect();
+        let suppressor = self.suppression.cooldown.map(|_| {
+            self.suppressors
+                .lock()
+                .expect("suppressors lock poisoned")
+                .entry(names.to_vec())
+                .or_insert_with(|| Arc::new(Suppressor::new(&self.suppression)))
+                .clone()
+        });
+        Ok(Dispatcher {
+            sinks,
+            formats,
+            suppressor,
+            dry_run: false,
+        })
+    }
+}
+
+fn formats(settings: &[SinkSettings]) -> HashMap<String, SinkFormat> {
+    settings
+        .iter()
+        .map(|sink| (sink.name.clone(), sink.format))
+        .collect()
+}
+
+// builds a sink along with its dead letter destination, out of all the sinks' settings
+// the request signing of the sqs and sns sinks is only built with the aws feature
+#[cfg(feature = "aws")]
+fn aws(sink: &SinkSettings) -> Result<Arc<dyn EventSink>, ErrorKind> {
+    let built = match sink.kind {
+        SinkKind::Sqs {
+            ref queue_url,
+            ref endpoint,
+            timeout,
+        } => aws::AwsSink::sqs(queue_url, endpoint.as_deref(), timeout, sink.format)?,
+        SinkKind::Sns {
+            ref topic_arn,
+            ref endpoint,
+            timeout,
+        } => aws::AwsSink::sns(topic_arn, endpoint.as_deref(), timeout, sink.format)?,
+        _ => return Err(format!("sink {} isn't an aws sink", sink.name).into()),
+    };
+    Ok(Arc::new(built))
+}
+
+#[cfg(not(feature = "aws"))]
+fn aws(sink: &SinkSettings) -> Result<Arc<dyn EventSink>, ErrorKind> {
+    Err(format!("sink {} needs k8rs built with the aws feature", sink.name).into())
+}
+
+fn build(sink: &SinkSettings, settings: &[SinkSettings]) -> Result<Arc<dyn EventSink>, ErrorKind> {
+    // the tokens are taken with the timeout of the sink's requests
+    let auth = |timeout| {
+        sink.auth
+            .as_ref()
+            .map(|auth| auth::Auth::new(auth, timeout))
+            .transpose()
+    };
+    let built: Arc<dyn EventSink> = match sink.kind {
+        SinkKind::Log {} => Arc::new(log::LogSink::new(&sink.name, sink.format)),
+        SinkKind::File { ref path } => Arc::new(file::FileSink::new(path, sink.format)),
+        SinkKind::Webhook {
+            ref url,
+            ref headers,
+            timeout,
+        } => Arc::new(webhook::WebhookSink::new(
+            url,
+            headers,
+            timeout,
+            sink.format,
+            auth(timeout)?,
+        )?),
+        SinkKind::Teams { ref url, timeout } => {
+            Arc::new(teams::TeamsSink::new(url, timeout, auth(timeout)?)?)
+        }
+        SinkKind::Sqs { .. } | SinkKind::Sns { .. } => aws(sink)?,
+        SinkKind::Email {
+            ref host,
+            port,
+            tls,
+            ref username,
+            ref password,
+            ref from,
+            ref to,
+            ref reasons,
+            timeout,
+            ..
+        } => Arc::new(email::EmailSink::new(
+            email::Mail {
+                host: host.clone(),
+                port: port.unwrap_or(tls.default_port()),
+                tls,
+                username: username.clone(),
+                password: password.clone(),
+                from: from.clone(),
+                to: to.clone(),
+                reasons: reasons.clone(),
+            },
+            timeout,
+        )?),
+        SinkKind::Pubsub {
+            ref topic,
+            ref endpoint,
+            timeout,
+        } => Arc::new(pubsub::PubSubSink::new(
+            topic,
+            endpoint,
+            timeout,
+            sink.format,
+            auth(timeout)?,
+        )?),
+        SinkKind::Bucket {
+            ref url,
+            ref key,
+            timeout,
+        } => Arc::new(bucket::BucketSink::new(
+            url,
+            key,
+            timeout,
+            sink.format,
+            auth(timeout)?,
+        )?),
+        SinkKind::Amqp {
+            ref url,
+            ref exchange,
+            ref routing_key,
+            timeout,
+        } => Arc::new(amqp::AmqpSink::new(
+            &sink.name,
+            url,
+            exchange,
+            routing_key,
+            timeout,
+            sink.format,
+        )?),
+    };
+    // built all the same, so its settings are checked
+    let built: Arc<dyn EventSink> = if sink.dry_run {
+        Arc::new(dry_run::DryRunSink::new(&sink.name, sink.format))
+    } else {
+        built
+    };
+    let built: Arc<dyn EventSink> = if sink.circuit_breaker.failures > 0 {
+        Arc::new(breaker::BreakingSink::new(
+            &sink.name,
+            built,
+            &sink.circuit_breaker,
+        ))
+    } else {
+        built
+    };
+    let dead_letter: Option<Arc<dyn EventSink>> = match sink.dead_letter {
+        None => None,
+        Some(DeadLetter::File(ref path)) => {
+            Some(Arc::new(file::FileSink::new(path, SinkFormat::Json)))
+        }
+        Some(DeadLetter::Sink(ref name)) => {
+            let Some(other) = settings.iter().find(|other| other.name == *name) else {
+                return Err(
+                    format!("sink {}: unknown dead letter sink {}", sink.name, name).into(),
+                );
+            };
+            if matches!(other.dead_letter, Some(DeadLetter::Sink(_))) {
+                return Err(format!(
+                    "sink {}: the dead letter sink {} has a dead letter sink of its own",
+                    sink.name, name
+                )
+                .into());
+            }
+            Some(build(other, settings)?)
+        }
+    };
+    // the retries are of the whole batch, and go through the breaker
+    let built: Arc<dyn EventSink> = if sink.retry.attempts > 1 || dead_letter.is_some() {
+        Arc::new(retry::RetryingSink::new(
+            &sink.name,
+            built,
+            &sink.retry,
+            dead_letter,
+        ))
+    } else {
+        built
+    };
+    // an email sink's batches are its digests
+    let batch = match sink.kind {
+        SinkKind::Email { interval, .. } => BatchSettings {
+            max_size: MAX_DIGEST,
+            max_interval: interval,
+        },
+        _ => sink.batch.clone(),
+    };
+    if batch.max_size <= 1 {
+        return Ok(built);
+    }
+    Ok(batch::BatchingSink::new(&sink.name, built, &batch))
+}
+
+// where the scripts and plugins want a record to go
+#[derive(Default)]
+pub struct Routing {
+    // sinks the record is delivered to, besides the pipeline's
+    pub sinks: Vec<String>,
+    // keeps the record from the pipeline's sinks
+    pub drop: bool,
+}
+
+// delivers records to a set of sinks, counting how each delivery went
+#[derive(Clone, Default)]
+pub struct Dispatcher {
+    sinks: Vec<(String, Arc<dyn EventSink>)>,
+    formats: HashMap<String, SinkFormat>,
+    suppressor: Option<Arc<Suppressor>>,
+    // nothing is handed to the sinks, whether they're in dry run or not
+    dry_run: bool,
+}
+
+impl Dispatcher {
+    pub fn dry_run(self) -> Self {
+        Dispatcher {
+            dry_run: true,
+            ..self
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.sinks.iter().map(|(name, _)| name)
+    }
+
+    pub async fn dispatch(&self, record: &EventRecord) {
+        if self.sinks.is_empty() {
+            return;
+        }
+        if let Some(ref suppressor) = self.suppressor {
+            if !suppressor.allows(record) {
+                counter!(SUPPRESSED_COUNTER).increment(1);
+                return;
+            }
+        }
+        // what the sinks get is cut down to the payload limits, not what's matched on
+        let limited = payloads::limited(record);
+        let record = limited.as_ref();
+        for (name, sink) in self.sinks.iter() {
+            if maintenance::holds(name, record) {
+                continue;
+            }
+            // what the sink would have sent, encoded the way it does
+            let delivered = if self.dry_run {
+                let format = self.formats.get(name).copied().unwrap_or_default();
+                encode(format, record)
+                    .map(|payload| not_sent(name, payload))
+                    .map_err(SinkError::from)
+            } else {
+                sink.deliver(record).await
+            };
+            let result = match delivered {
+                Ok(()) => {
+                    SNAPSHOT.delivery(name, Ok(()));
+                    "success"
+                }
+                Err(err) => {
+                    warn!(
+                        "Could not deliver event {} to sink {}: {}",
+                        record.name, name, err
+                    );
+                    SNAPSHOT.delivery(name, Err(err.to_string()));
+                    "failure"
+                }
+            };
+            counter!(
+                SINK_DELIVERY_COUNTER,
+                &[
+                    (SINK_LABEL, name.clone()),
+                    (SINK_RESULT_LABEL, result.to_string())
+                ]
+            )
+            .increment(1);
+        }
+    }
+
+    pub async fn flush(&self) {
+        for (name, sink) in self.sinks.iter() {
+            if let Err(err) = sink.flush().await {
+                warn!("Could not flush sink {}: {}", name, err);
+            }
+        }
+    }
+}
+
+// namespace, kind, object name and reason
+type SuppressionKey = (String, String, String, String);
+
+// past this many objects, the ones whose cooldown is over are forgotten
+const SUPPRESSOR_PRUNE_AT: usize = 4096;
+
+// keeps the same notification from going out over and over: once an object's event
+// of some reason went through, the next ones are held back until the cooldown is over,
+// except for every Nth of them, so a storm still shows up now and then
+struct Suppressor {
+    settings: SuppressionSettings,
+    // when the cooldown of each started, and how many came since
+    seen: Mutex<Lru<SuppressionKey, (Instant, u64)>>,
+}
+
+impl Suppressor {
+    fn new(settings: &SuppressionSettings) -> Self {
+        Suppressor {
+            settings: settings.clone(),
+            seen: Mutex::new(Lru::new(SUPPRESSION_STORE, |limits| limits.suppression)),
+        }
+    }
+
+    fn allows(&self, record: &EventRecord) -> bool {
+        let Some(cooldown) = self.settings.cooldown else {
+            return true;
+        };
+        let now = clock::instant();
+        let key = (
+            record.namespace.clone(),
+            record.kind.clone(),
+            record.object_name.clone(),
+            record.reason.clone(),
+        );
+        let mut seen = self.seen.lock().expect("suppression lock poisoned");
+        match seen.get_mut(&key) {
+            Some((since, count)) if now.duration_since(*since) < cooldown => {
+                *count += 1;
+                self.settings.every > 0 && *count % self.settings.every == 0
+            }
+            _ => {
+                if seen.len() >= SUPPRESSOR_PRUNE_AT {
+                    seen.retain(|_, (since, _)| now.duration_since(*since) < cooldown);
+                }
+                seen.insert(key, (now, 1));
+                true
+            }
+        }
+    }
+}
+
+// a sink keeping every batch it's handed and failing the first deliveries it's told
+// to, for the tests of the sinks wrapping another and of what dispatches to them
+#[cfg(test)]
+#[derive(Default)]
+pub struct TestSink {
+    pub delivered: Mutex<Vec<Vec<EventRecord>>>,
+    pub failing: std::sync::atomic::AtomicUsize,
+}
+
+#[cfg(test)]
+impl TestSink {
+    pub fn failing(deliveries: usize) -> Arc<Self> {
+        Arc::new(TestSink {
+            failing: deliveries.into(),
+            ..Default::default()
+        })
+    }
+
+    pub fn batches(&self) -> Vec<usize> {
+        let delivered = self.delivered.lock().unwrap();
+        delivered.iter().map(Vec::len).collect()
+    }
+
+    pub fn records(&self) -> Vec<EventRecord> {
+        self.delivered.lock().unwrap().concat()
+    }
+}
+
+#[cfg(test)]
+impl Dispatcher {
+    // straight to these sinks, for the tests of what dispatches the records
+    pub fn to(sinks: Vec<(String, Arc<dyn EventSink>)>) -> Self {
+        Dispatcher {
+            sinks,
+            formats: HashMap::new(),
+            suppressor: None,
+            dry_run: false,
+        }
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl EventSink for TestSink {
+    async fn deliver(&self, record: &EventRecord) -> Result<(), SinkError> {
+        self.deliver_batch(std::slice::from_ref(record)).await
+    }
+
+    async fn deliver_batch(&self, records: &[EventRecord]) -> Result<(), SinkError> {
+        use std::sync::atomic::Ordering;
+        self.delivered.lock().unwrap().push(records.to_vec());
+        let failing = self.failing.load(Ordering::SeqCst);
+        if failing > 0 {
+            self.failing.store(failing - 1, Ordering::SeqCst);
+            return Err("failing".into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use k8s_openapi::chrono::{TimeDelta, Utc};
+    use std::time::Duration;
+
+    fn record(object: &str, reason: &str) -> EventRecord {
+        EventRecord {
+            namespace: "shop".to_string(),
+            kind: "Pod".to_string(),
+            object_name: object.to_string(),
+            reason: reason.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn suppresses_the_repeats_within_the_cooldown() {
+        let start = Utc::now();
+        let manual = Arc::new(ManualClock::new(start));
+        clock::set_local(manual.clone());
+        let suppressor = Suppressor::new(&SuppressionSettings {
+            cooldown: Some(Duration::from_secs(60)),
+            every: 3,
+        });
+        let backoff = record("web-1", "BackOff");
+        // the first, then every third repeat
+        let allowed = (0..7)
+            .map(|_| suppressor.allows(&backoff))
+            .collect::<Vec<_>>();
+        assert_eq!(allowed, [true, false, true, false, false, true, false]);
+        assert!(suppressor.allows(&record("web-1", "Unhealthy")));
+        assert!(suppressor.allows(&record("web-2", "BackOff")));
+
+        manual.advance_to(start + TimeDelta::seconds(61));
+        assert!(suppressor.allows(&backoff));
+        assert!(!suppressor.allows(&backoff));
+
+        // without a cooldown, nothing is
+        let suppressor = Suppressor::new(&SuppressionSettings::default());
+        assert!(suppressor.allows(&backoff));
+        assert!(suppressor.allows(&backoff));
+    }
+
+    // the dispatchers of a route are made again for every record, its cooldowns aren't
+    #[test]
+    fn keeps_the_cooldowns_of_a_route() {
+        let settings = serde_yaml::from_str::<Vec<SinkSettings>>(
+            "[{name: log, type: log}, {name: audit, type: log}]",
+        )
+        .unwrap();
+        let suppression = SuppressionSettings {
+            cooldown: Some(Duration::from_secs(60)),
+            every: 0,
+        };
+        let sinks = SinkRegistry::from_settings(&settings, &suppression).unwrap();
+        let allows = |names: &[&str], record: &EventRecord| {
+            let names = names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>();
+            let dispatcher = sinks.dispatcher(&names).unwrap();
+            dispatcher.suppressor.unwrap().allows(record)
+        };
+        let backoff = record("web-1", "BackOff");
+        assert!(allows(&["log"], &backoff));
+        assert!(!allows(&["log"], &backoff));
+        // another route has its own
+        assert!(allows(&["audit"], &backoff));
+        assert!(allows(&["log"], &record("web-2", "BackOff")));
+    }
+
+    // a dispatcher in dry run logs what each sink would have sent
+    #[test]
+    fn knows_the_format_of_its_sinks() {
+        let settings = serde_yaml::from_str::<Vec<SinkSettings>>(
+            "[{name: log, type: log}, {name: events, type: log, format: cloudevents}]",
+        )
+        .unwrap();
+        let sinks = SinkRegistry::from_settings(&settings, &Default::default()).unwrap();
+        let dispatcher = sinks
+            .dispatcher(&["events".to_string(), "log".to_string()])
+            .unwrap()
+            .dry_run();
+        assert_eq!(dispatcher.formats["events"], SinkFormat::CloudEvents);
+        assert_eq!(dispatcher.formats["log"], SinkFormat::Json);
+    }
+}
@@ -0,0 +1,221 @@
+#[cfg(feature = "aws")]
+use super::aws::Signer;
+use super::SinkError;
+use crate::{clock, config::SinkAuth, error::ErrorKind, outbound};
+use reqwest::{header::AUTHORIZATION, Request, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+// the access token is taken again a while before it expires
+const REFRESH_BEFORE: Duration = Duration::from_secs(60);
+
+// how long the tokens without an expires_in are kept
+const DEFAULT_LIFETIME: Duration = Duration::from_secs(5 * 60);
+
+// the credentials of the requests of an http sink, added to each of them right before
+// it's sent
+pub enum Auth {
+    Bearer(String),
+    Oauth2(ClientCredentials),
+    #[cfg(feature = "aws")]
+    Sigv4 {
+        signer: Signer,
+        service: String,
+    },
+}
+
+pub struct ClientCredentials {
+    client: reqwest::Client,
+    token_url: String,
+    client_id: String,
+    client_secret: String,
+    scopes: Vec<String>,
+    audience: Option<String>,
+    token: CachedToken,
+}
+
+// an access token, kept until a while before it expires or it's rejected, like the
+// OAuth2 ones or the ones of GKE's metadata server
+#[derive(Default)]
+pub struct CachedToken(Mutex<Option<Token>>);
+
+struct Token {
+    value: String,
+    expires: Instant,
+}
+
+// what the token endpoints answer
+#[derive(Deserialize)]
+pub struct TokenAnswer {
+    pub access_token: String,
+    pub expires_in: Option<u64>,
+}
+
+impl CachedToken {
+    // the token kept, or a new one from fetch
+    pub async fn get<F>(&self, fetch: impl FnOnce() -> F) -> Result<String, SinkError>
+    where
+        F: Future<Output = Result<TokenAnswer, SinkError>>,
+    {
+        let mut cached = self.0.lock().await;
+        if let Some(ref token) = *cached {
+            if clock::instant() + REFRESH_BEFORE < token.expires {
+                return Ok(token.value.clone());
+            }
+        }
+        let answer = fetch().await?;
+        let lifetime = answer
+            .expires_in
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_LIFETIME);
+        *cached = Some(Token {
+            value: answer.access_token.clone(),
+            expires: clock::instant() + lifetime,
+        });
+        Ok(answer.access_token)
+    }
+
+    // after a 401, so the retry gets a new token in case this one was revoked
+    pub async fn forget(&self) {
+        *self.0.lock().await = None;
+    }
+}
+
+impl Auth {
+    pub fn new(settings: &SinkAuth, timeout: Duration) -> Result<Self, ErrorKind> {
+        Ok(match settings {
+            SinkAuth::Bearer { token } => Auth::Bearer(token.clone()),
+            SinkAuth::Oauth2 {
+                token_url,
+                client_id,
+                client_secret,
+                scopes,
+                audience,
+            } => Auth::Oauth2(ClientCredentials {
+                client: outbound::client().timeout(timeout).build()?,
+                token_url: token_url.clone(),
+                client_id: client_id.clone(),
+                client_secret: client_secret.clone(),
+                scopes: scopes.clone(),
+                audience: audience.clone(),
+                token: CachedToken::default(),
+            }),
+            #[cfg(feature = "aws")]
+            SinkAuth::Sigv4 { service, region } => Auth::Sigv4 {
+                signer: Signer::new(region, timeout)?,
+                service: service.clone(),
+            },
+            #[cfg(not(feature = "aws"))]
+            SinkAuth::Sigv4 { .. } => {
+                return Err("the sigv4 auth needs k8rs built with the aws feature".into())
+            }
+        })
+    }
+
+    // adds the credentials to a request
+    async fn authorize(&self, request: &mut Request) -> Result<(), SinkError> {
+        let token = match self {
+            Auth::Bearer(token) => token.clone(),
+            Auth::Oauth2(credentials) => credentials.token().await?,
+            #[cfg(feature = "aws")]
+            Auth::Sigv4 { signer, service } => return signer.sign(request, service).await,
+        };
+        request
+            .headers_mut()
+            .insert(AUTHORIZATION, format!("Bearer {}", token).parse()?);
+        Ok(())
+    }
+
+    // after a 401, so the retry gets a new OAuth2 token in case this one was revoked
+    async fn rejected(&self) {
+        if let Auth::Oauth2(credentials) = self {
+            credentials.token.forget().await;
+        }
+    }
+}
+
+// sends a request of an http sink with its credentials, if it has some
+pub async fn send(
+    client: &reqwest::Client,
+    auth: Option<&Auth>,
+    request: RequestBuilder,
+) -> Result<Response, SinkError> {
+    let mut request = request.build()?;
+    if let Some(auth) = auth {
+        auth.authorize(&mut request).await?;
+    }
+    let response = client.execute(request).await?;
+    if let Some(auth) = auth.filter(|_| response.status() == StatusCode::UNAUTHORIZED) {
+        auth.rejected().await;
+    }
+    Ok(response)
+}
+
+impl ClientCredentials {
+    async fn token(&self) -> Result<String, SinkError> {
+        self.token.get(|| self.fetch()).await
+    }
+
+    async fn fetch(&self) -> Result<TokenAnswer, SinkError> {
+        let mut form = vec![("grant_type", "client_credentials".to_string())];
+        if !self.scopes.is_empty() {
+            form.push(("scope", self.scopes.join(" ")));
+        }
+        if let Some(ref audience) = self.audience {
+            form.push(("audience", audience.clone()));
+        }
+        Ok(self
+            .client
+            .post(&self.token_url)
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .form(&form)
+            .send()
+            .await?
+            .error_for_status()
+            .map_err(|err| format!("could not get a token from {}: {}", self.token_url, err))?
+            .json()
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use k8s_openapi::chrono::{TimeDelta, Utc};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    // the token is taken again a minute before it expires, and after it was rejected
+    #[tokio::test]
+    async fn takes_the_token_again_before_it_expires() {
+        let start = Utc::now();
+        let manual = Arc::new(ManualClock::new(start));
+        clock::set_local(manual.clone());
+        let token = CachedToken::default();
+        let fetched = AtomicUsize::new(0);
+        let get = || {
+            token.get(|| async {
+                let count = fetched.fetch_add(1, Ordering::SeqCst) + 1;
+                Ok(TokenAnswer {
+                    access_token: format!("token-{}", count),
+                    expires_in: Some(3600),
+                })
+            })
+        };
+        assert_eq!(get().await.unwrap(), "token-1");
+        manual.advance_to(start + TimeDelta::minutes(58));
+        assert_eq!(get().await.unwrap(), "token-1");
+        manual.advance_to(start + TimeDelta::seconds(3541));
+        assert_eq!(get().await.unwrap(), "token-2");
+        token.forget().await;
+        assert_eq!(get().await.unwrap(), "token-3");
+        assert_eq!(fetched.load(Ordering::SeqCst), 3);
+    }
+}
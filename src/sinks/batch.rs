@@ -0,0 +1,140 @@
+use super::{EventSink, SinkError};
+use crate::{
+    config::BatchSettings,
+    metrics::{SINK_BATCHES_COUNTER, SINK_LABEL, SINK_RESULT_LABEL},
+    record::EventRecord,
+};
+use async_trait::async_trait;
+use axum_prometheus::metrics::counter;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use tracing::warn;
+
+// holds a sink's records back until there's a full batch of them, or until the
+// interval is over, and sends them all at once. A record counts as delivered as
+// soon as it's in a batch, how the batches went is counted on their own.
+pub struct BatchingSink {
+    name: String,
+    sink: Arc<dyn EventSink>,
+    max_size: usize,
+    batch: Mutex<Vec<EventRecord>>,
+    // the records of the batch and of those being sent
+    pending: AtomicUsize,
+}
+
+impl BatchingSink {
+    // the batches are also sent every max_interval, until the sink is dropped
+    pub fn new(name: &str, sink: Arc<dyn EventSink>, settings: &BatchSettings) -> Arc<Self> {
+        let batching = Arc::new(BatchingSink {
+            name: name.to_string(),
+            sink,
+            max_size: settings.max_size,
+            batch: Mutex::new(Vec::with_capacity(settings.max_size)),
+            pending: AtomicUsize::new(0),
+        });
+        let sink = Arc::downgrade(&batching);
+        let mut interval = tokio::time::interval(settings.max_interval);
+        tokio::spawn(async move {
+            // the first tick is right away
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let Some(sink) = sink.upgrade() else {
+                    return;
+                };
+                sink.send().await;
+            }
+        });
+        batching
+    }
+
+    async fn send(&self) {
+        let batch = std::mem::take(&mut *self.batch.lock().expect("batch lock poisoned"));
+        if batch.is_empty() {
+            return;
+        }
+        let result = match self.sink.deliver_batch(&batch).await {
+            Ok(()) => "success",
+            Err(err) => {
+                warn!(
+                    "Could not deliver a batch of {} events to sink {}: {}",
+                    batch.len(),
+                    self.name,
+                    err
+                );
+                "failure"
+            }
+        };
+        counter!(
+            SINK_BATCHES_COUNTER,
+            &[
+                (SINK_LABEL, self.name.clone()),
+                (SINK_RESULT_LABEL, result.to_string())
+            ]
+        )
+        .increment(1);
+        self.pending.fetch_sub(batch.len(), Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl EventSink for BatchingSink {
+    async fn deliver(&self, record: &EventRecord) -> Result<(), SinkError> {
+        let full = {
+            let mut batch = self.batch.lock().expect("batch lock poisoned");
+            batch.push(record.clone());
+            self.pending.fetch_add(1, Ordering::Relaxed);
+            batch.len() >= self.max_size
+        };
+        if full {
+            self.send().await;
+        }
+        Ok(())
+    }
+
+    // what's left in the batch goes out right away, like on shutdown
+    async fn flush(&self) -> Result<(), SinkError> {
+        self.send().await;
+        self.sink.flush().await
+    }
+
+    fn backlog(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sinks::TestSink;
+    use std::time::Duration;
+
+    #[tokio::test(start_paused = true)]
+    async fn sends_full_batches_and_the_rest_every_interval() {
+        let sink = TestSink::failing(0);
+        let settings = BatchSettings {
+            max_size: 3,
+            max_interval: Duration::from_secs(5),
+        };
+        let batching = BatchingSink::new("webhook", sink.clone(), &settings);
+        for _ in 0..4 {
+            batching.deliver(&EventRecord::default()).await.unwrap();
+        }
+        assert_eq!(sink.batches(), [3]);
+        assert_eq!(batching.backlog(), 1);
+        tokio::time::sleep(Duration::from_secs(6)).await;
+        assert_eq!(sink.batches(), [3, 1]);
+        assert_eq!(batching.backlog(), 0);
+
+        // the interval's task doesn't keep the sink around
+        let dropped = Arc::downgrade(&batching);
+        batching.deliver(&EventRecord::default()).await.unwrap();
+        drop(batching);
+        assert!(dropped.upgrade().is_none());
+        tokio::time::sleep(Duration::from_secs(6)).await;
+        assert_eq!(sink.batches(), [3, 1]);
+        assert_eq!(Arc::strong_count(&sink), 1);
+    }
+}
@@ -0,0 +1,188 @@
+use super::{EventSink, SinkError};
+use crate::{
+    clock,
+    config::BreakerSettings,
+    metrics::{SINK_BREAKER_GAUGE, SINK_LABEL},
+    record::EventRecord,
+};
+use async_trait::async_trait;
+use axum_prometheus::metrics::gauge;
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+// what a delivery fails with while the breaker is open, so the retries
+// don't wait for a sink that isn't even tried
+#[derive(Debug)]
+pub struct Open {
+    sink: String,
+    left: Duration,
+}
+
+impl fmt::Display for Open {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the circuit breaker of sink {} is open for another {:.0?}",
+            self.sink, self.left
+        )
+    }
+}
+
+impl std::error::Error for Open {}
+
+// the states on sink_circuit_breaker_state
+const CLOSED: f64 = 0.0;
+const OPEN: f64 = 1.0;
+const HALF_OPEN: f64 = 2.0;
+
+#[derive(Default)]
+struct State {
+    // the deliveries that failed in a row
+    failures: u32,
+    // when the sink can be tried again, while the breaker is open
+    until: Option<Instant>,
+    // a delivery is trying the sink again after the cooldown
+    probing: bool,
+}
+
+// stops trying a sink once enough deliveries to it failed in a row, so a dead
+// endpoint doesn't have every delivery wait for its timeout. After the cooldown a
+// single delivery tries it again: the breaker closes if it goes through, and opens
+// for another cooldown if it doesn't. The records it turns away fail right away,
+// on to the sink's dead letter destination if it has one.
+pub struct BreakingSink {
+    name: String,
+    sink: Arc<dyn EventSink>,
+    settings: BreakerSettings,
+    state: Mutex<State>,
+}
+
+impl BreakingSink {
+    pub fn new(name: &str, sink: Arc<dyn EventSink>, settings: &BreakerSettings) -> Self {
+        gauge!(SINK_BREAKER_GAUGE, &[(SINK_LABEL, name.to_string())]).set(CLOSED);
+        BreakingSink {
+            name: name.to_string(),
+            sink,
+            settings: settings.clone(),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn set(&self, state: f64) {
+        gauge!(SINK_BREAKER_GAUGE, &[(SINK_LABEL, self.name.clone())]).set(state);
+    }
+
+    // whether the sink can be tried, which it can't while the breaker is open
+    // or another delivery is the one trying it again
+    fn allow(&self) -> Result<(), Open> {
+        let mut state = self.state.lock().expect("breaker lock poisoned");
+        let Some(until) = state.until else {
+            return Ok(());
+        };
+        let now = clock::instant();
+        if now < until || state.probing {
+            return Err(Open {
+                sink: self.name.clone(),
+                left: until.saturating_duration_since(now),
+            });
+        }
+        state.probing = true;
+        self.set(HALF_OPEN);
+        Ok(())
+    }
+
+    fn record(&self, delivered: bool) {
+        let mut state = self.state.lock().expect("breaker lock poisoned");
+        state.probing = false;
+        if delivered {
+            if state.until.take().is_some() {
+                info!("Sink {} is back, closing its circuit breaker", self.name);
+                self.set(CLOSED);
+            }
+            state.failures = 0;
+            return;
+        }
+        state.failures += 1;
+        if state.until.is_some() || state.failures >= self.settings.failures {
+            if state.until.is_none() {
+                warn!(
+                    "{} deliveries to sink {} failed in a row, not trying it for {:?}",
+                    state.failures, self.name, self.settings.cooldown
+                );
+            }
+            state.until = Some(clock::instant() + self.settings.cooldown);
+            self.set(OPEN);
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for BreakingSink {
+    async fn deliver(&self, record: &EventRecord) -> Result<(), SinkError> {
+        self.allow()?;
+        let delivered = self.sink.deliver(record).await;
+        self.record(delivered.is_ok());
+        delivered
+    }
+
+    async fn deliver_batch(&self, records: &[EventRecord]) -> Result<(), SinkError> {
+        self.allow()?;
+        let delivered = self.sink.deliver_batch(records).await;
+        self.record(delivered.is_ok());
+        delivered
+    }
+
+    async fn flush(&self) -> Result<(), SinkError> {
+        self.sink.flush().await
+    }
+
+    fn backlog(&self) -> usize {
+        self.sink.backlog()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::ManualClock, sinks::TestSink};
+    use k8s_openapi::chrono::{TimeDelta, Utc};
+
+    #[tokio::test]
+    async fn opens_and_tries_the_sink_again_after_the_cooldown() {
+        let start = Utc::now();
+        let manual = Arc::new(ManualClock::new(start));
+        clock::set_local(manual.clone());
+        let sink = TestSink::failing(3);
+        let settings = BreakerSettings {
+            failures: 2,
+            cooldown: Duration::from_secs(30),
+        };
+        let breaker = BreakingSink::new("webhook", sink.clone(), &settings);
+        let record = EventRecord::default();
+        assert!(breaker.deliver(&record).await.is_err());
+        assert!(breaker.deliver(&record).await.is_err());
+        // open, the sink isn't tried
+        let err = breaker.deliver(&record).await.unwrap_err();
+        assert!(err.is::<Open>());
+        assert_eq!(sink.batches().len(), 2);
+
+        // half open, a single delivery tries it again and it still fails
+        manual.advance_to(start + TimeDelta::seconds(31));
+        assert!(breaker.deliver(&record).await.is_err());
+        assert_eq!(sink.batches().len(), 3);
+        assert!(breaker.deliver(&record).await.unwrap_err().is::<Open>());
+
+        manual.advance_to(start + TimeDelta::seconds(62));
+        breaker.allow().unwrap();
+        assert!(breaker.allow().is_err());
+        breaker.record(true);
+        // closed
+        breaker.deliver(&record).await.unwrap();
+        breaker.deliver(&record).await.unwrap();
+        assert_eq!(sink.batches().len(), 5);
+    }
+}
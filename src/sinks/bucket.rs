@@ -0,0 +1,77 @@
+use super::{
+    auth::{self, Auth},
+    encode, EventSink, SinkError,
+};
+use crate::{
+    config::SinkFormat, error::ErrorKind, messages::Template, outbound, record::EventRecord,
+};
+use async_trait::async_trait;
+use reqwest::header::CONTENT_TYPE;
+use std::time::Duration;
+
+// PUTs each record as an object of its own in an S3 compatible bucket, the key made from
+// the record's fields. The url is the bucket's, like https://incidents.s3.eu-west-1.amazonaws.com
+// or https://storage.googleapis.com/incidents, and the requests are signed with the
+// sink's auth: sigv4 (with the s3 service) for S3, MinIO and GCS's HMAC keys, or a
+// bearer token for GCS.
+pub struct BucketSink {
+    client: reqwest::Client,
+    url: String,
+    key: Template,
+    format: SinkFormat,
+    auth: Option<Auth>,
+}
+
+impl BucketSink {
+    pub fn new(
+        url: &str,
+        key: &str,
+        timeout: Duration,
+        format: SinkFormat,
+        auth: Option<Auth>,
+    ) -> Result<Self, ErrorKind> {
+        Ok(BucketSink {
+            client: outbound::client().timeout(timeout).build()?,
+            url: url.trim_end_matches('/').to_string(),
+            key: Template::parse(key)?,
+            format,
+            auth,
+        })
+    }
+}
+
+// the segments of a key escaped for the url, its slashes staying the "folders" they are
+fn escape(key: &str) -> String {
+    key.split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| {
+            segment
+                .bytes()
+                .map(|byte| match byte {
+                    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                        (byte as char).to_string()
+                    }
+                    _ => format!("%{:02X}", byte),
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[async_trait]
+impl EventSink for BucketSink {
+    async fn deliver(&self, record: &EventRecord) -> Result<(), SinkError> {
+        let key = escape(&self.key.render(record));
+        let body = serde_json::to_vec_pretty(&encode(self.format, record)?)?;
+        let request = self
+            .client
+            .put(format!("{}/{}", self.url, key))
+            .header(CONTENT_TYPE, "application/json")
+            .body(body);
+        auth::send(&self.client, self.auth.as_ref(), request)
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use crate::{record::EventRecord, schema};
+use serde::Serialize;
+
+pub const CONTENT_TYPE: &str = "application/cloudevents+json";
+pub const BATCH_CONTENT_TYPE: &str = "application/cloudevents-batch+json";
+
+// the same for every record, the consumers (like Knative triggers or Argo Events
+// sensors) filter on the extensions instead
+const TYPE: &str = "io.k8rs.event";
+
+// a record in a CloudEvents 1.0 envelope, in the structured json mode, the record
+// itself being the data
+#[derive(Serialize)]
+pub struct CloudEvent<'a> {
+    specversion: &'static str,
+    id: String,
+    source: String,
+    #[serde(rename = "type")]
+    type_: &'static str,
+    subject: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time: Option<&'a str>,
+    datacontenttype: &'static str,
+    dataschema: String,
+    // the extensions, lowercase letters only as the spec asks
+    reason: &'a str,
+    kind: &'a str,
+    // Normal or Warning
+    eventtype: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    namespace: &'a str,
+    data: &'a EventRecord,
+}
+
+pub fn envelope(record: &EventRecord) -> CloudEvent<'_> {
+    // an event is updated in place when it repeats, its count tells the repeats apart.
+    // The records we make up ourselves have no uid.
+    let id = match record.uid.is_empty() {
+        true => format!(
+            "{}/{}/{}/{}",
+            record.kind,
+            record.object_name,
+            record.reason,
+            record.last_timestamp.as_deref().unwrap_or_default()
+        ),
+        false => format!("{}/{}", record.uid, record.count),
+    };
+    let source = match record.namespace.is_empty() {
+        true => "/k8rs".to_string(),
+        false => format!("/k8rs/namespaces/{}", record.namespace),
+    };
+    CloudEvent {
+        specversion: "1.0",
+        id,
+        source,
+        type_: TYPE,
+        subject: format!("{}/{}", record.kind, record.object_name),
+        time: record.last_timestamp.as_deref(),
+        datacontenttype: "application/json",
+        dataschema: schema::id(),
+        reason: &record.reason,
+        kind: &record.kind,
+        eventtype: &record.type_,
+        namespace: &record.namespace,
+        data: record,
+    }
+}
@@ -0,0 +1,41 @@
+use super::{encode, EventSink, SinkError};
+use crate::{
+    config::SinkFormat,
+    metrics::{SINK_DRY_RUN_COUNTER, SINK_LABEL},
+    record::EventRecord,
+};
+use async_trait::async_trait;
+use axum_prometheus::metrics::counter;
+use std::fmt::Display;
+use tracing::info;
+
+// stands in for a sink in dry run, at the end of its retries, batches and breaker: the
+// records are encoded the way the sink would and logged instead of sent
+pub struct DryRunSink {
+    name: String,
+    format: SinkFormat,
+}
+
+impl DryRunSink {
+    pub fn new(name: &str, format: SinkFormat) -> Self {
+        DryRunSink {
+            name: name.to_string(),
+            format,
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for DryRunSink {
+    async fn deliver(&self, record: &EventRecord) -> Result<(), SinkError> {
+        not_sent(&self.name, encode(self.format, record)?);
+        Ok(())
+    }
+}
+
+// what a sink in dry run would have sent, Alertmanager and PagerDuty being sinks of the
+// alerts here
+pub fn not_sent(sink: &str, payload: impl Display) {
+    info!("[{}] dry run, not sent: {}", sink, payload);
+    counter!(SINK_DRY_RUN_COUNTER, &[(SINK_LABEL, sink.to_string())]).increment(1);
+}
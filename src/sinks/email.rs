@@ -0,0 +1,169 @@
+use super::{EventSink, SinkError};
+use crate::{
+    config::{Severity, SmtpTls},
+    error::ErrorKind,
+    record::EventRecord,
+    severity, tls,
+};
+use async_trait::async_trait;
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::{authentication::Credentials, client::Tls, extension::ClientId},
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use std::{collections::BTreeMap, time::Duration};
+
+// the objects listed in a digest, the others are only counted
+const MAX_LISTED: usize = 100;
+
+// how a mail gets to the relay and who it's for
+pub struct Mail {
+    pub host: String,
+    pub port: u16,
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    pub reasons: Vec<String>,
+}
+
+// emails a digest of the warning and critical events, their batches being the digests:
+// a pod crash looping for the whole interval is a line of the mail, with how many times
+// it happened. Only the reasons asked for are in it (every warning and critical one when
+// there are none), no mail is sent when there's nothing to tell.
+pub struct EmailSink {
+    mail: Mail,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+    timeout: Duration,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl EmailSink {
+    pub fn new(mail: Mail, timeout: Duration) -> Result<Self, ErrorKind> {
+        let mailbox = |address: &str| {
+            address
+                .parse::<Mailbox>()
+                .map_err(|err| ErrorKind::Config(format!("invalid address {:?}: {}", address, err)))
+        };
+        let from = mailbox(&mail.from)?;
+        let to = mail
+            .to
+            .iter()
+            .map(|to| mailbox(to))
+            .collect::<Result<Vec<_>, _>>()?;
+        // the tls of the relay is ours, from the backend built
+        let builder = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&mail.host);
+        let builder = match mail.tls {
+            SmtpTls::Tls => builder.tls(Tls::Wrapper(tls::smtp(&mail.host)?)),
+            SmtpTls::Starttls => builder.tls(Tls::Required(tls::smtp(&mail.host)?)),
+            SmtpTls::None => builder,
+        };
+        let mut builder = builder
+            .port(mail.port)
+            .timeout(Some(timeout))
+            .hello_name(ClientId::Domain(hostname()));
+        if let Some(ref username) = mail.username {
+            let password = mail.password.clone().unwrap_or_default();
+            builder = builder.credentials(Credentials::new(username.clone(), password));
+        }
+        Ok(EmailSink {
+            transport: builder.build(),
+            mail,
+            from,
+            to,
+            timeout,
+        })
+    }
+
+    // the subject and body of the digest of the records, none when none of them are in it
+    fn digest(&self, records: &[EventRecord]) -> Option<(String, String)> {
+        // how many times, and the last message, by object and reason
+        let mut objects = BTreeMap::<(&str, &str, &str, &str), (usize, &str)>::new();
+        let (mut events, mut critical) = (0, 0);
+        for record in records {
+            let severity = severity::of_record(record);
+            if severity < Severity::Warning
+                || !(self.mail.reasons.is_empty() || self.mail.reasons.contains(&record.reason))
+            {
+                continue;
+            }
+            events += 1;
+            if severity == Severity::Critical {
+                critical += 1;
+            }
+            let object = objects
+                .entry((
+                    &record.namespace,
+                    &record.kind,
+                    &record.object_name,
+                    &record.reason,
+                ))
+                .or_default();
+            object.0 += 1;
+            object.1 = &record.message;
+        }
+        if events == 0 {
+            return None;
+        }
+        let events = match critical {
+            0 => format!("{} warning event(s)", events),
+            critical => format!("{} event(s), {} critical,", events, critical),
+        };
+        let subject = format!("[k8rs] {} about {} object(s)", events, objects.len());
+        let mut body = format!("{} since the last digest:\r\n\r\n", events);
+        for ((namespace, kind, object, reason), (times, message)) in objects.iter().take(MAX_LISTED)
+        {
+            body.push_str(&format!(
+                "{}/{} {}: {} x{}\r\n    {}\r\n",
+                namespace,
+                kind,
+                object,
+                reason,
+                times,
+                message.replace(['\r', '\n'], " ")
+            ));
+        }
+        if objects.len() > MAX_LISTED {
+            body.push_str(&format!(
+                "\r\nand {} other object(s)\r\n",
+                objects.len() - MAX_LISTED
+            ));
+        }
+        Some((subject, body))
+    }
+
+    async fn send(&self, subject: &str, body: &str) -> Result<(), SinkError> {
+        let mut message = Message::builder()
+            .from(self.from.clone())
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN);
+        for to in self.to.iter() {
+            message = message.to(to.clone());
+        }
+        self.transport.send(message.body(body.to_string())?).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventSink for EmailSink {
+    async fn deliver(&self, record: &EventRecord) -> Result<(), SinkError> {
+        self.deliver_batch(std::slice::from_ref(record)).await
+    }
+
+    async fn deliver_batch(&self, records: &[EventRecord]) -> Result<(), SinkError> {
+        let Some((subject, body)) = self.digest(records) else {
+            return Ok(());
+        };
+        tokio::time::timeout(self.timeout, self.send(&subject, &body))
+            .await
+            .unwrap_or_else(|_| Err("timed out".into()))
+    }
+}
+
+// the name we greet the relay with
+fn hostname() -> String {
+    std::env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string())
+}
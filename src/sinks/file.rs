@@ -0,0 +1,65 @@
+use super::{encode, EventSink, SinkError};
+use crate::{config::SinkFormat, record::EventRecord};
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+};
+
+// appends each record as a line of json to a file
+pub struct FileSink {
+    path: PathBuf,
+    format: SinkFormat,
+    // opened on the first delivery, so a bad path shows up as failed deliveries
+    // instead of keeping the operator from starting
+    file: Mutex<Option<File>>,
+}
+
+impl FileSink {
+    pub fn new(path: &Path, format: SinkFormat) -> Self {
+        FileSink {
+            path: path.to_path_buf(),
+            format,
+            file: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for FileSink {
+    async fn deliver(&self, record: &EventRecord) -> Result<(), SinkError> {
+        self.deliver_batch(std::slice::from_ref(record)).await
+    }
+
+    // a single write for all of them
+    async fn deliver_batch(&self, records: &[EventRecord]) -> Result<(), SinkError> {
+        let mut lines = Vec::new();
+        for record in records {
+            serde_json::to_writer(&mut lines, &encode(self.format, record)?)?;
+            lines.push(b'\n');
+        }
+
+        let mut file = self.file.lock().await;
+        if file.is_none() {
+            let opened = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await?;
+            *file = Some(opened);
+        }
+        if let Some(ref mut file) = *file {
+            file.write_all(&lines).await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), SinkError> {
+        if let Some(ref mut file) = *self.file.lock().await {
+            file.sync_data().await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,27 @@
+use super::{encode, EventSink, SinkError};
+use crate::{config::SinkFormat, record::EventRecord};
+use async_trait::async_trait;
+use tracing::info;
+
+// writes each record as a json log line, mostly useful to try things out
+pub struct LogSink {
+    name: String,
+    format: SinkFormat,
+}
+
+impl LogSink {
+    pub fn new(name: &str, format: SinkFormat) -> Self {
+        LogSink {
+            name: name.to_string(),
+            format,
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for LogSink {
+    async fn deliver(&self, record: &EventRecord) -> Result<(), SinkError> {
+        info!("[{}] {}", self.name, encode(self.format, record)?);
+        Ok(())
+    }
+}
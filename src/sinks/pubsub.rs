@@ -0,0 +1,193 @@
+use super::{
+    auth::{self, Auth, CachedToken, TokenAnswer},
+    encode, EventSink, SinkError,
+};
+use crate::{config::SinkFormat, error::ErrorKind, outbound, record::EventRecord};
+use async_trait::async_trait;
+use k8s_openapi::ByteString;
+use reqwest::StatusCode;
+use std::{collections::BTreeMap, time::Duration};
+
+// the most a publish call takes, in messages and in bytes once they're in base64
+const MAX_BATCH: usize = 1000;
+const MAX_BATCH_BYTES: usize = 9 * 1024 * 1024;
+
+// where GKE's metadata server gives the tokens of the pod's service account, the
+// Google one its Kubernetes one is bound to with workload identity
+const TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+
+// publishes the records to a Pub/Sub topic, ordered by the object they're about: the
+// ordering key is the object's uid, so a subscription with message ordering gets the
+// events of each pod in the order they happened. The reason, kind, namespace, type and
+// severity are attributes too, for the subscriptions' filters. The sink's auth, when it
+// has one, is used instead of the token of workload identity.
+pub struct PubSubSink {
+    client: reqwest::Client,
+    url: String,
+    format: SinkFormat,
+    token: CachedToken,
+    auth: Option<Auth>,
+}
+
+impl PubSubSink {
+    pub fn new(
+        topic: &str,
+        endpoint: &str,
+        timeout: Duration,
+        format: SinkFormat,
+        auth: Option<Auth>,
+    ) -> Result<Self, ErrorKind> {
+        Ok(PubSubSink {
+            client: outbound::client().timeout(timeout).build()?,
+            url: format!("{}/v1/{}:publish", endpoint.trim_end_matches('/'), topic),
+            format,
+            token: CachedToken::default(),
+            auth,
+        })
+    }
+
+    async fn token(&self) -> Result<String, SinkError> {
+        self.token.get(|| self.fetch()).await
+    }
+
+    async fn fetch(&self) -> Result<TokenAnswer, SinkError> {
+        Ok(self
+            .client
+            .get(TOKEN_URL)
+            .header("Metadata-Flavor", "Google")
+            .send()
+            .await?
+            .error_for_status()
+            .map_err(|err| format!("could not get a token from the metadata server: {}", err))?
+            .json()
+            .await?)
+    }
+
+    async fn publish(&self, messages: &[serde_json::Value]) -> Result<(), SinkError> {
+        let request = self
+            .client
+            .post(&self.url)
+            .json(&serde_json::json!({ "messages": messages }));
+        let response = match self.auth {
+            Some(ref auth) => auth::send(&self.client, Some(auth), request).await?,
+            None => {
+                let response = request.bearer_auth(self.token().await?).send().await?;
+                if response.status() == StatusCode::UNAUTHORIZED {
+                    self.token.forget().await;
+                }
+                response
+            }
+        };
+        response.error_for_status()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventSink for PubSubSink {
+    async fn deliver(&self, record: &EventRecord) -> Result<(), SinkError> {
+        self.deliver_batch(std::slice::from_ref(record)).await
+    }
+
+    // in as few calls as the limits of the publish api allow, the messages of an
+    // ordering key staying in order within each
+    async fn deliver_batch(&self, records: &[EventRecord]) -> Result<(), SinkError> {
+        let mut batches: Vec<Vec<serde_json::Value>> = Vec::new();
+        let mut bytes = 0;
+        for record in records {
+            let (message, size) = message(self.format, record)?;
+            match batches.last_mut() {
+                Some(batch) if batch.len() < MAX_BATCH && bytes + size <= MAX_BATCH_BYTES => {
+                    bytes += size;
+                    batch.push(message);
+                }
+                _ => {
+                    bytes = size;
+                    batches.push(vec![message]);
+                }
+            }
+        }
+        for batch in batches.iter() {
+            self.publish(batch).await?;
+        }
+        Ok(())
+    }
+}
+
+// the message of a record, with the size its data takes in base64
+fn message(
+    format: SinkFormat,
+    record: &EventRecord,
+) -> Result<(serde_json::Value, usize), SinkError> {
+    let data = serde_json::to_vec(&encode(format, record)?)?;
+    let size = data.len().div_ceil(3) * 4;
+    let mut attributes = [
+        ("reason", &record.reason),
+        ("kind", &record.kind),
+        ("namespace", &record.namespace),
+        ("type", &record.type_),
+    ]
+    .into_iter()
+    .filter(|(_, value)| !value.is_empty())
+    .collect::<BTreeMap<_, _>>();
+    if let Some(ref severity) = record.severity {
+        attributes.insert("severity", severity);
+    }
+    let mut message = serde_json::json!({
+        "data": ByteString(data),
+        "attributes": attributes,
+    });
+    // the records we make up ourselves aren't about an object with a uid
+    if !record.object_uid.is_empty() {
+        message["orderingKey"] = record.object_uid.clone().into();
+    }
+    Ok((message, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde_json::json;
+
+    #[test]
+    fn makes_the_message_of_a_record() {
+        let record = EventRecord {
+            reason: "BackOff".to_string(),
+            kind: "Pod".to_string(),
+            namespace: "default".to_string(),
+            type_: "Warning".to_string(),
+            severity: Some("high".to_string()),
+            object_uid: "5d1c9a2e-7f3b-4b8e-a6c1-2e9f0d4b7a31".to_string(),
+            ..Default::default()
+        };
+        let (published, size) = message(SinkFormat::Json, &record).unwrap();
+        let data = serde_json::to_vec(&encode(SinkFormat::Json, &record).unwrap()).unwrap();
+        assert_eq!(published["data"], json!(STANDARD.encode(&data)));
+        assert_eq!(size, STANDARD.encode(&data).len());
+        assert_eq!(
+            published["attributes"],
+            json!({
+                "kind": "Pod",
+                "namespace": "default",
+                "reason": "BackOff",
+                "severity": "high",
+                "type": "Warning",
+            })
+        );
+        assert_eq!(
+            published["orderingKey"],
+            json!("5d1c9a2e-7f3b-4b8e-a6c1-2e9f0d4b7a31")
+        );
+
+        // no uid, no ordering key, and no empty attributes
+        let record = EventRecord {
+            reason: "Started".to_string(),
+            ..Default::default()
+        };
+        let (published, _) = message(SinkFormat::Json, &record).unwrap();
+        assert_eq!(published["attributes"], json!({"reason": "Started"}));
+        assert!(published.get("orderingKey").is_none());
+    }
+}
@@ -0,0 +1,160 @@
+use super::{breaker, EventSink, SinkError};
+use crate::{
+    config::RetrySettings,
+    metrics::{SINK_DEAD_LETTERS_COUNTER, SINK_LABEL},
+    record::EventRecord,
+};
+use async_trait::async_trait;
+use axum_prometheus::metrics::counter;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use tracing::{debug, warn};
+
+// tries a sink's deliveries again, waiting twice as long each time, and hands the
+// records that still couldn't make it to the sink's dead letter destination.
+// The worker delivering the record waits for all of it.
+pub struct RetryingSink {
+    name: String,
+    sink: Arc<dyn EventSink>,
+    retry: RetrySettings,
+    dead_letter: Option<Arc<dyn EventSink>>,
+    // the records of the deliveries still going on
+    delivering: AtomicUsize,
+}
+
+impl RetryingSink {
+    pub fn new(
+        name: &str,
+        sink: Arc<dyn EventSink>,
+        retry: &RetrySettings,
+        dead_letter: Option<Arc<dyn EventSink>>,
+    ) -> Self {
+        RetryingSink {
+            name: name.to_string(),
+            sink,
+            retry: retry.clone(),
+            dead_letter,
+            delivering: AtomicUsize::new(0),
+        }
+    }
+
+    // the delivery with all its attempts, then the dead letter
+    async fn retry(&self, records: &[EventRecord]) -> Result<(), SinkError> {
+        let mut backoff = self.retry.backoff;
+        let mut attempt = 1;
+        let err = loop {
+            let delivered = match records {
+                [record] => self.sink.deliver(record).await,
+                records => self.sink.deliver_batch(records).await,
+            };
+            match delivered {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt >= self.retry.attempts || err.is::<breaker::Open>() => {
+                    break err
+                }
+                Err(err) => {
+                    debug!(
+                        "Attempt {} to deliver {} event(s) to sink {} failed, retrying in {:?}: {}",
+                        attempt,
+                        records.len(),
+                        self.name,
+                        backoff,
+                        err
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(self.retry.max_backoff);
+                    attempt += 1;
+                }
+            }
+        };
+
+        if let Some(ref dead_letter) = self.dead_letter {
+            match dead_letter.deliver_batch(records).await {
+                Ok(()) => {
+                    counter!(
+                        SINK_DEAD_LETTERS_COUNTER,
+                        &[(SINK_LABEL, self.name.clone())]
+                    )
+                    .increment(records.len() as u64);
+                }
+                Err(dead) => warn!(
+                    "Could not dead-letter {} event(s) of sink {}: {}",
+                    records.len(),
+                    self.name,
+                    dead
+                ),
+            }
+        }
+        // still a failed delivery for the metrics
+        Err(err)
+    }
+}
+
+#[async_trait]
+impl EventSink for RetryingSink {
+    async fn deliver(&self, record: &EventRecord) -> Result<(), SinkError> {
+        self.deliver_batch(std::slice::from_ref(record)).await
+    }
+
+    async fn deliver_batch(&self, records: &[EventRecord]) -> Result<(), SinkError> {
+        self.delivering.fetch_add(records.len(), Ordering::Relaxed);
+        let delivered = self.retry(records).await;
+        self.delivering.fetch_sub(records.len(), Ordering::Relaxed);
+        delivered
+    }
+
+    async fn flush(&self) -> Result<(), SinkError> {
+        self.sink.flush().await?;
+        if let Some(ref dead_letter) = self.dead_letter {
+            dead_letter.flush().await?;
+        }
+        Ok(())
+    }
+
+    fn backlog(&self) -> usize {
+        self.delivering.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sinks::TestSink;
+    use std::time::Duration;
+
+    fn retrying(sink: Arc<TestSink>, dead_letter: Arc<TestSink>) -> RetryingSink {
+        let retry = RetrySettings {
+            attempts: 3,
+            backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(10),
+        };
+        RetryingSink::new("webhook", sink, &retry, Some(dead_letter))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dead_letters_what_still_fails() {
+        let sink = TestSink::failing(usize::MAX);
+        let dead_letter = TestSink::failing(0);
+        let records = vec![EventRecord::default(); 2];
+        let retrying = retrying(sink.clone(), dead_letter.clone());
+        assert!(retrying.deliver_batch(&records).await.is_err());
+        assert_eq!(sink.batches(), [2, 2, 2]);
+        assert_eq!(dead_letter.batches(), [2]);
+        assert_eq!(retrying.backlog(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tries_again_until_it_goes_through() {
+        let sink = TestSink::failing(2);
+        let dead_letter = TestSink::failing(0);
+        let retrying = retrying(sink.clone(), dead_letter.clone());
+        let started = tokio::time::Instant::now();
+        retrying.deliver(&EventRecord::default()).await.unwrap();
+        assert_eq!(sink.batches(), [1, 1, 1]);
+        assert!(dead_letter.batches().is_empty());
+        // 1s and then 2s
+        assert_eq!(started.elapsed().as_secs(), 3);
+    }
+}
@@ -0,0 +1,194 @@
+use super::{
+    auth::{self, Auth},
+    EventSink, SinkError,
+};
+use crate::{config::Severity, error::ErrorKind, outbound, record::EventRecord, severity};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+// the records in a card, a batch of more being sent as several messages. Teams takes
+// messages of up to 28KB.
+const MAX_PER_CARD: usize = 10;
+
+// POSTs the records to a Teams incoming webhook (or a Workflows one) as Adaptive Cards,
+// the warning and critical ones standing out. A batch of records is a card with a section each.
+pub struct TeamsSink {
+    client: reqwest::Client,
+    url: String,
+    auth: Option<Auth>,
+}
+
+impl TeamsSink {
+    pub fn new(url: &str, timeout: Duration, auth: Option<Auth>) -> Result<Self, ErrorKind> {
+        Ok(TeamsSink {
+            client: outbound::client().timeout(timeout).build()?,
+            url: url.to_string(),
+            auth,
+        })
+    }
+}
+
+#[async_trait]
+impl EventSink for TeamsSink {
+    async fn deliver(&self, record: &EventRecord) -> Result<(), SinkError> {
+        self.deliver_batch(std::slice::from_ref(record)).await
+    }
+
+    async fn deliver_batch(&self, records: &[EventRecord]) -> Result<(), SinkError> {
+        for records in records.chunks(MAX_PER_CARD) {
+            let request = self.client.post(&self.url).json(&message(records));
+            auth::send(&self.client, self.auth.as_ref(), request)
+                .await?
+                .error_for_status()?;
+        }
+        Ok(())
+    }
+}
+
+fn message(records: &[EventRecord]) -> Value {
+    let body = records
+        .iter()
+        .enumerate()
+        .map(|(index, record)| section(record, index > 0))
+        .collect::<Vec<_>>();
+    json!({
+        "type": "message",
+        "attachments": [{
+            "contentType": "application/vnd.microsoft.card.adaptive",
+            "content": {
+                "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+                "type": "AdaptiveCard",
+                "version": "1.4",
+                "msteams": { "width": "Full" },
+                "body": body,
+            },
+        }],
+    })
+}
+
+// a record's title, facts and message
+fn section(record: &EventRecord, separator: bool) -> Value {
+    let color = match severity::of_record(record) {
+        Severity::Critical => "Attention",
+        Severity::Warning => "Warning",
+        Severity::Info => "Default",
+    };
+    let mut facts = vec![
+        ("Namespace", record.namespace.clone()),
+        ("Object", format!("{}/{}", record.kind, record.object_name)),
+        ("Source", record.source.clone()),
+        ("Count", record.count.to_string()),
+    ];
+    for (title, value) in [
+        ("Node", &record.node),
+        ("Owner", &record.owner),
+        ("Last seen", &record.last_timestamp),
+    ] {
+        if let Some(value) = value {
+            facts.push((title, value.clone()));
+        }
+    }
+    json!({
+        "type": "Container",
+        "separator": separator,
+        "items": [
+            {
+                "type": "TextBlock",
+                "text": format!("{} {}", record.type_, record.reason),
+                "weight": "Bolder",
+                "size": "Medium",
+                "color": color,
+            },
+            {
+                "type": "FactSet",
+                "facts": facts
+                    .into_iter()
+                    .filter(|(_, value)| !value.is_empty())
+                    .map(|(title, value)| json!({ "title": title, "value": value }))
+                    .collect::<Vec<_>>(),
+            },
+            {
+                "type": "TextBlock",
+                // the message of messages.templates when there's one
+                "text": record.summary.as_ref().unwrap_or(&record.message),
+                "wrap": true,
+            },
+        ],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn makes_a_card_with_a_section_per_record() {
+        let record = EventRecord {
+            namespace: "shop".to_string(),
+            kind: "Pod".to_string(),
+            object_name: "web-1".to_string(),
+            reason: "BackOff".to_string(),
+            type_: "Warning".to_string(),
+            severity: Some("critical".to_string()),
+            message: "Back-off restarting failed container".to_string(),
+            source: "kubelet".to_string(),
+            count: 4,
+            node: Some("node-1".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            message(std::slice::from_ref(&record)),
+            json!({
+                "type": "message",
+                "attachments": [{
+                    "contentType": "application/vnd.microsoft.card.adaptive",
+                    "content": {
+                        "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+                        "type": "AdaptiveCard",
+                        "version": "1.4",
+                        "msteams": { "width": "Full" },
+                        "body": [{
+                            "type": "Container",
+                            "separator": false,
+                            "items": [
+                                {
+                                    "type": "TextBlock",
+                                    "text": "Warning BackOff",
+                                    "weight": "Bolder",
+                                    "size": "Medium",
+                                    "color": "Attention",
+                                },
+                                {
+                                    "type": "FactSet",
+                                    "facts": [
+                                        { "title": "Namespace", "value": "shop" },
+                                        { "title": "Object", "value": "Pod/web-1" },
+                                        { "title": "Source", "value": "kubelet" },
+                                        { "title": "Count", "value": "4" },
+                                        { "title": "Node", "value": "node-1" },
+                                    ],
+                                },
+                                {
+                                    "type": "TextBlock",
+                                    "text": "Back-off restarting failed container",
+                                    "wrap": true,
+                                },
+                            ],
+                        }],
+                    },
+                }],
+            })
+        );
+
+        // the summary of the template instead of the message, and a line between them
+        let summarized = EventRecord {
+            summary: Some("web-1 keeps crashing".to_string()),
+            ..record.clone()
+        };
+        let card = message(&[record, summarized]);
+        let body = &card["attachments"][0]["content"]["body"];
+        assert_eq!(body[1]["separator"], json!(true));
+        assert_eq!(body[1]["items"][2]["text"], json!("web-1 keeps crashing"));
+    }
+}
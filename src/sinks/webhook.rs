@@ -0,0 +1,77 @@
+use super::{
+    auth::{self, Auth},
+    cloudevents, encode, EventSink, SinkError,
+};
+use crate::{config::SinkFormat, error::ErrorKind, outbound, record::EventRecord};
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use std::{collections::BTreeMap, time::Duration};
+
+// POSTs each record as json to an http endpoint, or a json array of them when batched.
+// In the cloudevents format they're sent with the content types of the structured and
+// batched modes of the CloudEvents http binding.
+pub struct WebhookSink {
+    client: reqwest::Client,
+    url: String,
+    format: SinkFormat,
+    auth: Option<Auth>,
+}
+
+impl WebhookSink {
+    pub fn new(
+        url: &str,
+        headers: &BTreeMap<String, String>,
+        timeout: Duration,
+        format: SinkFormat,
+        auth: Option<Auth>,
+    ) -> Result<Self, ErrorKind> {
+        let mut default_headers = HeaderMap::new();
+        for (name, value) in headers {
+            default_headers.insert(
+                HeaderName::from_bytes(name.as_bytes()).map_err(ErrorKind::other)?,
+                HeaderValue::from_str(value).map_err(ErrorKind::other)?,
+            );
+        }
+
+        let client = outbound::client()
+            .default_headers(default_headers)
+            .timeout(timeout)
+            .build()?;
+        Ok(WebhookSink {
+            client,
+            url: url.to_string(),
+            format,
+            auth,
+        })
+    }
+}
+
+#[async_trait]
+impl EventSink for WebhookSink {
+    async fn deliver(&self, record: &EventRecord) -> Result<(), SinkError> {
+        let mut request = self.client.post(&self.url);
+        if self.format == SinkFormat::CloudEvents {
+            request = request.header(CONTENT_TYPE, cloudevents::CONTENT_TYPE);
+        }
+        let request = request.json(&encode(self.format, record)?);
+        auth::send(&self.client, self.auth.as_ref(), request)
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn deliver_batch(&self, records: &[EventRecord]) -> Result<(), SinkError> {
+        let mut request = self.client.post(&self.url);
+        if self.format == SinkFormat::CloudEvents {
+            request = request.header(CONTENT_TYPE, cloudevents::BATCH_CONTENT_TYPE);
+        }
+        let records = records
+            .iter()
+            .map(|record| encode(self.format, record))
+            .collect::<Result<Vec<_>, _>>()?;
+        auth::send(&self.client, self.auth.as_ref(), request.json(&records))
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
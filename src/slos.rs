@@ -0,0 +1,138 @@
+use crate::{
+    clock,
+    config::{SloObjective, SloSettings},
+    metrics::{
+        SLO_BUDGET_REMAINING_GAUGE, SLO_BURN_RATE_GAUGE, SLO_EVENTS_GAUGE, SLO_LABEL, WINDOW_LABEL,
+    },
+    record::EventRecord,
+};
+use axum_prometheus::metrics::gauge;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// the objectives of the config, fed with every record of the pod pipeline. Each one
+// has a budget of events it can take within its period, the gauges tell how much of it
+// is left and how fast it's being burnt, so alerting on it is a plain threshold.
+pub struct Slos {
+    objectives: Vec<Objective>,
+    interval: Duration,
+    windows: Vec<Duration>,
+}
+
+struct Objective {
+    settings: SloObjective,
+    // how many events came in each interval, the oldest first.
+    // Only what's within the period (or the longest window) is kept.
+    seen: Mutex<VecDeque<(Instant, u64)>>,
+}
+
+impl Slos {
+    pub fn new(settings: &SloSettings) -> Self {
+        Slos {
+            objectives: settings
+                .objectives
+                .iter()
+                .map(|objective| Objective {
+                    settings: objective.clone(),
+                    seen: Mutex::new(VecDeque::new()),
+                })
+                .collect(),
+            interval: settings.interval,
+            windows: settings.burn_rate_windows.clone(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objectives.is_empty()
+    }
+
+    // counts a record against every objective it matches
+    pub fn observe(&self, record: &EventRecord) {
+        let now = clock::instant();
+        for objective in self.objectives.iter() {
+            if !objective.settings.matches(record) {
+                continue;
+            }
+            let mut seen = objective.seen.lock().expect("slo lock poisoned");
+            match seen.back_mut() {
+                Some((since, count)) if now.duration_since(*since) < self.interval => *count += 1,
+                _ => seen.push_back((now, 1)),
+            }
+        }
+    }
+
+    // computes the gauges again every interval, as the windows move on
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(self.interval);
+        loop {
+            interval.tick().await;
+            let now = clock::instant();
+            for objective in self.objectives.iter() {
+                self.export(objective, now);
+            }
+        }
+    }
+
+    fn export(&self, objective: &Objective, now: Instant) {
+        let settings = &objective.settings;
+        let longest = self
+            .windows
+            .iter()
+            .copied()
+            .fold(settings.period, Duration::max);
+        let mut seen = objective.seen.lock().expect("slo lock poisoned");
+        while seen
+            .front()
+            .is_some_and(|(since, _)| now.duration_since(*since) > longest)
+        {
+            seen.pop_front();
+        }
+        let within = |window: Duration| {
+            seen.iter()
+                .filter(|(since, _)| now.duration_since(*since) <= window)
+                .map(|(_, count)| count)
+                .sum::<u64>()
+        };
+
+        let label = [(SLO_LABEL, settings.name.clone())];
+        let events = within(settings.period);
+        gauge!(SLO_EVENTS_GAUGE, &label).set(events as f64);
+        // below 0 once the budget is blown
+        let budget = settings.budget as f64;
+        gauge!(SLO_BUDGET_REMAINING_GAUGE, &label).set(1.0 - events as f64 / budget);
+        // 1 spends exactly the budget over the period, more runs out before its end
+        for window in self.windows.iter() {
+            let allowed = budget * window.as_secs_f64() / settings.period.as_secs_f64();
+            gauge!(
+                SLO_BURN_RATE_GAUGE,
+                &[
+                    (SLO_LABEL, settings.name.clone()),
+                    (
+                        WINDOW_LABEL,
+                        humantime::format_duration(*window).to_string()
+                    ),
+                ]
+            )
+            .set(within(*window) as f64 / allowed);
+        }
+    }
+}
+
+impl SloObjective {
+    fn matches(&self, record: &EventRecord) -> bool {
+        let allowed =
+            |allowed: &[String], value: &String| allowed.is_empty() || allowed.contains(value);
+        let workload = record
+            .owner
+            .clone()
+            .unwrap_or_else(|| format!("{}/{}", record.kind, record.object_name));
+        allowed(&self.kinds, &record.kind)
+            && allowed(&self.reasons, &record.reason)
+            && allowed(&self.types, &record.type_)
+            && allowed(&self.namespaces, &record.namespace)
+            && allowed(&self.workloads, &workload)
+    }
+}
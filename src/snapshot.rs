@@ -0,0 +1,233 @@
+use crate::{
+    config::SnapshotSettings,
+    memory::{Lru, SNAPSHOT_STORE},
+    record::EventRecord,
+};
+use k8s_openapi::{api::core::v1::Event, chrono::Utc};
+use kube::{runtime::watcher, Resource};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        LazyLock, Mutex, MutexGuard,
+    },
+    time::Instant,
+};
+use tokio::sync::mpsc::WeakSender;
+
+// what the operator has been up to, for /api/v1/snapshot.
+// It's filled in from all over the place, so like the registry it's a global.
+pub static SNAPSHOT: LazyLock<Snapshot> = LazyLock::new(Snapshot::default);
+
+#[derive(Default)]
+pub struct Snapshot {
+    enabled: AtomicBool,
+    // the watchers' and sinks' health are also wanted by /admin/state
+    tracking: AtomicBool,
+    // how many of the latest events are kept for each namespace
+    recent_events: AtomicUsize,
+    state: Mutex<State>,
+}
+
+struct State {
+    started: Option<(Instant, String)>,
+    reasons: BTreeMap<String, u64>,
+    // by namespace, as many of them as memory.recent_events has room for
+    recent: Lru<String, VecDeque<EventRecord>>,
+    watchers: BTreeMap<String, WatcherHealth>,
+    sinks: BTreeMap<String, SinkHealth>,
+    // a weak one, so we don't keep the pipeline open
+    queue: Option<WeakSender<Event>>,
+    urgent_queue: Option<WeakSender<Event>>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            started: None,
+            reasons: BTreeMap::new(),
+            recent: Lru::new(SNAPSHOT_STORE, |limits| {
+                limits.recent_events / SNAPSHOT.recent_events.load(Ordering::Relaxed).max(1)
+            }),
+            watchers: BTreeMap::new(),
+            sinks: BTreeMap::new(),
+            queue: None,
+            urgent_queue: None,
+        }
+    }
+}
+
+#[derive(Serialize, Default)]
+struct WatcherHealth {
+    // done with the initial list and watching
+    ready: bool,
+    last_event_at: Option<String>,
+    // of the latest object seen, where the stream is at
+    resource_version: Option<String>,
+    last_error: Option<String>,
+    last_error_at: Option<String>,
+    backoffs: u64,
+}
+
+#[derive(Serialize, Default)]
+struct SinkHealth {
+    delivered: u64,
+    failed: u64,
+    last_error: Option<String>,
+    last_error_at: Option<String>,
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339()
+}
+
+impl Snapshot {
+    pub fn configure(&self, settings: &SnapshotSettings, admin: bool) {
+        self.enabled.store(settings.enabled, Ordering::Relaxed);
+        self.tracking
+            .store(settings.enabled || admin, Ordering::Relaxed);
+        self.recent_events
+            .store(settings.recent_events, Ordering::Relaxed);
+        self.lock().started = Some((Instant::now(), now()));
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    fn tracking(&self) -> bool {
+        self.tracking.load(Ordering::Relaxed)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("snapshot lock poisoned")
+    }
+
+    pub fn track_queue(&self, queue: WeakSender<Event>, urgent: Option<WeakSender<Event>>) {
+        let mut state = self.lock();
+        state.queue = Some(queue);
+        state.urgent_queue = urgent;
+    }
+
+    // an event the pod pipeline processed
+    pub fn event(&self, record: &EventRecord) {
+        if !self.enabled() {
+            return;
+        }
+        let keep = self.recent_events.load(Ordering::Relaxed);
+        let mut state = self.lock();
+        *state.reasons.entry(record.reason.clone()).or_default() += 1;
+        if keep == 0 {
+            return;
+        }
+        let recent = state
+            .recent
+            .get_or_insert_with(record.namespace.clone(), VecDeque::new);
+        recent.push_front(record.clone());
+        recent.truncate(keep);
+    }
+
+    // keeps track of how a watch stream is doing, meant for `StreamExt::inspect`
+    pub fn watcher<K: Resource>(
+        &self,
+        name: &str,
+        event: &Result<watcher::Event<K>, watcher::Error>,
+    ) {
+        if !self.tracking() {
+            return;
+        }
+        let mut state = self.lock();
+        let health = state.watchers.entry(name.to_string()).or_default();
+        match event {
+            Ok(watcher::Event::Init) => health.ready = false,
+            Ok(watcher::Event::InitDone) => health.ready = true,
+            Ok(
+                watcher::Event::InitApply(object)
+                | watcher::Event::Apply(object)
+                | watcher::Event::Delete(object),
+            ) => {
+                health.last_event_at = Some(now());
+                health.resource_version = object.meta().resource_version.clone();
+            }
+            Err(err) => {
+                health.last_error = Some(err.to_string());
+                health.last_error_at = Some(now());
+            }
+        }
+    }
+
+    pub fn watcher_backoff(&self, name: &str) {
+        if self.tracking() {
+            self.lock()
+                .watchers
+                .entry(name.to_string())
+                .or_default()
+                .backoffs += 1;
+        }
+    }
+
+    // forgets a watcher that's gone for good, like the one of a deleted EventMonitor
+    pub fn remove_watcher(&self, name: &str) {
+        self.lock().watchers.remove(name);
+    }
+
+    pub fn delivery(&self, sink: &str, result: Result<(), String>) {
+        if !self.tracking() {
+            return;
+        }
+        let mut state = self.lock();
+        let health = state.sinks.entry(sink.to_string()).or_default();
+        match result {
+            Ok(()) => health.delivered += 1,
+            Err(err) => {
+                health.failed += 1;
+                health.last_error = Some(err);
+                health.last_error_at = Some(now());
+            }
+        }
+    }
+
+    pub fn watchers(&self) -> Value {
+        json!(self.lock().watchers)
+    }
+
+    pub fn sinks(&self) -> Value {
+        json!(self.lock().sinks)
+    }
+
+    pub fn render(&self) -> Value {
+        let state = self.lock();
+        let (uptime, started_at) = match state.started {
+            Some((ref started, ref at)) => (started.elapsed().as_secs(), Some(at.clone())),
+            None => (0, None),
+        };
+        // the sinks deliver in line, so whatever hasn't been delivered yet waits here
+        let depth = |queue: &Option<WeakSender<Event>>| {
+            queue
+                .as_ref()
+                .and_then(|queue| queue.upgrade())
+                .map(|queue| {
+                    json!({
+                        "depth": queue.max_capacity() - queue.capacity(),
+                        "capacity": queue.max_capacity(),
+                    })
+                })
+        };
+        let mut queue = depth(&state.queue);
+        if let (Some(queue), Some(urgent)) = (queue.as_mut(), depth(&state.urgent_queue)) {
+            queue["urgent"] = urgent;
+        }
+
+        json!({
+            "started_at": started_at,
+            "uptime_seconds": uptime,
+            "events_by_reason": state.reasons,
+            "recent_events": state.recent.iter().collect::<BTreeMap<_, _>>(),
+            "watchers": state.watchers,
+            "pipeline_queue": queue,
+            "sinks": state.sinks,
+        })
+    }
+}
@@ -0,0 +1,176 @@
+use crate::{
+    cache::PodStore,
+    config::SpotSettings,
+    metrics::{NODE_LABEL, SPOT_INTERRUPTIONS_COUNTER},
+    record::EventRecord,
+    sinks::SinkRegistry,
+};
+use axum_prometheus::metrics::counter;
+use k8s_openapi::{
+    api::core::v1::{Event, Node},
+    chrono::Utc,
+};
+use kube::ResourceExt;
+use std::{collections::HashMap, future::Future, sync::Mutex};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+// what told a node is about to go away
+#[derive(Clone)]
+enum Signal {
+    Taint(String),
+    Event(String),
+}
+
+impl std::fmt::Display for Signal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Signal::Taint(key) => write!(f, "taint {}", key),
+            Signal::Event(reason) => write!(f, "event {}", reason),
+        }
+    }
+}
+
+struct Interruption {
+    node: String,
+    signal: Signal,
+}
+
+// tells when a spot (or preemptible) node is about to be taken back, from the taints
+// put on it or the events about it, whichever comes first. The node cache and the
+// pipeline both tell it, the sinks are told from a task of its own.
+pub struct Interruptions {
+    settings: SpotSettings,
+    // the nodes being interrupted, counted once until they're gone (or untainted)
+    interrupted: Mutex<HashMap<String, Signal>>,
+    sender: mpsc::UnboundedSender<Interruption>,
+}
+
+// creates the detector and the future telling the sinks about the interruptions
+pub fn interruptions(
+    settings: &SpotSettings,
+    pods: PodStore,
+    sinks: SinkRegistry,
+) -> (Interruptions, impl Future<Output = ()> + Send + 'static) {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Interruption>();
+    let interruptions = Interruptions {
+        settings: settings.clone(),
+        interrupted: Mutex::new(HashMap::new()),
+        sender,
+    };
+    let names = settings.sinks.clone();
+    let notify = async move {
+        while let Some(interruption) = receiver.recv().await {
+            notify(&interruption, &pods, &sinks, &names).await;
+        }
+    };
+    (interruptions, notify)
+}
+
+impl Interruptions {
+    // called with every node of the node cache
+    pub fn node(&self, node: &Node) {
+        let name = node.name_any();
+        let taint = node
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.taints.as_ref())
+            .into_iter()
+            .flatten()
+            .find(|taint| self.settings.taints.contains(&taint.key));
+        match taint {
+            Some(taint) => self.interrupted(name, Signal::Taint(taint.key.clone())),
+            // the taint was taken back, the node wasn't interrupted after all
+            None => {
+                let mut interrupted = self.lock();
+                if matches!(interrupted.get(&name), Some(Signal::Taint(_))) {
+                    interrupted.remove(&name);
+                }
+            }
+        }
+    }
+
+    pub fn gone(&self, name: &str) {
+        self.lock().remove(name);
+    }
+
+    // called with every Node event of the pipeline
+    pub fn event(&self, event: &Event) {
+        let reason = event.reason.clone().unwrap_or_default();
+        if !self.settings.reasons.contains(&reason) {
+            return;
+        }
+        let name = event.involved_object.name.clone().unwrap_or_default();
+        self.interrupted(name, Signal::Event(reason));
+    }
+
+    fn interrupted(&self, node: String, signal: Signal) {
+        {
+            let mut interrupted = self.lock();
+            if interrupted.contains_key(&node) {
+                return;
+            }
+            interrupted.insert(node.clone(), signal.clone());
+        }
+        counter!(SPOT_INTERRUPTIONS_COUNTER, &[(NODE_LABEL, node.clone())]).increment(1);
+        let _ = self.sender.send(Interruption { node, signal });
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Signal>> {
+        self.interrupted
+            .lock()
+            .expect("spot interruptions lock poisoned")
+    }
+}
+
+// logs the pods about to go along with the node, and sends them to the sinks
+async fn notify(
+    interruption: &Interruption,
+    pods: &PodStore,
+    sinks: &SinkRegistry,
+    names: &[String],
+) {
+    // there's no index of the pods by node, the cache is gone through once per node
+    let mut on_node = pods
+        .state()
+        .into_iter()
+        .filter(|pod| {
+            pod.spec.as_ref().and_then(|spec| spec.node_name.as_deref())
+                == Some(interruption.node.as_str())
+        })
+        .map(|pod| format!("{}/{}", pod.namespace().unwrap_or_default(), pod.name_any()))
+        .collect::<Vec<_>>();
+    on_node.sort();
+    let message = format!(
+        "Node {} is being interrupted ({}), along with its {} pods: {}",
+        interruption.node,
+        interruption.signal,
+        on_node.len(),
+        on_node.join(", ")
+    );
+    warn!("{}", message);
+    if names.is_empty() {
+        return;
+    }
+    let record = EventRecord {
+        name: interruption.node.clone(),
+        kind: "Node".to_string(),
+        object_name: interruption.node.clone(),
+        reason: "SpotInterruption".to_string(),
+        message,
+        type_: "Warning".to_string(),
+        source: "k8rs".to_string(),
+        last_timestamp: Some(Utc::now().to_rfc3339()),
+        count: 1,
+        node: Some(interruption.node.clone()),
+        ..EventRecord::default()
+    };
+    // taken from the registry every time, so reloaded sinks are picked up
+    match sinks.dispatcher(names) {
+        Ok(dispatcher) => dispatcher.dispatch(&record).await,
+        Err(err) => warn!(
+            "Could not deliver the interruption of node {}: {}",
+            interruption.node, err
+        ),
+    }
+}
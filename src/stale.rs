@@ -0,0 +1,125 @@
+use crate::{
+    clock,
+    config::StaleSeriesSettings,
+    metrics::{POD_ID_LABEL, REASON_LABEL, STALE_SERIES_COUNTER},
+    registry::{Family, CREATED_PODS, DELETED_PODS, EXEMPLARS, LAST_EVENT},
+};
+use axum_prometheus::metrics::counter;
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::debug;
+
+// how often the stale series are looked for
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+// the families with a series per pod
+static PER_POD: [&LazyLock<Family>; 3] = [&CREATED_PODS, &DELETED_PODS, &LAST_EVENT];
+
+// forgets the series of the pods some time after they're gone, so /metrics doesn't keep
+// growing in the clusters where pods come and go all day. The pod cache tells when they
+// are, so like the snapshot it's a global.
+pub static STALE_SERIES: LazyLock<StaleSeries> = LazyLock::new(StaleSeries::default);
+
+#[derive(Default)]
+pub struct StaleSeries {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    settings: StaleSeriesSettings,
+    // the uids of the pods gone, along with when
+    deleted: HashMap<String, Instant>,
+}
+
+impl StaleSeries {
+    pub fn configure(&self, settings: &StaleSeriesSettings) {
+        let mut state = self.lock();
+        state.settings = settings.clone();
+        if settings.after_delete.is_none() {
+            state.deleted.clear();
+        }
+    }
+
+    pub fn pod_deleted(&self, uid: &str) {
+        let mut state = self.lock();
+        if state.settings.after_delete.is_some() {
+            state
+                .deleted
+                .entry(uid.to_string())
+                .or_insert(clock::instant());
+        }
+    }
+
+    pub async fn run(&self) {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.sweep();
+        }
+    }
+
+    fn sweep(&self) {
+        let mut due = Vec::new();
+        let ttl = {
+            let mut state = self.lock();
+            if let Some(after_delete) = state.settings.after_delete {
+                state.deleted.retain(|uid, at| {
+                    if clock::instant().duration_since(*at) < after_delete {
+                        return true;
+                    }
+                    due.push(uid.clone());
+                    false
+                });
+            }
+            state.settings.ttl
+        };
+        let mut removed = 0;
+        for uid in due.iter() {
+            for family in PER_POD {
+                removed += family.remove(POD_ID_LABEL, uid);
+            }
+            EXEMPLARS.remove(POD_ID_LABEL, uid);
+        }
+        if removed > 0 {
+            debug!(
+                "Removed the {} series of {} deleted pods",
+                removed,
+                due.len()
+            );
+            count(removed, "deleted");
+        }
+        self.remove_idle(ttl);
+    }
+
+    // the series of the pods the pod cache doesn't follow are only forgotten this way
+    fn remove_idle(&self, ttl: Option<Duration>) {
+        let Some(ttl) = ttl else {
+            return;
+        };
+        let mut removed = 0;
+        for family in PER_POD {
+            for labels in family.remove_idle(ttl) {
+                removed += 1;
+                if let Some((_, uid)) = labels.iter().find(|(name, _)| name == POD_ID_LABEL) {
+                    EXEMPLARS.remove(POD_ID_LABEL, uid);
+                }
+            }
+        }
+        if removed > 0 {
+            debug!("Removed {} series not updated for {:?}", removed, ttl);
+            count(removed, "idle");
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("stale series lock poisoned")
+    }
+}
+
+fn count(removed: usize, reason: &str) {
+    counter!(STALE_SERIES_COUNTER, &[(REASON_LABEL, reason.to_string())]).increment(removed as u64);
+}
@@ -0,0 +1,133 @@
+use crate::{
+    cache::NamespaceStore,
+    config::{TenantRoute, TenantSettings},
+    metrics::{naming, NAMESPACE_LABEL, TENANT_LABEL, TENANT_ROUTED_COUNTER},
+    record::EventRecord,
+    sinks::Routing,
+};
+use axum_prometheus::metrics::counter;
+use k8s_openapi::api::core::v1::Namespace;
+use kube::{runtime::reflector::ObjectRef, ResourceExt};
+use std::collections::HashMap;
+
+// sends the records about each team's namespaces to that team's sinks,
+// so one team's failures don't end up in everyone's channels
+pub struct Tenants {
+    routes: Vec<TenantRoute>,
+    metrics: bool,
+    // only there when a route matches namespaces by their labels
+    namespaces: Option<NamespaceStore>,
+}
+
+impl Tenants {
+    pub fn new(settings: &TenantSettings, namespaces: Option<NamespaceStore>) -> Self {
+        Tenants {
+            routes: settings.routes.clone(),
+            metrics: settings.metrics,
+            namespaces,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    // the first tenant whose namespaces include the record's
+    fn tenant(&self, record: &EventRecord) -> Option<&TenantRoute> {
+        self.tenant_of(&record.namespace)
+    }
+
+    fn tenant_of(&self, name: &str) -> Option<&TenantRoute> {
+        if name.is_empty() {
+            return None;
+        }
+        let mut namespace = None;
+        self.routes.iter().find(|route| {
+            if route.namespaces.iter().any(|namespace| namespace == name) {
+                return true;
+            }
+            if route.namespace_labels.is_empty() {
+                return false;
+            }
+            // a namespace the cache doesn't know (yet) has no labels
+            let cached = namespace.get_or_insert_with(|| {
+                self.namespaces
+                    .as_ref()
+                    .and_then(|namespaces| namespaces.get(&ObjectRef::<Namespace>::new(name)))
+            });
+            cached.as_ref().is_some_and(|namespace| {
+                let labels = namespace.labels();
+                route
+                    .namespace_labels
+                    .iter()
+                    .all(|(key, value)| labels.get(key) == Some(value))
+            })
+        })
+    }
+
+    // the rendered metrics about the tenant's namespaces, those whose namespace label is
+    // one of them, or None when the tenant has no metrics. The series of the operator
+    // itself, without a namespace, aren't anyone's.
+    pub fn metrics(&self, tenant: &str, metrics: &str) -> Option<String> {
+        if !self.metrics || !self.routes.iter().any(|route| route.name == tenant) {
+            return None;
+        }
+        let label = format!("{}=\"", naming().label(NAMESPACE_LABEL));
+        let mut theirs = HashMap::new();
+        let mut filtered = String::new();
+        // the HELP and TYPE of a metric only go out along with one of its series
+        let mut comments = String::new();
+        for line in metrics.lines() {
+            if line.starts_with('#') {
+                if line.starts_with("# HELP") {
+                    comments.clear();
+                }
+                comments.push_str(line);
+                comments.push('\n');
+                continue;
+            }
+            let Some(namespace) = label_value(line, &label) else {
+                continue;
+            };
+            let ours = *theirs.entry(namespace).or_insert_with(|| {
+                self.tenant_of(namespace)
+                    .is_some_and(|route| route.name == tenant)
+            });
+            if ours {
+                filtered.push_str(&comments);
+                comments.clear();
+                filtered.push_str(line);
+                filtered.push('\n');
+            }
+        }
+        Some(filtered)
+    }
+
+    pub fn route(&self, record: &EventRecord, routing: &mut Routing) {
+        if let Some(tenant) = self.tenant(record) {
+            counter!(
+                TENANT_ROUTED_COUNTER,
+                &[(TENANT_LABEL, tenant.name.clone())]
+            )
+            .increment(1);
+            routing.sinks.extend(tenant.sinks.iter().cloned());
+        }
+    }
+}
+
+// the value of a label of a series line, like `default` for namespace=" in
+// `events_total{namespace="default",reason="BackOff"} 3`
+fn label_value<'a>(line: &'a str, label: &str) -> Option<&'a str> {
+    let (_, labels) = line.split_once('{')?;
+    let mut rest = labels;
+    loop {
+        let at = rest.find(label)?;
+        // not the end of another label's name, like exported_namespace
+        let before = labels.len() - rest.len() + at;
+        if before == 0 || matches!(labels.as_bytes()[before - 1], b',' | b' ') {
+            let value = &rest[at + label.len()..];
+            return value.split_once('"').map(|(value, _)| value);
+        }
+        rest = &rest[at + label.len()..];
+    }
+}
@@ -0,0 +1,177 @@
+use crate::error::{Context, ErrorKind};
+use axum::{
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::Uri,
+    response::{IntoResponse, Response},
+    Json, Router,
+};
+use futures::StreamExt;
+use kube::Client;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{collections::HashMap, convert::Infallible, path::Path, sync::Arc};
+use tokio::net::TcpListener;
+
+// what the api server answered for one collection, like a pod's events:
+// the initial list and then what the watch that followed handed out
+#[derive(Deserialize, Debug, Clone)]
+pub struct Recording {
+    // like /api/v1/namespaces/default/events
+    pub path: String,
+    #[serde(default = "default_resource_version")]
+    pub resource_version: String,
+    #[serde(default)]
+    pub items: Vec<Value>,
+    // the watch events, like {"type": "ADDED", "object": {...}}
+    #[serde(default)]
+    pub watch: Vec<Value>,
+}
+
+fn default_resource_version() -> String {
+    "1".to_string()
+}
+
+// an api server serving recorded list and watch responses, so the client, the watchers
+// and everything after them run like they would against a cluster. The collections
+// it has no recording of are empty, and every watch stays open once it's done.
+pub struct MockApiServer {
+    recordings: HashMap<String, Recording>,
+}
+
+impl MockApiServer {
+    pub fn new(recordings: Vec<Recording>) -> Self {
+        MockApiServer {
+            recordings: recordings
+                .into_iter()
+                .map(|recording| (recording.path.clone(), recording))
+                .collect(),
+        }
+    }
+
+    // a json file with a list of recordings
+    pub fn load(path: &Path) -> Result<Self, ErrorKind> {
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("could not read {:?}", path))?;
+        let recordings = serde_json::from_str::<Vec<Recording>>(&contents)
+            .with_context(|| format!("invalid recordings {:?}", path))?;
+        Ok(MockApiServer::new(recordings))
+    }
+
+    // serves the recordings on a local port until the runtime is gone,
+    // returning a client talking to them (in the default namespace)
+    pub async fn start(self) -> Result<Client, ErrorKind> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?)
+            .parse()
+            .map_err(ErrorKind::other)?;
+        let app = Router::new()
+            .fallback(respond)
+            .with_state(Arc::new(self.recordings));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Ok(Client::try_from(kube::Config::new(url))?)
+    }
+}
+
+async fn respond(
+    State(recordings): State<Arc<HashMap<String, Recording>>>,
+    uri: Uri,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let recording = recordings.get(uri.path());
+    if params.get("watch").is_some_and(|watch| watch == "true") {
+        let events = recording
+            .map(|recording| recording.watch.clone())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|event| Ok::<_, Infallible>(Bytes::from(format!("{}\n", event))));
+        // like a real watch, nothing more happens until it times out
+        let stream = futures::stream::iter(events).chain(futures::stream::pending());
+        return Body::from_stream(stream).into_response();
+    }
+    let (resource_version, items) = match recording {
+        Some(recording) => (recording.resource_version.clone(), recording.items.clone()),
+        None => (default_resource_version(), Vec::new()),
+    };
+    Json(json!({
+        "apiVersion": "v1",
+        "kind": "List",
+        "metadata": { "resourceVersion": resource_version },
+        "items": items,
+    }))
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MockApiServer;
+    use crate::{
+        config::Config,
+        discovery::Kinds,
+        enrich::Enricher,
+        logging::LogSampler,
+        metrics::test_recorder,
+        namespaces::NamespaceFilter,
+        pipeline::{channel, handle_event},
+        registry,
+        watch::{watch_events, KubeEvents},
+    };
+    use kube::runtime::reflector;
+    use std::{path::Path, sync::Arc, time::Duration};
+
+    // the recorded events are counted like the ones of a cluster, but the listed
+    // ones aren't, they happened before we started watching
+    #[tokio::test]
+    async fn counts_the_watched_pod_events() {
+        let metrics = test_recorder();
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/pod-events.json");
+        let client = MockApiServer::load(&fixture)
+            .unwrap()
+            .start()
+            .await
+            .unwrap();
+
+        let config = Config::default();
+        let (sender, mut events) = channel(&config.pipeline, &config.alerts.rules);
+        let source = KubeEvents::new(client, &config.watcher);
+        tokio::spawn(watch_events(
+            source,
+            Kinds::new(config.event_kinds()),
+            NamespaceFilter::new(&config.namespaces),
+            sender,
+        ));
+
+        let enricher = Enricher::new(
+            Arc::new(reflector::store().0),
+            Arc::new(reflector::store().0),
+            &config.enrichment,
+        );
+        let sampler = LogSampler::new(&config.pipeline.log_sampling);
+        for _ in 0..2 {
+            let event = tokio::time::timeout(Duration::from_secs(10), events.recv())
+                .await
+                .expect("the recorded events are watched")
+                .unwrap();
+            handle_event(&event, &enricher, &sampler);
+        }
+
+        let metrics = metrics.render() + registry::render().as_str();
+        let series = |name: &str, uid: &str| {
+            metrics
+                .lines()
+                .any(|line| line.starts_with(name) && line.contains(uid))
+        };
+        assert!(series(
+            "deleted_pods",
+            "0b7e5d3c-1f0a-4c55-9f0e-5d2f3b1c7a10"
+        ));
+        assert!(series(
+            "created_pods",
+            "6f3c1a2e-8b4d-4e7a-b1d2-9c0e4f5a6b7c"
+        ));
+        assert!(!series(
+            "created_pods",
+            "0b7e5d3c-1f0a-4c55-9f0e-5d2f3b1c7a10"
+        ));
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{
+    clock,
+    config::ObjectRateLimit,
+    memory::{Lru, PER_OBJECT_STORE},
+    metrics::{KIND_LABEL, NAMESPACE_LABEL, RATE_LIMITED_COUNTER, REASON_LABEL},
+};
+use axum_prometheus::metrics::counter;
+use k8s_openapi::api::core::v1::Event;
+use std::{sync::Mutex, time::Instant};
+use tracing::info;
+
+// past this many objects, the ones whose bucket filled up again are forgotten
+const PRUNE_AT: usize = 10_000;
+
+// a token bucket per involved object, so a single pod stuck in a loop making thousands
+// of the same event a minute can't drown the counters and sinks. What doesn't fit is
+// only counted, by namespace, kind and reason.
+pub struct ObjectThrottle {
+    settings: ObjectRateLimit,
+    buckets: Mutex<Lru<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+    // whether its last event was over the limit, to log once per flood
+    limited: bool,
+}
+
+impl ObjectThrottle {
+    pub fn new(settings: &ObjectRateLimit) -> Self {
+        ObjectThrottle {
+            settings: settings.clone(),
+            buckets: Mutex::new(Lru::new(PER_OBJECT_STORE, |limits| limits.per_object)),
+        }
+    }
+
+    // whether the event goes through, always when there's no limit
+    pub fn admits(&self, event: &Event) -> bool {
+        let Some(per_minute) = self.settings.per_minute else {
+            return true;
+        };
+        let Some(ref uid) = event.involved_object.uid else {
+            return true;
+        };
+        let (rate, burst) = (per_minute / 60.0, self.settings.burst as f64);
+        let now = clock::instant();
+        let mut buckets = self.buckets.lock().expect("object throttle lock poisoned");
+        if buckets.len() >= PRUNE_AT && !buckets.contains_key(uid) {
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.last).as_secs_f64() * rate < burst
+            });
+        }
+        let bucket = buckets.get_or_insert_with(uid.clone(), || Bucket {
+            tokens: burst,
+            last: now,
+            limited: false,
+        });
+        bucket.tokens =
+            (bucket.tokens + now.duration_since(bucket.last).as_secs_f64() * rate).min(burst);
+        bucket.last = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            bucket.limited = false;
+            return true;
+        }
+
+        let object = &event.involved_object;
+        if !bucket.limited {
+            info!(
+                "{} {} is over {} events a minute, only counting the next ones",
+                object.kind.as_deref().unwrap_or_default(),
+                object.name.as_deref().unwrap_or_default(),
+                per_minute
+            );
+            bucket.limited = true;
+        }
+        counter!(
+            RATE_LIMITED_COUNTER,
+            &[
+                (
+                    NAMESPACE_LABEL,
+                    object.namespace.clone().unwrap_or_default()
+                ),
+                (KIND_LABEL, object.kind.clone().unwrap_or_default()),
+                (REASON_LABEL, event.reason.clone().unwrap_or_default()),
+            ]
+        )
+        .increment(1);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use k8s_openapi::{
+        api::core::v1::ObjectReference,
+        chrono::{TimeDelta, Utc},
+    };
+    use std::sync::Arc;
+
+    fn event(uid: Option<&str>) -> Event {
+        Event {
+            involved_object: ObjectReference {
+                kind: Some("Pod".to_string()),
+                name: Some("web-1".to_string()),
+                uid: uid.map(str::to_string),
+                ..Default::default()
+            },
+            reason: Some("BackOff".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn lets_a_burst_through_then_the_rate() {
+        let start = Utc::now();
+        let manual = Arc::new(ManualClock::new(start));
+        clock::set_local(manual.clone());
+        let throttle = ObjectThrottle::new(&ObjectRateLimit {
+            per_minute: Some(60.0),
+            burst: 2,
+        });
+        let web_1 = event(Some("5d1c9a2e"));
+        let admitted = (0..3).map(|_| throttle.admits(&web_1)).collect::<Vec<_>>();
+        assert_eq!(admitted, [true, true, false]);
+        // a bucket of its own
+        assert!(throttle.admits(&event(Some("9e4b2c7d"))));
+        // and the objects without a uid aren't limited
+        assert!((0..3).all(|_| throttle.admits(&event(None))));
+
+        // one a second
+        manual.advance_to(start + TimeDelta::seconds(1));
+        assert!(throttle.admits(&web_1));
+        assert!(!throttle.admits(&web_1));
+        // never more than the burst after a quiet while
+        manual.advance_to(start + TimeDelta::minutes(10));
+        let admitted = (0..3).map(|_| throttle.admits(&web_1)).collect::<Vec<_>>();
+        assert_eq!(admitted, [true, true, false]);
+    }
+
+    #[test]
+    fn admits_everything_without_a_limit() {
+        let throttle = ObjectThrottle::new(&ObjectRateLimit::default());
+        assert!((0..100).all(|_| throttle.admits(&event(Some("5d1c9a2e")))));
+    }
+}
@@ -0,0 +1,119 @@
+// the tls of everything the operator connects to or serves over https: the api server's
+// client, the http clients of the sinks, the alerts and the plugin registries, the smtp
+// relay and the admission webhook. It's rustls by default, built in, or with the
+// native-tls feature the system's openssl, for where only its FIPS validated provider may
+// be used. With both built rustls wins, like it does for kube's client. lapin and lettre
+// have their own, built with the same backend by the features (see Cargo.toml).
+#[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+compile_error!("k8rs needs a tls backend, build it with the rustls or native-tls feature");
+
+#[cfg(feature = "rustls")]
+pub use rustls::{http, smtp, Acceptor, BACKEND};
+
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+pub use native::{http, smtp, Acceptor, BACKEND};
+
+#[cfg(feature = "rustls")]
+mod rustls {
+    use crate::error::ErrorKind;
+    use axum::Router;
+    use axum_server::{tls_rustls::RustlsConfig, Handle};
+    use lettre::transport::smtp::client::{TlsParameters, TlsParametersBuilder};
+    use reqwest::ClientBuilder;
+    use std::{io, path::Path};
+    use tokio::net::TcpListener;
+
+    pub const BACKEND: &str = "rustls";
+
+    pub fn http(builder: ClientBuilder) -> ClientBuilder {
+        builder.use_rustls_tls()
+    }
+
+    // the smtp relay's, trusting the webpki roots
+    pub fn smtp(host: &str) -> Result<TlsParameters, ErrorKind> {
+        TlsParametersBuilder::new(host.to_string())
+            .build_rustls()
+            .map_err(ErrorKind::other)
+    }
+
+    // the server side, a certificate and its key read from pem files
+    #[derive(Clone)]
+    pub struct Acceptor(RustlsConfig);
+
+    impl Acceptor {
+        pub async fn from_pem_file(cert: &Path, key: &Path) -> io::Result<Self> {
+            Ok(Acceptor(RustlsConfig::from_pem_file(cert, key).await?))
+        }
+
+        pub async fn reload_from_pem_file(&self, cert: &Path, key: &Path) -> io::Result<()> {
+            self.0.reload_from_pem_file(cert, key).await
+        }
+
+        // on the socket bound with the servers', handed over on a restart like theirs
+        pub async fn serve(
+            self,
+            listener: TcpListener,
+            handle: Handle,
+            app: Router,
+        ) -> io::Result<()> {
+            axum_server::from_tcp_rustls(listener.into_std()?, self.0)
+                .handle(handle)
+                .serve(app.into_make_service())
+                .await
+        }
+    }
+}
+
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+mod native {
+    use crate::error::ErrorKind;
+    use axum::Router;
+    use axum_server::{tls_openssl::OpenSSLConfig, Handle};
+    use lettre::transport::smtp::client::{TlsParameters, TlsParametersBuilder};
+    use reqwest::ClientBuilder;
+    use std::{io, path::Path};
+    use tokio::net::TcpListener;
+
+    pub const BACKEND: &str = "native-tls";
+
+    pub fn http(builder: ClientBuilder) -> ClientBuilder {
+        builder.use_native_tls()
+    }
+
+    // the smtp relay's, trusting the system's roots
+    pub fn smtp(host: &str) -> Result<TlsParameters, ErrorKind> {
+        TlsParametersBuilder::new(host.to_string())
+            .build_native()
+            .map_err(ErrorKind::other)
+    }
+
+    // the server side, a certificate (with its chain) and its key read from pem files
+    #[derive(Clone)]
+    pub struct Acceptor(OpenSSLConfig);
+
+    impl Acceptor {
+        pub async fn from_pem_file(cert: &Path, key: &Path) -> io::Result<Self> {
+            OpenSSLConfig::from_pem_chain_file(cert, key)
+                .map(Acceptor)
+                .map_err(io::Error::other)
+        }
+
+        pub async fn reload_from_pem_file(&self, cert: &Path, key: &Path) -> io::Result<()> {
+            self.0
+                .reload_from_pem_chain_file(cert, key)
+                .map_err(io::Error::other)
+        }
+
+        pub async fn serve(
+            self,
+            listener: TcpListener,
+            handle: Handle,
+            app: Router,
+        ) -> io::Result<()> {
+            axum_server::from_tcp_openssl(listener.into_std()?, self.0)
+                .handle(handle)
+                .serve(app.into_make_service())
+                .await
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use crate::{clock, config::TopSettings};
+use axum::{extract::Query, http::StatusCode, response::IntoResponse, routing::get, Json, Router};
+use k8s_openapi::{
+    api::core::v1::Event,
+    chrono::{DateTime, Utc},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+// the objects making the most events lately, for /api/v1/top. The workers
+// fill it in with every event, so like the snapshot it's a global.
+pub static TOP: LazyLock<Leaderboard> = LazyLock::new(Leaderboard::default);
+
+#[derive(Default)]
+pub struct Leaderboard {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    settings: TopSettings,
+    // the events of every object and reason, a minute at a time, the oldest first
+    minutes: VecDeque<(i64, HashMap<Key, u64>)>,
+}
+
+#[derive(Serialize, Debug, Clone, Hash, PartialEq, Eq)]
+struct Key {
+    namespace: String,
+    kind: String,
+    name: String,
+    reason: String,
+}
+
+#[derive(Serialize)]
+struct Noisy {
+    #[serde(flatten)]
+    key: Key,
+    events: u64,
+}
+
+impl Leaderboard {
+    pub fn configure(&self, settings: &TopSettings) {
+        let mut state = self.state.lock().expect("top lock poisoned");
+        state.settings = settings.clone();
+        if !settings.enabled {
+            state.minutes.clear();
+        }
+    }
+
+    pub fn observe(&self, event: &Event) {
+        let minute = clock::now().timestamp() / 60;
+        let mut state = self.state.lock().expect("top lock poisoned");
+        if !state.settings.enabled {
+            return;
+        }
+        let retention = (state.settings.retention.as_secs() / 60) as i64;
+        while state
+            .minutes
+            .front()
+            .is_some_and(|(at, _)| minute - at >= retention)
+        {
+            state.minutes.pop_front();
+        }
+        if state.minutes.back().is_none_or(|(at, _)| *at != minute) {
+            state.minutes.push_back((minute, HashMap::new()));
+        }
+        let max_objects = state.settings.max_objects;
+        let object = &event.involved_object;
+        let key = Key {
+            namespace: object.namespace.clone().unwrap_or_default(),
+            kind: object.kind.clone().unwrap_or_default(),
+            name: object.name.clone().unwrap_or_default(),
+            reason: event.reason.clone().unwrap_or_default(),
+        };
+        let Some((_, counts)) = state.minutes.back_mut() else {
+            return;
+        };
+        // in a storm, the objects already in are the ones worth keeping track of
+        if counts.len() >= max_objects && !counts.contains_key(&key) {
+            return;
+        }
+        *counts.entry(key).or_default() += 1;
+    }
+
+    // the noisiest objects (by reason) of the last minutes of the window, the most first
+    fn top(&self, window: Duration, limit: usize) -> Vec<Noisy> {
+        let since = clock::now().timestamp() / 60 - (window.as_secs() / 60) as i64;
+        let state = self.state.lock().expect("top lock poisoned");
+        let mut totals = HashMap::<&Key, u64>::new();
+        for (_, counts) in state.minutes.iter().filter(|(at, _)| *at > since) {
+            for (key, count) in counts {
+                *totals.entry(key).or_default() += count;
+            }
+        }
+        let mut noisy = totals
+            .into_iter()
+            .map(|(key, events)| Noisy {
+                key: key.clone(),
+                events,
+            })
+            .collect::<Vec<_>>();
+        noisy.sort_by(|a, b| {
+            b.events
+                .cmp(&a.events)
+                .then_with(|| (&a.key.namespace, &a.key.name).cmp(&(&b.key.namespace, &b.key.name)))
+        });
+        noisy.truncate(limit);
+        noisy
+    }
+
+    fn retention(&self) -> Duration {
+        self.state
+            .lock()
+            .expect("top lock poisoned")
+            .settings
+            .retention
+    }
+}
+
+#[derive(Deserialize)]
+struct TopQuery {
+    #[serde(default, with = "humantime_serde")]
+    window: Option<Duration>,
+    limit: Option<usize>,
+}
+
+pub fn router() -> Router {
+    Router::new().route("/api/v1/top", get(top))
+}
+
+// like /api/v1/top?window=15m&limit=10, the whole retention and 10 objects by default
+async fn top(query: Option<Query<TopQuery>>) -> impl IntoResponse {
+    let Some(Query(query)) = query else {
+        return (
+            StatusCode::BAD_REQUEST,
+            "the window must be a duration like 15m, the limit a number\n",
+        )
+            .into_response();
+    };
+    let retention = TOP.retention();
+    let window = query.window.unwrap_or(retention);
+    if window < Duration::from_secs(60) || window > retention {
+        let message = format!(
+            "the window must be between 1m and top.retention ({})\n",
+            humantime::format_duration(retention)
+        );
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+    let since: DateTime<Utc> = clock::now() - window;
+    Json(serde_json::json!({
+        "window": humantime::format_duration(window).to_string(),
+        "since": since.to_rfc3339(),
+        "objects": TOP.top(window, query.limit.unwrap_or(10)),
+    }))
+    .into_response()
+}
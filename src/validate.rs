@@ -0,0 +1,420 @@
+use crate::{
+    config::{Cli, Config, DeadLetter, LogSampling, PluginSource, SinkAuth, SinkKind},
+    error::ErrorKind,
+    features,
+    manifests::permissions,
+    metrics::sanitize_label_name,
+    outbound,
+    sinks::SinkRegistry,
+};
+use std::time::Duration;
+
+// kube's watch timeout, used when watcher.timeout is unset
+const DEFAULT_WATCH_TIMEOUT: Duration = Duration::from_secs(290);
+
+// checks a config the same way the operator does at startup, without touching the
+// cluster, and prints what the operator would do with it.
+// Fails (so the exit code isn't 0) when the operator would refuse to start.
+pub fn run(cli: &Cli) -> Result<(), ErrorKind> {
+    let config = Config::load(cli)?;
+    // builds every sink, like the http clients of the webhooks
+    SinkRegistry::from_settings(&config.sinks, &config.suppression)?;
+
+    let mut report = Report::default();
+    match cli.config {
+        Some(ref path) => report.line(format!("config: {:?} is valid", path)),
+        None => report.line("config: no config file, using the defaults"),
+    }
+
+    report.section("sinks");
+    if config.sinks.is_empty() {
+        report.item("none");
+    }
+    for sink in config.sinks.iter() {
+        let kind = match sink.kind {
+            SinkKind::Log {} => "log".to_string(),
+            SinkKind::File { ref path } => format!("file {:?}", path),
+            SinkKind::Webhook { ref url, .. } => format!("webhook {}", url),
+            SinkKind::Teams { ref url, .. } => format!("teams {}", url),
+            SinkKind::Sqs { ref queue_url, .. } => format!("sqs queue {}", queue_url),
+            SinkKind::Sns { ref topic_arn, .. } => format!("sns topic {}", topic_arn),
+            SinkKind::Email { ref to, .. } => format!("email to {}", to.join(", ")),
+            SinkKind::Pubsub { ref topic, .. } => format!("pubsub topic {}", topic),
+            SinkKind::Bucket { ref url, .. } => format!("bucket {}", url),
+            SinkKind::Amqp { ref exchange, .. } => format!("amqp exchange {}", exchange),
+        };
+        report.item(format!("{}: {}", sink.name, kind));
+        if sink.dry_run {
+            report.item(format!("{}: dry run, nothing is sent", sink.name));
+        }
+        if matches!(sink.kind, SinkKind::Sqs { .. } | SinkKind::Sns { .. })
+            && !cfg!(feature = "aws")
+        {
+            return Err(format!("sink {} needs k8rs built with the aws feature", sink.name).into());
+        }
+        match sink.auth {
+            Some(SinkAuth::Bearer { .. }) => {
+                report.item(format!("{}: authenticated with a bearer token", sink.name))
+            }
+            Some(SinkAuth::Oauth2 { ref token_url, .. }) => report.item(format!(
+                "{}: authenticated with OAuth2 tokens of {}",
+                sink.name, token_url
+            )),
+            Some(SinkAuth::Sigv4 {
+                ref service,
+                ref region,
+            }) => {
+                if !cfg!(feature = "aws") {
+                    return Err(format!(
+                        "sink {}: the sigv4 auth needs k8rs built with the aws feature",
+                        sink.name
+                    )
+                    .into());
+                }
+                report.item(format!(
+                    "{}: signed for the {} service in {}",
+                    sink.name, service, region
+                ))
+            }
+            None => {}
+        }
+        if sink.retry.attempts > 1 {
+            report.item(format!(
+                "{}: tried up to {} times, from {} to {} apart",
+                sink.name,
+                sink.retry.attempts,
+                humantime::format_duration(sink.retry.backoff),
+                humantime::format_duration(sink.retry.max_backoff)
+            ));
+        }
+        if sink.circuit_breaker.failures > 0 {
+            report.item(format!(
+                "{}: not tried for {} after {} failed deliveries in a row",
+                sink.name,
+                humantime::format_duration(sink.circuit_breaker.cooldown),
+                sink.circuit_breaker.failures
+            ));
+        }
+        if sink.batch.max_size > 1 {
+            report.item(format!(
+                "{}: batches of up to {} records, sent at least every {}",
+                sink.name,
+                sink.batch.max_size,
+                humantime::format_duration(sink.batch.max_interval)
+            ));
+        }
+        match sink.dead_letter {
+            Some(DeadLetter::File(ref path)) => {
+                report.item(format!("{}: dead letters to file {:?}", sink.name, path))
+            }
+            Some(DeadLetter::Sink(ref name)) => {
+                report.item(format!("{}: dead letters to sink {}", sink.name, name))
+            }
+            None => {}
+        }
+        if let SinkKind::File { ref path } = sink.kind {
+            if path
+                .parent()
+                .is_some_and(|dir| !dir.as_os_str().is_empty() && !dir.is_dir())
+            {
+                report.warn(format!(
+                    "sink {}: the directory of {:?} doesn't exist",
+                    sink.name, path
+                ));
+            }
+        }
+    }
+
+    let pipeline = &config.pipeline;
+    report.section("pipeline");
+    report.item(format!(
+        "{} worker(s), queue of {} events, {:?} on overflow",
+        pipeline.workers, pipeline.capacity, pipeline.overflow
+    ));
+    report.item(format!("events about: {}", list(&config.event_kinds())));
+    report.item(format!("delivering to: {}", list(&pipeline.sinks)));
+    if let Some(window) = pipeline.aggregation.window {
+        report.item(format!(
+            "rolling up identical events over {}, in at most {} groups",
+            humantime::format_duration(window),
+            pipeline.aggregation.max_groups
+        ));
+    }
+    let mut sampling = pipeline.log_sampling.iter().collect::<Vec<_>>();
+    sampling.sort_by_key(|(reason, _)| reason.as_str());
+    for (reason, sampling) in sampling {
+        match *sampling {
+            LogSampling::OneIn { one_in } => {
+                report.item(format!("logging 1 in {} {} events", one_in, reason))
+            }
+            LogSampling::PerSecond { per_second } => report.item(format!(
+                "logging at most {} {} events per second",
+                per_second, reason
+            )),
+        }
+    }
+
+    let enrichment = &config.enrichment;
+    let labels = enrichment
+        .pod_labels
+        .iter()
+        .map(|key| sanitize_label_name("label", key))
+        .chain(
+            enrichment
+                .pod_annotations
+                .iter()
+                .map(|key| sanitize_label_name("annotation", key)),
+        )
+        .collect::<Vec<_>>();
+    report.section("enrichment");
+    report.item(format!("extra pod counter labels: {}", list(&labels)));
+
+    let alerts = &config.alerts;
+    if !alerts.rules.is_empty() {
+        report.section("alerts");
+        match alerts.alertmanager {
+            Some(ref url) => report.item(format!("sending to Alertmanager at {}", url)),
+            None => report.item("no Alertmanager, only logging and sinks"),
+        }
+        if let Some(ref pagerduty) = alerts.pagerduty {
+            report.item(format!(
+                "triggering PagerDuty incidents at {}",
+                pagerduty.url
+            ));
+        }
+        if alerts.dry_run {
+            report.item("dry run, the firing alerts are only logged");
+        }
+        for rule in alerts.rules.iter() {
+            let reasons = match rule.reasons.as_slice() {
+                [] => String::new(),
+                reasons => format!("{} ", reasons.join("/")),
+            };
+            report.item(format!(
+                "{}: more than {} {}events within {} by {:?}, delivering to: {}",
+                rule.name,
+                rule.threshold,
+                reasons,
+                humantime::format_duration(rule.window),
+                rule.group_by,
+                list(&rule.sinks)
+            ));
+        }
+    }
+
+    let slos = &config.slos;
+    if !slos.objectives.is_empty() {
+        report.section("slos");
+        for objective in slos.objectives.iter() {
+            let reasons = match objective.reasons.as_slice() {
+                [] => String::new(),
+                reasons => format!("{} ", reasons.join("/")),
+            };
+            report.item(format!(
+                "{}: at most {} {}events within {}",
+                objective.name,
+                objective.budget,
+                reasons,
+                humantime::format_duration(objective.period)
+            ));
+        }
+    }
+
+    let metrics = &config.metrics;
+    if metrics.prefix.is_some() || !metrics.names.is_empty() || !metrics.labels.is_empty() {
+        report.section("metrics");
+        if let Some(ref prefix) = metrics.prefix {
+            report.item(format!("prefix: {}", prefix));
+        }
+        for (name, renamed) in metrics.names.iter() {
+            report.item(format!("{} renamed to {}", name, renamed));
+        }
+        for (label, renamed) in metrics.labels.iter() {
+            report.item(format!("label {} renamed to {}", label, renamed));
+        }
+    }
+
+    let logs = &config.container_logs;
+    if !logs.reasons.is_empty() {
+        report.section("container_logs");
+        report.item(format!(
+            "the last {} lines on {} events, at most {} a minute",
+            logs.lines,
+            logs.reasons.join("/"),
+            logs.per_minute
+        ));
+    }
+
+    let messages = &config.messages;
+    if !messages.templates.is_empty() {
+        report.section("messages");
+        for (reason, template) in messages.templates.iter() {
+            report.item(format!("{}: {}", reason, template));
+        }
+    }
+
+    let tenants = &config.tenants;
+    if !tenants.routes.is_empty() {
+        report.section("tenants");
+        for route in tenants.routes.iter() {
+            let labels = route
+                .namespace_labels
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect::<Vec<_>>();
+            report.item(format!(
+                "{}: namespaces {}, labeled {}, delivering to: {}",
+                route.name,
+                list(&route.namespaces),
+                list(&labels),
+                list(&route.sinks)
+            ));
+            if tenants.metrics {
+                report.item(format!(
+                    "{}: its metrics on /metrics/{}",
+                    route.name, route.name
+                ));
+            }
+        }
+    }
+
+    let plugins = &config.plugins;
+    if !plugins.modules.is_empty() {
+        report.section("plugins");
+        for module in plugins.modules.iter() {
+            match module.source {
+                PluginSource::Path(ref path) => {
+                    report.item(format!("{}: from {:?}", module.name, path));
+                    if !path.is_file() {
+                        report.warn(format!("plugin {}: {:?} doesn't exist", module.name, path));
+                    }
+                }
+                PluginSource::Oci(ref reference) => {
+                    report.item(format!("{}: pulled from {}", module.name, reference))
+                }
+            }
+        }
+        if !cfg!(feature = "plugins") {
+            return Err("plugins.modules needs k8rs built with the plugins feature".into());
+        }
+    }
+
+    if config.outbound != Default::default() {
+        report.section("outbound");
+        outbound::configure(&config.outbound)?;
+        if let Some(ref proxy) = config.outbound.proxy {
+            report.item(format!("through the proxy {}", proxy));
+        }
+        if let Some(ref path) = config.outbound.ca_bundle {
+            report.item(format!("trusting the CA bundle {:?}", path));
+        }
+        if config.outbound.insecure_skip_verify {
+            report.item("not verifying the certificates");
+        }
+    }
+
+    // what's switched on whether with the config or by default
+    report.section("features");
+    let switches = config.features.switches();
+    for flag in features::FLAGS.iter() {
+        report.item(format!(
+            "{} ({}): {}",
+            flag.name,
+            flag.stage.as_str(),
+            if flag.on(&switches) { "on" } else { "off" }
+        ));
+    }
+
+    // everything the operator's service account must be allowed to do
+    report.section("permissions needed");
+    for permission in permissions(&config) {
+        let scope = if permission.cluster {
+            "cluster-wide"
+        } else {
+            "in the operator's namespace"
+        };
+        report.item(format!(
+            "{}: {} ({})",
+            permission.name(),
+            permission.verbs.join(", "),
+            scope
+        ));
+    }
+
+    let kube = &config.kube;
+    let watch_timeout = config.watcher.timeout.unwrap_or(DEFAULT_WATCH_TIMEOUT);
+    if kube
+        .read_timeout
+        .is_some_and(|timeout| timeout <= watch_timeout)
+    {
+        report.warn(format!(
+            "kube.read_timeout is not longer than the {:?} watch timeout, watches will be cut short",
+            watch_timeout
+        ));
+    }
+    if kube.burst.is_some() && kube.qps.is_none() {
+        report.warn("kube.burst does nothing without kube.qps");
+    }
+    let admission = &config.admission;
+    if admission.enabled {
+        for file in ["tls.crt", "tls.key"] {
+            if !admission.cert_dir.join(file).is_file() {
+                report.warn(format!(
+                    "admission: there's no {} in {:?} (yet?)",
+                    file, admission.cert_dir
+                ));
+            }
+        }
+    } else if admission.pod_mutation.enabled {
+        report.warn("admission.pod_mutation does nothing without admission.enabled");
+    }
+    if config.admin.enabled {
+        report.warn("the admin endpoints are open to anyone who can reach the metrics port");
+    }
+
+    print!("{}", report.finish());
+    Ok(())
+}
+
+fn list(values: &[String]) -> String {
+    if values.is_empty() {
+        "none".to_string()
+    } else {
+        values.join(", ")
+    }
+}
+
+#[derive(Default)]
+struct Report {
+    out: String,
+    warnings: Vec<String>,
+}
+
+impl Report {
+    fn line(&mut self, line: impl AsRef<str>) {
+        self.out.push_str(line.as_ref());
+        self.out.push('\n');
+    }
+
+    fn section(&mut self, name: &str) {
+        self.line(format!("{}:", name));
+    }
+
+    fn item(&mut self, item: impl AsRef<str>) {
+        self.line(format!("  - {}", item.as_ref()));
+    }
+
+    fn warn(&mut self, warning: impl Into<String>) {
+        self.warnings.push(warning.into());
+    }
+
+    fn finish(mut self) -> String {
+        if !self.warnings.is_empty() {
+            self.section("warnings");
+            for warning in std::mem::take(&mut self.warnings) {
+                self.item(warning);
+            }
+        }
+        self.out
+    }
+}
@@ -0,0 +1,59 @@
+use crate::{
+    metrics::{
+        BUILD_INFO_GAUGE, GIT_SHA_LABEL, RUSTC_LABEL, TLS_LABEL, UPTIME_COUNTER, VERSION_LABEL,
+    },
+    tls,
+};
+use axum::{routing::get, Json, Router};
+use axum_prometheus::metrics::{counter, gauge};
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+// set by build.rs
+pub const GIT_SHA: &str = env!("K8RS_GIT_SHA");
+pub const RUSTC: &str = env!("K8RS_RUSTC");
+
+// how often the uptime moves, a stalled runtime stops moving it
+const HEARTBEAT: Duration = Duration::from_secs(10);
+
+// the build as a series always at 1, for dashboards to tell upgrades apart
+pub fn export() {
+    gauge!(
+        BUILD_INFO_GAUGE,
+        &[
+            (VERSION_LABEL, VERSION.to_string()),
+            (GIT_SHA_LABEL, GIT_SHA.to_string()),
+            (RUSTC_LABEL, RUSTC.to_string()),
+            (TLS_LABEL, tls::BACKEND.to_string())
+        ]
+    )
+    .set(1.0);
+}
+
+// counts the seconds the operator has been up, every heartbeat
+pub async fn heartbeat() {
+    let mut interval = tokio::time::interval(HEARTBEAT);
+    let mut last = Instant::now();
+    loop {
+        interval.tick().await;
+        let now = Instant::now();
+        counter!(UPTIME_COUNTER).increment(now.duration_since(last).as_secs());
+        // the fractions of a second aren't lost
+        last += Duration::from_secs(now.duration_since(last).as_secs());
+    }
+}
+
+pub fn router() -> Router {
+    Router::new().route(
+        "/version",
+        get(|| async {
+            Json(json!({
+                "version": VERSION,
+                "git_sha": GIT_SHA,
+                "rustc": RUSTC,
+                "tls": tls::BACKEND,
+            }))
+        }),
+    )
+}
@@ -0,0 +1,649 @@
+use crate::{
+    config::{self, BackoffSettings, WatcherSettings},
+    discovery::Kinds,
+    error,
+    metrics::{
+        EVENTS_RECEIVED_COUNTER, MISSED_EVENTS_COUNTER, TYPE_LABEL, WATCHER_LABEL,
+        WATCHER_PAUSED_GAUGE, WATCH_GAPS_COUNTER,
+    },
+    namespaces::{NamespaceFilter, WATCHERS},
+    pipeline::EventSender,
+    registry::WATCHER_BACKOFFS,
+    resume::{self, ResumePoint},
+    retention::RETENTION,
+    snapshot::SNAPSHOT,
+};
+use axum_prometheus::metrics::{counter, gauge};
+use backoff::{backoff::Backoff, ExponentialBackoff, ExponentialBackoffBuilder};
+use futures::{Stream, StreamExt};
+use k8s_openapi::api::core::v1::Event;
+use k8s_openapi::chrono::{DateTime, Utc};
+use kube::{
+    runtime::{
+        utils::ResetTimerBackoff,
+        watcher::{self, watcher},
+        WatchStreamExt,
+    },
+    Api, Client, Resource, ResourceExt,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, LazyLock, Mutex},
+    time::Duration,
+};
+use tokio::{sync::watch, time::Instant};
+use tracing::{info, warn};
+
+// every watcher, by the name it has on the metrics, so they can be paused
+pub static PAUSES: LazyLock<Pauses> = LazyLock::new(Pauses::default);
+
+// where the events of the pipeline come from, the cluster or anything
+// that can stand in for it, so the pipeline can run without one
+pub trait EventSource {
+    fn events(&self) -> impl Stream<Item = Result<watcher::Event<Event>, watcher::Error>> + Send;
+
+    // the watcher's name on the metrics
+    fn name(&self) -> String {
+        "events".to_string()
+    }
+}
+
+// the events of the cluster, as the "events" watcher, or "events/<namespace>" for the
+// ones of a discovered namespace
+pub struct KubeEvents {
+    name: String,
+    // the discovered namespace's, whose health is followed
+    namespace: Option<String>,
+    api: Api<Event>,
+    settings: WatcherSettings,
+    resume: Option<Arc<ResumePoint>>,
+}
+
+impl KubeEvents {
+    pub fn new(client: Client, settings: &WatcherSettings) -> Self {
+        KubeEvents {
+            name: "events".to_string(),
+            namespace: None,
+            // all events that happen on the cluster's "default" namespace
+            api: Api::<Event>::default_namespaced(client.clone()),
+            settings: settings.clone(),
+            resume: settings
+                .resume
+                .as_ref()
+                .map(|store| Arc::new(ResumePoint::new("events", client, store))),
+        }
+    }
+
+    // a discovered namespace's events, which don't keep where they got to
+    pub fn namespaced(client: Client, namespace: &str, settings: &WatcherSettings) -> Self {
+        KubeEvents {
+            name: format!("events/{}", namespace),
+            namespace: Some(namespace.to_string()),
+            api: Api::<Event>::namespaced(client, namespace),
+            settings: settings.clone(),
+            resume: None,
+        }
+    }
+
+    // where the watch got to, when it's kept
+    pub fn resume_point(&self) -> Option<Arc<ResumePoint>> {
+        self.resume.clone()
+    }
+}
+
+impl EventSource for KubeEvents {
+    fn events(&self) -> impl Stream<Item = Result<watcher::Event<Event>, watcher::Error>> + Send {
+        let (api, settings) = (self.api.clone(), self.settings.clone());
+        let (name, backoff_name) = (self.name.clone(), self.name.clone());
+        let namespace = self.namespace.clone();
+        let timeout = settings
+            .timeout
+            .map_or(290, |timeout| timeout.as_secs() as u32);
+        let watch = resyncing(&self.name, self.settings.resync, move || {
+            watcher(api.clone(), watcher_config(&settings))
+                .backoff(WatcherBackoff::new(backoff_name.clone(), &settings.backoff))
+        });
+        pausable(
+            &self.name,
+            resume::resuming(self.api.clone(), self.resume.clone(), timeout, watch).inspect(
+                move |event| {
+                    SNAPSHOT.watcher(&name, event);
+                    RETENTION.observe(&name, event);
+                    if let Some(ref namespace) = namespace {
+                        WATCHERS.observe(namespace, event);
+                    }
+                },
+            ),
+        )
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+// this is the main routine, where we'll observe events and filter them into
+// only what we want to listen, handing them to the pipeline.
+// Only the events about the given kinds of objects, in the namespaces the filter
+// allows, are kept.
+pub async fn watch_events(
+    source: impl EventSource,
+    kinds: Kinds,
+    namespaces: NamespaceFilter,
+    sender: EventSender,
+) {
+    // we pin the stream for 'async rust' reasons
+    let mut event_stream = Box::pin(source.events());
+    let mut gaps = Gaps::new(&source.name());
+    let mut reconnects = Reconnects::new(&source.name());
+
+    while let Some(event) = event_stream.next().await {
+        reconnects.observe(&event);
+        // what a re-list finds that the watch didn't tell goes through like it did
+        let missed = event.as_ref().is_ok_and(|event| gaps.observe(event));
+        if missed
+            || matches!(
+                event,
+                Ok(watcher::Event::Apply(_) | watcher::Event::Delete(_))
+            )
+        {
+            counter!(EVENTS_RECEIVED_COUNTER).increment(1);
+        }
+        let kept = |event: &Event| {
+            kinds.contains(&event.involved_object) && namespaces.allows_event(event)
+        };
+        // this match is kinda self explanatory
+        match event {
+            Ok(watcher::Event::Apply(event)) | Ok(watcher::Event::Delete(event))
+                if kept(&event) =>
+            {
+                if !sender.send(event).await {
+                    info!("Pipeline closed, stopping the watcher");
+                    return;
+                }
+            }
+            Ok(watcher::Event::Init) => {
+                info!("Starting the watch stream...")
+            }
+            Ok(watcher::Event::InitDone) => {
+                info!("Watch stream up and running!")
+            }
+            Ok(watcher::Event::InitApply(event)) if missed && kept(&event) => {
+                if !sender.send(event).await {
+                    info!("Pipeline closed, stopping the watcher");
+                    return;
+                }
+            }
+            Ok(_) => {} // we're not interested in init apply
+            Err(err) => {
+                error::Error::new("events", err).report();
+            }
+        }
+    }
+}
+
+// what a watcher saw of each object, by uid, so its re-lists can tell what the watch
+// missed: the objects listed with a resource version it never saw, and the ones it
+// knew that aren't there anymore. The first list has nothing to compare to.
+pub struct Gaps {
+    watcher: String,
+    seen: HashMap<String, String>,
+    relisted: HashMap<String, String>,
+    listed: bool,
+}
+
+impl Gaps {
+    pub fn new(watcher: &str) -> Self {
+        Gaps {
+            watcher: watcher.to_string(),
+            seen: HashMap::new(),
+            relisted: HashMap::new(),
+            listed: false,
+        }
+    }
+
+    // whether the watch missed this listed object, counting every gap
+    pub fn observe<K: Resource>(&mut self, event: &watcher::Event<K>) -> bool {
+        let version =
+            |object: &K| Some((object.uid()?, object.resource_version().unwrap_or_default()));
+        match event {
+            watcher::Event::Init => self.relisted.clear(),
+            watcher::Event::InitApply(object) => {
+                let Some((uid, version)) = version(object) else {
+                    return false;
+                };
+                let missed = self.listed && self.seen.get(&uid) != Some(&version);
+                self.relisted.insert(uid, version);
+                if missed {
+                    self.count("update", 1);
+                }
+                return missed;
+            }
+            watcher::Event::InitDone => {
+                let relisted = std::mem::take(&mut self.relisted);
+                let deleted = self
+                    .seen
+                    .keys()
+                    .filter(|uid| !relisted.contains_key(*uid))
+                    .count();
+                if self.listed && deleted > 0 {
+                    self.count("delete", deleted as u64);
+                }
+                self.seen = relisted;
+                self.listed = true;
+            }
+            watcher::Event::Apply(object) => {
+                if let Some((uid, version)) = version(object) {
+                    // a resumed watch hands out some of its first list as watched
+                    self.relisted.insert(uid.clone(), version.clone());
+                    self.seen.insert(uid, version);
+                }
+            }
+            watcher::Event::Delete(object) => {
+                if let Some(uid) = object.uid() {
+                    self.seen.remove(&uid);
+                }
+            }
+        }
+        false
+    }
+
+    fn count(&self, kind: &str, gaps: u64) {
+        warn!(
+            "Watcher {} missed {} {}(s), found out while re-listing",
+            self.watcher, gaps, kind
+        );
+        counter!(
+            WATCH_GAPS_COUNTER,
+            &[
+                (WATCHER_LABEL, self.watcher.clone()),
+                (TYPE_LABEL, kind.to_string())
+            ]
+        )
+        .increment(gaps);
+    }
+}
+
+// how many times the events happened while an events watcher was reconnecting, from
+// the counts of the events it re-listed afterwards against the counts it saw before.
+// An event that happened 12 times meanwhile is 12 missed, not the one update the
+// gaps see. Only the re-lists after an error count, not the resyncs.
+pub struct Reconnects {
+    watcher: String,
+    // the last count seen of every event, by uid
+    counts: HashMap<String, i32>,
+    relisted: HashMap<String, i32>,
+    // when the watcher first failed since it last got an event, if it did
+    failed_at: Option<DateTime<Utc>>,
+    listed: bool,
+    relisting: bool,
+    missed: u64,
+}
+
+impl Reconnects {
+    pub fn new(watcher: &str) -> Self {
+        Reconnects {
+            watcher: watcher.to_string(),
+            counts: HashMap::new(),
+            relisted: HashMap::new(),
+            failed_at: None,
+            listed: false,
+            relisting: false,
+            missed: 0,
+        }
+    }
+
+    pub fn observe(&mut self, event: &Result<watcher::Event<Event>, watcher::Error>) {
+        let count = |event: &Event| event.count.unwrap_or(1).max(1);
+        match event {
+            Err(_) => {
+                self.failed_at.get_or_insert_with(Utc::now);
+            }
+            Ok(watcher::Event::Init) => {
+                self.relisting = self.listed && self.failed_at.is_some();
+                self.relisted.clear();
+                self.missed = 0;
+            }
+            Ok(watcher::Event::InitApply(event)) => {
+                let Some(uid) = event.uid() else {
+                    return;
+                };
+                let count = count(event);
+                if self.relisting {
+                    let seen = self.counts.get(&uid).copied().unwrap_or(0);
+                    self.missed += count.saturating_sub(seen).max(0) as u64;
+                }
+                self.relisted.insert(uid, count);
+            }
+            Ok(watcher::Event::InitDone) => {
+                if let Some(failed_at) = self.failed_at.take().filter(|_| self.relisting) {
+                    self.report(failed_at);
+                }
+                // the ones that weren't listed again expired meanwhile
+                self.counts = std::mem::take(&mut self.relisted);
+                self.listed = true;
+                self.relisting = false;
+            }
+            Ok(watcher::Event::Apply(event)) => {
+                if let Some(uid) = event.uid() {
+                    self.counts.insert(uid, count(event));
+                }
+                // it came back without having to list again
+                self.failed_at = None;
+            }
+            Ok(watcher::Event::Delete(event)) => {
+                if let Some(uid) = event.uid() {
+                    self.counts.remove(&uid);
+                }
+                self.failed_at = None;
+            }
+        }
+    }
+
+    fn report(&self, failed_at: DateTime<Utc>) {
+        let now = Utc::now();
+        let window = (now - failed_at).to_std().unwrap_or_default();
+        if self.missed == 0 {
+            info!(
+                "Watcher {} reconnected after {}, nothing happened meanwhile",
+                self.watcher,
+                humantime::format_duration(Duration::from_secs(window.as_secs()))
+            );
+            return;
+        }
+        warn!(
+            "Watcher {} was reconnecting from {} to {} ({}), about {} event(s) happened meanwhile",
+            self.watcher,
+            failed_at.to_rfc3339(),
+            now.to_rfc3339(),
+            humantime::format_duration(Duration::from_secs(window.as_secs())),
+            self.missed
+        );
+        counter!(
+            MISSED_EVENTS_COUNTER,
+            &[(WATCHER_LABEL, self.watcher.clone())]
+        )
+        .increment(self.missed);
+    }
+}
+
+// a watcher started over every so often, going through a whole new list like after a
+// desync, rather than trusting its watch forever. It only runs once without a resync.
+pub fn resyncing<S: Stream>(
+    name: &str,
+    every: Option<Duration>,
+    start: impl Fn() -> S,
+) -> impl Stream<Item = S::Item> {
+    let name = name.to_string();
+    let deadline = every.map(|every| Instant::now() + every);
+    futures::stream::unfold(
+        (Box::pin(start()), start, deadline),
+        move |(mut stream, start, mut deadline)| {
+            let name = name.clone();
+            async move {
+                loop {
+                    let Some(at) = deadline else {
+                        let item = stream.next().await?;
+                        return Some((item, (stream, start, deadline)));
+                    };
+                    tokio::select! {
+                        item = stream.next() => {
+                            return item.map(|item| (item, (stream, start, deadline)));
+                        }
+                        _ = tokio::time::sleep_until(at) => {
+                            info!("Watcher {} starting over to resync", name);
+                            stream = Box::pin(start());
+                            deadline = every.map(|every| Instant::now() + every);
+                        }
+                    }
+                }
+            }
+        },
+    )
+}
+
+// whether each watcher is paused
+#[derive(Default)]
+pub struct Pauses {
+    watchers: Mutex<BTreeMap<String, watch::Sender<bool>>>,
+}
+
+impl Pauses {
+    // a watcher started again under the same name is still paused
+    fn register(&self, name: &str) -> watch::Receiver<bool> {
+        let mut watchers = self.watchers.lock().expect("pauses lock poisoned");
+        watchers
+            .entry(name.to_string())
+            .or_insert_with(|| {
+                gauge!(WATCHER_PAUSED_GAUGE, &[(WATCHER_LABEL, name.to_string())]).set(0.0);
+                watch::Sender::new(false)
+            })
+            .subscribe()
+    }
+
+    // false when there's no such watcher
+    pub fn set(&self, name: &str, paused: bool) -> bool {
+        let watchers = self.watchers.lock().expect("pauses lock poisoned");
+        let Some(watcher) = watchers.get(name) else {
+            return false;
+        };
+        if watcher.send_replace(paused) != paused {
+            gauge!(WATCHER_PAUSED_GAUGE, &[(WATCHER_LABEL, name.to_string())]).set(if paused {
+                1.0
+            } else {
+                0.0
+            });
+            match paused {
+                true => info!("Watcher {} paused", name),
+                false => info!("Watcher {} resumed", name),
+            }
+        }
+        true
+    }
+
+    pub fn list(&self) -> BTreeMap<String, bool> {
+        let watchers = self.watchers.lock().expect("pauses lock poisoned");
+        watchers
+            .iter()
+            .map(|(name, paused)| (name.clone(), *paused.borrow()))
+            .collect()
+    }
+
+    // for the watchers that are gone for good
+    pub fn remove(&self, name: &str) {
+        let mut watchers = self.watchers.lock().expect("pauses lock poisoned");
+        if watchers.remove(name).is_some() {
+            gauge!(WATCHER_PAUSED_GAUGE, &[(WATCHER_LABEL, name.to_string())]).set(0.0);
+        }
+    }
+}
+
+// a watcher stream that isn't polled while it's paused, so nothing is read from the
+// api server meanwhile. What happened while paused is mostly skipped: the watch times
+// out and the watcher starts over from a fresh list once resumed.
+pub fn pausable<S: Stream>(name: &str, stream: S) -> impl Stream<Item = S::Item> {
+    let paused = PAUSES.register(name);
+    futures::stream::unfold(
+        (Box::pin(stream), paused),
+        |(mut stream, mut paused)| async move {
+            loop {
+                // the sender is only gone for watchers that are gone too
+                let _ = paused.wait_for(|paused| !paused).await;
+                let item = tokio::select! {
+                    item = stream.next() => item,
+                    // paused while waiting for the next item
+                    Ok(_) = paused.wait_for(|paused| *paused) => continue,
+                };
+                return item.map(|item| (item, (stream, paused)));
+            }
+        },
+    )
+}
+
+// translates our watcher settings into kube's watcher config
+pub fn watcher_config(settings: &WatcherSettings) -> watcher::Config {
+    let mut config = watcher::Config::default();
+
+    config.initial_list_strategy = match settings.initial_list_strategy {
+        config::InitialListStrategy::ListWatch => watcher::InitialListStrategy::ListWatch,
+        config::InitialListStrategy::StreamingList => watcher::InitialListStrategy::StreamingList,
+    };
+    config.list_semantic = match settings.list_semantic {
+        config::ListSemantic::MostRecent => watcher::ListSemantic::MostRecent,
+        config::ListSemantic::Any => watcher::ListSemantic::Any,
+    };
+    config.page_size = settings.page_size;
+    config.timeout = settings
+        .timeout
+        .map(|timeout| u32::try_from(timeout.as_secs()).unwrap_or(u32::MAX));
+
+    if settings.initial_list_strategy == config::InitialListStrategy::StreamingList {
+        info!("Using streaming lists, the page size and list semantic settings are ignored");
+    }
+    config
+}
+
+// the same exponential backoff kube's `default_backoff` uses, but with our own
+// knobs, and counting every time the watcher has to back off.
+pub struct WatcherBackoff {
+    watcher: String,
+    inner: ResetTimerBackoff<ExponentialBackoff>,
+}
+
+impl WatcherBackoff {
+    pub fn new(watcher: impl Into<String>, settings: &BackoffSettings) -> Self {
+        let exponential = ExponentialBackoffBuilder::new()
+            .with_initial_interval(settings.initial)
+            .with_max_interval(settings.max)
+            .with_randomization_factor(settings.jitter)
+            .with_multiplier(settings.multiplier)
+            .with_max_elapsed_time(None) // we never give up
+            .build();
+
+        WatcherBackoff {
+            watcher: watcher.into(),
+            inner: ResetTimerBackoff::new(exponential, settings.reset_after),
+        }
+    }
+}
+
+impl Backoff for WatcherBackoff {
+    fn next_backoff(&mut self) -> Option<Duration> {
+        let next = self.inner.next_backoff();
+        WATCHER_BACKOFFS.increment(&[(WATCHER_LABEL, self.watcher.clone())], 1);
+        SNAPSHOT.watcher_backoff(&self.watcher);
+        if let Some(delay) = next {
+            warn!("Watcher {} backing off for {:?}", self.watcher, delay);
+        }
+        next
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        enrich::Enricher,
+        error::{Context, ErrorKind},
+        logging::LogSampler,
+        metrics::test_recorder,
+        pipeline::{channel, handle_event},
+        registry,
+    };
+    use kube::runtime::reflector;
+    use std::{
+        fs::File,
+        io::{BufRead, BufReader},
+        path::Path,
+    };
+
+    // events known beforehand, handed out as if the watcher had seen them happen
+    // one after the other, once its (empty) initial list was done
+    struct FixtureEvents {
+        events: Vec<Event>,
+    }
+
+    impl FixtureEvents {
+        // a file with an event per line, as json, like `kubectl get events -o json`'s items
+        fn load(path: &Path) -> Result<Self, ErrorKind> {
+            let file = File::open(path).with_context(|| format!("could not open {:?}", path))?;
+            let mut events = Vec::new();
+            for (number, line) in BufReader::new(file).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let event = serde_json::from_str::<Event>(&line).with_context(|| {
+                    format!("invalid event on line {} of {:?}", number + 1, path)
+                })?;
+                events.push(event);
+            }
+            Ok(FixtureEvents { events })
+        }
+    }
+
+    impl EventSource for FixtureEvents {
+        fn events(
+            &self,
+        ) -> impl Stream<Item = Result<watcher::Event<Event>, watcher::Error>> + Send {
+            let events = self.events.iter().cloned().map(watcher::Event::Apply);
+            futures::stream::iter(
+                [watcher::Event::Init, watcher::Event::InitDone]
+                    .into_iter()
+                    .chain(events)
+                    .map(Ok),
+            )
+        }
+
+        fn name(&self) -> String {
+            "fixture".to_string()
+        }
+    }
+
+    // the pipeline without a cluster: the pods' events are counted, the ones about the
+    // kinds that aren't watched never get to it
+    #[tokio::test]
+    async fn counts_the_events_of_the_fixture() {
+        test_recorder();
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/events.jsonl");
+        let source = FixtureEvents::load(&fixture).unwrap();
+        assert_eq!(source.events.len(), 3);
+
+        let config = Config::default();
+        let (sender, mut events) = channel(&config.pipeline, &config.alerts.rules);
+        watch_events(
+            source,
+            Kinds::new(config.event_kinds()),
+            NamespaceFilter::new(&config.namespaces),
+            sender,
+        )
+        .await;
+
+        let enricher = Enricher::new(
+            Arc::new(reflector::store().0),
+            Arc::new(reflector::store().0),
+            &config.enrichment,
+        );
+        let sampler = LogSampler::new(&config.pipeline.log_sampling);
+        let mut handled = Vec::new();
+        while let Some(event) = events.recv().await {
+            handle_event(&event, &enricher, &sampler);
+            handled.push(event.involved_object.kind.unwrap_or_default());
+        }
+        assert_eq!(handled, ["Pod", "Pod"]);
+
+        let metrics = registry::render();
+        let created = |uid: &str| {
+            metrics
+                .lines()
+                .any(|line| line.starts_with("created_pods") && line.contains(uid))
+        };
+        assert!(created("5d1c9a2e-7f3b-4b8e-a6c1-2e9f0d4b7a31"));
+        assert!(created("9e4b2c7d-3a1f-4d6e-8b5c-7f0a1e2d3c49"));
+    }
+}
@@ -0,0 +1,321 @@
+// the whole pipeline against a real cluster: the operator is started on a kind or k3d
+// cluster, workloads are brought up and broken in a namespace of their own, and its
+// counters, histograms and sink deliveries are checked against what was done. Only
+// built with the chaos feature, see "Run the chaos tests" in the README.
+use k8s_openapi::api::{
+    apps::v1::Deployment,
+    core::v1::{Namespace, Pod},
+};
+use kube::{
+    api::{DeleteParams, ListParams, PostParams},
+    config::Kubeconfig,
+    Api, Client,
+};
+use serde_json::{json, Value};
+use std::{future::Future, net::TcpListener, path::PathBuf, process::Stdio, time::Duration};
+use tokio::{
+    process::{Child, Command},
+    time::{sleep, Instant},
+};
+
+// how long the cluster gets to do what it was asked, pulling an image included
+const DEADLINE: Duration = Duration::from_secs(180);
+const POLL: Duration = Duration::from_secs(2);
+
+// the clusters it's fine to break things on, unless K8RS_CHAOS_ANY_CONTEXT is set
+const CONTEXT_PREFIXES: [&str; 2] = ["kind-", "k3d-"];
+
+// what's left behind, even when an assertion fails halfway
+struct Run {
+    client: Client,
+    namespace: String,
+    dir: PathBuf,
+    exporter: Child,
+    metrics_url: String,
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = self.exporter.start_kill();
+        let _ = std::fs::remove_dir_all(&self.dir);
+        let namespaces = Api::<Namespace>::all(self.client.clone());
+        let namespace = self.namespace.clone();
+        tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(async move {
+                let _ = namespaces
+                    .delete(&namespace, &DeleteParams::default())
+                    .await;
+            })
+        });
+    }
+}
+
+impl Run {
+    async fn start() -> Run {
+        let kubeconfig = Kubeconfig::read().expect("a kubeconfig, like the one of kind");
+        let context = kubeconfig.current_context.unwrap_or_default();
+        assert!(
+            CONTEXT_PREFIXES
+                .iter()
+                .any(|prefix| context.starts_with(prefix))
+                || std::env::var_os("K8RS_CHAOS_ANY_CONTEXT").is_some(),
+            "refusing to break things on {:?}, it's not a kind or k3d cluster",
+            context
+        );
+        let client = Client::try_default()
+            .await
+            .expect("a client of the cluster");
+
+        let id = format!("{:08x}", rand::random::<u32>());
+        let namespace = format!("k8rs-chaos-{}", id);
+        let namespaces = Api::<Namespace>::all(client.clone());
+        let labeled = serde_json::from_value(json!({
+            "metadata": {"name": namespace, "labels": {"k8rs.io/chaos": id}},
+        }))
+        .unwrap();
+        namespaces
+            .create(&PostParams::default(), &labeled)
+            .await
+            .expect("a namespace of our own");
+
+        let dir = std::env::temp_dir().join(&namespace);
+        std::fs::create_dir_all(&dir).unwrap();
+        // a free port, for the exporter to take right after
+        let listen = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let config = json!({
+            "namespaces": {"selector": format!("k8rs.io/chaos={}", id)},
+            "server": {"listen": [listen.to_string()]},
+            "sinks": [{"name": "archive", "type": "file", "path": dir.join("records.jsonl")}],
+            "pipeline": {"sinks": ["archive"]},
+        });
+        // json is yaml too
+        let config_path = dir.join("config.yaml");
+        std::fs::write(&config_path, config.to_string()).unwrap();
+        let exporter = Command::new(env!("CARGO_BIN_EXE_k8rs"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdout(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .expect("the exporter starts");
+
+        let run = Run {
+            client,
+            namespace,
+            dir,
+            exporter,
+            metrics_url: format!("http://{}/metrics", listen),
+        };
+        // once the pods are listed it's seeing what we do
+        run.eventually("the exporter to be done with its first list", || async {
+            run.metrics()
+                .await
+                .lines()
+                .any(|line| line.starts_with("k8rs_initial_sync_in_progress 0"))
+        })
+        .await;
+        run
+    }
+
+    async fn metrics(&self) -> String {
+        match reqwest::get(&self.metrics_url).await {
+            Ok(response) => response.text().await.unwrap_or_default(),
+            Err(_) => String::new(),
+        }
+    }
+
+    // the sum of the series of a family with all the given labels
+    async fn value(&self, family: &str, labels: &[(&str, &str)]) -> f64 {
+        self.metrics()
+            .await
+            .lines()
+            .filter(|line| {
+                line.strip_prefix(family)
+                    .is_some_and(|rest| rest.starts_with('{') || rest.starts_with(' '))
+            })
+            .filter(|line| {
+                labels
+                    .iter()
+                    .all(|(label, value)| line.contains(&format!("{}=\"{}\"", label, value)))
+            })
+            .filter_map(|line| line.rsplit_once(' ')?.1.parse::<f64>().ok())
+            .sum()
+    }
+
+    // what the file sink got so far
+    fn records(&self) -> Vec<Value> {
+        std::fs::read_to_string(self.dir.join("records.jsonl"))
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect()
+    }
+
+    fn delivered(&self, object: &str, reasons: &[&str]) -> bool {
+        self.records().iter().any(|record| {
+            record["namespace"] == self.namespace.as_str()
+                && record["object_name"] == object
+                && reasons.iter().any(|reason| record["reason"] == *reason)
+        })
+    }
+
+    async fn eventually<F, Fut>(&self, what: &str, check: F)
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        let deadline = Instant::now() + DEADLINE;
+        while !check().await {
+            assert!(
+                Instant::now() < deadline,
+                "gave up waiting for {} after {:?}",
+                what,
+                DEADLINE
+            );
+            sleep(POLL).await;
+        }
+    }
+
+    async fn pod(&self, name: &str, image: &str, command: &[&str]) {
+        let pod = serde_json::from_value::<Pod>(json!({
+            "metadata": {"name": name},
+            "spec": {
+                "containers": [{"name": "main", "image": image, "command": command}],
+                "terminationGracePeriodSeconds": 0,
+            },
+        }))
+        .unwrap();
+        Api::<Pod>::namespaced(self.client.clone(), &self.namespace)
+            .create(&PostParams::default(), &pod)
+            .await
+            .expect("the pod is created");
+    }
+}
+
+// an image that can't be pulled is counted by its repository and delivered
+async fn bad_image(run: &Run) {
+    run.pod("bad-image", "k8rs.invalid/chaos/missing:1", &[])
+        .await;
+    run.eventually("the pull failures to be counted", || async {
+        run.value(
+            "image_pull_failures_total",
+            &[
+                ("namespace", run.namespace.as_str()),
+                ("repository", "k8rs.invalid/chaos/missing"),
+            ],
+        )
+        .await
+            > 0.0
+    })
+    .await;
+    run.eventually("the pull failures to be delivered", || async {
+        run.delivered("bad-image", &["Failed", "BackOff"])
+    })
+    .await;
+}
+
+// a container exiting over and over has its restarts counted and its back-offs delivered
+async fn crash_loop(run: &Run) {
+    run.pod("crasher", "busybox:1.36", &["sh", "-c", "exit 1"])
+        .await;
+    run.eventually("the restarts to be counted", || async {
+        run.value(
+            "container_restarts_total",
+            &[("namespace", run.namespace.as_str()), ("container", "main")],
+        )
+        .await
+            > 0.0
+    })
+    .await;
+    run.eventually("the back-offs to be delivered", || async {
+        run.delivered("crasher", &["BackOff"])
+    })
+    .await;
+}
+
+// the pods of a deployment deleted from under it are counted as deleted, and the
+// events the deployment's controller made about the new ones went through the pipeline
+async fn deleted_pods(run: &Run) {
+    let deployment = serde_json::from_value::<Deployment>(json!({
+        "metadata": {"name": "steady"},
+        "spec": {
+            "replicas": 2,
+            "selector": {"matchLabels": {"app": "steady"}},
+            "template": {
+                "metadata": {"labels": {"app": "steady"}},
+                "spec": {
+                    "containers": [{
+                        "name": "main",
+                        "image": "busybox:1.36",
+                        "command": ["sleep", "3600"],
+                    }],
+                    "terminationGracePeriodSeconds": 0,
+                },
+            },
+        },
+    }))
+    .unwrap();
+    Api::<Deployment>::namespaced(run.client.clone(), &run.namespace)
+        .create(&PostParams::default(), &deployment)
+        .await
+        .expect("the deployment is created");
+
+    let pods = Api::<Pod>::namespaced(run.client.clone(), &run.namespace);
+    let running = ListParams::default().labels("app=steady");
+    run.eventually("the deployment's pods to run", || async {
+        pods.list(&running).await.is_ok_and(|list| {
+            list.items.len() == 2
+                && list.items.iter().all(|pod| {
+                    pod.status
+                        .as_ref()
+                        .and_then(|status| status.phase.as_deref())
+                        == Some("Running")
+                })
+        })
+    })
+    .await;
+
+    let victim = pods.list(&running).await.unwrap().items[0]
+        .metadata
+        .name
+        .clone()
+        .unwrap();
+    pods.delete(&victim, &DeleteParams::default())
+        .await
+        .expect("the pod is deleted");
+    run.eventually("the deleted pod to be counted", || async {
+        run.value("deleted_pods", &[("namespace", run.namespace.as_str())])
+            .await
+            > 0.0
+    })
+    .await;
+    run.eventually("the kill to be delivered", || async {
+        run.delivered(&victim, &["Killing"])
+    })
+    .await;
+}
+
+// every event made above went through the pipeline and was timed
+async fn histograms(run: &Run) {
+    let count = run.value("event_processing_delay_seconds_count", &[]).await;
+    assert!(count > 0.0, "no event processing delay was observed");
+    let delivered = run
+        .value("sink_deliveries_total", &[("sink", "archive")])
+        .await;
+    assert!(
+        delivered >= run.records().len() as f64,
+        "the file sink has more records than deliveries were counted"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn survives_the_chaos() {
+    let run = Run::start().await;
+    bad_image(&run).await;
+    crash_loop(&run).await;
+    deleted_pods(&run).await;
+    histograms(&run).await;
+}